@@ -0,0 +1,508 @@
+//! The actor runtime: contracts as actors that exchange messages.
+//!
+//! A contract sends a message with `SEND`, naming the handler that should
+//! receive it: an exported function of the receiving contract that takes
+//! no arguments and reads the message with `RECV`. Sent messages wait in
+//! the VM until a [`Scheduler`] takes them into the receivers' mailboxes
+//! and delivers them one at a time, each as a transaction of its own with
+//! its own gas budget. A handler that traps has its effects undone,
+//! including the messages it sent, and its message becomes a
+//! [`DeadLetter`], as does one that cannot be delivered or that finds its
+//! mailbox full.
+//!
+//! Every node that replays the same messages must handle them in the same
+//! order, so nothing here depends on hashing, timing or threads: mailboxes
+//! are first in, first out, actors are kept in address order, and the only
+//! randomness is a generator seeded from the [`SchedulerConfig`].
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::value::{Address, Trap, Value};
+use crate::vm::Vm;
+
+/// A message from one contract to a handler of another
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub from: Address,
+    pub to: Address,
+    /// The exported function that handles it
+    pub handler: String,
+    /// What `RECV` pushes in the handler
+    pub body: Value,
+}
+
+/// How the scheduler picks the next mailbox to take a message from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Rounds over the actors with mail, one message each, in an order
+    /// shuffled by the seed at the start of every round
+    RoundRobin,
+    /// The actor with the highest [`Scheduler::set_priority`] first, and
+    /// among equals the one whose next message was posted first
+    Priority,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerConfig {
+    pub policy: Policy,
+    pub seed: u64,
+    /// Gas each delivery runs with
+    pub gas_per_message: u64,
+    /// Messages a mailbox holds before new ones are dead letters
+    pub mailbox_capacity: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            policy: Policy::RoundRobin,
+            seed: 0,
+            gas_per_message: 1_000_000,
+            mailbox_capacity: 1024,
+        }
+    }
+}
+
+/// A message the scheduler accepted, numbered in the order it was posted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub seq: u64,
+    pub message: Message,
+}
+
+/// A message that was handled, with the result of its handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub seq: u64,
+    pub message: Message,
+    pub result: Result<Option<Value>, Trap>,
+    pub gas_used: u64,
+}
+
+/// Why a message was not handled
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeadLetterReason {
+    /// The receiver's mailbox was at capacity when the message was posted
+    MailboxFull,
+    /// No contract at the receiver, or no handler of that name
+    Undeliverable(String),
+    /// The handler trapped, out of gas included
+    Trapped(Trap),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub seq: u64,
+    pub message: Message,
+    pub reason: DeadLetterReason,
+}
+
+/// Delivers messages between the contracts of a [`Vm`] in an order that
+/// only depends on the messages and the configuration
+pub struct Scheduler {
+    config: SchedulerConfig,
+    mailboxes: BTreeMap<Address, VecDeque<Envelope>>,
+    priorities: BTreeMap<Address, u8>,
+    /// Actors left to visit in the current round-robin round
+    round: VecDeque<Address>,
+    rng: SplitMix64,
+    next_seq: u64,
+    deliveries: Vec<Delivery>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Scheduler {
+        Scheduler {
+            rng: SplitMix64(config.seed),
+            config,
+            mailboxes: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            round: VecDeque::new(),
+            next_seq: 0,
+            deliveries: Vec::new(),
+            dead_letters: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &SchedulerConfig {
+        &self.config
+    }
+
+    /// The priority of `actor` under [`Policy::Priority`]; 0 by default
+    pub fn set_priority(&mut self, actor: Address, priority: u8) {
+        self.priorities.insert(actor, priority);
+    }
+
+    /// Puts `message` in its receiver's mailbox, or among the dead letters
+    /// if the mailbox is full, and returns its sequence number
+    pub fn post(&mut self, message: Message) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mailbox = self.mailboxes.entry(message.to).or_default();
+        if mailbox.len() >= self.config.mailbox_capacity {
+            self.dead_letters.push(DeadLetter { seq, message, reason: DeadLetterReason::MailboxFull });
+        } else {
+            mailbox.push_back(Envelope { seq, message });
+        }
+        seq
+    }
+
+    /// The messages waiting for `actor`, next first
+    pub fn mailbox(&self, actor: &Address) -> impl Iterator<Item = &Envelope> {
+        self.mailboxes.get(actor).into_iter().flatten()
+    }
+
+    /// Messages waiting in all mailboxes
+    pub fn pending(&self) -> usize {
+        self.mailboxes.values().map(VecDeque::len).sum()
+    }
+
+    /// Posts the messages the VM's contracts have sent, then delivers the
+    /// next message, if any. Returns whether a message was taken, whether
+    /// or not its handler succeeded. The error is the state backend's, as
+    /// for [`Vm::call`].
+    pub fn step(&mut self, vm: &mut Vm) -> Result<bool, String> {
+        for message in vm.take_outbox() {
+            self.post(message);
+        }
+        let Some(actor) = self.next_actor() else {
+            return Ok(false);
+        };
+        let Envelope { seq, message } = self.mailboxes.get_mut(&actor)
+            .and_then(VecDeque::pop_front)
+            .expect("the next actor has mail");
+
+        if let Err(error) = vm.handler(&message) {
+            let reason = DeadLetterReason::Undeliverable(error);
+            self.dead_letters.push(DeadLetter { seq, message, reason });
+            return Ok(true);
+        }
+        let outcome = vm.deliver(&message, self.config.gas_per_message)?;
+        if let Err(trap) = &outcome.result {
+            let reason = DeadLetterReason::Trapped(trap.clone());
+            self.dead_letters.push(DeadLetter { seq, message: message.clone(), reason });
+        }
+        self.deliveries.push(Delivery { seq, message, result: outcome.result, gas_used: outcome.gas_used });
+        Ok(true)
+    }
+
+    /// Steps until no message is left or `max_steps` have been taken, which
+    /// bounds actors that keep messaging each other. Returns the number of
+    /// messages taken.
+    pub fn run(&mut self, vm: &mut Vm, max_steps: usize) -> Result<usize, String> {
+        let mut steps = 0;
+        while steps < max_steps && self.step(vm)? {
+            steps += 1;
+        }
+        Ok(steps)
+    }
+
+    /// Every message handled so far, in the order it was handled
+    pub fn deliveries(&self) -> &[Delivery] {
+        &self.deliveries
+    }
+
+    pub fn dead_letters(&self) -> &[DeadLetter] {
+        &self.dead_letters
+    }
+
+    fn next_actor(&mut self) -> Option<Address> {
+        match self.config.policy {
+            Policy::RoundRobin => {
+                if let Some(next) = self.round.iter().position(|actor| self.has_mail(actor)) {
+                    self.round.drain(..next);
+                    return self.round.pop_front();
+                }
+                let mut round: Vec<Address> = self.mailboxes.iter()
+                    .filter(|(_, mailbox)| !mailbox.is_empty())
+                    .map(|(actor, _)| *actor)
+                    .collect();
+                self.rng.shuffle(&mut round);
+                self.round = round.into();
+                self.round.pop_front()
+            }
+            Policy::Priority => self.mailboxes.iter()
+                .filter_map(|(actor, mailbox)| Some((actor, mailbox.front()?.seq)))
+                .max_by_key(|&(actor, seq)| (self.priorities.get(actor).copied().unwrap_or(0), std::cmp::Reverse(seq)))
+                .map(|(actor, _)| *actor),
+        }
+    }
+
+    fn has_mail(&self, actor: &Address) -> bool {
+        self.mailboxes.get(actor).is_some_and(|mailbox| !mailbox.is_empty())
+    }
+}
+
+/// The scheduler's generator: SplitMix64, which is small, fast and the
+/// same on every platform
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Fisher-Yates
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::assemble;
+    use crate::num::U256;
+
+    /// An actor that counts the messages it records and plays ping-pong
+    /// with whoever pings it, down to zero
+    const ACTOR: &str = "\
+.contract Actor
+.const #0 string \"msg.sender\"
+.const #1 string \"ping\"
+.const #2 string \"record\"
+.const #3 u256 0
+.const #4 u256 1
+.const #5 string \"failed\"
+.storage 0 last: u256
+.storage 1 count: u256
+.export start(peer: address, n: u256)
+.export ping()
+.export record()
+.export burn()
+.export fail()
+.export received() -> u256
+
+.function start arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    SEND #1 ; string \"ping\"
+    RET
+
+.function ping arity=0 locals=1
+    CALL record 0
+    RECV
+    STORE 0
+    LOAD 0
+    PUSH #3 ; u256 0
+    GT
+    NOT
+    JUMPI done
+    ENV #0 ; string \"msg.sender\"
+    LOAD 0
+    PUSH #4 ; u256 1
+    SUB
+    SEND #1 ; string \"ping\"
+done:
+    RET
+
+.function record arity=0 locals=0
+    RECV
+    SSTORE 0
+    SLOAD 1
+    PUSH #4 ; u256 1
+    ADD
+    SSTORE 1
+    RET
+
+.function burn arity=0 locals=0
+    ENV #0 ; string \"msg.sender\"
+    PUSH #3 ; u256 0
+    SEND #2 ; string \"record\"
+spin:
+    JUMP spin
+
+.function fail arity=0 locals=0
+    ENV #0 ; string \"msg.sender\"
+    PUSH #3 ; u256 0
+    SEND #2 ; string \"record\"
+    REVERT #5 ; string \"failed\"
+
+.function received arity=0 locals=0
+    RECV
+    RET
+";
+
+    const A: Address = [0xa0; 20];
+    const B: Address = [0xb0; 20];
+    const C: Address = [0xc0; 20];
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    fn actors(threshold: Option<u32>) -> Vm {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(threshold);
+        for address in [A, B, C] {
+            vm.deploy(address, assemble(ACTOR).unwrap()).unwrap();
+        }
+        vm
+    }
+
+    /// A message from A to `to`, so that replies have an actor to go to
+    fn message(to: Address, handler: &str, n: u64) -> Message {
+        Message { from: A, to, handler: handler.into(), body: word(n) }
+    }
+
+    fn slot(vm: &Vm, actor: &Address, slot: u64) -> Option<Value> {
+        vm.storage().get(actor, &U256::from(slot))
+    }
+
+    /// Posts four numbered messages to each actor, interleaved, handles
+    /// them all and returns the order they were handled in
+    fn handle_all(config: SchedulerConfig) -> (Vec<u64>, Vm) {
+        let mut vm = actors(None);
+        let mut scheduler = Scheduler::new(config);
+        for n in 0..4 {
+            for to in [A, B, C] {
+                scheduler.post(message(to, "record", n));
+            }
+        }
+        assert_eq!(scheduler.run(&mut vm, 100), Ok(12));
+        assert!(scheduler.dead_letters().is_empty());
+        (scheduler.deliveries().iter().map(|delivery| delivery.seq).collect(), vm)
+    }
+
+    #[test]
+    fn test_the_same_seed_replays_the_same_order() {
+        let mut orders = Vec::new();
+        for seed in 0..8 {
+            let config = SchedulerConfig { seed, ..Default::default() };
+            let (order, vm) = handle_all(config.clone());
+            let (again, replayed) = handle_all(config);
+            assert_eq!(order, again);
+            assert_eq!(vm.state_tree().root(), replayed.state_tree().root());
+
+            // Each mailbox is first in, first out, and every round visits
+            // each actor once
+            for actor in 0..3 {
+                let own: Vec<_> = order.iter().filter(|&&seq| seq % 3 == actor).collect();
+                assert!(own.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+            for round in order.chunks(3) {
+                let mut actors: Vec<_> = round.iter().map(|seq| seq % 3).collect();
+                actors.sort();
+                assert_eq!(actors, [0, 1, 2]);
+            }
+            for actor in [A, B, C] {
+                assert_eq!(slot(&vm, &actor, 1), Some(word(4)));
+            }
+            orders.push(order);
+        }
+        orders.dedup();
+        assert!(orders.len() > 1, "the seed never changed the order");
+    }
+
+    #[test]
+    fn test_priority_serves_the_highest_first() {
+        let mut vm = actors(None);
+        let mut scheduler = Scheduler::new(SchedulerConfig { policy: Policy::Priority, ..Default::default() });
+        scheduler.set_priority(C, 9);
+        for n in 0..2 {
+            for to in [A, B, C] {
+                scheduler.post(message(to, "record", n));
+            }
+        }
+        scheduler.run(&mut vm, 100).unwrap();
+        let order: Vec<_> = scheduler.deliveries().iter().map(|delivery| delivery.seq).collect();
+        assert_eq!(order, [2, 5, 0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_handlers_message_each_other() {
+        for threshold in [None, Some(0)] {
+            let mut vm = actors(threshold);
+            let mut scheduler = Scheduler::new(SchedulerConfig::default());
+            vm.call(&A, "start", vec![Value::Address(B), word(5)], 100_000).unwrap().result.unwrap();
+            assert_eq!(scheduler.run(&mut vm, 100), Ok(6));
+
+            // Each reply goes back to the sender, with one less
+            let pings: Vec<_> = scheduler.deliveries().iter()
+                .map(|delivery| (delivery.message.to, delivery.message.body.clone()))
+                .collect();
+            let expected: Vec<_> = (0..6).rev().map(|n| (if n % 2 == 1 { B } else { A }, word(n))).collect();
+            assert_eq!(pings, expected);
+            assert!(scheduler.deliveries().iter().all(|delivery| delivery.result == Ok(None)));
+            assert_eq!(slot(&vm, &A, 1), Some(word(3)));
+            assert_eq!(slot(&vm, &B, 1), Some(word(3)));
+            assert_eq!(slot(&vm, &A, 0), Some(word(0)));
+        }
+    }
+
+    #[test]
+    fn test_each_message_has_its_own_gas() {
+        let mut vm = actors(None);
+        let gas = 50_000;
+        let mut scheduler = Scheduler::new(SchedulerConfig { gas_per_message: gas, ..Default::default() });
+        scheduler.post(message(B, "burn", 0));
+        scheduler.post(message(B, "record", 7));
+        assert_eq!(scheduler.run(&mut vm, 100), Ok(2));
+
+        // The spinning handler's send is undone with the rest of it
+        let [burnt, recorded] = scheduler.deliveries() else {
+            panic!("expected two deliveries, got {:?}", scheduler.deliveries());
+        };
+        assert_eq!((burnt.result.clone(), burnt.gas_used), (Err(Trap::OutOfGas), gas));
+        assert!(recorded.result.is_ok() && recorded.gas_used < gas);
+        assert_eq!(slot(&vm, &B, 0), Some(word(7)));
+        assert_eq!(slot(&vm, &A, 1), None);
+        assert_eq!(scheduler.dead_letters(), [DeadLetter {
+            seq: 0,
+            message: message(B, "burn", 0),
+            reason: DeadLetterReason::Trapped(Trap::OutOfGas),
+        }]);
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let mut vm = actors(None);
+        let mut scheduler = Scheduler::new(SchedulerConfig { mailbox_capacity: 2, ..Default::default() });
+        scheduler.post(message(B, "fail", 0));
+        scheduler.post(message(B, "record", 1));
+        scheduler.post(message(B, "record", 2));
+        scheduler.post(message(C, "nope", 3));
+        scheduler.post(message([0xdd; 20], "record", 4));
+        assert_eq!(scheduler.pending(), 4);
+        assert_eq!(scheduler.mailbox(&B).map(|envelope| envelope.seq).collect::<Vec<_>>(), [0, 1]);
+        scheduler.run(&mut vm, 100).unwrap();
+
+        let mut reasons: Vec<_> = scheduler.dead_letters().iter()
+            .map(|letter| (letter.seq, letter.reason.clone()))
+            .collect();
+        assert_eq!(reasons[0], (2, DeadLetterReason::MailboxFull));
+        reasons.sort_by_key(|(seq, _)| *seq);
+        assert_eq!(reasons, [
+            (0, DeadLetterReason::Trapped(Trap::Revert("failed".into()))),
+            (2, DeadLetterReason::MailboxFull),
+            (3, DeadLetterReason::Undeliverable("no handler `nope` in `Actor`".into())),
+            (4, DeadLetterReason::Undeliverable(format!("no contract at 0x{}", "dd".repeat(20)))),
+        ]);
+        // Only the recorded message was handled; the failed handler's send
+        // never left
+        assert_eq!(slot(&vm, &B, 1), Some(word(1)));
+        assert_eq!(slot(&vm, &A, 1), None);
+        assert_eq!(scheduler.pending(), 0);
+    }
+
+    #[test]
+    fn test_receive_needs_a_message() {
+        let mut vm = actors(None);
+        let outcome = vm.call(&A, "received", vec![], 100_000).unwrap();
+        assert_eq!(outcome.result, Err(Trap::NoMessage));
+
+        // Handlers take no arguments; the message is what they receive
+        let err = vm.deliver(&message(A, "start", 0), 100_000).unwrap_err();
+        assert_eq!(err, "handler `start` of `Actor` takes arguments");
+        let outcome = vm.deliver(&message(A, "received", 9), 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(9))));
+    }
+}
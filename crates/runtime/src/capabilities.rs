@@ -0,0 +1,301 @@
+//! Capabilities: tokens that grant one right over the contract that issued
+//! them, such as `mint` on a token.
+//!
+//! A contract issues a capability with `GRANTPERM` and checks with
+//! `CHECKPERM` whether an account holds one. The token itself is a
+//! [`Value::Capability`](crate::Value::Capability), a handle into the VM's
+//! table that no instruction can make from a number, so the only way to
+//! come by one is to be granted it. Its holder can derive more tokens from
+//! it, for another account to hold (delegation) or with a shorter life or
+//! without the right to delegate further (attenuation). A derived token is
+//! never stronger than its parent, and revoking a token or letting it
+//! expire ends every token derived from it.
+//!
+//! The table lives in the VM beside balances and is journaled like them,
+//! so a transaction that traps grants and revokes nothing. Every change is
+//! also written through to the state backend, under
+//! [`RUNTIME_ADDRESS`], so that tokens kept in durable storage still
+//! name the same capability when the state is reopened.
+
+use crate::num::U256;
+use crate::state::StateBackend;
+use crate::value::{Address, Trap, Value};
+use crate::vm::RUNTIME_ADDRESS;
+
+/// Where capability 0 is stored under [`RUNTIME_ADDRESS`]; the slots
+/// below it hold the VM's counters
+const FIRST_GRANT_SLOT: U256 = U256::from_u64(1);
+
+/// A capability as the VM records it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grant {
+    /// The contract the right is over
+    pub issuer: Address,
+    pub right: String,
+    pub holder: Address,
+    /// The last block the capability is valid in; `None` never expires
+    pub expires: Option<u64>,
+    /// Whether the holder may derive capabilities for other accounts
+    pub delegable: bool,
+    /// The capability this one was derived from
+    pub parent: Option<u64>,
+    pub revoked: bool,
+}
+
+impl Grant {
+    /// The grant as it is stored in the state backend
+    fn to_value(&self) -> Value {
+        let optional = |number: Option<u64>| match number {
+            Some(number) => Value::Variant { path: "Some".into(), payload: vec![Value::U256(U256::from(number))] },
+            None => Value::Variant { path: "None".into(), payload: Vec::new() },
+        };
+        Value::Struct {
+            name: "Grant".into(),
+            fields: vec![
+                ("issuer".into(), Value::Address(self.issuer)),
+                ("right".into(), Value::String(self.right.clone())),
+                ("holder".into(), Value::Address(self.holder)),
+                ("expires".into(), optional(self.expires)),
+                ("delegable".into(), Value::Bool(self.delegable)),
+                ("parent".into(), optional(self.parent)),
+                ("revoked".into(), Value::Bool(self.revoked)),
+            ],
+        }
+    }
+
+    fn from_value(value: &Value) -> Option<Grant> {
+        let optional = |value: &Value| match value {
+            Value::Variant { path, payload } if path == "Some" => match payload.as_slice() {
+                [Value::U256(number)] => number.to_u64().map(Some),
+                _ => None,
+            },
+            Value::Variant { path, payload } if path == "None" && payload.is_empty() => Some(None),
+            _ => None,
+        };
+        let Value::Struct { fields, .. } = value else {
+            return None;
+        };
+        match fields.as_slice() {
+            [
+                (_, Value::Address(issuer)),
+                (_, Value::String(right)),
+                (_, Value::Address(holder)),
+                (_, expires),
+                (_, Value::Bool(delegable)),
+                (_, parent),
+                (_, Value::Bool(revoked)),
+            ] => Some(Grant {
+                issuer: *issuer,
+                right: right.clone(),
+                holder: *holder,
+                expires: optional(expires)?,
+                delegable: *delegable,
+                parent: optional(parent)?,
+                revoked: *revoked,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Every capability issued so far, by id
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    grants: Vec<Grant>,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The table as written to `storage` by earlier VMs
+    pub fn load(storage: &dyn StateBackend) -> Result<Self, String> {
+        let mut grants = Vec::new();
+        for (slot, value) in storage.slots(&RUNTIME_ADDRESS) {
+            if slot < FIRST_GRANT_SLOT {
+                continue;
+            }
+            let expected = FIRST_GRANT_SLOT + U256::from(grants.len() as u64);
+            if slot != expected {
+                return Err(format!("capability table is missing slot {}", expected));
+            }
+            grants.push(Grant::from_value(&value).ok_or_else(|| format!("malformed capability in slot {}", slot))?);
+        }
+        Ok(Capabilities { grants })
+    }
+
+    /// Writes capability `id` as it stands to `storage`
+    pub(crate) fn persist(&self, id: u64, storage: &mut dyn StateBackend) {
+        storage.set(RUNTIME_ADDRESS, FIRST_GRANT_SLOT + U256::from(id), self.grants[id as usize].to_value());
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Grant> {
+        self.grants.get(id as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.grants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    /// Issues a new capability and returns its id
+    pub fn issue(&mut self, issuer: Address, right: &str, holder: Address, expires: Option<u64>) -> u64 {
+        let right = right.to_string();
+        self.push(Grant { issuer, right, holder, expires, delegable: true, parent: None, revoked: false })
+    }
+
+    /// Whether capability `id` exists and is still good at `block`: none
+    /// of it and its ancestors has been revoked or has expired
+    pub fn is_live(&self, id: u64, block: u64) -> bool {
+        self.get(id).is_some()
+            && self.lineage(id).all(|grant| !grant.revoked && grant.expires.is_none_or(|last| block <= last))
+    }
+
+    /// Whether `holder` holds a live capability for `right` over `issuer`
+    pub fn has_permission(&self, issuer: &Address, holder: &Address, right: &str, block: u64) -> bool {
+        (0..self.grants.len() as u64).any(|id| {
+            let grant = &self.grants[id as usize];
+            grant.issuer == *issuer && grant.holder == *holder && grant.right == right && self.is_live(id, block)
+        })
+    }
+
+    /// Derives a capability from `id`, which `by` must hold, for `holder`.
+    /// It expires no later than its parent and may only be delegated if
+    /// the parent may be: asking for more gets the parent's limits.
+    /// Deriving for another holder needs a delegable parent.
+    pub fn derive(
+        &mut self,
+        id: u64,
+        by: &Address,
+        holder: Address,
+        expires: Option<u64>,
+        delegable: bool,
+        block: u64,
+    ) -> Result<u64, Trap> {
+        let parent = self.get(id).filter(|_| self.is_live(id, block)).ok_or(Trap::PermissionDenied("dead capability"))?;
+        if parent.holder != *by {
+            return Err(Trap::PermissionDenied("capability held by another account"));
+        }
+        if holder != *by && !parent.delegable {
+            return Err(Trap::PermissionDenied("capability cannot be delegated"));
+        }
+        let expires = match (parent.expires, expires) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let grant = Grant {
+            issuer: parent.issuer,
+            right: parent.right.clone(),
+            holder,
+            expires,
+            delegable: parent.delegable && delegable,
+            parent: Some(id),
+            revoked: false,
+        };
+        Ok(self.push(grant))
+    }
+
+    /// Revokes capability `id` and with it everything derived from it, on
+    /// behalf of `by`: its issuer, its holder or the holder of a
+    /// capability it was derived from. Returns whether it was live before.
+    pub fn revoke(&mut self, id: u64, by: &Address) -> Result<bool, Trap> {
+        let grant = self.get(id).ok_or(Trap::PermissionDenied("no such capability"))?;
+        if grant.issuer != *by && !self.lineage(id).any(|grant| grant.holder == *by) {
+            return Err(Trap::PermissionDenied("only the issuer or a holder can revoke a capability"));
+        }
+        Ok(!std::mem::replace(&mut self.grants[id as usize].revoked, true))
+    }
+
+    /// Revokes every capability for `right` over `issuer` that `holder`
+    /// holds, and returns the ids that were not revoked already
+    pub fn revoke_held(&mut self, issuer: &Address, holder: &Address, right: &str) -> Vec<u64> {
+        let mut revoked = Vec::new();
+        for (id, grant) in self.grants.iter_mut().enumerate() {
+            if grant.issuer == *issuer && grant.holder == *holder && grant.right == right && !grant.revoked {
+                grant.revoked = true;
+                revoked.push(id as u64);
+            }
+        }
+        revoked
+    }
+
+    /// Undoes the last [`Capabilities::issue`] or [`Capabilities::derive`]
+    pub(crate) fn pop(&mut self) {
+        self.grants.pop();
+    }
+
+    /// Undoes the revocation of `id`
+    pub(crate) fn restore(&mut self, id: u64) {
+        self.grants[id as usize].revoked = false;
+    }
+
+    fn push(&mut self, grant: Grant) -> u64 {
+        self.grants.push(grant);
+        self.grants.len() as u64 - 1
+    }
+
+    /// Capability `id` and the ones it was derived from, up to the one
+    /// issued
+    fn lineage(&self, id: u64) -> impl Iterator<Item = &Grant> {
+        std::iter::successors(self.get(id), |grant| grant.parent.and_then(|parent| self.get(parent)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: Address = [0x70; 20];
+    const ALICE: Address = [0xa1; 20];
+    const BOB: Address = [0xb0; 20];
+    const CAROL: Address = [0xca; 20];
+
+    #[test]
+    fn test_delegation_and_cascading_revocation() {
+        let mut table = Capabilities::new();
+        let root = table.issue(TOKEN, "mint", ALICE, None);
+        let bob = table.derive(root, &ALICE, BOB, None, true, 1).unwrap();
+        let carol = table.derive(bob, &BOB, CAROL, None, true, 1).unwrap();
+        assert!(table.has_permission(&TOKEN, &CAROL, "mint", 1));
+        assert!(!table.has_permission(&TOKEN, &CAROL, "burn", 1));
+        assert!(!table.has_permission(&ALICE, &CAROL, "mint", 1));
+
+        // Only holders along the chain and the issuer may revoke
+        assert!(table.revoke(bob, &CAROL).is_err());
+        assert_eq!(table.revoke(bob, &ALICE), Ok(true));
+        assert!(!table.is_live(carol, 1));
+        assert!(!table.has_permission(&TOKEN, &CAROL, "mint", 1));
+        assert!(table.has_permission(&TOKEN, &ALICE, "mint", 1));
+        assert_eq!(table.derive(carol, &CAROL, CAROL, None, true, 1), Err(Trap::PermissionDenied("dead capability")));
+
+        table.restore(bob);
+        assert!(table.is_live(carol, 1));
+        assert_eq!(table.revoke_held(&TOKEN, &ALICE, "mint"), vec![root]);
+        assert!(!table.has_permission(&TOKEN, &BOB, "mint", 1));
+    }
+
+    #[test]
+    fn test_derived_capabilities_are_never_stronger() {
+        let mut table = Capabilities::new();
+        let root = table.issue(TOKEN, "mint", ALICE, Some(100));
+        let weak = table.derive(root, &ALICE, ALICE, Some(500), false, 1).unwrap();
+        assert_eq!(table.get(weak).unwrap().expires, Some(100));
+        let delegated = table.derive(weak, &ALICE, BOB, None, true, 1);
+        assert_eq!(delegated, Err(Trap::PermissionDenied("capability cannot be delegated")));
+        let again = table.derive(weak, &ALICE, ALICE, None, true, 1).unwrap();
+        assert!(!table.get(again).unwrap().delegable);
+        let stolen = table.derive(root, &BOB, BOB, None, true, 1);
+        assert_eq!(stolen, Err(Trap::PermissionDenied("capability held by another account")));
+
+        let short = table.derive(root, &ALICE, BOB, Some(50), true, 1).unwrap();
+        assert!(table.is_live(short, 50));
+        assert!(!table.is_live(short, 51));
+        assert!(table.is_live(root, 100));
+        assert!(!table.has_permission(&TOKEN, &ALICE, "mint", 101));
+        assert!(!table.is_live(7, 1));
+    }
+}
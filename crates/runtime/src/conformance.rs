@@ -0,0 +1,418 @@
+//! The per-opcode conformance suite. Each case runs a few instructions as
+//! the body of `run`, on arguments passed in its first locals, and states
+//! what `run` returns or how it traps. Every case runs in both tiers, which
+//! must also agree on its gas, and every opcode has at least one case.
+
+use std::collections::BTreeSet;
+
+use crate::bytecode::{assemble, Opcode};
+use crate::num::U256;
+use crate::value::{Address, Event, Trap, Value};
+use crate::vm::Vm;
+
+const GAS_LIMIT: u64 = 1_000_000;
+
+const CONTRACT: Address = [0xc0; 20];
+const SENDER: Address = [0x5e; 20];
+const OTHER: Address = [0x07; 20];
+
+/// Everything the cases refer to, ahead of their `run`
+const PRELUDE: &str = "\
+.contract Conformance
+.const #0 u256 0
+.const #1 u256 1
+.const #2 u256 2
+.const #3 u256 7
+.const #4 string \"failed\"
+.const #5 string \"msg.sender\"
+.const #6 string \"len\"
+.const #7 string \"double\"
+.const #8 string \"Pair {a, b}\"
+.const #9 string \"a\"
+.const #10 string \"Some\"
+.const #11 string \"u256\"
+.const #12 string \"Coin\"
+.const #13 string \"mint\"
+.const #14 string \"handle\"
+.const #15 string \"balance\"
+.const #16 string \"Coin {amount}\"
+.const #17 string \"amount\"
+.const #18 bool false
+.const #19 string \"push\"
+.const #20 string \"min\"
+.storage 0 count: u256
+.storage 1 values: map<u256, u256>
+.event Counted(n: u256)
+.export double(n: u256) -> u256
+.export handle() -> u256
+
+.function double arity=1 locals=1
+    LOAD 0
+    LOAD 0
+    ADD
+    RET
+
+.function handle arity=0 locals=0
+    RECV
+    RET
+
+.function locked arity=1 locals=1
+    GUARD
+    LOAD 0
+    JUMPI again
+    PUSH #1 ; u256 1
+    RET
+again:
+    PUSH #18 ; bool false
+    CALL locked 1
+    RET
+
+.function unlocked arity=1 locals=1
+    GUARD
+    UNGUARD
+    LOAD 0
+    JUMPI again
+    PUSH #1 ; u256 1
+    RET
+again:
+    PUSH #18 ; bool false
+    CALL unlocked 1
+    RET
+";
+
+struct Case {
+    opcode: Opcode,
+    body: &'static str,
+    args: Vec<Value>,
+    expected: Result<Option<Value>, Trap>,
+    /// Looks at what the case left outside the stack
+    check: fn(&mut Vm),
+}
+
+fn case(opcode: Opcode, body: &'static str, args: Vec<Value>, expected: Result<Option<Value>, Trap>) -> Case {
+    Case { opcode, body, args, expected, check: |_| {} }
+}
+
+fn word(n: u64) -> Value {
+    Value::U256(U256::from(n))
+}
+
+fn boolean(b: bool) -> Value {
+    Value::Bool(b)
+}
+
+fn returns(value: Value) -> Result<Option<Value>, Trap> {
+    Ok(Some(value))
+}
+
+fn binary(opcode: Opcode, a: Value, b: Value, expected: Result<Option<Value>, Trap>) -> Case {
+    let body = match opcode {
+        Opcode::Add => "LOAD 0\n    LOAD 1\n    ADD\n    RET",
+        Opcode::Sub => "LOAD 0\n    LOAD 1\n    SUB\n    RET",
+        Opcode::Mul => "LOAD 0\n    LOAD 1\n    MUL\n    RET",
+        Opcode::Div => "LOAD 0\n    LOAD 1\n    DIV\n    RET",
+        Opcode::Mod => "LOAD 0\n    LOAD 1\n    MOD\n    RET",
+        Opcode::Eq => "LOAD 0\n    LOAD 1\n    EQ\n    RET",
+        Opcode::Lt => "LOAD 0\n    LOAD 1\n    LT\n    RET",
+        Opcode::Gt => "LOAD 0\n    LOAD 1\n    GT\n    RET",
+        Opcode::LtEq => "LOAD 0\n    LOAD 1\n    LTE\n    RET",
+        Opcode::GtEq => "LOAD 0\n    LOAD 1\n    GTE\n    RET",
+        Opcode::And => "LOAD 0\n    LOAD 1\n    AND\n    RET",
+        Opcode::Or => "LOAD 0\n    LOAD 1\n    OR\n    RET",
+        Opcode::Xor => "LOAD 0\n    LOAD 1\n    XOR\n    RET",
+        _ => unreachable!("{} is not binary", opcode),
+    };
+    case(opcode, body, vec![a, b], expected)
+}
+
+fn cases() -> Vec<Case> {
+    let pair = Value::Struct { name: "Pair".into(), fields: vec![("a".into(), word(1)), ("b".into(), word(2))] };
+    let array = Value::Array(vec![word(4), word(5)]);
+    vec![
+        case(Opcode::Push, "PUSH #3\n    RET", vec![], returns(word(7))),
+        case(Opcode::Pop, "LOAD 0\n    LOAD 1\n    POP\n    RET", vec![word(1), word(2)], returns(word(1))),
+        case(Opcode::Pop, "POP\n    RET", vec![], Err(Trap::StackUnderflow)),
+        case(Opcode::Dup, "LOAD 0\n    DUP 0\n    ADD\n    RET", vec![word(7)], returns(word(14))),
+        case(Opcode::Dup, "LOAD 0\n    DUP 1\n    RET", vec![word(7)], Err(Trap::StackUnderflow)),
+        case(Opcode::Swap, "LOAD 0\n    LOAD 1\n    SWAP 0\n    SUB\n    RET", vec![word(1), word(7)], returns(word(6))),
+        case(Opcode::Swap, "LOAD 0\n    SWAP 0\n    RET", vec![word(7)], Err(Trap::StackUnderflow)),
+        case(Opcode::Load, "LOAD 0\n    RET", vec![word(7)], returns(word(7))),
+        case(Opcode::Store, "LOAD 0\n    STORE 1\n    LOAD 1\n    RET", vec![word(7)], returns(word(7))),
+
+        binary(Opcode::Add, word(2), word(3), returns(word(5))),
+        binary(Opcode::Add, Value::U256(U256::MAX), word(1), Err(Trap::ArithmeticOverflow)),
+        binary(Opcode::Add, word(2), boolean(true), Err(Trap::TypeMismatch("ADD"))),
+        binary(Opcode::Sub, word(7), word(2), returns(word(5))),
+        binary(Opcode::Sub, word(2), word(7), Err(Trap::ArithmeticOverflow)),
+        binary(Opcode::Mul, word(6), word(7), returns(word(42))),
+        binary(Opcode::Mul, Value::U256(U256::ONE << 128), Value::U256(U256::ONE << 128), Err(Trap::ArithmeticOverflow)),
+        binary(Opcode::Div, word(7), word(2), returns(word(3))),
+        binary(Opcode::Div, word(7), word(0), Err(Trap::DivisionByZero)),
+        binary(Opcode::Mod, word(7), word(2), returns(word(1))),
+        binary(Opcode::Mod, word(7), word(0), Err(Trap::DivisionByZero)),
+        binary(Opcode::Eq, word(7), word(7), returns(boolean(true))),
+        binary(Opcode::Eq, word(1), boolean(true), returns(boolean(false))),
+        binary(Opcode::Lt, word(2), word(7), returns(boolean(true))),
+        binary(Opcode::Lt, word(2), boolean(true), Err(Trap::TypeMismatch("LT"))),
+        binary(Opcode::Gt, word(2), word(7), returns(boolean(false))),
+        binary(Opcode::LtEq, word(7), word(7), returns(boolean(true))),
+        binary(Opcode::GtEq, word(2), word(7), returns(boolean(false))),
+        binary(Opcode::And, boolean(true), boolean(false), returns(boolean(false))),
+        binary(Opcode::And, word(0b110), word(0b011), returns(word(0b010))),
+        binary(Opcode::Or, boolean(true), boolean(false), returns(boolean(true))),
+        binary(Opcode::Xor, boolean(true), boolean(true), returns(boolean(false))),
+        binary(Opcode::Xor, boolean(true), word(1), Err(Trap::TypeMismatch("XOR"))),
+        case(Opcode::Not, "LOAD 0\n    NOT\n    RET", vec![boolean(false)], returns(boolean(true))),
+        case(Opcode::Not, "LOAD 0\n    NOT\n    RET", vec![word(0)], returns(Value::U256(U256::MAX))),
+
+        case(Opcode::Jump, "JUMP skip\n    REVERT #4\nskip:\n    PUSH #1\n    RET", vec![], returns(word(1))),
+        case(
+            Opcode::JumpIf,
+            "LOAD 0\n    JUMPI yes\n    PUSH #0\n    RET\nyes:\n    PUSH #1\n    RET",
+            vec![boolean(true)],
+            returns(word(1)),
+        ),
+        case(
+            Opcode::JumpIf,
+            "LOAD 0\n    JUMPI yes\n    PUSH #0\n    RET\nyes:\n    PUSH #1\n    RET",
+            vec![boolean(false)],
+            returns(word(0)),
+        ),
+        case(Opcode::JumpIf, "LOAD 0\n    JUMPI 0", vec![word(1)], Err(Trap::TypeMismatch("JUMPI"))),
+        case(Opcode::Return, "PUSH #1\n    RET\n    PUSH #2\n    RET", vec![], returns(word(1))),
+        case(Opcode::Return, "RET", vec![], Ok(None)),
+        case(Opcode::Call, "LOAD 0\n    CALL double 1\n    RET", vec![word(7)], returns(word(14))),
+        case(Opcode::Revert, "REVERT #4", vec![], Err(Trap::Revert("failed".into()))),
+        case(Opcode::CallMethod, "LOAD 0\n    CALLM #6 0\n    RET", vec![array.clone()], returns(word(2))),
+        case(
+            Opcode::CallMethod,
+            "LOAD 0\n    LOAD 1\n    CALLM #19 1\n    RET",
+            vec![array.clone(), word(6)],
+            returns(Value::Array(vec![word(4), word(5), word(6)])),
+        ),
+        case(Opcode::CallMethod, "LOAD 0\n    CALLM #7 0\n    RET", vec![word(1)], Err(Trap::UnknownMethod("double".into()))),
+        case(Opcode::CallHost, "LOAD 0\n    CALLH #15 1\n    RET", vec![Value::Address(CONTRACT)], returns(word(100))),
+        case(Opcode::CallHost, "LOAD 0\n    LOAD 1\n    CALLH #20 2\n    RET", vec![word(7), word(2)], returns(word(2))),
+        case(Opcode::CallHost, "CALLH #4 0\n    RET", vec![], Err(Trap::UnknownHostFunction("failed".into()))),
+        case(
+            Opcode::CallIndirect,
+            "CLOSURE double 0\n    PUSH #3\n    CALLI 1\n    RET",
+            vec![],
+            returns(word(14)),
+        ),
+        case(Opcode::CallIndirect, "LOAD 0\n    CALLI 0\n    RET", vec![word(1)], Err(Trap::TypeMismatch("CALLI"))),
+        case(
+            Opcode::Closure,
+            "LOAD 0\n    CLOSURE double 1\n    RET",
+            vec![word(7)],
+            returns(Value::Closure { function: 0, captured: vec![word(7)] }),
+        ),
+
+        case(Opcode::SLoad, "SLOAD 0\n    RET", vec![], returns(word(0))),
+        case(Opcode::SStore, "LOAD 0\n    SSTORE 0\n    SLOAD 0\n    RET", vec![word(7)], returns(word(7))),
+        Case {
+            check: |vm| {
+                let counted = Event { address: CONTRACT, name: "Counted".into(), fields: vec![word(7)] };
+                assert_eq!(vm.events(), [counted]);
+            },
+            ..case(Opcode::Emit, "LOAD 0\n    EMIT 0 1\n    RET", vec![word(7)], Ok(None))
+        },
+        case(Opcode::Balance, "LOAD 0\n    BALANCE\n    RET", vec![Value::Address(CONTRACT)], returns(word(100))),
+        case(Opcode::Balance, "LOAD 0\n    BALANCE\n    RET", vec![word(1)], Err(Trap::TypeMismatch("BALANCE"))),
+        Case {
+            check: |vm| assert_eq!(vm.balances()[&CONTRACT], U256::from(70u64)),
+            ..case(
+                Opcode::Transfer,
+                "LOAD 0\n    LOAD 1\n    TRANSFER\n    LOAD 0\n    BALANCE\n    RET",
+                vec![Value::Address(OTHER), word(30)],
+                returns(word(30)),
+            )
+        },
+        case(
+            Opcode::Transfer,
+            "LOAD 0\n    LOAD 1\n    TRANSFER\n    RET",
+            vec![Value::Address(OTHER), word(101)],
+            Err(Trap::InsufficientBalance),
+        ),
+        case(
+            Opcode::CallContract,
+            "LOAD 0\n    LOAD 1\n    CALLC #7 1\n    RET",
+            vec![Value::Address(CONTRACT), word(7)],
+            returns(word(14)),
+        ),
+        case(
+            Opcode::CallContract,
+            "LOAD 0\n    LOAD 1\n    CALLC #7 1\n    RET",
+            vec![Value::Address(OTHER), word(7)],
+            Err(Trap::NoContract { address: OTHER, method: "double".into() }),
+        ),
+        case(
+            Opcode::MapSlot,
+            "PUSH #1\n    LOAD 0\n    MAPSLOT\n    PUSH #1\n    LOAD 1\n    MAPSLOT\n    EQ\n    RET",
+            vec![word(1), word(2)],
+            returns(boolean(false)),
+        ),
+        case(Opcode::SLoadAt, "PUSH #1\n    LOAD 0\n    MAPSLOT\n    SLOADAT\n    RET", vec![word(7)], returns(word(0))),
+        case(
+            Opcode::SStoreAt,
+            "PUSH #1\n    LOAD 0\n    MAPSLOT\n    DUP 0\n    PUSH #3\n    SSTOREAT\n    SLOADAT\n    RET",
+            vec![word(1)],
+            returns(word(7)),
+        ),
+        case(Opcode::Env, "ENV #5\n    RET", vec![], returns(Value::Address(SENDER))),
+        case(Opcode::Env, "ENV #15\n    RET", vec![], Err(Trap::UnknownEnvironment("balance".into()))),
+
+        case(Opcode::GuardEnter, "LOAD 0\n    CALL locked 1\n    RET", vec![boolean(false)], returns(word(1))),
+        case(
+            Opcode::GuardEnter,
+            "LOAD 0\n    CALL locked 1\n    RET",
+            vec![boolean(true)],
+            Err(Trap::Reentrancy {
+                function: "locked".into(),
+                chain: vec!["Conformance.run".into(), "Conformance.locked".into(), "Conformance.locked".into()],
+            }),
+        ),
+        case(Opcode::GuardExit, "LOAD 0\n    CALL unlocked 1\n    RET", vec![boolean(true)], returns(word(1))),
+
+        Case {
+            check: |vm| {
+                let sent = vm.take_outbox();
+                assert_eq!(sent.len(), 1);
+                assert_eq!((sent[0].from, sent[0].to, sent[0].handler.as_str()), (CONTRACT, CONTRACT, "handle"));
+                let delivered = vm.deliver(&sent[0], GAS_LIMIT).unwrap();
+                assert_eq!(delivered.result, returns(word(7)));
+            },
+            ..case(Opcode::Send, "LOAD 0\n    PUSH #3\n    SEND #14\n    RET", vec![Value::Address(CONTRACT)], Ok(None))
+        },
+        case(Opcode::Receive, "RECV\n    RET", vec![], Err(Trap::NoMessage)),
+
+        case(
+            Opcode::Acquire,
+            "PUSH #3\n    STRUCT #16 1\n    ACQUIRE #12\n    DUP 0\n    GETFIELD #17\n    SWAP 0\n    RELEASE #12\n    RET",
+            vec![],
+            returns(word(7)),
+        ),
+        case(
+            Opcode::Acquire,
+            "PUSH #3\n    STRUCT #16 1\n    ACQUIRE #12\n    POP\n    RET",
+            vec![],
+            Err(Trap::ResourceLeak { kind: "Coin".into(), function: "run".into() }),
+        ),
+        case(Opcode::Release, "PUSH #3\n    STRUCT #16 1\n    ACQUIRE #12\n    RELEASE #12\n    RET", vec![], Ok(None)),
+        case(
+            Opcode::Release,
+            "PUSH #3\n    STRUCT #16 1\n    ACQUIRE #12\n    DUP 0\n    RELEASE #12\n    RELEASE #12\n    RET",
+            vec![],
+            Err(Trap::ResourceNotHeld("Coin".into())),
+        ),
+        case(Opcode::Release, "LOAD 0\n    RELEASE #12\n    RET", vec![word(1)], Err(Trap::TypeMismatch("RELEASE"))),
+
+        case(Opcode::CheckPermission, "LOAD 0\n    CHECKPERM #13\n    RET", vec![Value::Address(SENDER)], returns(boolean(false))),
+        case(
+            Opcode::GrantPermission,
+            "LOAD 0\n    PUSH #0\n    GRANTPERM #13\n    RET",
+            vec![Value::Address(SENDER)],
+            returns(Value::Capability(0)),
+        ),
+        case(
+            Opcode::GrantPermission,
+            "LOAD 0\n    PUSH #0\n    GRANTPERM #13\n    POP\n    LOAD 0\n    CHECKPERM #13\n    RET",
+            vec![Value::Address(SENDER)],
+            returns(boolean(true)),
+        ),
+        case(
+            Opcode::RevokePermission,
+            "LOAD 0\n    PUSH #0\n    GRANTPERM #13\n    POP\n    LOAD 0\n    REVOKEPERM #13\n    LOAD 0\n    CHECKPERM #13\n    RET",
+            vec![Value::Address(SENDER)],
+            returns(boolean(false)),
+        ),
+
+        case(
+            Opcode::Tuple,
+            "LOAD 0\n    LOAD 1\n    TUPLE 2\n    RET",
+            vec![word(1), word(2)],
+            returns(Value::Tuple(vec![word(1), word(2)])),
+        ),
+        case(
+            Opcode::Array,
+            "LOAD 0\n    LOAD 1\n    ARRAY 2\n    RET",
+            vec![word(1), word(2)],
+            returns(Value::Array(vec![word(1), word(2)])),
+        ),
+        case(Opcode::Array, "ARRAY 1\n    RET", vec![], Err(Trap::StackUnderflow)),
+        case(Opcode::Extract, "LOAD 0\n    LOAD 1\n    TUPLE 2\n    EXTRACT 1\n    RET", vec![word(1), word(2)], returns(word(2))),
+        case(
+            Opcode::Extract,
+            "LOAD 0\n    LOAD 1\n    TUPLE 2\n    EXTRACT 2\n    RET",
+            vec![word(1), word(2)],
+            Err(Trap::IndexOutOfBounds),
+        ),
+        case(Opcode::Struct, "LOAD 0\n    LOAD 1\n    STRUCT #8 2\n    RET", vec![word(1), word(2)], returns(pair.clone())),
+        case(
+            Opcode::Variant,
+            "LOAD 0\n    VARIANT #10 1\n    RET",
+            vec![word(7)],
+            returns(Value::Variant { path: "Some".into(), payload: vec![word(7)] }),
+        ),
+        case(Opcode::IsVariant, "LOAD 0\n    VARIANT #10 1\n    ISVARIANT #10\n    RET", vec![word(7)], returns(boolean(true))),
+        case(Opcode::IsVariant, "LOAD 0\n    ISVARIANT #10\n    RET", vec![word(7)], returns(boolean(false))),
+        case(Opcode::GetField, "LOAD 0\n    GETFIELD #9\n    RET", vec![pair.clone()], returns(word(1))),
+        case(Opcode::GetField, "LOAD 0\n    GETFIELD #9\n    RET", vec![word(1)], Err(Trap::TypeMismatch("GETFIELD"))),
+        case(
+            Opcode::SetField,
+            "LOAD 0\n    PUSH #3\n    SETFIELD #9\n    GETFIELD #9\n    RET",
+            vec![pair],
+            returns(word(7)),
+        ),
+        case(Opcode::Index, "LOAD 0\n    LOAD 1\n    INDEX\n    RET", vec![array.clone(), word(1)], returns(word(5))),
+        case(Opcode::Index, "LOAD 0\n    LOAD 1\n    INDEX\n    RET", vec![array.clone(), word(2)], Err(Trap::IndexOutOfBounds)),
+        case(
+            Opcode::SetIndex,
+            "LOAD 0\n    PUSH #1\n    PUSH #3\n    SETINDEX\n    RET",
+            vec![array],
+            returns(Value::Array(vec![word(4), word(7)])),
+        ),
+        case(Opcode::Cast, "LOAD 0\n    CAST #11\n    RET", vec![boolean(true)], returns(word(1))),
+        case(Opcode::Cast, "LOAD 0\n    CAST #11\n    RET", vec![Value::String("1".into())], Err(Trap::TypeMismatch("CAST"))),
+    ]
+}
+
+/// Runs `case` in a fresh VM, interpreted or compiled on first call
+fn run(case: &Case, jit_threshold: Option<u32>) -> (Vm, Result<Option<Value>, Trap>, u64) {
+    let source = format!(
+        "{}\n.function run arity={} locals={}\n    {}\n",
+        PRELUDE,
+        case.args.len(),
+        case.args.len() + 2,
+        case.body
+    );
+    let module = assemble(&source).unwrap_or_else(|e| panic!("case for {}: {}", case.opcode, e));
+    let mut vm = Vm::new();
+    vm.set_jit_threshold(jit_threshold);
+    vm.env.sender = SENDER;
+    vm.deploy(CONTRACT, module).unwrap();
+    vm.set_balance(CONTRACT, U256::from(100u64));
+    let outcome = vm.call(&CONTRACT, "run", case.args.clone(), GAS_LIMIT).unwrap();
+    (vm, outcome.result, outcome.gas_used)
+}
+
+#[test]
+fn test_every_opcode_has_a_case() {
+    let covered: BTreeSet<u8> = cases().iter().map(|case| case.opcode as u8).collect();
+    let missing: Vec<_> = Opcode::ALL.iter().filter(|opcode| !covered.contains(&(**opcode as u8))).collect();
+    assert!(missing.is_empty(), "no conformance case for {:?}", missing);
+}
+
+#[test]
+fn test_every_case_in_both_tiers() {
+    for case in cases() {
+        let (mut interpreted, result, interpreted_gas) = run(&case, None);
+        assert_eq!(result, case.expected, "{} interpreted:\n    {}", case.opcode, case.body);
+        (case.check)(&mut interpreted);
+
+        let (mut compiled, result, compiled_gas) = run(&case, Some(0));
+        assert_eq!(result, case.expected, "{} compiled:\n    {}", case.opcode, case.body);
+        assert_eq!(compiled_gas, interpreted_gas, "{} gas:\n    {}", case.opcode, case.body);
+        (case.check)(&mut compiled);
+    }
+}
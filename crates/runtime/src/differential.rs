@@ -0,0 +1,147 @@
+//! Differential tests: every example contract, as `strxc --target bytecode`
+//! compiles it into `tests/fixtures/bytecode`, runs the same calls in the
+//! interpreter, with every function compiled up front, and tiered part way
+//! through. The tiers must agree on every result, trap, storage slot,
+//! event, balance and unit of gas.
+
+use std::path::{Path, PathBuf};
+
+use crate::bytecode::{assemble, Module, Type};
+use crate::num::U256;
+use crate::value::{Address, Value};
+use crate::vm::Vm;
+
+const GAS_LIMIT: u64 = 10_000_000;
+const ROUNDS: usize = 4;
+
+const CONTRACT: Address = [0xcc; 20];
+const ALICE: Address = [0xa1; 20];
+const BOB: Address = [0xb0; 20];
+
+fn fixtures() -> Vec<PathBuf> {
+    fn visit(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit(&path, files);
+            } else if path.extension().is_some_and(|ext| ext == "asm") {
+                files.push(path);
+            }
+        }
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/fixtures/bytecode");
+    let mut files = Vec::new();
+    visit(&root, &mut files);
+    files.sort();
+    files
+}
+
+fn load(path: &Path) -> Module {
+    let source = std::fs::read_to_string(path).unwrap();
+    assemble(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// A VM with `module` deployed, funded the same way in every tier
+fn vm(module: &Module, threshold: Option<u32>) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_jit_threshold(threshold);
+    vm.deploy(CONTRACT, module.clone()).unwrap();
+    vm.set_balance(CONTRACT, U256::from(1_000_000u64));
+    vm.set_balance(ALICE, U256::from(1_000_000u64));
+    vm
+}
+
+/// An argument of type `ty`, varied by round so that later rounds reach
+/// different branches
+fn sample(ty: &Type, round: usize) -> Value {
+    match ty {
+        Type::U256 => Value::U256(U256::from([1u64, 100, 0, 1_000_000_000_000_000_000][round % 4])),
+        Type::Address => Value::Address([ALICE, BOB][round % 2]),
+        Type::Bool => Value::Bool(round.is_multiple_of(2)),
+        Type::String => Value::String(format!("sample {}", round)),
+        Type::Bytes => Value::Bytes(vec![round as u8; round]),
+        Type::Array(element) => Value::Array((0..round % 3).map(|i| sample(element, round + i)).collect()),
+        Type::Tuple(elements) => Value::Tuple(elements.iter().map(|ty| sample(ty, round)).collect()),
+        Type::Map { .. } | Type::Named(_) | Type::Void => Value::U256(U256::from(round as u64)),
+    }
+}
+
+/// Every exported function with the arguments of `round`
+fn calls(module: &Module, round: usize) -> Vec<(String, Vec<Value>)> {
+    module.abi.functions.iter()
+        .map(|entry| {
+            let name = module.functions[entry.function as usize].name.clone();
+            let args = entry.params.iter().map(|param| sample(&param.ty, round)).collect();
+            (name, args)
+        })
+        .collect()
+}
+
+fn assert_same_state(context: &str, expected: &Vm, actual: &Vm) {
+    let (expected_slots, actual_slots) = (expected.storage().slots(&CONTRACT), actual.storage().slots(&CONTRACT));
+    assert_eq!(actual_slots, expected_slots, "{}: storage differs", context);
+    assert_eq!(actual.events(), expected.events(), "{}: events differ", context);
+    assert_eq!(actual.balances(), expected.balances(), "{}: balances differ", context);
+}
+
+#[test]
+fn test_tiers_agree_on_every_example() {
+    let fixtures = fixtures();
+    assert!(!fixtures.is_empty());
+
+    let (mut succeeded, mut compiled) = (0, 0);
+    for path in fixtures {
+        let module = load(&path);
+        let mut interpreter = vm(&module, None);
+        let mut tiers = [vm(&module, Some(0)), vm(&module, Some(2))];
+
+        for round in 0..ROUNDS {
+            for (name, args) in calls(&module, round) {
+                let sender = [ALICE, BOB][round % 2];
+                let context = format!("{} {}({:?}) round {}", path.display(), name, args, round);
+                interpreter.env.sender = sender;
+                let expected = interpreter.call(&CONTRACT, &name, args.clone(), GAS_LIMIT).unwrap();
+                succeeded += expected.result.is_ok() as usize;
+                for tier in &mut tiers {
+                    tier.env.sender = sender;
+                    let actual = tier.call(&CONTRACT, &name, args.clone(), GAS_LIMIT).unwrap();
+                    assert_eq!(actual, expected, "{}", context);
+                    assert_same_state(&context, &interpreter, tier);
+                }
+            }
+        }
+        compiled += tiers[0].compiled_functions(&CONTRACT).len();
+        assert!(!tiers[1].compiled_functions(&CONTRACT).is_empty(), "{}: nothing tiered up", path.display());
+    }
+    assert!(succeeded > 0, "no call ran to completion");
+    assert!(compiled > 0);
+}
+
+#[test]
+fn test_tiers_agree_when_gas_runs_out() {
+    for path in fixtures() {
+        let module = load(&path);
+        let mut interpreter = vm(&module, None);
+        let mut compiled = vm(&module, Some(0));
+
+        for (name, args) in calls(&module, 1) {
+            let full = interpreter.call(&CONTRACT, &name, args.clone(), GAS_LIMIT).unwrap();
+            compiled.call(&CONTRACT, &name, args.clone(), GAS_LIMIT).unwrap();
+            // Limits that stop in the middle of blocks, on block boundaries
+            // and just short of the end
+            let used = full.gas_used;
+            let mut limits = vec![0, 1, 2, 3, used / 3, used / 2, used.saturating_sub(1), used];
+            limits.extend((1..=20).map(|step| used * step / 21));
+            limits.sort();
+            limits.dedup();
+            for limit in limits {
+                let context = format!("{} {} with gas {}", path.display(), name, limit);
+                let expected = interpreter.call(&CONTRACT, &name, args.clone(), limit).unwrap();
+                let actual = compiled.call(&CONTRACT, &name, args.clone(), limit).unwrap();
+                assert_eq!(actual, expected, "{}", context);
+                assert_same_state(&context, &interpreter, &compiled);
+            }
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! The compiling tier: call-threaded native code generated with Cranelift.
+//!
+//! A compiled function keeps the bytecode's control flow native and calls
+//! back into [`Vm::execute`] for everything else, so values, storage
+//! and traps behave exactly as in the interpreter. What it changes is gas:
+//! each basic block prepays the static cost of all its instructions at
+//! once. Calls end a basic block, so a callee always finds the gas its
+//! caller would have had in the interpreter. When a block cannot prepay,
+//! the function returns the block's first pc and the interpreter takes
+//! over from there, running out of gas on the same instruction it would
+//! have anyway. When an instruction traps, the cost of the rest of its
+//! block is refunded.
+
+use cranelift::prelude::*;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use crate::bytecode::{self, Instruction};
+use crate::vm::{Flow, Vm};
+
+/// Compiled code for one function. Returns [`RETURNED`], [`TRAPPED`] with
+/// the trap left in [`Vm::trap`], or the pc to resume interpreting at.
+pub type Entry = unsafe extern "C" fn(*mut Vm) -> i64;
+
+pub const RETURNED: i64 = -1;
+pub const TRAPPED: i64 = -2;
+
+/// Status codes of [`exec`]
+const NEXT: i64 = 0;
+const TRAP: i64 = 1;
+const JUMP: i64 = 2;
+
+/// Runs one instruction for compiled code. Its gas has been prepaid.
+extern "C" fn exec(vm: *mut Vm, instruction: *const Instruction) -> i32 {
+    // SAFETY: compiled code passes the VM that called it and an
+    // instruction of a module that VM keeps alive.
+    let (vm, instruction) = unsafe { (&mut *vm, &*instruction) };
+    let status = match vm.execute(instruction) {
+        Ok(Flow::Next | Flow::Return) => NEXT,
+        Ok(Flow::Jump(_)) => JUMP,
+        Err(trap) => {
+            vm.trap = Some(trap);
+            TRAP
+        }
+    };
+    status as i32
+}
+
+pub struct Jit {
+    module: Option<JITModule>,
+    ctx: codegen::Context,
+    builder_ctx: FunctionBuilderContext,
+    exec: cranelift_module::FuncId,
+    /// Functions compiled so far, which keeps their symbols unique when
+    /// contracts share function names
+    compiled: u32,
+}
+
+impl Jit {
+    pub fn new() -> Result<Jit, String> {
+        let mut builder = JITBuilder::new(default_libcall_names()).map_err(|e| e.to_string())?;
+        builder.symbol("strxvm_exec", exec as *const u8);
+        let mut module = JITModule::new(builder);
+
+        let pointer = module.target_config().pointer_type();
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I32));
+        let exec = module
+            .declare_function("strxvm_exec", Linkage::Import, &signature)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Jit {
+            ctx: module.make_context(),
+            module: Some(module),
+            builder_ctx: FunctionBuilderContext::new(),
+            exec,
+            compiled: 0,
+        })
+    }
+
+    /// Compiles function `index` of `contract`. The returned entry is valid
+    /// for as long as this `Jit` and `contract`, whose instructions it
+    /// points into.
+    pub fn compile(&mut self, contract: &bytecode::Module, index: u32) -> Result<Entry, String> {
+        let module = self.module.as_mut().expect("only taken on drop");
+        let pointer = module.target_config().pointer_type();
+        self.ctx.func.signature.params.push(AbiParam::new(pointer));
+        self.ctx.func.signature.returns.push(AbiParam::new(types::I64));
+
+        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_ctx);
+        let exec = module.declare_func_in_func(self.exec, builder.func);
+        let function = &contract.functions[index as usize];
+        let code = &function.code;
+
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        let leaders = leaders(code);
+        let blocks: Vec<Option<Block>> = leaders.iter()
+            .map(|&leader| leader.then(|| builder.create_block()))
+            .collect();
+        let returned = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let vm = builder.block_params(entry)[0];
+        match blocks.first() {
+            Some(Some(first)) => builder.ins().jump(*first, &[]),
+            _ => builder.ins().jump(returned, &[]),
+        };
+
+        let gas_offset = std::mem::offset_of!(Vm, gas_left) as i32;
+        let block_of = |pc: usize| blocks.get(pc).copied().flatten().unwrap_or(returned);
+        let mut pc = 0;
+        while pc < code.len() {
+            let start = pc;
+            let end = (start + 1..code.len()).find(|&pc| leaders[pc]).unwrap_or(code.len());
+            let costs: Vec<u64> = code[start..end].iter().map(|instruction| contract.gas_cost(instruction)).collect();
+
+            // Prepay the block or hand it to the interpreter
+            builder.switch_to_block(block_of(start));
+            let gas = builder.ins().load(types::I64, MemFlags::trusted(), vm, gas_offset);
+            let cost = builder.ins().iconst(types::I64, costs.iter().sum::<u64>() as i64);
+            let short = builder.ins().icmp(IntCC::UnsignedLessThan, gas, cost);
+            let deopt = builder.create_block();
+            let paid = builder.create_block();
+            builder.ins().brif(short, deopt, &[], paid, &[]);
+            builder.switch_to_block(deopt);
+            let resume = builder.ins().iconst(types::I64, start as i64);
+            builder.ins().return_(&[resume]);
+            builder.switch_to_block(paid);
+            let left = builder.ins().isub(gas, cost);
+            builder.ins().store(MemFlags::trusted(), left, vm, gas_offset);
+
+            for (offset, instruction) in code[start..end].iter().enumerate() {
+                pc = start + offset;
+                match *instruction {
+                    Instruction::Jump(target) => {
+                        builder.ins().jump(block_of(target as usize), &[]);
+                        continue;
+                    }
+                    Instruction::Return => {
+                        builder.ins().jump(returned, &[]);
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                let address = builder.ins().iconst(pointer, instruction as *const Instruction as i64);
+                let call = builder.ins().call(exec, &[vm, address]);
+                let status = builder.inst_results(call)[0];
+
+                // Refund what the rest of the block prepaid
+                let trapped = builder.create_block();
+                let next = builder.create_block();
+                let is_trap = builder.ins().icmp_imm(IntCC::Equal, status, TRAP);
+                builder.ins().brif(is_trap, trapped, &[], next, &[]);
+                builder.switch_to_block(trapped);
+                let refund: u64 = costs[offset + 1..].iter().sum();
+                if refund > 0 {
+                    let gas = builder.ins().load(types::I64, MemFlags::trusted(), vm, gas_offset);
+                    let gas = builder.ins().iadd_imm(gas, refund as i64);
+                    builder.ins().store(MemFlags::trusted(), gas, vm, gas_offset);
+                }
+                let result = builder.ins().iconst(types::I64, TRAPPED);
+                builder.ins().return_(&[result]);
+
+                builder.switch_to_block(next);
+                match *instruction {
+                    Instruction::JumpIf(target) => {
+                        let taken = builder.ins().icmp_imm(IntCC::Equal, status, JUMP);
+                        builder.ins().brif(taken, block_of(target as usize), &[], block_of(pc + 1), &[]);
+                    }
+                    _ if pc + 1 == end => {
+                        builder.ins().jump(block_of(end), &[]);
+                    }
+                    _ => {}
+                }
+            }
+            pc = end;
+        }
+
+        builder.switch_to_block(returned);
+        let result = builder.ins().iconst(types::I64, RETURNED);
+        builder.ins().return_(&[result]);
+        builder.seal_all_blocks();
+        builder.finalize();
+
+        let name = format!("{}#{}", function.name, self.compiled);
+        self.compiled += 1;
+        let id = module
+            .declare_function(&name, Linkage::Local, &self.ctx.func.signature)
+            .map_err(|e| e.to_string())?;
+        let defined = module.define_function(id, &mut self.ctx).map_err(|e| format!("{}: {}", name, e));
+        module.clear_context(&mut self.ctx);
+        defined?;
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+        let code = module.get_finalized_function(id);
+        // SAFETY: the function was declared with the `Entry` signature.
+        Ok(unsafe { std::mem::transmute::<*const u8, Entry>(code) })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // SAFETY: entries are only called through the VM owning this
+            // `Jit`, which is being dropped.
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// Which pcs start a basic block: the entry, jump targets, and whatever
+/// follows a branch, a return, a revert or a call
+fn leaders(code: &[Instruction]) -> Vec<bool> {
+    let mut leaders = vec![false; code.len()];
+    if let Some(first) = leaders.first_mut() {
+        *first = true;
+    }
+    for (pc, instruction) in code.iter().enumerate() {
+        if let Instruction::Jump(target) | Instruction::JumpIf(target) = *instruction {
+            leaders[target as usize] = true;
+        }
+        let ends_block = matches!(
+            instruction,
+            Instruction::Jump(_)
+                | Instruction::JumpIf(_)
+                | Instruction::Return
+                | Instruction::Revert(_)
+                | Instruction::Call(..)
+                | Instruction::CallMethod(..)
+                | Instruction::CallContract(..)
+                | Instruction::CallHost(..)
+                | Instruction::CallIndirect(_)
+        );
+        if ends_block && pc + 1 < code.len() {
+            leaders[pc + 1] = true;
+        }
+    }
+    leaders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::assemble;
+    use crate::num::U256;
+    use crate::value::{Address, Trap, Value};
+
+    const LOOP: &str = "\
+.contract Loop
+.const #0 u256 0
+.const #1 u256 1
+.const #2 string \"done\"
+.storage 0 total: u256
+.export sum(n: u256) -> u256
+.export fail(n: u256)
+
+.function sum arity=1 locals=2
+    PUSH #0 ; u256 0
+    STORE 1
+L2:
+    LOAD 0
+    PUSH #0 ; u256 0
+    EQ
+    JUMPI L15
+    LOAD 1
+    LOAD 0
+    ADD
+    STORE 1
+    LOAD 0
+    PUSH #1 ; u256 1
+    SUB
+    STORE 0
+    JUMP L2
+L15:
+    LOAD 1
+    SSTORE 0
+    LOAD 1
+    RET
+
+.function fail arity=1 locals=1
+    LOAD 0
+    SSTORE 0
+    REVERT #2 ; string \"done\"
+";
+
+    const LOOP_ADDRESS: Address = [0x10; 20];
+
+    fn vm(threshold: Option<u32>) -> Vm {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(threshold);
+        vm.deploy(LOOP_ADDRESS, assemble(LOOP).unwrap()).unwrap();
+        vm
+    }
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    #[test]
+    fn test_leaders() {
+        let module = assemble(LOOP).unwrap();
+        let leaders = leaders(&module.functions[0].code);
+        let starts: Vec<usize> = (0..leaders.len()).filter(|&pc| leaders[pc]).collect();
+        assert_eq!(starts, vec![0, 2, 6, 15]);
+    }
+
+    #[test]
+    fn test_compiled_code_matches_the_interpreter() {
+        let mut interpreted = vm(None);
+        let mut compiled = vm(Some(0));
+        for n in [0, 1, 10] {
+            let expected = interpreted.call(&LOOP_ADDRESS, "sum", vec![word(n)], 1_000_000).unwrap();
+            let actual = compiled.call(&LOOP_ADDRESS, "sum", vec![word(n)], 1_000_000).unwrap();
+            assert_eq!(actual, expected);
+        }
+        assert_eq!(compiled.compiled_functions(&LOOP_ADDRESS), vec!["sum"]);
+        assert_eq!(compiled.storage().slots(&LOOP_ADDRESS), interpreted.storage().slots(&LOOP_ADDRESS));
+    }
+
+    #[test]
+    fn test_out_of_gas_in_every_block_matches_the_interpreter() {
+        let mut interpreted = vm(None);
+        let mut compiled = vm(Some(0));
+        let full = interpreted.call(&LOOP_ADDRESS, "sum", vec![word(3)], 1_000_000).unwrap().gas_used;
+        for limit in 0..=full {
+            let expected = interpreted.call(&LOOP_ADDRESS, "sum", vec![word(3)], limit).unwrap();
+            let actual = compiled.call(&LOOP_ADDRESS, "sum", vec![word(3)], limit).unwrap();
+            assert_eq!(actual, expected, "gas limit {}", limit);
+        }
+    }
+
+    #[test]
+    fn test_traps_refund_the_rest_of_the_block() {
+        let mut interpreted = vm(None);
+        let mut compiled = vm(Some(0));
+        let expected = interpreted.call(&LOOP_ADDRESS, "fail", vec![word(4)], 100_000).unwrap();
+        let actual = compiled.call(&LOOP_ADDRESS, "fail", vec![word(4)], 100_000).unwrap();
+        assert_eq!(actual.result, Err(Trap::Revert("done".into())));
+        assert_eq!(actual, expected);
+        assert!(compiled.storage().slots(&LOOP_ADDRESS).is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+//! The Stremax runtime: the one virtual machine that runs compiled
+//! contracts, shared by the `strxvm` binary, the test environments and the
+//! tools.
+//!
+//! A [`Vm`] holds any number of [`bytecode::Module`]s deployed at an
+//! [`Address`], reads and writes their slots through a [`StateBackend`],
+//! and keeps balances and the events they emit. A [`Scheduler`] delivers
+//! the messages contracts send each other in a reproducible order, and
+//! [`Capabilities`] record who may do what to which contract. A
+//! [`StateTree`] over the storage commits to it with a single root, a
+//! [`Tracer`] watches calls run instruction by instruction, the
+//! [`StructLogger`] and [`CallTracer`] export what it sees, and a
+//! [`Recorder`] keeps a call as an undo log to move through both ways.
+//! Functions start out interpreted and are compiled to native code once
+//! they are hot; the two tiers agree on every result, trap and unit of
+//! gas.
+
+#[path = "../../../src/bytecode/mod.rs"]
+#[allow(dead_code, unused_imports)] // shared with strxc, which uses a different subset
+pub mod bytecode;
+#[path = "../../../src/num/mod.rs"]
+#[allow(dead_code, unused_imports)] // shared with strxc
+pub mod num;
+pub mod actors;
+pub mod capabilities;
+pub mod merkle;
+pub mod record;
+pub mod state;
+pub mod trace;
+pub mod value;
+mod jit;
+mod vm;
+#[cfg(test)]
+mod conformance;
+#[cfg(test)]
+mod differential;
+#[cfg(test)]
+mod reentrancy;
+
+pub use actors::{DeadLetter, Delivery, Message, Policy, Scheduler, SchedulerConfig};
+pub use capabilities::{Capabilities, Grant};
+pub use merkle::{StateTree, StorageProof};
+pub use record::{ExecutionStep, MemorySnapshot, Recorder, Recording};
+pub use state::{FileBackend, MemoryBackend, StateBackend};
+pub use trace::{CallTrace, CallTracer, Step, StructLog, StructLogger, Trace, TraceHandle, TraceKind, Tracer};
+pub use value::{Address, Event, Log, Trap, Value};
+pub use vm::{Environment, Outcome, Vm, DEFAULT_JIT_THRESHOLD, MAX_CALL_DEPTH, RUNTIME_ADDRESS};
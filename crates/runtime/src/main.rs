@@ -0,0 +1,181 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use stremax_runtime::bytecode;
+use stremax_runtime::{Address, FileBackend, TraceHandle, TraceKind, Value, Vm};
+
+#[path = "../../../src/core/error.rs"]
+#[allow(dead_code, clippy::crate_in_macro_def, clippy::enum_variant_names)] // shared with the tools
+mod error;
+#[path = "../../../src/tools/debugger/mod.rs"]
+#[allow(dead_code)] // shared with the tools, which use the rest of its interface
+mod debugger;
+
+/// The tools' error type, as the debugger refers to it
+mod core {
+    pub use super::error::{Error, Result};
+}
+
+const USAGE: &str = "\
+Usage: strxvm <COMMAND>
+
+Commands:
+  disasm <FILE>                        Print the assembly listing of a .strxb file
+  run <FILE> <FUNCTION> [ARG]...       Call FUNCTION of a .strxb or .asm file
+  debug <SOURCE> <FILE> <FUNCTION> [ARG]...
+                                       Debug a call of FUNCTION with SOURCE as its source
+  dap [--port <PORT>]                  Serve the Debug Adapter Protocol on stdio or a TCP port
+  -h, --help                           Print this help
+  -V, --version                        Print version
+
+Run options:
+  --gas <N>               Gas limit [default: 10000000]
+  --jit-threshold <N>     Compile a function after N interpreted calls [default: 10]
+  --no-jit                Only interpret
+  --state <DIR>           Keep contract storage in DIR across runs
+  --trace <struct|calls>  Write each instruction or the call tree as JSON lines to stderr
+  --trace-out <FILE>      Write the trace to FILE instead";
+
+const DEFAULT_GAS: u64 = 10_000_000;
+
+/// Where `run` deploys the contract
+const CONTRACT: Address = [0xcc; 20];
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["disasm", path] => disasm(Path::new(path)),
+        ["run", rest @ ..] => match parse_run(rest) {
+            Some(options) => run(options),
+            None => usage(),
+        },
+        ["debug", source, rest @ ..] => match parse_run(rest) {
+            Some(options) => debug(source, options),
+            None => usage(),
+        },
+        ["dap"] => debugger::dap::serve_stdio().map_err(|e| e.to_string()),
+        ["dap", "--port", port] => match port.parse() {
+            Ok(port) => debugger::dap::serve_tcp(port).map_err(|e| e.to_string()),
+            Err(_) => usage(),
+        },
+        ["-h" | "--help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        ["-V" | "--version"] => {
+            println!("Stremax VM v{}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        _ => usage(),
+    };
+    if let Err(msg) = result {
+        eprintln!("error: {}", msg);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn disasm(path: &Path) -> Result<(), String> {
+    print!("{}", bytecode::disassemble(&bytecode::load(path)?));
+    Ok(())
+}
+
+struct RunOptions<'a> {
+    file: &'a str,
+    function: &'a str,
+    args: Vec<&'a str>,
+    gas: u64,
+    jit_threshold: Option<u32>,
+    state: Option<&'a str>,
+    trace: Option<TraceKind>,
+    trace_out: Option<&'a str>,
+}
+
+fn parse_run<'a>(args: &[&'a str]) -> Option<RunOptions<'a>> {
+    let mut positional = Vec::new();
+    let mut gas = DEFAULT_GAS;
+    let mut jit_threshold = Some(stremax_runtime::DEFAULT_JIT_THRESHOLD);
+    let mut state = None;
+    let mut trace = None;
+    let mut trace_out = None;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--gas" => gas = args.next()?.parse().ok()?,
+            "--jit-threshold" => jit_threshold = Some(args.next()?.parse().ok()?),
+            "--no-jit" => jit_threshold = None,
+            "--state" => state = Some(*args.next()?),
+            "--trace" => trace = Some(args.next()?.parse().ok()?),
+            "--trace-out" => trace_out = Some(*args.next()?),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() < 2 {
+        return None;
+    }
+    let args = positional.split_off(2);
+    Some(RunOptions { file: positional[0], function: positional[1], args, gas, jit_threshold, state, trace, trace_out })
+}
+
+fn run(options: RunOptions) -> Result<(), String> {
+    let module = bytecode::load(Path::new(options.file))?;
+    let args = Value::parse_args(&module, options.function, &options.args)?;
+
+    let mut vm = open_vm(&options)?;
+    vm.deploy(CONTRACT, module)?;
+    let trace = options.trace.map(TraceHandle::new);
+    if let Some(trace) = &trace {
+        vm.set_tracer(Some(trace.tracer()));
+    }
+    let outcome = vm.call(&CONTRACT, options.function, args, options.gas)?;
+    if let Some(trace) = trace {
+        let lines = trace.finish().to_json_lines();
+        match options.trace_out {
+            Some(path) => fs::write(path, lines).map_err(|e| format!("{}: {}", path, e))?,
+            None => eprint!("{}", lines),
+        }
+    }
+    for log in vm.logs() {
+        println!("log: {}", log.message);
+    }
+    for event in vm.events() {
+        let fields: Vec<String> = event.fields.iter().map(Value::to_string).collect();
+        println!("event {}({})", event.name, fields.join(", "));
+    }
+    println!("gas used: {}", outcome.gas_used);
+    match outcome.result {
+        Ok(Some(value)) => println!("{}", value),
+        Ok(None) => {}
+        Err(trap) => return Err(trap.to_string()),
+    }
+    Ok(())
+}
+
+fn debug(source: &str, options: RunOptions) -> Result<(), String> {
+    let text = fs::read_to_string(source).map_err(|e| format!("{}: {}", source, e))?;
+    let module = bytecode::load(Path::new(options.file))?;
+    let args = Value::parse_args(&module, options.function, &options.args)?;
+
+    let mut vm = open_vm(&options)?;
+    vm.deploy(CONTRACT, module)?;
+    let mut debugger = debugger::Debugger::new(vm);
+    debugger.launch(&CONTRACT, options.function, args, options.gas).map_err(|e| e.to_string())?;
+    let stdin = io::stdin();
+    debugger::repl::Repl::new(debugger, source, &text, io::stdout()).run(stdin.lock()).map_err(|e| e.to_string())
+}
+
+fn open_vm(options: &RunOptions) -> Result<Vm, String> {
+    let mut vm = match options.state {
+        Some(dir) => Vm::with_storage(Box::new(FileBackend::open(dir)?))?,
+        None => Vm::new(),
+    };
+    vm.set_jit_threshold(options.jit_threshold);
+    Ok(vm)
+}
@@ -0,0 +1,278 @@
+//! Authenticated contract storage: a sparse Merkle tree over every
+//! written slot, whose root commits to the whole state and whose proofs
+//! show that a slot holds a value, or holds nothing, to anyone who only
+//! knows the root.
+//!
+//! A slot's key is the SHA-256 of the contract address and the slot
+//! number, and its bits, most significant first, give its path from the
+//! root. A subtree with no slots hashes to [`EMPTY`] and one with a single
+//! slot to that slot's leaf hash, wherever the slot sits below it, so the
+//! tree is only as deep as it needs to be to separate the keys. Leaves and
+//! inner nodes hash with different prefixes so neither can pass for the
+//! other.
+
+use std::collections::BTreeMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::num::U256;
+use crate::state::StateBackend;
+use crate::value::{Address, Value};
+
+pub type Hash = [u8; 32];
+
+/// The root of a tree with no slots
+pub const EMPTY: Hash = [0; 32];
+
+/// Where a slot sits in the tree
+pub fn key(contract: &Address, slot: &U256) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(contract);
+    hasher.update(slot.to_be_bytes());
+    hasher.finalize().into()
+}
+
+/// What a slot's leaf commits to
+pub fn value_hash(value: &Value) -> Hash {
+    Sha256::digest(value.to_bytes()).into()
+}
+
+fn leaf_hash(key: &Hash, value_hash: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0]);
+    hasher.update(key);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Whether `key` goes right at `depth`
+fn bit(key: &Hash, depth: usize) -> bool {
+    key[depth / 8] >> (7 - depth % 8) & 1 == 1
+}
+
+/// The written slots of a state, by key
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateTree {
+    leaves: BTreeMap<Hash, Hash>,
+}
+
+impl StateTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The tree over every written slot of `contracts`
+    pub fn from_storage<'a>(storage: &dyn StateBackend, contracts: impl IntoIterator<Item = &'a Address>) -> Self {
+        let mut tree = StateTree::new();
+        for contract in contracts {
+            for (slot, value) in storage.slots(contract) {
+                tree.insert(contract, &slot, &value);
+            }
+        }
+        tree
+    }
+
+    pub fn insert(&mut self, contract: &Address, slot: &U256, value: &Value) {
+        self.leaves.insert(key(contract, slot), value_hash(value));
+    }
+
+    pub fn remove(&mut self, contract: &Address, slot: &U256) {
+        self.leaves.remove(&key(contract, slot));
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn root(&self) -> Hash {
+        let leaves: Vec<_> = self.leaves.iter().map(|(key, value)| (*key, *value)).collect();
+        subtree(&leaves, 0)
+    }
+
+    /// Proves what the slot holds: its leaf if it is written, otherwise
+    /// the empty subtree or the other slot's leaf found on its path
+    pub fn prove(&self, contract: &Address, slot: &U256) -> StorageProof {
+        let key = key(contract, slot);
+        let leaves: Vec<_> = self.leaves.iter().map(|(key, value)| (*key, *value)).collect();
+        let mut siblings = Vec::new();
+        let mut path = leaves.as_slice();
+        while path.len() > 1 {
+            let depth = siblings.len();
+            let (left, right) = path.split_at(path.partition_point(|(key, _)| !bit(key, depth)));
+            if bit(&key, depth) {
+                siblings.push(subtree(left, depth + 1));
+                path = right;
+            } else {
+                siblings.push(subtree(right, depth + 1));
+                path = left;
+            }
+        }
+        StorageProof { siblings, leaf: path.first().copied() }
+    }
+}
+
+/// The hash of the subtree at `depth` holding `leaves`, which are sorted
+/// and share their first `depth` bits
+fn subtree(leaves: &[(Hash, Hash)], depth: usize) -> Hash {
+    match leaves {
+        [] => EMPTY,
+        [(key, value)] => leaf_hash(key, value),
+        _ => {
+            let (left, right) = leaves.split_at(leaves.partition_point(|(key, _)| !bit(key, depth)));
+            node_hash(&subtree(left, depth + 1), &subtree(right, depth + 1))
+        }
+    }
+}
+
+/// The path from the root to a slot's position in the tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageProof {
+    /// Hash of the subtree beside the path at each depth, from the root
+    /// down
+    pub siblings: Vec<Hash>,
+    /// The only leaf in the subtree the path ends in, as its key and value
+    /// hash, or `None` if the subtree is empty
+    pub leaf: Option<(Hash, Hash)>,
+}
+
+impl StorageProof {
+    /// Checks the proof against `root`: that the slot holds `value`, or,
+    /// for `None`, that it was never written or has been cleared
+    pub fn verify(&self, root: &Hash, contract: &Address, slot: &U256, value: Option<&Value>) -> bool {
+        let key = key(contract, slot);
+        if self.siblings.len() > 256 {
+            return false;
+        }
+        let proves = match (&self.leaf, value) {
+            (Some((leaf_key, hash)), Some(value)) => *leaf_key == key && *hash == value_hash(value),
+            // Another slot may only end the path if it shares the path
+            (Some((leaf_key, _)), None) => {
+                *leaf_key != key && (0..self.siblings.len()).all(|depth| bit(leaf_key, depth) == bit(&key, depth))
+            }
+            (None, value) => value.is_none(),
+        };
+        if !proves {
+            return false;
+        }
+
+        let mut hash = self.leaf.as_ref().map_or(EMPTY, |(key, value)| leaf_hash(key, value));
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if bit(&key, depth) { node_hash(sibling, &hash) } else { node_hash(&hash, sibling) };
+        }
+        hash == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    fn slot(n: u64) -> U256 {
+        U256::from(n)
+    }
+
+    const A: Address = [0xaa; 20];
+    const B: Address = [0xbb; 20];
+
+    fn tree(count: u64) -> StateTree {
+        let mut tree = StateTree::new();
+        for n in 0..count {
+            tree.insert(&A, &slot(n), &word(n * 10));
+        }
+        tree.insert(&B, &slot(0), &Value::Bool(true));
+        tree
+    }
+
+    #[test]
+    fn test_root_depends_on_every_slot_but_not_on_order() {
+        assert_eq!(StateTree::new().root(), EMPTY);
+        let root = tree(20).root();
+        assert_ne!(root, EMPTY);
+
+        let mut reversed = StateTree::new();
+        reversed.insert(&B, &slot(0), &Value::Bool(true));
+        for n in (0..20).rev() {
+            reversed.insert(&A, &slot(n), &word(n * 10));
+        }
+        assert_eq!(reversed.root(), root);
+
+        let mut changed = tree(20);
+        changed.insert(&A, &slot(7), &word(71));
+        assert_ne!(changed.root(), root);
+        changed.insert(&A, &slot(7), &word(70));
+        assert_eq!(changed.root(), root);
+        changed.remove(&B, &slot(0));
+        assert_ne!(changed.root(), root);
+    }
+
+    #[test]
+    fn test_inclusion_proofs() {
+        let tree = tree(20);
+        let root = tree.root();
+        for n in 0..20 {
+            let proof = tree.prove(&A, &slot(n));
+            assert!(proof.verify(&root, &A, &slot(n), Some(&word(n * 10))));
+            assert!(!proof.verify(&root, &A, &slot(n), Some(&word(n * 10 + 1))));
+            assert!(!proof.verify(&root, &A, &slot(n), None));
+            assert!(!proof.verify(&root, &B, &slot(n), Some(&word(n * 10))));
+        }
+        // A single slot is the whole tree
+        let mut single = StateTree::new();
+        single.insert(&A, &slot(1), &word(1));
+        let proof = single.prove(&A, &slot(1));
+        assert!(proof.siblings.is_empty());
+        assert!(proof.verify(&single.root(), &A, &slot(1), Some(&word(1))));
+    }
+
+    #[test]
+    fn test_exclusion_proofs() {
+        let tree = tree(20);
+        let root = tree.root();
+        let mut ended_at_a_leaf = false;
+        for n in 20..60 {
+            let proof = tree.prove(&A, &slot(n));
+            ended_at_a_leaf |= proof.leaf.is_some();
+            assert!(proof.verify(&root, &A, &slot(n), None));
+            assert!(!proof.verify(&root, &A, &slot(n), Some(&word(0))));
+        }
+        assert!(ended_at_a_leaf);
+        assert!(StateTree::new().prove(&A, &slot(0)).verify(&EMPTY, &A, &slot(0), None));
+
+        // A written slot's own proof cannot be passed off as exclusion,
+        // and neither can another slot's leaf off the path
+        let proof = tree.prove(&A, &slot(3));
+        assert!(!proof.verify(&root, &A, &slot(3), None));
+        let mut forged = tree.prove(&A, &slot(40));
+        forged.leaf = Some((key(&B, &slot(0)), value_hash(&Value::Bool(true))));
+        assert!(!forged.verify(&root, &A, &slot(40), None));
+    }
+
+    #[test]
+    fn test_proofs_are_bound_to_the_root() {
+        let tree = tree(5);
+        let proof = tree.prove(&A, &slot(2));
+        let mut later = tree.clone();
+        later.insert(&A, &slot(4), &word(0));
+        assert!(!proof.verify(&later.root(), &A, &slot(2), Some(&word(20))));
+
+        let mut tampered = proof.clone();
+        tampered.siblings[0][0] ^= 1;
+        assert!(!tampered.verify(&tree.root(), &A, &slot(2), Some(&word(20))));
+    }
+}
@@ -0,0 +1,383 @@
+//! Recording a call as an undo log, to move through it in either direction.
+//!
+//! A [`Recorder`] is a [`Tracer`] that keeps one [`ExecutionStep`] per
+//! instruction: where it ran, its gas, and the [`Change`]s it made to the
+//! frames' locals and operands, to storage and to the events. Each change
+//! holds the values on both sides of it, so a [`MemorySnapshot`] of the
+//! state before one instruction becomes the state before the next with
+//! [`MemorySnapshot::apply`], and back again with [`MemorySnapshot::undo`].
+//! Only what changed is kept; a whole snapshot is never stored per step.
+
+use std::cell::RefCell;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+
+use crate::bytecode::Instruction;
+use crate::num::U256;
+use crate::trace::{Step, Tracer};
+use crate::value::{Address, Event, Value};
+use crate::vm::Vm;
+
+/// A frame's memory: its locals and its operands, the top last
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameMemory {
+    pub locals: Vec<Value>,
+    pub stack: Vec<Value>,
+}
+
+/// The state of a recorded call just before one of its instructions runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemorySnapshot {
+    /// Every frame on the call stack, the transaction's first. A caller's
+    /// operands are shown as they were when it made the call.
+    pub frames: Vec<FrameMemory>,
+    /// Every slot the call writes at some point, with its value here
+    pub storage: BTreeMap<(Address, U256), Value>,
+    /// Events emitted by the call so far
+    pub events: Vec<Event>,
+}
+
+/// One difference between the states before two consecutive instructions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The top frame's operands above the first `keep` went from `popped`
+    /// to `pushed`
+    Stack { keep: usize, popped: Vec<Value>, pushed: Vec<Value> },
+    /// A local of the top frame
+    Local { slot: u32, old: Value, new: Value },
+    /// A call entered a new frame
+    Enter(FrameMemory),
+    /// The top frame returned or trapped, holding this memory
+    Leave(FrameMemory),
+    Storage { contract: Address, slot: U256, old: Value, new: Value },
+    /// The events after the first `keep` went from `removed` to `added`,
+    /// which only removes any when a call traps
+    Events { keep: usize, removed: Vec<Event>, added: Vec<Event> },
+}
+
+/// An instruction of a recorded call and what running it changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionStep {
+    pub contract: Address,
+    pub function: u32,
+    pub pc: usize,
+    /// Frames on the call stack, 1 in the function the transaction called
+    pub depth: usize,
+    /// Gas left before the instruction is charged
+    pub gas_left: u64,
+    pub gas_cost: u64,
+    /// Empty for the last instruction, which ends the call
+    pub changes: Vec<Change>,
+}
+
+/// A recorded call
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub steps: Vec<ExecutionStep>,
+    /// The state before the first instruction
+    pub initial: MemorySnapshot,
+}
+
+impl Recording {
+    /// The state before instruction `index`, replayed from the start
+    pub fn snapshot_at(&self, index: usize) -> MemorySnapshot {
+        let mut snapshot = self.initial.clone();
+        for step in &self.steps[..index.min(self.steps.len())] {
+            snapshot.apply(step);
+        }
+        snapshot
+    }
+
+    /// The last instruction before instruction `before` that changed
+    /// `slot` of `contract`, with the value it held before and after
+    pub fn last_write(&self, before: usize, contract: &Address, slot: &U256) -> Option<(usize, &Value, &Value)> {
+        self.steps[..before.min(self.steps.len())].iter().enumerate().rev()
+            .find_map(|(index, step)| step.changes.iter().rev().find_map(|change| match change {
+                Change::Storage { contract: c, slot: s, old, new } if c == contract && s == slot => {
+                    Some((index, old, new))
+                }
+                _ => None,
+            }))
+    }
+}
+
+impl MemorySnapshot {
+    /// Moves from the state before `step` to the state after it
+    pub fn apply(&mut self, step: &ExecutionStep) {
+        for change in &step.changes {
+            self.change(change, true);
+        }
+    }
+
+    /// Moves from the state after `step` back to the state before it
+    pub fn undo(&mut self, step: &ExecutionStep) {
+        for change in step.changes.iter().rev() {
+            self.change(change, false);
+        }
+    }
+
+    fn change(&mut self, change: &Change, forward: bool) {
+        match change {
+            Change::Stack { keep, popped, pushed } => {
+                let stack = &mut self.top().stack;
+                stack.truncate(*keep);
+                stack.extend(if forward { pushed } else { popped }.iter().cloned());
+            }
+            Change::Local { slot, old, new } => {
+                self.top().locals[*slot as usize] = if forward { new } else { old }.clone();
+            }
+            Change::Enter(frame) | Change::Leave(frame) => {
+                if forward == matches!(change, Change::Enter(_)) {
+                    self.frames.push(frame.clone());
+                } else {
+                    self.frames.pop();
+                }
+            }
+            Change::Storage { contract, slot, old, new } => {
+                self.storage.insert((*contract, *slot), if forward { new } else { old }.clone());
+            }
+            Change::Events { keep, removed, added } => {
+                self.events.truncate(*keep);
+                self.events.extend(if forward { added } else { removed }.iter().cloned());
+            }
+        }
+    }
+
+    fn top(&mut self) -> &mut FrameMemory {
+        self.frames.last_mut().expect("a recorded instruction runs in a frame")
+    }
+}
+
+/// Records the calls made while it is the VM's tracer
+#[derive(Clone, Default)]
+pub struct Recorder(Rc<RefCell<RecorderState>>);
+
+#[derive(Default)]
+struct RecorderState {
+    recording: Recording,
+    /// The state before the latest instruction
+    current: MemorySnapshot,
+    /// Events the VM held before the call
+    events_before: usize,
+    /// The slot the latest instruction writes, if it is a store
+    storing: Option<(Address, U256)>,
+}
+
+impl Recorder {
+    /// A recorder for the next call `vm` makes
+    pub fn new(vm: &Vm) -> Recorder {
+        let state = RecorderState { events_before: vm.events().len(), ..Default::default() };
+        Recorder(Rc::new(RefCell::new(state)))
+    }
+
+    /// What has been recorded so far, leaving the recorder empty
+    pub fn finish(&self) -> Recording {
+        mem::take(&mut self.0.borrow_mut().recording)
+    }
+}
+
+impl Tracer for Recorder {
+    fn step(&mut self, vm: &Vm, step: &Step<'_>) {
+        let mut state = self.0.borrow_mut();
+        let state = &mut *state;
+        let frame = FrameMemory { locals: step.locals.to_vec(), stack: step.stack.to_vec() };
+        if state.recording.steps.is_empty() {
+            state.current.frames = vec![frame];
+            state.recording.initial = state.current.clone();
+        } else {
+            let changes = state.changes(vm, step.depth, frame);
+            for change in &changes {
+                state.current.change(change, true);
+            }
+            state.recording.steps.last_mut().expect("checked above").changes = changes;
+        }
+
+        // A store's slot is known before it runs, and so is its old value
+        state.storing = match step.instruction {
+            Instruction::SStore(slot) => Some((step.contract, U256::from(*slot))),
+            Instruction::SStoreAt => match step.stack.iter().rev().nth(1) {
+                Some(Value::U256(slot)) => Some((step.contract, *slot)),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some((contract, slot)) = state.storing {
+            if let Entry::Vacant(entry) = state.current.storage.entry((contract, slot)) {
+                let value = entry.insert(vm.storage_at(&contract, slot));
+                state.recording.initial.storage.insert((contract, slot), value.clone());
+            }
+        }
+
+        state.recording.steps.push(ExecutionStep {
+            contract: step.contract,
+            function: step.function,
+            pc: step.pc,
+            depth: step.depth,
+            gas_left: step.gas_left,
+            gas_cost: step.gas_cost,
+            changes: Vec::new(),
+        });
+    }
+}
+
+impl RecorderState {
+    /// What the latest instruction changed, now that `frame` at `depth` is
+    /// about to run the next one
+    fn changes(&self, vm: &Vm, depth: usize, frame: FrameMemory) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let frames = &self.current.frames;
+        let returned = depth < frames.len();
+        if depth > frames.len() {
+            changes.push(Change::Enter(frame));
+        } else {
+            changes.extend(frames[depth..].iter().rev().cloned().map(Change::Leave));
+            let top = &frames[depth - 1];
+            for (slot, (old, new)) in top.locals.iter().zip(&frame.locals).enumerate() {
+                if old != new {
+                    changes.push(Change::Local { slot: slot as u32, old: old.clone(), new: new.clone() });
+                }
+            }
+            let keep = top.stack.iter().zip(&frame.stack).take_while(|(old, new)| old == new).count();
+            if keep < top.stack.len() || keep < frame.stack.len() {
+                changes.push(Change::Stack {
+                    keep,
+                    popped: top.stack[keep..].to_vec(),
+                    pushed: frame.stack[keep..].to_vec(),
+                });
+            }
+        }
+
+        // Only a store writes a slot, but a call that traps rolls back all
+        // of its writes
+        let written: Vec<(Address, U256)> = if returned {
+            self.current.storage.keys().copied().collect()
+        } else {
+            self.storing.into_iter().collect()
+        };
+        for (contract, slot) in written {
+            let old = &self.current.storage[&(contract, slot)];
+            let new = vm.storage_at(&contract, slot);
+            if *old != new {
+                changes.push(Change::Storage { contract, slot, old: old.clone(), new });
+            }
+        }
+
+        let events = &vm.events()[self.events_before..];
+        let keep = self.current.events.len().min(events.len());
+        if keep < self.current.events.len() || keep < events.len() {
+            changes.push(Change::Events {
+                keep,
+                removed: self.current.events[keep..].to_vec(),
+                added: events[keep..].to_vec(),
+            });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::assemble;
+
+    /// Stores its argument through a helper that emits it, then has a
+    /// call to itself store twice as much, emit it and revert
+    const VAULT: &str = "\
+.contract Vault
+.const #0 string \"no\"
+.const #1 string \"self\"
+.const #2 string \"refuse\"
+.storage 0 stored: u256
+.event Stored(value: u256)
+.export put(value: u256)
+.export refuse(value: u256)
+
+.function put arity=1 locals=2
+    LOAD 0
+    CALL keep 1
+    STORE 1
+    ENV #1 ; string \"self\"
+    LOAD 1
+    CALLM #2 1 ; string \"refuse\"
+    POP
+    RET
+
+.function keep arity=1 locals=1
+    LOAD 0
+    SSTORE 0
+    LOAD 0
+    EMIT Stored 1
+    LOAD 0
+    RET
+
+.function refuse arity=1 locals=1
+    LOAD 0
+    LOAD 0
+    ADD
+    SSTORE 0
+    SLOAD 0
+    EMIT Stored 1
+    REVERT #0 ; string \"no\"
+";
+
+    const VAULT_ADDRESS: Address = [0x5a; 20];
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    #[test]
+    fn test_recording_moves_both_ways() {
+        let mut vm = Vm::new();
+        vm.deploy(VAULT_ADDRESS, assemble(VAULT).unwrap()).unwrap();
+        let recorder = Recorder::new(&vm);
+        vm.set_tracer(Some(Box::new(recorder.clone())));
+        let outcome = vm.call(&VAULT_ADDRESS, "put", vec![word(7)], 100_000).unwrap();
+        vm.set_tracer(None);
+        let recording = recorder.finish();
+        assert_eq!(outcome.result, Ok(None));
+
+        let steps = &recording.steps;
+        let depths: Vec<usize> = steps.iter().map(|step| step.depth).collect();
+        assert_eq!(depths, [1, 1, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 1, 1]);
+        assert_eq!(recording.initial.frames, [FrameMemory { locals: vec![word(7), word(0)], stack: vec![] }]);
+        assert_eq!(recording.initial.storage, BTreeMap::from([((VAULT_ADDRESS, U256::ZERO), word(0))]));
+        assert!(steps.last().unwrap().changes.is_empty());
+
+        // Replaying forwards and undoing backwards meet at every step
+        let mut snapshot = recording.initial.clone();
+        let mut forwards = vec![snapshot.clone()];
+        for step in steps {
+            snapshot.apply(step);
+            forwards.push(snapshot.clone());
+        }
+        for (index, step) in steps.iter().enumerate().rev() {
+            snapshot.undo(step);
+            assert_eq!(snapshot, forwards[index], "at step {}", index);
+        }
+        assert_eq!(recording.snapshot_at(8), forwards[8]);
+
+        // Back in `put` after `keep` returned its argument
+        assert_eq!(forwards[8].frames, [FrameMemory { locals: vec![word(7), word(0)], stack: vec![word(7)] }]);
+        assert_eq!(forwards[8].storage[&(VAULT_ADDRESS, U256::ZERO)], word(7));
+        assert_eq!(forwards[8].events.len(), 1);
+
+        // `refuse` at its `REVERT`, with its caller's operands as it called
+        let reverting = &forwards[18];
+        assert_eq!(reverting.frames.len(), 2);
+        assert_eq!(reverting.frames[0].stack, [Value::Address(VAULT_ADDRESS), word(7)]);
+        assert_eq!(reverting.storage[&(VAULT_ADDRESS, U256::ZERO)], word(14));
+        assert_eq!(reverting.events.len(), 2);
+        assert_eq!(forwards[19].events.len(), 1);
+
+        // The rollback wrote the slot last, then `refuse`, then `keep`
+        let writes = |before| recording.last_write(before, &VAULT_ADDRESS, &U256::ZERO)
+            .map(|(index, old, new)| (steps[index].function, steps[index].pc, old.clone(), new.clone()));
+        assert_eq!(writes(steps.len()), Some((2, 6, word(14), word(7))));
+        assert_eq!(writes(18), Some((2, 3, word(7), word(14))));
+        assert_eq!(writes(15), Some((1, 1, word(0), word(7))));
+        assert_eq!(writes(3), None);
+    }
+}
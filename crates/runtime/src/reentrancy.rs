@@ -0,0 +1,267 @@
+//! Replays the classic reentrancy drain against the `examples/defi` pool,
+//! as strxc compiles it into `tests/fixtures/bytecode`. One of the pool's tokens
+//! calls back the recipient of every transfer, like an ERC-777 token, and
+//! the attacker uses that callback to swap again before the pool has
+//! updated its reserves, so every nested swap is priced as if it were the
+//! first. `@no_reentry` on `swap` must stop it.
+
+use std::path::Path;
+
+use crate::bytecode::{assemble, Instruction, Module};
+use crate::num::U256;
+use crate::value::{Address, Trap, Value};
+use crate::vm::Vm;
+
+const GAS_LIMIT: u64 = 10_000_000;
+
+const POOL: Address = [0x01; 20];
+const TOKEN_A: Address = [0x0a; 20];
+const TOKEN_B: Address = [0x0b; 20];
+const ATTACKER: Address = [0xee; 20];
+const PROVIDER: Address = [0x1b; 20];
+
+/// A token that calls `tokens_received` on the recipient of a `transfer`
+/// once the recipient has registered with `hook`
+const HOOK_TOKEN: &str = "\
+.contract HookToken
+.const #0 string \"msg.sender\"
+.const #1 string \"tokens_received\"
+.const #2 string \"Ok\"
+.const #3 u256 0
+.storage 0 balances: map<address, u256>
+.storage 1 hooked: address
+.export mint(to: address, amount: u256)
+.export hook(account: address)
+.export balance_of(account: address) -> u256
+.export transfer(to: address, amount: u256) -> Result<(), Error>
+.export transfer_from(from: address, to: address, amount: u256) -> Result<(), Error>
+
+.function mint arity=2 locals=2
+    PUSH #3 ; u256 0
+    LOAD 0
+    MAPSLOT
+    DUP 0
+    SLOADAT
+    LOAD 1
+    ADD
+    SSTOREAT
+    RET
+
+.function hook arity=1 locals=1
+    LOAD 0
+    SSTORE 1
+    RET
+
+.function balance_of arity=1 locals=1
+    PUSH #3 ; u256 0
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function transfer arity=2 locals=2
+    ENV #0 ; string \"msg.sender\"
+    LOAD 0
+    LOAD 1
+    CALL move 3
+    LOAD 0
+    SLOAD 1
+    EQ
+    NOT
+    JUMPI done
+    LOAD 0
+    LOAD 1
+    CALLM #1 1 ; string \"tokens_received\"
+    POP
+done:
+    TUPLE 0
+    VARIANT #2 1 ; string \"Ok\"
+    RET
+
+.function transfer_from arity=3 locals=3
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    CALL move 3
+    TUPLE 0
+    VARIANT #2 1 ; string \"Ok\"
+    RET
+
+.function move arity=3 locals=3
+    PUSH #3 ; u256 0
+    LOAD 0
+    MAPSLOT
+    DUP 0
+    SLOADAT
+    LOAD 2
+    SUB
+    SSTOREAT
+    PUSH #3 ; u256 0
+    LOAD 1
+    MAPSLOT
+    DUP 0
+    SLOADAT
+    LOAD 2
+    ADD
+    SSTOREAT
+    RET
+";
+
+/// Swaps on `pool`, then swaps the same amount again from inside the
+/// token callback, `reentries` more times
+const ATTACKER_CONTRACT: &str = "\
+.contract Attacker
+.const #0 string \"swap\"
+.const #1 u256 0
+.const #2 u256 1
+.storage 0 pool: address
+.storage 1 token_in: address
+.storage 2 amount: u256
+.storage 3 reentries: u256
+.export attack(pool: address, token_in: address, amount: u256, reentries: u256) -> Result<u256, Error>
+.export tokens_received(amount: u256)
+
+.function attack arity=4 locals=4
+    LOAD 0
+    SSTORE 0
+    LOAD 1
+    SSTORE 1
+    LOAD 2
+    SSTORE 2
+    LOAD 3
+    SSTORE 3
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    CALLM #0 2 ; string \"swap\"
+    RET
+
+.function tokens_received arity=1 locals=1
+    SLOAD 3
+    PUSH #1 ; u256 0
+    GT
+    JUMPI again
+    RET
+again:
+    SLOAD 3
+    PUSH #2 ; u256 1
+    SUB
+    SSTORE 3
+    SLOAD 0
+    SLOAD 1
+    SLOAD 2
+    CALLM #0 2 ; string \"swap\"
+    POP
+    RET
+";
+
+fn tokens(n: u64) -> U256 {
+    U256::from(n) * U256::from(1_000_000_000_000_000_000u64)
+}
+
+fn pool() -> Module {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../../tests/fixtures/bytecode/defi/liquidity_pool.LiquidityPool.asm");
+    assemble(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// The pool as it would compile without `@no_reentry`: each guard
+/// instruction becomes a jump to the next one, so nothing else moves
+fn unguarded(mut module: Module) -> Module {
+    for function in &mut module.functions {
+        for (pc, instruction) in function.code.iter_mut().enumerate() {
+            if matches!(instruction, Instruction::GuardEnter | Instruction::GuardExit) {
+                *instruction = Instruction::Jump(pc as u32 + 1);
+            }
+        }
+    }
+    module
+}
+
+fn call(vm: &mut Vm, sender: Address, contract: Address, function: &str, args: Vec<Value>) -> Result<Option<Value>, Trap> {
+    vm.env.sender = sender;
+    vm.call(&contract, function, args, GAS_LIMIT).unwrap().result
+}
+
+/// A pool with 1000 of each token from a provider, and an attacker
+/// holding 1000 of token A and hooked into token B
+fn setup(pool: Module, threshold: Option<u32>) -> Vm {
+    let mut vm = Vm::new();
+    vm.set_jit_threshold(threshold);
+    vm.deploy(POOL, pool).unwrap();
+    vm.deploy(TOKEN_A, assemble(HOOK_TOKEN).unwrap()).unwrap();
+    vm.deploy(TOKEN_B, assemble(HOOK_TOKEN).unwrap()).unwrap();
+    vm.deploy(ATTACKER, assemble(ATTACKER_CONTRACT).unwrap()).unwrap();
+
+    let (a, b) = (Value::Address(TOKEN_A), Value::Address(TOKEN_B));
+    call(&mut vm, PROVIDER, POOL, "init", vec![a, b]).unwrap();
+    for (token, holder) in [(TOKEN_A, PROVIDER), (TOKEN_B, PROVIDER), (TOKEN_A, ATTACKER)] {
+        call(&mut vm, PROVIDER, token, "mint", vec![Value::Address(holder), tokens(1000).into()]).unwrap();
+    }
+    let added = call(&mut vm, PROVIDER, POOL, "add_liquidity", vec![tokens(1000).into(), tokens(1000).into()]);
+    assert!(matches!(added, Ok(Some(Value::Variant { ref path, .. })) if path == "Ok"), "{:?}", added);
+    call(&mut vm, ATTACKER, TOKEN_B, "hook", vec![Value::Address(ATTACKER)]).unwrap();
+    vm
+}
+
+fn balance(vm: &mut Vm, token: Address, account: Address) -> U256 {
+    match call(vm, PROVIDER, token, "balance_of", vec![Value::Address(account)]) {
+        Ok(Some(Value::U256(balance))) => balance,
+        other => panic!("balance_of returned {:?}", other),
+    }
+}
+
+fn attack(vm: &mut Vm, reentries: u64) -> Result<Option<Value>, Trap> {
+    let args = vec![Value::Address(POOL), Value::Address(TOKEN_A), tokens(100).into(), U256::from(reentries).into()];
+    call(vm, ATTACKER, ATTACKER, "attack", args)
+}
+
+#[test]
+fn test_reentrant_swaps_drain_an_unguarded_pool() {
+    for threshold in [None, Some(0)] {
+        // Ten swaps of 100 A, one after the other
+        let mut honest = setup(unguarded(pool()), threshold);
+        for _ in 0..10 {
+            attack(&mut honest, 0).unwrap();
+        }
+        let fair = balance(&mut honest, TOKEN_B, ATTACKER);
+
+        // The same ten swaps, nested, all at the opening price
+        let mut drained = setup(unguarded(pool()), threshold);
+        attack(&mut drained, 9).unwrap();
+        assert_eq!(balance(&mut drained, TOKEN_A, ATTACKER), U256::ZERO);
+        let taken = balance(&mut drained, TOKEN_B, ATTACKER);
+        assert!(taken > fair * U256::from(3u64) / U256::from(2u64), "took {} where {} is fair", taken, fair);
+        assert!(taken > tokens(900));
+    }
+}
+
+#[test]
+fn test_no_reentry_stops_the_drain() {
+    // The compiler turned `@no_reentry` into a guard around `swap`
+    let compiled = pool();
+    let swap = compiled.functions.iter().find(|function| function.name == "swap").unwrap();
+    assert_eq!(swap.code.first(), Some(&Instruction::GuardEnter));
+    assert!(swap.code.contains(&Instruction::GuardExit));
+
+    for threshold in [None, Some(0)] {
+        let mut vm = setup(pool(), threshold);
+        let reserves = call(&mut vm, PROVIDER, POOL, "get_reserves", vec![]).unwrap();
+
+        let chain = ["Attacker.attack", "LiquidityPool.swap", "HookToken.transfer", "Attacker.tokens_received", "LiquidityPool.swap"];
+        let violation = Trap::Reentrancy { function: "swap".into(), chain: chain.map(String::from).to_vec() };
+        assert_eq!(attack(&mut vm, 9), Err(violation.clone()));
+        assert_eq!(
+            violation.to_string(),
+            "Reentrant call detected in `swap` via Attacker.attack -> LiquidityPool.swap -> HookToken.transfer \
+             -> Attacker.tokens_received -> LiquidityPool.swap"
+        );
+
+        // Nothing moved, and the pool still serves ordinary swaps
+        assert_eq!(call(&mut vm, PROVIDER, POOL, "get_reserves", vec![]).unwrap(), reserves);
+        assert_eq!(balance(&mut vm, TOKEN_A, ATTACKER), tokens(1000));
+        assert_eq!(balance(&mut vm, TOKEN_B, ATTACKER), U256::ZERO);
+        assert!(matches!(attack(&mut vm, 0), Ok(Some(Value::Variant { ref path, .. })) if path == "Ok"));
+        assert!(balance(&mut vm, TOKEN_B, ATTACKER) > U256::ZERO);
+    }
+}
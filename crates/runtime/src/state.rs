@@ -0,0 +1,489 @@
+//! Where contract storage lives. The [`Vm`](crate::Vm) reads and writes
+//! slots through the [`StateBackend`] trait, keyed by the contract's
+//! address and the slot number, so embedders can back it with whatever
+//! they like.
+//!
+//! Writes happen inside transactions, which nest: the VM opens one per
+//! top-level call and one per sub-call, and a test can open one around a
+//! whole scenario to fork the state and discard it afterwards. Only
+//! committing the outermost transaction makes writes durable.
+//!
+//! [`MemoryBackend`] keeps everything in memory and is the default.
+//! [`FileBackend`] keeps the same map in memory but logs every committed
+//! transaction to a file, and folds the log into a snapshot when it is
+//! opened, so a devnet's state survives restarts.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::num::U256;
+use crate::value::{Address, Value};
+
+/// Slots of every deployed contract. Only written slots are stored; the
+/// VM gives unwritten ones the zero of their declared type.
+pub trait StateBackend {
+    fn get(&self, contract: &Address, slot: &U256) -> Option<Value>;
+
+    /// Writes `value`, returning what the slot held before
+    fn set(&mut self, contract: Address, slot: U256, value: Value) -> Option<Value>;
+
+    /// Clears the slot, returning what it held
+    fn remove(&mut self, contract: &Address, slot: &U256) -> Option<Value>;
+
+    /// Every written slot of `contract`, in slot order
+    fn slots(&self, contract: &Address) -> Vec<(U256, Value)>;
+
+    /// Opens a transaction inside the current one, if any
+    fn begin(&mut self);
+
+    /// Closes the innermost transaction, keeping its writes. Its writes
+    /// become part of the enclosing transaction, or durable if there is
+    /// none; only the latter can fail, and a failed commit is rolled back.
+    fn commit(&mut self) -> Result<(), String>;
+
+    /// Closes the innermost transaction, undoing its writes
+    fn rollback(&mut self);
+
+    /// How many transactions are open
+    fn depth(&self) -> usize;
+}
+
+/// What each slot written inside the open transactions held before, so
+/// that any of them can be rolled back
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Journal {
+    writes: Vec<(Address, U256, Option<Value>)>,
+    /// Where each open transaction starts in `writes`, outermost first
+    marks: Vec<usize>,
+}
+
+/// State held in memory for the lifetime of the backend
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryBackend {
+    slots: BTreeMap<(Address, U256), Value>,
+    journal: Journal,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&mut self, contract: Address, slot: U256, previous: &Option<Value>) {
+        if !self.journal.marks.is_empty() {
+            self.journal.writes.push((contract, slot, previous.clone()));
+        }
+    }
+}
+
+impl StateBackend for MemoryBackend {
+    fn get(&self, contract: &Address, slot: &U256) -> Option<Value> {
+        self.slots.get(&(*contract, *slot)).cloned()
+    }
+
+    fn set(&mut self, contract: Address, slot: U256, value: Value) -> Option<Value> {
+        let previous = self.slots.insert((contract, slot), value);
+        self.record(contract, slot, &previous);
+        previous
+    }
+
+    fn remove(&mut self, contract: &Address, slot: &U256) -> Option<Value> {
+        let previous = self.slots.remove(&(*contract, *slot));
+        self.record(*contract, *slot, &previous);
+        previous
+    }
+
+    fn slots(&self, contract: &Address) -> Vec<(U256, Value)> {
+        let start = (*contract, U256::ZERO);
+        self.slots.range(start..)
+            .take_while(|((address, _), _)| address == contract)
+            .map(|((_, slot), value)| (*slot, value.clone()))
+            .collect()
+    }
+
+    fn begin(&mut self) {
+        self.journal.marks.push(self.journal.writes.len());
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        self.journal.marks.pop().ok_or("no transaction to commit")?;
+        if self.journal.marks.is_empty() {
+            self.journal.writes.clear();
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        let Some(mark) = self.journal.marks.pop() else {
+            return;
+        };
+        for (contract, slot, previous) in self.journal.writes.drain(mark..).rev() {
+            match previous {
+                Some(value) => self.slots.insert((contract, slot), value),
+                None => self.slots.remove(&(contract, slot)),
+            };
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.journal.marks.len()
+    }
+}
+
+const SNAPSHOT_FILE: &str = "state.snapshot";
+const LOG_FILE: &str = "state.log";
+
+/// State kept in a directory, as a snapshot of every slot plus a log of
+/// the transactions committed since. Each log record carries a checksum,
+/// so a record torn by a crash is dropped when the directory is reopened
+/// and its transaction is lost as a whole. Writes made outside any
+/// transaction are logged with the next commit.
+pub struct FileBackend {
+    dir: PathBuf,
+    memory: MemoryBackend,
+    log: File,
+    /// Slots written since the last record was logged
+    unlogged: BTreeSet<(Address, U256)>,
+    /// Makes the next flush write this many bytes of its record and fail
+    #[cfg(test)]
+    fail_after: Option<usize>,
+}
+
+impl FileBackend {
+    /// Opens the state in `dir`, creating the directory if needed, and
+    /// compacts its log into the snapshot
+    pub fn open(dir: impl AsRef<Path>) -> Result<FileBackend, String> {
+        let dir = dir.as_ref().to_path_buf();
+        let context = |e: io::Error| format!("{}: {}", dir.display(), e);
+        fs::create_dir_all(&dir).map_err(context)?;
+
+        let mut memory = MemoryBackend::new();
+        match fs::read(dir.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => {
+                let (payload, _) = read_record(&bytes)
+                    .ok_or_else(|| format!("{}: corrupt snapshot", dir.join(SNAPSHOT_FILE).display()))?;
+                apply(&mut memory, payload)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(context(e)),
+        }
+        let mut log = Vec::new();
+        if let Ok(mut file) = File::open(dir.join(LOG_FILE)) {
+            file.read_to_end(&mut log).map_err(context)?;
+        }
+        let mut rest = log.as_slice();
+        while let Some((payload, len)) = read_record(rest) {
+            apply(&mut memory, payload)?;
+            rest = &rest[len..];
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE)).map_err(context)?;
+        let mut backend = FileBackend {
+            dir,
+            memory,
+            log,
+            unlogged: BTreeSet::new(),
+            #[cfg(test)]
+            fail_after: None,
+        };
+        backend.compact()?;
+        Ok(backend)
+    }
+
+    /// Writes every slot to a fresh snapshot and empties the log. Only
+    /// possible between transactions.
+    pub fn compact(&mut self) -> Result<(), String> {
+        if self.memory.depth() > 0 {
+            return Err("cannot compact the state inside a transaction".into());
+        }
+        let context = |e: io::Error| format!("{}: {}", self.dir.display(), e);
+        let slots: Vec<_> = self.memory.slots.iter()
+            .map(|(&(contract, slot), value)| (contract, slot, Some(value)))
+            .collect();
+        let temporary = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut file = File::create(&temporary).map_err(context)?;
+        file.write_all(&record(&slots)).map_err(context)?;
+        file.sync_all().map_err(context)?;
+        fs::rename(&temporary, self.dir.join(SNAPSHOT_FILE)).map_err(context)?;
+        self.log.set_len(0).map_err(context)?;
+        self.log.sync_all().map_err(context)?;
+        self.unlogged.clear();
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Appends the current value of every unlogged slot as one record. A
+    /// record that fails part way is cut off again, or reopening would stop
+    /// at it and drop every record logged after it.
+    fn flush(&mut self) -> io::Result<()> {
+        let writes: Vec<_> = self.unlogged.iter()
+            .map(|&(contract, slot)| (contract, slot, self.memory.slots.get(&(contract, slot))))
+            .collect();
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let len = self.log.metadata()?.len();
+        if let Err(e) = self.append(&record(&writes)) {
+            self.log.set_len(len)?;
+            return Err(e);
+        }
+        self.unlogged.clear();
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        #[cfg(test)]
+        if let Some(len) = self.fail_after.take() {
+            self.log.write_all(&record[..len])?;
+            return Err(io::Error::other("injected write failure"));
+        }
+        self.log.write_all(record)?;
+        self.log.sync_data()
+    }
+}
+
+impl StateBackend for FileBackend {
+    fn get(&self, contract: &Address, slot: &U256) -> Option<Value> {
+        self.memory.get(contract, slot)
+    }
+
+    fn set(&mut self, contract: Address, slot: U256, value: Value) -> Option<Value> {
+        self.unlogged.insert((contract, slot));
+        self.memory.set(contract, slot, value)
+    }
+
+    fn remove(&mut self, contract: &Address, slot: &U256) -> Option<Value> {
+        self.unlogged.insert((*contract, *slot));
+        self.memory.remove(contract, slot)
+    }
+
+    fn slots(&self, contract: &Address) -> Vec<(U256, Value)> {
+        self.memory.slots(contract)
+    }
+
+    fn begin(&mut self) {
+        self.memory.begin();
+    }
+
+    fn commit(&mut self) -> Result<(), String> {
+        if self.memory.depth() == 1 {
+            if let Err(e) = self.flush() {
+                self.memory.rollback();
+                return Err(format!("{}: {}", self.dir.join(LOG_FILE).display(), e));
+            }
+        }
+        self.memory.commit()
+    }
+
+    fn rollback(&mut self) {
+        // Rolled-back slots stay unlogged; logging them again later writes
+        // the values they already had
+        self.memory.rollback();
+    }
+
+    fn depth(&self) -> usize {
+        self.memory.depth()
+    }
+}
+
+/// Frames `writes` as `length | payload | checksum`, where the payload
+/// lists each slot with its value, or a zero tag for a cleared slot
+fn record(writes: &[(Address, U256, Option<&Value>)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&(writes.len() as u32).to_be_bytes());
+    for (contract, slot, value) in writes {
+        payload.extend_from_slice(contract);
+        payload.extend_from_slice(&slot.to_be_bytes());
+        match value {
+            Some(value) => {
+                payload.push(1);
+                payload.extend_from_slice(&value.to_bytes());
+            }
+            None => payload.push(0),
+        }
+    }
+    let mut out = Vec::with_capacity(payload.len() + 12);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(&payload);
+    out.extend_from_slice(&Sha256::digest(&payload)[..8]);
+    out
+}
+
+/// The payload of the record at the front of `bytes` and the record's
+/// length, or `None` if it is incomplete or fails its checksum
+fn read_record(bytes: &[u8]) -> Option<(&[u8], usize)> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let payload = bytes.get(4..4 + len)?;
+    let checksum = bytes.get(4 + len..12 + len)?;
+    (Sha256::digest(payload)[..8] == *checksum).then_some((payload, 12 + len))
+}
+
+/// Applies the writes of a record's payload
+fn apply(memory: &mut MemoryBackend, mut payload: &[u8]) -> Result<(), String> {
+    fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+        let head = input.get(..len).ok_or("corrupt state record")?;
+        *input = &input[len..];
+        Ok(head)
+    }
+
+    let count = u32::from_be_bytes(take(&mut payload, 4)?.try_into().expect("four bytes"));
+    for _ in 0..count {
+        let contract: Address = take(&mut payload, 20)?.try_into().expect("20 bytes");
+        let slot = U256::from_be_bytes(take(&mut payload, 32)?.try_into().expect("32 bytes"));
+        if take(&mut payload, 1)?[0] == 0 {
+            memory.slots.remove(&(contract, slot));
+        } else {
+            memory.slots.insert((contract, slot), Value::from_bytes(&mut payload)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    /// A directory under the system's temporary one, emptied first
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stremax-state-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_contracts_have_separate_slots() {
+        let mut storage = MemoryBackend::new();
+        let (a, b) = ([1; 20], [2; 20]);
+        assert_eq!(storage.set(a, U256::ONE, Value::Bool(true)), None);
+        assert_eq!(storage.set(b, U256::ONE, Value::Bool(false)), None);
+        assert_eq!(storage.set(a, U256::ZERO, Value::unit()), None);
+        assert_eq!(storage.set(a, U256::ONE, Value::Bool(false)), Some(Value::Bool(true)));
+
+        assert_eq!(storage.get(&b, &U256::ONE), Some(Value::Bool(false)));
+        assert_eq!(storage.slots(&a), vec![(U256::ZERO, Value::unit()), (U256::ONE, Value::Bool(false))]);
+        assert_eq!(storage.remove(&a, &U256::ZERO), Some(Value::unit()));
+        assert_eq!(storage.get(&a, &U256::ZERO), None);
+        assert!(storage.slots(&[3; 20]).is_empty());
+    }
+
+    #[test]
+    fn test_nested_transactions() {
+        let mut storage = MemoryBackend::new();
+        let a = [1; 20];
+        storage.set(a, U256::ZERO, word(1));
+
+        storage.begin();
+        storage.set(a, U256::ZERO, word(2));
+        storage.begin();
+        storage.set(a, U256::ONE, word(3));
+        storage.remove(&a, &U256::ZERO);
+        storage.rollback();
+        assert_eq!(storage.slots(&a), vec![(U256::ZERO, word(2))]);
+
+        storage.begin();
+        storage.set(a, U256::ONE, word(4));
+        storage.commit().unwrap();
+        assert_eq!(storage.depth(), 1);
+        // Rolling back the outer transaction undoes the committed inner one
+        storage.rollback();
+        assert_eq!(storage.slots(&a), vec![(U256::ZERO, word(1))]);
+        assert_eq!(storage.depth(), 0);
+
+        storage.begin();
+        storage.set(a, U256::ONE, word(5));
+        storage.commit().unwrap();
+        storage.rollback();
+        assert_eq!(storage.get(&a, &U256::ONE), Some(word(5)));
+        assert!(storage.commit().is_err());
+    }
+
+    #[test]
+    fn test_file_backend_survives_reopening() {
+        let dir = scratch("reopen");
+        let (a, b) = ([1; 20], [2; 20]);
+        {
+            let mut storage = FileBackend::open(&dir).unwrap();
+            storage.begin();
+            storage.set(a, U256::ZERO, Value::String("kept".into()));
+            storage.set(b, U256::ONE, Value::Tuple(vec![word(7), Value::Address(a)]));
+            storage.commit().unwrap();
+
+            storage.begin();
+            storage.remove(&b, &U256::ONE);
+            storage.begin();
+            storage.set(a, U256::ONE, word(9));
+            storage.commit().unwrap();
+            storage.commit().unwrap();
+
+            storage.begin();
+            storage.set(a, U256::ZERO, Value::String("discarded".into()));
+            storage.rollback();
+        }
+
+        let storage = FileBackend::open(&dir).unwrap();
+        assert_eq!(storage.slots(&a), vec![(U256::ZERO, Value::String("kept".into())), (U256::ONE, word(9))]);
+        assert!(storage.slots(&b).is_empty());
+        assert_eq!(fs::metadata(dir.join(LOG_FILE)).unwrap().len(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_drops_a_torn_record() {
+        let dir = scratch("torn");
+        let a = [1; 20];
+        {
+            let mut storage = FileBackend::open(&dir).unwrap();
+            storage.begin();
+            storage.set(a, U256::ZERO, word(1));
+            storage.commit().unwrap();
+            storage.begin();
+            storage.set(a, U256::ZERO, word(2));
+            storage.set(a, U256::ONE, word(3));
+            storage.commit().unwrap();
+        }
+        let log = fs::read(dir.join(LOG_FILE)).unwrap();
+        // Both records are in the log until the next open compacts it
+        assert!(log.len() > 12);
+        fs::write(dir.join(LOG_FILE), &log[..log.len() - 1]).unwrap();
+
+        let storage = FileBackend::open(&dir).unwrap();
+        assert_eq!(storage.slots(&a), vec![(U256::ZERO, word(1))]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_cuts_off_a_failed_record() {
+        let dir = scratch("failed");
+        let a = [1; 20];
+        {
+            let mut storage = FileBackend::open(&dir).unwrap();
+            storage.begin();
+            storage.set(a, U256::ZERO, word(1));
+            storage.fail_after = Some(6);
+            assert!(storage.commit().is_err());
+            assert_eq!(storage.get(&a, &U256::ZERO), None);
+            assert_eq!(fs::metadata(dir.join(LOG_FILE)).unwrap().len(), 0);
+
+            storage.begin();
+            storage.set(a, U256::ONE, word(2));
+            storage.commit().unwrap();
+        }
+
+        // Left in the log, the failed record's first bytes would hide the
+        // committed one
+        let storage = FileBackend::open(&dir).unwrap();
+        assert_eq!(storage.slots(&a), vec![(U256::ONE, word(2))]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
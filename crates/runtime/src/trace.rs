@@ -0,0 +1,510 @@
+//! Watching a call run one instruction at a time.
+//!
+//! A [`Tracer`] installed with [`Vm::set_tracer`] is shown every
+//! instruction just before it runs, with the frame it runs in and the gas
+//! it is about to cost, and is told as frames are entered and left, as
+//! slots are read and written and as events are emitted. While a tracer is
+//! installed every function stays in the interpreter: compiled code runs
+//! whole blocks at once and has no instruction boundaries to show.
+//!
+//! Two tracers export a trace for offline tools. A [`StructLogger`] keeps
+//! a [`StructLog`] per instruction and a [`CallTracer`] the tree of
+//! [`CallTrace`]s; both turn into JSON, and [`TraceKind`] picks one by
+//! name for `strxvm run --trace` and the test environments.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::mem;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use serde_json::{json, Value as Json};
+
+use crate::bytecode::{Instruction, Module};
+use crate::num::U256;
+use crate::value::{hex, Address, Event, Trap, Value};
+use crate::vm::Vm;
+
+/// An instruction about to run, and the frame it runs in
+pub struct Step<'a> {
+    /// The contract whose code runs
+    pub contract: Address,
+    pub module: &'a Module,
+    /// Index of the running function in `module`
+    pub function: u32,
+    pub pc: usize,
+    pub instruction: &'a Instruction,
+    /// Gas left before the instruction is charged
+    pub gas_left: u64,
+    pub gas_cost: u64,
+    /// Frames on the call stack, 1 in the function the transaction called
+    pub depth: usize,
+    pub locals: &'a [Value],
+    /// The frame's operands, the top last
+    pub stack: &'a [Value],
+}
+
+/// How a frame was entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// A transaction, a delivered message or one contract calling another
+    Message,
+    /// A function calling another of its contract, directly or through a
+    /// closure
+    Internal,
+}
+
+/// A frame about to be entered
+pub struct CallEnter<'a> {
+    pub kind: CallKind,
+    /// `msg.sender` in the callee
+    pub from: Address,
+    pub to: Address,
+    pub module: &'a Module,
+    /// Index of the called function in `module`
+    pub function: u32,
+    pub args: &'a [Value],
+    pub value: U256,
+    pub gas_left: u64,
+    /// The depth the callee runs at
+    pub depth: usize,
+}
+
+/// A frame just left, by returning or by trapping
+pub struct CallExit<'a> {
+    pub kind: CallKind,
+    pub depth: usize,
+    /// Gas the frame and its callees spent, the call instruction aside
+    pub gas_used: u64,
+    /// What it returned, if anything, or what it trapped with
+    pub result: Result<Option<&'a Value>, &'a Trap>,
+}
+
+/// Every hook does nothing unless a tracer overrides it
+pub trait Tracer {
+    /// Called before each instruction runs. `vm` shows the state the
+    /// instructions before it left, such as storage and events.
+    fn step(&mut self, _vm: &Vm, _step: &Step<'_>) {}
+
+    /// Called before a frame is set up, so also for calls that trap
+    /// before their first instruction
+    fn enter(&mut self, _vm: &Vm, _call: &CallEnter<'_>) {}
+
+    /// Called once for each [`Tracer::enter`], after the frame is gone
+    fn exit(&mut self, _vm: &Vm, _exit: &CallExit<'_>) {}
+
+    fn storage_read(&mut self, _vm: &Vm, _contract: &Address, _slot: U256, _value: &Value) {}
+
+    /// Called before `value` is written, while `vm` still holds the old one
+    fn storage_write(&mut self, _vm: &Vm, _contract: &Address, _slot: U256, _value: &Value) {}
+
+    /// Called once `event` is among the VM's events
+    fn event(&mut self, _vm: &Vm, _event: &Event) {}
+}
+
+/// Shows everything to each tracer in turn
+impl Tracer for Vec<Box<dyn Tracer>> {
+    fn step(&mut self, vm: &Vm, step: &Step<'_>) {
+        self.iter_mut().for_each(|tracer| tracer.step(vm, step));
+    }
+
+    fn enter(&mut self, vm: &Vm, call: &CallEnter<'_>) {
+        self.iter_mut().for_each(|tracer| tracer.enter(vm, call));
+    }
+
+    fn exit(&mut self, vm: &Vm, exit: &CallExit<'_>) {
+        self.iter_mut().for_each(|tracer| tracer.exit(vm, exit));
+    }
+
+    fn storage_read(&mut self, vm: &Vm, contract: &Address, slot: U256, value: &Value) {
+        self.iter_mut().for_each(|tracer| tracer.storage_read(vm, contract, slot, value));
+    }
+
+    fn storage_write(&mut self, vm: &Vm, contract: &Address, slot: U256, value: &Value) {
+        self.iter_mut().for_each(|tracer| tracer.storage_write(vm, contract, slot, value));
+    }
+
+    fn event(&mut self, vm: &Vm, event: &Event) {
+        self.iter_mut().for_each(|tracer| tracer.event(vm, event));
+    }
+}
+
+/// One instruction of a struct log
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLog {
+    pub pc: usize,
+    pub op: String,
+    /// Gas left before the instruction is charged
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: usize,
+    /// The frame's operands before the instruction, the top last
+    pub stack: Vec<Value>,
+    /// Slots of the running contract the instruction wrote, and what to
+    pub storage: BTreeMap<U256, Value>,
+    /// The trap the frame ended with, on the instruction that raised it
+    pub error: Option<String>,
+}
+
+impl StructLog {
+    /// In the field names Ethereum's struct loggers use
+    pub fn to_json(&self) -> Json {
+        let storage: serde_json::Map<String, Json> = self.storage.iter()
+            .map(|(slot, value)| (word(*slot), Json::String(value.to_string())))
+            .collect();
+        let mut log = json!({
+            "pc": self.pc,
+            "op": self.op,
+            "gas": self.gas,
+            "gasCost": self.gas_cost,
+            "depth": self.depth,
+            "stack": self.stack.iter().map(Value::to_string).collect::<Vec<_>>(),
+            "storage": storage,
+        });
+        if let Some(error) = &self.error {
+            log["error"] = json!(error);
+        }
+        log
+    }
+}
+
+/// Logs every instruction of the calls made while it is the VM's tracer
+#[derive(Clone, Default)]
+pub struct StructLogger(Rc<RefCell<Vec<StructLog>>>);
+
+impl StructLogger {
+    pub fn new() -> StructLogger {
+        StructLogger::default()
+    }
+
+    /// What has been logged so far, leaving the logger empty
+    pub fn finish(&self) -> Vec<StructLog> {
+        mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Tracer for StructLogger {
+    fn step(&mut self, _vm: &Vm, step: &Step<'_>) {
+        self.0.borrow_mut().push(StructLog {
+            pc: step.pc,
+            op: step.instruction.opcode().to_string(),
+            gas: step.gas_left,
+            gas_cost: step.gas_cost,
+            depth: step.depth,
+            stack: step.stack.to_vec(),
+            storage: BTreeMap::new(),
+            error: None,
+        });
+    }
+
+    fn exit(&mut self, _vm: &Vm, exit: &CallExit<'_>) {
+        let mut logs = self.0.borrow_mut();
+        if let (Err(trap), Some(last)) = (exit.result, logs.last_mut()) {
+            if last.depth == exit.depth && last.error.is_none() {
+                last.error = Some(trap.to_string());
+            }
+        }
+    }
+
+    fn storage_write(&mut self, _vm: &Vm, _contract: &Address, slot: U256, value: &Value) {
+        if let Some(last) = self.0.borrow_mut().last_mut() {
+            last.storage.insert(slot, value.clone());
+        }
+    }
+}
+
+/// A frame and the frames it entered
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallTrace {
+    pub kind: CallKind,
+    pub from: Address,
+    pub to: Address,
+    pub function: String,
+    pub input: Vec<Value>,
+    pub value: U256,
+    pub output: Option<Value>,
+    pub gas_used: u64,
+    pub error: Option<String>,
+    pub calls: Vec<CallTrace>,
+}
+
+impl CallTrace {
+    /// In the field names Ethereum's call tracers use
+    pub fn to_json(&self) -> Json {
+        let mut call = json!({
+            "type": match self.kind {
+                CallKind::Message => "CALL",
+                CallKind::Internal => "INTERNAL",
+            },
+            "from": Value::Address(self.from).to_string(),
+            "to": Value::Address(self.to).to_string(),
+            "function": self.function,
+            "input": self.input.iter().map(Value::to_string).collect::<Vec<_>>(),
+            "value": self.value.to_string(),
+            "gasUsed": self.gas_used,
+        });
+        if let Some(output) = &self.output {
+            call["output"] = json!(output.to_string());
+        }
+        if let Some(error) = &self.error {
+            call["error"] = json!(error);
+        }
+        if !self.calls.is_empty() {
+            call["calls"] = self.calls.iter().map(CallTrace::to_json).collect();
+        }
+        call
+    }
+}
+
+/// Builds the call tree of each call made while it is the VM's tracer
+#[derive(Clone, Default)]
+pub struct CallTracer(Rc<RefCell<CallTracerState>>);
+
+#[derive(Default)]
+struct CallTracerState {
+    /// Finished top-level calls
+    calls: Vec<CallTrace>,
+    /// The frames entered and not yet left, the innermost last
+    open: Vec<CallTrace>,
+}
+
+impl CallTracer {
+    pub fn new() -> CallTracer {
+        CallTracer::default()
+    }
+
+    /// The top-level calls finished so far, leaving the tracer empty
+    pub fn finish(&self) -> Vec<CallTrace> {
+        mem::take(&mut self.0.borrow_mut().calls)
+    }
+}
+
+impl Tracer for CallTracer {
+    fn enter(&mut self, _vm: &Vm, call: &CallEnter<'_>) {
+        self.0.borrow_mut().open.push(CallTrace {
+            kind: call.kind,
+            from: call.from,
+            to: call.to,
+            function: call.module.functions[call.function as usize].name.clone(),
+            input: call.args.to_vec(),
+            value: call.value,
+            output: None,
+            gas_used: 0,
+            error: None,
+            calls: Vec::new(),
+        });
+    }
+
+    fn exit(&mut self, _vm: &Vm, exit: &CallExit<'_>) {
+        let mut state = self.0.borrow_mut();
+        let Some(mut call) = state.open.pop() else {
+            return;
+        };
+        call.gas_used = exit.gas_used;
+        match exit.result {
+            Ok(output) => call.output = output.cloned(),
+            Err(trap) => call.error = Some(trap.to_string()),
+        }
+        match state.open.last_mut() {
+            Some(caller) => caller.calls.push(call),
+            None => state.calls.push(call),
+        }
+    }
+}
+
+/// One of the built-in tracers, by the name `strxvm run --trace` takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// A [`StructLogger`], `struct`
+    Struct,
+    /// A [`CallTracer`], `calls`
+    Calls,
+}
+
+impl FromStr for TraceKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "struct" => Ok(TraceKind::Struct),
+            "calls" => Ok(TraceKind::Calls),
+            _ => Err(format!("unknown tracer `{}`, expected `struct` or `calls`", name)),
+        }
+    }
+}
+
+/// A built-in tracer, kept outside the VM to take its trace from
+#[derive(Clone)]
+pub enum TraceHandle {
+    Struct(StructLogger),
+    Calls(CallTracer),
+}
+
+impl TraceHandle {
+    pub fn new(kind: TraceKind) -> TraceHandle {
+        match kind {
+            TraceKind::Struct => TraceHandle::Struct(StructLogger::new()),
+            TraceKind::Calls => TraceHandle::Calls(CallTracer::new()),
+        }
+    }
+
+    /// The tracer to install; it shares what it traces with the handle
+    pub fn tracer(&self) -> Box<dyn Tracer> {
+        match self {
+            TraceHandle::Struct(logger) => Box::new(logger.clone()),
+            TraceHandle::Calls(tracer) => Box::new(tracer.clone()),
+        }
+    }
+
+    /// What has been traced so far
+    pub fn finish(&self) -> Trace {
+        match self {
+            TraceHandle::Struct(logger) => Trace::Struct(logger.finish()),
+            TraceHandle::Calls(tracer) => Trace::Calls(tracer.finish()),
+        }
+    }
+}
+
+/// What one of the built-in tracers traced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trace {
+    Struct(Vec<StructLog>),
+    Calls(Vec<CallTrace>),
+}
+
+impl Trace {
+    /// One JSON object per line: an instruction of a struct log, or a
+    /// top-level call with its tree
+    pub fn to_json_lines(&self) -> String {
+        let objects: Vec<Json> = match self {
+            Trace::Struct(logs) => logs.iter().map(StructLog::to_json).collect(),
+            Trace::Calls(calls) => calls.iter().map(CallTrace::to_json).collect(),
+        };
+        objects.iter().map(|object| format!("{}\n", object)).collect()
+    }
+}
+
+/// A slot as a 32-byte hex word
+fn word(slot: U256) -> String {
+    format!("0x{}", hex(&slot.to_be_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::assemble;
+
+    /// Stores its argument through a helper that emits it, then has a
+    /// call to itself store twice as much, emit it and revert
+    const VAULT: &str = "\
+.contract Vault
+.const #0 string \"no\"
+.const #1 string \"self\"
+.const #2 string \"refuse\"
+.storage 0 stored: u256
+.event Stored(value: u256)
+.export put(value: u256)
+.export refuse(value: u256)
+
+.function put arity=1 locals=2
+    LOAD 0
+    CALL keep 1
+    STORE 1
+    ENV #1 ; string \"self\"
+    LOAD 1
+    CALLM #2 1 ; string \"refuse\"
+    POP
+    RET
+
+.function keep arity=1 locals=1
+    LOAD 0
+    SSTORE 0
+    LOAD 0
+    EMIT Stored 1
+    LOAD 0
+    RET
+
+.function refuse arity=1 locals=1
+    LOAD 0
+    LOAD 0
+    ADD
+    SSTORE 0
+    SLOAD 0
+    EMIT Stored 1
+    REVERT #0 ; string \"no\"
+";
+
+    const VAULT_ADDRESS: Address = [0x5a; 20];
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    /// Storage reads, storage writes and events
+    #[derive(Clone, Default)]
+    struct Accesses(Rc<RefCell<(usize, usize, usize)>>);
+
+    impl Tracer for Accesses {
+        fn storage_read(&mut self, _: &Vm, _: &Address, _: U256, _: &Value) {
+            self.0.borrow_mut().0 += 1;
+        }
+
+        fn storage_write(&mut self, vm: &Vm, contract: &Address, slot: U256, value: &Value) {
+            assert_ne!(vm.storage_at(contract, slot), *value, "written before the hook");
+            self.0.borrow_mut().1 += 1;
+        }
+
+        fn event(&mut self, _: &Vm, _: &Event) {
+            self.0.borrow_mut().2 += 1;
+        }
+    }
+
+    #[test]
+    fn test_struct_logs_and_call_trees() {
+        let mut vm = Vm::new();
+        vm.deploy(VAULT_ADDRESS, assemble(VAULT).unwrap()).unwrap();
+        let (logger, calls, accesses) = (StructLogger::new(), CallTracer::new(), Accesses::default());
+        let tracers: Vec<Box<dyn Tracer>> = vec![Box::new(logger.clone()), Box::new(calls.clone()), Box::new(accesses.clone())];
+        vm.set_tracer(Some(Box::new(tracers)));
+        let outcome = vm.call(&VAULT_ADDRESS, "put", vec![word(7)], 100_000).unwrap();
+        vm.set_tracer(None);
+        assert_eq!(outcome.result, Ok(None));
+        assert_eq!(*accesses.0.borrow(), (1, 2, 2));
+
+        let logs = logger.finish();
+        assert_eq!(logs.len(), 21);
+        let written: Vec<(usize, &BTreeMap<U256, Value>)> = logs.iter().enumerate()
+            .filter(|(_, log)| !log.storage.is_empty())
+            .map(|(index, log)| (index, &log.storage))
+            .collect();
+        assert_eq!(written, [(3, &BTreeMap::from([(U256::ZERO, word(7))])), (15, &BTreeMap::from([(U256::ZERO, word(14))]))]);
+        let revert = Trap::Revert("no".into()).to_string();
+        assert_eq!((logs[18].op.as_str(), logs[18].depth, logs[18].error.as_ref()), ("REVERT", 2, Some(&revert)));
+        assert_eq!(logs.iter().filter(|log| log.error.is_some()).count(), 1);
+
+        let json = logs[3].to_json();
+        assert_eq!((&json["op"], &json["depth"], &json["stack"]), (&json!("SSTORE"), &json!(2), &json!(["7"])));
+        assert_eq!(json["storage"][super::word(U256::ZERO)], json!("7"));
+        assert_eq!(json["gas"].as_u64(), Some(logs[3].gas));
+        assert!(json.get("error").is_none());
+
+        let calls = calls.finish();
+        assert_eq!(calls.len(), 1);
+        let put = &calls[0];
+        assert_eq!((put.kind, put.function.as_str(), &put.input, &put.output), (CallKind::Message, "put", &vec![word(7)], &None));
+        assert_eq!(put.gas_used, outcome.gas_used);
+        let nested: Vec<(CallKind, &str, Option<&Value>, Option<&String>)> = put.calls.iter()
+            .map(|call| (call.kind, call.function.as_str(), call.output.as_ref(), call.error.as_ref()))
+            .collect();
+        assert_eq!(nested, [(CallKind::Internal, "keep", Some(&word(7)), None), (CallKind::Message, "refuse", None, Some(&revert))]);
+        assert_eq!((put.calls[1].from, put.calls[1].to, &put.calls[1].input), (VAULT_ADDRESS, VAULT_ADDRESS, &vec![word(7)]));
+        assert!(put.calls[1].gas_used > 0 && put.calls[1].gas_used < put.gas_used);
+
+        let lines = Trace::Calls(calls).to_json_lines();
+        assert_eq!(lines.lines().count(), 1);
+        let tree: Json = serde_json::from_str(&lines).unwrap();
+        assert_eq!(tree["calls"][1]["error"], json!(revert));
+        assert_eq!(tree["calls"][0]["type"], json!("INTERNAL"));
+        assert!(tree["calls"][0].get("calls").is_none());
+    }
+}
@@ -0,0 +1,389 @@
+use std::fmt;
+
+use crate::bytecode::{Constant, Module, Type};
+use crate::num::U256;
+
+/// An account or contract address. Every part of the runtime uses this
+/// width, as does the bytecode's `address` constant.
+pub type Address = [u8; 20];
+
+/// A value on the operand stack, in a local or in storage
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    U256(U256),
+    Address(Address),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    Tuple(Vec<Value>),
+    Array(Vec<Value>),
+    /// Fields in the order the `STRUCT` layout lists them
+    Struct { name: String, fields: Vec<(String, Value)> },
+    Variant { path: String, payload: Vec<Value> },
+    /// A function and the values it captured, which it takes ahead of its
+    /// own arguments
+    Closure { function: u32, captured: Vec<Value> },
+    /// A capability token, by its id in the VM's
+    /// [`Capabilities`](crate::capabilities::Capabilities). Only the VM
+    /// makes these.
+    Capability(u64),
+    /// A value of a linear type, which only moves: its `id` is held by
+    /// one frame, storage slot or message at a time, and never leaves the
+    /// VM
+    Resource { kind: String, id: u64, value: Box<Value> },
+}
+
+impl Value {
+    pub fn unit() -> Value {
+        Value::Tuple(Vec::new())
+    }
+
+    pub fn from_constant(constant: &Constant) -> Value {
+        match constant {
+            Constant::U256(bytes) => Value::U256(U256::from_be_bytes(*bytes)),
+            Constant::Address(bytes) => Value::Address(*bytes),
+            Constant::Bool(b) => Value::Bool(*b),
+            Constant::String(s) => Value::String(s.clone()),
+            Constant::Bytes(bytes) => Value::Bytes(bytes.clone()),
+        }
+    }
+
+    /// What an unwritten storage slot of type `ty` reads as
+    pub fn zero(ty: &Type) -> Value {
+        match ty {
+            Type::Address => Value::Address([0; 20]),
+            Type::Bool => Value::Bool(false),
+            Type::String => Value::String(String::new()),
+            Type::Bytes => Value::Bytes(Vec::new()),
+            Type::Array(_) => Value::Array(Vec::new()),
+            Type::Tuple(elements) => Value::Tuple(elements.iter().map(Value::zero).collect()),
+            Type::Void => Value::unit(),
+            Type::U256 | Type::Map { .. } | Type::Named(_) => Value::U256(U256::ZERO),
+        }
+    }
+
+    /// Reads an argument of type `ty` as it is written on the command line:
+    /// numbers in decimal, `true` or `false`, and addresses and byte
+    /// strings in `0x` hex
+    pub fn parse(ty: &Type, text: &str) -> Result<Value, String> {
+        let invalid = || format!("`{}` is not a valid {}", text, ty);
+        match ty {
+            Type::U256 => U256::from_dec_str(text).map(Value::U256).map_err(|_| invalid()),
+            Type::Bool => text.parse().map(Value::Bool).map_err(|_| invalid()),
+            Type::String => Ok(Value::String(text.to_string())),
+            Type::Address | Type::Bytes => {
+                let digits = text.strip_prefix("0x").ok_or_else(invalid)?;
+                if digits.len() % 2 != 0 {
+                    return Err(invalid());
+                }
+                let bytes = (0..digits.len()).step_by(2)
+                    .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .map_err(|_| invalid())?;
+                match ty {
+                    Type::Address => bytes.try_into().map(Value::Address).map_err(|_| invalid()),
+                    _ => Ok(Value::Bytes(bytes)),
+                }
+            }
+            _ => Err(format!("arguments of type {} cannot be given on the command line", ty)),
+        }
+    }
+
+    /// Reads the arguments of `function` in `module` as [`Value::parse`]
+    /// does, typed by its ABI entry or, without one, as numbers
+    pub fn parse_args(module: &Module, function: &str, args: &[&str]) -> Result<Vec<Value>, String> {
+        let index = module.function_index(function)
+            .ok_or_else(|| format!("no function `{}` in `{}`", function, module.name))?;
+        let params: Vec<Type> = match module.abi.functions.iter().find(|entry| entry.function == index) {
+            Some(entry) => entry.params.iter().map(|param| param.ty.clone()).collect(),
+            None => vec![Type::U256; args.len()],
+        };
+        if params.len() != args.len() {
+            return Err(format!("`{}` takes {} arguments, got {}", function, params.len(), args.len()));
+        }
+        params.iter().zip(args).map(|(ty, arg)| Value::parse(ty, arg)).collect()
+    }
+
+    /// Tagged encoding that map keys are hashed in and that persistent
+    /// state is stored in
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    /// Reads a value written by [`Value::to_bytes`] from the front of
+    /// `input`, advancing it past the value
+    pub fn from_bytes(input: &mut &[u8]) -> Result<Value, String> {
+        fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+            if input.len() < len {
+                return Err("value ends early".into());
+            }
+            let (head, rest) = input.split_at(len);
+            *input = rest;
+            Ok(head)
+        }
+        fn length(input: &mut &[u8]) -> Result<usize, String> {
+            Ok(u32::from_be_bytes(take(input, 4)?.try_into().expect("four bytes")) as usize)
+        }
+        fn list(input: &mut &[u8]) -> Result<Vec<Value>, String> {
+            (0..length(input)?).map(|_| Value::from_bytes(input)).collect()
+        }
+        fn text(input: &mut &[u8]) -> Result<String, String> {
+            let len = length(input)?;
+            String::from_utf8(take(input, len)?.to_vec()).map_err(|_| "invalid UTF-8 in a value".to_string())
+        }
+
+        let value = match take(input, 1)?[0] {
+            0 => Value::U256(U256::from_be_bytes(take(input, 32)?.try_into().expect("32 bytes"))),
+            1 => Value::Address(take(input, 20)?.try_into().expect("20 bytes")),
+            2 => Value::Bool(take(input, 1)?[0] != 0),
+            3 => Value::String(text(input)?),
+            4 => {
+                let len = length(input)?;
+                Value::Bytes(take(input, len)?.to_vec())
+            }
+            5 => Value::Tuple(list(input)?),
+            6 => Value::Array(list(input)?),
+            7 => {
+                let name = text(input)?;
+                let fields = (0..length(input)?)
+                    .map(|_| Ok((text(input)?, Value::from_bytes(input)?)))
+                    .collect::<Result<_, String>>()?;
+                Value::Struct { name, fields }
+            }
+            8 => Value::Variant { path: text(input)?, payload: list(input)? },
+            9 => {
+                let function = u32::from_be_bytes(take(input, 4)?.try_into().expect("four bytes"));
+                Value::Closure { function, captured: list(input)? }
+            }
+            10 => Value::Capability(u64::from_be_bytes(take(input, 8)?.try_into().expect("eight bytes"))),
+            11 => {
+                let kind = text(input)?;
+                let id = u64::from_be_bytes(take(input, 8)?.try_into().expect("eight bytes"));
+                Value::Resource { kind, id, value: Box::new(Value::from_bytes(input)?) }
+            }
+            tag => return Err(format!("invalid value tag {}", tag)),
+        };
+        Ok(value)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        fn list(out: &mut Vec<u8>, values: &[Value]) {
+            out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+            for value in values {
+                value.encode(out);
+            }
+        }
+        fn text(out: &mut Vec<u8>, text: &str) {
+            out.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            out.extend_from_slice(text.as_bytes());
+        }
+
+        match self {
+            Value::U256(n) => {
+                out.push(0);
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+            Value::Address(a) => {
+                out.push(1);
+                out.extend_from_slice(a);
+            }
+            Value::Bool(b) => out.extend_from_slice(&[2, *b as u8]),
+            Value::String(s) => {
+                out.push(3);
+                text(out, s);
+            }
+            Value::Bytes(bytes) => {
+                out.push(4);
+                out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+                out.extend_from_slice(bytes);
+            }
+            Value::Tuple(elements) => {
+                out.push(5);
+                list(out, elements);
+            }
+            Value::Array(elements) => {
+                out.push(6);
+                list(out, elements);
+            }
+            Value::Struct { name, fields } => {
+                out.push(7);
+                text(out, name);
+                out.extend_from_slice(&(fields.len() as u32).to_be_bytes());
+                for (field, value) in fields {
+                    text(out, field);
+                    value.encode(out);
+                }
+            }
+            Value::Variant { path, payload } => {
+                out.push(8);
+                text(out, path);
+                list(out, payload);
+            }
+            Value::Closure { function, captured } => {
+                out.push(9);
+                out.extend_from_slice(&function.to_be_bytes());
+                list(out, captured);
+            }
+            Value::Capability(id) => {
+                out.push(10);
+                out.extend_from_slice(&id.to_be_bytes());
+            }
+            Value::Resource { kind, id, value } => {
+                out.push(11);
+                text(out, kind);
+                out.extend_from_slice(&id.to_be_bytes());
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Value::U256(n) => write!(f, "{}", n),
+            Value::Address(a) => write!(f, "0x{}", hex(a)),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Bytes(bytes) => write!(f, "0x{}", hex(bytes)),
+            Value::Tuple(elements) => {
+                write!(f, "(")?;
+                list(f, elements)?;
+                write!(f, ")")
+            }
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                list(f, elements)?;
+                write!(f, "]")
+            }
+            Value::Struct { name, fields } => {
+                write!(f, "{} {{ ", name)?;
+                for (index, (field, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field, value)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Variant { path, payload } if payload.is_empty() => write!(f, "{}", path),
+            Value::Variant { path, payload } => {
+                write!(f, "{}(", path)?;
+                list(f, payload)?;
+                write!(f, ")")
+            }
+            Value::Closure { function, .. } => write!(f, "<closure #{}>", function),
+            Value::Capability(id) => write!(f, "<capability #{}>", id),
+            Value::Resource { id, value, .. } => write!(f, "{} (resource #{})", value, id),
+        }
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl From<U256> for Value {
+    fn from(n: U256) -> Self {
+        Value::U256(n)
+    }
+}
+
+/// An emitted event with its fields in declaration order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The contract that emitted it
+    pub address: Address,
+    pub name: String,
+    pub fields: Vec<Value>,
+}
+
+/// A message a contract passed to the `log` host function
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    pub address: Address,
+    pub message: String,
+}
+
+/// Why execution stopped abnormally. A trap undoes the effects of the
+/// contract call it happened in: a top-level call fails with it, while a
+/// sub-call returns `Err` with its message to the calling contract.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trap {
+    OutOfGas,
+    StackUnderflow,
+    /// The instruction found operands of the wrong type
+    TypeMismatch(&'static str),
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidLocal(u32),
+    IndexOutOfBounds,
+    CallDepthExceeded,
+    /// A `@no_reentry` function was entered while already running. The
+    /// chain lists the running calls as `Contract.function`, outermost
+    /// first, ending with the call that was refused. Unlike other traps
+    /// it is not caught at a sub-call: the whole transaction fails.
+    Reentrancy { function: String, chain: Vec<String> },
+    /// `REVERT` with its message
+    Revert(String),
+    InsufficientBalance,
+    UnknownEnvironment(String),
+    UnknownHostFunction(String),
+    UnknownMethod(String),
+    /// A method call on an address with no contract deployed, other than
+    /// a native balance query or transfer
+    NoContract { address: Address, method: String },
+    /// `RECV` outside the handling of a message
+    NoMessage,
+    /// A capability check or operation was refused
+    PermissionDenied(&'static str),
+    /// A function returned while still holding a resource of `kind`
+    ResourceLeak { kind: String, function: String },
+    /// A resource of this kind was released or moved by a function that
+    /// does not hold it, such as a copy or one already moved
+    ResourceNotHeld(String),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::OutOfGas => write!(f, "Out of gas"),
+            Trap::StackUnderflow => write!(f, "Stack underflow"),
+            Trap::TypeMismatch(instruction) => write!(f, "Type mismatch in {}", instruction),
+            Trap::ArithmeticOverflow => write!(f, "Arithmetic overflow"),
+            Trap::DivisionByZero => write!(f, "Division by zero"),
+            Trap::InvalidLocal(index) => write!(f, "Invalid local: {}", index),
+            Trap::IndexOutOfBounds => write!(f, "Index out of bounds"),
+            Trap::CallDepthExceeded => write!(f, "Call depth exceeded"),
+            Trap::Reentrancy { function, chain } => {
+                write!(f, "Reentrant call detected in `{}` via {}", function, chain.join(" -> "))
+            }
+            Trap::Revert(message) => write!(f, "Reverted: {}", message),
+            Trap::InsufficientBalance => write!(f, "Insufficient balance"),
+            Trap::UnknownEnvironment(name) => write!(f, "Unknown environment value `{}`", name),
+            Trap::UnknownHostFunction(name) => write!(f, "Unknown host function `{}`", name),
+            Trap::UnknownMethod(name) => write!(f, "Unknown method `{}`", name),
+            Trap::NoContract { address, method } => {
+                write!(f, "No contract at 0x{} to call `{}` on", hex(address), method)
+            }
+            Trap::NoMessage => write!(f, "No message to receive"),
+            Trap::PermissionDenied(reason) => write!(f, "Permission denied: {}", reason),
+            Trap::ResourceLeak { kind, function } => {
+                write!(f, "Resource `{}` still held when `{}` returned", kind, function)
+            }
+            Trap::ResourceNotHeld(kind) => write!(f, "Resource `{}` is not held by the running function", kind),
+        }
+    }
+}
@@ -0,0 +1,2571 @@
+//! The strxvm interpreter over the deployed [`Module`]s, and the tiering
+//! policy that hands hot functions to the [`jit`](crate::jit).
+//!
+//! Every instruction is executed by [`Vm::execute`], whichever tier runs
+//! the function: the interpreter loop calls it one instruction at a time
+//! after charging [`Module::gas_cost`], and compiled code calls it
+//! from its basic blocks after prepaying the whole block. Gas and traps
+//! are therefore the same in both tiers by construction.
+//!
+//! Contracts call each other with `CALLM` on an address: when a contract
+//! is deployed there, the call runs its exported function of that name
+//! with the caller as `msg.sender`.
+//!
+//! Contracts also send each other messages with `SEND`. A sent message
+//! waits in the VM's outbox until a [`Scheduler`](crate::actors::Scheduler) delivers it, as
+//! a transaction of its own, to the handler it names; the handler reads it
+//! with `RECV`.
+//!
+//! A [`Tracer`](crate::trace::Tracer) set with [`Vm::set_tracer`] sees each
+//! instruction before it runs, each frame entered and left and each slot
+//! and event, and keeps every function interpreted.
+//!
+//! Chain context and precompiles come from the
+//! [host function table](crate::bytecode::host): `ENV` reads its values and
+//! `CALLH` calls its functions, and both cost the entry's gas on top of
+//! their own.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+use crate::actors::Message;
+use crate::capabilities::Capabilities;
+use crate::bytecode::host::{self, HostFunction, HOST_VERSION};
+use crate::bytecode::{encode, parse_struct_layout, Constant, Instruction, Module, Type};
+use crate::jit::{self, Jit};
+use crate::merkle::StateTree;
+use crate::num::U256;
+use crate::state::{MemoryBackend, StateBackend};
+use crate::trace::{CallEnter, CallExit, CallKind, Step, Tracer};
+use crate::value::{Address, Event, Log, Trap, Value};
+
+/// Frames a call chain may hold, the top-level call's included. Each level
+/// takes native stack in either tier, so the limit keeps a debug build
+/// well within a 2 MiB thread stack.
+pub const MAX_CALL_DEPTH: usize = 128;
+
+/// Interpreted calls a function gets before it is compiled
+pub const DEFAULT_JIT_THRESHOLD: u32 = 10;
+
+/// Where the VM keeps its own state in the backend, so that handles kept
+/// in durable storage stay valid when it is reopened: the next resource
+/// id in slot 0 and the capability table above it. No contract can be
+/// deployed here.
+pub const RUNTIME_ADDRESS: Address = [0xff; 20];
+
+const NEXT_RESOURCE_SLOT: U256 = U256::ZERO;
+
+/// What the host functions that derive or revoke a capability charge on
+/// top of the call, so that they cost as much as `GRANTPERM`
+const CAPABILITY_WRITE_GAS: u64 = 4990;
+
+/// The transaction and block a top-level call runs in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Environment {
+    pub sender: Address,
+    pub origin: Address,
+    pub value: U256,
+    pub timestamp: u64,
+    pub block_number: u64,
+    pub chain_id: u64,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            sender: [0x11; 20],
+            origin: [0x11; 20],
+            value: U256::ZERO,
+            timestamp: 1_700_000_000,
+            block_number: 1,
+            chain_id: 1,
+        }
+    }
+}
+
+/// The result of a top-level call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// The returned value, if the function left one
+    pub result: Result<Option<Value>, Trap>,
+    pub gas_used: u64,
+}
+
+/// What the interpreter does after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(u32),
+    Return,
+}
+
+/// A deployed module and what the VM keeps per contract
+struct Contract {
+    module: Rc<Module>,
+    /// Declared types of storage slots and the map entries derived from
+    /// them, so that unwritten slots read as a zero of the right type
+    slot_types: HashMap<U256, Type>,
+    tiers: Vec<Tier>,
+    /// Keccak-256 of the encoded module
+    code_hash: U256,
+}
+
+struct Frame {
+    /// The contract whose code runs, which is also `self`
+    contract: Address,
+    module: Rc<Module>,
+    sender: Address,
+    value: U256,
+    function: u32,
+    locals: Vec<Value>,
+    /// Stack height below the frame's operands
+    base: usize,
+    /// Whether the frame holds its function's reentrancy lock
+    guarded: bool,
+    /// The message being handled, for `RECV`; shared with the internal
+    /// calls the handler makes but not with other contracts
+    message: Option<Rc<Value>>,
+    /// Resources the frame owns, by id and kind: acquired by it, passed to
+    /// it or returned to it, and not yet released, stored, sent or passed on
+    held: Vec<(u64, String)>,
+}
+
+impl Frame {
+    /// Gives up resource `id`, which must be held
+    fn give_up(&mut self, id: u64, kind: &str) -> Result<(), Trap> {
+        let index = self.held.iter()
+            .position(|(held, _)| *held == id)
+            .ok_or_else(|| Trap::ResourceNotHeld(kind.to_string()))?;
+        self.held.swap_remove(index);
+        Ok(())
+    }
+}
+
+/// An effect that rolling back the transaction it happened in undoes.
+/// Storage writes, the runtime's included, are journaled by the state
+/// backend.
+enum Change {
+    /// A balance as it was before a transfer or [`Vm::set_balance`]
+    Balance(Address, Option<U256>),
+    /// An event was emitted
+    Event,
+    /// A message was sent
+    Sent,
+    /// A capability was issued or derived
+    Granted,
+    /// The capability with this id was revoked
+    Revoked(u64),
+}
+
+#[derive(Clone, Copy)]
+enum Tier {
+    Interpreted { calls: u32 },
+    Compiled(jit::Entry),
+    /// The JIT refused the function; it stays interpreted
+    Rejected,
+}
+
+pub struct Vm {
+    /// Read and written in place by compiled code
+    pub(crate) gas_left: u64,
+    contracts: BTreeMap<Address, Contract>,
+    storage: Box<dyn StateBackend>,
+    /// Balance changes and events of the open transactions, in order
+    journal: Vec<Change>,
+    /// Where each open transaction starts in `journal`, outermost first.
+    /// Every top-level call and every sub-call runs in its own.
+    savepoints: Vec<usize>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    events: Vec<Event>,
+    /// Messages passed to `log`, kept when the call that logged them traps
+    logs: Vec<Log>,
+    /// Messages sent and not yet taken by a scheduler, in order
+    outbox: Vec<Message>,
+    balances: BTreeMap<Address, U256>,
+    capabilities: Capabilities,
+    pub env: Environment,
+    /// Functions currently inside their `@no_reentry` section
+    locked: Vec<(Address, u32)>,
+    jit: Option<Jit>,
+    jit_threshold: Option<u32>,
+    /// Set by compiled code when an instruction traps
+    pub(crate) trap: Option<Trap>,
+    /// The id the next `ACQUIRE` gives its resource
+    next_resource: u64,
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    /// A VM with no contracts, keeping storage in memory
+    pub fn new() -> Vm {
+        Vm::with_storage(Box::new(MemoryBackend::new())).expect("an empty backend holds no runtime state")
+    }
+
+    /// A VM over `storage`, picking up the capabilities and resource ids
+    /// that earlier VMs left in it
+    pub fn with_storage(storage: Box<dyn StateBackend>) -> Result<Vm, String> {
+        let capabilities = Capabilities::load(storage.as_ref())?;
+        let next_resource = match storage.get(&RUNTIME_ADDRESS, &NEXT_RESOURCE_SLOT) {
+            Some(Value::U256(next)) => next.to_u64().ok_or("resource id counter out of range")?,
+            Some(_) => return Err("malformed resource id counter".into()),
+            None => 0,
+        };
+        Ok(Vm {
+            gas_left: 0,
+            contracts: BTreeMap::new(),
+            storage,
+            journal: Vec::new(),
+            savepoints: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            events: Vec::new(),
+            logs: Vec::new(),
+            outbox: Vec::new(),
+            balances: BTreeMap::new(),
+            capabilities,
+            env: Environment::default(),
+            locked: Vec::new(),
+            jit: None,
+            jit_threshold: Some(DEFAULT_JIT_THRESHOLD),
+            trap: None,
+            next_resource,
+            tracer: None,
+        })
+    }
+
+    /// Validates `module` and deploys it at `address`
+    pub fn deploy(&mut self, address: Address, module: Module) -> Result<(), String> {
+        if self.contracts.contains_key(&address) {
+            return Err(format!("a contract is already deployed at 0x{}", crate::value::hex(&address)));
+        }
+        if address == RUNTIME_ADDRESS {
+            return Err(format!("0x{} is reserved for the runtime's own state", crate::value::hex(&address)));
+        }
+        // Host tables only grow, so a module built against any version up
+        // to this VM's finds every entry it was compiled to call
+        if !(1..=HOST_VERSION).contains(&module.host_version) {
+            return Err(format!(
+                "`{}` needs host version {}, but this VM implements versions 1 to {}",
+                module.name, module.host_version, HOST_VERSION
+            ));
+        }
+        module.validate()?;
+        let slot_types = module.storage.iter()
+            .map(|entry| (U256::from(entry.slot), entry.ty.clone()))
+            .collect();
+        let tiers = vec![Tier::Interpreted { calls: 0 }; module.functions.len()];
+        let code_hash = keccak256(&encode(&module));
+        self.contracts.insert(address, Contract { module: Rc::new(module), slot_types, tiers, code_hash });
+        Ok(())
+    }
+
+    /// Interpreted calls before a function is compiled; `None` keeps every
+    /// function in the interpreter and `Some(0)` compiles on first call
+    pub fn set_jit_threshold(&mut self, threshold: Option<u32>) {
+        self.jit_threshold = threshold;
+    }
+
+    /// Shows every instruction that runs from now on to `tracer`, and the
+    /// calls, storage accesses and events, or stops tracing with `None`.
+    /// Traced calls run in the interpreter whatever the JIT threshold.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) {
+        self.tracer = tracer;
+    }
+
+    /// Calls `name` of the contract at `address` with `gas_limit`, as one
+    /// transaction. A trap undoes the call's storage writes, events and
+    /// transfers but still consumes its gas. The error is for a call that
+    /// could not be made, or whose effects the state backend failed to
+    /// commit.
+    pub fn call(&mut self, address: &Address, name: &str, args: Vec<Value>, gas_limit: u64) -> Result<Outcome, String> {
+        let module = self.contracts.get(address)
+            .map(|contract| Rc::clone(&contract.module))
+            .ok_or_else(|| format!("no contract at 0x{}", crate::value::hex(address)))?;
+        let index = module.function_index(name)
+            .ok_or_else(|| format!("no function `{}` in `{}`", name, module.name))?;
+        let arity = module.functions[index as usize].arity as usize;
+        if args.len() != arity {
+            return Err(format!("`{}` takes {} arguments, got {}", name, arity, args.len()));
+        }
+        let (sender, value) = (self.env.sender, self.env.value);
+        self.transact(*address, index, args, sender, value, None, gas_limit)
+    }
+
+    /// Delivers `message` to its handler with `gas_limit`, as one
+    /// transaction with the sending contract as `msg.sender`. The handler
+    /// must be an exported function without arguments. The error is for a
+    /// message that cannot be delivered, as for [`Vm::call`].
+    pub fn deliver(&mut self, message: &Message, gas_limit: u64) -> Result<Outcome, String> {
+        let index = self.handler(message)?;
+        let body = Some(Rc::new(message.body.clone()));
+        self.transact(message.to, index, Vec::new(), message.from, U256::ZERO, body, gas_limit)
+    }
+
+    /// The function that handles `message`, or why there is none
+    pub(crate) fn handler(&self, message: &Message) -> Result<u32, String> {
+        let module = &self.contracts.get(&message.to)
+            .ok_or_else(|| format!("no contract at 0x{}", crate::value::hex(&message.to)))?
+            .module;
+        let index = module.abi.functions.iter()
+            .map(|entry| entry.function)
+            .find(|&index| module.functions[index as usize].name == message.handler)
+            .ok_or_else(|| format!("no handler `{}` in `{}`", message.handler, module.name))?;
+        if module.functions[index as usize].arity != 0 {
+            return Err(format!("handler `{}` of `{}` takes arguments", message.handler, module.name));
+        }
+        Ok(index)
+    }
+
+    /// Messages sent by calls whose transactions have closed, for a
+    /// scheduler to deliver. While a transaction is open nothing is taken,
+    /// since rolling it back unsends its messages.
+    pub fn take_outbox(&mut self) -> Vec<Message> {
+        if !self.savepoints.is_empty() {
+            return Vec::new();
+        }
+        std::mem::take(&mut self.outbox)
+    }
+
+    /// Runs function `index` of `address` as one top-level transaction
+    #[allow(clippy::too_many_arguments)]
+    fn transact(
+        &mut self,
+        address: Address,
+        index: u32,
+        args: Vec<Value>,
+        sender: Address,
+        value: U256,
+        message: Option<Rc<Value>>,
+        gas_limit: u64,
+    ) -> Result<Outcome, String> {
+        self.begin();
+        self.gas_left = gas_limit;
+        self.stack = args;
+        self.frames.clear();
+        self.locked.clear();
+        self.trap = None;
+
+        let result = self.invoke(CallKind::Message, address, index, sender, value, message).map(|()| self.stack.pop());
+        self.stack.clear();
+        match result {
+            Ok(_) => self.commit()?,
+            Err(_) => self.rollback(),
+        }
+        Ok(Outcome { result, gas_used: gas_limit - self.gas_left })
+    }
+
+    /// Opens a transaction inside the current one, if any. Calls made
+    /// until it is closed can be undone together, which lets a test fork
+    /// the state and discard it.
+    pub fn begin(&mut self) {
+        self.savepoints.push(self.journal.len());
+        self.storage.begin();
+    }
+
+    /// Closes the innermost transaction, keeping its effects; closing the
+    /// outermost makes them durable in the state backend
+    pub fn commit(&mut self) -> Result<(), String> {
+        let mark = self.savepoints.pop().ok_or("no transaction to commit")?;
+        if let Err(error) = self.storage.commit() {
+            self.undo(mark);
+            return Err(error);
+        }
+        if self.savepoints.is_empty() {
+            self.journal.clear();
+        }
+        Ok(())
+    }
+
+    /// Closes the innermost transaction, undoing its storage writes,
+    /// events and transfers
+    pub fn rollback(&mut self) {
+        if let Some(mark) = self.savepoints.pop() {
+            self.storage.rollback();
+            self.undo(mark);
+        }
+    }
+
+    /// Undoes the balance changes and events journaled from `mark` on
+    fn undo(&mut self, mark: usize) {
+        for change in self.journal.drain(mark..).rev() {
+            match change {
+                Change::Balance(address, Some(balance)) => {
+                    self.balances.insert(address, balance);
+                }
+                Change::Balance(address, None) => {
+                    self.balances.remove(&address);
+                }
+                Change::Event => {
+                    self.events.pop();
+                }
+                Change::Sent => {
+                    self.outbox.pop();
+                }
+                Change::Granted => self.capabilities.pop(),
+                Change::Revoked(id) => self.capabilities.restore(id),
+            }
+        }
+    }
+
+    /// Runs function `index` of the contract at `contract` on the arguments
+    /// at the top of the stack, leaving its return value, if any, in their
+    /// place. The tracer sees it as a call of `kind`.
+    fn invoke(
+        &mut self,
+        kind: CallKind,
+        contract: Address,
+        index: u32,
+        sender: Address,
+        value: U256,
+        message: Option<Rc<Value>>,
+    ) -> Result<(), Trap> {
+        if self.tracer.is_none() {
+            return self.call_frame(contract, index, sender, value, message);
+        }
+        let gas_left = self.gas_left;
+        let depth = self.frames.len() + 1;
+        let arity = self.contracts[&contract].module.functions[index as usize].arity as usize;
+        let base = self.stack.len().saturating_sub(arity);
+        self.with_tracer(|tracer, vm| {
+            let call = CallEnter {
+                kind,
+                from: sender,
+                to: contract,
+                module: &vm.contracts[&contract].module,
+                function: index,
+                args: &vm.stack[base..],
+                value,
+                gas_left,
+                depth,
+            };
+            tracer.enter(vm, &call);
+        });
+
+        let result = self.call_frame(contract, index, sender, value, message);
+        self.with_tracer(|tracer, vm| {
+            let result = match &result {
+                Ok(()) => Ok(vm.stack.get(base..).and_then(<[Value]>::last)),
+                Err(trap) => Err(trap),
+            };
+            tracer.exit(vm, &CallExit { kind, depth, gas_used: gas_left - vm.gas_left, result });
+        });
+        result
+    }
+
+    /// Sets up a frame for [`Vm::invoke`], runs it and takes it down
+    fn call_frame(
+        &mut self,
+        contract: Address,
+        index: u32,
+        sender: Address,
+        value: U256,
+        message: Option<Rc<Value>>,
+    ) -> Result<(), Trap> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err(Trap::CallDepthExceeded);
+        }
+        let module = Rc::clone(&self.contracts[&contract].module);
+        let function = &module.functions[index as usize];
+        let arity = function.arity as usize;
+        if self.stack.len() < arity {
+            return Err(Trap::StackUnderflow);
+        }
+        let mut locals = self.stack.split_off(self.stack.len() - arity);
+        // Resources in the arguments move from the caller to the callee.
+        // They never leave the VM, so a transaction cannot pass any in,
+        // but the handler of a message owns what it carries.
+        let mut held = Vec::new();
+        for argument in &locals {
+            resources(argument, &mut held);
+        }
+        match self.frames.last_mut() {
+            Some(caller) => {
+                for (id, kind) in &held {
+                    caller.give_up(*id, kind)?;
+                }
+            }
+            None => {
+                if let Some((_, kind)) = held.pop() {
+                    return Err(Trap::ResourceNotHeld(kind));
+                }
+                if let Some(message) = &message {
+                    resources(message, &mut held);
+                }
+            }
+        }
+        locals.resize(function.locals as usize, Value::U256(U256::ZERO));
+        let base = self.stack.len();
+        self.frames.push(Frame {
+            contract, module, sender, value, function: index, locals, base, guarded: false, message, held,
+        });
+
+        let result = self.run(contract, index);
+        let mut frame = self.frames.pop().expect("frame pushed above");
+        if frame.guarded {
+            self.locked.retain(|&locked| locked != (contract, index));
+        }
+        result?;
+        if self.stack.len() > frame.base + 1 {
+            let value = self.stack.pop();
+            self.stack.truncate(frame.base);
+            self.stack.extend(value);
+        }
+
+        // Resources in the return value move back to the caller; any
+        // other the callee still holds, or any returned from the
+        // transaction, would be lost
+        let mut returned = Vec::new();
+        if self.stack.len() > frame.base {
+            resources(self.stack.last().expect("checked above"), &mut returned);
+        }
+        if let Some(caller) = self.frames.last_mut() {
+            for (id, kind) in returned {
+                if frame.give_up(id, &kind).is_ok() {
+                    caller.held.push((id, kind));
+                }
+            }
+        }
+        if let Some((_, kind)) = frame.held.pop() {
+            let function = frame.module.functions[index as usize].name.clone();
+            return Err(Trap::ResourceLeak { kind, function });
+        }
+        Ok(())
+    }
+
+    /// Runs the top frame in whichever tier its function has reached
+    fn run(&mut self, contract: Address, index: u32) -> Result<(), Trap> {
+        let mut pc = 0;
+        if let Some(entry) = self.tier_up(contract, index) {
+            // SAFETY: `entry` was compiled by this VM's own `Jit` from a
+            // module the VM keeps alive, and the `Jit` outlives the call.
+            let status = unsafe { entry(self as *mut Vm) };
+            match status {
+                jit::RETURNED => return Ok(()),
+                jit::TRAPPED => return Err(self.trap.take().expect("compiled code trapped without a trap")),
+                // The block at `status` could not prepay its gas; the
+                // interpreter charges it instruction by instruction
+                deopt => pc = deopt as usize,
+            }
+        }
+        self.interpret(pc)
+    }
+
+    /// The compiled code for function `index` of `contract`, compiling it
+    /// once it is hot enough
+    fn tier_up(&mut self, contract: Address, index: u32) -> Option<jit::Entry> {
+        if self.tracer.is_some() {
+            return None;
+        }
+        let threshold = self.jit_threshold?;
+        let deployed = self.contracts.get_mut(&contract)?;
+        match deployed.tiers[index as usize] {
+            Tier::Compiled(entry) => return Some(entry),
+            Tier::Rejected => return None,
+            Tier::Interpreted { calls } if calls < threshold => {
+                deployed.tiers[index as usize] = Tier::Interpreted { calls: calls + 1 };
+                return None;
+            }
+            Tier::Interpreted { .. } => {}
+        }
+
+        if self.jit.is_none() {
+            self.jit = Jit::new().ok();
+        }
+        let compiled = self.jit.as_mut().and_then(|jit| jit.compile(&deployed.module, index).ok());
+        deployed.tiers[index as usize] = compiled.map_or(Tier::Rejected, Tier::Compiled);
+        compiled
+    }
+
+    fn interpret(&mut self, mut pc: usize) -> Result<(), Trap> {
+        let frame = self.frame();
+        let module = Rc::clone(&frame.module);
+        let code = &module.functions[frame.function as usize].code;
+        while let Some(instruction) = code.get(pc) {
+            let gas = module.gas_cost(instruction);
+            if self.tracer.is_some() {
+                self.trace(pc, instruction, gas);
+            }
+            self.charge(gas)?;
+            match self.execute(instruction)? {
+                Flow::Next => pc += 1,
+                Flow::Jump(target) => pc = target as usize,
+                Flow::Return => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows the instruction at `pc` of the top frame to the tracer
+    fn trace(&mut self, pc: usize, instruction: &Instruction, gas_cost: u64) {
+        self.with_tracer(|tracer, vm| {
+            let frame = vm.frame();
+            let step = Step {
+                contract: frame.contract,
+                module: &frame.module,
+                function: frame.function,
+                pc,
+                instruction,
+                gas_left: vm.gas_left,
+                gas_cost,
+                depth: vm.frames.len(),
+                locals: &frame.locals,
+                stack: &vm.stack[frame.base..],
+            };
+            tracer.step(vm, &step);
+        });
+    }
+
+    /// Shows the tracer, if there is one, the VM as it stands
+    fn with_tracer(&mut self, show: impl FnOnce(&mut dyn Tracer, &Vm)) {
+        if let Some(mut tracer) = self.tracer.take() {
+            show(tracer.as_mut(), self);
+            self.tracer = Some(tracer);
+        }
+    }
+
+    fn charge(&mut self, gas: u64) -> Result<(), Trap> {
+        match self.gas_left.checked_sub(gas) {
+            Some(left) => {
+                self.gas_left = left;
+                Ok(())
+            }
+            None => {
+                self.gas_left = 0;
+                Err(Trap::OutOfGas)
+            }
+        }
+    }
+
+    /// Executes one instruction in the top frame. Its gas has already been
+    /// paid.
+    pub(crate) fn execute(&mut self, instruction: &Instruction) -> Result<Flow, Trap> {
+        // Every level of a call chain keeps this function's native frame
+        // alive, so only calls run here and the rest of the instructions,
+        // whose frame is far larger in debug builds, run in `operate`
+        match *instruction {
+            Instruction::Call(index, _) => {
+                let frame = self.frame();
+                let message = frame.message.clone();
+                self.invoke(CallKind::Internal, frame.contract, index, frame.sender, frame.value, message)?;
+            }
+            Instruction::CallMethod(method, argc) => {
+                let args = self.pop_n(argc as usize)?;
+                let receiver = self.pop()?;
+                let result = self.call_method(receiver, self.name(method).to_string(), args)?;
+                self.stack.push(result);
+            }
+            Instruction::CallContract(method, argc) => {
+                let args = self.pop_n(argc as usize)?;
+                let address = self.pop_address("CALLC")?;
+                let method = self.name(method).to_string();
+                if !self.contracts.contains_key(&address) {
+                    return Err(Trap::NoContract { address, method });
+                }
+                let result = self.call_contract(address, method, args)?;
+                self.stack.push(result);
+            }
+            Instruction::CallIndirect(argc) => {
+                let args = self.pop_n(argc as usize)?;
+                match self.pop()? {
+                    Value::Closure { function, captured } => {
+                        self.stack.extend(captured);
+                        self.stack.extend(args);
+                        let frame = self.frame();
+                        let message = frame.message.clone();
+                        self.invoke(CallKind::Internal, frame.contract, function, frame.sender, frame.value, message)?;
+                    }
+                    _ => return Err(Trap::TypeMismatch("CALLI")),
+                }
+            }
+            _ => return self.operate(instruction),
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Executes an instruction other than a call for [`Vm::execute`]
+    #[inline(never)]
+    fn operate(&mut self, instruction: &Instruction) -> Result<Flow, Trap> {
+        match *instruction {
+            Instruction::Push(index) => {
+                let value = Value::from_constant(&self.frame().module.constants[index as usize]);
+                self.stack.push(value);
+            }
+            Instruction::Pop => {
+                self.pop()?;
+            }
+            Instruction::Dup(n) => {
+                let value = self.peek(n as usize)?.clone();
+                self.stack.push(value);
+            }
+            Instruction::Swap(n) => {
+                let top = self.stack.len().checked_sub(1).ok_or(Trap::StackUnderflow)?;
+                let base = self.frames.last().map_or(0, |frame| frame.base);
+                let other = top.checked_sub(n as usize + 1).filter(|&other| other >= base).ok_or(Trap::StackUnderflow)?;
+                self.stack.swap(top, other);
+            }
+
+            Instruction::Load(index) => {
+                let value = self.local(index)?.clone();
+                self.stack.push(value);
+            }
+            Instruction::Store(index) => {
+                let value = self.pop()?;
+                *self.local(index)? = value;
+            }
+
+            Instruction::Add => self.arithmetic("ADD", |a, b| a.checked_add(b).ok_or(Trap::ArithmeticOverflow))?,
+            Instruction::Sub => self.arithmetic("SUB", |a, b| a.checked_sub(b).ok_or(Trap::ArithmeticOverflow))?,
+            Instruction::Mul => self.arithmetic("MUL", |a, b| a.checked_mul(b).ok_or(Trap::ArithmeticOverflow))?,
+            Instruction::Div => self.arithmetic("DIV", |a, b| a.checked_div(b).ok_or(Trap::DivisionByZero))?,
+            Instruction::Mod => self.arithmetic("MOD", |a, b| a.checked_rem(b).ok_or(Trap::DivisionByZero))?,
+            Instruction::Eq => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push(Value::Bool(a == b));
+            }
+            Instruction::Lt => self.compare("LT", |a, b| a < b)?,
+            Instruction::Gt => self.compare("GT", |a, b| a > b)?,
+            Instruction::LtEq => self.compare("LTE", |a, b| a <= b)?,
+            Instruction::GtEq => self.compare("GTE", |a, b| a >= b)?,
+            Instruction::And => self.logic("AND", |a, b| a & b, |a, b| a && b)?,
+            Instruction::Or => self.logic("OR", |a, b| a | b, |a, b| a || b)?,
+            Instruction::Xor => self.logic("XOR", |a, b| a ^ b, |a, b| a != b)?,
+            Instruction::Not => {
+                let value = match self.pop()? {
+                    Value::Bool(b) => Value::Bool(!b),
+                    Value::U256(n) => Value::U256(!n),
+                    _ => return Err(Trap::TypeMismatch("NOT")),
+                };
+                self.stack.push(value);
+            }
+
+            Instruction::Jump(target) => return Ok(Flow::Jump(target)),
+            Instruction::JumpIf(target) => match self.pop()? {
+                Value::Bool(true) => return Ok(Flow::Jump(target)),
+                Value::Bool(false) => {}
+                _ => return Err(Trap::TypeMismatch("JUMPI")),
+            },
+            Instruction::Return => return Ok(Flow::Return),
+            Instruction::Call(..)
+            | Instruction::CallMethod(..)
+            | Instruction::CallContract(..)
+            | Instruction::CallIndirect(_) => unreachable!("`execute` runs calls itself"),
+            Instruction::Revert(message) => return Err(Trap::Revert(self.name(message).to_string())),
+            Instruction::CallHost(function, argc) => {
+                let args = self.pop_n(argc as usize)?;
+                let function = self.name(function).to_string();
+                let result = self.call_host(&function, args)?;
+                self.stack.push(result);
+            }
+            Instruction::Closure(function, captured) => {
+                let captured = self.pop_n(captured as usize)?;
+                self.stack.push(Value::Closure { function, captured });
+            }
+
+            Instruction::SLoad(slot) => {
+                let value = self.load(U256::from(slot));
+                self.stack.push(value);
+            }
+            Instruction::SStore(slot) => {
+                let value = self.pop()?;
+                self.store(U256::from(slot), value);
+            }
+            Instruction::Emit(event, argc) => {
+                let fields = self.pop_n(argc as usize)?;
+                let frame = self.frame();
+                let name = frame.module.abi.events[event as usize].name.clone();
+                self.events.push(Event { address: frame.contract, name, fields });
+                self.record(Change::Event);
+                self.with_tracer(|tracer, vm| tracer.event(vm, vm.events.last().expect("pushed above")));
+            }
+            Instruction::MapSlot => {
+                let key = self.pop()?;
+                let slot = self.pop_u256("MAPSLOT")?;
+                let mut hasher = Sha256::new();
+                hasher.update(slot.to_be_bytes());
+                hasher.update(key.to_bytes());
+                let derived = U256::from_be_bytes(hasher.finalize().into());
+                let contract = self.frame().contract;
+                let slot_types = &mut self.contracts.get_mut(&contract).expect("frames run deployed contracts").slot_types;
+                if let Some(Type::Map { value, .. }) = slot_types.get(&slot) {
+                    let ty = (**value).clone();
+                    slot_types.insert(derived, ty);
+                }
+                self.stack.push(Value::U256(derived));
+            }
+            Instruction::SLoadAt => {
+                let slot = self.pop_u256("SLOADAT")?;
+                let value = self.load(slot);
+                self.stack.push(value);
+            }
+            Instruction::SStoreAt => {
+                let value = self.pop()?;
+                let slot = self.pop_u256("SSTOREAT")?;
+                self.store(slot, value);
+            }
+            Instruction::Env(name) => {
+                let name = self.name(name);
+                let (_, entry) = host::lookup(name)
+                    .filter(|(_, entry)| entry.is_value())
+                    .ok_or_else(|| Trap::UnknownEnvironment(name.to_string()))?;
+                let value = self.host(entry, Vec::new())?;
+                self.stack.push(value);
+            }
+
+            Instruction::GuardEnter => {
+                let frame = self.frames.last_mut().ok_or(Trap::StackUnderflow)?;
+                let lock = (frame.contract, frame.function);
+                if self.locked.contains(&lock) {
+                    let function = frame.module.functions[frame.function as usize].name.clone();
+                    let chain = self.frames.iter()
+                        .map(|frame| format!("{}.{}", frame.module.name, frame.module.functions[frame.function as usize].name))
+                        .collect();
+                    return Err(Trap::Reentrancy { function, chain });
+                }
+                frame.guarded = true;
+                self.locked.push(lock);
+            }
+            Instruction::GuardExit => {
+                let frame = self.frames.last_mut().ok_or(Trap::StackUnderflow)?;
+                if frame.guarded {
+                    frame.guarded = false;
+                    let lock = (frame.contract, frame.function);
+                    self.locked.retain(|&locked| locked != lock);
+                }
+            }
+
+            Instruction::Tuple(n) => {
+                let elements = self.pop_n(n as usize)?;
+                self.stack.push(Value::Tuple(elements));
+            }
+            Instruction::Array(n) => {
+                let elements = self.pop_n(n as usize)?;
+                self.stack.push(Value::Array(elements));
+            }
+            Instruction::Extract(n) => {
+                let element = match self.pop()? {
+                    Value::Tuple(mut elements) | Value::Variant { payload: mut elements, .. } => {
+                        if (n as usize) < elements.len() {
+                            elements.swap_remove(n as usize)
+                        } else {
+                            return Err(Trap::IndexOutOfBounds);
+                        }
+                    }
+                    _ => return Err(Trap::TypeMismatch("EXTRACT")),
+                };
+                self.stack.push(element);
+            }
+            Instruction::Struct(layout, n) => {
+                let values = self.pop_n(n as usize)?;
+                let (name, fields) = parse_struct_layout(self.name(layout)).ok_or(Trap::TypeMismatch("STRUCT"))?;
+                let fields = fields.into_iter().map(String::from).zip(values).collect();
+                self.stack.push(Value::Struct { name: name.to_string(), fields });
+            }
+            Instruction::Variant(path, n) => {
+                let payload = self.pop_n(n as usize)?;
+                self.stack.push(Value::Variant { path: self.name(path).to_string(), payload });
+            }
+            Instruction::IsVariant(path) => {
+                let matches = match self.pop()? {
+                    Value::Variant { path: actual, .. } => same_variant(&actual, self.name(path)),
+                    _ => false,
+                };
+                self.stack.push(Value::Bool(matches));
+            }
+            Instruction::GetField(field) => {
+                let struct_value = match self.pop()? {
+                    Value::Resource { value, .. } => *value,
+                    value => value,
+                };
+                let field = self.name(field);
+                let value = match struct_value {
+                    Value::Struct { fields, .. } => fields.into_iter()
+                        .find(|(name, _)| name == field)
+                        .map(|(_, value)| value)
+                        .ok_or_else(|| Trap::UnknownMethod(field.to_string()))?,
+                    _ => return Err(Trap::TypeMismatch("GETFIELD")),
+                };
+                self.stack.push(value);
+            }
+            Instruction::SetField(field) => {
+                let value = self.pop()?;
+                let field = self.name(field).to_string();
+                let target = match self.stack.last_mut() {
+                    Some(Value::Resource { value, .. }) => Some(&mut **value),
+                    target => target,
+                };
+                match target {
+                    Some(Value::Struct { fields, .. }) => match fields.iter_mut().find(|(name, _)| *name == field) {
+                        Some((_, slot)) => *slot = value,
+                        None => fields.push((field, value)),
+                    },
+                    Some(_) => return Err(Trap::TypeMismatch("SETFIELD")),
+                    None => return Err(Trap::StackUnderflow),
+                }
+            }
+            Instruction::Index => {
+                let index = self.pop_index("INDEX")?;
+                let element = match self.pop()? {
+                    Value::Array(mut elements) | Value::Tuple(mut elements) if index < elements.len() => {
+                        elements.swap_remove(index)
+                    }
+                    Value::Bytes(bytes) if index < bytes.len() => Value::U256(U256::from(bytes[index])),
+                    Value::Array(_) | Value::Tuple(_) | Value::Bytes(_) => return Err(Trap::IndexOutOfBounds),
+                    _ => return Err(Trap::TypeMismatch("INDEX")),
+                };
+                self.stack.push(element);
+            }
+            Instruction::SetIndex => {
+                let element = self.pop()?;
+                let index = self.pop_index("SETINDEX")?;
+                match self.stack.last_mut() {
+                    Some(Value::Array(elements)) => {
+                        *elements.get_mut(index).ok_or(Trap::IndexOutOfBounds)? = element;
+                    }
+                    Some(_) => return Err(Trap::TypeMismatch("SETINDEX")),
+                    None => return Err(Trap::StackUnderflow),
+                }
+            }
+            Instruction::Cast(ty) => {
+                let value = self.pop()?;
+                let value = match (self.name(ty), value) {
+                    ("u256", Value::U256(n)) => Value::U256(n),
+                    ("u256", Value::Bool(b)) => Value::U256(U256::from(b)),
+                    ("u256", Value::Address(a)) => {
+                        Value::U256(U256::from_be_slice(&a).expect("20 bytes fit in a word"))
+                    }
+                    ("address", Value::Address(a)) => Value::Address(a),
+                    ("address", Value::U256(n)) => {
+                        let mut address = [0; 20];
+                        address.copy_from_slice(&n.to_be_bytes()[12..]);
+                        Value::Address(address)
+                    }
+                    ("bool", Value::Bool(b)) => Value::Bool(b),
+                    _ => return Err(Trap::TypeMismatch("CAST")),
+                };
+                self.stack.push(value);
+            }
+
+            Instruction::Balance => {
+                let account = self.pop_address("BALANCE")?;
+                self.stack.push(Value::U256(self.balance_of(&account)));
+            }
+            Instruction::Transfer => {
+                let amount = self.pop_u256("TRANSFER")?;
+                let to = self.pop_address("TRANSFER")?;
+                self.transfer(to, amount)?;
+            }
+            Instruction::Send(handler) => {
+                let body = self.pop()?;
+                let Value::Address(to) = self.pop()? else {
+                    return Err(Trap::TypeMismatch("SEND"));
+                };
+                self.give_up_resources(&body)?;
+                let from = self.frame().contract;
+                self.outbox.push(Message { from, to, handler: self.name(handler).to_string(), body });
+                self.record(Change::Sent);
+            }
+            Instruction::Receive => {
+                let message = self.frame().message.as_deref().ok_or(Trap::NoMessage)?.clone();
+                self.stack.push(message);
+            }
+            Instruction::Acquire(kind) => {
+                let value = self.pop()?;
+                let kind = self.name(kind).to_string();
+                let id = self.next_resource;
+                self.next_resource += 1;
+                self.storage.set(RUNTIME_ADDRESS, NEXT_RESOURCE_SLOT, Value::U256(U256::from(self.next_resource)));
+                self.frames.last_mut().ok_or(Trap::StackUnderflow)?.held.push((id, kind.clone()));
+                self.stack.push(Value::Resource { kind, id, value: Box::new(value) });
+            }
+            Instruction::Release(expected) => {
+                let expected = self.name(expected).to_string();
+                match self.pop()? {
+                    Value::Resource { kind, id, .. } if kind == expected => {
+                        self.frames.last_mut().ok_or(Trap::StackUnderflow)?.give_up(id, &kind)?;
+                    }
+                    _ => return Err(Trap::TypeMismatch("RELEASE")),
+                }
+            }
+            Instruction::CheckPermission(right) => {
+                let account = self.pop_address("CHECKPERM")?;
+                let frame = self.frame();
+                let right = self.name(right);
+                let held = self.capabilities.has_permission(&frame.contract, &account, right, self.env.block_number);
+                self.stack.push(Value::Bool(held));
+            }
+            Instruction::GrantPermission(right) => {
+                let expires = expiry(self.pop_u256("GRANTPERM")?);
+                let holder = self.pop_address("GRANTPERM")?;
+                let (issuer, right) = (self.frame().contract, self.name(right).to_string());
+                let id = self.capabilities.issue(issuer, &right, holder, expires);
+                self.record(Change::Granted);
+                self.capabilities.persist(id, self.storage.as_mut());
+                self.stack.push(Value::Capability(id));
+            }
+            Instruction::RevokePermission(right) => {
+                let holder = self.pop_address("REVOKEPERM")?;
+                let (issuer, right) = (self.frame().contract, self.name(right).to_string());
+                for id in self.capabilities.revoke_held(&issuer, &holder, &right) {
+                    self.record(Change::Revoked(id));
+                    self.capabilities.persist(id, self.storage.as_mut());
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn call_method(&mut self, receiver: Value, method: String, args: Vec<Value>) -> Result<Value, Trap> {
+        if let Value::Address(address) = receiver {
+            if self.contracts.contains_key(&address) {
+                return self.call_contract(address, method, args);
+            }
+        }
+        let result = match (method.as_str(), receiver, args.as_slice()) {
+            ("len", Value::Array(elements), []) => U256::from(elements.len()).into(),
+            ("len", Value::String(s), []) => U256::from(s.len()).into(),
+            ("len", Value::Bytes(bytes), []) => U256::from(bytes.len()).into(),
+            ("clone", value, []) => value,
+            // Collections are values, so `push` returns the grown array for
+            // the caller to store back
+            ("push", Value::Array(mut elements), [element]) => {
+                elements.push(element.clone());
+                Value::Array(elements)
+            }
+            ("contains", Value::Array(elements), [element]) => Value::Bool(elements.contains(element)),
+            ("to_string", Value::U256(n), []) => Value::String(n.to_string()),
+            ("balance", Value::Address(address), []) => Value::U256(self.balance_of(&address)),
+            ("transfer", Value::Address(to), [Value::U256(amount)]) => {
+                self.transfer(to, *amount)?;
+                Value::unit()
+            }
+            (_, Value::Address(address), _) => return Err(Trap::NoContract { address, method }),
+            _ => return Err(Trap::UnknownMethod(method)),
+        };
+        Ok(result)
+    }
+
+    /// Calls the exported function `method` of the contract at `address`
+    /// on behalf of the running contract. If the callee traps, its own
+    /// effects are rolled back and the caller gets a [`failure`] value
+    /// instead of trapping too, unless gas ran out or a `@no_reentry`
+    /// function was re-entered.
+    fn call_contract(&mut self, address: Address, method: String, args: Vec<Value>) -> Result<Value, Trap> {
+        let module = &self.contracts[&address].module;
+        let index = module.abi.functions.iter()
+            .map(|entry| entry.function)
+            .find(|&index| module.functions[index as usize].name == method)
+            .ok_or(Trap::UnknownMethod(method))?;
+        if module.functions[index as usize].arity as usize != args.len() {
+            return Err(Trap::TypeMismatch("CALLM"));
+        }
+        let sender = self.frame().contract;
+        let base = self.stack.len();
+        let mut passed = Vec::new();
+        args.iter().for_each(|arg| resources(arg, &mut passed));
+        self.stack.extend(args);
+        self.begin();
+        if let Err(trap) = self.invoke(CallKind::Message, address, index, sender, U256::ZERO, None) {
+            self.rollback();
+            self.stack.truncate(base);
+            return match trap {
+                // The caller has no gas left to handle the failure with, and
+                // must not get to handle a reentrancy violation. Resources
+                // it passed went down with the callee, so it fails as well.
+                Trap::OutOfGas | Trap::Reentrancy { .. } => Err(trap),
+                trap if !passed.is_empty() => Err(trap),
+                trap => Ok(failure(trap)),
+            };
+        }
+        self.commit().expect("a sub-call's transaction is nested in its caller's");
+        Ok(if self.stack.len() > base { self.stack.pop().expect("checked above") } else { Value::unit() })
+    }
+
+    fn call_host(&mut self, function: &str, args: Vec<Value>) -> Result<Value, Trap> {
+        if let Some((_, entry)) = host::lookup(function) {
+            return self.host(entry, args);
+        }
+        let result = match (function, args.as_slice()) {
+            ("has_permission", [Value::Address(account), Value::String(right)]) => {
+                let issuer = self.frame().contract;
+                Value::Bool(self.capabilities.has_permission(&issuer, account, right, self.env.block_number))
+            }
+            ("Capability::delegate", [Value::Capability(id), Value::Address(to)]) => self.derive(*id, *to, None, true)?,
+            ("Capability::attenuate", [Value::Capability(id), Value::U256(expires), Value::Bool(delegable)]) => {
+                let holder = self.frame().contract;
+                self.derive(*id, holder, expiry(*expires), *delegable)?
+            }
+            ("Capability::revoke", [Value::Capability(id)]) => {
+                self.charge(CAPABILITY_WRITE_GAS)?;
+                let by = self.frame().contract;
+                if self.capabilities.revoke(*id, &by)? {
+                    self.record(Change::Revoked(*id));
+                    self.capabilities.persist(*id, self.storage.as_mut());
+                }
+                Value::unit()
+            }
+            ("Capability::is_valid", [Value::Capability(id)]) => {
+                Value::Bool(self.capabilities.is_live(*id, self.env.block_number))
+            }
+            _ => return Err(Trap::UnknownHostFunction(function.to_string())),
+        };
+        Ok(result)
+    }
+
+    /// Runs an entry of the host function table
+    fn host(&mut self, entry: &HostFunction, args: Vec<Value>) -> Result<Value, Trap> {
+        let frame = self.frame();
+        let (sender, value, contract) = (frame.sender, frame.value, frame.contract);
+        let result = match (entry.name, args.as_slice()) {
+            ("msg.sender", []) => Value::Address(sender),
+            ("tx.origin", []) => Value::Address(self.env.origin),
+            ("msg.value", []) => Value::U256(value),
+            ("self", []) => Value::Address(contract),
+            ("block.number", []) => Value::U256(U256::from(self.env.block_number)),
+            ("block.timestamp", []) => Value::U256(U256::from(self.env.timestamp)),
+            ("chain.id", []) => Value::U256(U256::from(self.env.chain_id)),
+            ("self.balance", []) => Value::U256(self.balance_of(&contract)),
+            ("balance", [Value::Address(account)]) => Value::U256(self.balance_of(account)),
+            ("code_hash", [Value::Address(account)]) => {
+                Value::U256(self.contracts.get(account).map_or(U256::ZERO, |contract| contract.code_hash))
+            }
+            ("keccak256", [data]) => Value::U256(keccak256(&raw_bytes(data)?)),
+            ("sha256", [data]) => Value::U256(U256::from_be_bytes(Sha256::digest(raw_bytes(data)?).into())),
+            ("ed25519_verify", [key, message, signature]) => {
+                Value::Bool(ed25519_verify(&raw_bytes(key)?, &raw_bytes(message)?, &raw_bytes(signature)?))
+            }
+            ("min", [Value::U256(a), Value::U256(b)]) => Value::U256(*a.min(b)),
+            ("max", [Value::U256(a), Value::U256(b)]) => Value::U256(*a.max(b)),
+            ("sqrt", [Value::U256(n)]) => Value::U256(n.isqrt()),
+            ("log", [message]) => {
+                let message = match message {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                };
+                self.logs.push(Log { address: contract, message });
+                Value::unit()
+            }
+            _ => return Err(Trap::TypeMismatch("CALLH")),
+        };
+        Ok(result)
+    }
+
+    /// Derives a capability from `id`, which the running contract holds,
+    /// for `holder`
+    fn derive(&mut self, id: u64, holder: Address, expires: Option<u64>, delegable: bool) -> Result<Value, Trap> {
+        self.charge(CAPABILITY_WRITE_GAS)?;
+        let by = self.frame().contract;
+        let id = self.capabilities.derive(id, &by, holder, expires, delegable, self.env.block_number)?;
+        self.record(Change::Granted);
+        self.capabilities.persist(id, self.storage.as_mut());
+        Ok(Value::Capability(id))
+    }
+
+    /// Moves `amount` from the running contract's balance to `to`
+    fn transfer(&mut self, to: Address, amount: U256) -> Result<(), Trap> {
+        let from = self.frame().contract;
+        let left = self.balance_of(&from).checked_sub(amount).ok_or(Trap::InsufficientBalance)?;
+        self.write_balance(from, left);
+        let received = self.balance_of(&to).checked_add(amount).ok_or(Trap::ArithmeticOverflow)?;
+        self.write_balance(to, received);
+        Ok(())
+    }
+
+    fn write_balance(&mut self, address: Address, balance: U256) {
+        let previous = self.balances.insert(address, balance);
+        self.record(Change::Balance(address, previous));
+    }
+
+    /// Journals `change` if a transaction is open to undo it
+    fn record(&mut self, change: Change) {
+        if !self.savepoints.is_empty() {
+            self.journal.push(change);
+        }
+    }
+
+    fn balance_of(&self, address: &Address) -> U256 {
+        self.balances.get(address).copied().unwrap_or(U256::ZERO)
+    }
+
+    /// Reads a slot of the running contract
+    fn load(&mut self, slot: U256) -> Value {
+        let contract = self.frame().contract;
+        let value = self.storage_at(&contract, slot);
+        self.with_tracer(|tracer, vm| tracer.storage_read(vm, &contract, slot, &value));
+        value
+    }
+
+    /// Writes `value` to `slot` of the running contract. Resources the
+    /// frame holds move into storage; storing a copy read from storage
+    /// puts it back.
+    fn store(&mut self, slot: U256, value: Value) {
+        let contract = self.frame().contract;
+        self.with_tracer(|tracer, vm| tracer.storage_write(vm, &contract, slot, &value));
+        let mut stored = Vec::new();
+        resources(&value, &mut stored);
+        let frame = self.frames.last_mut().expect("instructions run in a frame");
+        frame.held.retain(|(id, _)| !stored.iter().any(|(stored, _)| stored == id));
+        self.storage.set(contract, slot, value);
+    }
+
+    /// Moves the resources in `value` out of the running frame
+    fn give_up_resources(&mut self, value: &Value) -> Result<(), Trap> {
+        let mut moved = Vec::new();
+        resources(value, &mut moved);
+        let frame = self.frames.last_mut().ok_or(Trap::StackUnderflow)?;
+        for (id, kind) in moved {
+            frame.give_up(id, &kind)?;
+        }
+        Ok(())
+    }
+
+    /// The running frame; instructions only execute inside one
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("instructions run in a frame")
+    }
+
+    /// The string constant an operand names; validation guarantees it
+    fn name(&self, index: u32) -> &str {
+        match &self.frame().module.constants[index as usize] {
+            Constant::String(name) => name,
+            _ => unreachable!("validated modules only name string constants"),
+        }
+    }
+
+    fn local(&mut self, index: u32) -> Result<&mut Value, Trap> {
+        self.frames.last_mut()
+            .and_then(|frame| frame.locals.get_mut(index as usize))
+            .ok_or(Trap::InvalidLocal(index))
+    }
+
+    /// Pops from the current frame's operands only
+    fn pop(&mut self) -> Result<Value, Trap> {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        if self.stack.len() <= base {
+            return Err(Trap::StackUnderflow);
+        }
+        Ok(self.stack.pop().expect("checked above"))
+    }
+
+    /// Pops `n` values, returning them in the order they were pushed
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Value>, Trap> {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        if self.stack.len() < base + n {
+            return Err(Trap::StackUnderflow);
+        }
+        Ok(self.stack.split_off(self.stack.len() - n))
+    }
+
+    fn peek(&self, depth: usize) -> Result<&Value, Trap> {
+        let base = self.frames.last().map_or(0, |frame| frame.base);
+        self.stack.len().checked_sub(depth + 1)
+            .filter(|&index| index >= base)
+            .map(|index| &self.stack[index])
+            .ok_or(Trap::StackUnderflow)
+    }
+
+    fn pop_u256(&mut self, instruction: &'static str) -> Result<U256, Trap> {
+        match self.pop()? {
+            Value::U256(n) => Ok(n),
+            _ => Err(Trap::TypeMismatch(instruction)),
+        }
+    }
+
+    fn pop_address(&mut self, instruction: &'static str) -> Result<Address, Trap> {
+        match self.pop()? {
+            Value::Address(address) => Ok(address),
+            _ => Err(Trap::TypeMismatch(instruction)),
+        }
+    }
+
+    fn pop_index(&mut self, instruction: &'static str) -> Result<usize, Trap> {
+        let index = self.pop_u256(instruction)?;
+        Ok(index.to_u64().map_or(usize::MAX, |index| index as usize))
+    }
+
+    fn arithmetic(
+        &mut self,
+        instruction: &'static str,
+        op: impl FnOnce(U256, U256) -> Result<U256, Trap>,
+    ) -> Result<(), Trap> {
+        let b = self.pop_u256(instruction)?;
+        let a = self.pop_u256(instruction)?;
+        self.stack.push(Value::U256(op(a, b)?));
+        Ok(())
+    }
+
+    fn compare(&mut self, instruction: &'static str, op: impl FnOnce(U256, U256) -> bool) -> Result<(), Trap> {
+        let b = self.pop_u256(instruction)?;
+        let a = self.pop_u256(instruction)?;
+        self.stack.push(Value::Bool(op(a, b)));
+        Ok(())
+    }
+
+    fn logic(
+        &mut self,
+        instruction: &'static str,
+        bits: impl FnOnce(U256, U256) -> U256,
+        bools: impl FnOnce(bool, bool) -> bool,
+    ) -> Result<(), Trap> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let value = match (a, b) {
+            (Value::U256(a), Value::U256(b)) => Value::U256(bits(a, b)),
+            (Value::Bool(a), Value::Bool(b)) => Value::Bool(bools(a, b)),
+            _ => return Err(Trap::TypeMismatch(instruction)),
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+}
+
+/// Inspection of the VM's state, used by the tests and by embedders
+impl Vm {
+    pub fn module(&self, address: &Address) -> Option<&Module> {
+        self.contracts.get(address).map(|contract| &*contract.module)
+    }
+
+    /// Addresses with a contract deployed, in order
+    pub fn contracts(&self) -> impl Iterator<Item = &Address> {
+        self.contracts.keys()
+    }
+
+    pub fn storage(&self) -> &dyn StateBackend {
+        &*self.storage
+    }
+
+    /// What `SLOAD` of `slot` reads in the contract at `address`: unwritten
+    /// slots of a declared type read as its zero
+    pub fn storage_at(&self, address: &Address, slot: U256) -> Value {
+        match self.storage.get(address, &slot) {
+            Some(value) => value,
+            None => self.contracts.get(address)
+                .and_then(|contract| contract.slot_types.get(&slot))
+                .map_or(Value::U256(U256::ZERO), Value::zero),
+        }
+    }
+
+    /// The authenticated tree over the storage of every contract
+    pub fn state_tree(&self) -> StateTree {
+        StateTree::from_storage(&*self.storage, self.contracts.keys())
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Everything contracts have logged, including from calls that trapped
+    pub fn logs(&self) -> &[Log] {
+        &self.logs
+    }
+
+    pub fn balances(&self) -> &BTreeMap<Address, U256> {
+        &self.balances
+    }
+
+    /// Every capability the contracts have issued or derived
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn set_balance(&mut self, address: Address, amount: U256) {
+        self.write_balance(address, amount);
+    }
+
+    /// Names of the functions of the contract at `address` running as
+    /// compiled code
+    pub fn compiled_functions(&self, address: &Address) -> Vec<&str> {
+        let Some(contract) = self.contracts.get(address) else {
+            return Vec::new();
+        };
+        contract.tiers.iter().zip(&contract.module.functions)
+            .filter(|(tier, _)| matches!(tier, Tier::Compiled(_)))
+            .map(|(_, function)| function.name.as_str())
+            .collect()
+    }
+}
+
+/// The last block of a capability that expires after block `n`; 0 never
+/// expires
+fn expiry(n: U256) -> Option<u64> {
+    (n != U256::ZERO).then(|| n.to_u64().unwrap_or(u64::MAX))
+}
+
+/// What a failed sub-call returns to its caller: `Err` with the revert
+/// message, or the trap's description, so that `?` passes it on
+fn failure(trap: Trap) -> Value {
+    let data = match trap {
+        Trap::Revert(message) => message,
+        other => other.to_string(),
+    };
+    Value::Variant { path: "Err".into(), payload: vec![Value::String(data)] }
+}
+
+/// Collects the resources in `value`, by id and kind, into `out`
+fn resources(value: &Value, out: &mut Vec<(u64, String)>) {
+    match value {
+        Value::Resource { kind, id, value } => {
+            out.push((*id, kind.clone()));
+            resources(value, out);
+        }
+        Value::Tuple(values)
+        | Value::Array(values)
+        | Value::Variant { payload: values, .. }
+        | Value::Closure { captured: values, .. } => values.iter().for_each(|value| resources(value, out)),
+        Value::Struct { fields, .. } => fields.iter().for_each(|(_, value)| resources(value, out)),
+        _ => {}
+    }
+}
+
+/// The bytes a `Bytes` parameter of a host function sees
+fn raw_bytes(value: &Value) -> Result<Vec<u8>, Trap> {
+    match value {
+        Value::Bytes(bytes) => Ok(bytes.clone()),
+        Value::String(text) => Ok(text.as_bytes().to_vec()),
+        Value::U256(n) => Ok(n.to_be_bytes().to_vec()),
+        Value::Address(address) => Ok(address.to_vec()),
+        _ => Err(Trap::TypeMismatch("CALLH")),
+    }
+}
+
+fn keccak256(data: &[u8]) -> U256 {
+    U256::from_be_bytes(Keccak256::digest(data).into())
+}
+
+/// Whether `signature` is `key`'s signature of `message`. Malformed keys
+/// and signatures, and keys of small order, do not verify.
+fn ed25519_verify(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let (Ok(key), Ok(signature)) = (<[u8; 32]>::try_from(key), <[u8; 64]>::try_from(signature)) else {
+        return false;
+    };
+    VerifyingKey::from_bytes(&key)
+        .is_ok_and(|key| key.verify_strict(message, &Signature::from_bytes(&signature)).is_ok())
+}
+
+/// Whether two variant paths name the same variant; `Some` matches
+/// `Option::Some`
+fn same_variant(a: &str, b: &str) -> bool {
+    a.rsplit("::").next() == b.rsplit("::").next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{assemble, decode, Function};
+
+    const COUNTER: &str = "\
+.contract Counter
+.const #0 u256 1
+.const #1 string \"too big\"
+.const #2 u256 3
+.storage 0 count: u256
+.storage 1 seen: map<address, bool>
+.event Bumped(by: u256)
+.export bump(by: u256) -> u256
+.export seen(who: address) -> bool
+
+.function bump arity=1 locals=1
+    LOAD 0
+    PUSH #2 ; u256 3
+    GT
+    NOT
+    JUMPI L6
+    REVERT #1 ; string \"too big\"
+L6:
+    SLOAD 0
+    LOAD 0
+    ADD
+    SSTORE 0
+    LOAD 0
+    EMIT 0 1
+    SLOAD 0
+    RET
+
+.function seen arity=1 locals=1 pure
+    PUSH #0 ; u256 1
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+";
+
+    /// Calls `bump` of the counter at `counter`, keeping its result or
+    /// reverting after it, and asks a contract who called it
+    const CALLER: &str = "\
+.contract Caller
+.const #0 string \"bump\"
+.const #1 string \"sender\"
+.const #2 string \"refused\"
+.storage 0 last: u256
+.storage 1 sender: address
+.export forward(counter: address, by: u256) -> u256
+.export refuse(counter: address, by: u256)
+.export whoami(counter: address) -> address
+
+.function forward arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    CALLM #0 1 ; string \"bump\"
+    DUP 0
+    SSTORE 0
+    RET
+
+.function refuse arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    CALLM #0 1 ; string \"bump\"
+    POP
+    REVERT #2 ; string \"refused\"
+
+.function whoami arity=1 locals=1
+    LOAD 0
+    CALLM #1 0 ; string \"sender\"
+    RET
+";
+
+    const COUNTER_ADDRESS: Address = [0xcc; 20];
+    const CALLER_ADDRESS: Address = [0xca; 20];
+
+    fn counter() -> Vm {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(None);
+        vm.deploy(COUNTER_ADDRESS, assemble(COUNTER).unwrap()).unwrap();
+        vm
+    }
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    fn boolean(b: bool) -> Value {
+        Value::Bool(b)
+    }
+
+    fn count(vm: &Vm) -> Option<Value> {
+        vm.storage().get(&COUNTER_ADDRESS, &U256::ZERO)
+    }
+
+    #[test]
+    fn test_calls_update_storage_and_emit() {
+        let mut vm = counter();
+        let outcome = vm.call(&COUNTER_ADDRESS, "bump", vec![word(2)], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(2))));
+        let outcome = vm.call(&COUNTER_ADDRESS, "bump", vec![word(3)], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(5))));
+        assert_eq!(count(&vm), Some(word(5)));
+        assert_eq!(vm.events().len(), 2);
+        let bumped = Event { address: COUNTER_ADDRESS, name: "Bumped".into(), fields: vec![word(3)] };
+        assert_eq!(vm.events()[1], bumped);
+    }
+
+    #[test]
+    fn test_gas_is_the_sum_of_instruction_costs() {
+        let mut vm = counter();
+        let outcome = vm.call(&COUNTER_ADDRESS, "bump", vec![word(1)], 100_000).unwrap();
+        let code = &vm.module(&COUNTER_ADDRESS).unwrap().functions[0].code;
+        let expected: u64 = code.iter().filter(|i| !matches!(i, Instruction::Revert(_))).map(Instruction::gas_cost).sum();
+        assert_eq!(outcome.gas_used, expected);
+    }
+
+    #[test]
+    fn test_traps_roll_back_and_consume_gas() {
+        let mut vm = counter();
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(1)], 100_000).unwrap();
+        let outcome = vm.call(&COUNTER_ADDRESS, "bump", vec![word(9)], 100_000).unwrap();
+        assert_eq!(outcome.result, Err(Trap::Revert("too big".into())));
+        assert!(outcome.gas_used > 0);
+
+        let outcome = vm.call(&COUNTER_ADDRESS, "bump", vec![word(1)], 5_020).unwrap();
+        assert_eq!(outcome.result, Err(Trap::OutOfGas));
+        assert_eq!(outcome.gas_used, 5_020);
+        assert_eq!(count(&vm), Some(word(1)));
+        assert_eq!(vm.events().len(), 1);
+    }
+
+    #[test]
+    fn test_an_outer_transaction_forks_the_state() {
+        let mut vm = counter();
+        vm.set_balance(COUNTER_ADDRESS, U256::from(5u64));
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(1)], 100_000).unwrap();
+
+        vm.begin();
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(2)], 100_000).unwrap();
+        vm.set_balance(COUNTER_ADDRESS, U256::ZERO);
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(3)], 100_000).unwrap();
+        assert_eq!(count(&vm), Some(word(6)));
+        vm.rollback();
+
+        assert_eq!(count(&vm), Some(word(1)));
+        assert_eq!(vm.events().len(), 1);
+        assert_eq!(vm.balances()[&COUNTER_ADDRESS], U256::from(5u64));
+        assert_eq!(vm.storage().depth(), 0);
+    }
+
+    #[test]
+    fn test_file_backed_storage_outlives_the_vm() {
+        let dir = std::env::temp_dir().join(format!("stremax-vm-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for expected in [2, 4] {
+            let mut vm = Vm::with_storage(Box::new(crate::FileBackend::open(&dir).unwrap())).unwrap();
+            vm.deploy(COUNTER_ADDRESS, assemble(COUNTER).unwrap()).unwrap();
+            vm.call(&COUNTER_ADDRESS, "bump", vec![word(2)], 100_000).unwrap();
+            vm.call(&COUNTER_ADDRESS, "bump", vec![word(9)], 100_000).unwrap();
+            assert_eq!(count(&vm), Some(word(expected)));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unwritten_map_entries_read_as_typed_zero() {
+        let mut vm = counter();
+        let outcome = vm.call(&COUNTER_ADDRESS, "seen", vec![Value::Address([7; 20])], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Bool(false))));
+    }
+
+    #[test]
+    fn test_bad_calls_are_errors() {
+        let mut vm = counter();
+        assert!(vm.call(&[1; 20], "bump", vec![], 100).unwrap_err().contains("no contract"));
+        assert!(vm.call(&COUNTER_ADDRESS, "missing", vec![], 100).unwrap_err().contains("no function"));
+        assert!(vm.call(&COUNTER_ADDRESS, "bump", vec![], 100).unwrap_err().contains("takes 1 arguments"));
+        let err = vm.deploy(COUNTER_ADDRESS, assemble(COUNTER).unwrap()).unwrap_err();
+        assert!(err.contains("already deployed"));
+    }
+
+    #[test]
+    fn test_deploy_checks_the_host_version() {
+        let mut vm = Vm::new();
+        let mut module = assemble(COUNTER).unwrap();
+        module.host_version = HOST_VERSION + 1;
+        let err = vm.deploy(COUNTER_ADDRESS, decode(&encode(&module)).unwrap()).unwrap_err();
+        assert!(err.contains(&format!("needs host version {}", HOST_VERSION + 1)), "{}", err);
+        module.host_version = 0;
+        assert!(vm.deploy(COUNTER_ADDRESS, module.clone()).is_err());
+        module.host_version = 1;
+        vm.deploy(COUNTER_ADDRESS, module).unwrap();
+    }
+
+    #[test]
+    fn test_guard_rejects_reentry() {
+        let source = "\
+.contract Loop
+.function again arity=0 locals=0
+    GUARD
+    CALL again 0
+    UNGUARD
+    RET
+";
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(None);
+        vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+        let outcome = vm.call(&COUNTER_ADDRESS, "again", vec![], 100_000).unwrap();
+        let chain = vec!["Loop.again".to_string(), "Loop.again".to_string()];
+        assert_eq!(outcome.result, Err(Trap::Reentrancy { function: "again".into(), chain }));
+    }
+
+    #[test]
+    fn test_functions_tier_up_after_the_threshold() {
+        let mut vm = counter();
+        vm.set_jit_threshold(Some(2));
+        for _ in 0..2 {
+            vm.call(&COUNTER_ADDRESS, "bump", vec![word(0)], 100_000).unwrap();
+            assert!(vm.compiled_functions(&COUNTER_ADDRESS).is_empty());
+        }
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(0)], 100_000).unwrap();
+        assert_eq!(vm.compiled_functions(&COUNTER_ADDRESS), vec!["bump"]);
+    }
+
+    /// An instruction a tracer saw: function, pc, depth and gas left
+    type Seen = (u32, usize, usize, u64);
+
+    #[derive(Default)]
+    struct Steps(Rc<std::cell::RefCell<Vec<Seen>>>);
+
+    impl Tracer for Steps {
+        fn step(&mut self, _: &Vm, step: &Step<'_>) {
+            self.0.borrow_mut().push((step.function, step.pc, step.depth, step.gas_left));
+        }
+    }
+
+    #[test]
+    fn test_tracer_sees_every_instruction_interpreted() {
+        let mut vm = counter();
+        vm.set_jit_threshold(Some(0));
+        vm.deploy(CALLER_ADDRESS, assemble(CALLER).unwrap()).unwrap();
+        let steps = Steps::default();
+        vm.set_tracer(Some(Box::new(Steps(Rc::clone(&steps.0)))));
+
+        let args = vec![Value::Address(COUNTER_ADDRESS), word(2)];
+        let outcome = vm.call(&CALLER_ADDRESS, "forward", args, 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(2))));
+        assert!(vm.compiled_functions(&COUNTER_ADDRESS).is_empty());
+
+        let steps = steps.0.borrow();
+        let depths: Vec<_> = steps.iter().map(|&(_, _, depth, _)| depth).collect();
+        // `forward` up to its CALLM, all of `bump`, then the rest of `forward`
+        assert_eq!(depths, [[1; 3].as_slice(), &[2; 13], &[1; 3]].concat());
+        assert_eq!(steps[3], (0, 0, 2, steps[3].3));
+        assert_eq!(steps.last().map(|&(_, pc, _, _)| pc), Some(5));
+        let gas_left = steps.last().unwrap().3;
+        assert_eq!(outcome.gas_used, 100_000 - gas_left + Instruction::Return.gas_cost());
+
+        vm.set_tracer(None);
+        vm.call(&COUNTER_ADDRESS, "bump", vec![word(0)], 100_000).unwrap();
+        assert_eq!(vm.compiled_functions(&COUNTER_ADDRESS), vec!["bump"]);
+        assert_eq!(vm.storage_at(&COUNTER_ADDRESS, U256::ZERO), word(2));
+        assert_eq!(vm.storage_at(&COUNTER_ADDRESS, U256::from(7u64)), word(0));
+    }
+
+    #[test]
+    fn test_contracts_call_each_other() {
+        for threshold in [None, Some(0)] {
+            let mut vm = counter();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(CALLER_ADDRESS, assemble(CALLER).unwrap()).unwrap();
+            let counter = Value::Address(COUNTER_ADDRESS);
+
+            let outcome = vm.call(&CALLER_ADDRESS, "forward", vec![counter.clone(), word(2)], 100_000).unwrap();
+            assert_eq!(outcome.result, Ok(Some(word(2))));
+            assert_eq!(count(&vm), Some(word(2)));
+            assert_eq!(vm.storage().get(&CALLER_ADDRESS, &U256::ZERO), Some(word(2)));
+            assert_eq!(vm.events()[0].address, COUNTER_ADDRESS);
+
+            // The callee's writes are undone with the caller's
+            let outcome = vm.call(&CALLER_ADDRESS, "refuse", vec![counter.clone(), word(1)], 100_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::Revert("refused".into())));
+            assert_eq!(count(&vm), Some(word(2)));
+            assert_eq!(vm.events().len(), 1);
+
+            let outcome = vm.call(&CALLER_ADDRESS, "whoami", vec![counter], 100_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::UnknownMethod("sender".into())));
+        }
+    }
+
+    #[test]
+    fn test_failed_sub_calls_roll_back_only_their_own_effects() {
+        for threshold in [None, Some(0)] {
+            let mut vm = counter();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(CALLER_ADDRESS, assemble(CALLER).unwrap()).unwrap();
+            let counter = Value::Address(COUNTER_ADDRESS);
+            vm.call(&CALLER_ADDRESS, "forward", vec![counter.clone(), word(2)], 100_000).unwrap();
+
+            let outcome = vm.call(&CALLER_ADDRESS, "forward", vec![counter, word(9)], 100_000).unwrap();
+            let failure = Value::Variant { path: "Err".into(), payload: vec![Value::String("too big".into())] };
+            assert_eq!(outcome.result, Ok(Some(failure.clone())));
+            assert_eq!(vm.storage().get(&CALLER_ADDRESS, &U256::ZERO), Some(failure));
+            assert_eq!(count(&vm), Some(word(2)));
+            assert_eq!(vm.events().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_failed_sub_calls_undo_their_transfers() {
+        let source = "\
+.contract Payer
+.const #0 string \"transfer\"
+.const #1 string \"pay_then_fail\"
+.const #2 string \"changed my mind\"
+.export pay_then_fail(to: address, amount: u256)
+.export relay(payer: address, to: address, amount: u256)
+
+.function pay_then_fail arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    CALLM #0 1 ; string \"transfer\"
+    POP
+    REVERT #2 ; string \"changed my mind\"
+
+.function relay arity=3 locals=3
+    LOAD 1
+    LOAD 2
+    CALLM #0 1 ; string \"transfer\"
+    POP
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    CALLM #1 2 ; string \"pay_then_fail\"
+    RET
+";
+        let mut vm = Vm::new();
+        for address in [COUNTER_ADDRESS, CALLER_ADDRESS] {
+            vm.deploy(address, assemble(source).unwrap()).unwrap();
+            vm.set_balance(address, U256::from(100u64));
+        }
+        let args = vec![Value::Address(COUNTER_ADDRESS), Value::Address([7; 20]), word(30)];
+        let outcome = vm.call(&CALLER_ADDRESS, "relay", args, 100_000).unwrap();
+        let failure = Value::Variant { path: "Err".into(), payload: vec![Value::String("changed my mind".into())] };
+        assert_eq!(outcome.result, Ok(Some(failure)));
+        assert_eq!(vm.balances()[&CALLER_ADDRESS], U256::from(70u64));
+        assert_eq!(vm.balances()[&COUNTER_ADDRESS], U256::from(100u64));
+        assert_eq!(vm.balances()[&[7; 20]], U256::from(30u64));
+
+        // At the top level the revert message is the call's error
+        let args = vec![Value::Address([7; 20]), word(30)];
+        let outcome = vm.call(&COUNTER_ADDRESS, "pay_then_fail", args, 100_000).unwrap();
+        assert_eq!(outcome.result, Err(Trap::Revert("changed my mind".into())));
+        assert_eq!(vm.balances()[&COUNTER_ADDRESS], U256::from(100u64));
+    }
+
+    #[test]
+    fn test_callee_sees_the_calling_contract_as_sender() {
+        let sender = "\
+.contract Sender
+.const #0 string \"msg.sender\"
+.const #1 string \"self\"
+.export sender() -> address
+.export me() -> address
+
+.function sender arity=0 locals=0
+    ENV #0 ; string \"msg.sender\"
+    RET
+
+.function me arity=0 locals=0
+    ENV #1 ; string \"self\"
+    RET
+";
+        let mut vm = Vm::new();
+        vm.deploy(COUNTER_ADDRESS, assemble(sender).unwrap()).unwrap();
+        vm.deploy(CALLER_ADDRESS, assemble(CALLER).unwrap()).unwrap();
+        let outcome = vm.call(&CALLER_ADDRESS, "whoami", vec![Value::Address(COUNTER_ADDRESS)], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Address(CALLER_ADDRESS))));
+        let outcome = vm.call(&COUNTER_ADDRESS, "sender", vec![], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Address(vm.env.sender))));
+        let outcome = vm.call(&COUNTER_ADDRESS, "me", vec![], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(Value::Address(COUNTER_ADDRESS))));
+    }
+
+    const HOST: &str = "\
+.contract Host
+.const #0 string \"chain.id\"
+.const #1 string \"self.balance\"
+.const #2 string \"keccak256\"
+.const #3 string \"ed25519_verify\"
+.const #4 string \"log\"
+.const #5 string \"code_hash\"
+.const #6 string \"logged\"
+.const #7 string \"block.difficulty\"
+
+.function context arity=0 locals=0
+    ENV #0 ; string \"chain.id\"
+    ENV #1 ; string \"self.balance\"
+    TUPLE 2
+    RET
+
+.function hash arity=1 locals=1
+    LOAD 0
+    CALLH #2 1 ; string \"keccak256\"
+    RET
+
+.function verify arity=3 locals=3
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    CALLH #3 3 ; string \"ed25519_verify\"
+    RET
+
+.function note arity=1 locals=1
+    LOAD 0
+    CALLH #4 1 ; string \"log\"
+    POP
+    REVERT #6 ; string \"logged\"
+
+.function code arity=1 locals=1
+    LOAD 0
+    CALLH #5 1 ; string \"code_hash\"
+    RET
+
+.function unknown arity=0 locals=0
+    ENV #7 ; string \"block.difficulty\"
+    RET
+";
+
+    fn hex_bytes(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_host_functions_read_the_chain_and_charge_their_gas() {
+        // RFC 8032, section 7.1, test 2
+        let key = hex_bytes("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let signature = hex_bytes(
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        );
+        for threshold in [None, Some(0)] {
+            let mut vm = Vm::new();
+            vm.set_jit_threshold(threshold);
+            vm.env.chain_id = 7;
+            vm.deploy(COUNTER_ADDRESS, assemble(HOST).unwrap()).unwrap();
+            vm.set_balance(COUNTER_ADDRESS, U256::from(40u64));
+
+            let outcome = vm.call(&COUNTER_ADDRESS, "context", vec![], 100_000).unwrap();
+            assert_eq!(outcome.result, Ok(Some(Value::Tuple(vec![word(7), word(40)]))));
+            let (_, chain_id) = host::lookup("chain.id").unwrap();
+            let (_, self_balance) = host::lookup("self.balance").unwrap();
+            let instructions: u64 = [Instruction::Env(0), Instruction::Env(1), Instruction::Tuple(2), Instruction::Return]
+                .iter().map(Instruction::gas_cost).sum();
+            assert_eq!(outcome.gas_used, instructions + chain_id.gas + self_balance.gas);
+
+            let hash = U256::from_be_bytes(hex_bytes("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45").try_into().unwrap());
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "hash", vec![Value::String("abc".into())]), Ok(Some(Value::U256(hash))));
+
+            let verify = |vm: &mut Vm, message: u8, signature: &[u8]| {
+                let args = vec![Value::Bytes(key.clone()), Value::Bytes(vec![message]), Value::Bytes(signature.to_vec())];
+                run(vm, COUNTER_ADDRESS, "verify", args)
+            };
+            assert_eq!(verify(&mut vm, 0x72, &signature), Ok(Some(Value::Bool(true))));
+            assert_eq!(verify(&mut vm, 0x73, &signature), Ok(Some(Value::Bool(false))));
+            assert_eq!(verify(&mut vm, 0x72, &signature[..63]), Ok(Some(Value::Bool(false))));
+
+            // Logs outlive the call that trapped after writing them
+            let outcome = run(&mut vm, COUNTER_ADDRESS, "note", vec![Value::String("checked".into())]);
+            assert_eq!(outcome, Err(Trap::Revert("logged".into())));
+            assert_eq!(vm.logs(), [Log { address: COUNTER_ADDRESS, message: "checked".into() }]);
+
+            let code_hash = keccak256(&encode(vm.module(&COUNTER_ADDRESS).unwrap()));
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "code", vec![Value::Address(COUNTER_ADDRESS)]), Ok(Some(Value::U256(code_hash))));
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "code", vec![Value::Address([7; 20])]), Ok(Some(word(0))));
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "code", vec![word(7)]), Err(Trap::TypeMismatch("CALLH")));
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "unknown", vec![]), Err(Trap::UnknownEnvironment("block.difficulty".into())));
+        }
+    }
+
+    #[test]
+    fn test_transfers_move_the_running_contracts_balance() {
+        let source = "\
+.contract Payer
+.const #0 string \"transfer\"
+.export pay(to: address, amount: u256)
+
+.function pay arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    CALLM #0 1 ; string \"transfer\"
+    POP
+    RET
+";
+        let mut vm = Vm::new();
+        vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+        vm.set_balance(COUNTER_ADDRESS, U256::from(100u64));
+        let outcome = vm.call(&COUNTER_ADDRESS, "pay", vec![Value::Address([7; 20]), word(30)], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(None));
+        assert_eq!(vm.balances()[&COUNTER_ADDRESS], U256::from(70u64));
+        assert_eq!(vm.balances()[&[7; 20]], U256::from(30u64));
+
+        let outcome = vm.call(&COUNTER_ADDRESS, "pay", vec![Value::Address([7; 20]), word(71)], 100_000).unwrap();
+        assert_eq!(outcome.result, Err(Trap::InsufficientBalance));
+        assert_eq!(vm.balances()[&COUNTER_ADDRESS], U256::from(70u64));
+    }
+
+    #[test]
+    fn test_value_and_contract_call_instructions() {
+        let source = "\
+.contract Direct
+.const #0 string \"bump\"
+.export pay(to: address, amount: u256) -> u256
+.export forward(counter: address, by: u256) -> u256
+
+.function pay arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    TRANSFER
+    LOAD 0
+    BALANCE
+    RET
+
+.function forward arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    CALLC #0 1 ; string \"bump\"
+    RET
+";
+        for threshold in [None, Some(0)] {
+            let mut vm = counter();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(CALLER_ADDRESS, assemble(source).unwrap()).unwrap();
+            vm.set_balance(CALLER_ADDRESS, U256::from(100u64));
+            let outcome = vm.call(&CALLER_ADDRESS, "pay", vec![Value::Address([7; 20]), word(30)], 100_000).unwrap();
+            assert_eq!(outcome.result, Ok(Some(word(30))));
+            assert_eq!(vm.balances()[&CALLER_ADDRESS], U256::from(70u64));
+            let outcome = vm.call(&CALLER_ADDRESS, "pay", vec![Value::Address([7; 20]), word(71)], 100_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::InsufficientBalance));
+
+            let counter = Value::Address(COUNTER_ADDRESS);
+            let outcome = vm.call(&CALLER_ADDRESS, "forward", vec![counter.clone(), word(2)], 100_000).unwrap();
+            assert_eq!(outcome.result, Ok(Some(word(2))));
+            assert_eq!(count(&vm), Some(word(2)));
+            let outcome = vm.call(&CALLER_ADDRESS, "forward", vec![counter, word(9)], 100_000).unwrap();
+            let failure = Value::Variant { path: "Err".into(), payload: vec![Value::String("too big".into())] };
+            assert_eq!(outcome.result, Ok(Some(failure)));
+            let outcome = vm.call(&CALLER_ADDRESS, "forward", vec![Value::Address([7; 20]), word(1)], 100_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::NoContract { address: [7; 20], method: "bump".into() }));
+        }
+    }
+
+    /// Runs `instruction` on `before`, passed in as arguments, and returns
+    /// the top `results` values it leaves on the stack
+    fn effect(instruction: Instruction, before: &[Value], results: usize) -> Result<Vec<Value>, Trap> {
+        let mut code: Vec<Instruction> = (0..before.len() as u32).map(Instruction::Load).collect();
+        code.extend([instruction, Instruction::Tuple(results as u8), Instruction::Return]);
+        let mut module = Module::new("Effect");
+        module.functions.push(Function {
+            name: "effect".into(),
+            arity: before.len() as u8,
+            locals: before.len() as u32,
+            is_pure: false,
+            code,
+        });
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(None);
+        vm.deploy(COUNTER_ADDRESS, module).unwrap();
+        match vm.call(&COUNTER_ADDRESS, "effect", before.to_vec(), 100_000).unwrap().result? {
+            Some(Value::Tuple(values)) => Ok(values),
+            other => panic!("expected a tuple, got {:?}", other),
+        }
+    }
+
+    fn assert_effect(instruction: Instruction, before: &[Value], after: &[Value]) {
+        assert_eq!(effect(instruction, before, after.len()), Ok(after.to_vec()), "{:?} on {:?}", instruction, before);
+    }
+
+    fn assert_trap(instruction: Instruction, before: &[Value], trap: Trap) {
+        assert_eq!(effect(instruction, before, 0), Err(trap), "{:?} on {:?}", instruction, before);
+    }
+
+    #[test]
+    fn test_stack_instructions() {
+        assert_effect(Instruction::Pop, &[word(1), word(2)], &[word(1)]);
+        assert_effect(Instruction::Dup(0), &[word(1), word(2)], &[word(1), word(2), word(2)]);
+        assert_effect(Instruction::Dup(1), &[word(1), word(2)], &[word(1), word(2), word(1)]);
+        assert_effect(Instruction::Swap(0), &[word(1), word(2)], &[word(2), word(1)]);
+        assert_effect(Instruction::Swap(1), &[word(1), word(2), word(3)], &[word(3), word(2), word(1)]);
+        assert_trap(Instruction::Pop, &[], Trap::StackUnderflow);
+        assert_trap(Instruction::Dup(2), &[word(1), word(2)], Trap::StackUnderflow);
+        assert_trap(Instruction::Swap(0), &[word(1)], Trap::StackUnderflow);
+    }
+
+    #[test]
+    fn test_swap_stays_within_the_callees_operands() {
+        let source = "\
+.contract Reach
+.const #0 u256 1
+.const #1 u256 2
+.const #2 u256 100
+.export f() -> u256
+
+.function f arity=0 locals=0
+    PUSH #0 ; u256 1
+    PUSH #1 ; u256 2
+    CALL g 0
+    ADD
+    RET
+
+.function g arity=0 locals=0
+    PUSH #2 ; u256 100
+    SWAP 1
+    POP
+    RET
+";
+        for threshold in [None, Some(0)] {
+            let mut vm = Vm::new();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+            let outcome = vm.call(&COUNTER_ADDRESS, "f", vec![], 100_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::StackUnderflow));
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_effect(Instruction::Add, &[word(2), word(3)], &[word(5)]);
+        assert_effect(Instruction::Add, &[word(u64::MAX), word(1)], &[Value::U256(U256::ONE << 64)]);
+        assert_effect(Instruction::Sub, &[word(3), word(2)], &[word(1)]);
+        assert_effect(Instruction::Mul, &[word(6), word(7)], &[word(42)]);
+        let supply = Value::U256("1000000000000000000000000000".parse().unwrap());
+        assert_effect(Instruction::Mul, &[word(1_000_000_000_000_000_000), word(1_000_000_000)], &[supply]);
+        assert_effect(Instruction::Div, &[word(7), word(2)], &[word(3)]);
+        assert_effect(Instruction::Mod, &[word(7), word(2)], &[word(1)]);
+
+        assert_trap(Instruction::Add, &[Value::U256(U256::MAX), word(1)], Trap::ArithmeticOverflow);
+        assert_trap(Instruction::Sub, &[word(2), word(3)], Trap::ArithmeticOverflow);
+        let big = Value::U256(U256::ONE << 128);
+        assert_trap(Instruction::Mul, &[big.clone(), big], Trap::ArithmeticOverflow);
+        assert_trap(Instruction::Div, &[word(7), word(0)], Trap::DivisionByZero);
+        assert_trap(Instruction::Mod, &[word(7), word(0)], Trap::DivisionByZero);
+        assert_trap(Instruction::Add, &[word(1), Value::Bool(true)], Trap::TypeMismatch("ADD"));
+        assert_trap(Instruction::Add, &[word(1)], Trap::StackUnderflow);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_effect(Instruction::Eq, &[word(1), word(1)], &[boolean(true)]);
+        assert_effect(Instruction::Eq, &[word(1), boolean(true)], &[boolean(false)]);
+        assert_effect(Instruction::Lt, &[word(1), Value::U256(U256::ONE << 200)], &[boolean(true)]);
+        assert_effect(Instruction::Gt, &[word(1), word(2)], &[boolean(false)]);
+        assert_effect(Instruction::LtEq, &[word(2), word(2)], &[boolean(true)]);
+        assert_effect(Instruction::GtEq, &[word(1), word(2)], &[boolean(false)]);
+        assert_trap(Instruction::Lt, &[word(1), boolean(true)], Trap::TypeMismatch("LT"));
+    }
+
+    #[test]
+    fn test_logic() {
+        assert_effect(Instruction::And, &[boolean(true), boolean(false)], &[boolean(false)]);
+        assert_effect(Instruction::And, &[word(0b110), word(0b011)], &[word(0b010)]);
+        assert_effect(Instruction::Or, &[boolean(true), boolean(false)], &[boolean(true)]);
+        assert_effect(Instruction::Xor, &[boolean(true), boolean(true)], &[boolean(false)]);
+        assert_effect(Instruction::Not, &[boolean(false)], &[boolean(true)]);
+        assert_effect(Instruction::Not, &[word(0)], &[Value::U256(U256::MAX)]);
+        assert_trap(Instruction::Not, &[Value::String("1".into())], Trap::TypeMismatch("NOT"));
+        assert_trap(Instruction::And, &[boolean(true), word(1)], Trap::TypeMismatch("AND"));
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let pair = || Value::Tuple(vec![word(1), word(2)]);
+        assert_effect(Instruction::Tuple(2), &[word(1), word(2)], &[pair()]);
+        assert_effect(Instruction::Extract(1), &[pair()], &[word(2)]);
+        assert_trap(Instruction::Extract(2), &[pair()], Trap::IndexOutOfBounds);
+        let array = Value::Array(vec![word(4), word(5)]);
+        assert_effect(Instruction::Index, &[array.clone(), word(1)], &[word(5)]);
+        assert_effect(Instruction::SetIndex, &[array.clone(), word(0), word(9)], &[Value::Array(vec![word(9), word(5)])]);
+        assert_trap(Instruction::Index, &[array, word(2)], Trap::IndexOutOfBounds);
+        assert_effect(Instruction::Index, &[Value::Bytes(vec![7, 8]), word(1)], &[word(8)]);
+    }
+
+    #[test]
+    fn test_jumps_and_loops() {
+        // factorial(n) with the loop in a called function
+        let source = "\
+.contract Factorial
+.const #0 u256 1
+.export factorial(n: u256) -> u256
+
+.function factorial arity=1 locals=1
+    LOAD 0
+    CALL loop 1
+    RET
+
+.function loop arity=1 locals=2
+    PUSH #0 ; u256 1
+    STORE 1
+L2:
+    LOAD 0
+    PUSH #0 ; u256 1
+    GT
+    NOT
+    JUMPI L15
+    LOAD 1
+    LOAD 0
+    MUL
+    STORE 1
+    LOAD 0
+    PUSH #0 ; u256 1
+    SUB
+    STORE 0
+    JUMP L2
+L15:
+    LOAD 1
+    RET
+";
+        let mut vm = Vm::new();
+        vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+        let outcome = vm.call(&COUNTER_ADDRESS, "factorial", vec![word(5)], 100_000).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(120))));
+        assert_trap(Instruction::JumpIf(0), &[word(1)], Trap::TypeMismatch("JUMPI"));
+    }
+
+    /// Mints only for holders of its `mint` capability
+    const MINTER: &str = "\
+.contract Minter
+.const #0 string \"msg.sender\"
+.const #1 string \"mint\"
+.const #2 string \"not allowed\"
+.const #3 u256 1
+.const #4 string \"has_permission\"
+.storage 0 supply: u256
+.export grant(to: address, expires: u256) -> Capability
+.export grant_and_fail(to: address)
+.export revoke(holder: address)
+.export mint()
+.export allowed(account: address) -> bool
+
+.function grant arity=2 locals=2
+    LOAD 0
+    LOAD 1
+    GRANTPERM #1 ; string \"mint\"
+    RET
+
+.function grant_and_fail arity=1 locals=1
+    LOAD 0
+    PUSH #3 ; u256 1
+    GRANTPERM #1 ; string \"mint\"
+    REVERT #2 ; string \"not allowed\"
+
+.function revoke arity=1 locals=1
+    LOAD 0
+    REVOKEPERM #1 ; string \"mint\"
+    RET
+
+.function mint arity=0 locals=0
+    ENV #0 ; string \"msg.sender\"
+    CHECKPERM #1 ; string \"mint\"
+    JUMPI allowed
+    REVERT #2 ; string \"not allowed\"
+allowed:
+    SLOAD 0
+    PUSH #3 ; u256 1
+    ADD
+    SSTORE 0
+    RET
+
+.function allowed arity=1 locals=1
+    LOAD 0
+    PUSH #1 ; string \"mint\"
+    CALLH #4 2 ; string \"has_permission\"
+    RET
+";
+
+    /// Keeps a capability, uses it, and derives others from it
+    const HOLDER: &str = "\
+.contract Holder
+.const #0 string \"mint\"
+.const #1 string \"Capability::delegate\"
+.const #2 string \"Capability::attenuate\"
+.const #3 string \"Capability::revoke\"
+.storage 0 capability: Capability
+.export keep(capability: Capability)
+.export mint(minter: address)
+.export delegate(to: address) -> Capability
+.export attenuate(expires: u256, delegable: bool) -> Capability
+.export revoke(capability: Capability)
+
+.function keep arity=1 locals=1
+    LOAD 0
+    SSTORE 0
+    RET
+
+.function mint arity=1 locals=1
+    LOAD 0
+    CALLM #0 0 ; string \"mint\"
+    RET
+
+.function delegate arity=1 locals=1
+    SLOAD 0
+    LOAD 0
+    CALLH #1 2 ; string \"Capability::delegate\"
+    RET
+
+.function attenuate arity=2 locals=2
+    SLOAD 0
+    LOAD 0
+    LOAD 1
+    CALLH #2 3 ; string \"Capability::attenuate\"
+    RET
+
+.function revoke arity=1 locals=1
+    LOAD 0
+    CALLH #3 1 ; string \"Capability::revoke\"
+    RET
+";
+
+    const MINTER_ADDRESS: Address = [0x3a; 20];
+    const HOLDERS: [Address; 2] = [[0x3b; 20], [0x3c; 20]];
+
+    fn minter(threshold: Option<u32>) -> Vm {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(threshold);
+        vm.deploy(MINTER_ADDRESS, assemble(MINTER).unwrap()).unwrap();
+        for holder in HOLDERS {
+            vm.deploy(holder, assemble(HOLDER).unwrap()).unwrap();
+        }
+        vm
+    }
+
+    fn run(vm: &mut Vm, address: Address, function: &str, args: Vec<Value>) -> Result<Option<Value>, Trap> {
+        vm.call(&address, function, args, 1_000_000).unwrap().result
+    }
+
+    /// Whether `holder` could mint through its own `mint`, which reports
+    /// the minter's refusal as a failure value
+    fn mints(vm: &mut Vm, holder: Address) -> bool {
+        match run(vm, holder, "mint", vec![Value::Address(MINTER_ADDRESS)]).unwrap() {
+            Some(value) if value == Value::unit() => true,
+            Some(value) if value == failure(Trap::Revert("not allowed".into())) => false,
+            other => panic!("mint returned {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_recursion_stops_at_the_call_depth_limit() {
+        // depth(n) recurses n times below the call that started it
+        let source = "\
+.contract Deep
+.const #0 u256 0
+.const #1 u256 1
+.export depth(n: u256) -> u256
+
+.function depth arity=1 locals=1
+    LOAD 0
+    PUSH #0 ; u256 0
+    EQ
+    JUMPI L12
+    LOAD 0
+    PUSH #1 ; u256 1
+    SUB
+    CALL depth 1
+    PUSH #1 ; u256 1
+    ADD
+    RET
+L12:
+    PUSH #0 ; u256 0
+    RET
+";
+        for threshold in [None, Some(0)] {
+            let mut vm = Vm::new();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+            let deepest = word(MAX_CALL_DEPTH as u64 - 1);
+            let outcome = vm.call(&COUNTER_ADDRESS, "depth", vec![deepest.clone()], 1_000_000).unwrap();
+            assert_eq!(outcome.result, Ok(Some(deepest)));
+            let outcome = vm.call(&COUNTER_ADDRESS, "depth", vec![word(MAX_CALL_DEPTH as u64)], 1_000_000).unwrap();
+            assert_eq!(outcome.result, Err(Trap::CallDepthExceeded));
+        }
+    }
+
+    #[test]
+    fn test_capabilities_gate_delegate_and_revoke() {
+        for threshold in [None, Some(0)] {
+            let mut vm = minter(threshold);
+            let [first, second] = HOLDERS;
+            assert!(!mints(&mut vm, first));
+
+            let granted = run(&mut vm, MINTER_ADDRESS, "grant", vec![Value::Address(first), word(0)]).unwrap();
+            let Some(Value::Capability(id)) = granted else { panic!("granted {:?}", granted) };
+            run(&mut vm, first, "keep", vec![Value::Capability(id)]).unwrap();
+            assert!(mints(&mut vm, first));
+            assert!(!mints(&mut vm, second));
+
+            let delegated = run(&mut vm, first, "delegate", vec![Value::Address(second)]).unwrap().unwrap();
+            run(&mut vm, second, "keep", vec![delegated.clone()]).unwrap();
+            assert!(mints(&mut vm, second));
+            assert_eq!(vm.storage().get(&MINTER_ADDRESS, &U256::ZERO), Some(word(2)));
+
+            // A token only works for its holder
+            run(&mut vm, second, "keep", vec![Value::Capability(id)]).unwrap();
+            let stolen = run(&mut vm, second, "delegate", vec![Value::Address(second)]);
+            assert_eq!(stolen, Err(Trap::PermissionDenied("capability held by another account")));
+            run(&mut vm, second, "keep", vec![delegated]).unwrap();
+
+            // Revoking the first holder's capability ends the one derived
+            // from it
+            run(&mut vm, MINTER_ADDRESS, "revoke", vec![Value::Address(first)]).unwrap();
+            assert!(!mints(&mut vm, first));
+            assert!(!mints(&mut vm, second));
+        }
+    }
+
+    #[test]
+    fn test_capabilities_expire_attenuate_and_roll_back() {
+        let mut vm = minter(None);
+        let [first, second] = HOLDERS;
+        let granted = run(&mut vm, MINTER_ADDRESS, "grant", vec![Value::Address(first), word(10)]).unwrap().unwrap();
+        run(&mut vm, first, "keep", vec![granted.clone()]).unwrap();
+
+        // A weaker capability cannot be delegated, nor outlive its parent
+        let weak = run(&mut vm, first, "attenuate", vec![word(5), boolean(false)]).unwrap().unwrap();
+        let Value::Capability(weak_id) = weak else { panic!("attenuated to {:?}", weak) };
+        let attenuated = vm.capabilities().get(weak_id).unwrap();
+        assert_eq!((attenuated.expires, attenuated.delegable), (Some(5), false));
+        run(&mut vm, first, "keep", vec![weak.clone()]).unwrap();
+        let delegated = run(&mut vm, first, "delegate", vec![Value::Address(second)]);
+        assert_eq!(delegated, Err(Trap::PermissionDenied("capability cannot be delegated")));
+        run(&mut vm, first, "keep", vec![granted.clone()]).unwrap();
+
+        // The holder of the parent may revoke the weaker one; nobody else
+        assert_eq!(run(&mut vm, second, "revoke", vec![weak.clone()]).unwrap_err(), Trap::PermissionDenied(
+            "only the issuer or a holder can revoke a capability",
+        ));
+        run(&mut vm, first, "revoke", vec![weak]).unwrap();
+        assert!(vm.capabilities().get(weak_id).unwrap().revoked);
+
+        vm.env.block_number = 10;
+        assert!(mints(&mut vm, first));
+        vm.env.block_number = 11;
+        assert!(!mints(&mut vm, first));
+        assert_eq!(run(&mut vm, MINTER_ADDRESS, "allowed", vec![Value::Address(first)]), Ok(Some(boolean(false))));
+        vm.env.block_number = 1;
+        assert_eq!(run(&mut vm, MINTER_ADDRESS, "allowed", vec![Value::Address(first)]), Ok(Some(boolean(true))));
+
+        // A trap takes back what the transaction granted
+        let issued = vm.capabilities().len();
+        let outcome = run(&mut vm, MINTER_ADDRESS, "grant_and_fail", vec![Value::Address(second)]);
+        assert_eq!(outcome, Err(Trap::Revert("not allowed".into())));
+        assert_eq!(vm.capabilities().len(), issued);
+        let host_call = Instruction::CallHost(0, 0).gas_cost();
+        assert_eq!(CAPABILITY_WRITE_GAS + host_call, Instruction::GrantPermission(0).gas_cost());
+    }
+
+    /// Mints `Coin` resources and consumes them correctly and otherwise
+    const VAULT: &str = "\
+.contract Vault
+.const #0 string \"Coin\"
+.const #1 string \"Coin {amount}\"
+.const #2 string \"amount\"
+.storage 0 reserve: Coin
+.export mint(amount: u256) -> Coin
+.export burn(coin: Coin) -> u256
+.export deposit(coin: Coin)
+.export round_trip(amount: u256) -> u256
+.export save(amount: u256)
+.export leak(amount: u256)
+.export burn_twice(amount: u256)
+.export burn_reserve()
+
+.function mint arity=1 locals=1
+    LOAD 0
+    STRUCT #1 1 ; string \"Coin {amount}\"
+    ACQUIRE #0 ; string \"Coin\"
+    RET
+
+.function burn arity=1 locals=1
+    LOAD 0
+    GETFIELD #2 ; string \"amount\"
+    LOAD 0
+    RELEASE #0 ; string \"Coin\"
+    RET
+
+.function deposit arity=1 locals=1
+    LOAD 0
+    SSTORE 0
+    RET
+
+.function round_trip arity=1 locals=1
+    LOAD 0
+    CALL mint 1
+    CALL burn 1
+    RET
+
+.function save arity=1 locals=1
+    LOAD 0
+    CALL mint 1
+    CALL deposit 1
+    RET
+
+.function leak arity=1 locals=1
+    LOAD 0
+    CALL mint 1
+    POP
+    RET
+
+.function burn_twice arity=1 locals=2
+    LOAD 0
+    CALL mint 1
+    STORE 1
+    LOAD 1
+    RELEASE #0 ; string \"Coin\"
+    LOAD 1
+    RELEASE #0 ; string \"Coin\"
+    RET
+
+.function burn_reserve arity=0 locals=0
+    SLOAD 0
+    RELEASE #0 ; string \"Coin\"
+    RET
+";
+
+    #[test]
+    fn test_resources_move_once_and_trap_when_leaked() {
+        for threshold in [None, Some(0)] {
+            let mut vm = Vm::new();
+            vm.set_jit_threshold(threshold);
+            vm.deploy(COUNTER_ADDRESS, assemble(VAULT).unwrap()).unwrap();
+            assert_eq!(run(&mut vm, COUNTER_ADDRESS, "round_trip", vec![word(7)]), Ok(Some(word(7))));
+            let leak = run(&mut vm, COUNTER_ADDRESS, "leak", vec![word(1)]);
+            assert_eq!(leak, Err(Trap::ResourceLeak { kind: "Coin".into(), function: "leak".into() }));
+            let twice = run(&mut vm, COUNTER_ADDRESS, "burn_twice", vec![word(1)]);
+            assert_eq!(twice, Err(Trap::ResourceNotHeld("Coin".into())));
+
+            // Resources cannot leave the VM or be forged from outside it
+            let escaped = run(&mut vm, COUNTER_ADDRESS, "mint", vec![word(5)]);
+            assert_eq!(escaped, Err(Trap::ResourceLeak { kind: "Coin".into(), function: "mint".into() }));
+            let coin = Value::Resource {
+                kind: "Coin".into(),
+                id: 0,
+                value: Box::new(Value::Struct { name: "Coin".into(), fields: vec![("amount".into(), word(5))] }),
+            };
+            assert_eq!(Value::from_bytes(&mut coin.to_bytes().as_slice()), Ok(coin.clone()));
+            let forged = run(&mut vm, COUNTER_ADDRESS, "burn", vec![coin]);
+            assert_eq!(forged, Err(Trap::ResourceNotHeld("Coin".into())));
+
+            // Stored resources belong to storage, and what is read back is
+            // a copy the reader cannot consume
+            run(&mut vm, COUNTER_ADDRESS, "save", vec![word(3)]).unwrap();
+            let Some(Value::Resource { value, .. }) = vm.storage().get(&COUNTER_ADDRESS, &U256::ZERO) else {
+                panic!("nothing saved");
+            };
+            assert_eq!(value.to_string(), "Coin { amount: 3 }");
+            let stolen = run(&mut vm, COUNTER_ADDRESS, "burn_reserve", vec![]);
+            assert_eq!(stolen, Err(Trap::ResourceNotHeld("Coin".into())));
+        }
+    }
+
+    #[test]
+    fn test_handles_in_durable_storage_survive_reopening_the_state() {
+        let dir = std::env::temp_dir().join(format!("stremax-vm-handles-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let [first, second] = HOLDERS;
+        for session in 0..2u64 {
+            let mut vm = Vm::with_storage(Box::new(crate::FileBackend::open(&dir).unwrap())).unwrap();
+            vm.deploy(MINTER_ADDRESS, assemble(MINTER).unwrap()).unwrap();
+            vm.deploy(first, assemble(HOLDER).unwrap()).unwrap();
+            vm.deploy(COUNTER_ADDRESS, assemble(VAULT).unwrap()).unwrap();
+
+            // The token kept last session still names the first holder's
+            // capability, and new ones get fresh ids
+            assert_eq!(mints(&mut vm, first), session == 1);
+            let granted = run(&mut vm, MINTER_ADDRESS, "grant", vec![Value::Address(first), word(0)]).unwrap();
+            assert_eq!(granted, Some(Value::Capability(session * 2)));
+            run(&mut vm, first, "keep", vec![granted.unwrap()]).unwrap();
+            run(&mut vm, MINTER_ADDRESS, "grant_and_fail", vec![Value::Address(second)]).unwrap_err();
+            let other = run(&mut vm, MINTER_ADDRESS, "grant", vec![Value::Address(second), word(0)]).unwrap();
+            assert_eq!(other, Some(Value::Capability(session * 2 + 1)));
+            assert_eq!(vm.capabilities().get(session * 2 + 1).unwrap().holder, second);
+
+            run(&mut vm, COUNTER_ADDRESS, "save", vec![word(3)]).unwrap();
+            let Some(Value::Resource { id, .. }) = vm.storage().get(&COUNTER_ADDRESS, &U256::ZERO) else {
+                panic!("nothing saved");
+            };
+            assert_eq!(id, session);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let reserved = Vm::new().deploy(RUNTIME_ADDRESS, assemble(MINTER).unwrap());
+        assert!(reserved.unwrap_err().contains("reserved"));
+    }
+
+    #[test]
+    fn test_out_of_gas_stops_execution() {
+        let source = "\
+.contract Spin
+.function spin arity=0 locals=0
+L0:
+    JUMP L0
+";
+        let mut vm = Vm::new();
+        vm.deploy(COUNTER_ADDRESS, assemble(source).unwrap()).unwrap();
+        let outcome = vm.call(&COUNTER_ADDRESS, "spin", vec![], 50).unwrap();
+        assert_eq!(outcome, Outcome { result: Err(Trap::OutOfGas), gas_used: 50 });
+    }
+}
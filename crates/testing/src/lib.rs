@@ -0,0 +1,22 @@
+//! Test environments for Stremax: a simulated chain that mines blocks
+//! over the runtime's [`Vm`](stremax_runtime::Vm), contract deployment and
+//! calls through their ABI, and helpers that run single instructions and
+//! check what they do to the stack.
+//!
+//! The modules live in `src/testing`, next to the language they test.
+
+#[path = "../../../src/core/error.rs"]
+#[allow(dead_code, clippy::crate_in_macro_def, clippy::enum_variant_names)] // shared with the tools
+mod error;
+#[path = "../../../src/testing/mod.rs"]
+pub mod testing;
+
+/// The tools' error type, as the test environments refer to it
+pub mod core {
+    pub use super::error::{Error, Result};
+}
+
+pub use testing::{
+    BlockchainTestConfig, BlockchainTestEnvironment, ContractTestConfig, ContractTestEnvironment, TestConfig,
+    TestEnvironment, VMTestConfig, VMTestEnvironment,
+};
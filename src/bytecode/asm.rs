@@ -0,0 +1,833 @@
+//! Text form of a module, as printed by `strxc --emit asm` and `strxvm disasm`.
+//!
+//! ```text
+//! .contract Counter
+//! .host 1
+//! .const #0 u256 1
+//! .storage 0 count: u256
+//! .event Incremented(value: u256)
+//! .export increment(by: u256) -> u256
+//!
+//! .function increment arity=1 locals=1
+//!     .var 0 0..5 by: u256
+//!     .loc 4:9
+//!     SLOAD 0
+//!     JUMPI L3
+//!     EMIT Incremented 1
+//! L3:
+//!     RET
+//! ```
+//!
+//! `.host` is only needed for a module built against an older host table
+//! than this one. `;` starts a comment. Jump targets may be labels or instruction indices,
+//! `CALL`, `CLOSURE` and `EMIT` take a name or an index, and `PUSH` and the
+//! instructions naming a string constant take `#<constant>`.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use super::host::HOST_VERSION;
+use super::opcode::{Instruction, Opcode, Operands};
+use super::{
+    AbiEvent, AbiFunction, Constant, DebugInfo, DebugLocation, DebugVariable, Function, Module,
+    Param, StorageEntry, Type,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    /// 1-based source line, or 0 for errors found after parsing
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Renders a module as assembly that [`assemble`] reads back unchanged
+pub fn disassemble(module: &Module) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, ".contract {}", module.name);
+    if module.host_version != HOST_VERSION {
+        let _ = writeln!(out, ".host {}", module.host_version);
+    }
+    if let Some(debug) = &module.debug {
+        let _ = writeln!(out, ".source {}", quote(&debug.source));
+    }
+
+    if !module.constants.is_empty() {
+        out.push('\n');
+        for (index, constant) in module.constants.iter().enumerate() {
+            let _ = writeln!(out, ".const #{} {}", index, format_constant(constant));
+        }
+    }
+    if !module.storage.is_empty() {
+        out.push('\n');
+        for entry in &module.storage {
+            let _ = writeln!(out, ".storage {} {}: {}", entry.slot, entry.name, format_type(&entry.ty));
+        }
+    }
+    if !module.abi.events.is_empty() {
+        out.push('\n');
+        for event in &module.abi.events {
+            let _ = writeln!(out, ".event {}({})", event.name, format_params(&event.params));
+        }
+    }
+    if !module.abi.functions.is_empty() {
+        out.push('\n');
+        for entry in &module.abi.functions {
+            let name = module.functions.get(entry.function as usize)
+                .map_or_else(|| entry.function.to_string(), |f| f.name.clone());
+            let _ = write!(out, ".export {}({})", name, format_params(&entry.params));
+            if let Some(ty) = &entry.returns {
+                let _ = write!(out, " -> {}", format_type(ty));
+            }
+            out.push('\n');
+        }
+    }
+
+    for (index, function) in module.functions.iter().enumerate() {
+        let _ = write!(out, "\n.function {} arity={} locals={}", function.name, function.arity, function.locals);
+        if function.is_pure {
+            out.push_str(" pure");
+        }
+        out.push('\n');
+        let variables = module.debug.iter()
+            .flat_map(|debug| &debug.variables)
+            .filter(|v| v.function == index as u32);
+        for v in variables {
+            let _ = writeln!(out, "    .var {} {}..{} {}: {}", v.slot, v.start, v.end, v.name, format_type(&v.ty));
+        }
+
+        let mut targets: Vec<u32> = function.code.iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Jump(target) | Instruction::JumpIf(target) => Some(*target),
+                _ => None,
+            })
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+
+        for (pc, instruction) in function.code.iter().enumerate() {
+            if targets.binary_search(&(pc as u32)).is_ok() {
+                let _ = writeln!(out, "L{}:", pc);
+            }
+            let location = module.debug.as_ref().and_then(|debug| {
+                debug.locations.iter()
+                    .find(|l| l.function == index as u32 && l.pc == pc as u32)
+            });
+            if let Some(location) = location {
+                let _ = writeln!(out, "    .loc {}:{}", location.line, location.column);
+            }
+            let _ = writeln!(out, "    {}", format_instruction(module, instruction));
+        }
+    }
+
+    out
+}
+
+/// One instruction as [`disassemble`] prints it, naming what its operands
+/// index in `module`
+pub fn format_instruction(module: &Module, instruction: &Instruction) -> String {
+    let opcode = instruction.opcode();
+    match *instruction {
+        Instruction::Push(index) => match module.constants.get(index as usize) {
+            Some(constant) => format!("{} #{} ; {}", opcode, index, format_constant(constant)),
+            None => format!("{} #{}", opcode, index),
+        },
+        Instruction::Jump(target) | Instruction::JumpIf(target) => format!("{} L{}", opcode, target),
+        Instruction::Call(index, argc) | Instruction::Closure(index, argc) => match module.functions.get(index as usize) {
+            Some(function) => format!("{} {} {}", opcode, function.name, argc),
+            None => format!("{} {} {}", opcode, index, argc),
+        },
+        Instruction::Emit(index, argc) => match module.abi.events.get(index as usize) {
+            Some(event) => format!("{} {} {}", opcode, event.name, argc),
+            None => format!("{} {} {}", opcode, index, argc),
+        },
+        _ if opcode.names_constant() => {
+            let (index, count) = instruction.operand_values();
+            let operands = match opcode.operands() {
+                Operands::U32U8 => format!("#{} {}", index, count),
+                _ => format!("#{}", index),
+            };
+            match module.constants.get(index as usize) {
+                Some(constant) => format!("{} {} ; {}", opcode, operands, format_constant(constant)),
+                None => format!("{} {}", opcode, operands),
+            }
+        }
+        _ => {
+            let (a, _) = instruction.operand_values();
+            match opcode.operands() {
+                Operands::None => opcode.to_string(),
+                _ => format!("{} {}", opcode, a),
+            }
+        }
+    }
+}
+
+fn format_constant(constant: &Constant) -> String {
+    match constant {
+        Constant::U256(bytes) if bytes[..16].iter().all(|b| *b == 0) => {
+            let mut low = [0u8; 16];
+            low.copy_from_slice(&bytes[16..]);
+            format!("u256 {}", u128::from_be_bytes(low))
+        }
+        Constant::U256(bytes) => format!("u256 0x{}", hex(bytes)),
+        Constant::Address(bytes) => format!("address 0x{}", hex(bytes)),
+        Constant::Bool(value) => format!("bool {}", value),
+        Constant::String(value) => format!("string {}", quote(value)),
+        Constant::Bytes(bytes) => format!("bytes 0x{}", hex(bytes)),
+    }
+}
+
+fn format_type(ty: &Type) -> String {
+    match ty {
+        Type::U256 => "u256".into(),
+        Type::Address => "address".into(),
+        Type::Bool => "bool".into(),
+        Type::String => "string".into(),
+        Type::Bytes => "bytes".into(),
+        Type::Array(element) => format!("array<{}>", format_type(element)),
+        Type::Map { key, value } => format!("map<{}, {}>", format_type(key), format_type(value)),
+        Type::Tuple(elements) => {
+            format!("({})", elements.iter().map(format_type).collect::<Vec<_>>().join(", "))
+        }
+        Type::Named(name) => name.clone(),
+        Type::Void => "void".into(),
+    }
+}
+
+/// Types print in their assembly spelling
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_type(self))
+    }
+}
+
+fn format_params(params: &[Param]) -> String {
+    params.iter()
+        .map(|p| format!("{}: {}", p.name, format_type(&p.ty)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn quote(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{{{:x}}}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parses assembly text into a validated module
+pub fn assemble(source: &str) -> Result<Module, AsmError> {
+    let mut assembler = Assembler::default();
+    let lines: Vec<(usize, &str)> = source.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, strip_comment(line).trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+
+    // Calls and emits may refer forward, so collect names first
+    for &(number, line) in &lines {
+        if let Some(rest) = line.strip_prefix(".function") {
+            let name = rest.split_whitespace().next()
+                .ok_or_else(|| error(number, "expected a function name"))?;
+            assembler.function_names.push(name.to_string());
+        } else if let Some(rest) = line.strip_prefix(".event") {
+            let (name, params, _) = parse_signature(rest.trim()).map_err(|msg| error(number, msg))?;
+            assembler.module.abi.events.push(AbiEvent { name, params });
+        }
+    }
+
+    for &(number, line) in &lines {
+        if line.starts_with(".function") {
+            assembler.finish_function()?;
+        }
+        assembler.line(number, line).map_err(|msg| error(number, msg))?;
+    }
+    assembler.finish_function()?;
+
+    let mut module = assembler.module;
+    for (number, name, params, returns) in assembler.exports {
+        let function = module.function_index(&name)
+            .ok_or_else(|| error(number, format!("unknown function `{}`", name)))?;
+        module.abi.functions.push(AbiFunction { function, params, returns });
+    }
+    if module.name.is_empty() {
+        return Err(error(0, "missing `.contract` directive"));
+    }
+    module.validate().map_err(|msg| error(0, msg))?;
+    Ok(module)
+}
+
+fn error(line: usize, message: impl Into<String>) -> AsmError {
+    AsmError { line, message: message.into() }
+}
+
+#[derive(Default)]
+struct Assembler {
+    module: Module,
+    function_names: Vec<String>,
+    exports: Vec<(usize, String, Vec<Param>, Option<Type>)>,
+    current: Option<PendingFunction>,
+}
+
+/// A function whose jumps are resolved once all its labels are known
+struct PendingFunction {
+    function: Function,
+    labels: HashMap<String, u32>,
+    jumps: Vec<(usize, usize, String)>,
+    location: Option<(u32, u32)>,
+}
+
+impl Assembler {
+    fn line(&mut self, number: usize, line: &str) -> Result<(), String> {
+        if let Some(directive) = line.strip_prefix('.') {
+            let (name, rest) = split_word(directive);
+            return match name {
+                "contract" => {
+                    self.module.name = rest.to_string();
+                    Ok(())
+                }
+                "host" => {
+                    self.module.host_version = parse_number(rest)?;
+                    Ok(())
+                }
+                "source" => {
+                    let source = parse_string(rest)?;
+                    self.module.debug.get_or_insert_with(DebugInfo::default).source = source;
+                    Ok(())
+                }
+                "const" => self.constant(rest),
+                "storage" => self.storage(rest),
+                "event" => Ok(()),
+                "export" => {
+                    let (name, params, returns) = parse_signature(rest)?;
+                    self.exports.push((number, name, params, returns));
+                    Ok(())
+                }
+                "function" => self.start_function(rest),
+                "loc" => {
+                    let pending = self.current.as_mut().ok_or("`.loc` outside a function")?;
+                    let (line, column) = rest.split_once(':').ok_or("expected `.loc <line>:<column>`")?;
+                    pending.location = Some((parse_number(line)?, parse_number(column)?));
+                    Ok(())
+                }
+                "var" => self.variable(rest),
+                _ => Err(format!("unknown directive `.{}`", name)),
+            };
+        }
+
+        let pending = self.current.as_mut().ok_or("instruction outside a function")?;
+        let mut line = line;
+        if let Some((label, rest)) = line.split_once(':') {
+            if !label.contains(char::is_whitespace) && !label.contains('"') {
+                let pc = pending.function.code.len() as u32;
+                if pending.labels.insert(label.to_string(), pc).is_some() {
+                    return Err(format!("label `{}` defined twice", label));
+                }
+                line = rest.trim();
+                if line.is_empty() {
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut words = line.split_whitespace();
+        let mnemonic = words.next().unwrap_or_default();
+        let opcode = Opcode::from_mnemonic(mnemonic)
+            .ok_or_else(|| format!("unknown instruction `{}`", mnemonic))?;
+        let operands: Vec<&str> = words.collect();
+        let expected = match opcode.operands() {
+            Operands::None => 0,
+            Operands::U8 | Operands::U32 => 1,
+            Operands::U32U8 => 2,
+        };
+        if operands.len() != expected {
+            return Err(format!("`{}` takes {} operand(s), found {}", opcode, expected, operands.len()));
+        }
+
+        let pc = pending.function.code.len();
+        if let Some((line, column)) = pending.location.take() {
+            self.module.debug.get_or_insert_with(DebugInfo::default).locations.push(DebugLocation {
+                function: self.module.functions.len() as u32,
+                pc: pc as u32,
+                line,
+                column,
+            });
+        }
+
+        let constant = |operand: &str| -> Result<u32, String> {
+            parse_number(operand.strip_prefix('#').ok_or("expected `#<constant>`")?)
+        };
+        let (a, b) = match opcode {
+            Opcode::Push => (constant(operands[0])?, 0),
+            _ if opcode.names_constant() => {
+                let count = match operands.get(1) {
+                    Some(count) => parse_number(count)?,
+                    None => 0,
+                };
+                (constant(operands[0])?, count)
+            }
+            Opcode::Jump | Opcode::JumpIf => {
+                match operands[0].parse::<u32>() {
+                    Ok(target) => (target, 0),
+                    Err(_) => {
+                        pending.jumps.push((number, pc, operands[0].to_string()));
+                        (0, 0)
+                    }
+                }
+            }
+            Opcode::Call | Opcode::Closure => {
+                let index = resolve(operands[0], &self.function_names, "function")?;
+                (index, parse_number(operands[1])?)
+            }
+            Opcode::Emit => {
+                let names: Vec<String> = self.module.abi.events.iter().map(|e| e.name.clone()).collect();
+                let index = resolve(operands[0], &names, "event")?;
+                (index, parse_number(operands[1])?)
+            }
+            _ if opcode.operands() == Operands::U8 => (parse_number::<u8>(operands[0])? as u32, 0),
+            _ if expected == 1 => (parse_number(operands[0])?, 0),
+            _ => (0, 0),
+        };
+        pending.function.code.push(Instruction::from_parts(opcode, a, b));
+        Ok(())
+    }
+
+    fn start_function(&mut self, header: &str) -> Result<(), String> {
+        let (name, rest) = split_word(header);
+        let mut function = Function {
+            name: name.to_string(),
+            arity: 0,
+            locals: 0,
+            is_pure: false,
+            code: Vec::new(),
+        };
+        for word in rest.split_whitespace() {
+            match word.split_once('=') {
+                Some(("arity", value)) => function.arity = parse_number(value)?,
+                Some(("locals", value)) => function.locals = parse_number(value)?,
+                None if word == "pure" => function.is_pure = true,
+                _ => return Err(format!("unexpected `{}` in function header", word)),
+            }
+        }
+        self.current = Some(PendingFunction {
+            function,
+            labels: HashMap::new(),
+            jumps: Vec::new(),
+            location: None,
+        });
+        Ok(())
+    }
+
+    fn finish_function(&mut self) -> Result<(), AsmError> {
+        let Some(mut pending) = self.current.take() else {
+            return Ok(());
+        };
+        for (number, pc, label) in pending.jumps {
+            let target = *pending.labels.get(&label)
+                .ok_or_else(|| error(number, format!("undefined label `{}`", label)))?;
+            let opcode = pending.function.code[pc].opcode();
+            pending.function.code[pc] = Instruction::from_parts(opcode, target, 0);
+        }
+        self.module.functions.push(pending.function);
+        Ok(())
+    }
+
+    fn constant(&mut self, rest: &str) -> Result<(), String> {
+        let mut rest = rest;
+        if let Some(indexed) = rest.strip_prefix('#') {
+            let (index, tail) = split_word(indexed);
+            if parse_number::<usize>(index)? != self.module.constants.len() {
+                return Err(format!("expected constant #{}", self.module.constants.len()));
+            }
+            rest = tail;
+        }
+        let (kind, value) = split_word(rest);
+        let constant = match kind {
+            "u256" => Constant::U256(parse_u256(value)?),
+            "address" => {
+                let bytes = parse_hex(value)?;
+                Constant::Address(bytes.try_into().map_err(|_| "an address is 20 bytes")?)
+            }
+            "bool" => Constant::Bool(match value {
+                "true" => true,
+                "false" => false,
+                _ => return Err(format!("invalid bool `{}`", value)),
+            }),
+            "string" => Constant::String(parse_string(value)?),
+            "bytes" => Constant::Bytes(parse_hex(value)?),
+            _ => return Err(format!("unknown constant kind `{}`", kind)),
+        };
+        self.module.constants.push(constant);
+        Ok(())
+    }
+
+    fn variable(&mut self, rest: &str) -> Result<(), String> {
+        if self.current.is_none() {
+            return Err("`.var` outside a function".into());
+        }
+        let syntax = "expected `.var <slot> <start>..<end> <name>: <type>`";
+        let (slot, rest) = split_word(rest);
+        let (range, rest) = split_word(rest);
+        let (start, end) = range.split_once("..").ok_or(syntax)?;
+        let (name, ty) = rest.split_once(':').ok_or(syntax)?;
+        self.module.debug.get_or_insert_with(DebugInfo::default).variables.push(DebugVariable {
+            function: self.module.functions.len() as u32,
+            slot: parse_number(slot)?,
+            name: name.trim().to_string(),
+            ty: parse_type(ty)?,
+            start: parse_number(start)?,
+            end: parse_number(end)?,
+        });
+        Ok(())
+    }
+
+    fn storage(&mut self, rest: &str) -> Result<(), String> {
+        let (slot, rest) = split_word(rest);
+        let (name, ty) = rest.split_once(':').ok_or("expected `.storage <slot> <name>: <type>`")?;
+        self.module.storage.push(StorageEntry {
+            name: name.trim().to_string(),
+            slot: parse_number(slot)?,
+            ty: parse_type(ty)?,
+        });
+        Ok(())
+    }
+}
+
+fn resolve(operand: &str, names: &[String], what: &str) -> Result<u32, String> {
+    if let Ok(index) = operand.parse::<u32>() {
+        return Ok(index);
+    }
+    names.iter().position(|name| name == operand)
+        .map(|index| index as u32)
+        .ok_or_else(|| format!("unknown {} `{}`", what, operand))
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+/// Drops a `;` comment, ignoring semicolons inside string literals
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.trim().parse().map_err(|_| format!("invalid number `{}`", text.trim()))
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text.strip_prefix("0x").ok_or_else(|| format!("expected hex bytes, found `{}`", text))?;
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in `{}`", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("invalid hex `{}`", text)))
+        .collect()
+}
+
+fn parse_u256(text: &str) -> Result<[u8; 32], String> {
+    let mut bytes = [0u8; 32];
+    if text.starts_with("0x") {
+        let digits = parse_hex(text)?;
+        if digits.len() > 32 {
+            return Err(format!("`{}` does not fit in 256 bits", text));
+        }
+        bytes[32 - digits.len()..].copy_from_slice(&digits);
+    } else {
+        let value: u128 = parse_number(text)?;
+        bytes[16..].copy_from_slice(&value.to_be_bytes());
+    }
+    Ok(bytes)
+}
+
+fn parse_string(text: &str) -> Result<String, String> {
+    let inner = text.strip_prefix('"').and_then(|t| t.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a quoted string, found `{}`", text))?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('u') => {
+                let rest: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let code = rest.strip_prefix('{')
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid escape in {}", text))?;
+                out.push(code);
+            }
+            _ => return Err(format!("invalid escape in {}", text)),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_type(text: &str) -> Result<Type, String> {
+    let text = text.trim();
+    let generic = |prefix: &str| {
+        text.strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('<'))
+            .and_then(|rest| rest.strip_suffix('>'))
+    };
+    if let Some(element) = generic("array") {
+        return Ok(Type::Array(Box::new(parse_type(element)?)));
+    }
+    if let Some(arguments) = generic("map") {
+        let parts = split_top_level(arguments);
+        if parts.len() != 2 {
+            return Err(format!("expected `map<key, value>`, found `{}`", text));
+        }
+        return Ok(Type::Map {
+            key: Box::new(parse_type(parts[0])?),
+            value: Box::new(parse_type(parts[1])?),
+        });
+    }
+    if let Some(elements) = text.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        return Ok(Type::Tuple(split_top_level(elements).into_iter().map(parse_type).collect::<Result<_, _>>()?));
+    }
+    match text {
+        "u256" => Ok(Type::U256),
+        "address" => Ok(Type::Address),
+        "bool" => Ok(Type::Bool),
+        "string" => Ok(Type::String),
+        "bytes" => Ok(Type::Bytes),
+        "void" => Ok(Type::Void),
+        _ if text.starts_with(|c: char| c.is_alphabetic() || c == '_') => Ok(Type::Named(text.to_string())),
+        _ => Err(format!("unknown type `{}`", text)),
+    }
+}
+
+/// Splits on commas that are not nested inside `<...>` or `(...)`
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = text[start..].trim();
+    if !last.is_empty() || !parts.is_empty() {
+        parts.push(last);
+    }
+    parts
+}
+
+/// `name(a: T, b: U) -> R`, the return type being optional
+fn parse_signature(text: &str) -> Result<(String, Vec<Param>, Option<Type>), String> {
+    let open = text.find('(').ok_or_else(|| format!("expected `name(...)`, found `{}`", text))?;
+    let mut depth = 0;
+    let close = text[open..].char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })
+        .map(|(i, _)| open + i)
+        .ok_or_else(|| format!("missing `)` in `{}`", text))?;
+    let name = text[..open].trim().to_string();
+    let params = split_top_level(&text[open + 1..close])
+        .into_iter()
+        .map(|param| {
+            let (name, ty) = param.split_once(':')
+                .ok_or_else(|| format!("expected `name: type`, found `{}`", param))?;
+            Ok(Param { name: name.trim().to_string(), ty: parse_type(ty)? })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let returns = match text[close + 1..].trim() {
+        "" => None,
+        rest => {
+            let ty = rest.strip_prefix("->").ok_or_else(|| format!("unexpected `{}`", rest))?;
+            Some(parse_type(ty)?)
+        }
+    };
+    Ok((name, params, returns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::sample_module;
+
+    #[test]
+    fn test_disassembly_round_trips() {
+        let module = sample_module();
+        let text = disassemble(&module);
+        assert!(text.contains("JUMPI L12"), "{}", text);
+        assert!(text.contains("CALL is_zero 1"), "{}", text);
+        assert!(text.contains("EMIT Incremented 1"), "{}", text);
+        assert!(text.contains(".storage 1 owners: map<address, bool>"), "{}", text);
+        assert!(text.contains(".var 1 5..14 next: u256"), "{}", text);
+        assert!(!text.contains(".host"), "{}", text);
+        assert_eq!(assemble(&text), Ok(module.clone()));
+
+        let older = Module { host_version: 1, ..module };
+        assert_eq!(assemble(&disassemble(&older)), Ok(older));
+    }
+
+    #[test]
+    fn test_constants_round_trip() {
+        let mut module = Module::new("Constants");
+        let mut big = [0xffu8; 32];
+        big[0] = 0x7f;
+        module.constants = vec![
+            Constant::U256(big),
+            Constant::u256(u64::MAX),
+            Constant::String("semi; \"quoted\"\n\u{1}".into()),
+            Constant::Bytes(Vec::new()),
+        ];
+        assert_eq!(assemble(&disassemble(&module)), Ok(module));
+    }
+
+    #[test]
+    fn test_aggregates_and_named_operands_round_trip() {
+        let mut module = Module::new("Market");
+        module.constants = vec![
+            Constant::String("Listing {seller, price}".into()),
+            Constant::String("price".into()),
+            Constant::String("Err".into()),
+            Constant::String("msg.sender".into()),
+            Constant::String("transfer_from".into()),
+            Constant::String("not listed".into()),
+            Constant::String("min".into()),
+        ];
+        module.storage = vec![StorageEntry {
+            name: "listings".into(),
+            slot: 0,
+            ty: Type::Map { key: Box::new(Type::U256), value: Box::new(Type::Named("Listing".into())) },
+        }];
+        module.functions = vec![
+            Function {
+                name: "buy".into(),
+                arity: 2,
+                locals: 2,
+                is_pure: false,
+                code: vec![
+                    Instruction::Env(3),
+                    Instruction::Load(1),
+                    Instruction::Struct(0, 2),
+                    Instruction::GetField(1),
+                    Instruction::Load(0),
+                    Instruction::Load(1),
+                    Instruction::CallHost(6, 2),
+                    Instruction::CallMethod(4, 1),
+                    Instruction::IsVariant(2),
+                    Instruction::JumpIf(14),
+                    Instruction::Load(0),
+                    Instruction::Closure(1, 1),
+                    Instruction::CallIndirect(0),
+                    Instruction::Tuple(2),
+                    Instruction::Revert(5),
+                ],
+            },
+            Function { name: "by_price".into(), arity: 2, locals: 2, is_pure: true, code: vec![Instruction::Return] },
+        ];
+        module.abi.functions.push(AbiFunction {
+            function: 0,
+            params: vec![
+                Param { name: "id".into(), ty: Type::U256 },
+                Param { name: "pair".into(), ty: Type::Tuple(vec![Type::U256, Type::Named("Result<u256, Error>".into())]) },
+            ],
+            returns: Some(Type::Tuple(vec![Type::Bool, Type::U256])),
+        });
+        let text = disassemble(&module);
+        assert!(text.contains("STRUCT #0 2 ; string \"Listing {seller, price}\""), "{}", text);
+        assert!(text.contains("CLOSURE by_price 1"), "{}", text);
+        assert!(text.contains("-> (bool, u256)"), "{}", text);
+        assert_eq!(assemble(&text), Ok(module));
+    }
+
+    #[test]
+    fn test_assemble_handwritten_source() {
+        let source = "
+            .contract Loop
+            .const u256 10 ; limit
+
+            .function count arity=0 locals=1
+            top: LOAD 0
+                PUSH #0
+                LT
+                JUMPI done
+                JUMP top
+            done:
+                RET
+        ";
+        let module = assemble(source).unwrap();
+        assert_eq!(module.functions[0].code[3], Instruction::JumpIf(5));
+        assert_eq!(module.functions[0].code[4], Instruction::Jump(0));
+    }
+
+    #[test]
+    fn test_assembler_errors_have_line_numbers() {
+        let err = assemble(".contract C\n.function f arity=0 locals=0\n    FROB\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("FROB"));
+
+        let err = assemble(".contract C\n.function f arity=0 locals=0\n    JUMP nowhere\n").unwrap_err();
+        assert_eq!(err.line, 3);
+
+        let err = assemble(".contract C\n.function f arity=0 locals=0\n    LOAD 3\n").unwrap_err();
+        assert!(err.message.contains("local 3"));
+    }
+}
@@ -0,0 +1,426 @@
+use std::fmt;
+
+use super::encode::{
+    CONST_ADDRESS, CONST_BOOL, CONST_BYTES, CONST_STRING, CONST_U256, TYPE_ADDRESS, TYPE_ARRAY,
+    TYPE_BOOL, TYPE_BYTES, TYPE_MAP, TYPE_NAMED, TYPE_STRING, TYPE_TUPLE, TYPE_U256, TYPE_VOID,
+};
+use super::opcode::{Instruction, Opcode, Operands};
+use super::{
+    Abi, AbiEvent, AbiFunction, Constant, DebugInfo, DebugLocation, DebugVariable, Function, Module, Param,
+    StorageEntry, Type, MAGIC, SECTION_ABI, SECTION_CONSTANTS, SECTION_DEBUG, SECTION_FUNCTIONS,
+    SECTION_STORAGE, VERSION,
+};
+
+/// A malformed container, located by byte offset
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeErrorKind {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnexpectedEnd,
+    InvalidUtf8,
+    InvalidOpcode(u8),
+    InvalidTag { what: &'static str, tag: u8 },
+    UnknownSection(u8),
+    SectionOutOfOrder(u8),
+    MissingSection(u8),
+    /// A section's declared length exceeds what its contents use
+    TrailingBytes,
+    /// A type nests arrays, maps or tuples deeper than `MAX_TYPE_DEPTH`
+    TypeTooDeep,
+    /// Well-formed but inconsistent, e.g. a jump past the end of a function.
+    /// The offset is the end of the container.
+    Invalid(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            DecodeErrorKind::BadMagic => write!(f, "not a .strxb file (bad magic)"),
+            DecodeErrorKind::UnsupportedVersion(version) => write!(
+                f, "unsupported bytecode version {} (expected {})", version, VERSION
+            ),
+            DecodeErrorKind::UnexpectedEnd => write!(f, "unexpected end of data at byte {}", self.offset),
+            DecodeErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8 string at byte {}", self.offset),
+            DecodeErrorKind::InvalidOpcode(byte) => {
+                write!(f, "invalid opcode 0x{:02x} at byte {}", byte, self.offset)
+            }
+            DecodeErrorKind::InvalidTag { what, tag } => {
+                write!(f, "invalid {} tag {} at byte {}", what, tag, self.offset)
+            }
+            DecodeErrorKind::UnknownSection(id) => write!(f, "unknown section {} at byte {}", id, self.offset),
+            DecodeErrorKind::SectionOutOfOrder(id) => {
+                write!(f, "section {} out of order at byte {}", id, self.offset)
+            }
+            DecodeErrorKind::MissingSection(id) => write!(f, "missing section {}", id),
+            DecodeErrorKind::TrailingBytes => write!(f, "unexpected trailing bytes at byte {}", self.offset),
+            DecodeErrorKind::TypeTooDeep => {
+                write!(f, "type nested deeper than {} levels at byte {}", MAX_TYPE_DEPTH, self.offset)
+            }
+            DecodeErrorKind::Invalid(msg) => write!(f, "invalid module: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Parses and validates a `.strxb` container
+pub fn decode(bytes: &[u8]) -> Result<Module, DecodeError> {
+    let mut reader = Reader { bytes, position: 0, depth: 0 };
+
+    if !bytes.starts_with(&MAGIC) {
+        return Err(reader.error(DecodeErrorKind::BadMagic));
+    }
+    reader.take(MAGIC.len())?;
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(reader.error_at(4, DecodeErrorKind::UnsupportedVersion(version)));
+    }
+
+    let host_version = reader.u16()?;
+    let mut module = Module::new(reader.string()?);
+    module.host_version = host_version;
+    let mut next = SECTION_CONSTANTS;
+    while !reader.at_end() {
+        let start = reader.position;
+        let id = reader.u8()?;
+        if !(SECTION_CONSTANTS..=SECTION_DEBUG).contains(&id) {
+            return Err(reader.error_at(start, DecodeErrorKind::UnknownSection(id)));
+        }
+        if id != next {
+            let kind = if id < next {
+                DecodeErrorKind::SectionOutOfOrder(id)
+            } else {
+                DecodeErrorKind::MissingSection(next)
+            };
+            return Err(reader.error_at(start, kind));
+        }
+        let length = reader.u32()? as usize;
+        let payload_start = reader.position;
+        let payload = reader.take(length)?;
+        let mut section = Reader { bytes: &bytes[..payload_start + payload.len()], position: payload_start, depth: 0 };
+
+        match id {
+            SECTION_CONSTANTS => module.constants = section.list(Reader::constant)?,
+            SECTION_FUNCTIONS => module.functions = section.list(Reader::function)?,
+            SECTION_STORAGE => module.storage = section.list(Reader::storage_entry)?,
+            SECTION_ABI => module.abi = section.abi()?,
+            _ => module.debug = Some(section.debug()?),
+        }
+        if !section.at_end() {
+            return Err(section.error(DecodeErrorKind::TrailingBytes));
+        }
+        next = id + 1;
+    }
+    if next <= SECTION_ABI {
+        return Err(reader.error(DecodeErrorKind::MissingSection(next)));
+    }
+
+    module.validate()
+        .map_err(|msg| reader.error(DecodeErrorKind::Invalid(msg)))?;
+    Ok(module)
+}
+
+/// How deeply array, map and tuple types may nest, so that decoding a
+/// crafted type cannot exhaust the native stack
+const MAX_TYPE_DEPTH: usize = 64;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Types currently being decoded, innermost last
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        self.error_at(self.position, kind)
+    }
+
+    fn error_at(&self, offset: usize, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError { offset, kind }
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.position.checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| self.error_at(self.bytes.len(), DecodeErrorKind::UnexpectedEnd))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        let start = self.position;
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(self.error_at(start, DecodeErrorKind::InvalidTag { what: "bool", tag })),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let start = self.position;
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| self.error_at(start, DecodeErrorKind::InvalidUtf8))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// A `u32` count followed by that many items
+    fn list<T>(&mut self, item: fn(&mut Self) -> Result<T, DecodeError>) -> Result<Vec<T>, DecodeError> {
+        let count = self.u32()?;
+        // Counts come from untrusted input, so grow as items actually decode
+        let mut items = Vec::new();
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn constant(&mut self) -> Result<Constant, DecodeError> {
+        let start = self.position;
+        match self.u8()? {
+            CONST_U256 => Ok(Constant::U256(self.array()?)),
+            CONST_ADDRESS => Ok(Constant::Address(self.array()?)),
+            CONST_BOOL => Ok(Constant::Bool(self.bool()?)),
+            CONST_STRING => Ok(Constant::String(self.string()?)),
+            CONST_BYTES => Ok(Constant::Bytes(self.bytes()?)),
+            tag => Err(self.error_at(start, DecodeErrorKind::InvalidTag { what: "constant", tag })),
+        }
+    }
+
+    fn ty(&mut self) -> Result<Type, DecodeError> {
+        if self.depth == MAX_TYPE_DEPTH {
+            return Err(self.error(DecodeErrorKind::TypeTooDeep));
+        }
+        self.depth += 1;
+        let ty = self.type_tag();
+        self.depth -= 1;
+        ty
+    }
+
+    fn type_tag(&mut self) -> Result<Type, DecodeError> {
+        let start = self.position;
+        match self.u8()? {
+            TYPE_U256 => Ok(Type::U256),
+            TYPE_ADDRESS => Ok(Type::Address),
+            TYPE_BOOL => Ok(Type::Bool),
+            TYPE_STRING => Ok(Type::String),
+            TYPE_BYTES => Ok(Type::Bytes),
+            TYPE_ARRAY => Ok(Type::Array(Box::new(self.ty()?))),
+            TYPE_MAP => {
+                let key = Box::new(self.ty()?);
+                let value = Box::new(self.ty()?);
+                Ok(Type::Map { key, value })
+            }
+            TYPE_TUPLE => Ok(Type::Tuple(self.list(Reader::ty)?)),
+            TYPE_NAMED => Ok(Type::Named(self.string()?)),
+            TYPE_VOID => Ok(Type::Void),
+            tag => Err(self.error_at(start, DecodeErrorKind::InvalidTag { what: "type", tag })),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, DecodeError> {
+        Ok(Function {
+            name: self.string()?,
+            arity: self.u8()?,
+            locals: self.u32()?,
+            is_pure: self.bool()?,
+            code: self.list(Reader::instruction)?,
+        })
+    }
+
+    fn instruction(&mut self) -> Result<Instruction, DecodeError> {
+        let start = self.position;
+        let byte = self.u8()?;
+        let opcode = Opcode::from_byte(byte)
+            .ok_or_else(|| self.error_at(start, DecodeErrorKind::InvalidOpcode(byte)))?;
+        let (a, b) = match opcode.operands() {
+            Operands::None => (0, 0),
+            Operands::U8 => (self.u8()? as u32, 0),
+            Operands::U32 => (self.u32()?, 0),
+            Operands::U32U8 => (self.u32()?, self.u8()?),
+        };
+        Ok(Instruction::from_parts(opcode, a, b))
+    }
+
+    fn storage_entry(&mut self) -> Result<StorageEntry, DecodeError> {
+        Ok(StorageEntry {
+            name: self.string()?,
+            slot: self.u32()?,
+            ty: self.ty()?,
+        })
+    }
+
+    fn param(&mut self) -> Result<Param, DecodeError> {
+        Ok(Param { name: self.string()?, ty: self.ty()? })
+    }
+
+    fn abi_function(&mut self) -> Result<AbiFunction, DecodeError> {
+        Ok(AbiFunction {
+            function: self.u32()?,
+            params: self.list(Reader::param)?,
+            returns: if self.bool()? { Some(self.ty()?) } else { None },
+        })
+    }
+
+    fn abi_event(&mut self) -> Result<AbiEvent, DecodeError> {
+        Ok(AbiEvent {
+            name: self.string()?,
+            params: self.list(Reader::param)?,
+        })
+    }
+
+    fn abi(&mut self) -> Result<Abi, DecodeError> {
+        Ok(Abi {
+            functions: self.list(Reader::abi_function)?,
+            events: self.list(Reader::abi_event)?,
+        })
+    }
+
+    fn debug_location(&mut self) -> Result<DebugLocation, DecodeError> {
+        Ok(DebugLocation {
+            function: self.u32()?,
+            pc: self.u32()?,
+            line: self.u32()?,
+            column: self.u32()?,
+        })
+    }
+
+    fn debug_variable(&mut self) -> Result<DebugVariable, DecodeError> {
+        Ok(DebugVariable {
+            function: self.u32()?,
+            slot: self.u32()?,
+            name: self.string()?,
+            ty: self.ty()?,
+            start: self.u32()?,
+            end: self.u32()?,
+        })
+    }
+
+    fn debug(&mut self) -> Result<DebugInfo, DecodeError> {
+        Ok(DebugInfo {
+            source: self.string()?,
+            locations: self.list(Reader::debug_location)?,
+            variables: self.list(Reader::debug_variable)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encode;
+    use super::super::tests::sample_module;
+
+    #[test]
+    fn test_round_trip() {
+        let module = sample_module();
+        assert_eq!(decode(&encode(&module)), Ok(module.clone()));
+
+        let stripped = Module { debug: None, ..module };
+        assert_eq!(decode(&encode(&stripped)), Ok(stripped));
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        let mut bytes = encode(&sample_module());
+        bytes[0] = b'X';
+        assert_eq!(decode(&bytes).unwrap_err().kind, DecodeErrorKind::BadMagic);
+        assert_eq!(decode(b"ST").unwrap_err().kind, DecodeErrorKind::BadMagic);
+
+        let mut bytes = encode(&sample_module());
+        bytes[4] = 9;
+        assert_eq!(decode(&bytes).unwrap_err().kind, DecodeErrorKind::UnsupportedVersion(9));
+    }
+
+    #[test]
+    fn test_rejects_every_truncation() {
+        // Without debug info, so no prefix ends on an optional section boundary
+        let bytes = encode(&Module { debug: None, ..sample_module() });
+        for length in 0..bytes.len() {
+            assert!(decode(&bytes[..length]).is_err(), "accepted {} of {} bytes", length, bytes.len());
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_opcode() {
+        let mut module = Module::new("M");
+        module.functions.push(Function {
+            name: "f".into(),
+            arity: 0,
+            locals: 0,
+            is_pure: true,
+            code: vec![Instruction::Return],
+        });
+        let mut bytes = encode(&module);
+        let offset = bytes.iter().rposition(|b| *b == Opcode::Return as u8).unwrap();
+        bytes[offset] = 0xee;
+        let err = decode(&bytes).unwrap_err();
+        assert_eq!(err.kind, DecodeErrorKind::InvalidOpcode(0xee));
+        assert_eq!(err.offset, offset);
+    }
+
+    #[test]
+    fn test_rejects_dangling_references() {
+        let mut module = sample_module();
+        module.functions[1].code.push(Instruction::Jump(40));
+        let err = decode(&encode(&module)).unwrap_err();
+        assert!(matches!(err.kind, DecodeErrorKind::Invalid(ref msg) if msg.contains("jump target 40")));
+    }
+
+    #[test]
+    fn test_rejects_trailing_and_misordered_sections() {
+        let mut bytes = encode(&sample_module());
+        bytes.push(SECTION_DEBUG);
+        assert!(decode(&bytes).is_err());
+
+        let mut bytes = encode(&sample_module());
+        bytes.extend_from_slice(&[SECTION_ABI, 0, 0, 0, 0]);
+        assert_eq!(decode(&bytes).unwrap_err().kind, DecodeErrorKind::SectionOutOfOrder(SECTION_ABI));
+    }
+
+    #[test]
+    fn test_rejects_deeply_nested_types() {
+        let crafted = vec![TYPE_ARRAY; 100_000];
+        let err = Reader { bytes: &crafted, position: 0, depth: 0 }.ty().unwrap_err();
+        assert_eq!(err, DecodeError { offset: MAX_TYPE_DEPTH, kind: DecodeErrorKind::TypeTooDeep });
+
+        let mut nested = vec![TYPE_ARRAY; MAX_TYPE_DEPTH - 1];
+        nested.push(TYPE_U256);
+        assert!(Reader { bytes: &nested, position: 0, depth: 0 }.ty().is_ok());
+    }
+}
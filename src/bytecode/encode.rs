@@ -0,0 +1,228 @@
+use super::opcode::{Instruction, Operands};
+use super::{
+    Abi, Constant, DebugInfo, Function, Module, Param, StorageEntry, Type, MAGIC, SECTION_ABI,
+    SECTION_CONSTANTS, SECTION_DEBUG, SECTION_FUNCTIONS, SECTION_STORAGE, VERSION,
+};
+
+// Tags shared with the decoder
+pub(super) const CONST_U256: u8 = 0;
+pub(super) const CONST_ADDRESS: u8 = 1;
+pub(super) const CONST_BOOL: u8 = 2;
+pub(super) const CONST_STRING: u8 = 3;
+pub(super) const CONST_BYTES: u8 = 4;
+
+pub(super) const TYPE_U256: u8 = 0;
+pub(super) const TYPE_ADDRESS: u8 = 1;
+pub(super) const TYPE_BOOL: u8 = 2;
+pub(super) const TYPE_STRING: u8 = 3;
+pub(super) const TYPE_BYTES: u8 = 4;
+pub(super) const TYPE_ARRAY: u8 = 5;
+pub(super) const TYPE_MAP: u8 = 6;
+pub(super) const TYPE_VOID: u8 = 7;
+pub(super) const TYPE_TUPLE: u8 = 8;
+pub(super) const TYPE_NAMED: u8 = 9;
+
+/// Serializes a module into a `.strxb` container.
+///
+/// The encoder does not validate; run [`Module::validate`] first if the
+/// module was built by hand.
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&module.host_version.to_le_bytes());
+    out.extend_from_slice(&(module.name.len() as u32).to_le_bytes());
+    out.extend_from_slice(module.name.as_bytes());
+
+    section(&mut out, SECTION_CONSTANTS, |w| {
+        w.u32(module.constants.len() as u32);
+        for constant in &module.constants {
+            w.constant(constant);
+        }
+    });
+    section(&mut out, SECTION_FUNCTIONS, |w| {
+        w.u32(module.functions.len() as u32);
+        for function in &module.functions {
+            w.function(function);
+        }
+    });
+    section(&mut out, SECTION_STORAGE, |w| {
+        w.u32(module.storage.len() as u32);
+        for entry in &module.storage {
+            w.storage_entry(entry);
+        }
+    });
+    section(&mut out, SECTION_ABI, |w| w.abi(&module.abi));
+    if let Some(debug) = &module.debug {
+        section(&mut out, SECTION_DEBUG, |w| w.debug(debug));
+    }
+
+    out
+}
+
+fn section(out: &mut Vec<u8>, id: u8, body: impl FnOnce(&mut Writer)) {
+    let mut writer = Writer { bytes: Vec::new() };
+    body(&mut writer);
+    out.push(id);
+    out.extend_from_slice(&(writer.bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(&writer.bytes);
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::U256(value) => {
+                self.u8(CONST_U256);
+                self.bytes.extend_from_slice(value);
+            }
+            Constant::Address(value) => {
+                self.u8(CONST_ADDRESS);
+                self.bytes.extend_from_slice(value);
+            }
+            Constant::Bool(value) => {
+                self.u8(CONST_BOOL);
+                self.u8(*value as u8);
+            }
+            Constant::String(value) => {
+                self.u8(CONST_STRING);
+                self.string(value);
+            }
+            Constant::Bytes(value) => {
+                self.u8(CONST_BYTES);
+                self.bytes(value);
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::U256 => self.u8(TYPE_U256),
+            Type::Address => self.u8(TYPE_ADDRESS),
+            Type::Bool => self.u8(TYPE_BOOL),
+            Type::String => self.u8(TYPE_STRING),
+            Type::Bytes => self.u8(TYPE_BYTES),
+            Type::Array(element) => {
+                self.u8(TYPE_ARRAY);
+                self.ty(element);
+            }
+            Type::Map { key, value } => {
+                self.u8(TYPE_MAP);
+                self.ty(key);
+                self.ty(value);
+            }
+            Type::Tuple(elements) => {
+                self.u8(TYPE_TUPLE);
+                self.u32(elements.len() as u32);
+                for element in elements {
+                    self.ty(element);
+                }
+            }
+            Type::Named(name) => {
+                self.u8(TYPE_NAMED);
+                self.string(name);
+            }
+            Type::Void => self.u8(TYPE_VOID),
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.string(&function.name);
+        self.u8(function.arity);
+        self.u32(function.locals);
+        self.u8(function.is_pure as u8);
+        self.u32(function.code.len() as u32);
+        for instruction in &function.code {
+            self.instruction(instruction);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        let opcode = instruction.opcode();
+        let (a, b) = instruction.operand_values();
+        self.u8(opcode as u8);
+        match opcode.operands() {
+            Operands::None => {}
+            Operands::U8 => self.u8(a as u8),
+            Operands::U32 => self.u32(a),
+            Operands::U32U8 => {
+                self.u32(a);
+                self.u8(b);
+            }
+        }
+    }
+
+    fn storage_entry(&mut self, entry: &StorageEntry) {
+        self.string(&entry.name);
+        self.u32(entry.slot);
+        self.ty(&entry.ty);
+    }
+
+    fn params(&mut self, params: &[Param]) {
+        self.u32(params.len() as u32);
+        for param in params {
+            self.string(&param.name);
+            self.ty(&param.ty);
+        }
+    }
+
+    fn abi(&mut self, abi: &Abi) {
+        self.u32(abi.functions.len() as u32);
+        for entry in &abi.functions {
+            self.u32(entry.function);
+            self.params(&entry.params);
+            match &entry.returns {
+                Some(ty) => {
+                    self.u8(1);
+                    self.ty(ty);
+                }
+                None => self.u8(0),
+            }
+        }
+        self.u32(abi.events.len() as u32);
+        for event in &abi.events {
+            self.string(&event.name);
+            self.params(&event.params);
+        }
+    }
+
+    fn debug(&mut self, debug: &DebugInfo) {
+        self.string(&debug.source);
+        self.u32(debug.locations.len() as u32);
+        for location in &debug.locations {
+            self.u32(location.function);
+            self.u32(location.pc);
+            self.u32(location.line);
+            self.u32(location.column);
+        }
+        self.u32(debug.variables.len() as u32);
+        for variable in &debug.variables {
+            self.u32(variable.function);
+            self.u32(variable.slot);
+            self.string(&variable.name);
+            self.ty(&variable.ty);
+            self.u32(variable.start);
+            self.u32(variable.end);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! The host function table: everything a contract can learn about, or ask
+//! of, the chain it runs on.
+//!
+//! Entries without parameters are read with `ENV` and the others are
+//! called with `CALLH`, both naming the entry by its source spelling. The
+//! Wasm backend imports every entry instead, from the `stremax` namespace
+//! under its `import` name. Either way, reaching an entry costs its fixed
+//! `gas` on top of the instruction that reached it.
+//!
+//! The table is versioned. Entries are only ever appended, each recording
+//! the [`HOST_VERSION`] that introduced it, so an entry's position never
+//! changes and a host implementing version N serves exactly the entries
+//! with `since <= N`.
+
+use super::Type;
+
+/// Version of [`HOST_FUNCTIONS`] that this build implements
+pub const HOST_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostFunction {
+    /// What source code, `ENV` and `CALLH` call it
+    pub name: &'static str,
+    /// Import name in the Wasm backend's `stremax` namespace
+    pub import: &'static str,
+    /// `Bytes` parameters take the raw bytes of a byte string, a string, a
+    /// word or an address
+    pub params: &'static [Type],
+    /// `Void` for entries run for their effect
+    pub returns: Type,
+    pub gas: u64,
+    /// The [`HOST_VERSION`] that added the entry
+    pub since: u16,
+}
+
+impl HostFunction {
+    /// Whether the entry is a value read with `ENV` rather than called
+    pub fn is_value(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+const fn entry(
+    name: &'static str,
+    import: &'static str,
+    params: &'static [Type],
+    returns: Type,
+    gas: u64,
+) -> HostFunction {
+    added(1, name, import, params, returns, gas)
+}
+
+/// An entry that host version `since` appended
+const fn added(
+    since: u16,
+    name: &'static str,
+    import: &'static str,
+    params: &'static [Type],
+    returns: Type,
+    gas: u64,
+) -> HostFunction {
+    HostFunction { name, import, params, returns, gas, since }
+}
+
+/// Every host function, in the order the Wasm backend imports them
+pub static HOST_FUNCTIONS: [HostFunction; 17] = [
+    entry("msg.sender", "caller", &[], Type::Address, 2),
+    entry("tx.origin", "origin", &[], Type::Address, 2),
+    entry("msg.value", "value", &[], Type::U256, 2),
+    entry("self", "address", &[], Type::Address, 2),
+    entry("block.number", "block_number", &[], Type::U256, 2),
+    entry("block.timestamp", "block_timestamp", &[], Type::U256, 2),
+    entry("chain.id", "chain_id", &[], Type::U256, 2),
+    entry("self.balance", "self_balance", &[], Type::U256, 5),
+    entry("balance", "balance", &[Type::Address], Type::U256, 20),
+    entry("code_hash", "code_hash", &[Type::Address], Type::U256, 100),
+    entry("keccak256", "keccak256", &[Type::Bytes], Type::U256, 30),
+    entry("sha256", "sha256", &[Type::Bytes], Type::U256, 60),
+    entry("ed25519_verify", "ed25519_verify", &[Type::Bytes, Type::Bytes, Type::Bytes], Type::Bool, 3000),
+    entry("log", "log", &[Type::String], Type::Void, 50),
+    added(2, "min", "min", &[Type::U256, Type::U256], Type::U256, 3),
+    added(2, "max", "max", &[Type::U256, Type::U256], Type::U256, 3),
+    added(2, "sqrt", "sqrt", &[Type::U256], Type::U256, 50),
+];
+
+/// The entry called `name` and its position in [`HOST_FUNCTIONS`]
+pub fn lookup(name: &str) -> Option<(usize, &'static HostFunction)> {
+    HOST_FUNCTIONS.iter().enumerate().find(|(_, entry)| entry.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_table_is_append_only_and_unambiguous() {
+        let mut names = std::collections::HashSet::new();
+        let mut imports = std::collections::HashSet::new();
+        for entry in &HOST_FUNCTIONS {
+            assert!(names.insert(entry.name), "`{}` is listed twice", entry.name);
+            assert!(imports.insert(entry.import), "`{}` is imported twice", entry.import);
+            assert!((1..=HOST_VERSION).contains(&entry.since), "`{}` comes from an unknown version", entry.name);
+            assert!(entry.gas > 0, "`{}` is free", entry.name);
+        }
+        assert!(HOST_FUNCTIONS.windows(2).all(|pair| pair[0].since <= pair[1].since));
+
+        assert_eq!(lookup("block.number").map(|(id, _)| id), Some(4));
+        assert!(lookup("msg.sender").unwrap().1.is_value());
+        assert!(!lookup("keccak256").unwrap().1.is_value());
+        assert!(!lookup("min").unwrap().1.is_value());
+        assert_eq!(lookup("vec!"), None);
+    }
+}
@@ -0,0 +1,546 @@
+//! The `.strxb` bytecode container shared by `strxc` and `strxvm`.
+//!
+//! A container holds one compiled contract. All integers are little-endian
+//! and strings are a `u32` byte length followed by UTF-8:
+//!
+//! ```text
+//! magic "STRX" | version u16 | host version u16 | contract name | section*
+//! section = id u8 | length u32 | payload
+//! ```
+//!
+//! The host version is the [`host::HOST_VERSION`] the contract was compiled
+//! against; a VM implementing an older host table refuses to deploy it.
+//! Sections appear in id order: constants (1), functions (2), storage (3),
+//! ABI (4) and, optionally, debug info (5), which maps instructions back to
+//! source lines and local slots to the variables they hold. Jump operands
+//! are instruction indices within the enclosing function; call, closure,
+//! emit and push operands index the function table, the ABI event list and
+//! the constant pool. Operands that name something the VM resolves at run
+//! time (methods, host functions, fields, variants, environment values,
+//! cast targets and revert messages) index a string constant; [`host`]
+//! lists the environment values and host functions every VM provides.
+//!
+//! This module is compiled into both binaries, so it only refers to its own
+//! submodules.
+
+mod asm;
+mod decode;
+mod encode;
+pub mod host;
+mod opcode;
+
+pub use asm::{assemble, disassemble, format_instruction, AsmError};
+pub use decode::{decode, DecodeError, DecodeErrorKind};
+pub use encode::encode;
+pub use opcode::{Instruction, Opcode, Operands};
+
+/// File magic, the first four bytes of every container
+pub const MAGIC: [u8; 4] = *b"STRX";
+
+/// Container format version written by [`encode`]; [`decode`] rejects others
+pub const VERSION: u16 = 3;
+
+/// Conventional file extension for containers
+pub const EXTENSION: &str = "strxb";
+
+/// Most local slots a function may declare; the VM allocates them per call
+pub const MAX_LOCALS: u32 = 1 << 16;
+
+const SECTION_CONSTANTS: u8 = 1;
+const SECTION_FUNCTIONS: u8 = 2;
+const SECTION_STORAGE: u8 = 3;
+const SECTION_ABI: u8 = 4;
+const SECTION_DEBUG: u8 = 5;
+
+/// A compiled contract
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    /// Version of the host function table the contract needs
+    pub host_version: u16,
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+    pub storage: Vec<StorageEntry>,
+    pub abi: Abi,
+    pub debug: Option<DebugInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    /// 256-bit unsigned integer, big-endian
+    U256([u8; 32]),
+    Address([u8; 20]),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl Constant {
+    /// A `U256` constant holding `value`
+    pub fn u256(value: u64) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[24..].copy_from_slice(&value.to_be_bytes());
+        Constant::U256(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// Number of arguments popped by `CALL`; they occupy the first locals
+    pub arity: u8,
+    /// Number of local slots, arguments included
+    pub locals: u32,
+    pub is_pure: bool,
+    pub code: Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageEntry {
+    pub name: String,
+    pub slot: u32,
+    pub ty: Type,
+}
+
+/// Value types as they appear in the storage layout and the ABI
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    U256,
+    Address,
+    Bool,
+    String,
+    Bytes,
+    Array(Box<Type>),
+    Map { key: Box<Type>, value: Box<Type> },
+    Tuple(Vec<Type>),
+    /// A struct, enum or library type such as `Result<u256, Error>`, kept
+    /// as its source spelling
+    Named(String),
+    Void,
+}
+
+/// Externally visible interface of the contract
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Abi {
+    pub functions: Vec<AbiFunction>,
+    pub events: Vec<AbiEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbiFunction {
+    /// Index into the function table
+    pub function: u32,
+    pub params: Vec<Param>,
+    pub returns: Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbiEvent {
+    pub name: String,
+    pub params: Vec<Param>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// Maps instructions back to source positions
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DebugInfo {
+    pub source: String,
+    /// Where each statement starts; a location covers the instructions up
+    /// to the function's next one
+    pub locations: Vec<DebugLocation>,
+    pub variables: Vec<DebugVariable>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLocation {
+    pub function: u32,
+    pub pc: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A named local: the slot holds the variable from instruction `start` up
+/// to, but not including, `end`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugVariable {
+    pub function: u32,
+    pub slot: u32,
+    pub name: String,
+    pub ty: Type,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl DebugInfo {
+    /// The location covering instruction `pc` of `function`
+    pub fn location(&self, function: u32, pc: u32) -> Option<&DebugLocation> {
+        self.locations.iter()
+            .filter(|l| l.function == function && l.pc <= pc)
+            .max_by_key(|l| l.pc)
+    }
+
+    /// The variables in scope at instruction `pc` of `function`, by slot
+    pub fn variables_at(&self, function: u32, pc: u32) -> impl Iterator<Item = &DebugVariable> {
+        self.variables.iter()
+            .filter(move |v| v.function == function && (v.start..v.end).contains(&pc))
+    }
+}
+
+/// The operand of `STRUCT`: the struct's name and the order its fields
+/// were pushed in, written `Name {a, b}`
+pub fn struct_layout(name: &str, fields: &[String]) -> String {
+    format!("{} {{{}}}", name, fields.join(", "))
+}
+
+/// Splits a [`struct_layout`] back into the name and field order
+pub fn parse_struct_layout(layout: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, fields) = layout.strip_suffix('}')?.split_once(" {")?;
+    let fields = fields.split(", ").filter(|field| !field.is_empty()).collect();
+    Some((name, fields))
+}
+
+/// Reads a container, or assembly when the file ends in `.asm`
+pub fn load(path: &std::path::Path) -> Result<Module, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let module = if path.extension().is_some_and(|ext| ext == "asm") {
+        let source = String::from_utf8(bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        assemble(&source).map_err(|e| e.to_string())
+    } else {
+        decode(&bytes).map_err(|e| e.to_string())
+    };
+    module.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+impl Default for Module {
+    fn default() -> Self {
+        Module::new("")
+    }
+}
+
+impl Module {
+    pub fn new(name: impl Into<String>) -> Self {
+        Module {
+            name: name.into(),
+            host_version: host::HOST_VERSION,
+            constants: Vec::new(),
+            functions: Vec::new(),
+            storage: Vec::new(),
+            abi: Abi::default(),
+            debug: None,
+        }
+    }
+
+    pub fn function_index(&self, name: &str) -> Option<u32> {
+        self.functions.iter().position(|f| f.name == name).map(|i| i as u32)
+    }
+
+    pub fn event_index(&self, name: &str) -> Option<u32> {
+        self.abi.events.iter().position(|e| e.name == name).map(|i| i as u32)
+    }
+
+    /// What `instruction` costs in this module: [`Instruction::gas_cost`]
+    /// plus, for `ENV` and `CALLH`, the gas of the host function it names
+    pub fn gas_cost(&self, instruction: &Instruction) -> u64 {
+        let host = match *instruction {
+            Instruction::Env(name) | Instruction::CallHost(name, _) => match self.constants.get(name as usize) {
+                Some(Constant::String(name)) => host::lookup(name).map_or(0, |(_, entry)| entry.gas),
+                _ => 0,
+            },
+            _ => 0,
+        };
+        instruction.gas_cost() + host
+    }
+
+    /// Checks every cross-reference in the module: operand indices, jump
+    /// targets, ABI entries and debug locations.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for function in &self.functions {
+            if !names.insert(function.name.as_str()) {
+                return Err(format!("duplicate function `{}`", function.name));
+            }
+            if function.locals > MAX_LOCALS {
+                return Err(format!(
+                    "function `{}` declares {} locals, more than the {} allowed",
+                    function.name, function.locals, MAX_LOCALS
+                ));
+            }
+            if (function.arity as u32) > function.locals {
+                return Err(format!(
+                    "function `{}` takes {} arguments but has only {} locals",
+                    function.name, function.arity, function.locals
+                ));
+            }
+            for (pc, instruction) in function.code.iter().enumerate() {
+                self.validate_instruction(function, instruction)
+                    .map_err(|msg| format!("{}+{}: {}", function.name, pc, msg))?;
+            }
+        }
+
+        let mut slots = std::collections::HashSet::new();
+        for entry in &self.storage {
+            if !slots.insert(entry.slot) {
+                return Err(format!("storage slot {} is assigned twice", entry.slot));
+            }
+        }
+
+        for entry in &self.abi.functions {
+            let function = self.functions.get(entry.function as usize).ok_or_else(|| {
+                format!("ABI refers to missing function {}", entry.function)
+            })?;
+            if entry.params.len() != function.arity as usize {
+                return Err(format!(
+                    "ABI for `{}` lists {} parameters but the function takes {}",
+                    function.name, entry.params.len(), function.arity
+                ));
+            }
+        }
+
+        if let Some(debug) = &self.debug {
+            for location in &debug.locations {
+                let in_range = self.functions.get(location.function as usize)
+                    .is_some_and(|f| (location.pc as usize) < f.code.len());
+                if !in_range {
+                    return Err(format!(
+                        "debug location {}+{} does not refer to an instruction",
+                        location.function, location.pc
+                    ));
+                }
+            }
+            for variable in &debug.variables {
+                let in_range = self.functions.get(variable.function as usize).is_some_and(|f| {
+                    variable.slot < f.locals
+                        && variable.start < variable.end
+                        && variable.end as usize <= f.code.len()
+                });
+                if !in_range {
+                    return Err(format!(
+                        "debug variable `{}` does not refer to a local of function {}",
+                        variable.name, variable.function
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_instruction(&self, function: &Function, instruction: &Instruction) -> Result<(), String> {
+        match *instruction {
+            Instruction::Push(index) if index as usize >= self.constants.len() => {
+                Err(format!("constant #{} out of range", index))
+            }
+            Instruction::Load(index) | Instruction::Store(index) if index >= function.locals => {
+                Err(format!("local {} out of range", index))
+            }
+            Instruction::SLoad(slot) | Instruction::SStore(slot)
+                if !self.storage.iter().any(|entry| entry.slot == slot) =>
+            {
+                Err(format!("storage slot {} is not in the layout", slot))
+            }
+            Instruction::Jump(target) | Instruction::JumpIf(target)
+                if target as usize >= function.code.len() =>
+            {
+                Err(format!("jump target {} is past the end of the function", target))
+            }
+            Instruction::Call(index, argc) => match self.functions.get(index as usize) {
+                None => Err(format!("function {} out of range", index)),
+                Some(callee) if callee.arity != argc => Err(format!(
+                    "`{}` takes {} arguments, called with {}",
+                    callee.name, callee.arity, argc
+                )),
+                Some(_) => Ok(()),
+            },
+            Instruction::Closure(index, captured) => match self.functions.get(index as usize) {
+                None => Err(format!("function {} out of range", index)),
+                Some(callee) if callee.arity < captured => Err(format!(
+                    "`{}` takes {} arguments but the closure captures {}",
+                    callee.name, callee.arity, captured
+                )),
+                Some(_) => Ok(()),
+            },
+            _ if instruction.opcode().names_constant() => {
+                let (index, _) = instruction.operand_values();
+                match self.constants.get(index as usize) {
+                    None => Err(format!("constant #{} out of range", index)),
+                    Some(Constant::String(name)) => match *instruction {
+                        Instruction::Struct(_, fields) => match parse_struct_layout(name) {
+                            Some((_, names)) if names.len() == fields as usize => Ok(()),
+                            _ => Err(format!("`{}` is not a layout of {} fields", name, fields)),
+                        },
+                        _ => Ok(()),
+                    },
+                    Some(_) => Err(format!("constant #{} is not a string", index)),
+                }
+            }
+            Instruction::Emit(index, argc) => match self.abi.events.get(index as usize) {
+                None => Err(format!("event {} out of range", index)),
+                Some(event) if event.params.len() != argc as usize => Err(format!(
+                    "event `{}` has {} fields, emitted with {}",
+                    event.name, event.params.len(), argc
+                )),
+                Some(_) => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A small module touching every section
+    pub(crate) fn sample_module() -> Module {
+        let mut module = Module::new("Counter");
+        module.constants = vec![
+            Constant::u256(1),
+            Constant::String("overflow".into()),
+            Constant::Address([0xab; 20]),
+            Constant::Bool(true),
+            Constant::Bytes(vec![0xde, 0xad]),
+        ];
+        module.storage = vec![
+            StorageEntry { name: "count".into(), slot: 0, ty: Type::U256 },
+            StorageEntry {
+                name: "owners".into(),
+                slot: 1,
+                ty: Type::Map { key: Box::new(Type::Address), value: Box::new(Type::Bool) },
+            },
+            StorageEntry {
+                name: "pending".into(),
+                slot: 2,
+                ty: Type::Tuple(vec![Type::U256, Type::Named("Option<address>".into())]),
+            },
+        ];
+        module.functions = vec![
+            Function {
+                name: "increment".into(),
+                arity: 1,
+                locals: 2,
+                is_pure: false,
+                code: vec![
+                    Instruction::SLoad(0),
+                    Instruction::Load(0),
+                    Instruction::Add,
+                    Instruction::Dup(0),
+                    Instruction::Store(1),
+                    Instruction::SStore(0),
+                    Instruction::Load(1),
+                    Instruction::Emit(0, 1),
+                    Instruction::Load(1),
+                    Instruction::Call(1, 1),
+                    Instruction::JumpIf(12),
+                    Instruction::Return,
+                    Instruction::Push(0),
+                    Instruction::Return,
+                ],
+            },
+            Function {
+                name: "is_zero".into(),
+                arity: 1,
+                locals: 1,
+                is_pure: true,
+                code: vec![
+                    Instruction::Load(0),
+                    Instruction::Push(0),
+                    Instruction::Lt,
+                    Instruction::Return,
+                ],
+            },
+        ];
+        module.abi = Abi {
+            functions: vec![AbiFunction {
+                function: 0,
+                params: vec![Param { name: "by".into(), ty: Type::U256 }],
+                returns: Some(Type::U256),
+            }],
+            events: vec![AbiEvent {
+                name: "Incremented".into(),
+                params: vec![Param { name: "value".into(), ty: Type::U256 }],
+            }],
+        };
+        module.debug = Some(DebugInfo {
+            source: "counter.strx".into(),
+            locations: vec![
+                DebugLocation { function: 0, pc: 0, line: 4, column: 9 },
+                DebugLocation { function: 1, pc: 3, line: 9, column: 9 },
+            ],
+            variables: vec![
+                DebugVariable { function: 0, slot: 0, name: "by".into(), ty: Type::U256, start: 0, end: 14 },
+                DebugVariable { function: 0, slot: 1, name: "next".into(), ty: Type::U256, start: 5, end: 14 },
+            ],
+        });
+        module
+    }
+
+    #[test]
+    fn test_sample_module_is_valid() {
+        assert_eq!(sample_module().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_references() {
+        let mut module = sample_module();
+        module.functions[0].code[10] = Instruction::JumpIf(14);
+        assert!(module.validate().unwrap_err().contains("jump target 14"));
+
+        let mut module = sample_module();
+        module.functions[0].code[9] = Instruction::Call(1, 2);
+        assert!(module.validate().unwrap_err().contains("called with 2"));
+
+        let mut module = sample_module();
+        module.functions[1].code[1] = Instruction::Push(5);
+        assert!(module.validate().unwrap_err().contains("constant #5"));
+
+        let mut module = sample_module();
+        module.functions[0].code[0] = Instruction::SLoad(7);
+        assert!(module.validate().unwrap_err().contains("storage slot 7"));
+
+        let mut module = sample_module();
+        module.functions[0].code[0] = Instruction::GetField(0);
+        assert!(module.validate().unwrap_err().contains("constant #0 is not a string"));
+
+        let mut module = sample_module();
+        module.functions[0].code[0] = Instruction::Struct(1, 2);
+        assert!(module.validate().unwrap_err().contains("layout of 2 fields"));
+
+        let mut module = sample_module();
+        module.functions[0].code[0] = Instruction::Closure(1, 2);
+        assert!(module.validate().unwrap_err().contains("captures 2"));
+
+        let mut module = sample_module();
+        module.functions[0].locals = 4_000_000_000;
+        assert!(module.validate().unwrap_err().contains("declares 4000000000 locals"));
+
+        let mut module = sample_module();
+        module.debug.as_mut().unwrap().variables[1].slot = 2;
+        assert!(module.validate().unwrap_err().contains("debug variable `next`"));
+    }
+
+    #[test]
+    fn test_debug_info_lookups() {
+        let module = sample_module();
+        let debug = module.debug.as_ref().unwrap();
+        assert_eq!(debug.location(0, 7).map(|l| l.line), Some(4));
+        assert_eq!(debug.location(1, 2), None);
+        let names: Vec<_> = debug.variables_at(0, 3).map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["by"]);
+        assert_eq!(debug.variables_at(0, 5).count(), 2);
+    }
+
+    #[test]
+    fn test_struct_layouts_round_trip() {
+        let fields = vec!["seller".to_string(), "price".to_string()];
+        let layout = struct_layout("Listing", &fields);
+        assert_eq!(layout, "Listing {seller, price}");
+        assert_eq!(parse_struct_layout(&layout), Some(("Listing", vec!["seller", "price"])));
+        assert_eq!(parse_struct_layout("Empty {}"), Some(("Empty", vec![])));
+        assert_eq!(parse_struct_layout("Listing"), None);
+    }
+}
@@ -0,0 +1,597 @@
+use std::fmt;
+
+/// Operand layout following an opcode byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    None,
+    /// One byte
+    U8,
+    /// One little-endian `u32`
+    U32,
+    /// A `u32` index followed by a one-byte argument count
+    U32U8,
+}
+
+impl Operands {
+    /// Encoded size in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Operands::None => 0,
+            Operands::U8 => 1,
+            Operands::U32 => 4,
+            Operands::U32U8 => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Opcode {
+    // Stack operations
+    Push = 0x01,
+    Pop = 0x02,
+    Dup = 0x03,
+    Swap = 0x04,
+
+    // Locals
+    Load = 0x10,
+    Store = 0x11,
+
+    // Arithmetic, comparison and logic
+    Add = 0x20,
+    Sub = 0x21,
+    Mul = 0x22,
+    Div = 0x23,
+    Mod = 0x24,
+    Eq = 0x25,
+    Lt = 0x26,
+    Gt = 0x27,
+    LtEq = 0x28,
+    GtEq = 0x29,
+    And = 0x2a,
+    Or = 0x2b,
+    Xor = 0x2c,
+    Not = 0x2d,
+
+    // Control flow
+    Jump = 0x30,
+    JumpIf = 0x31,
+    Return = 0x32,
+    Call = 0x33,
+    Revert = 0x34,
+    CallMethod = 0x35,
+    CallHost = 0x36,
+    CallIndirect = 0x37,
+    Closure = 0x38,
+
+    // Blockchain specific
+    SLoad = 0x40,
+    SStore = 0x41,
+    Emit = 0x42,
+    Balance = 0x43,
+    Transfer = 0x44,
+    CallContract = 0x45,
+    MapSlot = 0x47,
+    SLoadAt = 0x48,
+    SStoreAt = 0x49,
+    Env = 0x4a,
+
+    // Reentrancy guard
+    GuardEnter = 0x50,
+    GuardExit = 0x51,
+
+    // Actors
+    Send = 0x60,
+    Receive = 0x61,
+
+    // Resources
+    Acquire = 0x70,
+    Release = 0x71,
+
+    // Permissions
+    CheckPermission = 0x80,
+    GrantPermission = 0x81,
+    RevokePermission = 0x82,
+
+    // Aggregates
+    Tuple = 0x90,
+    Extract = 0x91,
+    Struct = 0x92,
+    Variant = 0x93,
+    IsVariant = 0x94,
+    GetField = 0x95,
+    SetField = 0x96,
+    Index = 0x97,
+    SetIndex = 0x98,
+    Cast = 0x99,
+    Array = 0x9a,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 59] = [
+        Opcode::Push, Opcode::Pop, Opcode::Dup, Opcode::Swap,
+        Opcode::Load, Opcode::Store,
+        Opcode::Add, Opcode::Sub, Opcode::Mul, Opcode::Div, Opcode::Mod,
+        Opcode::Eq, Opcode::Lt, Opcode::Gt, Opcode::LtEq, Opcode::GtEq,
+        Opcode::And, Opcode::Or, Opcode::Xor, Opcode::Not,
+        Opcode::Jump, Opcode::JumpIf, Opcode::Return, Opcode::Call,
+        Opcode::Revert, Opcode::CallMethod, Opcode::CallHost, Opcode::CallIndirect, Opcode::Closure,
+        Opcode::SLoad, Opcode::SStore, Opcode::Emit, Opcode::Balance,
+        Opcode::Transfer, Opcode::CallContract,
+        Opcode::MapSlot, Opcode::SLoadAt, Opcode::SStoreAt, Opcode::Env,
+        Opcode::GuardEnter, Opcode::GuardExit,
+        Opcode::Send, Opcode::Receive,
+        Opcode::Acquire, Opcode::Release,
+        Opcode::CheckPermission, Opcode::GrantPermission, Opcode::RevokePermission,
+        Opcode::Tuple, Opcode::Extract, Opcode::Struct, Opcode::Variant, Opcode::IsVariant,
+        Opcode::GetField, Opcode::SetField, Opcode::Index, Opcode::SetIndex, Opcode::Cast, Opcode::Array,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        Opcode::ALL.iter().copied().find(|op| *op as u8 == byte)
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Opcode::ALL.iter().copied().find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Push => "PUSH",
+            Opcode::Pop => "POP",
+            Opcode::Dup => "DUP",
+            Opcode::Swap => "SWAP",
+            Opcode::Load => "LOAD",
+            Opcode::Store => "STORE",
+            Opcode::Add => "ADD",
+            Opcode::Sub => "SUB",
+            Opcode::Mul => "MUL",
+            Opcode::Div => "DIV",
+            Opcode::Mod => "MOD",
+            Opcode::Eq => "EQ",
+            Opcode::Lt => "LT",
+            Opcode::Gt => "GT",
+            Opcode::LtEq => "LTE",
+            Opcode::GtEq => "GTE",
+            Opcode::And => "AND",
+            Opcode::Or => "OR",
+            Opcode::Xor => "XOR",
+            Opcode::Not => "NOT",
+            Opcode::Jump => "JUMP",
+            Opcode::JumpIf => "JUMPI",
+            Opcode::Return => "RET",
+            Opcode::Call => "CALL",
+            Opcode::Revert => "REVERT",
+            Opcode::CallMethod => "CALLM",
+            Opcode::CallHost => "CALLH",
+            Opcode::CallIndirect => "CALLI",
+            Opcode::Closure => "CLOSURE",
+            Opcode::SLoad => "SLOAD",
+            Opcode::SStore => "SSTORE",
+            Opcode::Emit => "EMIT",
+            Opcode::Balance => "BALANCE",
+            Opcode::Transfer => "TRANSFER",
+            Opcode::CallContract => "CALLC",
+            Opcode::MapSlot => "MAPSLOT",
+            Opcode::SLoadAt => "SLOADAT",
+            Opcode::SStoreAt => "SSTOREAT",
+            Opcode::Env => "ENV",
+            Opcode::GuardEnter => "GUARD",
+            Opcode::GuardExit => "UNGUARD",
+            Opcode::Send => "SEND",
+            Opcode::Receive => "RECV",
+            Opcode::Acquire => "ACQUIRE",
+            Opcode::Release => "RELEASE",
+            Opcode::CheckPermission => "CHECKPERM",
+            Opcode::GrantPermission => "GRANTPERM",
+            Opcode::RevokePermission => "REVOKEPERM",
+            Opcode::Tuple => "TUPLE",
+            Opcode::Extract => "EXTRACT",
+            Opcode::Struct => "STRUCT",
+            Opcode::Variant => "VARIANT",
+            Opcode::IsVariant => "ISVARIANT",
+            Opcode::GetField => "GETFIELD",
+            Opcode::SetField => "SETFIELD",
+            Opcode::Index => "INDEX",
+            Opcode::SetIndex => "SETINDEX",
+            Opcode::Cast => "CAST",
+            Opcode::Array => "ARRAY",
+        }
+    }
+
+    pub fn operands(self) -> Operands {
+        match self {
+            Opcode::Dup | Opcode::Swap | Opcode::CallIndirect | Opcode::Tuple | Opcode::Array | Opcode::Extract => Operands::U8,
+            Opcode::Push
+            | Opcode::Revert
+            | Opcode::Env
+            | Opcode::IsVariant
+            | Opcode::GetField
+            | Opcode::SetField
+            | Opcode::Cast
+            | Opcode::Load
+            | Opcode::Store
+            | Opcode::Jump
+            | Opcode::JumpIf
+            | Opcode::SLoad
+            | Opcode::SStore
+            | Opcode::Send
+            | Opcode::Acquire
+            | Opcode::Release
+            | Opcode::CheckPermission
+            | Opcode::GrantPermission
+            | Opcode::RevokePermission => Operands::U32,
+            Opcode::Call
+            | Opcode::Emit
+            | Opcode::CallMethod
+            | Opcode::CallContract
+            | Opcode::CallHost
+            | Opcode::Closure
+            | Opcode::Struct
+            | Opcode::Variant => Operands::U32U8,
+            _ => Operands::None,
+        }
+    }
+
+    /// Whether the `u32` operand indexes a string constant naming something
+    /// (a method, message handler, permission, resource kind, host
+    /// function, field, variant, environment value, type or revert message)
+    /// rather than the function table or a code position
+    pub fn names_constant(self) -> bool {
+        matches!(
+            self,
+            Opcode::Revert
+                | Opcode::CallMethod
+                | Opcode::CallContract
+                | Opcode::Send
+                | Opcode::Acquire
+                | Opcode::Release
+                | Opcode::CheckPermission
+                | Opcode::GrantPermission
+                | Opcode::RevokePermission
+                | Opcode::CallHost
+                | Opcode::Env
+                | Opcode::Struct
+                | Opcode::Variant
+                | Opcode::IsVariant
+                | Opcode::GetField
+                | Opcode::SetField
+                | Opcode::Cast
+        )
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+/// A decoded instruction with its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Push(u32),
+    Pop,
+    Dup(u8),
+    Swap(u8),
+
+    Load(u32),
+    Store(u32),
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    And,
+    Or,
+    Xor,
+    Not,
+
+    Jump(u32),
+    JumpIf(u32),
+    Return,
+    Call(u32, u8),
+    /// Aborts with the message in the given string constant
+    Revert(u32),
+    /// `[receiver, args..] -> [result]`; the constant names the method
+    CallMethod(u32, u8),
+    /// `[args..] -> [result]`; the constant names a function the VM provides
+    CallHost(u32, u8),
+    /// `[closure, args..] -> [result]`
+    CallIndirect(u8),
+    /// `[captured..] -> [closure]` over the given function
+    Closure(u32, u8),
+
+    SLoad(u32),
+    SStore(u32),
+    Emit(u32, u8),
+    /// `[account] -> [balance]`
+    Balance,
+    /// `[to, amount] -> []` from the running contract's balance
+    Transfer,
+    /// `[address, args..] -> [result]`; the constant names an exported
+    /// function of the contract at `address`
+    CallContract(u32, u8),
+    /// `[slot, key] -> [slot']`
+    MapSlot,
+    /// `[slot] -> [value]`
+    SLoadAt,
+    /// `[slot, value] -> []`
+    SStoreAt,
+    /// `[] -> [value]` of the environment value named by the constant
+    Env(u32),
+
+    GuardEnter,
+    GuardExit,
+
+    Send(u32),
+    Receive,
+
+    /// `[value] -> [resource]`, a new resource of the kind named by the
+    /// constant that the running function holds
+    Acquire(u32),
+    /// `[resource] -> []`, ending a resource of the kind named by the
+    /// constant that the running function holds
+    Release(u32),
+
+    CheckPermission(u32),
+    GrantPermission(u32),
+    RevokePermission(u32),
+
+    /// `[a, b..] -> [(a, b..)]`
+    Tuple(u8),
+    /// `[a, b..] -> [[a, b..]]`
+    Array(u8),
+    /// `[aggregate] -> [element]` of a tuple or a variant's payload
+    Extract(u8),
+    /// `[fields..] -> [struct]`; the constant is a [`struct_layout`](super::struct_layout)
+    Struct(u32, u8),
+    /// `[payload..] -> [variant]`; the constant is the variant's path
+    Variant(u32, u8),
+    /// `[value] -> [bool]`
+    IsVariant(u32),
+    /// `[struct] -> [field]`
+    GetField(u32),
+    /// `[struct, value] -> [struct']`
+    SetField(u32),
+    /// `[collection, index] -> [element]`
+    Index,
+    /// `[collection, index, element] -> [collection']`
+    SetIndex,
+    /// `[value] -> [value']` converted to the type spelled by the constant
+    Cast(u32),
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Instruction::Push(_) => Opcode::Push,
+            Instruction::Pop => Opcode::Pop,
+            Instruction::Dup(_) => Opcode::Dup,
+            Instruction::Swap(_) => Opcode::Swap,
+            Instruction::Load(_) => Opcode::Load,
+            Instruction::Store(_) => Opcode::Store,
+            Instruction::Add => Opcode::Add,
+            Instruction::Sub => Opcode::Sub,
+            Instruction::Mul => Opcode::Mul,
+            Instruction::Div => Opcode::Div,
+            Instruction::Mod => Opcode::Mod,
+            Instruction::Eq => Opcode::Eq,
+            Instruction::Lt => Opcode::Lt,
+            Instruction::Gt => Opcode::Gt,
+            Instruction::LtEq => Opcode::LtEq,
+            Instruction::GtEq => Opcode::GtEq,
+            Instruction::And => Opcode::And,
+            Instruction::Or => Opcode::Or,
+            Instruction::Xor => Opcode::Xor,
+            Instruction::Not => Opcode::Not,
+            Instruction::Jump(_) => Opcode::Jump,
+            Instruction::JumpIf(_) => Opcode::JumpIf,
+            Instruction::Return => Opcode::Return,
+            Instruction::Call(..) => Opcode::Call,
+            Instruction::Revert(_) => Opcode::Revert,
+            Instruction::CallMethod(..) => Opcode::CallMethod,
+            Instruction::CallHost(..) => Opcode::CallHost,
+            Instruction::CallIndirect(_) => Opcode::CallIndirect,
+            Instruction::Closure(..) => Opcode::Closure,
+            Instruction::SLoad(_) => Opcode::SLoad,
+            Instruction::SStore(_) => Opcode::SStore,
+            Instruction::Emit(..) => Opcode::Emit,
+            Instruction::Balance => Opcode::Balance,
+            Instruction::Transfer => Opcode::Transfer,
+            Instruction::CallContract(..) => Opcode::CallContract,
+            Instruction::MapSlot => Opcode::MapSlot,
+            Instruction::SLoadAt => Opcode::SLoadAt,
+            Instruction::SStoreAt => Opcode::SStoreAt,
+            Instruction::Env(_) => Opcode::Env,
+            Instruction::GuardEnter => Opcode::GuardEnter,
+            Instruction::GuardExit => Opcode::GuardExit,
+            Instruction::Send(_) => Opcode::Send,
+            Instruction::Receive => Opcode::Receive,
+            Instruction::Acquire(_) => Opcode::Acquire,
+            Instruction::Release(_) => Opcode::Release,
+            Instruction::CheckPermission(_) => Opcode::CheckPermission,
+            Instruction::GrantPermission(_) => Opcode::GrantPermission,
+            Instruction::RevokePermission(_) => Opcode::RevokePermission,
+            Instruction::Tuple(_) => Opcode::Tuple,
+            Instruction::Array(_) => Opcode::Array,
+            Instruction::Extract(_) => Opcode::Extract,
+            Instruction::Struct(..) => Opcode::Struct,
+            Instruction::Variant(..) => Opcode::Variant,
+            Instruction::IsVariant(_) => Opcode::IsVariant,
+            Instruction::GetField(_) => Opcode::GetField,
+            Instruction::SetField(_) => Opcode::SetField,
+            Instruction::Index => Opcode::Index,
+            Instruction::SetIndex => Opcode::SetIndex,
+            Instruction::Cast(_) => Opcode::Cast,
+        }
+    }
+
+    /// Raw operand values; unused positions are zero
+    pub(super) fn operand_values(&self) -> (u32, u8) {
+        match *self {
+            Instruction::Dup(n)
+            | Instruction::Swap(n)
+            | Instruction::CallIndirect(n)
+            | Instruction::Tuple(n)
+            | Instruction::Array(n)
+            | Instruction::Extract(n) => (n as u32, 0),
+            Instruction::Push(a)
+            | Instruction::Revert(a)
+            | Instruction::Env(a)
+            | Instruction::IsVariant(a)
+            | Instruction::GetField(a)
+            | Instruction::SetField(a)
+            | Instruction::Cast(a)
+            | Instruction::Load(a)
+            | Instruction::Store(a)
+            | Instruction::Jump(a)
+            | Instruction::JumpIf(a)
+            | Instruction::SLoad(a)
+            | Instruction::SStore(a)
+            | Instruction::Send(a)
+            | Instruction::Acquire(a)
+            | Instruction::Release(a)
+            | Instruction::CheckPermission(a)
+            | Instruction::GrantPermission(a)
+            | Instruction::RevokePermission(a) => (a, 0),
+            Instruction::Call(a, b)
+            | Instruction::Emit(a, b)
+            | Instruction::CallMethod(a, b)
+            | Instruction::CallContract(a, b)
+            | Instruction::CallHost(a, b)
+            | Instruction::Closure(a, b)
+            | Instruction::Struct(a, b)
+            | Instruction::Variant(a, b) => (a, b),
+            _ => (0, 0),
+        }
+    }
+
+    /// Rebuilds an instruction from its opcode and raw operand values
+    pub(super) fn from_parts(opcode: Opcode, a: u32, b: u8) -> Instruction {
+        match opcode {
+            Opcode::Push => Instruction::Push(a),
+            Opcode::Pop => Instruction::Pop,
+            Opcode::Dup => Instruction::Dup(a as u8),
+            Opcode::Swap => Instruction::Swap(a as u8),
+            Opcode::Load => Instruction::Load(a),
+            Opcode::Store => Instruction::Store(a),
+            Opcode::Add => Instruction::Add,
+            Opcode::Sub => Instruction::Sub,
+            Opcode::Mul => Instruction::Mul,
+            Opcode::Div => Instruction::Div,
+            Opcode::Mod => Instruction::Mod,
+            Opcode::Eq => Instruction::Eq,
+            Opcode::Lt => Instruction::Lt,
+            Opcode::Gt => Instruction::Gt,
+            Opcode::LtEq => Instruction::LtEq,
+            Opcode::GtEq => Instruction::GtEq,
+            Opcode::And => Instruction::And,
+            Opcode::Or => Instruction::Or,
+            Opcode::Xor => Instruction::Xor,
+            Opcode::Not => Instruction::Not,
+            Opcode::Jump => Instruction::Jump(a),
+            Opcode::JumpIf => Instruction::JumpIf(a),
+            Opcode::Return => Instruction::Return,
+            Opcode::Call => Instruction::Call(a, b),
+            Opcode::Revert => Instruction::Revert(a),
+            Opcode::CallMethod => Instruction::CallMethod(a, b),
+            Opcode::CallHost => Instruction::CallHost(a, b),
+            Opcode::CallIndirect => Instruction::CallIndirect(a as u8),
+            Opcode::Closure => Instruction::Closure(a, b),
+            Opcode::SLoad => Instruction::SLoad(a),
+            Opcode::SStore => Instruction::SStore(a),
+            Opcode::Emit => Instruction::Emit(a, b),
+            Opcode::Balance => Instruction::Balance,
+            Opcode::Transfer => Instruction::Transfer,
+            Opcode::CallContract => Instruction::CallContract(a, b),
+            Opcode::MapSlot => Instruction::MapSlot,
+            Opcode::SLoadAt => Instruction::SLoadAt,
+            Opcode::SStoreAt => Instruction::SStoreAt,
+            Opcode::Env => Instruction::Env(a),
+            Opcode::GuardEnter => Instruction::GuardEnter,
+            Opcode::GuardExit => Instruction::GuardExit,
+            Opcode::Send => Instruction::Send(a),
+            Opcode::Receive => Instruction::Receive,
+            Opcode::Acquire => Instruction::Acquire(a),
+            Opcode::Release => Instruction::Release(a),
+            Opcode::CheckPermission => Instruction::CheckPermission(a),
+            Opcode::GrantPermission => Instruction::GrantPermission(a),
+            Opcode::RevokePermission => Instruction::RevokePermission(a),
+            Opcode::Tuple => Instruction::Tuple(a as u8),
+            Opcode::Array => Instruction::Array(a as u8),
+            Opcode::Extract => Instruction::Extract(a as u8),
+            Opcode::Struct => Instruction::Struct(a, b),
+            Opcode::Variant => Instruction::Variant(a, b),
+            Opcode::IsVariant => Instruction::IsVariant(a),
+            Opcode::GetField => Instruction::GetField(a),
+            Opcode::SetField => Instruction::SetField(a),
+            Opcode::Index => Instruction::Index,
+            Opcode::SetIndex => Instruction::SetIndex,
+            Opcode::Cast => Instruction::Cast(a),
+        }
+    }
+
+    /// Encoded size in bytes, opcode included
+    pub fn size(&self) -> usize {
+        1 + self.opcode().operands().size()
+    }
+
+    /// Gas charged before the instruction runs. Instructions that come from
+    /// the IR cost what strxc's static estimate charges for them.
+    pub fn gas_cost(&self) -> u64 {
+        match *self {
+            Instruction::Load(_) | Instruction::Store(_) => 3,
+            Instruction::Mul | Instruction::Div | Instruction::Mod => 5,
+            Instruction::Call(..) | Instruction::CallHost(..) | Instruction::CallIndirect(_) => 10,
+            Instruction::Balance => 20,
+            Instruction::MapSlot => 30,
+            Instruction::Send(_) => 50,
+            Instruction::CallMethod(..)
+            | Instruction::Emit(..)
+            | Instruction::Transfer
+            | Instruction::CallContract(..) => 100,
+            Instruction::SLoad(_) | Instruction::SLoadAt | Instruction::CheckPermission(_) => 200,
+            Instruction::SStore(_)
+            | Instruction::SStoreAt
+            | Instruction::GrantPermission(_)
+            | Instruction::RevokePermission(_) => 5000,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opcode_bytes_and_mnemonics_are_unique() {
+        for (i, a) in Opcode::ALL.iter().enumerate() {
+            for b in &Opcode::ALL[i + 1..] {
+                assert_ne!(*a as u8, *b as u8);
+                assert_ne!(a.mnemonic(), b.mnemonic());
+            }
+            assert_eq!(Opcode::from_byte(*a as u8), Some(*a));
+            assert_eq!(Opcode::from_mnemonic(&a.mnemonic().to_lowercase()), Some(*a));
+        }
+        assert_eq!(Opcode::from_byte(0x00), None);
+        assert_eq!(Opcode::from_byte(0xff), None);
+    }
+
+    #[test]
+    fn test_parts_round_trip() {
+        for opcode in Opcode::ALL {
+            let instruction = Instruction::from_parts(opcode, 7, 2);
+            assert_eq!(instruction.opcode(), opcode);
+            let (a, b) = instruction.operand_values();
+            assert_eq!(Instruction::from_parts(opcode, a, b), instruction);
+        }
+    }
+}
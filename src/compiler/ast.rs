@@ -1,29 +1,112 @@
-use std::collections::HashMap;
+use crate::span::Span;
 
 #[derive(Debug, Clone)]
 pub struct Program {
+    pub uses: Vec<UseDecl>,
     pub contracts: Vec<Contract>,
+    pub interfaces: Vec<Interface>,
+    pub modules: Vec<Module>,
+}
+
+/// `use std::math::{sqrt, min};` has path `[std, math]` and names
+/// `[sqrt, min]`; `use super::*;` has path `[super]` and `glob` set
+#[derive(Debug, Clone)]
+pub struct UseDecl {
+    pub path: Vec<String>,
+    pub names: Vec<String>,
+    pub glob: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Contract {
     pub name: String,
+    pub span: Span,
     pub state_vars: Vec<StateVar>,
     pub events: Vec<Event>,
     pub functions: Vec<Function>,
+    pub type_aliases: Vec<TypeAlias>,
+    pub structs: Vec<StructDecl>,
+    pub enums: Vec<EnumDecl>,
 }
 
 #[derive(Debug, Clone)]
 pub struct StateVar {
     pub name: String,
     pub type_info: Type,
-    pub visibility: Visibility,
+    pub initializer: Option<Expression>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeAlias {
+    pub name: String,
+    pub type_info: Type,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDecl {
+    pub name: String,
+    pub fields: Vec<Parameter>,
+    /// Declared with `resource`: values must be moved exactly once
+    pub is_resource: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDecl {
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub discriminant: Option<Expression>,
+    pub span: Span,
+}
+
+/// Functions a contract expects from another contract
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub functions: Vec<InterfaceFunction>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceFunction {
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Type>,
+    pub span: Span,
+}
+
+/// A `mod` block, such as the `#[cfg(test)] mod tests` next to a contract
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub uses: Vec<UseDecl>,
+    pub functions: Vec<Function>,
+    pub span: Span,
+}
+
+/// `#[name]` or `#[name(arguments)]`, e.g. `#[cfg(test)]`
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: String,
+    pub arguments: Vec<String>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Event {
     pub name: String,
     pub parameters: Vec<Parameter>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -33,16 +116,20 @@ pub struct Function {
     pub return_type: Option<Type>,
     pub body: Block,
     pub modifiers: Vec<Modifier>,
+    pub attributes: Vec<Attribute>,
     pub is_pure: bool,
+    pub is_mutable: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Parameter {
     pub name: String,
     pub type_info: Type,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Address,
     U256,
@@ -51,25 +138,31 @@ pub enum Type {
     Map { key_type: Box<Type>, value_type: Box<Type> },
     Array(Box<Type>),
     Result { ok_type: Box<Type>, err_type: Box<Type> },
+    /// `(A, B)`; the unit type `()` is the empty tuple
+    Tuple(Vec<Type>),
+    /// Any other generic type such as `Option<T>` or `Set<T>`
+    Generic { name: String, arguments: Vec<Type> },
     Custom(String),
+    /// Type of an expression that failed to parse; compatible with every type
+    Error,
 }
 
 #[derive(Debug, Clone)]
-pub enum Visibility {
-    Public,
-    Private,
-    Internal,
+pub struct Block {
+    pub statements: Vec<Statement>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
-pub struct Block {
-    pub statements: Vec<Statement>,
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub enum StatementKind {
     Let {
-        name: String,
+        pattern: Pattern,
         type_info: Option<Type>,
         value: Expression,
     },
@@ -90,6 +183,11 @@ pub enum Statement {
         condition: Expression,
         block: Block,
     },
+    For {
+        pattern: Pattern,
+        iterable: Expression,
+        block: Block,
+    },
     Return(Option<Expression>),
     Emit {
         event: String,
@@ -99,10 +197,24 @@ pub enum Statement {
         condition: Expression,
         message: String,
     },
+    /// Any other expression used as a statement. Without a semicolon at the
+    /// end of a block it is the block's value.
+    Expression {
+        expression: Expression,
+        has_semicolon: bool,
+    },
+    /// Statement that failed to parse
+    Error,
 }
 
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
     Identifier(String),
     NumberLiteral(String),
     StringLiteral(String),
@@ -129,6 +241,85 @@ pub enum Expression {
         array: Box<Expression>,
         index: Box<Expression>,
     },
+    /// `Error::InvalidToken`, `Duration::from_days`
+    Path(Vec<String>),
+    /// `(a, b)`; `()` is the empty tuple
+    Tuple(Vec<Expression>),
+    StructLiteral {
+        name: String,
+        fields: Vec<(String, Expression)>,
+    },
+    /// `vec![..]`, `format!(..)`, `assert_eq!(..)`
+    MacroCall {
+        name: String,
+        arguments: Vec<Expression>,
+    },
+    Closure {
+        parameters: Vec<Pattern>,
+        body: Box<Expression>,
+    },
+    /// `expr?`
+    Try(Box<Expression>),
+    Cast {
+        expression: Box<Expression>,
+        type_info: Type,
+    },
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+    },
+    /// `if` in expression position; `else if` is sugar for an else block
+    /// holding the nested `if` as its value
+    If {
+        condition: Box<Expression>,
+        then_block: Block,
+        else_block: Option<Block>,
+    },
+    /// `let pattern = value` as the condition of `if let`
+    Let {
+        pattern: Pattern,
+        value: Box<Expression>,
+    },
+    Match {
+        scrutinee: Box<Expression>,
+        arms: Vec<MatchArm>,
+    },
+    Block(Block),
+    /// Assignment in expression position, e.g. a match arm body; compound
+    /// assignments are desugared as in statements
+    Assign {
+        target: Box<Expression>,
+        value: Box<Expression>,
+    },
+    /// Expression that failed to parse
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expression,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    Identifier { name: String, mutable: bool },
+    Wildcard,
+    Tuple(Vec<Pattern>),
+    /// `VoteType::Against`
+    Path(Vec<String>),
+    /// `Some(x)`, `Ok((_, amount))`
+    TupleStruct { path: Vec<String>, fields: Vec<Pattern> },
+    /// `&id`
+    Reference(Box<Pattern>),
+    Literal(Box<Expression>),
 }
 
 #[derive(Debug, Clone)]
@@ -151,6 +342,9 @@ pub enum BinaryOp {
 pub enum UnaryOp {
     Not,
     Neg,
+    Deref,
+    Ref,
+    RefMut,
 }
 
 #[derive(Debug, Clone)]
@@ -164,7 +358,12 @@ pub enum Modifier {
 // Helper functions for AST construction
 impl Program {
     pub fn new() -> Self {
-        Program { contracts: Vec::new() }
+        Program {
+            uses: Vec::new(),
+            contracts: Vec::new(),
+            interfaces: Vec::new(),
+            modules: Vec::new(),
+        }
     }
 
     pub fn add_contract(&mut self, contract: Contract) {
@@ -173,22 +372,44 @@ impl Program {
 }
 
 impl Contract {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, span: Span) -> Self {
         Contract {
             name,
+            span,
             state_vars: Vec::new(),
             events: Vec::new(),
             functions: Vec::new(),
+            type_aliases: Vec::new(),
+            structs: Vec::new(),
+            enums: Vec::new(),
         }
     }
 }
 
 impl Block {
-    pub fn new() -> Self {
-        Block { statements: Vec::new() }
+    pub fn new(span: Span) -> Self {
+        Block { statements: Vec::new(), span }
     }
 
     pub fn add_statement(&mut self, statement: Statement) {
         self.statements.push(statement);
     }
-} 
\ No newline at end of file
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement { kind, span }
+    }
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Pattern { kind, span }
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use crate::bytecode::{
+    self, AbiEvent, AbiFunction, Constant, DebugInfo, DebugLocation, DebugVariable, Function,
+    Instruction, Module, Param, StorageEntry,
+};
+use crate::diagnostics::SourceMap;
+use crate::ir;
+
+/// Translates each IR contract into a `.strxb` module, resolving labels to
+/// instruction indices and names to table indices.
+pub fn generate(program: &ir::Program) -> Result<Vec<Module>, String> {
+    program.contracts.iter().map(|contract| generate_contract(contract, None)).collect()
+}
+
+/// Like [`generate`], and turns the debug marks of a program lowered with
+/// [`ir::lower_with_debug_info`] into each module's debug section, with
+/// positions resolved through `sources`
+pub fn generate_with_debug_info(program: &ir::Program, sources: &SourceMap) -> Result<Vec<Module>, String> {
+    program.contracts.iter().map(|contract| generate_contract(contract, Some(sources))).collect()
+}
+
+fn generate_contract(contract: &ir::Contract, sources: Option<&SourceMap>) -> Result<Module, String> {
+    let mut module = Module::new(contract.name.clone());
+
+    module.storage = contract.storage.iter()
+        .map(|slot| Ok(StorageEntry {
+            name: slot.name.clone(),
+            slot: slot.slot,
+            ty: convert_type(&slot.ty)?,
+        }))
+        .collect::<Result<_, String>>()
+        .map_err(|msg| format!("{}: {}", contract.name, msg))?;
+    module.abi.events = contract.events.iter()
+        .map(|event| Ok(AbiEvent {
+            name: event.name.clone(),
+            params: convert_params(&event.params)?,
+        }))
+        .collect::<Result<_, String>>()
+        .map_err(|msg| format!("{}: {}", contract.name, msg))?;
+
+    // Register every function first so calls can refer forward
+    for function in &contract.functions {
+        module.functions.push(Function {
+            name: function.name.clone(),
+            arity: function.params.len() as u8,
+            locals: function.locals.iter()
+                .map(|local| local.index + 1)
+                .max()
+                .unwrap_or(0)
+                .max(function.params.len() as u32),
+            is_pure: function.is_pure,
+            code: Vec::new(),
+        });
+    }
+
+    let mut constants = ConstantPool::default();
+    let mut debug = DebugInfo::default();
+    for (index, function) in contract.functions.iter().enumerate() {
+        let in_function = |msg| format!("{}::{}: {}", contract.name, function.name, msg);
+        let (code, marks) = translate_body(&module, &mut constants, function).map_err(in_function)?;
+        if let Some(sources) = sources {
+            record_debug_info(&mut debug, sources, index as u32, function, code.len() as u32, &marks);
+        }
+        module.functions[index].code = code;
+        // Lifted closures are only reachable through closure values
+        if function.name.contains("::") {
+            continue;
+        }
+        module.abi.functions.push(AbiFunction {
+            function: index as u32,
+            params: convert_params(&function.params).map_err(in_function)?,
+            returns: function.return_type.as_ref().map(convert_type).transpose().map_err(in_function)?,
+        });
+    }
+    module.constants = constants.constants;
+    if sources.is_some() {
+        module.debug = Some(debug);
+    }
+
+    module.validate()
+        .map_err(|msg| format!("{}: {}", contract.name, msg))?;
+    Ok(module)
+}
+
+#[derive(Default)]
+struct ConstantPool {
+    constants: Vec<Constant>,
+}
+
+impl ConstantPool {
+    /// Index of `constant`, adding it on first use
+    fn intern(&mut self, constant: Constant) -> u32 {
+        match self.constants.iter().position(|c| *c == constant) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(constant);
+                (self.constants.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Index of the string constant `name`
+    fn name(&mut self, name: &str) -> u32 {
+        self.intern(Constant::String(name.to_string()))
+    }
+}
+
+/// Where a debug mark sits: the index of the instruction after it
+type PlacedMark<'a> = (u32, &'a ir::DebugMark);
+
+fn translate_body<'f>(
+    module: &Module,
+    constants: &mut ConstantPool,
+    function: &'f ir::Function,
+) -> Result<(Vec<Instruction>, Vec<PlacedMark<'f>>), String> {
+    // Labels are positions, not instructions; guard ends also emit an exit
+    let guard_ends: Vec<&ir::Label> = function.body.iter()
+        .filter_map(|instruction| match instruction {
+            ir::Instruction::NoReentry(_, end) => Some(end),
+            _ => None,
+        })
+        .collect();
+
+    let mut labels = HashMap::new();
+    let mut pc = 0u32;
+    for instruction in &function.body {
+        match instruction {
+            ir::Instruction::Label(label) => {
+                labels.insert(label, pc);
+                if guard_ends.contains(&label) {
+                    pc += 1;
+                }
+            }
+            ir::Instruction::Debug(_) => {}
+            _ => pc += 1,
+        }
+    }
+    let target = |label: &ir::Label| {
+        labels.get(label).copied()
+            .ok_or_else(|| format!("jump to undefined label `{}`", label.0))
+    };
+
+    let mut code = Vec::with_capacity(pc as usize);
+    let mut marks = Vec::new();
+    for instruction in &function.body {
+        code.push(match instruction {
+            ir::Instruction::Push(value) => Instruction::Push(constants.intern(convert_value(value))),
+            ir::Instruction::Pop => Instruction::Pop,
+            ir::Instruction::Dup(n) => Instruction::Dup(*n),
+            ir::Instruction::Load(index) => Instruction::Load(*index),
+            ir::Instruction::Store(index) => Instruction::Store(*index),
+            ir::Instruction::SLoad(slot) => Instruction::SLoad(*slot),
+            ir::Instruction::SStore(slot) => Instruction::SStore(*slot),
+            ir::Instruction::Add => Instruction::Add,
+            ir::Instruction::Sub => Instruction::Sub,
+            ir::Instruction::Mul => Instruction::Mul,
+            ir::Instruction::Div => Instruction::Div,
+            ir::Instruction::Eq => Instruction::Eq,
+            ir::Instruction::Lt => Instruction::Lt,
+            ir::Instruction::Gt => Instruction::Gt,
+            ir::Instruction::LtEq => Instruction::LtEq,
+            ir::Instruction::GtEq => Instruction::GtEq,
+            ir::Instruction::Not => Instruction::Not,
+            ir::Instruction::Jump(label) => Instruction::Jump(target(label)?),
+            ir::Instruction::JumpIf(label) => Instruction::JumpIf(target(label)?),
+            ir::Instruction::Label(label) => {
+                if guard_ends.contains(&label) {
+                    Instruction::GuardExit
+                } else {
+                    continue;
+                }
+            }
+            // Anything the contract does not define is left for the VM to provide
+            ir::Instruction::Call(name, argc) => match module.function_index(name) {
+                Some(index) => Instruction::Call(index, *argc),
+                None => Instruction::CallHost(constants.name(name), *argc),
+            },
+            ir::Instruction::CallMethod(name, argc) => Instruction::CallMethod(constants.name(name), *argc),
+            ir::Instruction::CallIndirect(argc) => Instruction::CallIndirect(*argc),
+            ir::Instruction::Closure(name, captured) => {
+                let index = module.function_index(name)
+                    .ok_or_else(|| format!("closure over unknown function `{}`", name))?;
+                Instruction::Closure(index, *captured)
+            }
+            ir::Instruction::Return => Instruction::Return,
+            ir::Instruction::Revert(message) => Instruction::Revert(constants.name(message)),
+            ir::Instruction::MapSlot => Instruction::MapSlot,
+            ir::Instruction::SLoadAt => Instruction::SLoadAt,
+            ir::Instruction::SStoreAt => Instruction::SStoreAt,
+            ir::Instruction::Env(name) => Instruction::Env(constants.name(name)),
+            ir::Instruction::Tuple(n) => Instruction::Tuple(*n),
+            ir::Instruction::Array(n) => Instruction::Array(*n),
+            ir::Instruction::Extract(n) => Instruction::Extract(*n),
+            ir::Instruction::Struct(name, fields) => {
+                let layout = bytecode::struct_layout(name, fields);
+                Instruction::Struct(constants.name(&layout), fields.len() as u8)
+            }
+            ir::Instruction::Variant(path, n) => Instruction::Variant(constants.name(path), *n),
+            ir::Instruction::IsVariant(path) => Instruction::IsVariant(constants.name(path)),
+            ir::Instruction::GetField(name) => Instruction::GetField(constants.name(name)),
+            ir::Instruction::SetField(name) => Instruction::SetField(constants.name(name)),
+            ir::Instruction::Index => Instruction::Index,
+            ir::Instruction::SetIndex => Instruction::SetIndex,
+            ir::Instruction::Cast(ty) => Instruction::Cast(constants.name(&ty.to_string())),
+            ir::Instruction::EmitEvent(name, argc) => {
+                let index = module.event_index(name)
+                    .ok_or_else(|| format!("emit of unknown event `{}`", name))?;
+                Instruction::Emit(index, *argc)
+            }
+            ir::Instruction::NoReentry(..) => Instruction::GuardEnter,
+            ir::Instruction::Acquire(kind) => Instruction::Acquire(constants.name(kind)),
+            ir::Instruction::Release(kind) => Instruction::Release(constants.name(kind)),
+            ir::Instruction::Debug(mark) => {
+                marks.push((code.len() as u32, mark));
+                continue;
+            }
+        });
+    }
+
+    // Falling off the end returns, and labels at the end need an instruction to land on
+    let end = code.len() as u32;
+    if code.last() != Some(&Instruction::Return) || labels.values().any(|pc| *pc == end) {
+        code.push(Instruction::Return);
+    }
+    Ok((code, marks))
+}
+
+/// Adds the statement locations and variable scopes of function `index`,
+/// whose code is `len` instructions long, to `debug`
+fn record_debug_info(
+    debug: &mut DebugInfo,
+    sources: &SourceMap,
+    index: u32,
+    function: &ir::Function,
+    len: u32,
+    marks: &[PlacedMark],
+) {
+    let mut open: Vec<(u32, u32)> = Vec::new();
+    let close = |debug: &mut DebugInfo, slot: u32, start: u32, end: u32| {
+        let Some(local) = function.locals.iter().find(|local| local.index == slot) else {
+            return;
+        };
+        // Temporaries have no source name
+        if local.name.starts_with('$') || start >= end {
+            return;
+        }
+        debug.variables.push(DebugVariable {
+            function: index,
+            slot,
+            name: local.name.clone(),
+            ty: convert_type(&local.ty).unwrap_or_else(|_| bytecode::Type::Named(local.ty.to_string())),
+            start,
+            end,
+        });
+    };
+
+    for &(pc, mark) in marks {
+        match *mark {
+            ir::DebugMark::Statement(offset) => {
+                let Some((file, line, column)) = sources.locate(offset) else {
+                    continue;
+                };
+                if debug.source.is_empty() {
+                    debug.source = file.to_string();
+                }
+                // A statement without code of its own shares the next one's
+                let last = debug.locations.last();
+                if last.is_some_and(|l| l.function == index && l.pc == pc) {
+                    debug.locations.pop();
+                }
+                if pc < len {
+                    debug.locations.push(DebugLocation { function: index, pc, line: line as u32, column: column as u32 });
+                }
+            }
+            ir::DebugMark::Bind(slot) => open.push((slot, pc)),
+            ir::DebugMark::Unbind(slot) => {
+                if let Some(at) = open.iter().position(|(bound, _)| *bound == slot) {
+                    let (_, start) = open.remove(at);
+                    close(debug, slot, start, pc);
+                }
+            }
+        }
+    }
+    for (slot, start) in open {
+        close(debug, slot, start, len);
+    }
+    debug.variables.sort_by_key(|v| (v.function, v.slot));
+}
+
+fn convert_value(value: &ir::Value) -> Constant {
+    match value {
+        ir::Value::U256(n) => Constant::U256(n.to_be_bytes()),
+        ir::Value::Address(bytes) => Constant::Address(*bytes),
+        ir::Value::Bool(b) => Constant::Bool(*b),
+        ir::Value::String(s) => Constant::String(s.clone()),
+    }
+}
+
+fn convert_type(ty: &ir::Type) -> Result<bytecode::Type, String> {
+    Ok(match ty {
+        ir::Type::U256 => bytecode::Type::U256,
+        ir::Type::Address => bytecode::Type::Address,
+        ir::Type::Bool => bytecode::Type::Bool,
+        ir::Type::String => bytecode::Type::String,
+        ir::Type::Array(element) => bytecode::Type::Array(Box::new(convert_type(element)?)),
+        ir::Type::Map { key, value } => bytecode::Type::Map {
+            key: Box::new(convert_type(key)?),
+            value: Box::new(convert_type(value)?),
+        },
+        ir::Type::Void => bytecode::Type::Void,
+        ir::Type::Tuple(elements) if elements.is_empty() => bytecode::Type::Void,
+        ir::Type::Tuple(elements) => {
+            bytecode::Type::Tuple(elements.iter().map(convert_type).collect::<Result<_, _>>()?)
+        }
+        ir::Type::Result { .. } | ir::Type::Generic { .. } | ir::Type::Named(_) => {
+            bytecode::Type::Named(ty.to_string())
+        }
+        other => return Err(format!("type `{}` has no bytecode equivalent yet", other)),
+    })
+}
+
+fn convert_params(params: &[ir::Parameter]) -> Result<Vec<Param>, String> {
+    params.iter()
+        .map(|p| Ok(Param { name: p.name.clone(), ty: convert_type(&p.ty)? }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use crate::{lexer, optimizer, parser, type_checker};
+
+    fn compile(source: &str) -> Vec<Module> {
+        let tokens = lexer::tokenize(source).expect("lexing failed");
+        let ast = parser::parse(tokens).expect("parsing failed");
+        let typed = type_checker::check(ast).expect("type checking failed");
+        generate(&ir::lower(typed).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_generates_valid_module() {
+        let modules = compile(r#"
+            contract Counter {
+                state count: u256;
+                event Bumped(value: u256);
+
+                fn bump(by: u256) {
+                    if by > 100 {
+                        count = count + 100;
+                    } else {
+                        count = count + by;
+                    }
+                    emit Bumped(count);
+                }
+            }
+        "#);
+        assert_eq!(modules.len(), 1);
+        let module = &modules[0];
+        assert_eq!(module.name, "Counter");
+        assert_eq!(module.functions[0].arity, 1);
+        assert!(module.functions[0].code.contains(&Instruction::Load(0)));
+        assert!(module.functions[0].code.iter().any(|i| matches!(i, Instruction::JumpIf(_))));
+        assert_eq!(module.abi.functions[0].params[0].name, "by");
+        // Both literals 100 share one pool entry
+        assert_eq!(module.constants, vec![Constant::u256(100)]);
+    }
+
+    #[test]
+    fn test_artifact_survives_encode_and_assembly() {
+        let module = compile(r#"
+            contract Flag {
+                state set: bool;
+                fn flip(on: bool) { set = on; }
+            }
+        "#).remove(0);
+        let bytes = bytecode::encode(&module);
+        assert_eq!(bytecode::decode(&bytes), Ok(module.clone()));
+        assert_eq!(bytecode::assemble(&bytecode::disassemble(&module)), Ok(module));
+    }
+
+    #[test]
+    fn test_debug_info_maps_statements_and_variables() {
+        let source = "contract Wallet {
+    state balance: u256;
+
+    fn withdraw(amount: u256) {
+        let fee = amount / 100;
+        if fee > 0 {
+            let rest = amount - fee;
+            balance = balance - rest;
+        }
+        balance = balance - fee;
+    }
+}
+";
+        let mut sources = SourceMap::new();
+        sources.add_file("wallet.strx", source);
+        let tokens = lexer::tokenize(source).expect("lexing failed");
+        let typed = type_checker::check(parser::parse(tokens).expect("parsing failed")).expect("type checking failed");
+        let module = generate_with_debug_info(&ir::lower_with_debug_info(typed).unwrap(), &sources).unwrap().remove(0);
+        // Debug marks add no instructions
+        let typed = type_checker::check(parser::parse(lexer::tokenize(source).unwrap()).unwrap()).unwrap();
+        let plain = generate(&ir::lower(typed).unwrap()).unwrap().remove(0);
+        assert_eq!(plain.functions, module.functions);
+        assert_eq!(plain.debug, None);
+
+        let debug = module.debug.as_ref().unwrap();
+        assert_eq!(debug.source, "wallet.strx");
+        let lines: Vec<_> = debug.locations.iter().map(|l| (l.line, l.column)).collect();
+        assert_eq!(lines, [(5, 9), (6, 9), (7, 13), (8, 13), (10, 9)]);
+
+        let scope = |name: &str| {
+            let v = debug.variables.iter().find(|v| v.name == name).unwrap();
+            (v.slot, v.start, v.end)
+        };
+        let at_line = |line| debug.locations.iter().find(|l| l.line == line).unwrap().pc;
+        let len = module.functions[0].code.len() as u32;
+        assert_eq!(scope("amount"), (0, 0, len));
+        assert_eq!((scope("fee").0, scope("fee").1), (1, at_line(6)));
+        assert!(scope("fee").2 > at_line(10));
+        assert_eq!(scope("rest").1, at_line(8));
+        assert!(scope("rest").2 <= at_line(10));
+        assert_eq!(debug.variables.iter().find(|v| v.name == "fee").unwrap().ty, bytecode::Type::U256);
+
+        assert_eq!(bytecode::decode(&bytecode::encode(&module)), Ok(module.clone()));
+        assert_eq!(bytecode::assemble(&bytecode::disassemble(&module)), Ok(module));
+    }
+
+    #[test]
+    fn test_labels_resolve_past_guard_exits() {
+        let function = ir::Function {
+            name: "guarded".into(),
+            params: Vec::new(),
+            return_type: None,
+            body: vec![
+                ir::Instruction::NoReentry(ir::Label("start".into()), ir::Label("end".into())),
+                ir::Instruction::Jump(ir::Label("end".into())),
+                ir::Instruction::Label(ir::Label("end".into())),
+                ir::Instruction::Jump(ir::Label("out".into())),
+                ir::Instruction::Label(ir::Label("out".into())),
+                ir::Instruction::Return,
+            ],
+            locals: Vec::new(),
+            is_pure: false,
+        };
+        let module = Module::new("M");
+        let (code, _) = translate_body(&module, &mut ConstantPool::default(), &function).unwrap();
+        assert_eq!(code, vec![
+            Instruction::GuardEnter,
+            Instruction::Jump(2),
+            Instruction::GuardExit,
+            Instruction::Jump(4),
+            Instruction::Return,
+        ]);
+
+        let function = ir::Function {
+            body: vec![
+                ir::Instruction::Jump(ir::Label("end".into())),
+                ir::Instruction::Label(ir::Label("end".into())),
+            ],
+            ..function
+        };
+        let (code, _) = translate_body(&module, &mut ConstantPool::default(), &function).unwrap();
+        assert_eq!(code, vec![Instruction::Jump(1), Instruction::Return]);
+    }
+
+    /// Compares the assembly of every example, compiled as `strxc --target
+    /// bytecode` compiles it, with `tests/fixtures/bytecode`, which strxvm's
+    /// differential tests run. Run with `UPDATE_GOLDEN=1` to rewrite the
+    /// expected files after a deliberate change.
+    #[test]
+    fn test_examples_match_golden_bytecode() {
+        fn strx_files(dir: &Path, files: &mut Vec<PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    strx_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "strx") {
+                    files.push(path);
+                }
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let examples = root.join("examples");
+        let golden = root.join("tests").join("fixtures").join("bytecode");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut files = Vec::new();
+        strx_files(&examples, &mut files);
+        assert!(!files.is_empty());
+
+        let mut mismatches = Vec::new();
+        for path in files {
+            let tokens = lexer::tokenize(&std::fs::read_to_string(&path).unwrap()).expect("lexing failed");
+            let typed = type_checker::check(parser::parse(tokens).expect("parsing failed"))
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e.to_diagnostic().message));
+            let mut program = ir::lower(typed).unwrap();
+            // strxc's default level
+            optimizer::PassManager::new(2).run(&mut program, |_, _| {});
+            let modules = generate(&program).unwrap_or_else(|msg| panic!("{}: {}", path.display(), msg));
+            for module in &modules {
+                let bytes = bytecode::encode(module);
+                assert_eq!(bytecode::decode(&bytes).as_ref(), Ok(module));
+                let asm = bytecode::disassemble(module);
+                assert_eq!(bytecode::assemble(&asm).as_ref(), Ok(module));
+
+                let expected_path = golden
+                    .join(path.strip_prefix(&examples).unwrap())
+                    .with_extension(format!("{}.asm", module.name));
+                if update {
+                    std::fs::create_dir_all(expected_path.parent().unwrap()).unwrap();
+                    std::fs::write(&expected_path, &asm).unwrap();
+                } else if std::fs::read_to_string(&expected_path).ok().as_deref() != Some(asm.as_str()) {
+                    mismatches.push(expected_path.display().to_string());
+                }
+            }
+        }
+        assert!(mismatches.is_empty(), "bytecode differs from {} (rerun with UPDATE_GOLDEN=1 to accept)", mismatches.join(", "));
+    }
+}
@@ -0,0 +1,58 @@
+//! Code generation for the file-producing targets. The bytecode target
+//! lives in `bytecode_gen` because its container format is shared with
+//! strxvm.
+
+pub mod wasm;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::ir;
+
+/// Output file for one contract. A single contract goes to `output_file`;
+/// several get the contract name spliced into the file name, e.g.
+/// `token.Token.wasm`.
+pub fn contract_path(output_file: &Path, contract: &str, contracts: usize) -> PathBuf {
+    if contracts == 1 {
+        return output_file.to_path_buf();
+    }
+    let stem = output_file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output_file.extension().unwrap_or_default().to_string_lossy();
+    output_file.with_file_name(format!("{}.{}.{}", stem, contract, extension))
+}
+
+/// Writes one validated Wasm module per contract
+pub fn emit_wasm(mut program: ir::Program, output_file: &Path) -> Result<(), String> {
+    // Wasm modules carry no debug section for `-g` marks to go in
+    for function in program.contracts.iter_mut().flat_map(|contract| &mut contract.functions) {
+        function.body.retain(|instruction| !matches!(instruction, ir::Instruction::Debug(_)));
+    }
+    let modules = wasm::generate(&program)?;
+    let engine = wasmtime::Engine::default();
+    for module in &modules {
+        wasmtime::Module::validate(&engine, &module.bytes)
+            .map_err(|e| format!("{}: generated an invalid wasm module: {}", module.name, e))?;
+        let path = contract_path(output_file, &module.name, modules.len());
+        fs::write(&path, &module.bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+pub fn emit_ir(program: ir::Program, output_file: &Path) -> Result<(), String> {
+    fs::write(output_file, program.to_string()).map_err(|e| format!("{}: {}", output_file.display(), e))
+}
+
+pub fn emit_native(_program: ir::Program, _output_file: &Path) -> Result<(), String> {
+    Err("native code generation is not supported yet; use --target wasm or --target bytecode".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contract_paths() {
+        let output = Path::new("out/token.wasm");
+        assert_eq!(contract_path(output, "Token", 1), PathBuf::from("out/token.wasm"));
+        assert_eq!(contract_path(output, "Token", 2), PathBuf::from("out/token.Token.wasm"));
+    }
+}
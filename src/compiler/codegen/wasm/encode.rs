@@ -0,0 +1,438 @@
+//! Just enough of the WebAssembly binary format for the backend: one
+//! memory, function imports, mutable globals, exports and active data
+//! segments. Blocks never carry values.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    /// Targets by index, then the default
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// Loads and stores take a constant offset from the address operand
+    I32Load(u32),
+    I64Load(u32),
+    I32Store(u32),
+    I64Store(u32),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32GtU,
+    I32GeU,
+    I64Eqz,
+    I64Ne,
+    I64LtU,
+    I32Add,
+    I32Sub,
+    I32And,
+    I32Or,
+    I32Shl,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64And,
+    I64Or,
+    I64Shl,
+    I64ShrU,
+    I32WrapI64,
+    I64ExtendI32U,
+}
+
+pub struct Import {
+    pub module: &'static str,
+    pub name: &'static str,
+    pub ty: u32,
+}
+
+pub struct Function {
+    pub ty: u32,
+    /// Locals after the parameters
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+pub struct Global {
+    pub ty: ValType,
+    pub mutable: bool,
+    pub init: i64,
+}
+
+#[derive(Clone, Copy)]
+pub enum ExportKind {
+    Func,
+    Memory,
+    Global,
+}
+
+pub struct Export {
+    pub name: String,
+    pub kind: ExportKind,
+    pub index: u32,
+}
+
+#[derive(Default)]
+pub struct Module {
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    /// Minimum size of the only memory, in 64 KiB pages
+    pub memory_pages: u32,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    /// Active segments as (offset, bytes)
+    pub data: Vec<(u32, Vec<u8>)>,
+}
+
+impl Module {
+    /// Index of `ty`, adding it on first use
+    pub fn intern_type(&mut self, ty: FuncType) -> u32 {
+        match self.types.iter().position(|t| *t == ty) {
+            Some(index) => index as u32,
+            None => {
+                self.types.push(ty);
+                (self.types.len() - 1) as u32
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = b"\0asm".to_vec();
+        out.extend_from_slice(&1u32.to_le_bytes());
+
+        section(&mut out, SECTION_TYPE, self.types.len(), |w| {
+            for ty in &self.types {
+                w.byte(0x60);
+                w.val_types(&ty.params);
+                w.val_types(&ty.results);
+            }
+        });
+        section(&mut out, SECTION_IMPORT, self.imports.len(), |w| {
+            for import in &self.imports {
+                w.name(import.module);
+                w.name(import.name);
+                w.byte(0x00);
+                w.u32(import.ty);
+            }
+        });
+        section(&mut out, SECTION_FUNCTION, self.functions.len(), |w| {
+            for function in &self.functions {
+                w.u32(function.ty);
+            }
+        });
+        section(&mut out, SECTION_MEMORY, 1, |w| {
+            w.byte(0x00);
+            w.u32(self.memory_pages);
+        });
+        section(&mut out, SECTION_GLOBAL, self.globals.len(), |w| {
+            for global in &self.globals {
+                w.val_type(global.ty);
+                w.byte(global.mutable as u8);
+                match global.ty {
+                    ValType::I32 => w.instr(&Instr::I32Const(global.init as i32)),
+                    ValType::I64 => w.instr(&Instr::I64Const(global.init)),
+                }
+                w.instr(&Instr::End);
+            }
+        });
+        section(&mut out, SECTION_EXPORT, self.exports.len(), |w| {
+            for export in &self.exports {
+                w.name(&export.name);
+                w.byte(match export.kind {
+                    ExportKind::Func => 0x00,
+                    ExportKind::Memory => 0x02,
+                    ExportKind::Global => 0x03,
+                });
+                w.u32(export.index);
+            }
+        });
+        section(&mut out, SECTION_CODE, self.functions.len(), |w| {
+            for function in &self.functions {
+                let mut body = Writer::default();
+                // Runs of equal types share a declaration
+                let mut runs: Vec<(u32, ValType)> = Vec::new();
+                for &ty in &function.locals {
+                    match runs.last_mut() {
+                        Some((count, last)) if *last == ty => *count += 1,
+                        _ => runs.push((1, ty)),
+                    }
+                }
+                body.u32(runs.len() as u32);
+                for (count, ty) in runs {
+                    body.u32(count);
+                    body.val_type(ty);
+                }
+                for instr in &function.body {
+                    body.instr(instr);
+                }
+                body.instr(&Instr::End);
+                w.u32(body.bytes.len() as u32);
+                w.bytes.extend_from_slice(&body.bytes);
+            }
+        });
+        section(&mut out, SECTION_DATA, self.data.len(), |w| {
+            for (offset, bytes) in &self.data {
+                w.byte(0x00);
+                w.instr(&Instr::I32Const(*offset as i32));
+                w.instr(&Instr::End);
+                w.u32(bytes.len() as u32);
+                w.bytes.extend_from_slice(bytes);
+            }
+        });
+        out
+    }
+}
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+/// Writes a section holding `count` entries; empty sections are left out
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: impl FnOnce(&mut Writer)) {
+    if count == 0 {
+        return;
+    }
+    let mut w = Writer::default();
+    w.u32(count as u32);
+    entries(&mut w);
+    out.push(id);
+    let mut size = Writer::default();
+    size.u32(w.bytes.len() as u32);
+    out.extend_from_slice(&size.bytes);
+    out.extend_from_slice(&w.bytes);
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    /// Unsigned LEB128
+    fn u32(&mut self, mut value: u32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.byte(byte);
+                return;
+            }
+            self.byte(byte | 0x80);
+        }
+    }
+
+    /// Signed LEB128
+    fn i64(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done {
+                self.byte(byte);
+                return;
+            }
+            self.byte(byte | 0x80);
+        }
+    }
+
+    fn name(&mut self, name: &str) {
+        self.u32(name.len() as u32);
+        self.bytes.extend_from_slice(name.as_bytes());
+    }
+
+    fn val_type(&mut self, ty: ValType) {
+        self.byte(match ty {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        });
+    }
+
+    fn val_types(&mut self, types: &[ValType]) {
+        self.u32(types.len() as u32);
+        for &ty in types {
+            self.val_type(ty);
+        }
+    }
+
+    /// Alignment hint and offset of a load or store
+    fn memarg(&mut self, align: u32, offset: u32) {
+        self.u32(align);
+        self.u32(offset);
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match *instr {
+            Instr::Unreachable => self.byte(0x00),
+            Instr::Block => self.bytes.extend_from_slice(&[0x02, 0x40]),
+            Instr::Loop => self.bytes.extend_from_slice(&[0x03, 0x40]),
+            Instr::If => self.bytes.extend_from_slice(&[0x04, 0x40]),
+            Instr::End => self.byte(0x0b),
+            Instr::Br(depth) => {
+                self.byte(0x0c);
+                self.u32(depth);
+            }
+            Instr::BrIf(depth) => {
+                self.byte(0x0d);
+                self.u32(depth);
+            }
+            Instr::BrTable(ref targets, default) => {
+                self.byte(0x0e);
+                self.u32(targets.len() as u32);
+                for &target in targets {
+                    self.u32(target);
+                }
+                self.u32(default);
+            }
+            Instr::Return => self.byte(0x0f),
+            Instr::Call(index) => {
+                self.byte(0x10);
+                self.u32(index);
+            }
+            Instr::Drop => self.byte(0x1a),
+            Instr::Select => self.byte(0x1b),
+            Instr::LocalGet(index) => {
+                self.byte(0x20);
+                self.u32(index);
+            }
+            Instr::LocalSet(index) => {
+                self.byte(0x21);
+                self.u32(index);
+            }
+            Instr::LocalTee(index) => {
+                self.byte(0x22);
+                self.u32(index);
+            }
+            Instr::GlobalGet(index) => {
+                self.byte(0x23);
+                self.u32(index);
+            }
+            Instr::GlobalSet(index) => {
+                self.byte(0x24);
+                self.u32(index);
+            }
+            Instr::I32Load(offset) => {
+                self.byte(0x28);
+                self.memarg(2, offset);
+            }
+            Instr::I64Load(offset) => {
+                self.byte(0x29);
+                self.memarg(3, offset);
+            }
+            Instr::I32Store(offset) => {
+                self.byte(0x36);
+                self.memarg(2, offset);
+            }
+            Instr::I64Store(offset) => {
+                self.byte(0x37);
+                self.memarg(3, offset);
+            }
+            Instr::MemorySize => self.bytes.extend_from_slice(&[0x3f, 0x00]),
+            Instr::MemoryGrow => self.bytes.extend_from_slice(&[0x40, 0x00]),
+            Instr::I32Const(value) => {
+                self.byte(0x41);
+                self.i64(value as i64);
+            }
+            Instr::I64Const(value) => {
+                self.byte(0x42);
+                self.i64(value);
+            }
+            Instr::I32Eqz => self.byte(0x45),
+            Instr::I32Eq => self.byte(0x46),
+            Instr::I32Ne => self.byte(0x47),
+            Instr::I32GtU => self.byte(0x4b),
+            Instr::I32GeU => self.byte(0x4f),
+            Instr::I64Eqz => self.byte(0x50),
+            Instr::I64Ne => self.byte(0x52),
+            Instr::I64LtU => self.byte(0x54),
+            Instr::I32Add => self.byte(0x6a),
+            Instr::I32Sub => self.byte(0x6b),
+            Instr::I32And => self.byte(0x71),
+            Instr::I32Or => self.byte(0x72),
+            Instr::I32Shl => self.byte(0x74),
+            Instr::I32ShrU => self.byte(0x76),
+            Instr::I64Add => self.byte(0x7c),
+            Instr::I64Sub => self.byte(0x7d),
+            Instr::I64Mul => self.byte(0x7e),
+            Instr::I64And => self.byte(0x83),
+            Instr::I64Or => self.byte(0x84),
+            Instr::I64Shl => self.byte(0x86),
+            Instr::I64ShrU => self.byte(0x88),
+            Instr::I32WrapI64 => self.byte(0xa7),
+            Instr::I64ExtendI32U => self.byte(0xad),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb_u32(value: u32) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u32(value);
+        w.bytes
+    }
+
+    fn leb_i64(value: i64) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.i64(value);
+        w.bytes
+    }
+
+    #[test]
+    fn test_leb128() {
+        assert_eq!(leb_u32(0), [0x00]);
+        assert_eq!(leb_u32(127), [0x7f]);
+        assert_eq!(leb_u32(128), [0x80, 0x01]);
+        assert_eq!(leb_u32(624485), [0xe5, 0x8e, 0x26]);
+        assert_eq!(leb_i64(0), [0x00]);
+        assert_eq!(leb_i64(-1), [0x7f]);
+        assert_eq!(leb_i64(63), [0x3f]);
+        assert_eq!(leb_i64(64), [0xc0, 0x00]);
+        assert_eq!(leb_i64(-123456), [0xc0, 0xbb, 0x78]);
+    }
+
+    #[test]
+    fn test_empty_module() {
+        let module = Module { memory_pages: 1, ..Module::default() };
+        assert_eq!(module.encode(), b"\0asm\x01\0\0\0\x05\x03\x01\x00\x01");
+    }
+}
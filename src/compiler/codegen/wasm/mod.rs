@@ -0,0 +1,1015 @@
+//! WebAssembly backend: one standalone module per contract.
+//!
+//! Every IR value is a 32-byte little-endian word in linear memory and the
+//! generated code passes `i32` pointers to words around, so `u256`
+//! arithmetic keeps its full width. Addresses fill the first 20 bytes of a
+//! word, booleans are 0 or 1 and strings of up to 32 bytes are their UTF-8
+//! bytes, zero padded. Words are never modified once written, and memory is
+//! only ever bump allocated, so hosts should run each transaction in a
+//! fresh instance.
+//!
+//! # Host interface
+//!
+//! The module imports these functions from the `stremax` namespace. Every
+//! parameter is an `i32`: a pointer to a word, or a pointer and length for
+//! names and messages. None of them return a value.
+//!
+//! | import                                 | effect                                             |
+//! |----------------------------------------|----------------------------------------------------|
+//! | `storage_load(key, out)`               | writes the word stored under `key` to `out`        |
+//! | `storage_store(key, value)`            | stores `value` under `key`                         |
+//! | `map_slot(slot, key, out)`             | writes the storage key of `key` in map `slot`      |
+//! | `emit_event(name, len, args, count)`   | emits `count` consecutive argument words           |
+//! | `transfer(to, amount)`                 | sends native tokens; traps if it cannot            |
+//! | `revert(message, len)`                 | aborts the transaction; must trap                  |
+//! | `out_of_gas()`                         | called when metering runs out; must trap           |
+//!
+//! After them comes one import per entry of the
+//! [host function table](crate::bytecode::host), in table order and under
+//! the entry's `import` name: `caller(out)`, `block_timestamp(out)`,
+//! `balance(account, out)`, `keccak256(data, out)`, `log(message)` and so
+//! on. Each takes a pointer to every argument word, then an `out` pointer
+//! to write the result word to unless the entry returns nothing; `bytes`
+//! arguments are a single word too. Calling an entry charges its gas,
+//! along with that of the block it is in.
+//!
+//! Statically numbered state lives under the word holding its slot number.
+//!
+//! # Exports
+//!
+//! `memory`, the mutable `i64` global `gas`, the constant `i32` global
+//! `args` and the constant `i32` global `host_version`, the version of the
+//! host function table the module imports from.
+//!
+//! Each contract function is exported under its own name with no
+//! parameters: the host writes up to [`MAX_ARGS`] argument words starting
+//! at `args` and gets back a pointer to the result word, or nothing for
+//! functions without a result. Gas is charged from `gas` at the start of
+//! each basic block, using the same schedule as the optimizer's estimates.
+
+mod encode;
+mod runtime;
+
+use std::collections::HashMap;
+use crate::bytecode::host::{self, HOST_FUNCTIONS, HOST_VERSION};
+use crate::bytecode::Type;
+use crate::ir;
+use crate::optimizer::gas_cost;
+use encode::{Export, ExportKind, FuncType, Global, Import, Instr, ValType};
+use runtime::Runtime;
+
+pub use encode::Module as RawModule;
+
+/// Namespace of every host import
+pub const IMPORT_MODULE: &str = "stremax";
+
+/// Functions with more parameters than this are not exported
+pub const MAX_ARGS: usize = 16;
+
+pub const WORD: u32 = 32;
+
+// Fixed memory layout. Data segments and then the heap follow `DATA`.
+const ZERO: u32 = 0;
+const ONE: u32 = 32;
+/// Columns of a 512-bit product
+const SCRATCH: u32 = 64;
+/// Running remainder of a division
+const REMAINDER: u32 = 128;
+const ARGS: u32 = 256;
+const DATA: u32 = ARGS + MAX_ARGS as u32 * WORD;
+
+/// Imports besides the host function table, which follows them
+const IMPORTS: [(&str, usize); 7] = [
+    ("storage_load", 2),
+    ("storage_store", 2),
+    ("map_slot", 3),
+    ("emit_event", 4),
+    ("transfer", 2),
+    ("revert", 2),
+    ("out_of_gas", 0),
+];
+
+const STORAGE_LOAD: u32 = 0;
+const STORAGE_STORE: u32 = 1;
+const MAP_SLOT: u32 = 2;
+const EMIT_EVENT: u32 = 3;
+const TRANSFER: u32 = 4;
+const REVERT: u32 = 5;
+const OUT_OF_GAS: u32 = 6;
+
+const IMPORT_COUNT: usize = IMPORTS.len() + HOST_FUNCTIONS.len();
+
+// Globals, followed by one reentrancy lock per `@no_reentry` function
+const GAS: u32 = 0;
+const HEAP: u32 = 1;
+const ARGS_GLOBAL: u32 = 2;
+const HOST_VERSION_GLOBAL: u32 = 3;
+const FIRST_LOCK: u32 = 4;
+
+/// A compiled contract
+pub struct WasmModule {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+pub fn generate(program: &ir::Program) -> Result<Vec<WasmModule>, String> {
+    program.contracts.iter()
+        .map(|contract| {
+            let bytes = Generator::new(contract).generate()
+                .map_err(|msg| format!("{}: {}", contract.name, msg))?;
+            Ok(WasmModule { name: contract.name.clone(), bytes })
+        })
+        .collect()
+}
+
+struct Generator<'a> {
+    contract: &'a ir::Contract,
+    module: RawModule,
+    /// Bytes of the data segment at `DATA`
+    data: Vec<u8>,
+    words: HashMap<[u8; 32], u32>,
+    strings: HashMap<String, u32>,
+    runtime: Runtime,
+    /// Function index, arity and whether it returns a value, by name
+    signatures: HashMap<&'a str, (u32, usize, bool)>,
+    /// Reentrancy locks handed out so far
+    locks: u32,
+}
+
+impl<'a> Generator<'a> {
+    fn new(contract: &'a ir::Contract) -> Self {
+        Generator {
+            contract,
+            module: RawModule::default(),
+            data: Vec::new(),
+            words: HashMap::new(),
+            strings: HashMap::new(),
+            runtime: Runtime::default(),
+            signatures: HashMap::new(),
+            locks: 0,
+        }
+    }
+
+    fn generate(mut self) -> Result<Vec<u8>, String> {
+        for (name, arity) in IMPORTS {
+            let ty = self.func_type(arity, false);
+            self.module.imports.push(Import { module: IMPORT_MODULE, name, ty });
+        }
+        for entry in &HOST_FUNCTIONS {
+            let arity = entry.params.len() + (entry.returns != Type::Void) as usize;
+            let ty = self.func_type(arity, false);
+            self.module.imports.push(Import { module: IMPORT_MODULE, name: entry.import, ty });
+        }
+        self.add_runtime();
+
+        // Register every function first so calls can refer forward
+        let first = (IMPORT_COUNT + self.module.functions.len()) as u32;
+        for (index, function) in self.contract.functions.iter().enumerate() {
+            self.signatures.insert(
+                &function.name,
+                (first + index as u32, function.params.len(), returns_value(function)),
+            );
+        }
+
+        for function in &self.contract.functions {
+            let body = self.lower_function(function)
+                .map_err(|msg| format!("{}: {}", function.name, msg))?;
+            self.module.functions.push(body);
+        }
+        for function in self.contract.functions.iter().filter(|f| is_exported(f)) {
+            self.export(function)?;
+        }
+
+        let heap = (DATA + self.data.len() as u32).next_multiple_of(WORD);
+        self.module.memory_pages = heap / 65536 + 1;
+        self.module.globals = vec![
+            Global { ty: ValType::I64, mutable: true, init: 0 },
+            Global { ty: ValType::I32, mutable: true, init: heap as i64 },
+            Global { ty: ValType::I32, mutable: false, init: ARGS as i64 },
+            Global { ty: ValType::I32, mutable: false, init: HOST_VERSION as i64 },
+        ];
+        self.module.globals.extend((0..self.locks).map(|_| Global { ty: ValType::I32, mutable: true, init: 0 }));
+        self.module.exports.push(Export { name: "memory".into(), kind: ExportKind::Memory, index: 0 });
+        self.module.exports.push(Export { name: "gas".into(), kind: ExportKind::Global, index: GAS });
+        self.module.exports.push(Export { name: "args".into(), kind: ExportKind::Global, index: ARGS_GLOBAL });
+        self.module.exports.push(Export { name: "host_version".into(), kind: ExportKind::Global, index: HOST_VERSION_GLOBAL });
+
+        let mut one = [0; 32];
+        one[0] = 1;
+        self.module.data.push((ONE, one.to_vec()));
+        if !self.data.is_empty() {
+            self.module.data.push((DATA, std::mem::take(&mut self.data)));
+        }
+        Ok(self.module.encode())
+    }
+
+    fn func_type(&mut self, params: usize, result: bool) -> u32 {
+        self.module.intern_type(FuncType {
+            params: vec![ValType::I32; params],
+            results: if result { vec![ValType::I32] } else { Vec::new() },
+        })
+    }
+
+    /// Address of a constant word
+    fn word(&mut self, bytes: [u8; 32]) -> u32 {
+        if let Some(&address) = self.words.get(&bytes) {
+            return address;
+        }
+        while !self.data.len().is_multiple_of(WORD as usize) {
+            self.data.push(0);
+        }
+        let address = DATA + self.data.len() as u32;
+        self.data.extend_from_slice(&bytes);
+        self.words.insert(bytes, address);
+        address
+    }
+
+    fn number(&mut self, value: u64) -> u32 {
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&value.to_le_bytes());
+        self.word(bytes)
+    }
+
+    /// Gas of the host function `instruction` reaches, which it costs on
+    /// top of its own
+    fn host_gas(&self, instruction: &ir::Instruction) -> u64 {
+        let name = match instruction {
+            ir::Instruction::Env(name) => name,
+            ir::Instruction::Call(name, _) if !self.signatures.contains_key(name.as_str()) => name,
+            _ => return 0,
+        };
+        host::lookup(name).map_or(0, |(_, entry)| entry.gas)
+    }
+
+    /// Address and length of a string constant
+    fn string(&mut self, text: &str) -> (i32, i32) {
+        let address = match self.strings.get(text) {
+            Some(&address) => address,
+            None => {
+                let address = DATA + self.data.len() as u32;
+                self.data.extend_from_slice(text.as_bytes());
+                self.strings.insert(text.to_string(), address);
+                address
+            }
+        };
+        (address as i32, text.len() as i32)
+    }
+
+    fn value(&mut self, value: &ir::Value) -> Result<u32, String> {
+        let mut bytes = [0; 32];
+        match value {
+            ir::Value::U256(n) => bytes = n.to_le_bytes(),
+            ir::Value::Bool(b) => return Ok(if *b { ONE } else { ZERO }),
+            ir::Value::Address(a) => bytes[..20].copy_from_slice(a),
+            ir::Value::String(s) if s.len() <= 32 => bytes[..s.len()].copy_from_slice(s.as_bytes()),
+            ir::Value::String(s) => {
+                return Err(format!("string {:?} is longer than a word", s));
+            }
+        }
+        Ok(self.word(bytes))
+    }
+
+    /// `revert(message)` followed by `unreachable`
+    fn revert(&mut self, message: &str) -> [Instr; 4] {
+        let (address, len) = self.string(message);
+        [Instr::I32Const(address), Instr::I32Const(len), Instr::Call(REVERT), Instr::Unreachable]
+    }
+
+    /// Exports a wrapper that copies the arguments out of the argument
+    /// area, so a reentrant call cannot overwrite them, then calls `function`
+    fn export(&mut self, function: &ir::Function) -> Result<(), String> {
+        if ["memory", "gas", "args"].contains(&function.name.as_str()) {
+            return Err(format!("function `{}` clashes with a module export", function.name));
+        }
+        if function.params.len() > MAX_ARGS {
+            return Err(format!("{}: more than {} parameters", function.name, MAX_ARGS));
+        }
+        let (index, arity, returns) = self.signatures[function.name.as_str()];
+        let mut body = Vec::new();
+        for arg in 0..arity as u32 {
+            body.extend([
+                Instr::Call(self.runtime.alloc),
+                Instr::LocalTee(0),
+                Instr::I32Const((ARGS + arg * WORD) as i32),
+                Instr::Call(self.runtime.copy),
+                Instr::LocalGet(0),
+            ]);
+        }
+        body.push(Instr::Call(index));
+
+        let ty = self.func_type(0, returns);
+        let wrapper = (IMPORT_COUNT + self.module.functions.len()) as u32;
+        self.module.functions.push(encode::Function { ty, locals: vec![ValType::I32], body });
+        self.module.exports.push(Export { name: function.name.clone(), kind: ExportKind::Func, index: wrapper });
+        Ok(())
+    }
+
+    fn lower_function(&mut self, function: &ir::Function) -> Result<encode::Function, String> {
+        let locals = function.locals.iter()
+            .map(|local| local.index + 1)
+            .max()
+            .unwrap_or(0)
+            .max(function.params.len() as u32);
+        let blocks = basic_blocks(&function.body);
+        let (depths, max_depth) = self.stack_depths(function, &blocks)?;
+
+        // IR locals keep their indices, then one local per stack position
+        let slots = locals;
+        let pc = slots + max_depth;
+        let tmp = pc + 1;
+        let guard_ends: Vec<&ir::Label> = function.body.iter()
+            .filter_map(|instruction| match instruction {
+                ir::Instruction::NoReentry(_, end) => Some(end),
+                _ => None,
+            })
+            .collect();
+        // The function's own lock, so that other guarded functions can
+        // still be entered while it runs
+        let lock = FIRST_LOCK + self.locks;
+        if !guard_ends.is_empty() {
+            self.locks += 1;
+        }
+        let block_of: HashMap<&ir::Label, usize> = blocks.iter()
+            .enumerate()
+            .flat_map(|(index, block)| {
+                block.labels(&function.body).map(move |label| (label, index))
+            })
+            .collect();
+
+        // With labels, blocks sit in a dispatch loop: `br_table` on `pc`
+        // enters block k, and a jump sets `pc` and branches back to the loop
+        let dispatch = !block_of.is_empty();
+        let mut body = Vec::new();
+        if dispatch {
+            body.push(Instr::Loop);
+            body.extend(std::iter::repeat_n(Instr::Block, blocks.len()));
+            body.push(Instr::LocalGet(pc));
+            body.push(Instr::BrTable((0..blocks.len() as u32).collect(), 0));
+        }
+
+        for (k, block) in blocks.iter().enumerate() {
+            if dispatch {
+                body.push(Instr::End);
+            }
+            // Branch depth of the dispatch loop from inside this block
+            let to_loop = (blocks.len() - 1 - k) as u32;
+
+            if block.labels(&function.body).any(|label| guard_ends.contains(&label)) {
+                body.extend([Instr::I32Const(0), Instr::GlobalSet(lock)]);
+            }
+            let cost: u64 = function.body[block.range.clone()].iter()
+                .map(|instruction| gas_cost(instruction) + self.host_gas(instruction))
+                .sum();
+            if cost > 0 {
+                body.extend([Instr::I64Const(cost as i64), Instr::Call(self.runtime.charge)]);
+            }
+
+            let mut d = depths[k];
+            for instruction in &function.body[block.range.clone()] {
+                let s = |p: u32| Instr::LocalGet(slots + p);
+                let set = |p: u32| Instr::LocalSet(slots + p);
+                let tee = |p: u32| Instr::LocalTee(slots + p);
+                let rt = self.runtime;
+                let jump = |label: &ir::Label, extra: u32| -> Result<[Instr; 3], String> {
+                    let target = block_of.get(label)
+                        .ok_or_else(|| format!("jump to undefined label `{}`", label))?;
+                    Ok([Instr::I32Const(*target as i32), Instr::LocalSet(pc), Instr::Br(to_loop + extra)])
+                };
+
+                match instruction {
+                    ir::Instruction::Label(_) => {}
+                    ir::Instruction::Push(value) => {
+                        let address = self.value(value)?;
+                        body.extend([Instr::I32Const(address as i32), set(d)]);
+                    }
+                    ir::Instruction::Pop => {}
+                    ir::Instruction::Dup(n) => body.extend([s(d - 1 - *n as u32), set(d)]),
+                    ir::Instruction::Load(index) => body.extend([Instr::LocalGet(*index), set(d)]),
+                    ir::Instruction::Store(index) => body.extend([s(d - 1), Instr::LocalSet(*index)]),
+                    ir::Instruction::SLoad(slot) => {
+                        let key = self.number(*slot as u64);
+                        body.extend([Instr::I32Const(key as i32), Instr::Call(rt.alloc), tee(d), Instr::Call(STORAGE_LOAD)]);
+                    }
+                    ir::Instruction::SStore(slot) => {
+                        let key = self.number(*slot as u64);
+                        body.extend([Instr::I32Const(key as i32), s(d - 1), Instr::Call(STORAGE_STORE)]);
+                    }
+                    ir::Instruction::MapSlot => {
+                        body.extend([s(d - 2), s(d - 1), Instr::Call(rt.alloc), tee(d - 2), Instr::Call(MAP_SLOT)]);
+                    }
+                    ir::Instruction::SLoadAt => {
+                        body.extend([s(d - 1), Instr::Call(rt.alloc), tee(d - 1), Instr::Call(STORAGE_LOAD)]);
+                    }
+                    ir::Instruction::SStoreAt => body.extend([s(d - 2), s(d - 1), Instr::Call(STORAGE_STORE)]),
+                    ir::Instruction::Add | ir::Instruction::Sub | ir::Instruction::Mul | ir::Instruction::Div => {
+                        let helper = match instruction {
+                            ir::Instruction::Add => rt.add,
+                            ir::Instruction::Sub => rt.sub,
+                            ir::Instruction::Mul => rt.mul,
+                            _ => rt.div,
+                        };
+                        body.extend([s(d - 2), s(d - 1), Instr::Call(helper), set(d - 2)]);
+                    }
+                    ir::Instruction::Eq | ir::Instruction::Lt | ir::Instruction::Gt
+                    | ir::Instruction::LtEq | ir::Instruction::GtEq => {
+                        // cmp gives -1, 0 or 1
+                        let test: &[Instr] = match instruction {
+                            ir::Instruction::Eq => &[Instr::I32Eqz],
+                            ir::Instruction::Lt => &[Instr::I32Const(-1), Instr::I32Eq],
+                            ir::Instruction::Gt => &[Instr::I32Const(1), Instr::I32Eq],
+                            ir::Instruction::LtEq => &[Instr::I32Const(1), Instr::I32Ne],
+                            _ => &[Instr::I32Const(-1), Instr::I32Ne],
+                        };
+                        body.extend([s(d - 2), s(d - 1), Instr::Call(rt.cmp)]);
+                        body.extend_from_slice(test);
+                        body.extend([Instr::Call(rt.bool), set(d - 2)]);
+                    }
+                    ir::Instruction::Not => {
+                        body.extend([s(d - 1), Instr::Call(rt.is_zero), Instr::Call(rt.bool), set(d - 1)]);
+                    }
+                    ir::Instruction::Jump(label) => body.extend(jump(label, 0)?),
+                    ir::Instruction::JumpIf(label) => {
+                        body.extend([s(d - 1), Instr::Call(rt.is_zero), Instr::I32Eqz, Instr::If]);
+                        body.extend(jump(label, 1)?);
+                        body.push(Instr::End);
+                    }
+                    ir::Instruction::Call(name, argc) if !self.signatures.contains_key(name.as_str()) => {
+                        let (id, entry) = host::lookup(name)
+                            .filter(|(_, entry)| !entry.is_value())
+                            .ok_or_else(|| format!("call to `{}` has no wasm equivalent yet", name))?;
+                        if entry.params.len() != *argc as usize {
+                            return Err(format!("`{}` takes {} arguments, not {}", name, entry.params.len(), argc));
+                        }
+                        let base = d - *argc as u32;
+                        body.extend((base..d).map(s));
+                        if entry.returns == Type::Void {
+                            body.extend([Instr::Call(host_import(id)), Instr::I32Const(ZERO as i32), set(base)]);
+                        } else {
+                            body.extend([Instr::Call(rt.alloc), tee(base), Instr::Call(host_import(id))]);
+                        }
+                    }
+                    ir::Instruction::Call(name, argc) => {
+                        let &(index, arity, returns) = self.signatures.get(name.as_str())
+                            .ok_or_else(|| format!("call to `{}` has no wasm equivalent yet", name))?;
+                        if arity != *argc as usize {
+                            return Err(format!("`{}` takes {} arguments, not {}", name, arity, argc));
+                        }
+                        let base = d - arity as u32;
+                        body.extend((base..d).map(s));
+                        body.push(Instr::Call(index));
+                        if returns {
+                            body.push(set(base));
+                        }
+                    }
+                    ir::Instruction::CallMethod(method, 0) if method == "balance" => {
+                        let (balance, _) = host::lookup("balance").expect("`balance` is in the host table");
+                        body.extend([s(d - 1), Instr::Call(rt.alloc), tee(d - 1), Instr::Call(host_import(balance))]);
+                    }
+                    ir::Instruction::CallMethod(method, 1) if method == "transfer" => {
+                        // The host traps when a transfer fails, so the result is always `()`
+                        body.extend([s(d - 2), s(d - 1), Instr::Call(TRANSFER), Instr::I32Const(ZERO as i32), set(d - 2)]);
+                    }
+                    ir::Instruction::Return => {
+                        if returns_value(function) {
+                            body.push(s(d - 1));
+                        }
+                        body.push(Instr::Return);
+                    }
+                    ir::Instruction::Revert(message) => body.extend(self.revert(message)),
+                    ir::Instruction::EmitEvent(name, argc) => {
+                        let argc = *argc as u32;
+                        for arg in 0..argc {
+                            body.push(Instr::Call(rt.alloc));
+                            if arg == 0 {
+                                body.push(Instr::LocalTee(tmp));
+                            }
+                            body.extend([s(d - argc + arg), Instr::Call(rt.copy)]);
+                        }
+                        let (address, len) = self.string(name);
+                        let args = if argc == 0 { Instr::I32Const(0) } else { Instr::LocalGet(tmp) };
+                        body.extend([Instr::I32Const(address), Instr::I32Const(len), args, Instr::I32Const(argc as i32), Instr::Call(EMIT_EVENT)]);
+                    }
+                    ir::Instruction::NoReentry(..) => {
+                        body.extend([Instr::GlobalGet(lock), Instr::If]);
+                        body.extend(self.revert("Reentrant call detected"));
+                        body.extend([Instr::End, Instr::I32Const(1), Instr::GlobalSet(lock)]);
+                    }
+                    ir::Instruction::Env(name) => {
+                        let (id, _) = host::lookup(name)
+                            .filter(|(_, entry)| entry.is_value())
+                            .ok_or_else(|| format!("`{}` is not provided by the host", name))?;
+                        body.extend([Instr::Call(rt.alloc), tee(d), Instr::Call(host_import(id))]);
+                    }
+                    _ => return Err(format!("`{}` has no wasm equivalent yet", instruction)),
+                }
+                d = (d as i64 + stack_effect(instruction, &self.signatures)?) as u32;
+            }
+        }
+
+        if dispatch {
+            body.push(Instr::End);
+        }
+        // Every path ends in a return, revert or jump
+        body.push(Instr::Unreachable);
+
+        let params = function.params.len();
+        let ty = self.func_type(params, returns_value(function));
+        let count = locals as usize - params + max_depth as usize + 2;
+        Ok(encode::Function { ty, locals: vec![ValType::I32; count], body })
+    }
+
+    /// Stack depth at the start of each block, and the deepest the stack
+    /// gets. A label's depth comes from the jumps to it, or from the code
+    /// falling into it.
+    fn stack_depths(&self, function: &ir::Function, blocks: &[Block]) -> Result<(Vec<u32>, u32), String> {
+        let mut at_label: HashMap<&ir::Label, u32> = HashMap::new();
+        let mut depths = Vec::with_capacity(blocks.len());
+        let mut depth: i64 = 0;
+        let mut max_depth = 0;
+        for block in blocks {
+            for label in block.labels(&function.body) {
+                if let Some(&expected) = at_label.get(label) {
+                    depth = expected as i64;
+                }
+            }
+            depths.push(depth as u32);
+            for instruction in &function.body[block.range.clone()] {
+                match instruction {
+                    ir::Instruction::Label(label) => {
+                        record(&mut at_label, label, depth)?;
+                    }
+                    ir::Instruction::Jump(label) => record(&mut at_label, label, depth)?,
+                    ir::Instruction::JumpIf(label) => record(&mut at_label, label, depth - 1)?,
+                    _ => {}
+                }
+                let effect = stack_effect(instruction, &self.signatures)?;
+                let required = match instruction {
+                    ir::Instruction::Dup(n) => *n as i64 + 1,
+                    _ => pops(instruction, &self.signatures, function),
+                };
+                if depth < required {
+                    return Err(format!("stack underflow at `{}`", instruction));
+                }
+                depth += effect;
+                max_depth = max_depth.max(depth);
+            }
+        }
+        Ok((depths, max_depth as u32))
+    }
+}
+
+fn record<'a>(at_label: &mut HashMap<&'a ir::Label, u32>, label: &'a ir::Label, depth: i64) -> Result<(), String> {
+    match at_label.insert(label, depth as u32) {
+        Some(previous) if previous != depth as u32 => {
+            Err(format!("stack depth at `{}` is {} on one path and {} on another", label, previous, depth))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Values `instruction` needs on the stack
+fn pops(instruction: &ir::Instruction, signatures: &HashMap<&str, (u32, usize, bool)>, function: &ir::Function) -> i64 {
+    match instruction {
+        ir::Instruction::Return => returns_value(function) as i64,
+        ir::Instruction::Call(name, argc) => signatures.get(name.as_str()).map_or(*argc as i64, |&(_, arity, _)| arity as i64),
+        ir::Instruction::EmitEvent(_, argc) => *argc as i64,
+        ir::Instruction::Pop | ir::Instruction::Store(_) | ir::Instruction::SStore(_) | ir::Instruction::SLoadAt
+        | ir::Instruction::Not | ir::Instruction::JumpIf(_) => 1,
+        ir::Instruction::MapSlot | ir::Instruction::SStoreAt | ir::Instruction::Add | ir::Instruction::Sub
+        | ir::Instruction::Mul | ir::Instruction::Div | ir::Instruction::Eq | ir::Instruction::Lt
+        | ir::Instruction::Gt | ir::Instruction::LtEq | ir::Instruction::GtEq => 2,
+        ir::Instruction::CallMethod(_, argc) => *argc as i64 + 1,
+        _ => 0,
+    }
+}
+
+/// Net change in stack depth, or an error for instructions the backend
+/// cannot translate
+fn stack_effect(instruction: &ir::Instruction, signatures: &HashMap<&str, (u32, usize, bool)>) -> Result<i64, String> {
+    Ok(match instruction {
+        ir::Instruction::Push(_) | ir::Instruction::Dup(_) | ir::Instruction::Load(_)
+        | ir::Instruction::SLoad(_) | ir::Instruction::Env(_) => 1,
+        ir::Instruction::SLoadAt | ir::Instruction::Not | ir::Instruction::Jump(_) | ir::Instruction::Label(_)
+        | ir::Instruction::Return | ir::Instruction::Revert(_) | ir::Instruction::NoReentry(..) => 0,
+        ir::Instruction::Pop | ir::Instruction::Store(_) | ir::Instruction::SStore(_) | ir::Instruction::MapSlot
+        | ir::Instruction::Add | ir::Instruction::Sub | ir::Instruction::Mul | ir::Instruction::Div
+        | ir::Instruction::Eq | ir::Instruction::Lt | ir::Instruction::Gt | ir::Instruction::LtEq
+        | ir::Instruction::GtEq | ir::Instruction::JumpIf(_) => -1,
+        ir::Instruction::SStoreAt => -2,
+        ir::Instruction::Call(name, argc) => match signatures.get(name.as_str()) {
+            Some(&(_, _, returns)) => returns as i64 - *argc as i64,
+            // Host functions leave a word, if only `()`
+            None if host::lookup(name).is_some() => 1 - *argc as i64,
+            None => return Err(format!("call to `{}` has no wasm equivalent yet", name)),
+        },
+        ir::Instruction::CallMethod(method, 0) if method == "balance" => 0,
+        ir::Instruction::CallMethod(method, 1) if method == "transfer" => -1,
+        ir::Instruction::EmitEvent(_, argc) => -(*argc as i64),
+        _ => return Err(format!("`{}` has no wasm equivalent yet", instruction)),
+    })
+}
+
+/// Import index of entry `id` of the host function table
+fn host_import(id: usize) -> u32 {
+    (IMPORTS.len() + id) as u32
+}
+
+fn returns_value(function: &ir::Function) -> bool {
+    !matches!(function.return_type, None | Some(ir::Type::Void))
+}
+
+/// Lambda-lifted closures are only reachable through their closure values
+fn is_exported(function: &ir::Function) -> bool {
+    !function.name.contains("::")
+}
+
+/// A run of instructions entered only at the top: it starts at a label or
+/// after a branch, and a branch, return or revert ends it
+struct Block {
+    range: std::ops::Range<usize>,
+}
+
+impl Block {
+    fn labels<'b>(&self, body: &'b [ir::Instruction]) -> impl Iterator<Item = &'b ir::Label> {
+        body[self.range.clone()].iter().map_while(|instruction| match instruction {
+            ir::Instruction::Label(label) => Some(label),
+            _ => None,
+        })
+    }
+}
+
+fn basic_blocks(body: &[ir::Instruction]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (i, instruction) in body.iter().enumerate() {
+        let starts_block = matches!(instruction, ir::Instruction::Label(_))
+            && !matches!(body.get(i.wrapping_sub(1)), Some(ir::Instruction::Label(_)));
+        if starts_block && i > start {
+            blocks.push(Block { range: start..i });
+            start = i;
+        }
+        if matches!(instruction, ir::Instruction::Jump(_) | ir::Instruction::JumpIf(_)
+            | ir::Instruction::Return | ir::Instruction::Revert(_))
+        {
+            blocks.push(Block { range: start..i + 1 });
+            start = i + 1;
+        }
+    }
+    if start < body.len() || blocks.is_empty() {
+        blocks.push(Block { range: start..body.len() });
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use wasmtime::{Caller, Engine, Extern, Linker, Memory, Module, Store, Val};
+    use crate::num::U256;
+    use crate::{lexer, parser};
+
+    type Word = [u8; 32];
+
+    #[derive(Default, Clone)]
+    struct Host {
+        storage: HashMap<Word, Word>,
+        events: Vec<(String, Vec<Word>)>,
+        balances: HashMap<Word, U256>,
+        caller: Word,
+        timestamp: u64,
+        logs: Vec<Word>,
+        /// Called from inside `transfer`, to test reentrancy
+        reenter: Option<String>,
+    }
+
+    fn word(n: u64) -> Word {
+        U256::from(n).to_le_bytes()
+    }
+
+    fn address(byte: u8) -> Word {
+        let mut word = [0; 32];
+        word[..20].fill(byte);
+        word
+    }
+
+    fn memory(caller: &mut Caller<'_, Host>) -> Memory {
+        match caller.get_export("memory") {
+            Some(Extern::Memory(memory)) => memory,
+            _ => panic!("module does not export its memory"),
+        }
+    }
+
+    fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: usize) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        memory(caller).read(&*caller, ptr as usize, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn read_word(caller: &mut Caller<'_, Host>, ptr: i32) -> Word {
+        read(caller, ptr, 32).try_into().unwrap()
+    }
+
+    fn write_word(caller: &mut Caller<'_, Host>, ptr: i32, word: Word) {
+        memory(caller).write(&mut *caller, ptr as usize, &word).unwrap();
+    }
+
+    fn linker(engine: &Engine) -> Linker<Host> {
+        let mut linker = Linker::new(engine);
+        linker.func_wrap(IMPORT_MODULE, "storage_load", |mut caller: Caller<'_, Host>, key: i32, out: i32| {
+            let key = read_word(&mut caller, key);
+            let value = caller.data().storage.get(&key).copied().unwrap_or_default();
+            write_word(&mut caller, out, value);
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "storage_store", |mut caller: Caller<'_, Host>, key: i32, value: i32| {
+            let key = read_word(&mut caller, key);
+            let value = read_word(&mut caller, value);
+            caller.data_mut().storage.insert(key, value);
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "map_slot", |mut caller: Caller<'_, Host>, slot: i32, key: i32, out: i32| {
+            let mut hasher = DefaultHasher::new();
+            (read_word(&mut caller, slot), read_word(&mut caller, key)).hash(&mut hasher);
+            let mut derived = word(hasher.finish());
+            derived[31] = 0xff;
+            write_word(&mut caller, out, derived);
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "emit_event", |mut caller: Caller<'_, Host>, name: i32, len: i32, args: i32, count: i32| {
+            let name = String::from_utf8(read(&mut caller, name, len as usize)).unwrap();
+            let args = (0..count).map(|i| read_word(&mut caller, args + i * 32)).collect();
+            caller.data_mut().events.push((name, args));
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "transfer", |mut caller: Caller<'_, Host>, to: i32, amount: i32| -> wasmtime::Result<()> {
+            let to = read_word(&mut caller, to);
+            let amount = U256::from_le_bytes(read_word(&mut caller, amount));
+            let contract = address(0xcc);
+            let host = caller.data_mut();
+            let available = host.balances.get(&contract).copied().unwrap_or(U256::ZERO);
+            let remaining = available.checked_sub(amount)
+                .ok_or_else(|| wasmtime::Error::msg("insufficient balance"))?;
+            host.balances.insert(contract, remaining);
+            let received = host.balances.get(&to).copied().unwrap_or(U256::ZERO) + amount;
+            host.balances.insert(to, received);
+
+            if let Some(function) = caller.data_mut().reenter.take() {
+                let callee = caller.get_export(&function).and_then(Extern::into_func).unwrap();
+                let mut results = vec![Val::I32(0); callee.ty(&caller).results().len()];
+                callee.call(&mut caller, &[], &mut results)?;
+            }
+            Ok(())
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "revert", |mut caller: Caller<'_, Host>, message: i32, len: i32| -> wasmtime::Result<()> {
+            let message = String::from_utf8(read(&mut caller, message, len as usize)).unwrap();
+            Err(wasmtime::Error::msg(format!("revert: {}", message)))
+        }).unwrap();
+        linker.func_wrap(IMPORT_MODULE, "out_of_gas", || -> wasmtime::Result<()> {
+            Err(wasmtime::Error::msg("out of gas"))
+        }).unwrap();
+        for entry in &HOST_FUNCTIONS {
+            let arity = entry.params.len() + (entry.returns != Type::Void) as usize;
+            let ty = wasmtime::FuncType::new(engine, vec![wasmtime::ValType::I32; arity], []);
+            linker.func_new(IMPORT_MODULE, entry.import, ty, move |mut caller, params, _| {
+                let args: Vec<Word> = params[..entry.params.len()].iter()
+                    .map(|param| read_word(&mut caller, param.unwrap_i32()))
+                    .collect();
+                let result = match (entry.name, args.as_slice()) {
+                    ("msg.sender", []) => caller.data().caller,
+                    ("self", []) => address(0xcc),
+                    ("block.timestamp", []) => word(caller.data().timestamp),
+                    ("self.balance", []) => caller.data().balances.get(&address(0xcc)).copied().unwrap_or(U256::ZERO).to_le_bytes(),
+                    ("balance", [account]) => caller.data().balances.get(account).copied().unwrap_or(U256::ZERO).to_le_bytes(),
+                    ("log", [message]) => {
+                        caller.data_mut().logs.push(*message);
+                        return Ok(());
+                    }
+                    _ => return Err(wasmtime::Error::msg(format!("the test host has no `{}`", entry.name))),
+                };
+                write_word(&mut caller, params[entry.params.len()].unwrap_i32(), result);
+                Ok(())
+            }).unwrap();
+        }
+        linker
+    }
+
+    fn compile(source: &str) -> Vec<u8> {
+        let tokens = lexer::tokenize(source).expect("lexing failed");
+        let program = ir::lower(parser::parse(tokens).expect("parsing failed")).unwrap();
+        let mut modules = generate(&program).unwrap();
+        let bytes = modules.remove(0).bytes;
+        Module::validate(&Engine::default(), &bytes).expect("generated module is invalid");
+        bytes
+    }
+
+    /// A deployed contract. Like a chain, every call runs in a fresh
+    /// instance and only a call that returns commits its host state.
+    struct Contract {
+        engine: Engine,
+        module: Module,
+        host: Host,
+    }
+
+    impl Contract {
+        fn new(source: &str, host: Host) -> Self {
+            let engine = Engine::default();
+            let module = Module::new(&engine, compile(source)).unwrap();
+            Contract { engine, module, host }
+        }
+
+        /// Calls `function` with `gas`, returning its result word and the
+        /// gas left, or the error it trapped with
+        fn call(&mut self, function: &str, args: &[Word], gas: u64) -> Result<(Option<Word>, u64), String> {
+            let mut store = Store::new(&self.engine, self.host.clone());
+            let instance = linker(&self.engine).instantiate(&mut store, &self.module).unwrap();
+            let memory = instance.get_memory(&mut store, "memory").unwrap();
+            for (i, arg) in args.iter().enumerate() {
+                memory.write(&mut store, ARGS as usize + i * 32, arg).unwrap();
+            }
+            let meter = instance.get_global(&mut store, "gas").unwrap();
+            meter.set(&mut store, Val::I64(gas as i64)).unwrap();
+
+            let func = instance.get_func(&mut store, function).unwrap();
+            let mut results = vec![Val::I32(0); func.ty(&store).results().len()];
+            func.call(&mut store, &[], &mut results)
+                .map_err(|e| e.root_cause().to_string())?;
+
+            let result = results.first().map(|result| {
+                let mut word = [0; 32];
+                memory.read(&store, result.unwrap_i32() as usize, &mut word).unwrap();
+                word
+            });
+            let left = meter.get(&mut store).unwrap_i64() as u64;
+            self.host = store.into_data();
+            Ok((result, left))
+        }
+    }
+
+    const COUNTER: &str = r#"
+        contract Counter {
+            state count: u256;
+            state owner: Address;
+            event Incremented(by: Address, count: u256);
+            fn init() { owner = msg.sender; }
+            fn increment(by: u256) -> u256 {
+                count = count + by;
+                emit Incremented(msg.sender, count);
+                count
+            }
+            fn sum_to(n: u256) -> u256 {
+                let total = 0;
+                while n > 0 {
+                    total = total + n;
+                    n = n - 1;
+                }
+                total
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_storage_events_and_caller() {
+        let mut contract = Contract::new(COUNTER, Host { caller: address(7), ..Host::default() });
+        contract.call("init", &[], 1_000_000).unwrap();
+        assert_eq!(contract.call("increment", &[word(5)], 1_000_000).unwrap().0, Some(word(5)));
+        assert_eq!(contract.call("increment", &[word(2)], 1_000_000).unwrap().0, Some(word(7)));
+
+        let host = &contract.host;
+        assert_eq!(host.storage[&word(0)], word(7));
+        assert_eq!(host.storage[&word(1)], address(7));
+        assert_eq!(host.events.len(), 2);
+        assert_eq!(host.events[1], ("Incremented".to_string(), vec![address(7), word(7)]));
+    }
+
+    #[test]
+    fn test_loops_and_gas_metering() {
+        let mut contract = Contract::new(COUNTER, Host::default());
+        let (result, left) = contract.call("sum_to", &[word(10)], 1_000_000).unwrap();
+        assert_eq!(result, Some(word(55)));
+
+        // Each iteration costs the same, so gas grows linearly with n
+        let (_, left_20) = contract.call("sum_to", &[word(20)], 1_000_000).unwrap();
+        let (_, left_30) = contract.call("sum_to", &[word(30)], 1_000_000).unwrap();
+        assert_eq!(left - left_20, left_20 - left_30);
+        assert!(left_20 < left);
+
+        let used = 1_000_000 - left;
+        assert_eq!(contract.call("sum_to", &[word(10)], used).unwrap().1, 0);
+        assert_eq!(contract.call("sum_to", &[word(10)], used - 1), Err("out of gas".to_string()));
+    }
+
+    #[test]
+    fn test_full_width_arithmetic() {
+        let source = r#"
+            contract Math {
+                fn mul(a: u256, b: u256) -> u256 { a * b }
+                fn div(a: u256, b: u256) -> u256 { a / b }
+                fn sub(a: u256, b: u256) -> u256 { a - b }
+                fn lt(a: u256, b: u256) -> bool { a < b }
+            }
+        "#;
+        let mut contract = Contract::new(source, Host::default());
+        let big = U256::from(u64::MAX) * U256::from(1u64 << 40);
+        let call = |contract: &mut Contract, f: &str, a: U256, b: U256| {
+            contract.call(f, &[a.to_le_bytes(), b.to_le_bytes()], 1_000_000).map(|(r, _)| U256::from_le_bytes(r.unwrap()))
+        };
+
+        assert_eq!(call(&mut contract, "mul", big, big), Ok(big * big));
+        assert_eq!(call(&mut contract, "div", big * big + U256::from(9u64), big), Ok(big));
+        assert_eq!(call(&mut contract, "div", U256::MAX, U256::from(3u64)), Ok(U256::MAX / U256::from(3u64)));
+        // Divisors above 2^255 shift bits out of the remainder
+        let huge = U256::MAX / U256::from(2u64) + U256::from(2u64);
+        assert_eq!(call(&mut contract, "div", U256::MAX, huge), Ok(U256::ONE));
+        assert_eq!(call(&mut contract, "div", huge - U256::ONE, huge), Ok(U256::ZERO));
+        assert_eq!(call(&mut contract, "sub", big, U256::ONE), Ok(big - U256::ONE));
+        assert_eq!(call(&mut contract, "lt", big, big * big), Ok(U256::ONE));
+        assert_eq!(call(&mut contract, "lt", U256::MAX, big), Ok(U256::ZERO));
+
+        assert_eq!(call(&mut contract, "mul", U256::MAX, U256::from(2u64)), Err("revert: Arithmetic overflow".to_string()));
+        assert_eq!(call(&mut contract, "sub", U256::ZERO, U256::ONE), Err("revert: Arithmetic overflow".to_string()));
+        assert_eq!(call(&mut contract, "div", U256::ONE, U256::ZERO), Err("revert: Division by zero".to_string()));
+    }
+
+    #[test]
+    fn test_balance_transfer_and_reentrancy_guard() {
+        let source = r#"
+            contract Vault {
+                state paid: Map<Address, u256>;
+                @no_reentry
+                fn pay(to: Address, amount: u256) -> u256 {
+                    ensure!(amount > 0, "Zero amount");
+                    to.transfer(amount);
+                    paid[to] += amount;
+                    to.balance()
+                }
+                event Pinged();
+                @no_reentry
+                fn ping() {
+                    emit Pinged();
+                }
+            }
+        "#;
+        let mut host = Host::default();
+        host.balances.insert(address(0xcc), U256::from(100u64));
+        let mut contract = Contract::new(source, host);
+
+        let (result, _) = contract.call("pay", &[address(1), word(30)], 1_000_000).unwrap();
+        assert_eq!(result, Some(word(30)));
+        assert_eq!(contract.host.balances[&address(0xcc)], U256::from(70u64));
+        assert_eq!(contract.call("pay", &[address(1), word(0)], 1_000_000), Err("revert: Zero amount".to_string()));
+        assert_eq!(contract.call("pay", &[address(1), word(500)], 1_000_000), Err("insufficient balance".to_string()));
+
+        // `pay` calling back into itself from inside the transfer
+        let mut contract = Contract::new(source, Host { reenter: Some("pay".into()), ..Host::default() });
+        contract.host.balances.insert(address(0xcc), U256::from(100u64));
+        assert_eq!(contract.call("pay", &[address(1), word(30)], 1_000_000), Err("revert: Reentrant call detected".to_string()));
+
+        // Each guarded function has its own lock
+        let mut contract = Contract::new(source, Host { reenter: Some("ping".into()), ..Host::default() });
+        contract.host.balances.insert(address(0xcc), U256::from(100u64));
+        let (result, _) = contract.call("pay", &[address(1), word(30)], 1_000_000).unwrap();
+        assert_eq!(result, Some(word(30)));
+        assert_eq!(contract.host.events.len(), 1);
+    }
+
+    #[test]
+    fn test_host_functions_are_imported_from_the_table() {
+        let source = r#"
+            contract Clock {
+                state stamped: u256;
+                fn stamp() -> u256 {
+                    log("stamping");
+                    stamped = block.timestamp;
+                    self.balance
+                }
+            }
+        "#;
+        let mut contract = Contract::new(source, Host { timestamp: 1_700_000_000, ..Host::default() });
+        contract.host.balances.insert(address(0xcc), U256::from(40u64));
+        let imports = contract.module.imports().filter(|import| import.module() == IMPORT_MODULE).count();
+        assert_eq!(imports, IMPORT_COUNT);
+        assert!(contract.module.exports().any(|export| export.name() == "host_version"));
+
+        let (result, left) = contract.call("stamp", &[], 1_000_000).unwrap();
+        assert_eq!(result, Some(word(40)));
+        assert_eq!(contract.host.storage[&word(0)], word(1_700_000_000));
+        let mut message = [0; 32];
+        message[..8].copy_from_slice(b"stamping");
+        assert_eq!(contract.host.logs, [message]);
+
+        // The instructions' own gas, and then each host function's
+        let tokens = lexer::tokenize(source).unwrap();
+        let program = ir::lower(parser::parse(tokens).unwrap()).unwrap();
+        let host_gas: u64 = ["log", "block.timestamp", "self.balance"].iter()
+            .map(|name| host::lookup(name).unwrap().1.gas)
+            .sum();
+        assert_eq!(1_000_000 - left, crate::optimizer::static_gas(&program) + host_gas);
+    }
+
+    #[test]
+    fn test_unsupported_instructions_are_reported() {
+        let tokens = lexer::tokenize("contract C { fn f() -> u256 { let v = vec![1, 2]; v.len() } }").unwrap();
+        let program = ir::lower(parser::parse(tokens).unwrap()).unwrap();
+        let error = generate(&program).err().unwrap();
+        assert!(error.starts_with("C: f: "), "{}", error);
+        assert!(error.contains("has no wasm equivalent yet"), "{}", error);
+    }
+}
@@ -0,0 +1,238 @@
+//! Helper functions every module carries: word allocation and 256-bit
+//! arithmetic over little-endian words, plus the gas charge made at the
+//! top of each basic block.
+
+use super::encode::{self, Instr, ValType};
+use super::{Generator, GAS, HEAP, IMPORT_COUNT, ONE, OUT_OF_GAS, REMAINDER, SCRATCH, ZERO};
+use Instr::*;
+
+/// Function indices of the helpers
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Runtime {
+    /// `() -> word`: a fresh zeroed word
+    pub alloc: u32,
+    /// `(dst, src)`
+    pub copy: u32,
+    /// `(dst, a, b) -> carry`
+    pub add_into: u32,
+    /// `(dst, a, b) -> borrow`; `dst` may be `a`
+    pub sub_into: u32,
+    /// `(a, b) -> word`; these four revert on overflow or division by zero
+    pub add: u32,
+    pub sub: u32,
+    pub mul: u32,
+    pub div: u32,
+    /// `(a, b) -> -1, 0 or 1`
+    pub cmp: u32,
+    /// `(a) -> i32`
+    pub is_zero: u32,
+    /// `(i32) -> word`: the constant `true` or `false`
+    pub bool: u32,
+    /// `(i64)`: takes gas or calls `out_of_gas`
+    pub charge: u32,
+}
+
+impl Generator<'_> {
+    pub(super) fn add_runtime(&mut self) {
+        let base = (IMPORT_COUNT + self.module.functions.len()) as u32;
+        let rt = Runtime {
+            alloc: base,
+            copy: base + 1,
+            add_into: base + 2,
+            sub_into: base + 3,
+            add: base + 4,
+            sub: base + 5,
+            mul: base + 6,
+            div: base + 7,
+            cmp: base + 8,
+            is_zero: base + 9,
+            bool: base + 10,
+            charge: base + 11,
+        };
+        self.runtime = rt;
+        let overflow = self.revert("Arithmetic overflow");
+        let division_by_zero = self.revert("Division by zero");
+
+        // alloc: bump the heap pointer, growing memory a page at a time
+        self.helper(0, true, &[ValType::I32], vec![
+            GlobalGet(HEAP), LocalTee(0), I32Const(32), I32Add, GlobalSet(HEAP),
+            GlobalGet(HEAP), MemorySize, I32Const(16), I32Shl, I32GtU, If,
+                I32Const(1), MemoryGrow, I32Const(-1), I32Eq, If, Unreachable, End,
+            End,
+            LocalGet(0),
+        ]);
+
+        // copy
+        let mut body = Vec::new();
+        for offset in limbs() {
+            body.extend([LocalGet(0), LocalGet(1), I64Load(offset), I64Store(offset)]);
+        }
+        self.helper(2, false, &[], body);
+
+        // add_into: limb by limb with carry; locals x, s, carry
+        let mut body = Vec::new();
+        for offset in limbs() {
+            body.extend([
+                LocalGet(1), I64Load(offset), LocalSet(3),
+                LocalGet(3), LocalGet(2), I64Load(offset), I64Add, LocalSet(4),
+                // carry out of a + b, then of adding the carry in
+                LocalGet(4), LocalGet(3), I64LtU, I64ExtendI32U,
+                LocalGet(4), LocalGet(5), I64Add, LocalTee(3),
+                LocalGet(4), I64LtU, I64ExtendI32U, I64Or, LocalSet(5),
+                LocalGet(0), LocalGet(3), I64Store(offset),
+            ]);
+        }
+        body.extend([LocalGet(5), I32WrapI64]);
+        self.helper(3, true, &[ValType::I64; 3], body);
+
+        // sub_into: limb by limb with borrow; locals x, y, borrow
+        let mut body = Vec::new();
+        for offset in limbs() {
+            body.extend([
+                LocalGet(1), I64Load(offset), LocalSet(3),
+                LocalGet(2), I64Load(offset), LocalSet(4),
+                LocalGet(3), LocalGet(4), I64LtU, I64ExtendI32U,
+                LocalGet(3), LocalGet(4), I64Sub, LocalTee(3),
+                LocalGet(5), I64LtU, I64ExtendI32U,
+                LocalGet(0), LocalGet(3), LocalGet(5), I64Sub, I64Store(offset),
+                I64Or, LocalSet(5),
+            ]);
+        }
+        body.extend([LocalGet(5), I32WrapI64]);
+        self.helper(3, true, &[ValType::I64; 3], body);
+
+        // add and sub
+        for into in [rt.add_into, rt.sub_into] {
+            let mut body = vec![Call(rt.alloc), LocalTee(2), LocalGet(0), LocalGet(1), Call(into), If];
+            body.extend(overflow.clone());
+            body.extend([End, LocalGet(2)]);
+            self.helper(2, true, &[ValType::I32], body);
+        }
+
+        // mul: schoolbook over 32-bit limbs into 16 columns at SCRATCH;
+        // locals i, j, column, a_i, t, carry, result
+        let mut body = Vec::new();
+        for offset in (0..64).step_by(8) {
+            body.extend([I32Const(0), I64Const(0), I64Store(SCRATCH + offset)]);
+        }
+        body.extend([
+            I32Const(0), LocalSet(2),
+            Block, Loop,
+                LocalGet(2), I32Const(8), I32GeU, BrIf(1),
+                LocalGet(0), LocalGet(2), I32Const(2), I32Shl, I32Add, I32Load(0), I64ExtendI32U, LocalSet(5),
+                I64Const(0), LocalSet(7),
+                I32Const(0), LocalSet(3),
+                Block, Loop,
+                    LocalGet(3), I32Const(8), I32GeU, BrIf(1),
+                    LocalGet(2), LocalGet(3), I32Add, I32Const(2), I32Shl, LocalSet(4),
+                    // t = a_i * b_j + column + carry, which cannot overflow 64 bits
+                    LocalGet(5),
+                    LocalGet(1), LocalGet(3), I32Const(2), I32Shl, I32Add, I32Load(0), I64ExtendI32U,
+                    I64Mul,
+                    LocalGet(4), I32Load(SCRATCH), I64ExtendI32U, I64Add,
+                    LocalGet(7), I64Add, LocalSet(6),
+                    LocalGet(4), LocalGet(6), I32WrapI64, I32Store(SCRATCH),
+                    LocalGet(6), I64Const(32), I64ShrU, LocalSet(7),
+                    LocalGet(3), I32Const(1), I32Add, LocalSet(3), Br(0),
+                End, End,
+                LocalGet(2), I32Const(2), I32Shl, LocalGet(7), I32WrapI64, I32Store(SCRATCH + 32),
+                LocalGet(2), I32Const(1), I32Add, LocalSet(2), Br(0),
+            End, End,
+            // Any bit in the upper half is an overflow
+            I32Const((SCRATCH + 32) as i32), Call(rt.is_zero), I32Eqz, If,
+        ]);
+        body.extend(overflow);
+        body.extend([
+            End,
+            Call(rt.alloc), LocalTee(8), I32Const(SCRATCH as i32), Call(rt.copy), LocalGet(8),
+        ]);
+        self.helper(2, true, &[ValType::I32, ValType::I32, ValType::I32, ValType::I64, ValType::I64, ValType::I64, ValType::I32], body);
+
+        // div: restoring division one bit at a time, keeping the remainder
+        // at REMAINDER; locals quotient, bit, limb address, bit shifted out
+        let mut body = vec![LocalGet(1), Call(rt.is_zero), If];
+        body.extend(division_by_zero);
+        body.extend([End, Call(rt.alloc), LocalSet(2)]);
+        for offset in limbs() {
+            body.extend([I32Const(0), I64Const(0), I64Store(REMAINDER + offset)]);
+        }
+        body.extend([I32Const(255), LocalSet(3), Block, Loop]);
+        // remainder = remainder << 1 | bit of a, noting the bit shifted out:
+        // with it set the remainder exceeds b and wrapping subtraction is exact
+        body.extend([I32Const(0), I64Load(REMAINDER + 24), I64Const(63), I64ShrU, I32WrapI64, LocalSet(5)]);
+        for offset in [24, 16, 8] {
+            body.extend([
+                I32Const(0),
+                I32Const(0), I64Load(REMAINDER + offset), I64Const(1), I64Shl,
+                I32Const(0), I64Load(REMAINDER + offset - 8), I64Const(63), I64ShrU, I64Or,
+                I64Store(REMAINDER + offset),
+            ]);
+        }
+        body.extend([
+            I32Const(0),
+            I32Const(0), I64Load(REMAINDER), I64Const(1), I64Shl,
+            LocalGet(0), LocalGet(3), I32Const(6), I32ShrU, I32Const(3), I32Shl, I32Add, I64Load(0),
+            LocalGet(3), I32Const(63), I32And, I64ExtendI32U, I64ShrU, I64Const(1), I64And,
+            I64Or, I64Store(REMAINDER),
+            // if remainder >= b, subtract it and set the quotient bit
+            I32Const(REMAINDER as i32), LocalGet(1), Call(rt.cmp), I32Const(-1), I32Ne, LocalGet(5), I32Or, If,
+                I32Const(REMAINDER as i32), I32Const(REMAINDER as i32), LocalGet(1), Call(rt.sub_into), Drop,
+                LocalGet(2), LocalGet(3), I32Const(6), I32ShrU, I32Const(3), I32Shl, I32Add, LocalTee(4),
+                LocalGet(4), I64Load(0),
+                I64Const(1), LocalGet(3), I32Const(63), I32And, I64ExtendI32U, I64Shl,
+                I64Or, I64Store(0),
+            End,
+            LocalGet(3), I32Eqz, BrIf(1),
+            LocalGet(3), I32Const(1), I32Sub, LocalSet(3), Br(0),
+            End, End,
+            LocalGet(2),
+        ]);
+        self.helper(2, true, &[ValType::I32; 4], body);
+
+        // cmp: from the most significant limb down; locals x, y
+        let mut body = Vec::new();
+        for offset in limbs().rev() {
+            body.extend([
+                LocalGet(0), I64Load(offset), LocalSet(2),
+                LocalGet(1), I64Load(offset), LocalSet(3),
+                LocalGet(2), LocalGet(3), I64Ne, If,
+                    I32Const(-1), I32Const(1), LocalGet(2), LocalGet(3), I64LtU, Select, Return,
+                End,
+            ]);
+        }
+        body.push(I32Const(0));
+        self.helper(2, true, &[ValType::I64; 2], body);
+
+        // is_zero
+        self.helper(1, true, &[], vec![
+            LocalGet(0), I64Load(0), LocalGet(0), I64Load(8), I64Or,
+            LocalGet(0), I64Load(16), I64Or, LocalGet(0), I64Load(24), I64Or,
+            I64Eqz,
+        ]);
+
+        // bool
+        self.helper(1, true, &[], vec![I32Const(ONE as i32), I32Const(ZERO as i32), LocalGet(0), Select]);
+
+        // charge
+        let ty = self.module.intern_type(encode::FuncType { params: vec![ValType::I64], results: Vec::new() });
+        self.module.functions.push(encode::Function {
+            ty,
+            locals: Vec::new(),
+            body: vec![
+                GlobalGet(GAS), LocalGet(0), I64LtU, If, Call(OUT_OF_GAS), Unreachable, End,
+                GlobalGet(GAS), LocalGet(0), I64Sub, GlobalSet(GAS),
+            ],
+        });
+    }
+
+    /// Adds a helper taking `params` words
+    fn helper(&mut self, params: usize, result: bool, locals: &[ValType], body: Vec<Instr>) {
+        let ty = self.func_type(params, result);
+        self.module.functions.push(encode::Function { ty, locals: locals.to_vec(), body });
+    }
+}
+
+/// Byte offsets of the four 64-bit limbs of a word, least significant first
+fn limbs() -> impl DoubleEndedIterator<Item = u32> {
+    (0..32).step_by(8)
+}
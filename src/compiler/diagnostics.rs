@@ -0,0 +1,238 @@
+use std::fmt::Write;
+use crate::span::Span;
+
+/// A source file with a precomputed line index for span lookups
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        let text = text.into();
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            name: name.into(),
+            text,
+            line_starts,
+        }
+    }
+
+    /// 1-based line and column of a byte offset
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let column = self.text[self.line_starts[line]..offset].chars().count() + 1;
+        (line + 1, column)
+    }
+
+    /// Text of a 1-based line without its line terminator
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).copied().unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// All files of a compilation laid out in one global offset space, so spans
+/// from different files never collide
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<(usize, SourceFile)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: Vec::new() }
+    }
+
+    /// Adds a file and returns the global offset of its first byte
+    pub fn add_file(&mut self, name: impl Into<String>, text: impl Into<String>) -> usize {
+        let base = self.files.last()
+            .map_or(0, |(base, file)| base + file.text.len() + 1);
+        self.files.push((base, SourceFile::new(name, text)));
+        base
+    }
+
+    fn lookup(&self, offset: usize) -> Option<&(usize, SourceFile)> {
+        self.files.iter().rev().find(|(base, _)| *base <= offset)
+    }
+
+    /// The file holding a global offset, and the offset's 1-based line and
+    /// column in it
+    pub fn locate(&self, offset: usize) -> Option<(&str, usize, usize)> {
+        let (base, file) = self.lookup(offset)?;
+        let (line, column) = file.location(offset - base);
+        Some((&file.name, line, column))
+    }
+
+    /// Renders a diagnostic whose spans are global offsets. Secondary labels
+    /// pointing into another file than the primary one are left out.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let (base, file) = match self.lookup(diagnostic.primary.span.start) {
+            Some(entry) => entry,
+            None => return format!("error: {}\n", diagnostic.message),
+        };
+        let end = base + file.text.len();
+        let local = |label: &Label| Label::new(
+            Span::new(label.span.start - base, label.span.end.min(end) - base),
+            label.message.clone(),
+        );
+
+        let mut rebased = diagnostic.clone();
+        rebased.primary = local(&diagnostic.primary);
+        rebased.secondary = diagnostic.secondary.iter()
+            .filter(|label| label.span.start >= *base && label.span.start <= end)
+            .map(local)
+            .collect();
+        rebased.render(file)
+    }
+}
+
+/// A span with an optional message attached to it
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Label { span, message: message.into() }
+    }
+}
+
+/// A located compiler message with one primary and any number of secondary labels
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, primary: Label) -> Self {
+        Diagnostic {
+            message: message.into(),
+            primary,
+            secondary: Vec::new(),
+        }
+    }
+
+    /// Renders the diagnostic in rustc style with caret underlines
+    pub fn render(&self, file: &SourceFile) -> String {
+        let mut out = String::new();
+        let (line, column) = file.location(self.primary.span.start);
+
+        let mut labels: Vec<(&Label, bool)> = vec![(&self.primary, true)];
+        labels.extend(self.secondary.iter().map(|label| (label, false)));
+        labels.sort_by_key(|(label, _)| label.span.start);
+
+        let max_line = labels.iter()
+            .map(|(label, _)| file.location(label.span.start).0)
+            .max()
+            .unwrap_or(line);
+        let gutter = " ".repeat(max_line.to_string().len());
+
+        let _ = writeln!(out, "error: {}", self.message);
+        let _ = writeln!(out, "{}--> {}:{}:{}", gutter, file.name, line, column);
+        let _ = writeln!(out, "{} |", gutter);
+
+        let mut previous_line = None;
+        for (label, is_primary) in labels {
+            let (label_line, label_column) = file.location(label.span.start);
+            let text = file.line_text(label_line);
+
+            if let Some(previous) = previous_line {
+                if label_line > previous + 1 {
+                    let _ = writeln!(out, "...");
+                }
+            }
+            if previous_line != Some(label_line) {
+                let _ = writeln!(out, "{:>width$} | {}", label_line, text, width = gutter.len());
+            }
+            previous_line = Some(label_line);
+
+            // Multi-line spans are underlined up to the end of their first line
+            let line_chars = text.chars().count();
+            let (end_line, end_column) = file.location(label.span.end);
+            let underline_end = if end_line == label_line { end_column } else { line_chars + 1 };
+            let width = underline_end.saturating_sub(label_column).max(1);
+            let marker = if is_primary { "^" } else { "-" };
+
+            let _ = write!(
+                out,
+                "{} | {}{}",
+                gutter,
+                " ".repeat(label_column - 1),
+                marker.repeat(width)
+            );
+            if !label.message.is_empty() {
+                let _ = write!(out, " {}", label.message);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        let file = SourceFile::new("a.strx", "contract A {\n    state x: u256;\n}");
+        assert_eq!(file.location(0), (1, 1));
+        assert_eq!(file.location(13), (2, 1));
+        assert_eq!(file.location(23), (2, 11));
+        assert_eq!(file.line_text(2), "    state x: u256;");
+    }
+
+    #[test]
+    fn test_render_primary_and_secondary() {
+        let source = "event E(a: u256);\nfn f() {\n    emit E(true);\n}";
+        let file = SourceFile::new("e.strx", source);
+        let arg = source.find("true").unwrap();
+        let mut diagnostic = Diagnostic::error(
+            "mismatched types",
+            Label::new(Span::new(arg, arg + 4), "expected U256, found Bool"),
+        );
+        diagnostic.secondary.push(Label::new(Span::new(0, 17), "event declared here"));
+
+        let rendered = diagnostic.render(&file);
+        let expected = "\
+error: mismatched types
+ --> e.strx:3:12
+  |
+1 | event E(a: u256);
+  | ----------------- event declared here
+...
+3 |     emit E(true);
+  |            ^^^^ expected U256, found Bool
+";
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn test_source_map_rebases_spans() {
+        let mut map = SourceMap::new();
+        map.add_file("a.strx", "contract A {}");
+        let base = map.add_file("b.strx", "contract B {\n    oops\n}");
+        let start = base + 17;
+        let diagnostic = Diagnostic::error("expected item", Label::new(Span::new(start, start + 4), ""));
+
+        let rendered = map.render(&diagnostic);
+        assert!(rendered.contains("--> b.strx:2:5"));
+        assert!(rendered.contains("2 |     oops"));
+        assert_eq!(map.locate(start), Some(("b.strx", 2, 5)));
+        assert_eq!(map.locate(3), Some(("a.strx", 1, 4)));
+    }
+}
@@ -1,5 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
 use crate::ast;
+use crate::bytecode::{self, host};
+use crate::num::U256;
 
 #[derive(Debug, Clone)]
 pub struct Program {
@@ -10,9 +14,16 @@ pub struct Program {
 pub struct Contract {
     pub name: String,
     pub storage: Vec<StorageSlot>,
+    pub events: Vec<Event>,
     pub functions: Vec<Function>,
 }
 
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub params: Vec<Parameter>,
+}
+
 #[derive(Debug, Clone)]
 pub struct StorageSlot {
     pub name: String,
@@ -51,55 +62,100 @@ pub enum Type {
     String,
     Array(Box<Type>),
     Map { key: Box<Type>, value: Box<Type> },
+    Result { ok: Box<Type>, err: Box<Type> },
+    /// `()` is the empty tuple
+    Tuple(Vec<Type>),
+    /// `Option<T>`, `Set<T>` and other generic library types
+    Generic { name: String, arguments: Vec<Type> },
+    /// A struct, enum or library type referred to by name
+    Named(String),
     Void,
+    /// A temporary whose type the lowering could not work out
+    Unknown,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     // Stack operations
     Push(Value),
     Pop,
     Dup(u8),
-    Swap(u8),
-    
+
     // Memory operations
     Load(u32),  // Load from local variable
     Store(u32), // Store to local variable
     SLoad(u32), // Load from storage
     SStore(u32), // Store to storage
-    
+    MapSlot,  // Hash a slot and the key above it into the key's slot
+    SLoadAt,  // Load from the storage slot on top of the stack
+    SStoreAt, // Store the top value to the storage slot beneath it
+
     // Arithmetic
     Add,
     Sub,
     Mul,
     Div,
-    
+
     // Comparison
     Eq,
     Lt,
     Gt,
     LtEq,
     GtEq,
-    
+
+    // Logic
+    Not,
+
+    // Aggregates
+    Tuple(u8),                   // Pack the top n values
+    Array(u8),                   // Pack the top n values into an array
+    Struct(String, Vec<String>), // Struct name and the order its fields were pushed in
+    Variant(String, u8),         // Enum variant path and number of payload values
+    IsVariant(String),           // Whether the top value is the named variant
+    Extract(u8),                 // Element n of a tuple or variant payload
+    GetField(String),
+    SetField(String), // Replace a field of the value beneath the top
+    Index,
+    SetIndex,         // Pops the collection, index and new element
+    Cast(Type),
+
     // Control flow
     Jump(Label),
     JumpIf(Label),
     Label(Label),
     Call(String, u8), // Function name and number of arguments
+    CallMethod(String, u8), // Method name and number of arguments after the receiver
+    CallIndirect(u8), // Call the closure beneath the arguments
+    Closure(String, u8), // Lifted function and number of captured values
     Return,
-    
+    Revert(String), // Abort the transaction with a message
+
     // Blockchain specific
     EmitEvent(String, u8), // Event name and number of arguments
     NoReentry(Label, Label), // Start and end labels
-    
-    // Memory management
-    Alloc(Type),
-    Free,
+    Env(String), // Environment value such as `msg.sender` or `self`
+    Acquire(String), // Make the struct on top a new resource of the named kind
+    Release(String), // Consume the resource of the named kind on top
+
+    // Debug info, only emitted by `lower_with_debug_info`; runs as nothing
+    Debug(DebugMark),
 }
 
-#[derive(Debug, Clone)]
+/// Where source-level debug info attaches to the instruction stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugMark {
+    /// The instructions that follow belong to the statement or match arm
+    /// starting at this global source offset
+    Statement(usize),
+    /// Local `n` holds a named variable from here on
+    Bind(u32),
+    /// Local `n` goes out of scope
+    Unbind(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    U256(u64), // Simplified for example
+    U256(U256),
     Address([u8; 20]),
     Bool(bool),
     String(String),
@@ -108,12 +164,62 @@ pub enum Value {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Label(pub String);
 
+/// How a name in scope is stored. `let p = &mut proposals[id]` binds `p`
+/// to the storage slot of the entry rather than to a copy of it, so writes
+/// through `p` reach storage.
+#[derive(Debug, Clone, Copy)]
+enum Binding {
+    Local(u32),
+    StorageRef(u32),
+}
+
+/// A storage location: either a state variable's own slot or one derived
+/// from it by hashing in map keys and struct field names
+enum StoragePlace {
+    Static(u32),
+    Dynamic(Vec<Instruction>),
+}
+
+impl StoragePlace {
+    /// Instructions that push the slot
+    fn into_slot(self) -> Vec<Instruction> {
+        match self {
+            StoragePlace::Static(slot) => vec![Instruction::Push(Value::U256(U256::from(slot)))],
+            StoragePlace::Dynamic(code) => code,
+        }
+    }
+}
+
+/// Environment objects whose members are provided by the host
+const ENV_OBJECTS: [&str; 4] = ["msg", "block", "tx", "chain"];
+
+/// `Duration`s are seconds
+const SECONDS_PER_DAY: u64 = 86_400;
+
 pub struct IRBuilder {
     current_contract: Option<Contract>,
     current_function: Option<Function>,
     label_counter: u32,
     local_counter: u32,
     storage_counter: u32,
+    closure_counter: u32,
+    // Declarations of the current contract
+    aliases: HashMap<String, ast::Type>,
+    structs: HashMap<String, Vec<(String, Type)>>,
+    resources: HashSet<String>,
+    enums: HashMap<String, Vec<(String, U256)>>,
+    functions: HashMap<String, Option<Type>>,
+    // Names in scope, innermost block last
+    scopes: Vec<Vec<(String, Binding)>>,
+    // Where `return` jumps to in a `@no_reentry` function
+    guard_exit: Option<Label>,
+    // Closures lifted out of the current contract's functions
+    lifted: Vec<Function>,
+    // Whether to emit `Instruction::Debug` marks, and the named locals
+    // declared since the last one
+    debug_info: bool,
+    unbound: Vec<u32>,
+    errors: Vec<String>,
 }
 
 impl IRBuilder {
@@ -124,19 +230,34 @@ impl IRBuilder {
             label_counter: 0,
             local_counter: 0,
             storage_counter: 0,
+            closure_counter: 0,
+            aliases: HashMap::new(),
+            structs: HashMap::new(),
+            resources: HashSet::new(),
+            enums: HashMap::new(),
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+            guard_exit: None,
+            lifted: Vec::new(),
+            debug_info: false,
+            unbound: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
-    pub fn build(mut self, ast: &ast::Program) -> Program {
+    pub fn build(mut self, ast: &ast::Program) -> Result<Program, String> {
         let mut contracts = Vec::new();
-        
+
         for ast_contract in &ast.contracts {
             self.current_contract = Some(Contract {
                 name: ast_contract.name.clone(),
                 storage: Vec::new(),
+                events: Vec::new(),
                 functions: Vec::new(),
             });
-            
+            self.storage_counter = 0;
+            self.collect_declarations(ast_contract);
+
             // Convert state variables to storage slots
             for var in &ast_contract.state_vars {
                 let slot = StorageSlot {
@@ -147,36 +268,166 @@ impl IRBuilder {
                 self.storage_counter += 1;
                 self.current_contract.as_mut().unwrap().storage.push(slot);
             }
-            
-            // Convert functions
-            for ast_fn in &ast_contract.functions {
-                self.current_function = Some(Function {
-                    name: ast_fn.name.clone(),
-                    params: ast_fn.parameters.iter()
+
+            for event in &ast_contract.events {
+                let event = Event {
+                    name: event.name.clone(),
+                    params: event.parameters.iter()
                         .map(|p| Parameter {
                             name: p.name.clone(),
                             ty: self.convert_type(&p.type_info),
                         })
                         .collect(),
-                    return_type: ast_fn.return_type.as_ref()
-                        .map(|t| self.convert_type(t)),
-                    body: Vec::new(),
-                    locals: Vec::new(),
-                    is_pure: ast_fn.is_pure,
-                });
-                
-                // Convert function body
-                let body = self.convert_block(&ast_fn.body);
-                self.current_function.as_mut().unwrap().body = body;
-                
-                self.current_contract.as_mut().unwrap()
-                    .functions.push(self.current_function.take().unwrap());
-            }
-            
-            contracts.push(self.current_contract.take().unwrap());
-        }
-        
-        Program { contracts }
+                };
+                self.current_contract.as_mut().unwrap().events.push(event);
+            }
+
+            // Convert functions
+            for ast_fn in &ast_contract.functions {
+                let function = self.convert_function(ast_fn, ast_contract);
+                self.current_contract.as_mut().unwrap().functions.push(function);
+            }
+
+            // State initializers need a constructor to run in
+            let has_initializers = ast_contract.state_vars.iter().any(|v| v.initializer.is_some());
+            if has_initializers && !ast_contract.functions.iter().any(|f| f.name == "init") {
+                let function = self.convert_implicit_init(ast_contract);
+                self.current_contract.as_mut().unwrap().functions.push(function);
+            }
+
+            let mut contract = self.current_contract.take().unwrap();
+            contract.functions.append(&mut self.lifted);
+            contracts.push(contract);
+        }
+
+        if self.errors.is_empty() {
+            Ok(Program { contracts })
+        } else {
+            Err(self.errors.join("\n"))
+        }
+    }
+
+    fn collect_declarations(&mut self, contract: &ast::Contract) {
+        self.aliases = contract.type_aliases.iter()
+            .map(|alias| (alias.name.clone(), alias.type_info.clone()))
+            .collect();
+
+        self.structs = HashMap::new();
+        for decl in &contract.structs {
+            let fields = decl.fields.iter()
+                .map(|field| (field.name.clone(), self.convert_type(&field.type_info)))
+                .collect();
+            self.structs.insert(decl.name.clone(), fields);
+        }
+        self.resources = contract.structs.iter()
+            .filter(|decl| decl.is_resource)
+            .map(|decl| decl.name.clone())
+            .collect();
+
+        // Variants without an explicit discriminant count up from the previous one
+        self.enums = HashMap::new();
+        for decl in &contract.enums {
+            let mut next = U256::ZERO;
+            let mut variants = Vec::new();
+            for variant in &decl.variants {
+                if let Some(ast::ExpressionKind::NumberLiteral(n)) = variant.discriminant.as_ref().map(|d| &d.kind) {
+                    next = n.parse().expect("the parser only accepts literals that fit in u256");
+                }
+                variants.push((variant.name.clone(), next));
+                next = next.saturating_add(U256::ONE);
+            }
+            self.enums.insert(decl.name.clone(), variants);
+        }
+
+        self.functions = HashMap::new();
+        for function in &contract.functions {
+            let return_type = function.return_type.as_ref().map(|t| self.convert_type(t));
+            self.functions.insert(function.name.clone(), return_type);
+        }
+    }
+
+    fn convert_function(&mut self, ast_fn: &ast::Function, contract: &ast::Contract) -> Function {
+        let params = ast_fn.parameters.iter()
+            .map(|p| Parameter {
+                name: p.name.clone(),
+                ty: self.convert_type(&p.type_info),
+            })
+            .collect();
+        self.begin_function(&ast_fn.name, params, self.functions[&ast_fn.name].clone(), ast_fn.is_pure);
+
+        let mut body = self.bind_declared();
+        if ast_fn.modifiers.iter().any(|m| matches!(m, ast::Modifier::NoReentry)) {
+            let start = self.new_label("guard");
+            let end = self.new_label("unguard");
+            body.push(Instruction::NoReentry(start, end.clone()));
+            self.guard_exit = Some(end);
+        }
+        // `@only_owner` calls `only_owner()`, which returns nothing
+        for modifier in &ast_fn.modifiers {
+            if let ast::Modifier::Custom(name) = modifier {
+                body.push(Instruction::Call(name.clone(), 0));
+            }
+        }
+        if ast_fn.name == "init" {
+            body.extend(self.convert_initializers(contract));
+        }
+
+        let returns_value = ast_fn.return_type.is_some();
+        body.extend(self.convert_body(&ast_fn.body, returns_value));
+        self.finish_function(body)
+    }
+
+    fn convert_implicit_init(&mut self, contract: &ast::Contract) -> Function {
+        self.begin_function("init", Vec::new(), None, false);
+        let body = self.convert_initializers(contract);
+        self.finish_function(body)
+    }
+
+    /// Stores the initial value of each state variable that has one
+    fn convert_initializers(&mut self, contract: &ast::Contract) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        for (slot, var) in contract.state_vars.iter().enumerate() {
+            if let Some(initializer) = &var.initializer {
+                instructions.extend(self.convert_expression(initializer));
+                instructions.push(Instruction::SStore(slot as u32));
+            }
+        }
+        instructions
+    }
+
+    fn begin_function(&mut self, name: &str, params: Vec<Parameter>, return_type: Option<Type>, is_pure: bool) {
+        self.current_function = Some(Function {
+            name: name.to_string(),
+            params: Vec::new(),
+            return_type,
+            body: Vec::new(),
+            locals: Vec::new(),
+            is_pure,
+        });
+        self.label_counter = 0;
+        self.local_counter = 0;
+        self.closure_counter = 0;
+        self.scopes = vec![Vec::new()];
+        self.guard_exit = None;
+        self.unbound.clear();
+
+        // Arguments occupy the first locals of each frame
+        for param in &params {
+            self.new_local(&param.name, param.ty.clone());
+        }
+        self.function().params = params;
+    }
+
+    fn finish_function(&mut self, mut body: Vec<Instruction>) -> Function {
+        if let Some(end) = self.guard_exit.take() {
+            body.push(Instruction::Label(end));
+            body.push(Instruction::Return);
+        } else if !matches!(body.last(), Some(Instruction::Return)) {
+            body.push(Instruction::Return);
+        }
+        let mut function = self.current_function.take().unwrap();
+        function.body = body;
+        function
     }
 
     fn convert_type(&self, ast_type: &ast::Type) -> Type {
@@ -190,179 +441,1901 @@ impl IRBuilder {
                 key: Box::new(self.convert_type(key_type)),
                 value: Box::new(self.convert_type(value_type)),
             },
-            _ => Type::Void, // Handle other cases
+            ast::Type::Result { ok_type, err_type } => Type::Result {
+                ok: Box::new(self.convert_type(ok_type)),
+                err: Box::new(self.convert_type(err_type)),
+            },
+            ast::Type::Tuple(elements) => Type::Tuple(
+                elements.iter().map(|t| self.convert_type(t)).collect()
+            ),
+            ast::Type::Generic { name, arguments } => Type::Generic {
+                name: name.clone(),
+                arguments: arguments.iter().map(|t| self.convert_type(t)).collect(),
+            },
+            ast::Type::Custom(name) => match self.aliases.get(name) {
+                Some(aliased) => self.convert_type(aliased),
+                None => Type::Named(name.clone()),
+            },
+            // Parse errors never reach lowering
+            ast::Type::Error => Type::Unknown,
+        }
+    }
+
+    // Statements
+
+    /// Lowers a function body. With `returns_value`, a trailing expression
+    /// without a semicolon is the return value.
+    fn convert_body(&mut self, block: &ast::Block, returns_value: bool) -> Vec<Instruction> {
+        self.scopes.push(Vec::new());
+        let mut instructions = Vec::new();
+
+        for (index, stmt) in block.statements.iter().enumerate() {
+            let is_last = index + 1 == block.statements.len();
+            instructions.extend(self.statement_marks(stmt));
+            match &stmt.kind {
+                ast::StatementKind::Expression { expression, has_semicolon: false } if is_last && returns_value => {
+                    instructions.extend(self.convert_expression(expression));
+                    instructions.extend(self.return_instructions());
+                }
+                _ => instructions.extend(self.convert_statement(stmt)),
+            }
         }
+
+        instructions.extend(self.end_scope());
+        instructions
     }
 
     fn convert_block(&mut self, block: &ast::Block) -> Vec<Instruction> {
+        self.scopes.push(Vec::new());
         let mut instructions = Vec::new();
-        
+
         for stmt in &block.statements {
+            instructions.extend(self.statement_marks(stmt));
+            instructions.extend(self.convert_statement(stmt));
+        }
+
+        instructions.extend(self.end_scope());
+        instructions
+    }
+
+    /// Lowers a block used as a value: its trailing expression, or `()`
+    fn convert_block_value(&mut self, block: &ast::Block) -> Vec<Instruction> {
+        self.scopes.push(Vec::new());
+        let mut instructions = Vec::new();
+
+        let (last, rest) = match block.statements.split_last() {
+            Some(split) => split,
+            None => {
+                self.scopes.pop();
+                return vec![Instruction::Tuple(0)];
+            }
+        };
+        for stmt in rest {
+            instructions.extend(self.statement_marks(stmt));
             instructions.extend(self.convert_statement(stmt));
         }
-        
+        instructions.extend(self.statement_marks(last));
+        match &last.kind {
+            ast::StatementKind::Expression { expression, has_semicolon: false } => {
+                instructions.extend(self.convert_expression(expression));
+            }
+            ast::StatementKind::Return(_) => instructions.extend(self.convert_statement(last)),
+            _ => {
+                instructions.extend(self.convert_statement(last));
+                instructions.push(Instruction::Tuple(0));
+            }
+        }
+
+        instructions.extend(self.end_scope());
         instructions
     }
 
     fn convert_statement(&mut self, stmt: &ast::Statement) -> Vec<Instruction> {
-        match stmt {
-            ast::Statement::Let { name, type_info: _, value } => {
+        match &stmt.kind {
+            ast::StatementKind::Let { pattern, type_info, value } => {
+                if let Some(instructions) = self.convert_storage_ref(pattern, value) {
+                    return instructions;
+                }
                 let mut instructions = self.convert_expression(value);
-                let local_index = self.local_counter;
-                self.local_counter += 1;
-                
-                if let Some(ref mut function) = self.current_function {
-                    function.locals.push(Local {
-                        name: name.clone(),
-                        ty: Type::U256, // Infer type from value
-                        index: local_index,
-                    });
-                }
-                
-                instructions.push(Instruction::Store(local_index));
+                let ty = match type_info {
+                    Some(t) => self.convert_type(t),
+                    None => self.infer_type(value),
+                };
+                instructions.extend(self.bind(pattern, ty, None));
                 instructions
             }
-            ast::Statement::Assignment { target, value } => {
-                let mut instructions = self.convert_expression(value);
-                instructions.extend(self.convert_assignment_target(target));
+            ast::StatementKind::Assignment { target, value } => {
+                let value = self.convert_expression(value);
+                self.convert_store(target, value)
+            }
+            ast::StatementKind::FunctionCall { function, arguments } => {
+                let (mut instructions, returns_value) = self.convert_call(function, arguments);
+                if returns_value {
+                    instructions.push(Instruction::Pop);
+                }
                 instructions
             }
-            ast::Statement::Return(Some(expr)) => {
+            ast::StatementKind::Return(Some(expr)) => {
                 let mut instructions = self.convert_expression(expr);
-                instructions.push(Instruction::Return);
+                instructions.extend(self.return_instructions());
                 instructions
             }
-            ast::Statement::Return(None) => {
-                vec![Instruction::Return]
+            ast::StatementKind::Return(None) => self.return_instructions(),
+            ast::StatementKind::If { condition, then_block, else_block } => {
+                self.convert_if(condition, then_block, else_block.as_ref(), false)
             }
-            ast::Statement::If { condition, then_block, else_block } => {
+            ast::StatementKind::While { condition, block } => {
+                let start_label = self.new_label("while");
+                let end_label = self.new_label("endwhile");
+
+                self.scopes.push(Vec::new());
+                let mut instructions = vec![Instruction::Label(start_label.clone())];
+                instructions.extend(self.convert_branch(condition, &end_label));
+                instructions.extend(self.convert_block(block));
+                instructions.push(Instruction::Jump(start_label));
+                instructions.push(Instruction::Label(end_label));
+                instructions.extend(self.end_scope());
+
+                instructions
+            }
+            ast::StatementKind::For { pattern, iterable, block } => {
+                self.convert_for(pattern, iterable, block)
+            }
+            ast::StatementKind::Emit { event, arguments } => {
                 let mut instructions = Vec::new();
-                let then_label = self.new_label("then");
-                let else_label = self.new_label("else");
-                let end_label = self.new_label("endif");
-                
-                // Condition
-                instructions.extend(self.convert_expression(condition));
-                instructions.push(Instruction::JumpIf(then_label.clone()));
-                
-                // Else block
-                if let Some(else_block) = else_block {
-                    instructions.extend(self.convert_block(else_block));
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
                 }
-                instructions.push(Instruction::Jump(end_label.clone()));
-                
-                // Then block
-                instructions.push(Instruction::Label(then_label));
-                instructions.extend(self.convert_block(then_block));
-                instructions.push(Instruction::Label(end_label));
-                
+                instructions.push(Instruction::EmitEvent(event.clone(), arguments.len() as u8));
                 instructions
             }
-            _ => Vec::new(), // Handle other cases
+            ast::StatementKind::Ensure { condition, message } => {
+                let ok_label = self.new_label("ensure");
+                let mut instructions = self.convert_expression(condition);
+                instructions.push(Instruction::JumpIf(ok_label.clone()));
+                instructions.push(Instruction::Revert(message.clone()));
+                instructions.push(Instruction::Label(ok_label));
+                instructions
+            }
+            ast::StatementKind::Expression { expression, .. } => self.convert_effect(expression),
+            // Parse errors never reach lowering
+            ast::StatementKind::Error => Vec::new(),
         }
     }
 
-    fn convert_expression(&mut self, expr: &ast::Expression) -> Vec<Instruction> {
-        match expr {
-            ast::Expression::NumberLiteral(n) => {
-                vec![Instruction::Push(Value::U256(n.parse().unwrap_or(0)))]
-            }
-            ast::Expression::Identifier(name) => {
-                // Check if it's a local variable
-                if let Some(ref function) = self.current_function {
-                    if let Some(local) = function.locals.iter().find(|l| l.name == *name) {
-                        return vec![Instruction::Load(local.index)];
-                    }
-                }
-                
-                // Check if it's a storage variable
-                if let Some(ref contract) = self.current_contract {
-                    if let Some(slot) = contract.storage.iter().find(|s| s.name == *name) {
-                        return vec![Instruction::SLoad(slot.slot)];
-                    }
-                }
-                
-                Vec::new()
+    /// `let p = &mut proposals[id]` keeps the entry's slot instead of a copy
+    fn convert_storage_ref(&mut self, pattern: &ast::Pattern, value: &ast::Expression) -> Option<Vec<Instruction>> {
+        let name = match &pattern.kind {
+            ast::PatternKind::Identifier { name, .. } => name,
+            _ => return None,
+        };
+        let operand = match &value.kind {
+            ast::ExpressionKind::Unary { operator: ast::UnaryOp::Ref | ast::UnaryOp::RefMut, operand } => operand,
+            _ => return None,
+        };
+        let place = self.storage_place(operand)?;
+        let ty = self.infer_type(operand);
+
+        let mut instructions = place.into_slot();
+        let index = self.new_local(name, ty);
+        self.scopes.last_mut().unwrap().last_mut().unwrap().1 = Binding::StorageRef(index);
+        // The local holds a slot, not the variable's value
+        self.unbound.retain(|&local| local != index);
+        instructions.push(Instruction::Store(index));
+        Some(instructions)
+    }
+
+    fn convert_if(
+        &mut self,
+        condition: &ast::Expression,
+        then_block: &ast::Block,
+        else_block: Option<&ast::Block>,
+        as_value: bool,
+    ) -> Vec<Instruction> {
+        let else_label = match (else_block, as_value) {
+            (None, false) => None,
+            _ => Some(self.new_label("else")),
+        };
+        let end_label = self.new_label("endif");
+
+        // Bindings made by `if let` are only visible in the then block
+        self.scopes.push(Vec::new());
+        let mut instructions = self.convert_branch(condition, else_label.as_ref().unwrap_or(&end_label));
+        instructions.extend(if as_value {
+            self.convert_block_value(then_block)
+        } else {
+            self.convert_block(then_block)
+        });
+        instructions.extend(self.end_scope());
+
+        if let Some(else_label) = else_label {
+            instructions.push(Instruction::Jump(end_label.clone()));
+            instructions.push(Instruction::Label(else_label));
+            match (else_block, as_value) {
+                (Some(block), true) => instructions.extend(self.convert_block_value(block)),
+                (Some(block), false) => instructions.extend(self.convert_block(block)),
+                (None, _) => instructions.push(Instruction::Tuple(0)),
             }
-            ast::Expression::Binary { left, operator, right } => {
-                let mut instructions = self.convert_expression(left);
-                instructions.extend(self.convert_expression(right));
-                
-                instructions.push(match operator {
-                    ast::BinaryOp::Add => Instruction::Add,
-                    ast::BinaryOp::Sub => Instruction::Sub,
-                    ast::BinaryOp::Mul => Instruction::Mul,
-                    ast::BinaryOp::Div => Instruction::Div,
-                    ast::BinaryOp::Eq => Instruction::Eq,
-                    ast::BinaryOp::Lt => Instruction::Lt,
-                    ast::BinaryOp::Gt => Instruction::Gt,
-                    ast::BinaryOp::LtEq => Instruction::LtEq,
-                    ast::BinaryOp::GtEq => Instruction::GtEq,
-                    _ => return Vec::new(), // Handle other cases
-                });
-                
+        }
+        instructions.push(Instruction::Label(end_label));
+
+        instructions
+    }
+
+    /// Falls through when `condition` holds and jumps to `false_label`
+    /// otherwise. An `if let` condition also binds its pattern.
+    fn convert_branch(&mut self, condition: &ast::Expression, false_label: &Label) -> Vec<Instruction> {
+        match &condition.kind {
+            ast::ExpressionKind::Let { pattern, value } => {
+                let ty = self.infer_type(value);
+                let mut instructions = self.convert_expression(value);
+                instructions.extend(self.bind(pattern, ty, Some(false_label)));
+                instructions
+            }
+            _ => {
+                let mut instructions = self.convert_expression(condition);
+                instructions.push(Instruction::Not);
+                instructions.push(Instruction::JumpIf(false_label.clone()));
                 instructions
             }
-            _ => Vec::new(), // Handle other cases
         }
     }
 
-    fn convert_assignment_target(&self, target: &ast::Expression) -> Vec<Instruction> {
-        match target {
-            ast::Expression::Identifier(name) => {
-                // Check if it's a local variable
-                if let Some(ref function) = self.current_function {
-                    if let Some(local) = function.locals.iter().find(|l| l.name == *name) {
-                        return vec![Instruction::Store(local.index)];
-                    }
+    fn convert_for(&mut self, pattern: &ast::Pattern, iterable: &ast::Expression, block: &ast::Block) -> Vec<Instruction> {
+        self.scopes.push(Vec::new());
+        let start_label = self.new_label("for");
+        let end_label = self.new_label("endfor");
+        let counter = self.new_temp(Type::U256);
+        let end = self.new_temp(Type::U256);
+        let mut instructions = Vec::new();
+
+        // Ranges count directly; anything else is indexed up to its `len()`
+        let (element, element_ty) = match &iterable.kind {
+            ast::ExpressionKind::Range { start, end: range_end } => {
+                instructions.extend(self.convert_expression(start));
+                instructions.push(Instruction::Store(counter));
+                instructions.extend(self.convert_expression(range_end));
+                instructions.push(Instruction::Store(end));
+                (vec![Instruction::Load(counter)], Type::U256)
+            }
+            _ => {
+                let collection_ty = self.infer_type(iterable);
+                let element_ty = match &collection_ty {
+                    Type::Array(element) => (**element).clone(),
+                    _ => Type::Unknown,
+                };
+                let collection = self.new_temp(collection_ty);
+                instructions.extend(self.convert_expression(iterable));
+                instructions.push(Instruction::Store(collection));
+                instructions.push(Instruction::Push(Value::U256(U256::ZERO)));
+                instructions.push(Instruction::Store(counter));
+                instructions.push(Instruction::Load(collection));
+                instructions.push(Instruction::CallMethod("len".to_string(), 0));
+                instructions.push(Instruction::Store(end));
+                (vec![Instruction::Load(collection), Instruction::Load(counter), Instruction::Index], element_ty)
+            }
+        };
+
+        instructions.push(Instruction::Label(start_label.clone()));
+        instructions.push(Instruction::Load(counter));
+        instructions.push(Instruction::Load(end));
+        instructions.push(Instruction::Lt);
+        instructions.push(Instruction::Not);
+        instructions.push(Instruction::JumpIf(end_label.clone()));
+
+        self.scopes.push(Vec::new());
+        instructions.extend(element);
+        instructions.extend(self.bind(pattern, element_ty, None));
+        instructions.extend(self.bind_declared());
+        instructions.extend(self.convert_block(block));
+        instructions.extend(self.end_scope());
+
+        instructions.push(Instruction::Load(counter));
+        instructions.push(Instruction::Push(Value::U256(U256::ONE)));
+        instructions.push(Instruction::Add);
+        instructions.push(Instruction::Store(counter));
+        instructions.push(Instruction::Jump(start_label));
+        instructions.push(Instruction::Label(end_label));
+        instructions.extend(self.end_scope());
+
+        instructions
+    }
+
+    fn convert_match(&mut self, scrutinee: &ast::Expression, arms: &[ast::MatchArm], as_value: bool) -> Vec<Instruction> {
+        let ty = self.infer_type(scrutinee);
+        let mut instructions = self.convert_expression(scrutinee);
+        let subject = self.new_temp(ty.clone());
+        instructions.push(Instruction::Store(subject));
+        let end_label = self.new_label("endmatch");
+
+        let mut exhaustive = false;
+        for arm in arms {
+            let next_label = self.new_label("arm");
+            self.scopes.push(Vec::new());
+            instructions.extend(self.convert_pattern(&arm.pattern, subject, &ty, Some(&next_label)));
+            instructions.extend(self.bind_declared());
+            if self.debug_info {
+                instructions.push(Instruction::Debug(DebugMark::Statement(arm.span.start)));
+            }
+            instructions.extend(if as_value {
+                self.convert_expression(&arm.body)
+            } else {
+                self.convert_effect(&arm.body)
+            });
+            instructions.extend(self.end_scope());
+            instructions.push(Instruction::Jump(end_label.clone()));
+            instructions.push(Instruction::Label(next_label));
+
+            if is_irrefutable(&arm.pattern) {
+                exhaustive = true;
+                break;
+            }
+        }
+        if !exhaustive {
+            instructions.push(Instruction::Revert("no match arm".to_string()));
+        }
+        instructions.push(Instruction::Label(end_label));
+
+        instructions
+    }
+
+    fn return_instructions(&self) -> Vec<Instruction> {
+        match &self.guard_exit {
+            Some(end) => vec![Instruction::Jump(end.clone())],
+            None => vec![Instruction::Return],
+        }
+    }
+
+    // Patterns
+
+    /// Pops the top of the stack into `pattern`. Refutable parts jump to
+    /// `fail` when they do not match; without `fail` they are assumed to.
+    fn bind(&mut self, pattern: &ast::Pattern, ty: Type, fail: Option<&Label>) -> Vec<Instruction> {
+        match &pattern.kind {
+            ast::PatternKind::Identifier { name, .. } if name != "None" => {
+                vec![Instruction::Store(self.new_local(name, ty))]
+            }
+            ast::PatternKind::Wildcard => vec![Instruction::Pop],
+            _ => {
+                let temp = self.new_temp(ty.clone());
+                let mut instructions = vec![Instruction::Store(temp)];
+                instructions.extend(self.convert_pattern(pattern, temp, &ty, fail));
+                instructions
+            }
+        }
+    }
+
+    /// Matches the value in local `source` against `pattern`
+    fn convert_pattern(&mut self, pattern: &ast::Pattern, source: u32, ty: &Type, fail: Option<&Label>) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        match &pattern.kind {
+            ast::PatternKind::Identifier { name, .. } if name == "None" => {
+                if let Some(fail) = fail {
+                    instructions.push(Instruction::Load(source));
+                    instructions.extend(self.variant_test(std::slice::from_ref(name)));
+                    instructions.push(Instruction::Not);
+                    instructions.push(Instruction::JumpIf(fail.clone()));
                 }
-                
-                // Check if it's a storage variable
-                if let Some(ref contract) = self.current_contract {
-                    if let Some(slot) = contract.storage.iter().find(|s| s.name == *name) {
-                        return vec![Instruction::SStore(slot.slot)];
-                    }
+            }
+            ast::PatternKind::Identifier { name, .. } => {
+                instructions.push(Instruction::Load(source));
+                instructions.push(Instruction::Store(self.new_local(name, ty.clone())));
+            }
+            ast::PatternKind::Wildcard => {}
+            ast::PatternKind::Reference(inner) => {
+                instructions.extend(self.convert_pattern(inner, source, ty, fail));
+            }
+            ast::PatternKind::Tuple(elements) => {
+                instructions.extend(self.convert_elements(elements, source, ty, None, fail));
+            }
+            ast::PatternKind::Path(path) => {
+                if let Some(fail) = fail {
+                    instructions.push(Instruction::Load(source));
+                    instructions.extend(self.variant_test(path));
+                    instructions.push(Instruction::Not);
+                    instructions.push(Instruction::JumpIf(fail.clone()));
+                }
+            }
+            ast::PatternKind::TupleStruct { path, fields } => {
+                if let Some(fail) = fail {
+                    instructions.push(Instruction::Load(source));
+                    instructions.push(Instruction::IsVariant(path.join("::")));
+                    instructions.push(Instruction::Not);
+                    instructions.push(Instruction::JumpIf(fail.clone()));
+                }
+                let variant = path.last().map(String::as_str);
+                instructions.extend(self.convert_elements(fields, source, ty, variant, fail));
+            }
+            ast::PatternKind::Literal(literal) => {
+                if let Some(fail) = fail {
+                    instructions.push(Instruction::Load(source));
+                    instructions.extend(self.convert_expression(literal));
+                    instructions.push(Instruction::Eq);
+                    instructions.push(Instruction::Not);
+                    instructions.push(Instruction::JumpIf(fail.clone()));
                 }
-                
-                Vec::new()
             }
-            _ => Vec::new(), // Handle other cases
         }
+        instructions
     }
 
-    fn new_label(&mut self, prefix: &str) -> Label {
-        let label = Label(format!("{}{}", prefix, self.label_counter));
-        self.label_counter += 1;
-        label
+    /// Binds the elements of a tuple, or the payload of `variant`
+    fn convert_elements(
+        &mut self,
+        elements: &[ast::Pattern],
+        source: u32,
+        ty: &Type,
+        variant: Option<&str>,
+        fail: Option<&Label>,
+    ) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        for (index, element) in elements.iter().enumerate() {
+            if matches!(element.kind, ast::PatternKind::Wildcard) {
+                continue;
+            }
+            instructions.push(Instruction::Load(source));
+            instructions.push(Instruction::Extract(index as u8));
+            let element_ty = element_type(ty, variant, index);
+            instructions.extend(self.bind(element, element_ty, fail));
+        }
+        instructions
     }
-}
 
-// Optimization passes
-pub fn optimize(program: Program, level: u8) -> Program {
-    let mut optimized = program;
-    
-    if level >= 1 {
-        optimized = constant_folding(optimized);
+    /// Tests whether the top value is the variant at `path`. Variants of
+    /// the contract's own enums are plain numbers.
+    fn variant_test(&self, path: &[String]) -> Vec<Instruction> {
+        match self.enum_value(path) {
+            Some(value) => vec![Instruction::Push(Value::U256(value)), Instruction::Eq],
+            None => vec![Instruction::IsVariant(path.join("::"))],
+        }
     }
-    
-    if level >= 2 {
-        optimized = dead_code_elimination(optimized);
+
+    fn enum_value(&self, path: &[String]) -> Option<U256> {
+        match path {
+            [name, variant] => self.enums.get(name)?
+                .iter()
+                .find(|(v, _)| v == variant)
+                .map(|(_, value)| *value),
+            _ => None,
+        }
     }
-    
-    optimized
-}
 
-fn constant_folding(program: Program) -> Program {
-    // Implement constant folding optimization
-    program
-}
+    // Expressions
 
-fn dead_code_elimination(program: Program) -> Program {
-    // Implement dead code elimination
-    program
-}
+    /// Lowers an expression whose value is not used
+    fn convert_effect(&mut self, expr: &ast::Expression) -> Vec<Instruction> {
+        match &expr.kind {
+            ast::ExpressionKind::If { condition, then_block, else_block } => {
+                self.convert_if(condition, then_block, else_block.as_ref(), false)
+            }
+            ast::ExpressionKind::Match { scrutinee, arms } => self.convert_match(scrutinee, arms, false),
+            ast::ExpressionKind::Block(block) => self.convert_block(block),
+            ast::ExpressionKind::Assign { target, value } => {
+                let value = self.convert_expression(value);
+                self.convert_store(target, value)
+            }
+            ast::ExpressionKind::FunctionCall { function, arguments } => {
+                let (mut instructions, returns_value) = self.convert_call(function, arguments);
+                if returns_value {
+                    instructions.push(Instruction::Pop);
+                }
+                instructions
+            }
+            // Parse errors never reach lowering
+            ast::ExpressionKind::Error => Vec::new(),
+            _ => {
+                let mut instructions = self.convert_expression(expr);
+                instructions.push(Instruction::Pop);
+                instructions
+            }
+        }
+    }
 
-pub fn lower(ast: ast::Program) -> Result<Program, String> {
-    let builder = IRBuilder::new();
-    Ok(builder.build(&ast))
-} 
\ No newline at end of file
+    /// Lowers an expression to instructions that push exactly one value
+    fn convert_expression(&mut self, expr: &ast::Expression) -> Vec<Instruction> {
+        if let Some(place) = self.storage_place(expr) {
+            return match place {
+                StoragePlace::Static(slot) => vec![Instruction::SLoad(slot)],
+                StoragePlace::Dynamic(mut instructions) => {
+                    instructions.push(Instruction::SLoadAt);
+                    instructions
+                }
+            };
+        }
+
+        match &expr.kind {
+            ast::ExpressionKind::NumberLiteral(n) => {
+                let value = n.parse().expect("the parser only accepts literals that fit in u256");
+                vec![Instruction::Push(Value::U256(value))]
+            }
+            ast::ExpressionKind::StringLiteral(s) => vec![Instruction::Push(Value::String(s.clone()))],
+            ast::ExpressionKind::BoolLiteral(b) => vec![Instruction::Push(Value::Bool(*b))],
+            ast::ExpressionKind::AddressLiteral(text) => match parse_address(text) {
+                Some(bytes) => vec![Instruction::Push(Value::Address(bytes))],
+                None => {
+                    self.error(format!("invalid address literal `{}`", text));
+                    Vec::new()
+                }
+            },
+            ast::ExpressionKind::Identifier(name) => {
+                // Storage variables were handled as places above
+                match self.lookup(name) {
+                    Some(Binding::Local(index)) => vec![Instruction::Load(index)],
+                    Some(Binding::StorageRef(_)) => unreachable!("storage references are places"),
+                    None if name == "None" => vec![Instruction::Variant(name.clone(), 0)],
+                    None => vec![Instruction::Env(name.clone())],
+                }
+            }
+            ast::ExpressionKind::Binary { left, operator: ast::BinaryOp::And, right } => {
+                // `a && b`: b only runs when a holds
+                let rhs_label = self.new_label("and");
+                let end_label = self.new_label("endand");
+                let mut instructions = self.convert_expression(left);
+                instructions.push(Instruction::JumpIf(rhs_label.clone()));
+                instructions.push(Instruction::Push(Value::Bool(false)));
+                instructions.push(Instruction::Jump(end_label.clone()));
+                instructions.push(Instruction::Label(rhs_label));
+                instructions.extend(self.convert_expression(right));
+                instructions.push(Instruction::Label(end_label));
+                instructions
+            }
+            ast::ExpressionKind::Binary { left, operator: ast::BinaryOp::Or, right } => {
+                // `a || b`: b only runs when a fails
+                let true_label = self.new_label("or");
+                let end_label = self.new_label("endor");
+                let mut instructions = self.convert_expression(left);
+                instructions.push(Instruction::JumpIf(true_label.clone()));
+                instructions.extend(self.convert_expression(right));
+                instructions.push(Instruction::Jump(end_label.clone()));
+                instructions.push(Instruction::Label(true_label));
+                instructions.push(Instruction::Push(Value::Bool(true)));
+                instructions.push(Instruction::Label(end_label));
+                instructions
+            }
+            ast::ExpressionKind::Binary { left, operator, right } => {
+                let mut instructions = self.convert_expression(left);
+                instructions.extend(self.convert_expression(right));
+
+                match operator {
+                    ast::BinaryOp::Add => instructions.push(Instruction::Add),
+                    ast::BinaryOp::Sub => instructions.push(Instruction::Sub),
+                    ast::BinaryOp::Mul => instructions.push(Instruction::Mul),
+                    ast::BinaryOp::Div => instructions.push(Instruction::Div),
+                    ast::BinaryOp::Eq => instructions.push(Instruction::Eq),
+                    ast::BinaryOp::NotEq => {
+                        instructions.push(Instruction::Eq);
+                        instructions.push(Instruction::Not);
+                    }
+                    ast::BinaryOp::Lt => instructions.push(Instruction::Lt),
+                    ast::BinaryOp::Gt => instructions.push(Instruction::Gt),
+                    ast::BinaryOp::LtEq => instructions.push(Instruction::LtEq),
+                    ast::BinaryOp::GtEq => instructions.push(Instruction::GtEq),
+                    ast::BinaryOp::And | ast::BinaryOp::Or => unreachable!("short-circuit operators are lowered above"),
+                }
+
+                instructions
+            }
+            ast::ExpressionKind::Unary { operator, operand } => match operator {
+                ast::UnaryOp::Not => {
+                    let mut instructions = self.convert_expression(operand);
+                    instructions.push(Instruction::Not);
+                    instructions
+                }
+                ast::UnaryOp::Neg => {
+                    let mut instructions = vec![Instruction::Push(Value::U256(U256::ZERO))];
+                    instructions.extend(self.convert_expression(operand));
+                    instructions.push(Instruction::Sub);
+                    instructions
+                }
+                // Values are copied, so references and dereferences are the value itself
+                ast::UnaryOp::Deref | ast::UnaryOp::Ref | ast::UnaryOp::RefMut => self.convert_expression(operand),
+            },
+            ast::ExpressionKind::FunctionCall { function, arguments } => {
+                let (mut instructions, returns_value) = self.convert_call(function, arguments);
+                if !returns_value {
+                    instructions.push(Instruction::Tuple(0));
+                }
+                instructions
+            }
+            ast::ExpressionKind::MemberAccess { object, member } => match &object.kind {
+                // `self.balance` too, unless a storage variable took the name
+                ast::ExpressionKind::Identifier(name)
+                    if self.lookup(name).is_none()
+                        && (ENV_OBJECTS.contains(&name.as_str()) || host_value(&format!("{}.{}", name, member)).is_some()) =>
+                {
+                    vec![Instruction::Env(format!("{}.{}", name, member))]
+                }
+                _ => {
+                    let mut instructions = self.convert_expression(object);
+                    instructions.push(Instruction::GetField(member.clone()));
+                    instructions
+                }
+            },
+            ast::ExpressionKind::IndexAccess { array, index } => {
+                let mut instructions = self.convert_expression(array);
+                instructions.extend(self.convert_expression(index));
+                instructions.push(Instruction::Index);
+                instructions
+            }
+            ast::ExpressionKind::Path(path) => match self.enum_value(path) {
+                Some(value) => vec![Instruction::Push(Value::U256(value))],
+                None => vec![Instruction::Variant(path.join("::"), 0)],
+            },
+            ast::ExpressionKind::Tuple(elements) => {
+                let mut instructions = Vec::new();
+                for element in elements {
+                    instructions.extend(self.convert_expression(element));
+                }
+                instructions.push(Instruction::Tuple(elements.len() as u8));
+                instructions
+            }
+            ast::ExpressionKind::StructLiteral { name, fields } => {
+                let mut instructions = Vec::new();
+                for (_, value) in fields {
+                    instructions.extend(self.convert_expression(value));
+                }
+                let names = fields.iter().map(|(field, _)| field.clone()).collect();
+                instructions.push(Instruction::Struct(name.clone(), names));
+                if self.resources.contains(name) {
+                    instructions.push(Instruction::Acquire(name.clone()));
+                }
+                instructions
+            }
+            ast::ExpressionKind::MacroCall { name, arguments } => {
+                let mut instructions = Vec::new();
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
+                }
+                if name == "vec" {
+                    instructions.push(Instruction::Array(arguments.len() as u8));
+                } else {
+                    instructions.push(Instruction::Call(format!("{}!", name), arguments.len() as u8));
+                }
+                instructions
+            }
+            ast::ExpressionKind::Closure { parameters, body } => self.convert_closure(parameters, body),
+            ast::ExpressionKind::Try(inner) => {
+                // `x?` returns the error (or `None`) early and unwraps otherwise
+                let failure = match self.function().return_type {
+                    Some(Type::Generic { ref name, .. }) if name == "Option" => "None",
+                    _ => "Err",
+                };
+                let ty = self.infer_type(inner);
+                let mut instructions = self.convert_expression(inner);
+                let result = self.new_temp(ty);
+                let ok_label = self.new_label("ok");
+                instructions.push(Instruction::Store(result));
+                instructions.push(Instruction::Load(result));
+                instructions.push(Instruction::IsVariant(failure.to_string()));
+                instructions.push(Instruction::Not);
+                instructions.push(Instruction::JumpIf(ok_label.clone()));
+                instructions.push(Instruction::Load(result));
+                instructions.extend(self.return_instructions());
+                instructions.push(Instruction::Label(ok_label));
+                instructions.push(Instruction::Load(result));
+                instructions.push(Instruction::Extract(0));
+                instructions
+            }
+            ast::ExpressionKind::Cast { expression, type_info } => {
+                let mut instructions = self.convert_expression(expression);
+                instructions.push(Instruction::Cast(self.convert_type(type_info)));
+                instructions
+            }
+            ast::ExpressionKind::Range { start, end } => {
+                let mut instructions = self.convert_expression(start);
+                instructions.extend(self.convert_expression(end));
+                instructions.push(Instruction::Struct("Range".to_string(), vec!["start".to_string(), "end".to_string()]));
+                instructions
+            }
+            ast::ExpressionKind::If { condition, then_block, else_block } => {
+                self.convert_if(condition, then_block, else_block.as_ref(), true)
+            }
+            ast::ExpressionKind::Let { pattern, value } => {
+                // A pattern test used as a plain boolean
+                let fail_label = self.new_label("nomatch");
+                let end_label = self.new_label("endlet");
+                let ty = self.infer_type(value);
+                let mut instructions = self.convert_expression(value);
+                instructions.extend(self.bind(pattern, ty, Some(&fail_label)));
+                instructions.push(Instruction::Push(Value::Bool(true)));
+                instructions.push(Instruction::Jump(end_label.clone()));
+                instructions.push(Instruction::Label(fail_label));
+                instructions.push(Instruction::Push(Value::Bool(false)));
+                instructions.push(Instruction::Label(end_label));
+                instructions
+            }
+            ast::ExpressionKind::Match { scrutinee, arms } => self.convert_match(scrutinee, arms, true),
+            ast::ExpressionKind::Block(block) => self.convert_block_value(block),
+            ast::ExpressionKind::Assign { target, value } => {
+                let value = self.convert_expression(value);
+                let mut instructions = self.convert_store(target, value);
+                instructions.push(Instruction::Tuple(0));
+                instructions
+            }
+            // Parse errors never reach lowering
+            ast::ExpressionKind::Error => Vec::new(),
+        }
+    }
+
+    /// Lowers a call and reports whether it leaves a result on the stack.
+    /// Only the contract's own functions are known to return nothing.
+    fn convert_call(&mut self, function: &ast::Expression, arguments: &[ast::Expression]) -> (Vec<Instruction>, bool) {
+        let argc = arguments.len() as u8;
+        let mut instructions = Vec::new();
+
+        let call = match &function.kind {
+            ast::ExpressionKind::Identifier(name) if self.lookup(name).is_none() => {
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
+                }
+                match name.as_str() {
+                    "Ok" | "Err" | "Some" => Instruction::Variant(name.clone(), argc),
+                    "destroy" if !self.functions.contains_key(name) => {
+                        match arguments.first().map(|argument| self.infer_type(argument)) {
+                            Some(Type::Named(kind)) if self.resources.contains(&kind) => {
+                                instructions.push(Instruction::Release(kind));
+                            }
+                            _ => self.error("`destroy` takes a resource".to_string()),
+                        }
+                        return (instructions, false);
+                    }
+                    _ => {
+                        let returns_value = !matches!(self.functions.get(name), Some(None));
+                        instructions.push(Instruction::Call(name.clone(), argc));
+                        return (instructions, returns_value);
+                    }
+                }
+            }
+            // `Error::Custom(..)` builds a variant, `Token::new(..)` calls
+            ast::ExpressionKind::Path(path) => {
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
+                }
+                let is_variant = path.last().is_some_and(|name| name.starts_with(char::is_uppercase));
+                let path = path.join("::");
+                if path == "Duration::from_days" && argc == 1 {
+                    instructions.push(Instruction::Push(Value::U256(U256::from(SECONDS_PER_DAY))));
+                    Instruction::Mul
+                } else if path == "Address::zero" && argc == 0 {
+                    Instruction::Push(Value::Address([0; 20]))
+                } else if is_variant {
+                    Instruction::Variant(path, argc)
+                } else {
+                    Instruction::Call(path, argc)
+                }
+            }
+            // Collections are values, so `list.push(x)` stores the grown
+            // list back where it came from
+            ast::ExpressionKind::MemberAccess { object, member } if member == "push" && argc == 1 && self.is_place(object) => {
+                let mut grown = self.convert_expression(object);
+                grown.extend(self.convert_expression(&arguments[0]));
+                grown.push(Instruction::CallMethod(member.clone(), argc));
+                return (self.convert_store(object, grown), false);
+            }
+            ast::ExpressionKind::MemberAccess { object, member }
+                if member == "as_days" && argc == 0 && self.infer_type(object) != Type::Address =>
+            {
+                instructions.extend(self.convert_expression(object));
+                instructions.push(Instruction::Push(Value::U256(U256::from(SECONDS_PER_DAY))));
+                Instruction::Div
+            }
+            ast::ExpressionKind::MemberAccess { object, member } => {
+                instructions.extend(self.convert_expression(object));
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
+                }
+                Instruction::CallMethod(member.clone(), argc)
+            }
+            _ => {
+                instructions.extend(self.convert_expression(function));
+                for argument in arguments {
+                    instructions.extend(self.convert_expression(argument));
+                }
+                Instruction::CallIndirect(argc)
+            }
+        };
+
+        instructions.push(call);
+        (instructions, true)
+    }
+
+    /// Lifts a closure into a function of its own that takes the captured
+    /// locals ahead of its parameters
+    fn convert_closure(&mut self, parameters: &[ast::Pattern], body: &ast::Expression) -> Vec<Instruction> {
+        let mut names = Vec::new();
+        collect_names(body, &mut names);
+        let mut captures: Vec<(String, Binding)> = Vec::new();
+        for name in names {
+            if let Some(binding) = self.lookup(&name) {
+                if !captures.iter().any(|(captured, _)| *captured == name) {
+                    captures.push((name, binding));
+                }
+            }
+        }
+        let capture_params: Vec<Parameter> = captures.iter()
+            .map(|(name, binding)| Parameter { name: name.clone(), ty: self.binding_type(*binding) })
+            .collect();
+
+        let counter = self.closure_counter;
+        self.closure_counter += 1;
+        let name = format!("{}::closure{}", self.function().name, counter);
+
+        // Lower the body as a separate function, then resume the enclosing one
+        let saved = (
+            self.current_function.take(),
+            mem::take(&mut self.scopes),
+            self.label_counter,
+            self.local_counter,
+            self.closure_counter,
+            self.guard_exit.take(),
+            mem::take(&mut self.unbound),
+        );
+        self.begin_function(&name, capture_params, None, false);
+        for (index, (_, binding)) in captures.iter().enumerate() {
+            if let Binding::StorageRef(_) = binding {
+                self.scopes[0][index].1 = Binding::StorageRef(index as u32);
+            }
+        }
+
+        let mut instructions = Vec::new();
+        let mut destructure = Vec::new();
+        for (index, pattern) in parameters.iter().enumerate() {
+            let ty = Type::Unknown;
+            let param_name = match &pattern.kind {
+                ast::PatternKind::Identifier { name, .. } => name.clone(),
+                ast::PatternKind::Reference(inner) => match &inner.kind {
+                    ast::PatternKind::Identifier { name, .. } => name.clone(),
+                    _ => format!("${}", index),
+                },
+                _ => format!("${}", index),
+            };
+            let local = self.new_local(&param_name, ty.clone());
+            self.function().params.push(Parameter { name: param_name.clone(), ty: ty.clone() });
+            if param_name.starts_with('$') {
+                destructure.push((pattern, local, ty));
+            }
+        }
+        for (pattern, local, ty) in destructure {
+            instructions.extend(self.convert_pattern(pattern, local, &ty, None));
+        }
+        instructions.extend(self.bind_declared());
+        let return_type = self.infer_type(body);
+        instructions.extend(self.convert_expression(body));
+        self.function().return_type = Some(return_type);
+        let lifted = self.finish_function(instructions);
+        self.lifted.push(lifted);
+
+        let (function, scopes, labels, locals, closures, guard_exit, unbound) = saved;
+        self.current_function = function;
+        self.scopes = scopes;
+        self.label_counter = labels;
+        self.local_counter = locals;
+        self.closure_counter = closures;
+        self.guard_exit = guard_exit;
+        self.unbound = unbound;
+
+        let mut instructions: Vec<Instruction> = captures.iter()
+            .map(|(_, binding)| match binding {
+                Binding::Local(index) | Binding::StorageRef(index) => Instruction::Load(*index),
+            })
+            .collect();
+        instructions.push(Instruction::Closure(name, captures.len() as u8));
+        instructions
+    }
+
+    // Places
+
+    /// The storage location `expr` refers to, if it is rooted in a state
+    /// variable. Map keys and struct field names are hashed into the slot.
+    fn storage_place(&mut self, expr: &ast::Expression) -> Option<StoragePlace> {
+        match &expr.kind {
+            ast::ExpressionKind::Identifier(name) => match self.lookup(name) {
+                Some(Binding::StorageRef(index)) => Some(StoragePlace::Dynamic(vec![Instruction::Load(index)])),
+                Some(Binding::Local(_)) => None,
+                None => self.storage_slot(name).map(StoragePlace::Static),
+            },
+            ast::ExpressionKind::MemberAccess { object, member } => {
+                if let ast::ExpressionKind::Identifier(name) = &object.kind {
+                    if name == "self" && self.lookup(name).is_none() {
+                        return self.storage_slot(member).map(StoragePlace::Static);
+                    }
+                }
+                let mut instructions = self.storage_place(object)?.into_slot();
+                instructions.push(Instruction::Push(Value::String(member.clone())));
+                instructions.push(Instruction::MapSlot);
+                Some(StoragePlace::Dynamic(instructions))
+            }
+            ast::ExpressionKind::IndexAccess { array, index } => {
+                let mut instructions = self.storage_place(array)?.into_slot();
+                instructions.extend(self.convert_expression(index));
+                instructions.push(Instruction::MapSlot);
+                Some(StoragePlace::Dynamic(instructions))
+            }
+            ast::ExpressionKind::Unary { operator: ast::UnaryOp::Deref, operand } => self.storage_place(operand),
+            _ => None,
+        }
+    }
+
+    /// Whether `expr` names something [`Self::convert_store`] can store to:
+    /// a local or state variable, or a field or element of one
+    fn is_place(&self, expr: &ast::Expression) -> bool {
+        match &expr.kind {
+            ast::ExpressionKind::Identifier(name) => self.lookup(name).is_some() || self.storage_slot(name).is_some(),
+            ast::ExpressionKind::MemberAccess { object, member } => match &object.kind {
+                ast::ExpressionKind::Identifier(name) if name == "self" && self.lookup(name).is_none() => {
+                    self.storage_slot(member).is_some()
+                }
+                _ => self.is_place(object),
+            },
+            ast::ExpressionKind::IndexAccess { array, .. } => self.is_place(array),
+            ast::ExpressionKind::Unary { operator: ast::UnaryOp::Deref, operand } => self.is_place(operand),
+            _ => false,
+        }
+    }
+
+    fn storage_slot(&self, name: &str) -> Option<u32> {
+        self.current_contract.as_ref()?
+            .storage.iter()
+            .find(|s| s.name == name)
+            .map(|s| s.slot)
+    }
+
+    /// Stores the value pushed by `value` into `target`. Fields and
+    /// elements of locals are updated by storing back the whole value.
+    fn convert_store(&mut self, target: &ast::Expression, value: Vec<Instruction>) -> Vec<Instruction> {
+        if let Some(place) = self.storage_place(target) {
+            return match place {
+                StoragePlace::Static(slot) => {
+                    let mut instructions = value;
+                    instructions.push(Instruction::SStore(slot));
+                    instructions
+                }
+                StoragePlace::Dynamic(mut instructions) => {
+                    instructions.extend(value);
+                    instructions.push(Instruction::SStoreAt);
+                    instructions
+                }
+            };
+        }
+
+        match &target.kind {
+            ast::ExpressionKind::Identifier(name) => match self.lookup(name) {
+                Some(Binding::Local(index)) => {
+                    let mut instructions = value;
+                    instructions.push(Instruction::Store(index));
+                    instructions
+                }
+                _ => {
+                    self.error(format!("cannot assign to `{}`", name));
+                    Vec::new()
+                }
+            },
+            ast::ExpressionKind::MemberAccess { object, member } => {
+                let mut updated = self.convert_expression(object);
+                updated.extend(value);
+                updated.push(Instruction::SetField(member.clone()));
+                self.convert_store(object, updated)
+            }
+            ast::ExpressionKind::IndexAccess { array, index } => {
+                let mut updated = self.convert_expression(array);
+                updated.extend(self.convert_expression(index));
+                updated.extend(value);
+                updated.push(Instruction::SetIndex);
+                self.convert_store(array, updated)
+            }
+            ast::ExpressionKind::Unary { operator: ast::UnaryOp::Deref, operand } => {
+                self.convert_store(operand, value)
+            }
+            _ => {
+                self.error("invalid assignment target".to_string());
+                Vec::new()
+            }
+        }
+    }
+
+    // Types of values, as far as they can be told without the type checker
+
+    fn infer_type(&self, expr: &ast::Expression) -> Type {
+        match &expr.kind {
+            ast::ExpressionKind::NumberLiteral(_) => Type::U256,
+            ast::ExpressionKind::StringLiteral(_) => Type::String,
+            ast::ExpressionKind::BoolLiteral(_) => Type::Bool,
+            ast::ExpressionKind::AddressLiteral(_) => Type::Address,
+            ast::ExpressionKind::Identifier(name) => match self.lookup(name) {
+                Some(binding) => self.binding_type(binding),
+                None if name == "self" => Type::Address,
+                None => self.storage_type(name).unwrap_or(Type::Unknown),
+            },
+            ast::ExpressionKind::Binary { left, operator, .. } => match operator {
+                ast::BinaryOp::Add | ast::BinaryOp::Sub | ast::BinaryOp::Mul | ast::BinaryOp::Div => {
+                    self.infer_type(left)
+                }
+                _ => Type::Bool,
+            },
+            ast::ExpressionKind::Unary { operator: ast::UnaryOp::Not, .. } => Type::Bool,
+            ast::ExpressionKind::Unary { operand, .. } => self.infer_type(operand),
+            ast::ExpressionKind::FunctionCall { function, .. } => match &function.kind {
+                ast::ExpressionKind::Identifier(name) => match self.functions.get(name) {
+                    Some(return_type) => return_type.clone().unwrap_or(Type::Void),
+                    None => host::lookup(name).map_or(Type::Unknown, |(_, entry)| host_type(&entry.returns)),
+                },
+                ast::ExpressionKind::MemberAccess { member, .. } if member == "len" => Type::U256,
+                _ => Type::Unknown,
+            },
+            ast::ExpressionKind::MemberAccess { object, member } => {
+                if let ast::ExpressionKind::Identifier(name) = &object.kind {
+                    if self.lookup(name).is_none() {
+                        if let Some(ty) = self.storage_type(member).filter(|_| name == "self") {
+                            return ty;
+                        }
+                        if let Some(entry) = host_value(&format!("{}.{}", name, member)) {
+                            return host_type(&entry.returns);
+                        }
+                    }
+                }
+                match self.infer_type(object) {
+                    Type::Named(name) => self.structs.get(&name)
+                        .and_then(|fields| fields.iter().find(|(field, _)| field == member))
+                        .map(|(_, ty)| ty.clone())
+                        .unwrap_or(Type::Unknown),
+                    Type::Tuple(elements) => member.parse::<usize>().ok()
+                        .and_then(|index| elements.get(index).cloned())
+                        .unwrap_or(Type::Unknown),
+                    _ => Type::Unknown,
+                }
+            }
+            ast::ExpressionKind::IndexAccess { array, .. } => match self.infer_type(array) {
+                Type::Map { value, .. } => *value,
+                Type::Array(element) => *element,
+                _ => Type::Unknown,
+            },
+            ast::ExpressionKind::Path(path) => match (self.enum_value(path), path.first()) {
+                (Some(_), Some(name)) => Type::Named(name.clone()),
+                _ => Type::Unknown,
+            },
+            ast::ExpressionKind::Tuple(elements) => {
+                Type::Tuple(elements.iter().map(|e| self.infer_type(e)).collect())
+            }
+            ast::ExpressionKind::StructLiteral { name, .. } => Type::Named(name.clone()),
+            ast::ExpressionKind::Try(inner) => element_type(&self.infer_type(inner), Some("Ok"), 0),
+            ast::ExpressionKind::Cast { type_info, .. } => self.convert_type(type_info),
+            ast::ExpressionKind::If { then_block, .. } => self.block_type(then_block),
+            ast::ExpressionKind::Block(block) => self.block_type(block),
+            ast::ExpressionKind::Match { arms, .. } => arms.first()
+                .map_or(Type::Unknown, |arm| self.infer_type(&arm.body)),
+            ast::ExpressionKind::Let { .. } => Type::Bool,
+            ast::ExpressionKind::Assign { .. } => Type::Tuple(Vec::new()),
+            _ => Type::Unknown,
+        }
+    }
+
+    fn block_type(&self, block: &ast::Block) -> Type {
+        match block.statements.last().map(|s| &s.kind) {
+            Some(ast::StatementKind::Expression { expression, has_semicolon: false }) => self.infer_type(expression),
+            _ => Type::Tuple(Vec::new()),
+        }
+    }
+
+    fn storage_type(&self, name: &str) -> Option<Type> {
+        self.current_contract.as_ref()?
+            .storage.iter()
+            .find(|s| s.name == name)
+            .map(|s| s.ty.clone())
+    }
+
+    fn binding_type(&self, binding: Binding) -> Type {
+        let index = match binding {
+            Binding::Local(index) | Binding::StorageRef(index) => index,
+        };
+        self.current_function.as_ref()
+            .and_then(|f| f.locals.iter().find(|l| l.index == index))
+            .map_or(Type::Unknown, |l| l.ty.clone())
+    }
+
+    // Bookkeeping
+
+    fn function(&mut self) -> &mut Function {
+        self.current_function.as_mut().expect("lowering outside of a function")
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes.iter().rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(bound, _)| bound == name)
+            .map(|(_, binding)| *binding)
+    }
+
+    /// Declares `name` in the innermost scope
+    fn new_local(&mut self, name: &str, ty: Type) -> u32 {
+        let index = self.new_temp(ty);
+        self.function().locals.last_mut().unwrap().name = name.to_string();
+        self.scopes.last_mut().unwrap().push((name.to_string(), Binding::Local(index)));
+        if self.debug_info {
+            self.unbound.push(index);
+        }
+        index
+    }
+
+    /// Closes the innermost scope; its variables go out of scope here
+    fn end_scope(&mut self) -> Vec<Instruction> {
+        let scope = self.scopes.pop().unwrap_or_default();
+        let mut marks = Vec::new();
+        for (_, binding) in scope.iter().rev() {
+            if let Binding::Local(index) = *binding {
+                if self.unbound.contains(&index) {
+                    self.unbound.retain(|&local| local != index);
+                } else if self.debug_info {
+                    marks.push(Instruction::Debug(DebugMark::Unbind(index)));
+                }
+            }
+        }
+        marks
+    }
+
+    /// Brings the names declared since the last mark into scope
+    fn bind_declared(&mut self) -> Vec<Instruction> {
+        self.unbound.drain(..)
+            .map(|index| Instruction::Debug(DebugMark::Bind(index)))
+            .collect()
+    }
+
+    /// What precedes `stmt` with debug info: the names the statements before
+    /// it declared, then where it starts
+    fn statement_marks(&mut self, stmt: &ast::Statement) -> Vec<Instruction> {
+        if !self.debug_info {
+            return Vec::new();
+        }
+        let mut marks = self.bind_declared();
+        marks.push(Instruction::Debug(DebugMark::Statement(stmt.span.start)));
+        marks
+    }
+
+    /// Allocates a local that no name refers to
+    fn new_temp(&mut self, ty: Type) -> u32 {
+        let index = self.local_counter;
+        self.local_counter += 1;
+        self.function().locals.push(Local {
+            name: format!("${}", index),
+            ty,
+            index,
+        });
+        index
+    }
+
+    fn new_label(&mut self, prefix: &str) -> Label {
+        let label = Label(format!("{}{}", prefix, self.label_counter));
+        self.label_counter += 1;
+        label
+    }
+
+    fn error(&mut self, message: String) {
+        let function = self.current_function.as_ref().map_or("", |f| f.name.as_str());
+        let contract = self.current_contract.as_ref().map_or("", |c| c.name.as_str());
+        self.errors.push(format!("{}::{}: {}", contract, function, message));
+    }
+}
+
+/// Type of element `index` of a tuple, or of the payload of `variant`
+fn element_type(ty: &Type, variant: Option<&str>, index: usize) -> Type {
+    match (ty, variant) {
+        (Type::Tuple(elements), None) => elements.get(index).cloned().unwrap_or(Type::Unknown),
+        (Type::Result { ok, .. }, Some("Ok")) => (**ok).clone(),
+        (Type::Result { err, .. }, Some("Err")) => (**err).clone(),
+        (Type::Generic { name, arguments }, Some("Some")) if name == "Option" => {
+            arguments.first().cloned().unwrap_or(Type::Unknown)
+        }
+        _ => Type::Unknown,
+    }
+}
+
+fn is_irrefutable(pattern: &ast::Pattern) -> bool {
+    match &pattern.kind {
+        ast::PatternKind::Identifier { name, .. } => name != "None",
+        ast::PatternKind::Wildcard => true,
+        ast::PatternKind::Tuple(elements) => elements.iter().all(is_irrefutable),
+        ast::PatternKind::Reference(inner) => is_irrefutable(inner),
+        _ => false,
+    }
+}
+
+/// The value of the host function table called `name`, such as `msg.sender`
+fn host_value(name: &str) -> Option<&'static host::HostFunction> {
+    host::lookup(name).map(|(_, entry)| entry).filter(|entry| entry.is_value())
+}
+
+fn host_type(ty: &bytecode::Type) -> Type {
+    match ty {
+        bytecode::Type::U256 => Type::U256,
+        bytecode::Type::Address => Type::Address,
+        bytecode::Type::Bool => Type::Bool,
+        bytecode::Type::String => Type::String,
+        bytecode::Type::Void => Type::Void,
+        _ => Type::Unknown,
+    }
+}
+
+/// `0x` followed by 40 hex digits
+fn parse_address(text: &str) -> Option<[u8; 20]> {
+    let digits = text.strip_prefix("0x")?;
+    if digits.len() != 40 || !digits.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; 20];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+
+/// Every identifier mentioned in `expr`, in order of appearance
+fn collect_names(expr: &ast::Expression, names: &mut Vec<String>) {
+    match &expr.kind {
+        ast::ExpressionKind::Identifier(name) => names.push(name.clone()),
+        ast::ExpressionKind::Binary { left, right, .. }
+        | ast::ExpressionKind::Range { start: left, end: right }
+        | ast::ExpressionKind::IndexAccess { array: left, index: right }
+        | ast::ExpressionKind::Assign { target: left, value: right } => {
+            collect_names(left, names);
+            collect_names(right, names);
+        }
+        ast::ExpressionKind::Unary { operand: inner, .. }
+        | ast::ExpressionKind::MemberAccess { object: inner, .. }
+        | ast::ExpressionKind::Closure { body: inner, .. }
+        | ast::ExpressionKind::Try(inner)
+        | ast::ExpressionKind::Cast { expression: inner, .. }
+        | ast::ExpressionKind::Let { value: inner, .. } => collect_names(inner, names),
+        ast::ExpressionKind::FunctionCall { function, arguments } => {
+            collect_names(function, names);
+            arguments.iter().for_each(|a| collect_names(a, names));
+        }
+        ast::ExpressionKind::Tuple(elements) | ast::ExpressionKind::MacroCall { arguments: elements, .. } => {
+            elements.iter().for_each(|e| collect_names(e, names));
+        }
+        ast::ExpressionKind::StructLiteral { fields, .. } => {
+            fields.iter().for_each(|(_, value)| collect_names(value, names));
+        }
+        ast::ExpressionKind::If { condition, then_block, else_block } => {
+            collect_names(condition, names);
+            collect_block_names(then_block, names);
+            if let Some(block) = else_block {
+                collect_block_names(block, names);
+            }
+        }
+        ast::ExpressionKind::Match { scrutinee, arms } => {
+            collect_names(scrutinee, names);
+            arms.iter().for_each(|arm| collect_names(&arm.body, names));
+        }
+        ast::ExpressionKind::Block(block) => collect_block_names(block, names),
+        ast::ExpressionKind::NumberLiteral(_)
+        | ast::ExpressionKind::StringLiteral(_)
+        | ast::ExpressionKind::BoolLiteral(_)
+        | ast::ExpressionKind::AddressLiteral(_)
+        | ast::ExpressionKind::Path(_)
+        | ast::ExpressionKind::Error => {}
+    }
+}
+
+fn collect_block_names(block: &ast::Block, names: &mut Vec<String>) {
+    for stmt in &block.statements {
+        match &stmt.kind {
+            ast::StatementKind::Let { value, .. } => collect_names(value, names),
+            ast::StatementKind::Assignment { target, value } => {
+                collect_names(target, names);
+                collect_names(value, names);
+            }
+            ast::StatementKind::FunctionCall { function, arguments } => {
+                collect_names(function, names);
+                arguments.iter().for_each(|a| collect_names(a, names));
+            }
+            ast::StatementKind::If { condition, then_block, else_block } => {
+                collect_names(condition, names);
+                collect_block_names(then_block, names);
+                if let Some(block) = else_block {
+                    collect_block_names(block, names);
+                }
+            }
+            ast::StatementKind::While { condition, block } => {
+                collect_names(condition, names);
+                collect_block_names(block, names);
+            }
+            ast::StatementKind::For { iterable, block, .. } => {
+                collect_names(iterable, names);
+                collect_block_names(block, names);
+            }
+            ast::StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    collect_names(value, names);
+                }
+            }
+            ast::StatementKind::Emit { arguments, .. } => {
+                arguments.iter().for_each(|a| collect_names(a, names));
+            }
+            ast::StatementKind::Ensure { condition, .. } => collect_names(condition, names),
+            ast::StatementKind::Expression { expression, .. } => collect_names(expression, names),
+            ast::StatementKind::Error => {}
+        }
+    }
+}
+
+// Textual form, printed by `strxc --emit ir`
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, contract) in self.contracts.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", contract)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Contract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "contract {} {{", self.name)?;
+        for slot in &self.storage {
+            writeln!(f, "    storage {} {}: {}", slot.slot, slot.name, slot.ty)?;
+        }
+        for event in &self.events {
+            writeln!(f, "    event {}({})", event.name, DisplayParams(&event.params))?;
+        }
+        for function in &self.functions {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let purity = if self.is_pure { "pure " } else { "" };
+        write!(f, "    {}fn {}({})", purity, self.name, DisplayParams(&self.params))?;
+        if let Some(return_type) = &self.return_type {
+            write!(f, " -> {}", return_type)?;
+        }
+        writeln!(f, " {{")?;
+        for local in self.locals.iter().skip(self.params.len()) {
+            writeln!(f, "        local {} {}: {}", local.index, local.name, local.ty)?;
+        }
+        for instruction in &self.body {
+            match instruction {
+                Instruction::Label(label) => writeln!(f, "    {}:", label)?,
+                _ => writeln!(f, "        {}", instruction)?,
+            }
+        }
+        writeln!(f, "    }}")
+    }
+}
+
+struct DisplayParams<'a>(&'a [Parameter]);
+
+impl fmt::Display for DisplayParams<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, param) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", param.name, param.ty)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Push(value) => write!(f, "push {}", value),
+            Instruction::Pop => write!(f, "pop"),
+            Instruction::Dup(n) => write!(f, "dup {}", n),
+            Instruction::Load(index) => write!(f, "load {}", index),
+            Instruction::Store(index) => write!(f, "store {}", index),
+            Instruction::SLoad(slot) => write!(f, "sload {}", slot),
+            Instruction::SStore(slot) => write!(f, "sstore {}", slot),
+            Instruction::MapSlot => write!(f, "mapslot"),
+            Instruction::SLoadAt => write!(f, "sloadat"),
+            Instruction::SStoreAt => write!(f, "sstoreat"),
+            Instruction::Add => write!(f, "add"),
+            Instruction::Sub => write!(f, "sub"),
+            Instruction::Mul => write!(f, "mul"),
+            Instruction::Div => write!(f, "div"),
+            Instruction::Eq => write!(f, "eq"),
+            Instruction::Lt => write!(f, "lt"),
+            Instruction::Gt => write!(f, "gt"),
+            Instruction::LtEq => write!(f, "lteq"),
+            Instruction::GtEq => write!(f, "gteq"),
+            Instruction::Not => write!(f, "not"),
+            Instruction::Tuple(n) => write!(f, "tuple {}", n),
+            Instruction::Array(n) => write!(f, "array {}", n),
+            Instruction::Struct(name, fields) => write!(f, "struct {} {{{}}}", name, fields.join(", ")),
+            Instruction::Variant(path, n) => write!(f, "variant {} {}", path, n),
+            Instruction::IsVariant(path) => write!(f, "isvariant {}", path),
+            Instruction::Extract(n) => write!(f, "extract {}", n),
+            Instruction::GetField(name) => write!(f, "getfield {}", name),
+            Instruction::SetField(name) => write!(f, "setfield {}", name),
+            Instruction::Index => write!(f, "index"),
+            Instruction::SetIndex => write!(f, "setindex"),
+            Instruction::Cast(ty) => write!(f, "cast {}", ty),
+            Instruction::Jump(label) => write!(f, "jump {}", label),
+            Instruction::JumpIf(label) => write!(f, "jumpif {}", label),
+            Instruction::Label(label) => write!(f, "{}:", label),
+            Instruction::Call(name, argc) => write!(f, "call {} {}", name, argc),
+            Instruction::CallMethod(name, argc) => write!(f, "callmethod {} {}", name, argc),
+            Instruction::CallIndirect(argc) => write!(f, "callindirect {}", argc),
+            Instruction::Closure(name, captures) => write!(f, "closure {} {}", name, captures),
+            Instruction::Return => write!(f, "return"),
+            Instruction::Revert(message) => write!(f, "revert {:?}", message),
+            Instruction::EmitEvent(name, argc) => write!(f, "emit {} {}", name, argc),
+            Instruction::NoReentry(start, end) => write!(f, "noreentry {} {}", start, end),
+            Instruction::Env(name) => write!(f, "env {}", name),
+            Instruction::Acquire(kind) => write!(f, "acquire {}", kind),
+            Instruction::Release(kind) => write!(f, "release {}", kind),
+            Instruction::Debug(DebugMark::Statement(offset)) => write!(f, "debug statement {}", offset),
+            Instruction::Debug(DebugMark::Bind(index)) => write!(f, "debug bind {}", index),
+            Instruction::Debug(DebugMark::Unbind(index)) => write!(f, "debug unbind {}", index),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::U256(n) => write!(f, "{}", n),
+            Value::Address(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::U256 => write!(f, "u256"),
+            Type::Address => write!(f, "Address"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "String"),
+            Type::Array(element) => write!(f, "Vec<{}>", element),
+            Type::Map { key, value } => write!(f, "Map<{}, {}>", key, value),
+            Type::Result { ok, err } => write!(f, "Result<{}, {}>", ok, err),
+            Type::Tuple(elements) => {
+                write!(f, "(")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, ")")
+            }
+            Type::Generic { name, arguments } => {
+                write!(f, "{}<", name)?;
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ">")
+            }
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Void => write!(f, "void"),
+            Type::Unknown => write!(f, "_"),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub fn lower(ast: ast::Program) -> Result<Program, String> {
+    let builder = IRBuilder::new();
+    builder.build(&ast)
+}
+
+/// Lowers like [`lower`], marking where each statement starts and where
+/// each named local comes into and goes out of scope
+pub fn lower_with_debug_info(ast: ast::Program) -> Result<Program, String> {
+    let mut builder = IRBuilder::new();
+    builder.debug_info = true;
+    builder.build(&ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use crate::{lexer, parser, type_checker};
+
+    fn lower_source(source: &str) -> Program {
+        let tokens = lexer::tokenize(source).expect("lexing failed");
+        let ast = parser::parse(tokens).expect("parsing failed");
+        let ast = type_checker::check(ast).map_err(|e| e.to_diagnostic().message).expect("type checking failed");
+        lower(ast).unwrap()
+    }
+
+    /// The IR text of `function` in the only contract of `source`
+    fn function_ir(source: &str, function: &str) -> String {
+        let program = lower_source(source);
+        let function = program.contracts[0].functions.iter()
+            .find(|f| f.name == function)
+            .expect("no such function");
+        function.body.iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_short_circuit_operators() {
+        let ir = function_ir("contract C { fn f(a: bool, b: bool) -> bool { a && b || !a } }", "f");
+        assert_eq!(ir, "\
+load 0
+jumpif and2
+push false
+jump endand3
+and2:
+load 1
+endand3:
+jumpif or0
+load 0
+not
+jump endor1
+or0:
+push true
+endor1:
+return");
+    }
+
+    #[test]
+    fn test_control_flow_uses_labels() {
+        let ir = function_ir(r#"
+            contract C {
+                fn f(n: u256) -> u256 {
+                    let mut total = 0;
+                    while n > 0 {
+                        if n != 3 { total = total + n; }
+                        n = n - 1;
+                    }
+                    total
+                }
+            }
+        "#, "f");
+        assert_eq!(ir, "\
+push 0
+store 1
+while0:
+load 0
+push 0
+gt
+not
+jumpif endwhile1
+load 0
+push 3
+eq
+not
+not
+jumpif endif2
+load 1
+load 0
+add
+store 1
+endif2:
+load 0
+push 1
+sub
+store 0
+jump while0
+endwhile1:
+load 1
+return");
+    }
+
+    #[test]
+    fn test_ensure_reverts_with_message() {
+        let ir = function_ir(r#"contract C { fn f(a: u256) { ensure!(a > 0, "Zero amount"); } }"#, "f");
+        assert_eq!(ir, "\
+load 0
+push 0
+gt
+jumpif ensure0
+revert \"Zero amount\"
+ensure0:
+return");
+    }
+
+    #[test]
+    fn test_map_entries_use_hashed_slots() {
+        let ir = function_ir(r#"
+            contract C {
+                state total: u256;
+                state shares: Map<Address, u256>;
+                event Added(who: Address, amount: u256);
+                fn add(amount: u256) {
+                    shares[msg.sender] += amount;
+                    total = total + amount;
+                    emit Added(msg.sender, amount);
+                }
+            }
+        "#, "add");
+        assert_eq!(ir, "\
+push 1
+env msg.sender
+mapslot
+push 1
+env msg.sender
+mapslot
+sloadat
+load 0
+add
+sstoreat
+sload 0
+load 0
+add
+sstore 0
+env msg.sender
+load 0
+emit Added 2
+return");
+    }
+
+    #[test]
+    fn test_storage_references_write_through() {
+        let program = lower_source(r#"
+            contract C {
+                struct Proposal { votes: u256 }
+                state proposals: Map<u256, Proposal>;
+                fn vote(id: u256) {
+                    let proposal = &mut proposals[id];
+                    proposal.votes += 1;
+                }
+            }
+        "#);
+        let function = &program.contracts[0].functions[0];
+        assert_eq!(function.locals[1].ty, Type::Named("Proposal".to_string()));
+        let ir: Vec<_> = function.body.iter().map(|i| i.to_string()).collect();
+        assert_eq!(ir, [
+            "push 0", "load 0", "mapslot", "store 1",
+            "load 1", "push \"votes\"", "mapslot",
+            "load 1", "push \"votes\"", "mapslot", "sloadat",
+            "push 1", "add", "sstoreat", "return",
+        ]);
+    }
+
+    #[test]
+    fn test_push_stores_the_grown_array_back() {
+        let ir = function_ir(r#"
+            contract C {
+                state offers: Map<u256, Vec<u256>>;
+                fn add(id: u256, offer: u256) {
+                    offers[id].push(offer);
+                    let mine = Vec::new();
+                    mine.push(offer);
+                }
+            }
+        "#, "add");
+        assert_eq!(ir, "\
+push 0
+load 0
+mapslot
+push 0
+load 0
+mapslot
+sloadat
+load 1
+callmethod push 1
+sstoreat
+call Vec::new 0
+store 2
+load 2
+load 1
+callmethod push 1
+store 2
+return");
+    }
+
+    #[test]
+    fn test_guarded_returns_leave_through_the_guard() {
+        let ir = function_ir(r#"
+            contract C {
+                @no_reentry
+                fn f(a: u256) -> u256 {
+                    if a == 0 { return 1; }
+                    a
+                }
+            }
+        "#, "f");
+        assert_eq!(ir, "\
+noreentry guard0 unguard1
+load 0
+push 0
+eq
+not
+jumpif endif2
+push 1
+jump unguard1
+endif2:
+load 0
+jump unguard1
+unguard1:
+return");
+    }
+
+    #[test]
+    fn test_custom_modifiers_call_their_function() {
+        let ir = function_ir(r#"
+            contract C {
+                state owner: Address;
+                fn only_owner() { ensure!(msg.sender == owner, "Not owner"); }
+                @no_reentry
+                @only_owner
+                fn f() {}
+            }
+        "#, "f");
+        assert_eq!(ir, "\
+noreentry guard0 unguard1
+call only_owner 0
+unguard1:
+return");
+    }
+
+    #[test]
+    fn test_match_on_enum_and_try() {
+        let ir = function_ir(r#"
+            contract C {
+                enum Kind { A = 1, B }
+                fn g() -> Result<u256, Error> { Ok(1) }
+                fn f(kind: Kind) -> Result<u256, Error> {
+                    let x = g()?;
+                    match kind {
+                        Kind::A => Ok(x),
+                        _ => Err(Error::Other),
+                    }
+                }
+            }
+        "#, "f");
+        assert_eq!(ir, "\
+call g 0
+store 1
+load 1
+isvariant Err
+not
+jumpif ok0
+load 1
+return
+ok0:
+load 1
+extract 0
+store 2
+load 0
+store 3
+load 3
+push 1
+eq
+not
+jumpif arm2
+load 2
+variant Ok 1
+jump endmatch1
+arm2:
+variant Error::Other 0
+variant Err 1
+jump endmatch1
+arm3:
+endmatch1:
+return");
+    }
+
+    #[test]
+    fn test_match_arms_get_debug_marks() {
+        let source = "contract C { fn f(a: u256) -> u256 { match a { 0 => 1, n => n } } }";
+        let ast = type_checker::check(parser::parse(lexer::tokenize(source).unwrap()).unwrap()).unwrap();
+        let program = lower_with_debug_info(ast).unwrap();
+        let marks: Vec<_> = program.contracts[0].functions[0].body.iter()
+            .filter_map(|i| match i {
+                Instruction::Debug(DebugMark::Statement(offset)) => Some(*offset),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(marks, ["match", "0 =>", "n =>"].map(|text| source.find(text).unwrap()));
+    }
+
+    #[test]
+    fn test_initializers_run_in_init() {
+        let program = lower_source("contract C { state a: u256; state fee: u256 = 0.003e18; }");
+        let init = &program.contracts[0].functions[0];
+        assert_eq!(init.name, "init");
+        let ir: Vec<_> = init.body.iter().map(|i| i.to_string()).collect();
+        assert_eq!(ir, ["push 3000000000000000", "sstore 1", "return"]);
+    }
+
+    #[test]
+    fn test_closures_are_lifted() {
+        let program = lower_source(r#"
+            contract C {
+                fn f(ids: Vec<u256>, wanted: u256) -> bool {
+                    ids.iter().any(|&id| id == wanted)
+                }
+            }
+        "#);
+        let functions = &program.contracts[0].functions;
+        let f: Vec<_> = functions[0].body.iter().map(|i| i.to_string()).collect();
+        assert_eq!(f, ["load 0", "callmethod iter 0", "load 1", "closure f::closure0 1", "callmethod any 1", "return"]);
+        assert_eq!(functions[1].name, "f::closure0");
+        assert_eq!(functions[1].params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["wanted", "id"]);
+        let closure: Vec<_> = functions[1].body.iter().map(|i| i.to_string()).collect();
+        assert_eq!(closure, ["load 1", "load 0", "eq", "return"]);
+    }
+
+    #[test]
+    fn test_resources_are_acquired_and_released() {
+        let source = r#"
+            contract Bank {
+                resource Coin { amount: u256 }
+                fn mint(amount: u256) -> Coin { Coin { amount: amount } }
+                fn burn(coin: Coin) -> u256 {
+                    let amount = coin.amount;
+                    destroy(coin);
+                    amount
+                }
+            }
+        "#;
+        assert_eq!(function_ir(source, "mint"), "load 0\nstruct Coin {amount}\nacquire Coin\nreturn");
+        assert_eq!(function_ir(source, "burn"), "\
+load 0
+getfield amount
+store 1
+load 0
+release Coin
+load 1
+return");
+    }
+
+    /// Compares the IR of every example with `tests/fixtures/ir`. Run with
+    /// `UPDATE_GOLDEN=1` to rewrite the expected files after a deliberate
+    /// change to the lowering.
+    #[test]
+    fn test_examples_match_golden_ir() {
+        fn strx_files(dir: &Path, files: &mut Vec<PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    strx_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "strx") {
+                    files.push(path);
+                }
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let examples = root.join("examples");
+        let golden = root.join("tests").join("fixtures").join("ir");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut files = Vec::new();
+        strx_files(&examples, &mut files);
+        assert!(!files.is_empty());
+
+        let mut mismatches = Vec::new();
+        for path in files {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let ir = lower_source(&source).to_string();
+            let expected_path = golden.join(path.strip_prefix(&examples).unwrap()).with_extension("ir");
+
+            if update {
+                std::fs::create_dir_all(expected_path.parent().unwrap()).unwrap();
+                std::fs::write(&expected_path, &ir).unwrap();
+            } else if std::fs::read_to_string(&expected_path).ok().as_deref() != Some(ir.as_str()) {
+                mismatches.push(expected_path.display().to_string());
+            }
+        }
+        assert!(mismatches.is_empty(), "IR differs from {} (rerun with UPDATE_GOLDEN=1 to accept)", mismatches.join(", "));
+    }
+}
@@ -1,7 +1,9 @@
 use logos::Logos;
-use std::error::Error;
+use std::fmt;
+use crate::diagnostics::{Diagnostic, Label};
+use crate::span::Span;
 
-#[derive(Logos, Debug, PartialEq)]
+#[derive(Logos, Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords
     #[token("contract")]
@@ -43,10 +45,53 @@ pub enum Token {
     #[token("emit")]
     Emit,
     
+    #[token("for")]
+    For,
+    
+    #[token("in")]
+    In,
+    
+    #[token("match")]
+    Match,
+    
+    #[token("as")]
+    As,
+    
+    #[token("type")]
+    Type,
+    
+    #[token("struct")]
+    Struct,
+    
+    #[token("resource")]
+    Resource,
+    
+    #[token("enum")]
+    Enum,
+    
+    #[token("interface")]
+    Interface,
+    
+    #[token("use")]
+    Use,
+    
+    #[token("mod")]
+    Mod,
+    
+    #[token("true")]
+    True,
+    
+    #[token("false")]
+    False,
+    
     // Blockchain-specific keywords
     #[token("@no_reentry")]
     NoReentry,
     
+    // Any other `@name` function modifier
+    #[regex("@[a-zA-Z_][a-zA-Z0-9_]*")]
+    ModifierName,
+    
     // Types
     #[token("Address")]
     Address,
@@ -88,6 +133,9 @@ pub enum Token {
     #[token(":")]
     Colon,
     
+    #[token("::")]
+    PathSep,
+    
     #[token(";")]
     Semicolon,
     
@@ -97,12 +145,30 @@ pub enum Token {
     #[token(".")]
     Dot,
     
+    #[token("..")]
+    DotDot,
+    
+    #[token("?")]
+    Question,
+    
+    #[token("#")]
+    Hash,
+    
+    #[token("|")]
+    Pipe,
+    
+    #[token("&")]
+    Amp,
+    
     #[token("=")]
     Assign,
     
     #[token("->")]
     Arrow,
     
+    #[token("=>")]
+    FatArrow,
+    
     // Operators
     #[token("+")]
     Plus,
@@ -134,13 +200,27 @@ pub enum Token {
     #[token("!=")]
     NotEqual,
     
+    #[token("!")]
+    Bang,
+    
+    #[token("&&")]
+    AndAnd,
+    
+    #[token("||")]
+    OrOr,
+    
     // Literals
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*")]
     Identifier,
     
-    #[regex("[0-9]+")]
+    // Integers, optionally written in scientific notation (`1e18`, `0.003e18`)
+    #[regex(r"[0-9]+(\.[0-9]+)?([eE][0-9]+)?")]
     Number,
     
+    // `0x` and 40 hex digits
+    #[regex("0x[0-9a-fA-F]{40}")]
+    AddressLiteral,
+    
     #[regex(r#""[^"]*""#)]
     String,
     
@@ -149,21 +229,60 @@ pub enum Token {
     #[regex(r"/\*([^*]|\*[^/])*\*/", logos::skip)]
     Comment,
     
-    #[regex(r"[ \t\n\f]+", logos::skip)]
+    #[regex(r"[ \t\r\n\f]+", logos::skip)]
     Whitespace,
-    
-    // Error
-    #[error]
-    Error,
 }
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
-    let lexer = Token::lexer(input);
-    let tokens: Vec<Token> = lexer.collect();
-    
-    // Check for lexical errors
-    if tokens.iter().any(|t| matches!(t, Token::Error)) {
-        return Err("Lexical error: Invalid token found".into());
+/// A token together with its location and source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+    pub text: String,
+}
+
+/// Input that does not form any token
+#[derive(Debug)]
+pub struct LexError {
+    pub span: Span,
+    pub text: String,
+}
+
+impl LexError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(
+            format!("invalid token `{}`", self.text),
+            Label::new(self.span, "not recognized by the lexer"),
+        )
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Lexical error: invalid token `{}` at byte {}", self.text, self.span.start)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Tokenizes a lone source; the driver places every file in a `SourceMap`
+#[cfg(test)]
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>, LexError> {
+    tokenize_at(input, 0)
+}
+
+/// Tokenizes `input` with every span shifted by `base`, for files placed in a
+/// `SourceMap`
+pub fn tokenize_at(input: &str, base: usize) -> Result<Vec<SpannedToken>, LexError> {
+    let mut tokens = Vec::new();
+    
+    for (token, range) in Token::lexer(input).spanned() {
+        let text = input[range.clone()].to_string();
+        let span = Span::from(range).offset(base);
+        match token {
+            Ok(token) => tokens.push(SpannedToken { token, span, text }),
+            Err(()) => return Err(LexError { span, text }),
+        }
     }
     
     Ok(tokens)
@@ -173,10 +292,14 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
 mod tests {
     use super::*;
 
+    fn kinds(input: &str) -> Vec<Token> {
+        tokenize(input).unwrap().into_iter().map(|t| t.token).collect()
+    }
+
     #[test]
     fn test_basic_tokens() {
         let input = "contract TokenContract { fn transfer() -> Result }";
-        let tokens = tokenize(input).unwrap();
+        let tokens = kinds(input);
         assert!(tokens.contains(&Token::Contract));
         assert!(tokens.contains(&Token::Fn));
         assert!(tokens.contains(&Token::Arrow));
@@ -186,8 +309,58 @@ mod tests {
     #[test]
     fn test_blockchain_specific() {
         let input = "@no_reentry { Address }";
-        let tokens = tokenize(input).unwrap();
+        let tokens = kinds(input);
         assert!(tokens.contains(&Token::NoReentry));
         assert!(tokens.contains(&Token::Address));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_numbers_and_ranges() {
+        let tokens = tokenize("0..n 1e18 0.003e18").unwrap();
+        let texts: Vec<_> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["0", "..", "n", "1e18", "0.003e18"]);
+        assert_eq!(tokens[1].token, Token::DotDot);
+        assert_eq!(tokens[4].token, Token::Number);
+
+        let tokens = kinds("0x00000000000000000000000000000000000000aB 0x1");
+        assert_eq!(tokens, [Token::AddressLiteral, Token::Number, Token::Identifier]);
+    }
+
+    #[test]
+    fn test_paths_and_modifiers() {
+        let input = "@only_owner @no_reentry Error::InvalidToken x? |&id|";
+        let tokens = kinds(input);
+        assert_eq!(
+            tokens,
+            [
+                Token::ModifierName,
+                Token::NoReentry,
+                Token::Identifier,
+                Token::PathSep,
+                Token::Identifier,
+                Token::Identifier,
+                Token::Question,
+                Token::Pipe,
+                Token::Amp,
+                Token::Identifier,
+                Token::Pipe,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans_and_text() {
+        let input = "state  total: u256;";
+        let tokens = tokenize(input).unwrap();
+        assert_eq!(tokens[1].token, Token::Identifier);
+        assert_eq!(tokens[1].text, "total");
+        assert_eq!(tokens[1].span, Span::new(7, 12));
+    }
+
+    #[test]
+    fn test_invalid_token_is_located() {
+        let err = tokenize("let x = 1 $ 2;").unwrap_err();
+        assert_eq!(err.span, Span::new(10, 11));
+        assert_eq!(err.text, "$");
+    }
+}
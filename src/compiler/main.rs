@@ -1,27 +1,280 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::fmt;
 use std::fs;
-use std::error::Error;
+use std::io::{self, Write};
+use std::process;
+use crate::diagnostics::SourceMap;
 
+mod span;
+mod diagnostics;
 mod lexer;
 mod parser;
 mod ast;
 mod type_checker;
 mod ir;
+mod optimizer;
 mod codegen;
+mod bytecode_gen;
+#[path = "../bytecode/mod.rs"]
+#[allow(dead_code, unused_imports)] // shared with strxvm, which uses a different subset
+mod bytecode;
+#[path = "../num/mod.rs"]
+#[allow(dead_code, unused_imports)] // shared with strxvm and the runtime
+mod num;
+
+const USAGE: &str = "\
+Usage: strxc [OPTIONS] <INPUT>...
+
+Options:
+  -o <FILE>              Write output to <FILE>
+  --target <TARGET>      Code generation target: native, wasm, ir, bytecode [default: wasm]
+  -O<LEVEL>              Optimization level 0-3 [default: 2]
+  -g                     Include debug info in bytecode containers
+  --enable-pass <PASS>   Run <PASS> regardless of the optimization level
+  --disable-pass <PASS>  Skip <PASS> regardless of the optimization level
+  --print-after <PASS>   Print the IR to stderr after <PASS> runs
+  --emit <STAGE>         Stop after <STAGE> and print it: tokens, ast, typed-ast, ir, asm
+  --check                Stop after type checking
+  -h, --help             Print this help
+  -V, --version          Print version
+
+Exit codes:
+  0  success
+  1  I/O error
+  2  invalid command line
+  3  lexical error
+  4  syntax error
+  5  type error
+  6  IR lowering or code generation error";
 
 #[derive(Debug)]
 struct CompilerOptions {
-    input_file: PathBuf,
-    output_file: PathBuf,
+    input_files: Vec<PathBuf>,
+    output_file: Option<PathBuf>,
     optimization_level: u8,
+    enabled_passes: Vec<String>,
+    disabled_passes: Vec<String>,
+    print_after: Vec<String>,
     target: Target,
+    emit: Option<Emit>,
+    check_only: bool,
+    /// Map bytecode back to source lines and variable names
+    debug_info: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Target {
     Native,
     Wasm,
     IR,
+    /// `.strxb` containers for strxvm
+    Bytecode,
+}
+
+/// Intermediate stage to print instead of generating code
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    Tokens,
+    Ast,
+    TypedAst,
+    Ir,
+    /// Bytecode disassembly
+    Asm,
+}
+
+impl Target {
+    fn extension(&self) -> &'static str {
+        match self {
+            Target::Native => "o",
+            Target::Wasm => "wasm",
+            Target::IR => "ir",
+            Target::Bytecode => bytecode::EXTENSION,
+        }
+    }
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        CompilerOptions {
+            input_files: Vec::new(),
+            output_file: None,
+            optimization_level: 2,
+            enabled_passes: Vec::new(),
+            disabled_passes: Vec::new(),
+            print_after: Vec::new(),
+            target: Target::Wasm,
+            emit: None,
+            check_only: false,
+            debug_info: false,
+        }
+    }
+}
+
+/// Outcome of parsing the command line
+enum Command {
+    Compile(CompilerOptions),
+    Help,
+    Version,
+}
+
+impl CompilerOptions {
+    fn from_args<I>(args: I) -> Result<Command, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = CompilerOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "-V" | "--version" => return Ok(Command::Version),
+                "-o" => {
+                    let value = args.next().ok_or("-o requires a file name")?;
+                    options.output_file = Some(PathBuf::from(value));
+                }
+                "--target" => {
+                    let value = args.next().ok_or("--target requires a value")?;
+                    options.target = parse_target(&value)?;
+                }
+                "--emit" => {
+                    let value = args.next().ok_or("--emit requires a value")?;
+                    options.emit = Some(parse_emit(&value)?);
+                }
+                "--enable-pass" => {
+                    let value = args.next().ok_or("--enable-pass requires a pass name")?;
+                    options.enabled_passes.push(parse_pass(&value)?);
+                }
+                "--disable-pass" => {
+                    let value = args.next().ok_or("--disable-pass requires a pass name")?;
+                    options.disabled_passes.push(parse_pass(&value)?);
+                }
+                "--print-after" => {
+                    let value = args.next().ok_or("--print-after requires a pass name")?;
+                    options.print_after.push(parse_pass(&value)?);
+                }
+                "--check" => options.check_only = true,
+                "-g" => options.debug_info = true,
+                _ if arg.starts_with("--target=") => {
+                    options.target = parse_target(&arg["--target=".len()..])?;
+                }
+                _ if arg.starts_with("--emit=") => {
+                    options.emit = Some(parse_emit(&arg["--emit=".len()..])?);
+                }
+                _ if arg.starts_with("--enable-pass=") => {
+                    options.enabled_passes.push(parse_pass(&arg["--enable-pass=".len()..])?);
+                }
+                _ if arg.starts_with("--disable-pass=") => {
+                    options.disabled_passes.push(parse_pass(&arg["--disable-pass=".len()..])?);
+                }
+                _ if arg.starts_with("--print-after=") => {
+                    options.print_after.push(parse_pass(&arg["--print-after=".len()..])?);
+                }
+                _ if arg.starts_with("-O") => {
+                    options.optimization_level = match &arg[2..] {
+                        "0" => 0,
+                        "1" => 1,
+                        "2" => 2,
+                        "3" => 3,
+                        level => return Err(format!("invalid optimization level '{}' (expected 0-3)", level)),
+                    };
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    return Err(format!("unknown option '{}'", arg));
+                }
+                _ => options.input_files.push(PathBuf::from(arg)),
+            }
+        }
+
+        if options.input_files.is_empty() {
+            return Err("no input files".into());
+        }
+
+        Ok(Command::Compile(options))
+    }
+
+    /// The passes `-O<level>` selects, adjusted by `--enable-pass` and `--disable-pass`
+    fn pass_manager(&self) -> optimizer::PassManager {
+        let mut manager = optimizer::PassManager::new(self.optimization_level);
+        // Names were validated while parsing the command line
+        for name in &self.enabled_passes {
+            manager.enable(name).expect("validated pass name");
+        }
+        for name in &self.disabled_passes {
+            manager.disable(name).expect("validated pass name");
+        }
+        for name in &self.print_after {
+            manager.print_after(name).expect("validated pass name");
+        }
+        manager
+    }
+
+    /// Output path: `-o` if given, otherwise the first input with the target's extension
+    fn output_path(&self) -> PathBuf {
+        self.output_file.clone().unwrap_or_else(|| {
+            self.input_files[0].with_extension(self.target.extension())
+        })
+    }
+}
+
+fn parse_target(value: &str) -> Result<Target, String> {
+    match value {
+        "native" => Ok(Target::Native),
+        "wasm" => Ok(Target::Wasm),
+        "ir" => Ok(Target::IR),
+        "bytecode" => Ok(Target::Bytecode),
+        _ => Err(format!("invalid target '{}' (expected native, wasm, ir or bytecode)", value)),
+    }
+}
+
+fn parse_pass(value: &str) -> Result<String, String> {
+    optimizer::find_pass(value).map(|pass| pass.name.to_string())
+}
+
+fn parse_emit(value: &str) -> Result<Emit, String> {
+    match value {
+        "tokens" => Ok(Emit::Tokens),
+        "ast" => Ok(Emit::Ast),
+        "typed-ast" => Ok(Emit::TypedAst),
+        "ir" => Ok(Emit::Ir),
+        "asm" => Ok(Emit::Asm),
+        _ => Err(format!("invalid emit stage '{}' (expected tokens, ast, typed-ast, ir or asm)", value)),
+    }
+}
+
+/// Errors from each compilation phase, mapped to distinct exit codes.
+/// Lex, parse and type errors hold their rendered diagnostic.
+#[derive(Debug)]
+enum CompileError {
+    Io(PathBuf, io::Error),
+    Lex(String),
+    Parse(String),
+    Type(String),
+    Codegen(String),
+}
+
+impl CompileError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CompileError::Io(..) => 1,
+            CompileError::Lex(_) => 3,
+            CompileError::Parse(_) => 4,
+            CompileError::Type(_) => 5,
+            CompileError::Codegen(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Io(path, err) => write!(f, "error: {}: {}", path.display(), err),
+            CompileError::Lex(rendered)
+            | CompileError::Parse(rendered)
+            | CompileError::Type(rendered) => write!(f, "{}", rendered.trim_end()),
+            CompileError::Codegen(msg) => write!(f, "error: code generation failed: {}", msg),
+        }
+    }
 }
 
 struct Compiler {
@@ -33,51 +286,243 @@ impl Compiler {
         Self { options }
     }
 
-    fn compile(&self) -> Result<(), Box<dyn Error>> {
-        // 1. Read source file
-        let source = fs::read_to_string(&self.options.input_file)?;
+    fn compile(&self) -> Result<(), CompileError> {
+        let mut program = ast::Program::new();
+        let mut sources = SourceMap::new();
+
+        for path in &self.options.input_files {
+            // 1. Read source file
+            let source = fs::read_to_string(path)
+                .map_err(|e| CompileError::Io(path.clone(), e))?;
+            let base = sources.add_file(path.display().to_string(), source.as_str());
 
-        // 2. Lexical analysis
-        let tokens = lexer::tokenize(&source)?;
+            // 2. Lexical analysis
+            let tokens = lexer::tokenize_at(&source, base)
+                .map_err(|e| CompileError::Lex(sources.render(&e.to_diagnostic())))?;
 
-        // 3. Parsing
-        let ast = parser::parse(tokens)?;
+            if self.options.emit == Some(Emit::Tokens) {
+                self.write_stage(&format!("// {}\n{:#?}", path.display(), tokens))?;
+                continue;
+            }
+
+            // 3. Parsing
+            let file_ast = parser::parse(tokens)
+                .map_err(|errors| CompileError::Parse(
+                    errors.iter()
+                        .map(|e| sources.render(&e.to_diagnostic()))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ))?;
+            program.uses.extend(file_ast.uses);
+            program.contracts.extend(file_ast.contracts);
+            program.interfaces.extend(file_ast.interfaces);
+            program.modules.extend(file_ast.modules);
+        }
+
+        match self.options.emit {
+            Some(Emit::Tokens) => return Ok(()),
+            Some(Emit::Ast) => return self.write_stage(&format!("{:#?}", program)),
+            _ => {}
+        }
 
         // 4. Type checking and semantic analysis
-        let typed_ast = type_checker::check(ast)?;
+        let typed_ast = type_checker::check(program)
+            .map_err(|e| CompileError::Type(sources.render(&e.to_diagnostic())))?;
 
-        // 5. IR generation
-        let ir = ir::lower(typed_ast)?;
+        if self.options.emit == Some(Emit::TypedAst) {
+            return self.write_stage(&format!("{:#?}", typed_ast));
+        }
+        if self.options.check_only {
+            return Ok(());
+        }
 
-        // 6. Optimization passes
-        let optimized_ir = if self.options.optimization_level > 0 {
-            ir::optimize(ir, self.options.optimization_level)
+        // 5. IR generation
+        let lowered = if self.options.debug_info {
+            ir::lower_with_debug_info(typed_ast)
         } else {
-            ir
+            ir::lower(typed_ast)
         };
+        let mut optimized_ir = lowered.map_err(CompileError::Codegen)?;
+
+        // 6. Optimization passes
+        self.options.pass_manager().run(&mut optimized_ir, |pass, program| {
+            eprintln!("// IR after {} (static gas {})\n{}", pass, optimizer::static_gas(program), program);
+        });
+
+        if self.options.emit == Some(Emit::Ir) {
+            return self.write_stage(optimized_ir.to_string().trim_end());
+        }
+        if self.options.emit == Some(Emit::Asm) {
+            let modules = self.generate_bytecode(&optimized_ir, &sources)?;
+            let listing = modules.iter()
+                .map(bytecode::disassemble)
+                .collect::<Vec<_>>()
+                .join("\n");
+            return self.write_stage(listing.trim_end());
+        }
 
         // 7. Code generation
+        let output_file = self.options.output_path();
         match self.options.target {
-            Target::Native => codegen::emit_native(optimized_ir, &self.options.output_file)?,
-            Target::Wasm => codegen::emit_wasm(optimized_ir, &self.options.output_file)?,
-            Target::IR => codegen::emit_ir(optimized_ir, &self.options.output_file)?,
+            Target::Native => codegen::emit_native(optimized_ir, &output_file),
+            Target::Wasm => codegen::emit_wasm(optimized_ir, &output_file),
+            Target::IR => codegen::emit_ir(optimized_ir, &output_file),
+            Target::Bytecode => return self.write_bytecode(&optimized_ir, &sources, &output_file),
         }
+        .map_err(CompileError::Codegen)?;
 
         Ok(())
     }
+
+    fn generate_bytecode(&self, ir: &ir::Program, sources: &SourceMap) -> Result<Vec<bytecode::Module>, CompileError> {
+        let modules = if self.options.debug_info {
+            bytecode_gen::generate_with_debug_info(ir, sources)
+        } else {
+            bytecode_gen::generate(ir)
+        };
+        modules.map_err(CompileError::Codegen)
+    }
+
+    /// Writes one container per contract, named by [`codegen::contract_path`]
+    fn write_bytecode(&self, ir: &ir::Program, sources: &SourceMap, output_file: &Path) -> Result<(), CompileError> {
+        let modules = self.generate_bytecode(ir, sources)?;
+        for module in &modules {
+            let path = codegen::contract_path(output_file, &module.name, modules.len());
+            fs::write(&path, bytecode::encode(module))
+                .map_err(|e| CompileError::Io(path.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Writes an intermediate stage to `-o` if given, otherwise to stdout
+    fn write_stage(&self, text: &str) -> Result<(), CompileError> {
+        match &self.options.output_file {
+            Some(path) => append_to_file(path, text)
+                .map_err(|e| CompileError::Io(path.clone(), e)),
+            None => {
+                println!("{}", text);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn append_to_file(path: &Path, text: &str) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", text)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // TODO: Parse command line arguments
-    let options = CompilerOptions {
-        input_file: PathBuf::from("input.strx"),
-        output_file: PathBuf::from("output.wasm"),
-        optimization_level: 2,
-        target: Target::Wasm,
+fn main() {
+    let options = match CompilerOptions::from_args(std::env::args().skip(1)) {
+        Ok(Command::Compile(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("strxc {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
     };
 
+    // Intermediate stages written to a file start from an empty file
+    if options.emit.is_some() {
+        if let Some(path) = &options.output_file {
+            if let Err(e) = fs::write(path, "") {
+                eprintln!("error: {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
     let compiler = Compiler::new(options);
-    compiler.compile()?;
+    if let Err(err) = compiler.compile() {
+        eprintln!("{}", err);
+        process::exit(err.exit_code());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn options(list: &[&str]) -> CompilerOptions {
+        match CompilerOptions::from_args(args(list)) {
+            Ok(Command::Compile(options)) => options,
+            _ => panic!("expected compile command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_full_command_line() {
+        let opts = options(&["a.strx", "b.strx", "-o", "out.ir", "--target", "ir", "-O3", "--check", "-g"]);
+        assert_eq!(opts.input_files, vec![PathBuf::from("a.strx"), PathBuf::from("b.strx")]);
+        assert_eq!(opts.output_file, Some(PathBuf::from("out.ir")));
+        assert_eq!(opts.target, Target::IR);
+        assert_eq!(opts.optimization_level, 3);
+        assert!(opts.check_only);
+        assert!(opts.debug_info);
+    }
 
-    Ok(())
-} 
\ No newline at end of file
+    #[test]
+    fn test_default_output_path() {
+        let opts = options(&["contracts/token.strx"]);
+        assert_eq!(opts.output_path(), PathBuf::from("contracts/token.wasm"));
+
+        let opts = options(&["--target=native", "token.strx"]);
+        assert_eq!(opts.output_path(), PathBuf::from("token.o"));
+
+        let opts = options(&["--target", "bytecode", "token.strx"]);
+        assert_eq!(opts.output_path(), PathBuf::from("token.strxb"));
+    }
+
+    #[test]
+    fn test_emit_stages() {
+        assert_eq!(options(&["--emit", "tokens", "x.strx"]).emit, Some(Emit::Tokens));
+        assert_eq!(options(&["--emit=typed-ast", "x.strx"]).emit, Some(Emit::TypedAst));
+        assert_eq!(options(&["--emit", "asm", "x.strx"]).emit, Some(Emit::Asm));
+        assert!(CompilerOptions::from_args(args(&["--emit", "bytes", "x.strx"])).is_err());
+    }
+
+    #[test]
+    fn test_pass_options() {
+        let opts = options(&["-O1", "--enable-pass", "jump-threading", "--disable-pass=dead-code",
+            "--print-after=constant-folding", "x.strx"]);
+        assert_eq!(opts.pass_manager().pass_names(), ["constant-folding", "jump-threading"]);
+        assert_eq!(opts.print_after, ["constant-folding"]);
+
+        assert!(options(&["-O0", "x.strx"]).pass_manager().pass_names().is_empty());
+        assert!(CompilerOptions::from_args(args(&["--enable-pass", "inline", "x.strx"])).is_err());
+        assert!(CompilerOptions::from_args(args(&["x.strx", "--print-after"])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_command_lines() {
+        assert!(CompilerOptions::from_args(args(&[])).is_err());
+        assert!(CompilerOptions::from_args(args(&["-O4", "x.strx"])).is_err());
+        assert!(CompilerOptions::from_args(args(&["--target", "evm", "x.strx"])).is_err());
+        assert!(CompilerOptions::from_args(args(&["--frobnicate", "x.strx"])).is_err());
+        assert!(CompilerOptions::from_args(args(&["x.strx", "-o"])).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct_per_phase() {
+        let path = PathBuf::from("x.strx");
+        let codes = [
+            CompileError::Io(path, io::Error::new(io::ErrorKind::NotFound, "missing")).exit_code(),
+            CompileError::Lex(String::new()).exit_code(),
+            CompileError::Parse(String::new()).exit_code(),
+            CompileError::Type(String::new()).exit_code(),
+            CompileError::Codegen(String::new()).exit_code(),
+        ];
+        assert_eq!(codes, [1, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,785 @@
+//! IR optimization passes and the pass manager that runs them for `-O1`
+//! to `-O3`.
+//!
+//! Each pass rewrites one function at a time and reports whether it
+//! changed anything. `-O<n>` runs every pass whose level is at most `n`,
+//! in the order of [`PASSES`]; `--enable-pass` and `--disable-pass` adjust
+//! that set and `--print-after` dumps the program after a pass has run.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use crate::ir::{Function, Instruction, Label, Local, Program, StorageSlot, Type, Value};
+
+pub struct Pass {
+    pub name: &'static str,
+    /// Lowest `-O` level that runs the pass
+    pub level: u8,
+    run: fn(&mut Function, &Context) -> bool,
+}
+
+pub const PASSES: [Pass; 5] = [
+    Pass { name: "constant-folding", level: 1, run: constant_folding },
+    Pass { name: "jump-threading", level: 2, run: jump_threading },
+    Pass { name: "dead-code", level: 1, run: dead_code },
+    Pass { name: "redundant-sload", level: 2, run: redundant_sload },
+    Pass { name: "cache-storage", level: 3, run: cache_storage },
+];
+
+pub fn find_pass(name: &str) -> Result<&'static Pass, String> {
+    PASSES.iter()
+        .find(|pass| pass.name == name)
+        .ok_or_else(|| {
+            let names: Vec<_> = PASSES.iter().map(|pass| pass.name).collect();
+            format!("unknown pass '{}' (expected {})", name, names.join(", "))
+        })
+}
+
+/// What the passes know about the contract around a function
+pub struct Context {
+    storage: Vec<StorageSlot>,
+    /// Functions that cannot write storage, so calls to them keep
+    /// cached storage reads valid
+    pure_functions: HashSet<String>,
+}
+
+pub struct PassManager {
+    passes: Vec<&'static Pass>,
+    print_after: Vec<&'static str>,
+}
+
+impl PassManager {
+    /// The passes `-O<level>` runs
+    pub fn new(level: u8) -> Self {
+        PassManager {
+            passes: PASSES.iter().filter(|pass| pass.level <= level).collect(),
+            print_after: Vec::new(),
+        }
+    }
+
+    pub fn enable(&mut self, name: &str) -> Result<(), String> {
+        let pass = find_pass(name)?;
+        if !self.passes.iter().any(|p| p.name == pass.name) {
+            self.passes.push(pass);
+            self.passes.sort_by_key(|p| PASSES.iter().position(|q| q.name == p.name));
+        }
+        Ok(())
+    }
+
+    pub fn disable(&mut self, name: &str) -> Result<(), String> {
+        let pass = find_pass(name)?;
+        self.passes.retain(|p| p.name != pass.name);
+        Ok(())
+    }
+
+    /// Requests a dump of the program after `name` runs
+    pub fn print_after(&mut self, name: &str) -> Result<(), String> {
+        self.print_after.push(find_pass(name)?.name);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name).collect()
+    }
+
+    /// Runs every enabled pass over every function, calling `dump` after
+    /// each pass named by [`PassManager::print_after`]
+    pub fn run(&self, program: &mut Program, mut dump: impl FnMut(&'static str, &Program)) {
+        for pass in &self.passes {
+            for contract in &mut program.contracts {
+                let context = Context {
+                    storage: contract.storage.clone(),
+                    pure_functions: contract.functions.iter()
+                        .filter(|f| f.is_pure)
+                        .map(|f| f.name.clone())
+                        .collect(),
+                };
+                for function in &mut contract.functions {
+                    (pass.run)(function, &context);
+                }
+            }
+            if self.print_after.contains(&pass.name) {
+                dump(pass.name, program);
+            }
+        }
+    }
+}
+
+// Gas
+
+/// Estimated gas for one execution of `instruction`. Arithmetic, calls and
+/// events follow the runtime's schedule; a storage read costs far more
+/// than a local, as it does on chain.
+pub fn gas_cost(instruction: &Instruction) -> u64 {
+    match instruction {
+        Instruction::Label(_) | Instruction::Debug(_) => 0,
+        Instruction::Load(_) | Instruction::Store(_) => 3,
+        Instruction::Mul | Instruction::Div => 5,
+        Instruction::Call(..) | Instruction::CallIndirect(_) => 10,
+        Instruction::MapSlot => 30,
+        Instruction::CallMethod(..) | Instruction::EmitEvent(..) => 100,
+        Instruction::SLoad(_) | Instruction::SLoadAt => 200,
+        Instruction::SStore(_) | Instruction::SStoreAt => 5000,
+        _ => 1,
+    }
+}
+
+/// Gas for running every instruction of every function once
+pub fn static_gas(program: &Program) -> u64 {
+    program.contracts.iter()
+        .flat_map(|contract| &contract.functions)
+        .flat_map(|function| &function.body)
+        .map(gas_cost)
+        .sum()
+}
+
+// Passes
+
+/// Evaluates operations on constants and branches on constant conditions.
+/// Arithmetic that would trap is left for the VM to report.
+fn constant_folding(function: &mut Function, _: &Context) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < function.body.len() {
+        match fold(&function.body[i..]) {
+            Some((len, replacement)) => {
+                function.body.splice(i..i + len, replacement);
+                changed = true;
+                // The result may complete a fold that starts earlier
+                i = i.saturating_sub(2);
+            }
+            None => i += 1,
+        }
+    }
+    changed
+}
+
+/// The number of instructions at the start of `code` that fold, and what
+/// they fold to
+fn fold(code: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match code {
+        [Instruction::Push(Value::U256(a)), Instruction::Push(Value::U256(b)), op, ..] => {
+            let value = match op {
+                Instruction::Add => Value::U256(a.checked_add(*b)?),
+                Instruction::Sub => Value::U256(a.checked_sub(*b)?),
+                Instruction::Mul => Value::U256(a.checked_mul(*b)?),
+                Instruction::Div => Value::U256(a.checked_div(*b)?),
+                Instruction::Eq => Value::Bool(a == b),
+                Instruction::Lt => Value::Bool(a < b),
+                Instruction::Gt => Value::Bool(a > b),
+                Instruction::LtEq => Value::Bool(a <= b),
+                Instruction::GtEq => Value::Bool(a >= b),
+                _ => return None,
+            };
+            Some((3, vec![Instruction::Push(value)]))
+        }
+        [Instruction::Push(a), Instruction::Push(b), Instruction::Eq, ..]
+            if mem::discriminant(a) == mem::discriminant(b) =>
+        {
+            Some((3, vec![Instruction::Push(Value::Bool(a == b))]))
+        }
+        [Instruction::Push(Value::Bool(b)), Instruction::Not, ..] => {
+            Some((2, vec![Instruction::Push(Value::Bool(!b))]))
+        }
+        [Instruction::Not, Instruction::Not, ..] => Some((2, Vec::new())),
+        [Instruction::Push(Value::Bool(true)), Instruction::JumpIf(label), ..] => {
+            Some((2, vec![Instruction::Jump(label.clone())]))
+        }
+        [Instruction::Push(Value::Bool(false)), Instruction::JumpIf(_), ..] => Some((2, Vec::new())),
+        [Instruction::Push(_), Instruction::Pop, ..] => Some((2, Vec::new())),
+        _ => None,
+    }
+}
+
+/// Sends jumps straight to their final destination. A jump to a label
+/// that holds another jump takes the second jump's target, a jump to a
+/// `return` returns, and a jump to the next instruction is dropped.
+/// Threading never skips the label that ends a reentrancy guard, since
+/// passing it releases the guard.
+fn jump_threading(function: &mut Function, _: &Context) -> bool {
+    let guard_ends = guard_ends(&function.body);
+    let positions: HashMap<Label, usize> = function.body.iter()
+        .enumerate()
+        .filter_map(|(i, instruction)| match instruction {
+            Instruction::Label(label) => Some((label.clone(), i)),
+            _ => None,
+        })
+        .collect();
+    let landing = |body: &[Instruction], label: &Label| -> Option<usize> {
+        let mut i = *positions.get(label)?;
+        while let Instruction::Label(passed) = &body[i] {
+            if guard_ends.contains(passed) {
+                return None;
+            }
+            i += 1;
+        }
+        Some(i)
+    };
+
+    let mut changed = false;
+    for i in 0..function.body.len() {
+        let (label, unconditional) = match &function.body[i] {
+            Instruction::Jump(label) => (label, true),
+            Instruction::JumpIf(label) => (label, false),
+            _ => continue,
+        };
+
+        let mut target = label.clone();
+        let mut visited = HashSet::new();
+        while let Some(Instruction::Jump(next)) = landing(&function.body, &target).map(|p| &function.body[p]) {
+            if !visited.insert(next.clone()) {
+                break;
+            }
+            target = next.clone();
+        }
+
+        let lands_on_return = matches!(
+            landing(&function.body, &target).map(|p| &function.body[p]),
+            Some(Instruction::Return)
+        );
+        let replacement = if unconditional && lands_on_return {
+            Instruction::Return
+        } else if target != *label {
+            if unconditional { Instruction::Jump(target) } else { Instruction::JumpIf(target) }
+        } else {
+            continue;
+        };
+        function.body[i] = replacement;
+        changed = true;
+    }
+
+    // A jump over nothing but labels falls through instead
+    let mut i = 0;
+    while i < function.body.len() {
+        if let Instruction::Jump(label) = &function.body[i] {
+            let falls_through = function.body[i + 1..].iter()
+                .map_while(|instruction| match instruction {
+                    Instruction::Label(passed) => Some(passed),
+                    _ => None,
+                })
+                .take_while(|passed| *passed == label || !guard_ends.contains(*passed))
+                .any(|passed| passed == label);
+            if falls_through {
+                function.body.remove(i);
+                changed = true;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    changed
+}
+
+/// Removes code that no jump reaches and labels that no jump names
+fn dead_code(function: &mut Function, _: &Context) -> bool {
+    let mut changed = false;
+    loop {
+        let referenced = referenced_labels(&function.body);
+        let before = function.body.len();
+        let mut reachable = true;
+        function.body.retain(|instruction| match instruction {
+            Instruction::Label(label) if referenced.contains(label) => {
+                reachable = true;
+                true
+            }
+            Instruction::Label(_) => false,
+            // Scopes end after the code that leaves them
+            Instruction::Debug(_) => true,
+            _ if !reachable => false,
+            Instruction::Jump(_) | Instruction::Return | Instruction::Revert(_) => {
+                reachable = false;
+                true
+            }
+            _ => true,
+        });
+        if function.body.len() == before {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Within a basic block, reads a storage slot once and reuses the value,
+/// and reuses a value just stored instead of reading it back
+fn redundant_sload(function: &mut Function, context: &Context) -> bool {
+    // Where a slot's value was last on the stack, and the local it is kept in
+    struct Available {
+        copy_at: usize,
+        local: Option<u32>,
+    }
+
+    let mut available: HashMap<u32, Available> = HashMap::new();
+    let mut copies: Vec<(usize, u32)> = Vec::new();
+    let mut body = Vec::with_capacity(function.body.len());
+
+    for instruction in mem::take(&mut function.body) {
+        match &instruction {
+            Instruction::SLoad(slot) => {
+                if let Some(entry) = available.get_mut(slot) {
+                    let local = match entry.local {
+                        Some(local) => local,
+                        None => {
+                            let local = new_local(function, slot_type(context, *slot));
+                            copies.push((entry.copy_at, local));
+                            entry.local = Some(local);
+                            local
+                        }
+                    };
+                    body.push(Instruction::Load(local));
+                    continue;
+                }
+                body.push(instruction.clone());
+                available.insert(*slot, Available { copy_at: body.len(), local: None });
+                continue;
+            }
+            Instruction::SStore(slot) => {
+                available.insert(*slot, Available { copy_at: body.len(), local: None });
+            }
+            Instruction::Label(_) => available.clear(),
+            _ if may_write_storage(&instruction, context) => available.clear(),
+            _ => {}
+        }
+        body.push(instruction);
+    }
+
+    // Copy each reused value into its local where it was first on the stack
+    copies.sort_by_key(|(at, _)| *at);
+    for (at, local) in copies.iter().rev() {
+        body.splice(*at..*at, [Instruction::Dup(0), Instruction::Store(*local)]);
+    }
+    function.body = body;
+    !copies.is_empty()
+}
+
+/// Across a pure section, a stretch of code with no write to a slot and
+/// no call that could make one, reads the slot into a local once at the
+/// top of the section. Sections that a jump enters anywhere but the top
+/// are left alone.
+fn cache_storage(function: &mut Function, context: &Context) -> bool {
+    let mut changed = false;
+    while let Some((slot, start, reads)) = find_cacheable_section(&function.body, context) {
+        let local = new_local(function, slot_type(context, slot));
+        for read in reads {
+            function.body[read] = Instruction::Load(local);
+        }
+        function.body.splice(start..start, [Instruction::SLoad(slot), Instruction::Store(local)]);
+        changed = true;
+    }
+    changed
+}
+
+/// A slot read at least twice in a section control only enters at the top,
+/// with where to load it and the reads to replace
+fn find_cacheable_section(body: &[Instruction], context: &Context) -> Option<(u32, usize, Vec<usize>)> {
+    let mut slots: Vec<u32> = body.iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::SLoad(slot) => Some(*slot),
+            _ => None,
+        })
+        .collect();
+    slots.sort_unstable();
+    slots.dedup();
+
+    for slot in slots {
+        let mut start = 0;
+        for end in 0..=body.len() {
+            let at_boundary = end == body.len() || writes_slot(&body[end], slot, context);
+            if !at_boundary {
+                continue;
+            }
+            let section = start..end;
+            start = end + 1;
+
+            let reads: Vec<usize> = section.clone()
+                .filter(|&i| matches!(body[i], Instruction::SLoad(s) if s == slot))
+                .collect();
+            if reads.len() < 2 {
+                continue;
+            }
+
+            // Labels at the top are entry points too; load after them
+            let top = section.clone()
+                .find(|&i| !matches!(body[i], Instruction::Label(_)))
+                .unwrap_or(section.end);
+            let inner_labels: HashSet<&Label> = body[top..section.end].iter()
+                .filter_map(|instruction| match instruction {
+                    Instruction::Label(label) => Some(label),
+                    _ => None,
+                })
+                .collect();
+            let entered_from_outside = body.iter()
+                .enumerate()
+                .filter(|(i, _)| !section.contains(i))
+                .any(|(_, instruction)| jump_targets(instruction).any(|label| inner_labels.contains(label)));
+            if !entered_from_outside {
+                return Some((slot, top, reads));
+            }
+        }
+    }
+    None
+}
+
+// Helpers
+
+fn jump_targets(instruction: &Instruction) -> impl Iterator<Item = &Label> {
+    let (first, second) = match instruction {
+        Instruction::Jump(label) | Instruction::JumpIf(label) => (Some(label), None),
+        Instruction::NoReentry(start, end) => (Some(start), Some(end)),
+        _ => (None, None),
+    };
+    first.into_iter().chain(second)
+}
+
+fn referenced_labels(body: &[Instruction]) -> HashSet<Label> {
+    body.iter().flat_map(jump_targets).cloned().collect()
+}
+
+fn guard_ends(body: &[Instruction]) -> HashSet<Label> {
+    body.iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::NoReentry(_, end) => Some(end.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `instruction` could change any storage slot other than through
+/// a static `SStore`
+fn may_write_storage(instruction: &Instruction, context: &Context) -> bool {
+    match instruction {
+        Instruction::SStoreAt | Instruction::CallMethod(..) | Instruction::CallIndirect(_) => true,
+        Instruction::Call(name, _) => !context.pure_functions.contains(name),
+        _ => false,
+    }
+}
+
+fn writes_slot(instruction: &Instruction, slot: u32, context: &Context) -> bool {
+    matches!(instruction, Instruction::SStore(s) if *s == slot) || may_write_storage(instruction, context)
+}
+
+fn slot_type(context: &Context, slot: u32) -> Type {
+    context.storage.iter()
+        .find(|s| s.slot == slot)
+        .map_or(Type::Unknown, |s| s.ty.clone())
+}
+
+fn new_local(function: &mut Function, ty: Type) -> u32 {
+    let index = function.locals.iter()
+        .map(|local| local.index + 1)
+        .max()
+        .unwrap_or(0)
+        .max(function.params.len() as u32);
+    function.locals.push(Local { name: format!("${}", index), ty, index });
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+    use std::path::{Path, PathBuf};
+    use crate::num::U256;
+    use crate::{ir, lexer, parser, type_checker};
+
+    fn lower(source: &str) -> Program {
+        let tokens = lexer::tokenize(source).expect("lexing failed");
+        let ast = parser::parse(tokens).expect("parsing failed");
+        let ast = type_checker::check(ast).map_err(|e| e.to_diagnostic().message).expect("type checking failed");
+        ir::lower(ast).unwrap()
+    }
+
+    fn context() -> Context {
+        Context {
+            storage: vec![StorageSlot { name: "a".into(), slot: 0, ty: Type::U256 }],
+            pure_functions: HashSet::from(["view".to_string()]),
+        }
+    }
+
+    fn function(body: Vec<Instruction>) -> Function {
+        Function {
+            name: "f".into(),
+            params: Vec::new(),
+            return_type: None,
+            body,
+            locals: Vec::new(),
+            is_pure: false,
+        }
+    }
+
+    fn label(name: &str) -> Label {
+        Label(name.to_string())
+    }
+
+    fn push(n: u64) -> Instruction {
+        Instruction::Push(Value::U256(U256::from(n)))
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        use Instruction::*;
+        let mut f = function(vec![
+            push(2), push(3), Add, push(4), Mul, Store(0),
+            push(1), push(2), Lt, Not, JumpIf(label("a")),
+            push(1), push(0), Div, Pop,
+            Push(Value::String("x".into())), Push(Value::String("x".into())), Eq, Pop,
+            Label(label("a")),
+            Return,
+        ]);
+        assert!(constant_folding(&mut f, &context()));
+        assert_eq!(f.body, vec![
+            push(20), Store(0),
+            push(1), push(0), Div, Pop,
+            Label(label("a")),
+            Return,
+        ]);
+        assert!(!constant_folding(&mut f, &context()));
+    }
+
+    #[test]
+    fn test_dead_code_after_return() {
+        use Instruction::*;
+        let mut f = function(vec![
+            Load(0), JumpIf(label("a")),
+            Revert("no".into()), Load(1), Label(label("unused")), Pop,
+            Label(label("a")),
+            Return,
+            Label(label("dead")), push(1), Return,
+        ]);
+        assert!(dead_code(&mut f, &context()));
+        assert_eq!(f.body, vec![
+            Load(0), JumpIf(label("a")),
+            Revert("no".into()),
+            Label(label("a")),
+            Return,
+        ]);
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        use Instruction::*;
+        let mut f = function(vec![
+            Load(0), JumpIf(label("a")),
+            Jump(label("b")),
+            Label(label("a")),
+            Jump(label("c")),
+            Label(label("b")),
+            Label(label("c")),
+            Load(1),
+            Jump(label("d")),
+            Label(label("d")),
+            Return,
+        ]);
+        assert!(jump_threading(&mut f, &context()));
+        assert_eq!(f.body, vec![
+            Load(0), JumpIf(label("c")),
+            Jump(label("b")),
+            Label(label("a")),
+            Label(label("b")),
+            Label(label("c")),
+            Load(1),
+            Return,
+            Label(label("d")),
+            Return,
+        ]);
+    }
+
+    #[test]
+    fn test_jump_threading_keeps_guard_exits() {
+        use Instruction::*;
+        let mut f = function(vec![
+            NoReentry(label("guard"), label("unguard")),
+            Load(0), JumpIf(label("a")),
+            Jump(label("unguard")),
+            Label(label("a")),
+            Jump(label("unguard")),
+            Label(label("unguard")),
+            Return,
+        ]);
+        // Jumps may reach the guard end sooner but never return past it
+        jump_threading(&mut f, &context());
+        assert_eq!(f.body, vec![
+            NoReentry(label("guard"), label("unguard")),
+            Load(0), JumpIf(label("unguard")),
+            Jump(label("unguard")),
+            Label(label("a")),
+            Label(label("unguard")),
+            Return,
+        ]);
+    }
+
+    #[test]
+    fn test_redundant_sload_within_block() {
+        use Instruction::*;
+        let mut f = function(vec![
+            SLoad(0), SLoad(0), Add, push(1), SStore(1), SLoad(1), Call("view".into(), 0), SLoad(0),
+            Call("write".into(), 0), SLoad(0),
+            Label(label("a")), SLoad(0), Return,
+        ]);
+        assert!(redundant_sload(&mut f, &context()));
+        assert_eq!(f.body, vec![
+            SLoad(0), Dup(0), Store(0), Load(0), Add,
+            push(1), Dup(0), Store(1), SStore(1), Load(1),
+            Call("view".into(), 0), Load(0),
+            Call("write".into(), 0), SLoad(0),
+            Label(label("a")), SLoad(0), Return,
+        ]);
+        assert_eq!(f.locals[0].ty, Type::U256);
+    }
+
+    #[test]
+    fn test_cache_storage_across_blocks() {
+        use Instruction::*;
+        let mut f = function(vec![
+            Load(0), JumpIf(label("a")),
+            SLoad(0), Return,
+            Label(label("a")),
+            SLoad(0), SLoad(0), Add, Return,
+        ]);
+        assert!(cache_storage(&mut f, &context()));
+        assert_eq!(f.body, vec![
+            SLoad(0), Store(0),
+            Load(0), JumpIf(label("a")),
+            Load(0), Return,
+            Label(label("a")),
+            Load(0), Load(0), Add, Return,
+        ]);
+
+        // A loop that writes the slot jumps back into the section
+        let mut f = function(vec![
+            Label(label("loop")),
+            SLoad(0), SLoad(0), Add,
+            SStore(0),
+            Jump(label("loop")),
+        ]);
+        assert!(cache_storage(&mut f, &context()));
+        assert_eq!(&f.body[..3], &[Label(label("loop")), SLoad(0), Store(0)]);
+
+        // A jump into the middle of the section skips the load
+        let mut f = function(vec![
+            push(1), SStore(0),
+            SLoad(0), Pop,
+            Label(label("inner")),
+            SLoad(0), Pop,
+            Call("write".into(), 0),
+            Jump(label("inner")),
+        ]);
+        assert!(!cache_storage(&mut f, &context()));
+    }
+
+    #[test]
+    fn test_pass_selection() {
+        assert!(PassManager::new(0).pass_names().is_empty());
+        assert_eq!(PassManager::new(1).pass_names(), ["constant-folding", "dead-code"]);
+        assert_eq!(PassManager::new(3).pass_names().len(), PASSES.len());
+
+        let mut manager = PassManager::new(1);
+        manager.enable("cache-storage").unwrap();
+        manager.enable("jump-threading").unwrap();
+        manager.disable("constant-folding").unwrap();
+        assert_eq!(manager.pass_names(), ["jump-threading", "dead-code", "cache-storage"]);
+        assert!(manager.enable("inlining").is_err());
+    }
+
+    #[test]
+    fn test_print_after_dumps_the_program() {
+        let mut program = lower("contract C { fn f() -> u256 { 1 + 2 } }");
+        let mut manager = PassManager::new(2);
+        manager.print_after("constant-folding").unwrap();
+
+        let mut dumps = Vec::new();
+        manager.run(&mut program, |pass, program| dumps.push((pass, program.to_string())));
+        assert_eq!(dumps.len(), 1);
+        assert_eq!(dumps[0].0, "constant-folding");
+        assert!(dumps[0].1.contains("push 3\n        return"));
+    }
+
+    /// Static gas of each example with each pass on its own and at each
+    /// level, checked against `tests/fixtures/gas/optimizer.txt`. Run with
+    /// `UPDATE_GOLDEN=1` to rewrite the table.
+    #[test]
+    fn test_gas_benchmarks_on_examples() {
+        fn strx_files(dir: &Path, files: &mut Vec<PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    strx_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "strx") {
+                    files.push(path);
+                }
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let examples = root.join("examples");
+        let mut files = Vec::new();
+        strx_files(&examples, &mut files);
+        files.sort();
+
+        let mut columns = vec!["unoptimized".to_string()];
+        columns.extend(PASSES.iter().map(|pass| pass.name.to_string()));
+        columns.extend((1..=3).map(|level| format!("-O{}", level)));
+
+        let mut rows = Vec::new();
+        for path in &files {
+            let program = lower(&std::fs::read_to_string(path).unwrap());
+            let unoptimized = static_gas(&program);
+            let mut row = vec![unoptimized];
+
+            for pass in &PASSES {
+                let mut manager = PassManager::new(0);
+                manager.enable(pass.name).unwrap();
+                let mut optimized = program.clone();
+                manager.run(&mut optimized, |_, _| {});
+                row.push(static_gas(&optimized));
+            }
+            for level in 1..=3 {
+                let mut optimized = program.clone();
+                PassManager::new(level).run(&mut optimized, |_, _| {});
+                for function in optimized.contracts.iter().flat_map(|c| &c.functions) {
+                    let defined: HashSet<_> = function.body.iter()
+                        .filter_map(|instruction| match instruction {
+                            Instruction::Label(label) => Some(label),
+                            _ => None,
+                        })
+                        .collect();
+                    let jumps_resolve = function.body.iter().all(|instruction| match instruction {
+                        Instruction::Jump(label) | Instruction::JumpIf(label) => defined.contains(label),
+                        _ => true,
+                    });
+                    assert!(jumps_resolve, "jump to a removed label in\n{}", function);
+                }
+                row.push(static_gas(&optimized));
+            }
+
+            let name = path.strip_prefix(&examples).unwrap().with_extension("");
+            assert!(row.iter().all(|gas| *gas <= unoptimized), "a pass made {} more expensive", name.display());
+            assert!(row[row.len() - 1] <= row[row.len() - 2] && row[row.len() - 2] <= row[row.len() - 3]);
+            rows.push((name.display().to_string(), row));
+        }
+
+        let mut table = String::from("# Static gas: every instruction of every function run once\n");
+        let name_width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        write!(table, "{:name_width$}", "example").unwrap();
+        for column in &columns {
+            write!(table, "  {:>w$}", column, w = column.len().max(6)).unwrap();
+        }
+        table.push('\n');
+        for (name, row) in &rows {
+            write!(table, "{:name_width$}", name).unwrap();
+            for (column, gas) in columns.iter().zip(row) {
+                write!(table, "  {:>w$}", gas, w = column.len().max(6)).unwrap();
+            }
+            table.push('\n');
+        }
+
+        let golden = root.join("tests").join("fixtures").join("gas").join("optimizer.txt");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(golden.parent().unwrap()).unwrap();
+            std::fs::write(&golden, &table).unwrap();
+        } else {
+            let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+            assert!(expected == table, "gas table differs from {} (rerun with UPDATE_GOLDEN=1 to accept):\n{}", golden.display(), table);
+        }
+    }
+}
@@ -1,168 +1,1108 @@
 use crate::ast::*;
-use crate::lexer::Token;
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use crate::diagnostics::{Diagnostic, Label};
+use crate::lexer::{SpannedToken, Token};
+use crate::num::U256;
+use crate::span::Span;
 
 pub struct Parser {
-    tokens: Peekable<IntoIter<Token>>,
+    tokens: Vec<SpannedToken>,
+    position: usize,
+    eof_span: Span,
+    errors: Vec<ParseError>,
+    struct_literals_allowed: bool,
 }
 
 #[derive(Debug)]
-pub enum ParseError {
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    pub secondary: Vec<Label>,
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
     UnexpectedToken(String),
     UnexpectedEOF,
     InvalidExpression,
+    InvalidNumber(String),
+    NumberTooLarge(String),
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError {
+            kind,
+            span,
+            secondary: Vec::new(),
+        }
+    }
+
+    fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let (message, label) = match &self.kind {
+            ParseErrorKind::UnexpectedToken(expected) => (expected.clone(), "unexpected token"),
+            ParseErrorKind::UnexpectedEOF => ("unexpected end of file".to_string(), "expected more input"),
+            ParseErrorKind::InvalidExpression => ("invalid left-hand side of assignment".to_string(), "cannot be assigned to"),
+            ParseErrorKind::InvalidNumber(text) => (format!("invalid number literal `{}`", text), "not an integer"),
+            ParseErrorKind::NumberTooLarge(text) => (format!("number literal `{}` does not fit in u256", text), "exceeds 2^256 - 1"),
+        };
+        let mut diagnostic = Diagnostic::error(message, Label::new(self.span, label));
+        diagnostic.secondary = self.secondary.clone();
+        diagnostic
+    }
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
+        let eof_span = tokens.last()
+            .map(|t| t.span.shrink_to_end())
+            .unwrap_or_default();
         Parser {
-            tokens: tokens.into_iter().peekable(),
+            tokens,
+            position: 0,
+            eof_span,
+            errors: Vec::new(),
+            struct_literals_allowed: true,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Program, ParseError> {
+    /// Parses the whole input, recovering from syntax errors. The returned
+    /// program marks broken regions with `Error` statements and expressions,
+    /// and comes with every error found.
+    pub fn parse_recovering(&mut self) -> (Program, Vec<ParseError>) {
         let mut program = Program::new();
-        
-        while self.tokens.peek().is_some() {
-            if let Some(contract) = self.parse_contract()? {
-                program.add_contract(contract);
+
+        while self.peek().is_some() {
+            let from = self.position;
+            if let Err(err) = self.parse_item(&mut program) {
+                self.report(err);
+                self.synchronize_item(from);
             }
         }
-        
-        Ok(program)
+
+        (program, std::mem::take(&mut self.errors))
     }
 
-    fn parse_contract(&mut self) -> Result<Option<Contract>, ParseError> {
-        if !matches!(self.tokens.peek(), Some(Token::Contract)) {
-            return Ok(None);
+    // Items
+
+    fn parse_item(&mut self, program: &mut Program) -> Result<(), ParseError> {
+        let attributes = self.parse_attributes()?;
+        if !attributes.is_empty() && !self.check(&Token::Mod) {
+            return Err(self.unexpected("Expected module after attributes"));
+        }
+
+        match self.peek() {
+            Some(Token::Use) => program.uses.push(self.parse_use()?),
+            Some(Token::Contract) => program.add_contract(self.parse_contract()?),
+            Some(Token::Interface) => program.interfaces.push(self.parse_interface()?),
+            Some(Token::Mod) => program.modules.push(self.parse_module(attributes)?),
+            Some(_) => return Err(self.unexpected("Expected contract declaration")),
+            None => return Err(self.eof()),
+        }
+        Ok(())
+    }
+
+    fn parse_use(&mut self) -> Result<UseDecl, ParseError> {
+        let start = self.consume(Token::Use)?;
+        let mut path = vec![self.parse_name()?];
+        let mut names = Vec::new();
+        let mut glob = false;
+
+        while self.check(&Token::PathSep) {
+            self.advance();
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.advance();
+                    glob = true;
+                    break;
+                }
+                Some(Token::LBrace) => {
+                    self.advance();
+                    names = self.parse_comma_separated(Token::RBrace, Self::parse_name)?.0;
+                    break;
+                }
+                _ => path.push(self.parse_name()?),
+            }
+        }
+
+        // `use a::b::C;` imports the single name `C` from `a::b`
+        if !glob && names.is_empty() {
+            names.extend(path.pop());
+        }
+
+        let end = self.consume(Token::Semicolon)?;
+        Ok(UseDecl { path, names, glob, span: start.to(end) })
+    }
+
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, ParseError> {
+        let mut attributes = Vec::new();
+
+        while self.check(&Token::Hash) {
+            let start = self.advance().span;
+            self.consume(Token::LBracket)?;
+            let name = self.parse_name()?;
+            let arguments = if self.check(&Token::LParen) {
+                self.advance();
+                self.parse_comma_separated(Token::RParen, Self::parse_name)?.0
+            } else {
+                Vec::new()
+            };
+            let end = self.consume(Token::RBracket)?;
+            attributes.push(Attribute { name, arguments, span: start.to(end) });
         }
-        
-        self.consume(Token::Contract)?;
+
+        Ok(attributes)
+    }
+
+    fn parse_contract(&mut self) -> Result<Contract, ParseError> {
+        let start = self.consume(Token::Contract)?;
         let name = self.parse_identifier()?;
-        self.consume(Token::LBrace)?;
-        
-        let mut contract = Contract::new(name);
-        
-        while !matches!(self.tokens.peek(), Some(Token::RBrace)) {
-            match self.tokens.peek() {
+        let open = self.consume(Token::LBrace)?;
+
+        let mut contract = Contract::new(name, start);
+        let mut modifiers = Vec::new();
+
+        while !self.check(&Token::RBrace) {
+            let from = self.position;
+            let item = match self.peek() {
                 Some(Token::State) => {
-                    contract.state_vars.push(self.parse_state_var()?);
+                    self.parse_state_var().map(|var| contract.state_vars.push(var))
                 }
                 Some(Token::Event) => {
-                    contract.events.push(self.parse_event()?);
+                    self.parse_event().map(|event| contract.events.push(event))
+                }
+                Some(Token::Type) => {
+                    self.parse_type_alias().map(|alias| contract.type_aliases.push(alias))
+                }
+                Some(Token::Struct) | Some(Token::Resource) => {
+                    self.parse_struct().map(|decl| contract.structs.push(decl))
+                }
+                Some(Token::Enum) => {
+                    self.parse_enum().map(|decl| contract.enums.push(decl))
+                }
+                Some(Token::NoReentry) | Some(Token::ModifierName) => {
+                    modifiers.push(self.parse_modifier());
+                    Ok(())
                 }
                 Some(Token::Pure) | Some(Token::Mut) | Some(Token::Fn) => {
-                    contract.functions.push(self.parse_function()?);
+                    self.parse_function(Vec::new()).map(|mut function| {
+                        function.modifiers = std::mem::take(&mut modifiers);
+                        contract.functions.push(function);
+                    })
+                }
+                Some(Token::Contract) | Some(Token::Interface) | Some(Token::Use)
+                | Some(Token::Hash) | Some(Token::Mod) | None => {
+                    // The closing brace is missing; let the caller continue
+                    // with whatever follows
+                    let err = self.unexpected("Expected `}`").with_secondary(open, "unclosed delimiter");
+                    self.report(err);
+                    contract.span = start.to(self.previous_span());
+                    return Ok(contract);
                 }
-                _ => return Err(ParseError::UnexpectedToken("Expected state, event, or function declaration".into())),
+                Some(_) => Err(self.unexpected("Expected state, event, type, or function declaration")),
+            };
+
+            if let Err(err) = item {
+                self.report(err);
+                self.synchronize_item(from);
             }
         }
-        
-        self.consume(Token::RBrace)?;
-        Ok(Some(contract))
+
+        let end = self.consume(Token::RBrace)?;
+        contract.span = start.to(end);
+        Ok(contract)
     }
 
     fn parse_state_var(&mut self) -> Result<StateVar, ParseError> {
-        self.consume(Token::State)?;
+        let start = self.consume(Token::State)?;
         let name = self.parse_identifier()?;
         self.consume(Token::Colon)?;
         let type_info = self.parse_type()?;
-        self.consume(Token::Semicolon)?;
-        
+        let initializer = if self.check(&Token::Assign) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let end = self.consume(Token::Semicolon)?;
+
         Ok(StateVar {
             name,
             type_info,
-            visibility: Visibility::Public, // Default visibility
+            initializer,
+            span: start.to(end),
         })
     }
 
     fn parse_event(&mut self) -> Result<Event, ParseError> {
-        self.consume(Token::Event)?;
+        let start = self.consume(Token::Event)?;
         let name = self.parse_identifier()?;
-        self.consume(Token::LParen)?;
-        
-        let mut parameters = Vec::new();
-        while !matches!(self.tokens.peek(), Some(Token::RParen)) {
-            if !parameters.is_empty() {
-                self.consume(Token::Comma)?;
-            }
-            parameters.push(self.parse_parameter()?);
-        }
-        
-        self.consume(Token::RParen)?;
-        self.consume(Token::Semicolon)?;
-        
-        Ok(Event { name, parameters })
+        let parameters = self.parse_parameters()?;
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(Event { name, parameters, span: start.to(end) })
     }
 
-    fn parse_function(&mut self) -> Result<Function, ParseError> {
-        let is_pure = matches!(self.tokens.peek(), Some(Token::Pure));
-        if is_pure {
-            self.consume(Token::Pure)?;
+    fn parse_type_alias(&mut self) -> Result<TypeAlias, ParseError> {
+        let start = self.consume(Token::Type)?;
+        let name = self.parse_identifier()?;
+        self.consume(Token::Assign)?;
+        let type_info = self.parse_type()?;
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(TypeAlias { name, type_info, span: start.to(end) })
+    }
+
+    /// `struct Name { fields }`, or `resource Name { fields }` for a
+    /// struct that values cannot be copied or dropped out of
+    fn parse_struct(&mut self) -> Result<StructDecl, ParseError> {
+        let is_resource = self.check(&Token::Resource);
+        let start = self.consume(if is_resource { Token::Resource } else { Token::Struct })?;
+        let name = self.parse_identifier()?;
+        self.consume(Token::LBrace)?;
+        let (fields, end) = self.parse_comma_separated(Token::RBrace, Self::parse_parameter)?;
+
+        Ok(StructDecl { name, fields, is_resource, span: start.to(end) })
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDecl, ParseError> {
+        let start = self.consume(Token::Enum)?;
+        let name = self.parse_identifier()?;
+        self.consume(Token::LBrace)?;
+        let (variants, end) = self.parse_comma_separated(Token::RBrace, |p| {
+            let start = p.current_span();
+            let name = p.parse_identifier()?;
+            let discriminant = if p.check(&Token::Assign) {
+                p.advance();
+                Some(p.parse_expression()?)
+            } else {
+                None
+            };
+            Ok(EnumVariant { name, discriminant, span: start.to(p.previous_span()) })
+        })?;
+
+        Ok(EnumDecl { name, variants, span: start.to(end) })
+    }
+
+    fn parse_interface(&mut self) -> Result<Interface, ParseError> {
+        let start = self.consume(Token::Interface)?;
+        let name = self.parse_identifier()?;
+        self.consume(Token::LBrace)?;
+
+        let mut functions = Vec::new();
+        while !self.check(&Token::RBrace) {
+            let fn_start = self.consume(Token::Fn)?;
+            let name = self.parse_name()?;
+            let parameters = self.parse_parameters()?;
+            let return_type = self.parse_return_type()?;
+            let end = self.consume(Token::Semicolon)?;
+            functions.push(InterfaceFunction {
+                name,
+                parameters,
+                return_type,
+                span: fn_start.to(end),
+            });
         }
-        
-        self.consume(Token::Fn)?;
+
+        let end = self.consume(Token::RBrace)?;
+        Ok(Interface { name, functions, span: start.to(end) })
+    }
+
+    fn parse_module(&mut self, attributes: Vec<Attribute>) -> Result<Module, ParseError> {
+        let start = attributes.first().map_or(self.current_span(), |a| a.span);
+        self.consume(Token::Mod)?;
         let name = self.parse_identifier()?;
-        
-        self.consume(Token::LParen)?;
-        let mut parameters = Vec::new();
-        while !matches!(self.tokens.peek(), Some(Token::RParen)) {
-            if !parameters.is_empty() {
-                self.consume(Token::Comma)?;
+        let open = self.consume(Token::LBrace)?;
+
+        let mut module = Module {
+            name,
+            attributes,
+            uses: Vec::new(),
+            functions: Vec::new(),
+            span: start,
+        };
+
+        while !self.check(&Token::RBrace) {
+            let from = self.position;
+            let item = match self.peek() {
+                Some(Token::Use) => self.parse_use().map(|decl| module.uses.push(decl)),
+                Some(Token::Hash) | Some(Token::Pure) | Some(Token::Mut) | Some(Token::Fn) => {
+                    self.parse_attributes()
+                        .and_then(|attributes| self.parse_function(attributes))
+                        .map(|function| module.functions.push(function))
+                }
+                Some(Token::Contract) | Some(Token::Interface) | Some(Token::Mod) | None => {
+                    let err = self.unexpected("Expected `}`").with_secondary(open, "unclosed delimiter");
+                    self.report(err);
+                    module.span = start.to(self.previous_span());
+                    return Ok(module);
+                }
+                Some(_) => Err(self.unexpected("Expected function or use declaration")),
+            };
+
+            if let Err(err) = item {
+                self.report(err);
+                self.synchronize_item(from);
             }
-            parameters.push(self.parse_parameter()?);
         }
-        self.consume(Token::RParen)?;
-        
-        let return_type = if matches!(self.tokens.peek(), Some(Token::Arrow)) {
-            self.consume(Token::Arrow)?;
-            Some(self.parse_type()?)
-        } else {
-            None
-        };
-        
+
+        let end = self.consume(Token::RBrace)?;
+        module.span = start.to(end);
+        Ok(module)
+    }
+
+    fn parse_modifier(&mut self) -> Modifier {
+        let token = self.advance();
+        match (&token.token, &token.text[1..]) {
+            (Token::NoReentry, _) => Modifier::NoReentry,
+            (_, "payable") => Modifier::Payable,
+            (_, "view") => Modifier::View,
+            (_, name) => Modifier::Custom(name.to_string()),
+        }
+    }
+
+    fn parse_function(&mut self, attributes: Vec<Attribute>) -> Result<Function, ParseError> {
+        let start = attributes.first().map_or(self.current_span(), |a| a.span);
+        let is_pure = self.check(&Token::Pure);
+        let is_mutable = self.check(&Token::Mut);
+        if is_pure || is_mutable {
+            self.advance();
+        }
+
+        self.consume(Token::Fn)?;
+        let name = self.parse_name()?;
+        let parameters = self.parse_parameters()?;
+        let return_type = self.parse_return_type()?;
         let body = self.parse_block()?;
-        
+
         Ok(Function {
             name,
             parameters,
             return_type,
+            span: start.to(body.span),
             body,
             modifiers: Vec::new(),
+            attributes,
             is_pure,
+            is_mutable,
         })
     }
 
+    fn parse_return_type(&mut self) -> Result<Option<Type>, ParseError> {
+        if !self.check(&Token::Arrow) {
+            return Ok(None);
+        }
+        self.advance();
+        Ok(Some(self.parse_type()?))
+    }
+
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, ParseError> {
+        self.consume(Token::LParen)?;
+        Ok(self.parse_comma_separated(Token::RParen, Self::parse_parameter)?.0)
+    }
+
+    fn parse_parameter(&mut self) -> Result<Parameter, ParseError> {
+        let start = self.current_span();
+        let name = self.parse_identifier()?;
+        self.consume(Token::Colon)?;
+        let type_info = self.parse_type()?;
+        Ok(Parameter {
+            name,
+            type_info,
+            span: start.to(self.previous_span()),
+        })
+    }
+
+    // Statements
+
     fn parse_block(&mut self) -> Result<Block, ParseError> {
-        self.consume(Token::LBrace)?;
-        let mut block = Block::new();
-        
-        while !matches!(self.tokens.peek(), Some(Token::RBrace)) {
-            block.add_statement(self.parse_statement()?);
+        let open = self.consume(Token::LBrace)?;
+        self.with_struct_literals(true, |p| p.parse_block_contents(open))
+    }
+
+    fn parse_block_contents(&mut self, open: Span) -> Result<Block, ParseError> {
+        let mut block = Block::new(open);
+
+        while !self.check(&Token::RBrace) {
+            if self.peek().is_none() || self.at_item_start() {
+                // Ran into the next item: the block was never closed
+                let err = self.unexpected("Expected `}`").with_secondary(open, "unclosed delimiter");
+                self.report(err);
+                block.span = open.to(self.previous_span());
+                return Ok(block);
+            }
+
+            let from = self.position;
+            match self.parse_statement() {
+                Ok(statement) => block.add_statement(statement),
+                Err(err) => {
+                    self.report(err);
+                    let span = self.synchronize_statement(from);
+                    block.add_statement(Statement::new(StatementKind::Error, span));
+                }
+            }
         }
-        
-        self.consume(Token::RBrace)?;
+
+        let close = self.consume(Token::RBrace)?;
+        block.span = open.to(close);
         Ok(block)
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
-        match self.tokens.peek() {
+        match self.peek() {
             Some(Token::Let) => self.parse_let_statement(),
             Some(Token::Return) => self.parse_return_statement(),
             Some(Token::If) => self.parse_if_statement(),
             Some(Token::While) => self.parse_while_statement(),
+            Some(Token::For) => self.parse_for_statement(),
             Some(Token::Emit) => self.parse_emit_statement(),
             Some(Token::Ensure) => self.parse_ensure_statement(),
             _ => self.parse_expression_statement(),
         }
     }
 
+    fn parse_let_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::Let)?;
+        let pattern = self.parse_pattern()?;
+        let type_info = if self.check(&Token::Colon) {
+            self.advance();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        self.consume(Token::Assign)?;
+        let value = self.parse_expression()?;
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(Statement::new(StatementKind::Let { pattern, type_info, value }, start.to(end)))
+    }
+
+    fn parse_return_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::Return)?;
+        let value = if self.check(&Token::Semicolon) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(Statement::new(StatementKind::Return(value), start.to(end)))
+    }
+
+    fn parse_if_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::If)?;
+        let condition = self.parse_condition()?;
+        let then_block = self.parse_block()?;
+
+        let else_block = if self.check(&Token::Else) {
+            let else_span = self.consume(Token::Else)?;
+            if self.check(&Token::If) {
+                // `else if` is sugar for an else block holding a single if statement
+                let nested = self.parse_if_statement()?;
+                Some(Block {
+                    span: else_span.to(nested.span),
+                    statements: vec![nested],
+                })
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        let end = else_block.as_ref().map_or(then_block.span, |b| b.span);
+        Ok(Statement::new(
+            StatementKind::If { condition, then_block, else_block },
+            start.to(end),
+        ))
+    }
+
+    fn parse_while_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::While)?;
+        let condition = self.parse_condition()?;
+        let block = self.parse_block()?;
+        let span = start.to(block.span);
+
+        Ok(Statement::new(StatementKind::While { condition, block }, span))
+    }
+
+    fn parse_for_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::For)?;
+        let pattern = self.parse_pattern()?;
+        self.consume(Token::In)?;
+        let iterable = self.with_struct_literals(false, Self::parse_expression)?;
+        let block = self.parse_block()?;
+        let span = start.to(block.span);
+
+        Ok(Statement::new(StatementKind::For { pattern, iterable, block }, span))
+    }
+
+    fn parse_emit_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::Emit)?;
+        let event = self.parse_identifier()?;
+        let arguments = self.parse_arguments()?;
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(Statement::new(StatementKind::Emit { event, arguments }, start.to(end)))
+    }
+
+    fn parse_ensure_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.consume(Token::Ensure)?;
+        if self.check(&Token::Bang) {
+            self.advance();
+        }
+        self.consume(Token::LParen)?;
+        let condition = self.with_struct_literals(true, Self::parse_expression)?;
+        self.consume(Token::Comma)?;
+        let message = match self.peek() {
+            Some(Token::String) => {
+                let text = self.advance().text;
+                text[1..text.len() - 1].to_string()
+            }
+            _ => return Err(self.unexpected("Expected error message string")),
+        };
+        self.consume(Token::RParen)?;
+        let end = self.consume(Token::Semicolon)?;
+
+        Ok(Statement::new(StatementKind::Ensure { condition, message }, start.to(end)))
+    }
+
+    fn parse_expression_statement(&mut self) -> Result<Statement, ParseError> {
+        let target = self.parse_expression()?;
+        let start = target.span;
+
+        let kind = match self.parse_assigned_value(&target)? {
+            Some(value) => StatementKind::Assignment { target, value },
+            // The value of a block, or a block-like expression, needs no semicolon
+            None if self.check(&Token::RBrace)
+                || (is_block_like(&target) && !self.check(&Token::Semicolon)) =>
+            {
+                let kind = StatementKind::Expression { expression: target, has_semicolon: false };
+                return Ok(Statement::new(kind, start));
+            }
+            None => match target.kind {
+                ExpressionKind::FunctionCall { function, arguments } => {
+                    StatementKind::FunctionCall { function, arguments }
+                }
+                _ => StatementKind::Expression { expression: target, has_semicolon: true },
+            },
+        };
+
+        let end = self.consume(Token::Semicolon)?;
+        Ok(Statement::new(kind, start.to(end)))
+    }
+
+    /// Parses the `= value`, `+= value` or `-= value` following `target`, if
+    /// any. `a += b` is desugared into `a = a + b`.
+    fn parse_assigned_value(&mut self, target: &Expression) -> Result<Option<Expression>, ParseError> {
+        let operator = match self.peek() {
+            Some(Token::Assign) => None,
+            Some(Token::PlusAssign) => Some(BinaryOp::Add),
+            Some(Token::MinusAssign) => Some(BinaryOp::Sub),
+            _ => return Ok(None),
+        };
+        if !is_place(target) {
+            return Err(ParseError::new(ParseErrorKind::InvalidExpression, target.span));
+        }
+        self.advance();
+
+        let value = self.parse_expression()?;
+        Ok(Some(match operator {
+            Some(operator) => binary(target.clone(), operator, value),
+            None => value,
+        }))
+    }
+
+    // Expressions, from lowest to highest precedence
+
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.parse_or()?;
+        if !self.check(&Token::DotDot) {
+            return Ok(start);
+        }
+        self.advance();
+        let end = self.parse_or()?;
+        let span = start.span.to(end.span);
+
+        Ok(Expression::new(
+            ExpressionKind::Range { start: Box::new(start), end: Box::new(end) },
+            span,
+        ))
+    }
+
+    /// Condition of `if` and `while`, including `let pattern = value`
+    fn parse_condition(&mut self) -> Result<Expression, ParseError> {
+        if !self.check(&Token::Let) {
+            return self.with_struct_literals(false, Self::parse_expression);
+        }
+
+        let start = self.advance().span;
+        let pattern = self.parse_pattern()?;
+        self.consume(Token::Assign)?;
+        let value = self.with_struct_literals(false, Self::parse_expression)?;
+        let span = start.to(value.span);
+
+        Ok(Expression::new(ExpressionKind::Let { pattern, value: Box::new(value) }, span))
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.check(&Token::OrOr) {
+            self.advance();
+            let right = self.parse_and()?;
+            left = binary(left, BinaryOp::Or, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_equality()?;
+        while self.check(&Token::AndAnd) {
+            self.advance();
+            let right = self.parse_equality()?;
+            left = binary(left, BinaryOp::And, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Equal) => BinaryOp::Eq,
+                Some(Token::NotEqual) => BinaryOp::NotEq,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_comparison()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::LAngle) => BinaryOp::Lt,
+                Some(Token::RAngle) => BinaryOp::Gt,
+                Some(Token::LessEqual) => BinaryOp::LtEq,
+                Some(Token::GreaterEqual) => BinaryOp::GtEq,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_additive()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Sub,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_cast()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Star) => BinaryOp::Mul,
+                Some(Token::Slash) => BinaryOp::Div,
+                _ => return Ok(left),
+            };
+            self.advance();
+            let right = self.parse_cast()?;
+            left = binary(left, operator, right);
+        }
+    }
+
+    fn parse_cast(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_unary()?;
+        while self.check(&Token::As) {
+            self.advance();
+            let type_info = self.parse_type()?;
+            let span = expr.span.to(self.previous_span());
+            expr = Expression::new(
+                ExpressionKind::Cast { expression: Box::new(expr), type_info },
+                span,
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let operator = match self.peek() {
+            Some(Token::Bang) => UnaryOp::Not,
+            Some(Token::Minus) => UnaryOp::Neg,
+            Some(Token::Star) => UnaryOp::Deref,
+            Some(Token::Amp) if self.peek_nth(1) == Some(&Token::Mut) => {
+                self.advance();
+                UnaryOp::RefMut
+            }
+            Some(Token::Amp) => UnaryOp::Ref,
+            _ => return self.parse_postfix(),
+        };
+        let start = self.advance().span;
+        let operand = self.parse_unary()?;
+        let span = start.to(operand.span);
+
+        Ok(Expression::new(
+            ExpressionKind::Unary { operator, operand: Box::new(operand) },
+            span,
+        ))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            match self.peek() {
+                Some(Token::LParen) => {
+                    let arguments = self.parse_arguments()?;
+                    let span = expr.span.to(self.previous_span());
+                    expr = Expression::new(
+                        ExpressionKind::FunctionCall { function: Box::new(expr), arguments },
+                        span,
+                    );
+                }
+                Some(Token::Dot) => {
+                    self.advance();
+                    let member = self.parse_name()?;
+                    let span = expr.span.to(self.previous_span());
+                    expr = Expression::new(
+                        ExpressionKind::MemberAccess { object: Box::new(expr), member },
+                        span,
+                    );
+                }
+                Some(Token::LBracket) => {
+                    self.advance();
+                    let index = self.with_struct_literals(true, Self::parse_expression)?;
+                    let end = self.consume(Token::RBracket)?;
+                    let span = expr.span.to(end);
+                    expr = Expression::new(
+                        ExpressionKind::IndexAccess { array: Box::new(expr), index: Box::new(index) },
+                        span,
+                    );
+                }
+                Some(Token::Question) => {
+                    let end = self.advance().span;
+                    let span = expr.span.to(end);
+                    expr = Expression::new(ExpressionKind::Try(Box::new(expr)), span);
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let kind = match self.peek() {
+            Some(Token::Identifier) => return self.parse_path_expression(),
+            // Type keywords start paths such as `Address::zero()`
+            Some(Token::Address) | Some(Token::Map) | Some(Token::Result) | Some(Token::U256)
+                if self.peek_nth(1) == Some(&Token::PathSep) =>
+            {
+                return self.parse_path_expression();
+            }
+            // `state(proposal_id)`, `event.amount`
+            Some(Token::State) | Some(Token::Event) if self.at_contextual_identifier() => {
+                ExpressionKind::Identifier(self.current_text().to_string())
+            }
+            Some(Token::Number) => match normalize_number(self.current_text()) {
+                Some(digits) if U256::from_dec_str(&digits).is_ok() => ExpressionKind::NumberLiteral(digits),
+                digits => {
+                    let token = self.advance();
+                    let kind = match digits {
+                        Some(_) => ParseErrorKind::NumberTooLarge(token.text),
+                        None => ParseErrorKind::InvalidNumber(token.text),
+                    };
+                    self.report(ParseError::new(kind, token.span));
+                    return Ok(Expression::new(ExpressionKind::Error, token.span));
+                }
+            },
+            Some(Token::String) => {
+                let text = self.current_text();
+                ExpressionKind::StringLiteral(text[1..text.len() - 1].to_string())
+            }
+            Some(Token::AddressLiteral) => ExpressionKind::AddressLiteral(self.current_text().to_string()),
+            Some(Token::True) => ExpressionKind::BoolLiteral(true),
+            Some(Token::False) => ExpressionKind::BoolLiteral(false),
+            Some(Token::LParen) => return self.with_struct_literals(true, Self::parse_parenthesized),
+            Some(Token::If) => return self.parse_if_expression(),
+            Some(Token::Match) => return self.parse_match_expression(),
+            Some(Token::Pipe) | Some(Token::OrOr) => return self.parse_closure(),
+            Some(Token::LBrace) => {
+                let block = self.parse_block()?;
+                let span = block.span;
+                return Ok(Expression::new(ExpressionKind::Block(block), span));
+            }
+            Some(token) if !is_expression_terminator(token) => {
+                return Err(self.unexpected("Expected expression"));
+            }
+            // The expression is missing entirely: leave a placeholder and
+            // let the enclosing construct carry on from the terminator
+            _ => {
+                let err = match self.peek() {
+                    Some(_) => self.unexpected("Expected expression"),
+                    None => self.eof(),
+                };
+                self.report(err);
+                let span = self.previous_span().shrink_to_end();
+                return Ok(Expression::new(ExpressionKind::Error, span));
+            }
+        };
+        let span = self.advance().span;
+        Ok(Expression::new(kind, span))
+    }
+
+    /// Identifiers, `a::b` paths, macro calls and struct literals
+    fn parse_path_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.current_span();
+        let mut segments = vec![self.advance().text];
+        while self.check(&Token::PathSep) {
+            self.advance();
+            segments.push(self.parse_name()?);
+        }
+
+        if segments.len() == 1 && self.check(&Token::Bang) {
+            self.advance();
+            let close = match self.peek() {
+                Some(Token::LParen) => Token::RParen,
+                Some(Token::LBracket) => Token::RBracket,
+                _ => return Err(self.unexpected("Expected `(` or `[` after macro name")),
+            };
+            self.advance();
+            let (arguments, end) = self.with_struct_literals(true, |p| {
+                p.parse_comma_separated(close, Self::parse_expression)
+            })?;
+            let name = segments.remove(0);
+            return Ok(Expression::new(ExpressionKind::MacroCall { name, arguments }, start.to(end)));
+        }
+
+        if self.struct_literals_allowed && self.at_struct_literal_body() {
+            return self.parse_struct_literal(segments.join("::"), start);
+        }
+
+        let span = start.to(self.previous_span());
+        let kind = if segments.len() == 1 {
+            ExpressionKind::Identifier(segments.remove(0))
+        } else {
+            ExpressionKind::Path(segments)
+        };
+        Ok(Expression::new(kind, span))
+    }
+
+    /// Whether the next tokens are `{ field:` or `{ }`
+    fn at_struct_literal_body(&self) -> bool {
+        self.check(&Token::LBrace)
+            && match self.peek_nth(1) {
+                Some(Token::RBrace) => true,
+                Some(Token::Identifier) => self.peek_nth(2) == Some(&Token::Colon),
+                _ => false,
+            }
+    }
+
+    fn parse_struct_literal(&mut self, name: String, start: Span) -> Result<Expression, ParseError> {
+        self.consume(Token::LBrace)?;
+        let (fields, end) = self.parse_comma_separated(Token::RBrace, |p| {
+            let field = p.parse_identifier()?;
+            p.consume(Token::Colon)?;
+            Ok((field, p.parse_expression()?))
+        })?;
+
+        Ok(Expression::new(ExpressionKind::StructLiteral { name, fields }, start.to(end)))
+    }
+
+    /// `(expr)`, or a tuple: `()`, `(a, b)`, `(a,)`
+    fn parse_parenthesized(&mut self) -> Result<Expression, ParseError> {
+        let open = self.consume(Token::LParen)?;
+        let mut elements = Vec::new();
+        let mut is_tuple = false;
+        while !self.check(&Token::RParen) {
+            elements.push(self.parse_expression()?);
+            if !self.check(&Token::Comma) {
+                break;
+            }
+            self.advance();
+            is_tuple = true;
+        }
+        let close = self.consume(Token::RParen)?;
+        let span = open.to(close);
+
+        if elements.len() == 1 && !is_tuple {
+            let mut inner = elements.remove(0);
+            inner.span = span;
+            return Ok(inner);
+        }
+        Ok(Expression::new(ExpressionKind::Tuple(elements), span))
+    }
+
+    fn parse_if_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.consume(Token::If)?;
+        let condition = self.parse_condition()?;
+        let then_block = self.parse_block()?;
+
+        let else_block = if self.check(&Token::Else) {
+            let else_span = self.advance().span;
+            if self.check(&Token::If) {
+                let nested = self.parse_if_expression()?;
+                let span = nested.span;
+                let kind = StatementKind::Expression { expression: nested, has_semicolon: false };
+                Some(Block {
+                    span: else_span.to(span),
+                    statements: vec![Statement::new(kind, span)],
+                })
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        let end = else_block.as_ref().map_or(then_block.span, |b| b.span);
+        Ok(Expression::new(
+            ExpressionKind::If { condition: Box::new(condition), then_block, else_block },
+            start.to(end),
+        ))
+    }
+
+    fn parse_match_expression(&mut self) -> Result<Expression, ParseError> {
+        let start = self.consume(Token::Match)?;
+        let scrutinee = self.with_struct_literals(false, Self::parse_expression)?;
+        self.consume(Token::LBrace)?;
+
+        let arms = self.with_struct_literals(true, |p| {
+            let mut arms = Vec::new();
+            while !p.check(&Token::RBrace) {
+                let pattern = p.parse_pattern()?;
+                p.consume(Token::FatArrow)?;
+                let mut body = p.parse_expression()?;
+                if let Some(value) = p.parse_assigned_value(&body)? {
+                    let span = body.span.to(value.span);
+                    body = Expression::new(
+                        ExpressionKind::Assign { target: Box::new(body), value: Box::new(value) },
+                        span,
+                    );
+                }
+
+                // The comma after an arm is optional when its body is a block
+                let needs_comma = !matches!(body.kind, ExpressionKind::Block(_));
+                let span = pattern.span.to(body.span);
+                arms.push(MatchArm { pattern, body, span });
+
+                if p.check(&Token::Comma) {
+                    p.advance();
+                } else if needs_comma && !p.check(&Token::RBrace) {
+                    return Err(p.unexpected("Expected `,` or `}` after match arm"));
+                }
+            }
+            Ok(arms)
+        })?;
+
+        let end = self.consume(Token::RBrace)?;
+        Ok(Expression::new(
+            ExpressionKind::Match { scrutinee: Box::new(scrutinee), arms },
+            start.to(end),
+        ))
+    }
+
+    fn parse_closure(&mut self) -> Result<Expression, ParseError> {
+        let start = self.current_span();
+        let parameters = if self.check(&Token::OrOr) {
+            self.advance();
+            Vec::new()
+        } else {
+            self.consume(Token::Pipe)?;
+            self.parse_comma_separated(Token::Pipe, Self::parse_pattern)?.0
+        };
+        let body = self.parse_expression()?;
+        let span = start.to(body.span);
+
+        Ok(Expression::new(ExpressionKind::Closure { parameters, body: Box::new(body) }, span))
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        self.consume(Token::LParen)?;
+        let (arguments, _) = self.with_struct_literals(true, |p| {
+            p.parse_comma_separated(Token::RParen, Self::parse_expression)
+        })?;
+        Ok(arguments)
+    }
+
+    // Patterns
+
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let start = self.current_span();
+        let kind = match self.peek() {
+            Some(Token::Amp) => {
+                self.advance();
+                PatternKind::Reference(Box::new(self.parse_pattern()?))
+            }
+            Some(Token::Mut) => {
+                self.advance();
+                PatternKind::Identifier { name: self.parse_identifier()?, mutable: true }
+            }
+            Some(Token::LParen) => {
+                self.advance();
+                PatternKind::Tuple(self.parse_comma_separated(Token::RParen, Self::parse_pattern)?.0)
+            }
+            Some(Token::Number) | Some(Token::AddressLiteral) | Some(Token::String) | Some(Token::True)
+            | Some(Token::False) | Some(Token::Minus) => {
+                PatternKind::Literal(Box::new(self.parse_unary()?))
+            }
+            Some(Token::State) | Some(Token::Event) if self.at_contextual_identifier() => {
+                PatternKind::Identifier { name: self.advance().text, mutable: false }
+            }
+            Some(Token::Identifier) => {
+                let mut path = vec![self.advance().text];
+                while self.check(&Token::PathSep) {
+                    self.advance();
+                    path.push(self.parse_name()?);
+                }
+
+                if self.check(&Token::LParen) {
+                    self.advance();
+                    let fields = self.parse_comma_separated(Token::RParen, Self::parse_pattern)?.0;
+                    PatternKind::TupleStruct { path, fields }
+                } else if path.len() > 1 {
+                    PatternKind::Path(path)
+                } else if path[0] == "_" {
+                    PatternKind::Wildcard
+                } else {
+                    PatternKind::Identifier { name: path.remove(0), mutable: false }
+                }
+            }
+            Some(_) => return Err(self.unexpected("Expected pattern")),
+            None => return Err(self.eof()),
+        };
+
+        Ok(Pattern::new(kind, start.to(self.previous_span())))
+    }
+
+    // Types
+
     fn parse_type(&mut self) -> Result<Type, ParseError> {
-        match self.tokens.next() {
-            Some(Token::Address) => Ok(Type::Address),
-            Some(Token::U256) => Ok(Type::U256),
+        match self.peek() {
+            Some(Token::Address) => {
+                self.advance();
+                Ok(Type::Address)
+            }
+            Some(Token::U256) => {
+                self.advance();
+                Ok(Type::U256)
+            }
             Some(Token::Map) => {
+                self.advance();
                 self.consume(Token::LAngle)?;
                 let key_type = Box::new(self.parse_type()?);
                 self.consume(Token::Comma)?;
@@ -171,6 +1111,7 @@ impl Parser {
                 Ok(Type::Map { key_type, value_type })
             }
             Some(Token::Result) => {
+                self.advance();
                 self.consume(Token::LAngle)?;
                 let ok_type = Box::new(self.parse_type()?);
                 self.consume(Token::Comma)?;
@@ -178,35 +1119,321 @@ impl Parser {
                 self.consume(Token::RAngle)?;
                 Ok(Type::Result { ok_type, err_type })
             }
-            Some(Token::Identifier) => Ok(Type::Custom(self.parse_identifier()?)),
-            _ => Err(ParseError::UnexpectedToken("Expected type".into())),
+            Some(Token::LParen) => {
+                self.advance();
+                let (mut elements, _) = self.parse_comma_separated(Token::RParen, Self::parse_type)?;
+                Ok(if elements.len() == 1 {
+                    elements.remove(0)
+                } else {
+                    Type::Tuple(elements)
+                })
+            }
+            Some(Token::Identifier) => {
+                let name = self.parse_identifier()?;
+                if self.check(&Token::LAngle) {
+                    self.advance();
+                    let (mut arguments, _) = self.parse_comma_separated(Token::RAngle, Self::parse_type)?;
+                    return Ok(if name == "Vec" && arguments.len() == 1 {
+                        Type::Array(Box::new(arguments.remove(0)))
+                    } else {
+                        Type::Generic { name, arguments }
+                    });
+                }
+                Ok(match name.as_str() {
+                    "bool" => Type::Bool,
+                    "String" | "string" => Type::String,
+                    _ => Type::Custom(name),
+                })
+            }
+            _ => Err(self.unexpected("Expected type")),
         }
     }
 
     fn parse_identifier(&mut self) -> Result<String, ParseError> {
-        match self.tokens.next() {
-            Some(Token::Identifier) => Ok("identifier".to_string()), // In a real implementation, we'd get the actual identifier text
-            _ => Err(ParseError::UnexpectedToken("Expected identifier".into())),
+        match self.peek() {
+            Some(Token::Identifier) => Ok(self.advance().text),
+            Some(_) => Err(self.unexpected("Expected identifier")),
+            None => Err(self.eof()),
+        }
+    }
+
+    /// Like `parse_identifier`, but also accepts keywords, for names that
+    /// cannot be mistaken for one: function names, members, path segments
+    fn parse_name(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some(Token::Identifier) => Ok(self.advance().text),
+            Some(_) if is_word(self.current_text()) => Ok(self.advance().text),
+            Some(_) => Err(self.unexpected("Expected identifier")),
+            None => Err(self.eof()),
+        }
+    }
+
+    /// Parses `item, item, ...` up to and including `close`, allowing a
+    /// trailing comma. Returns the items and the span of `close`.
+    fn parse_comma_separated<T>(
+        &mut self,
+        close: Token,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<(Vec<T>, Span), ParseError> {
+        let mut items = Vec::new();
+        while !self.check(&close) {
+            items.push(item(self)?);
+            if !self.check(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        let end = self.consume(close)?;
+        Ok((items, end))
+    }
+
+    /// Runs `parse` with struct literals allowed or not. They are not allowed
+    /// in the head of `if`, `while`, `for` and `match`, where `Name {` opens
+    /// the body instead.
+    fn with_struct_literals<T>(
+        &mut self,
+        allowed: bool,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let saved = std::mem::replace(&mut self.struct_literals_allowed, allowed);
+        let result = parse(self);
+        self.struct_literals_allowed = saved;
+        result
+    }
+
+    // Token helpers
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.position + n).map(|t| &t.token)
+    }
+
+    fn check(&self, expected: &Token) -> bool {
+        self.peek() == Some(expected)
+    }
+
+    fn advance(&mut self) -> SpannedToken {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        token
+    }
+
+    fn current_text(&self) -> &str {
+        &self.tokens[self.position].text
+    }
+
+    fn current_span(&self) -> Span {
+        self.tokens.get(self.position).map_or(self.eof_span, |t| t.span)
+    }
+
+    fn previous_span(&self) -> Span {
+        self.position
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+            .map_or(self.eof_span, |t| t.span)
+    }
+
+    fn consume(&mut self, expected: Token) -> Result<Span, ParseError> {
+        match self.peek() {
+            Some(token) if *token == expected => Ok(self.advance().span),
+            Some(_) => Err(self.unexpected(&format!("Expected {:?}", expected))),
+            None => Err(self.eof()),
+        }
+    }
+
+    // Error recovery
+
+    /// Records an error, dropping it if it points at the same place as the
+    /// previous one: that is a cascade of the same mistake
+    fn report(&mut self, err: ParseError) {
+        if self.errors.last().is_some_and(|last| last.span.start == err.span.start) {
+            return;
+        }
+        self.errors.push(err);
+    }
+
+    /// Skips the rest of a broken statement that started at token `from`:
+    /// up to and including the next `;`, or up to the `}` closing the
+    /// enclosing block, the start of the next statement or the next item.
+    /// Returns the span of the skipped region.
+    fn synchronize_statement(&mut self, from: usize) -> Span {
+        let start = self.tokens.get(from).map_or(self.eof_span, |t| t.span);
+        let mut depth = 0usize;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => break,
+                Token::RBrace => depth -= 1,
+                Token::Semicolon if depth == 0 => {
+                    self.advance();
+                    break;
+                }
+                _ if self.at_item_start() => break,
+                token if depth == 0 && self.position > from && is_statement_start(token) => break,
+                _ => {}
+            }
+            self.advance();
+        }
+
+        start.to(self.previous_span())
+    }
+
+    /// Skips the rest of a broken contract item (or top-level item) that
+    /// started at token `from`, stopping before the next item keyword or the
+    /// `}` closing the enclosing contract
+    fn synchronize_item(&mut self, from: usize) {
+        let mut depth = 0usize;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LBrace => depth += 1,
+                Token::RBrace if depth == 0 => {
+                    // A stray `}` at top level would otherwise stop progress
+                    if self.position == from {
+                        self.advance();
+                    }
+                    return;
+                }
+                Token::RBrace => depth -= 1,
+                _ if depth == 0 && self.position > from && self.at_item_start() => return,
+                _ => {}
+            }
+            self.advance();
         }
     }
 
-    fn consume(&mut self, expected: Token) -> Result<(), ParseError> {
-        match self.tokens.next() {
-            Some(token) if token == expected => Ok(()),
-            Some(_) => Err(ParseError::UnexpectedToken(format!("Expected {:?}", expected))),
-            None => Err(ParseError::UnexpectedEOF),
+    /// Whether the next tokens start a contract or top-level item. `mut` is
+    /// only treated as such when followed by `fn`, `state` and `event` when
+    /// followed by a name.
+    fn at_item_start(&self) -> bool {
+        match self.peek() {
+            Some(Token::Mut) => self.peek_nth(1) == Some(&Token::Fn),
+            Some(Token::State) | Some(Token::Event) => !self.at_contextual_identifier(),
+            Some(token) => is_item_start(token),
+            None => false,
         }
     }
+
+    /// `state` and `event` only introduce declarations when a name follows;
+    /// elsewhere they are ordinary identifiers
+    fn at_contextual_identifier(&self) -> bool {
+        matches!(self.peek(), Some(Token::State) | Some(Token::Event))
+            && self.peek_nth(1) != Some(&Token::Identifier)
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError::new(ParseErrorKind::UnexpectedToken(expected.to_string()), self.current_span())
+    }
+
+    fn eof(&self) -> ParseError {
+        ParseError::new(ParseErrorKind::UnexpectedEOF, self.eof_span)
+    }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Program, ParseError> {
-    let mut parser = Parser::new(tokens);
-    parser.parse()
+fn is_item_start(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Contract | Token::Interface | Token::Use | Token::Mod | Token::Hash
+            | Token::State | Token::Event | Token::Type | Token::Struct | Token::Resource | Token::Enum
+            | Token::Pure | Token::Mut | Token::Fn | Token::NoReentry | Token::ModifierName
+    )
+}
+
+fn is_statement_start(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Let | Token::Return | Token::If | Token::While | Token::For | Token::Emit | Token::Ensure
+    )
+}
+
+/// Keywords and identifiers
+fn is_word(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expressions that end in a block and need no semicolon as statements
+fn is_block_like(expr: &Expression) -> bool {
+    matches!(
+        expr.kind,
+        ExpressionKind::If { .. } | ExpressionKind::Match { .. } | ExpressionKind::Block(_)
+    )
+}
+
+/// Expressions that can be assigned to
+fn is_place(expr: &Expression) -> bool {
+    matches!(
+        expr.kind,
+        ExpressionKind::Identifier(_)
+            | ExpressionKind::MemberAccess { .. }
+            | ExpressionKind::IndexAccess { .. }
+            | ExpressionKind::Error
+            | ExpressionKind::Unary { operator: UnaryOp::Deref, .. }
+    )
+}
+
+/// Expands a number literal written in scientific notation into plain
+/// decimal digits, e.g. `0.003e18` into `3000000000000000`. Returns `None`
+/// when the literal is not an integer, such as `1.5` or `1.25e1`.
+fn normalize_number(text: &str) -> Option<String> {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (&text[..i], text[i + 1..].parse::<usize>().ok()?),
+        None => (text, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > exponent {
+        return None;
+    }
+
+    let digits = format!("{}{}{}", integer, fraction, "0".repeat(exponent - fraction.len()));
+    let digits = digits.trim_start_matches('0');
+    Some(if digits.is_empty() { "0".to_string() } else { digits.to_string() })
+}
+
+/// Tokens that can directly follow a complete expression
+fn is_expression_terminator(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Semicolon | Token::Comma | Token::RParen | Token::RBracket | Token::RBrace
+    )
+}
+
+fn binary(left: Expression, operator: BinaryOp, right: Expression) -> Expression {
+    let span = left.span.to(right.span);
+    Expression::new(
+        ExpressionKind::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        },
+        span,
+    )
+}
+
+/// Parses the whole input, failing with every syntax error found
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<Program, Vec<ParseError>> {
+    let (program, errors) = parse_recovering(tokens);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses as much as possible, for tools that need an AST of incomplete code
+pub fn parse_recovering(tokens: Vec<SpannedToken>) -> (Program, Vec<ParseError>) {
+    Parser::new(tokens).parse_recovering()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diagnostics::SourceFile;
     use crate::lexer::tokenize;
 
     #[test]
@@ -214,18 +1441,18 @@ mod tests {
         let input = r#"
             contract TokenContract {
                 state balance: Map<Address, u256>;
-                
+
                 event Transfer(from: Address, to: Address, amount: u256);
-                
+
                 pure fn get_balance(owner: Address) -> u256 {
                     return balance[owner];
                 }
             }
         "#;
-        
+
         let tokens = tokenize(input).unwrap();
         let program = parse(tokens).unwrap();
-        
+
         assert_eq!(program.contracts.len(), 1);
         let contract = &program.contracts[0];
         assert_eq!(contract.name, "TokenContract");
@@ -233,4 +1460,233 @@ mod tests {
         assert_eq!(contract.events.len(), 1);
         assert_eq!(contract.functions.len(), 1);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_statement_and_expression_spans() {
+        let input = "contract C { fn f() { let total = a + b * 2; } }";
+        let program = parse(tokenize(input).unwrap()).unwrap();
+        let statement = &program.contracts[0].functions[0].body.statements[0];
+
+        assert_eq!(&input[statement.span.start..statement.span.end], "let total = a + b * 2;");
+        match &statement.kind {
+            StatementKind::Let { value, .. } => {
+                assert_eq!(&input[value.span.start..value.span.end], "a + b * 2");
+            }
+            other => panic!("expected let, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unclosed_block_points_at_opening_brace() {
+        let input = "contract C { fn f() { return;";
+        let errors = parse(tokenize(input).unwrap()).unwrap_err();
+        let err = &errors[0];
+
+        assert_eq!(err.span, Span::new(input.len(), input.len()));
+        assert_eq!(err.secondary[0].span.start, input.find("{ return").unwrap());
+    }
+
+    #[test]
+    fn test_reports_every_syntax_error() {
+        let input = r#"
+            contract C {
+                state total u256;
+                fn f() {
+                    let a = 1 +;
+                    let b = 2
+                    let c = ) 3;
+                    return a;
+                }
+                event E(x: u256);
+            }
+        "#;
+        let (program, errors) = parse_recovering(tokenize(input).unwrap());
+
+        assert_eq!(errors.len(), 4);
+        let contract = &program.contracts[0];
+        assert_eq!(contract.events.len(), 1);
+        assert_eq!(contract.functions.len(), 1);
+
+        let statements = &contract.functions[0].body.statements;
+        assert_eq!(statements.len(), 4);
+        match &statements[0].kind {
+            StatementKind::Let { value, .. } => match &value.kind {
+                ExpressionKind::Binary { right, .. } => {
+                    assert!(matches!(right.kind, ExpressionKind::Error));
+                }
+                other => panic!("expected binary, found {:?}", other),
+            },
+            other => panic!("expected let, found {:?}", other),
+        }
+        assert!(matches!(statements[1].kind, StatementKind::Error));
+        assert!(matches!(statements[2].kind, StatementKind::Error));
+        assert!(matches!(statements[3].kind, StatementKind::Return(Some(_))));
+    }
+
+    #[test]
+    fn test_partial_ast_for_half_typed_file() {
+        let input = "contract C { state x: u256; fn f() { x = x + ";
+        let (program, errors) = parse_recovering(tokenize(input).unwrap());
+
+        assert!(!errors.is_empty());
+        let contract = &program.contracts[0];
+        assert_eq!(contract.state_vars.len(), 1);
+        assert_eq!(contract.functions[0].name, "f");
+    }
+
+    #[test]
+    fn test_tuples_type_aliases_and_mut_fn() {
+        let input = r#"
+            use std::math::{sqrt, min};
+
+            contract Pool {
+                type Token = Address;
+                state fee_rate: u256 = 0.003e18;
+
+                @only_owner
+                mut fn reserves(token_in: Token) -> Result<(u256, u256), Error> {
+                    ensure!(token_in != token_a, "Same tokens");
+                    let (reserve_in, reserve_out) = if token_in == token_a {
+                        (reserve_a, reserve_b)
+                    } else {
+                        return Err(Error::InvalidToken);
+                    };
+                    token_a.transfer(msg.sender, 1e18)?;
+                    Ok((reserve_in, reserve_out))
+                }
+            }
+        "#;
+        let program = parse(tokenize(input).unwrap()).unwrap();
+
+        assert_eq!(program.uses[0].path, ["std", "math"]);
+        assert_eq!(program.uses[0].names, ["sqrt", "min"]);
+
+        let contract = &program.contracts[0];
+        assert_eq!(contract.type_aliases[0].name, "Token");
+        assert!(matches!(contract.type_aliases[0].type_info, Type::Address));
+        match &contract.state_vars[0].initializer.as_ref().unwrap().kind {
+            ExpressionKind::NumberLiteral(digits) => assert_eq!(digits, "3000000000000000"),
+            other => panic!("expected number, found {:?}", other),
+        }
+
+        let function = &contract.functions[0];
+        assert!(function.is_mutable);
+        assert!(matches!(&function.modifiers[..], [Modifier::Custom(name)] if name == "only_owner"));
+        assert_eq!(
+            function.return_type,
+            Some(Type::Result {
+                ok_type: Box::new(Type::Tuple(vec![Type::U256, Type::U256])),
+                err_type: Box::new(Type::Custom("Error".to_string())),
+            })
+        );
+
+        let statements = &function.body.statements;
+        match &statements[1].kind {
+            StatementKind::Let { pattern, value, .. } => {
+                assert!(matches!(&pattern.kind, PatternKind::Tuple(elements) if elements.len() == 2));
+                assert!(matches!(value.kind, ExpressionKind::If { .. }));
+            }
+            other => panic!("expected let, found {:?}", other),
+        }
+        match &statements[2].kind {
+            StatementKind::Expression { expression, has_semicolon: true } => {
+                assert!(matches!(expression.kind, ExpressionKind::Try(_)));
+            }
+            other => panic!("expected expression statement, found {:?}", other),
+        }
+        assert!(matches!(
+            statements[3].kind,
+            StatementKind::Expression { has_semicolon: false, .. }
+        ));
+    }
+
+    #[test]
+    fn test_struct_literal_is_not_parsed_in_conditions() {
+        let input = r#"
+            contract C {
+                fn f() {
+                    if total == limit { total = 0; }
+                    let p = Proposal { id: 1, executed: false };
+                    match vote { VoteType::For => p.for_votes += 1, _ => {} }
+                    for (i, x) in items.iter().enumerate() { }
+                }
+            }
+        "#;
+        let program = parse(tokenize(input).unwrap()).unwrap();
+        let statements = &program.contracts[0].functions[0].body.statements;
+
+        assert!(matches!(statements[0].kind, StatementKind::If { .. }));
+        match &statements[1].kind {
+            StatementKind::Let { value, .. } => {
+                assert!(matches!(&value.kind, ExpressionKind::StructLiteral { fields, .. } if fields.len() == 2));
+            }
+            other => panic!("expected let, found {:?}", other),
+        }
+        match &statements[2].kind {
+            StatementKind::Expression { expression, .. } => match &expression.kind {
+                ExpressionKind::Match { arms, .. } => {
+                    assert!(matches!(arms[0].body.kind, ExpressionKind::Assign { .. }));
+                    assert!(matches!(arms[1].pattern.kind, PatternKind::Wildcard));
+                }
+                other => panic!("expected match, found {:?}", other),
+            },
+            other => panic!("expected expression statement, found {:?}", other),
+        }
+        assert!(matches!(statements[3].kind, StatementKind::For { .. }));
+    }
+
+    #[test]
+    fn test_number_literals() {
+        assert_eq!(normalize_number("42").as_deref(), Some("42"));
+        assert_eq!(normalize_number("1e18").as_deref(), Some("1000000000000000000"));
+        assert_eq!(normalize_number("1.5e3").as_deref(), Some("1500"));
+        assert_eq!(normalize_number("0.0").as_deref(), Some("0"));
+        assert_eq!(normalize_number("1.5"), None);
+        assert_eq!(normalize_number("1.25e1"), None);
+
+        // 1e77 fits in u256, 1e78 does not
+        let input = "contract C { state a: u256 = 1e77; state b: u256 = 1e78; }";
+        let errors = parse(tokenize(input).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0].kind, ParseErrorKind::NumberTooLarge(text) if text == "1e78"));
+    }
+
+    #[test]
+    fn test_address_literals() {
+        let input = "contract C { state admin: Address = 0x00000000000000000000000000000000000000aB; }";
+        let program = parse(tokenize(input).unwrap()).unwrap();
+        match &program.contracts[0].state_vars[0].initializer.as_ref().unwrap().kind {
+            ExpressionKind::AddressLiteral(text) => assert_eq!(text, "0x00000000000000000000000000000000000000aB"),
+            other => panic!("expected address, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_examples() {
+        fn strx_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    strx_files(&path, files);
+                } else if path.extension().is_some_and(|ext| ext == "strx") {
+                    files.push(path);
+                }
+            }
+        }
+
+        let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut files = Vec::new();
+        strx_files(&examples, &mut files);
+        assert!(!files.is_empty());
+
+        for path in files {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let file = SourceFile::new(path.display().to_string(), source.clone());
+            let tokens = tokenize(&source).unwrap_or_else(|e| panic!("{}", e.to_diagnostic().render(&file)));
+            if let Err(errors) = parse(tokens) {
+                let rendered: Vec<_> = errors.iter().map(|e| e.to_diagnostic().render(&file)).collect();
+                panic!("{}", rendered.join("\n"));
+            }
+        }
+    }
+}
@@ -1,8 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::ast::*;
+use crate::bytecode::{self, host};
+use crate::diagnostics::{Diagnostic, Label};
+use crate::span::Span;
 
 #[derive(Debug)]
-pub enum TypeError {
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    pub span: Span,
+    pub secondary: Vec<Label>,
+}
+
+#[derive(Debug)]
+pub enum TypeErrorKind {
     TypeMismatch {
         expected: Type,
         found: Type,
@@ -10,19 +20,146 @@ pub enum TypeError {
     UndefinedVariable(String),
     UndefinedFunction(String),
     UndefinedType(String),
+    /// A type or enum variant declared twice
+    DuplicateDefinition(String),
+    /// A `use` of a path that is not in the standard library
+    UnresolvedImport(String),
     InvalidOperation {
         op: String,
         type_name: String,
     },
-    ReentrancyVulnerability(String),
     StateModificationInPureFunction,
+    /// Assignment to a `let` binding declared without `mut`
+    AssignToImmutable(String),
     InvalidEventEmission(String),
+    /// Any attribute but `#[cfg(test)]` on a module or `#[test]` on one
+    /// of its functions
+    UnknownAttribute(String),
+    /// A `#[test]` function in the named module, which lacks `#[cfg(test)]`
+    TestOutsideTestModule(String),
+    /// A resource variable still held where it goes out of scope
+    ResourceLeak(String),
+    /// A value of the named resource type computed and thrown away
+    ResourceDropped(String),
+    ResourceUsedAfterMove(String),
+    /// `.clone()` of a resource, or a resource moved out of storage
+    ResourceCopied(String),
+    /// A resource moved on one path through a branch but not another
+    ResourceMovedOnSomePaths(String),
+    /// A resource declared outside a loop moved or replaced inside it
+    ResourceMovedInLoop(String),
+}
+
+impl TypeError {
+    pub fn new(kind: TypeErrorKind, span: Span) -> Box<Self> {
+        Box::new(TypeError {
+            kind,
+            span,
+            secondary: Vec::new(),
+        })
+    }
+
+    fn with_secondary(mut self: Box<Self>, span: Span, message: impl Into<String>) -> Box<Self> {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let (message, label) = match &self.kind {
+            TypeErrorKind::TypeMismatch { expected, found } => (
+                "mismatched types".to_string(),
+                format!("expected {:?}, found {:?}", expected, found),
+            ),
+            TypeErrorKind::UndefinedVariable(name) => (
+                format!("cannot find value `{}` in this scope", name),
+                "not found in this scope".to_string(),
+            ),
+            TypeErrorKind::UndefinedFunction(name) => (
+                format!("cannot find function `{}` in this scope", name),
+                "not found in this scope".to_string(),
+            ),
+            TypeErrorKind::UndefinedType(name) => (
+                format!("cannot find type `{}` in this scope", name),
+                "not found in this scope".to_string(),
+            ),
+            TypeErrorKind::DuplicateDefinition(name) => (
+                format!("the name `{}` is defined multiple times", name),
+                "redefined here".to_string(),
+            ),
+            TypeErrorKind::UnresolvedImport(path) => (
+                format!("unresolved import `{}`", path),
+                "no such item in the standard library".to_string(),
+            ),
+            TypeErrorKind::InvalidOperation { op, type_name } => (
+                format!("invalid operation `{}`", op),
+                format!("not supported for {}", type_name),
+            ),
+            TypeErrorKind::StateModificationInPureFunction => (
+                "state modification in pure function".to_string(),
+                "pure functions cannot modify state".to_string(),
+            ),
+            TypeErrorKind::AssignToImmutable(name) => (
+                format!("cannot assign twice to immutable variable `{}`", name),
+                "cannot assign twice to immutable variable".to_string(),
+            ),
+            TypeErrorKind::InvalidEventEmission(msg) => (
+                "invalid event emission".to_string(),
+                msg.clone(),
+            ),
+            TypeErrorKind::UnknownAttribute(attribute) => (
+                format!("cannot find attribute `{}`", attribute),
+                "only `#[cfg(test)]` modules and their `#[test]` functions take attributes".to_string(),
+            ),
+            TypeErrorKind::TestOutsideTestModule(module) => (
+                format!("test function in module `{}`, which is not `#[cfg(test)]`", module),
+                "tests belong in a `#[cfg(test)]` module".to_string(),
+            ),
+            TypeErrorKind::ResourceLeak(name) => (
+                format!("resource `{}` is never consumed", name),
+                "must be moved or destroyed before it goes out of scope".to_string(),
+            ),
+            TypeErrorKind::ResourceDropped(type_name) => (
+                format!("value of resource type `{}` is dropped", type_name),
+                "this value must be bound, moved or destroyed".to_string(),
+            ),
+            TypeErrorKind::ResourceUsedAfterMove(name) => (
+                format!("use of moved resource `{}`", name),
+                "value used here after move".to_string(),
+            ),
+            TypeErrorKind::ResourceCopied(name) => (
+                format!("resource `{}` cannot be copied", name),
+                "resources can only be moved".to_string(),
+            ),
+            TypeErrorKind::ResourceMovedOnSomePaths(name) => (
+                format!("resource `{}` is consumed on some paths but not others", name),
+                "every branch must consume it, or none".to_string(),
+            ),
+            TypeErrorKind::ResourceMovedInLoop(name) => (
+                format!("resource `{}` is moved inside a loop", name),
+                "a later iteration would use it again".to_string(),
+            ),
+        };
+        let mut diagnostic = Diagnostic::error(message, Label::new(self.span, label));
+        diagnostic.secondary = self.secondary.clone();
+        diagnostic
+    }
 }
 
 pub struct TypeChecker {
     variables: HashMap<String, Type>,
+    /// State variables, which `self.x` names even where a local shadows `x`
+    state: HashMap<String, Type>,
+    /// `let` bindings without `mut` in the current function, where each
+    /// was declared
+    immutable: HashMap<String, Span>,
     functions: HashMap<String, FunctionSignature>,
-    events: HashMap<String, Vec<Parameter>>,
+    interfaces: HashMap<String, Interface>,
+    events: HashMap<String, (Vec<Parameter>, Span)>,
+    structs: HashMap<String, StructDecl>,
+    /// Enum and imported type names
+    type_names: HashSet<String>,
+    /// Type aliases, already resolved
+    aliases: HashMap<String, Type>,
     current_function: Option<String>,
     is_pure_context: bool,
 }
@@ -31,43 +168,119 @@ pub struct TypeChecker {
 struct FunctionSignature {
     parameters: Vec<Parameter>,
     return_type: Option<Type>,
-    is_pure: bool,
+    is_mutable: bool,
+    span: Span,
 }
 
 impl TypeChecker {
     pub fn new() -> Self {
         TypeChecker {
             variables: HashMap::new(),
+            state: HashMap::new(),
+            immutable: HashMap::new(),
             functions: HashMap::new(),
+            interfaces: HashMap::new(),
             events: HashMap::new(),
+            structs: HashMap::new(),
+            type_names: HashSet::new(),
+            // Narrower integers and hashes are checked as `u256`
+            aliases: ["u8", "u64", "bytes32"].into_iter()
+                .map(|name| (name.to_string(), Type::U256))
+                .collect(),
             current_function: None,
             is_pure_context: false,
         }
     }
 
-    pub fn check(&mut self, program: &Program) -> Result<(), TypeError> {
+    pub fn check(&mut self, program: &Program) -> Result<(), Box<TypeError>> {
+        for decl in &program.uses {
+            self.resolve_use(decl)?;
+        }
+
         // First pass: collect all declarations
+        for interface in &program.interfaces {
+            self.interfaces.insert(interface.name.clone(), interface.clone());
+        }
         for contract in &program.contracts {
             self.collect_declarations(contract)?;
         }
 
         // Second pass: check implementations
+        for interface in &program.interfaces {
+            self.check_interface(interface)?;
+        }
         for contract in &program.contracts {
             self.check_contract(contract)?;
         }
+        for module in &program.modules {
+            self.check_module(module)?;
+        }
+
+        Ok(())
+    }
 
+    /// Brings the types named by a `use` of a `std` module into scope.
+    /// Functions it names must be host functions.
+    fn resolve_use(&mut self, decl: &UseDecl) -> Result<(), Box<TypeError>> {
+        let unresolved = |path: String| TypeError::new(TypeErrorKind::UnresolvedImport(path), decl.span);
+        let types = match decl.path.as_slice() {
+            [std, module] if std == "std" => STD_TYPES.iter()
+                .find(|(name, _)| name == module)
+                .map(|(_, types)| *types)
+                .ok_or_else(|| unresolved(decl.path.join("::")))?,
+            _ => return Err(unresolved(decl.path.join("::"))),
+        };
+        if decl.glob {
+            types.iter().for_each(|name| self.import_type(name));
+        }
+        for name in &decl.names {
+            if types.contains(&name.as_str()) {
+                self.import_type(name);
+            } else if host::lookup(name).filter(|(_, entry)| !entry.is_value()).is_none() {
+                return Err(unresolved(format!("{}::{}", decl.path.join("::"), name)));
+            }
+        }
         Ok(())
     }
 
-    fn collect_declarations(&mut self, contract: &Contract) -> Result<(), TypeError> {
+    fn import_type(&mut self, name: &str) {
+        if let Some((_, type_info)) = STD_ALIASES.iter().find(|(alias, _)| *alias == name) {
+            self.aliases.insert(name.to_string(), type_info.clone());
+        } else {
+            self.type_names.insert(name.to_string());
+        }
+    }
+
+    fn collect_declarations(&mut self, contract: &Contract) -> Result<(), Box<TypeError>> {
         // Collect state variables
         for var in &contract.state_vars {
             self.variables.insert(var.name.clone(), var.type_info.clone());
+            self.state.insert(var.name.clone(), var.type_info.clone());
+        }
+
+        // Collect types. Structs, enums and aliases share one namespace.
+        let mut types: Vec<_> = contract.structs.iter().map(|decl| (&decl.name, decl.span))
+            .chain(contract.enums.iter().map(|decl| (&decl.name, decl.span)))
+            .chain(contract.type_aliases.iter().map(|alias| (&alias.name, alias.span)))
+            .collect();
+        types.sort_by_key(|(_, span)| span.start);
+        check_unique(types)?;
+        for decl in &contract.enums {
+            check_unique(decl.variants.iter().map(|variant| (&variant.name, variant.span)).collect())?;
+        }
+        for decl in &contract.structs {
+            self.structs.insert(decl.name.clone(), decl.clone());
+        }
+        for decl in &contract.enums {
+            self.type_names.insert(decl.name.clone());
+        }
+        for alias in &contract.type_aliases {
+            self.aliases.insert(alias.name.clone(), self.resolve(&alias.type_info));
         }
 
         // Collect events
         for event in &contract.events {
-            self.events.insert(event.name.clone(), event.parameters.clone());
+            self.events.insert(event.name.clone(), (event.parameters.clone(), event.span));
         }
 
         // Collect function signatures
@@ -77,7 +290,8 @@ impl TypeChecker {
                 FunctionSignature {
                     parameters: function.parameters.clone(),
                     return_type: function.return_type.clone(),
-                    is_pure: function.is_pure,
+                    is_mutable: function.is_mutable,
+                    span: function.span,
                 },
             );
         }
@@ -85,19 +299,29 @@ impl TypeChecker {
         Ok(())
     }
 
-    fn check_contract(&mut self, contract: &Contract) -> Result<(), TypeError> {
+    fn check_contract(&mut self, contract: &Contract) -> Result<(), Box<TypeError>> {
         // Check state variables
         for var in &contract.state_vars {
-            self.check_type(&var.type_info)?;
+            self.check_type(&var.type_info, var.span)?;
         }
 
         // Check events
         for event in &contract.events {
             for param in &event.parameters {
-                self.check_type(&param.type_info)?;
+                self.check_type(&param.type_info, param.span)?;
             }
         }
 
+        // Check struct fields and aliases
+        for decl in &contract.structs {
+            for field in &decl.fields {
+                self.check_type(&field.type_info, field.span)?;
+            }
+        }
+        for alias in &contract.type_aliases {
+            self.check_type(&alias.type_info, alias.span)?;
+        }
+
         // Check functions
         for function in &contract.functions {
             self.check_function(function)?;
@@ -106,9 +330,66 @@ impl TypeChecker {
         Ok(())
     }
 
-    fn check_function(&mut self, function: &Function) -> Result<(), TypeError> {
+    fn check_interface(&self, interface: &Interface) -> Result<(), Box<TypeError>> {
+        for function in &interface.functions {
+            for param in &function.parameters {
+                self.check_type(&param.type_info, param.span)?;
+            }
+            if let Some(ref return_type) = function.return_type {
+                self.check_type(return_type, function.span)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Modules hold tests, and the only attributes are `#[cfg(test)]` on
+    /// the module and `#[test]` on its functions
+    fn check_module(&self, module: &Module) -> Result<(), Box<TypeError>> {
+        let unknown = |attribute: &Attribute| {
+            let name = match attribute.arguments.as_slice() {
+                [] => attribute.name.clone(),
+                arguments => format!("{}({})", attribute.name, arguments.join(", ")),
+            };
+            TypeError::new(TypeErrorKind::UnknownAttribute(name), attribute.span)
+        };
+        for attribute in &module.attributes {
+            if attribute.name != "cfg" || attribute.arguments != ["test"] {
+                return Err(unknown(attribute));
+            }
+        }
+        for function in &module.functions {
+            for attribute in &function.attributes {
+                if attribute.name != "test" || !attribute.arguments.is_empty() {
+                    return Err(unknown(attribute));
+                }
+                if module.attributes.is_empty() {
+                    return Err(TypeError::new(TypeErrorKind::TestOutsideTestModule(module.name.clone()), attribute.span)
+                        .with_secondary(module.span, "module declared here"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_function(&mut self, function: &Function) -> Result<(), Box<TypeError>> {
         self.current_function = Some(function.name.clone());
         self.is_pure_context = function.is_pure;
+        self.immutable.clear();
+
+        // `@name` runs contract function `name` first, which takes no
+        // arguments and returns nothing
+        for modifier in &function.modifiers {
+            let Modifier::Custom(name) = modifier else { continue };
+            let Some(signature) = self.functions.get(name) else {
+                return Err(TypeError::new(TypeErrorKind::UndefinedFunction(name.clone()), function.span));
+            };
+            if !signature.parameters.is_empty() || signature.return_type.is_some() {
+                return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                    op: format!("@{}", name),
+                    type_name: "functions that take arguments or return a value".to_string(),
+                }, function.span).with_secondary(signature.span, "function defined here"));
+            }
+        }
 
         // Create new scope for function parameters
         let outer_vars = self.variables.clone();
@@ -120,9 +401,12 @@ impl TypeChecker {
 
         // Check return type if present
         if let Some(ref return_type) = function.return_type {
-            self.check_type(return_type)?;
+            self.check_type(return_type, function.span)?;
         }
 
+        // Resources must be moved exactly once on every path
+        Linearity::new(self).check_function(function)?;
+
         // Check function body
         self.check_block(&function.body)?;
 
@@ -134,109 +418,125 @@ impl TypeChecker {
         Ok(())
     }
 
-    fn check_block(&mut self, block: &Block) -> Result<(), TypeError> {
+    fn check_block(&mut self, block: &Block) -> Result<(), Box<TypeError>> {
         for statement in &block.statements {
             self.check_statement(statement)?;
         }
         Ok(())
     }
 
-    fn check_statement(&mut self, statement: &Statement) -> Result<(), TypeError> {
-        match statement {
-            Statement::Let { name, type_info, value } => {
+    fn check_statement(&mut self, statement: &Statement) -> Result<(), Box<TypeError>> {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::Let { pattern, type_info, value } => {
                 let value_type = self.check_expression(value)?;
                 if let Some(ref declared_type) = type_info {
                     if !self.types_match(declared_type, &value_type) {
-                        return Err(TypeError::TypeMismatch {
+                        return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                             expected: declared_type.clone(),
                             found: value_type,
-                        });
+                        }, value.span).with_secondary(span, "expected due to this declaration"));
                     }
                 }
-                self.variables.insert(name.clone(), value_type);
+                self.bind_pattern(pattern, value_type);
             }
-            Statement::Assignment { target, value } => {
+            StatementKind::Assignment { target, value } => {
+                self.check_assignable(target)?;
                 let target_type = self.check_expression(target)?;
                 let value_type = self.check_expression(value)?;
                 if !self.types_match(&target_type, &value_type) {
-                    return Err(TypeError::TypeMismatch {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: target_type,
                         found: value_type,
-                    });
+                    }, value.span).with_secondary(target.span, "expected due to the type of this place"));
                 }
             }
-            Statement::If { condition, then_block, else_block } => {
-                let condition_type = self.check_expression(condition)?;
-                if !matches!(condition_type, Type::Bool) {
-                    return Err(TypeError::TypeMismatch {
+            StatementKind::If { condition, then_block, else_block } => {
+                let condition_type = self.check_condition(condition)?;
+                if !self.types_match(&Type::Bool, &condition_type) {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: Type::Bool,
                         found: condition_type,
-                    });
+                    }, condition.span));
                 }
                 self.check_block(then_block)?;
                 if let Some(else_block) = else_block {
                     self.check_block(else_block)?;
                 }
             }
-            Statement::While { condition, block } => {
-                let condition_type = self.check_expression(condition)?;
-                if !matches!(condition_type, Type::Bool) {
-                    return Err(TypeError::TypeMismatch {
+            StatementKind::While { condition, block } => {
+                let condition_type = self.check_condition(condition)?;
+                if !self.types_match(&Type::Bool, &condition_type) {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: Type::Bool,
                         found: condition_type,
-                    });
+                    }, condition.span));
                 }
                 self.check_block(block)?;
             }
-            Statement::Return(expr) => {
-                if let Some(expr) = expr {
-                    let expr_type = self.check_expression(expr)?;
-                    if let Some(current_fn) = &self.current_function {
-                        if let Some(fn_sig) = self.functions.get(current_fn) {
-                            if let Some(ref return_type) = fn_sig.return_type {
-                                if !self.types_match(return_type, &expr_type) {
-                                    return Err(TypeError::TypeMismatch {
-                                        expected: return_type.clone(),
-                                        found: expr_type,
-                                    });
-                                }
+            StatementKind::Return(Some(expr)) => {
+                let expr_type = self.check_expression(expr)?;
+                if let Some(current_fn) = &self.current_function {
+                    if let Some(fn_sig) = self.functions.get(current_fn) {
+                        if let Some(ref return_type) = fn_sig.return_type {
+                            if !self.types_match(return_type, &expr_type) {
+                                return Err(TypeError::new(TypeErrorKind::TypeMismatch {
+                                    expected: return_type.clone(),
+                                    found: expr_type,
+                                }, expr.span).with_secondary(fn_sig.span, "expected due to this function's return type"));
                             }
                         }
                     }
                 }
             }
-            Statement::Emit { event, arguments } => {
+            StatementKind::Return(None) => {}
+            StatementKind::Emit { event, arguments } => {
                 if self.is_pure_context {
-                    return Err(TypeError::StateModificationInPureFunction);
+                    return Err(TypeError::new(TypeErrorKind::StateModificationInPureFunction, span));
                 }
-                if let Some(event_params) = self.events.get(event) {
+                if let Some((event_params, event_span)) = self.events.get(event) {
                     if arguments.len() != event_params.len() {
-                        return Err(TypeError::InvalidEventEmission(
+                        return Err(TypeError::new(TypeErrorKind::InvalidEventEmission(
                             format!("Wrong number of arguments for event {}", event)
-                        ));
+                        ), span).with_secondary(*event_span, "event declared here"));
                     }
                     for (arg, param) in arguments.iter().zip(event_params) {
                         let arg_type = self.check_expression(arg)?;
                         if !self.types_match(&param.type_info, &arg_type) {
-                            return Err(TypeError::TypeMismatch {
+                            return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                                 expected: param.type_info.clone(),
                                 found: arg_type,
-                            });
+                            }, arg.span).with_secondary(param.span, "parameter declared here"));
                         }
                     }
                 } else {
-                    return Err(TypeError::InvalidEventEmission(
+                    return Err(TypeError::new(TypeErrorKind::InvalidEventEmission(
                         format!("Undefined event {}", event)
-                    ));
+                    ), span));
                 }
             }
-            Statement::Ensure { condition, message: _ } => {
+            StatementKind::For { pattern, iterable, block } => {
+                self.check_expression(iterable)?;
+                // Element types of iterables are not tracked yet
+                self.bind_pattern(pattern, Type::Error);
+                self.check_block(block)?;
+            }
+            StatementKind::Expression { expression, .. } => {
+                self.check_expression(expression)?;
+            }
+            StatementKind::FunctionCall { function, arguments }
+                if matches!(&function.kind, ExpressionKind::Identifier(name)
+                    if name == "destroy" && !self.functions.contains_key(name)) =>
+            {
+                self.check_destroy(arguments, span)?;
+            }
+            StatementKind::Ensure { condition, message: _ } => {
                 let condition_type = self.check_expression(condition)?;
-                if !matches!(condition_type, Type::Bool) {
-                    return Err(TypeError::TypeMismatch {
+                if !self.types_match(&Type::Bool, &condition_type) {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: Type::Bool,
                         found: condition_type,
-                    });
+                    }, condition.span));
                 }
             }
             _ => {}
@@ -244,54 +544,169 @@ impl TypeChecker {
         Ok(())
     }
 
-    fn check_expression(&self, expr: &Expression) -> Result<Type, TypeError> {
-        match expr {
-            Expression::Identifier(name) => {
+    /// The type of `expr`, with aliases resolved
+    fn check_expression(&self, expr: &Expression) -> Result<Type, Box<TypeError>> {
+        self.expression_type(expr).map(|type_info| self.resolve(&type_info))
+    }
+
+    fn expression_type(&self, expr: &Expression) -> Result<Type, Box<TypeError>> {
+        let span = expr.span;
+        match &expr.kind {
+            ExpressionKind::Identifier(name) => {
                 self.variables.get(name)
                     .cloned()
-                    .ok_or_else(|| TypeError::UndefinedVariable(name.clone()))
+                    .or_else(|| host_value(name))
+                    .ok_or_else(|| TypeError::new(TypeErrorKind::UndefinedVariable(name.clone()), span))
             }
-            Expression::NumberLiteral(_) => Ok(Type::U256),
-            Expression::StringLiteral(_) => Ok(Type::String),
-            Expression::BoolLiteral(_) => Ok(Type::Bool),
-            Expression::AddressLiteral(_) => Ok(Type::Address),
-            Expression::Binary { left, operator, right } => {
+            ExpressionKind::NumberLiteral(_) => Ok(Type::U256),
+            ExpressionKind::StringLiteral(_) => Ok(Type::String),
+            ExpressionKind::BoolLiteral(_) => Ok(Type::Bool),
+            ExpressionKind::AddressLiteral(_) => Ok(Type::Address),
+            ExpressionKind::Binary { left, operator, right } => {
                 let left_type = self.check_expression(left)?;
                 let right_type = self.check_expression(right)?;
-                self.check_binary_operation(operator, &left_type, &right_type)
+                self.check_binary_operation(operator, &left_type, &right_type, span)
             }
-            Expression::Unary { operator, operand } => {
+            ExpressionKind::Unary { operator, operand } => {
                 let operand_type = self.check_expression(operand)?;
-                self.check_unary_operation(operator, &operand_type)
+                self.check_unary_operation(operator, &operand_type, span)
+            }
+            ExpressionKind::FunctionCall { function, arguments } => {
+                self.check_function_call(function, arguments, span)
             }
-            Expression::FunctionCall { function, arguments } => {
-                self.check_function_call(function, arguments)
+            ExpressionKind::MemberAccess { object, member } => {
+                self.check_member_access(object, member, span)
             }
-            Expression::MemberAccess { object, member } => {
-                self.check_member_access(object, member)
+            ExpressionKind::IndexAccess { array, index } => {
+                self.check_index_access(array, index, span)
             }
-            Expression::IndexAccess { array, index } => {
-                self.check_index_access(array, index)
+            ExpressionKind::Tuple(elements) => {
+                let types = elements.iter()
+                    .map(|element| self.check_expression(element))
+                    .collect::<Result<_, _>>()?;
+                Ok(Type::Tuple(types))
+            }
+            ExpressionKind::Cast { expression, type_info } => {
+                self.check_expression(expression)?;
+                self.check_type(type_info, span)?;
+                Ok(type_info.clone())
+            }
+            // Fields of imported types are not known
+            ExpressionKind::StructLiteral { name, fields } if self.type_names.contains(name) => {
+                for (_, value) in fields {
+                    self.check_expression(value)?;
+                }
+                Ok(Type::Custom(name.clone()))
+            }
+            ExpressionKind::StructLiteral { name, fields } => {
+                let Some(decl) = self.structs.get(name) else {
+                    return Err(TypeError::new(TypeErrorKind::UndefinedType(name.clone()), span));
+                };
+                for (field, value) in fields {
+                    let value_type = self.check_expression(value)?;
+                    let Some(param) = decl.fields.iter().find(|param| param.name == *field) else {
+                        return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                            op: format!("field {}", field),
+                            type_name: name.clone(),
+                        }, value.span).with_secondary(decl.span, "struct declared here"));
+                    };
+                    if !self.types_match(&param.type_info, &value_type) {
+                        return Err(TypeError::new(TypeErrorKind::TypeMismatch {
+                            expected: param.type_info.clone(),
+                            found: value_type,
+                        }, value.span).with_secondary(param.span, "field declared here"));
+                    }
+                }
+                Ok(Type::Custom(name.clone()))
+            }
+            // Not checked yet; `Type::Error` keeps them from causing
+            // follow-up mismatches
+            ExpressionKind::Path(_)
+            | ExpressionKind::MacroCall { .. }
+            | ExpressionKind::Closure { .. }
+            | ExpressionKind::Try(_)
+            | ExpressionKind::Range { .. }
+            | ExpressionKind::If { .. }
+            | ExpressionKind::Let { .. }
+            | ExpressionKind::Match { .. }
+            | ExpressionKind::Block(_) => Ok(Type::Error),
+            ExpressionKind::Assign { target, value } => {
+                self.check_assignable(target)?;
+                self.check_expression(value)?;
+                Ok(Type::Error)
+            }
+            // Already reported by the parser
+            ExpressionKind::Error => Ok(Type::Error),
+        }
+    }
+
+    /// Checks the condition of `if` or `while`. An `if let` condition binds
+    /// its pattern and is always `Bool`.
+    fn check_condition(&mut self, condition: &Expression) -> Result<Type, Box<TypeError>> {
+        match &condition.kind {
+            ExpressionKind::Let { pattern, value } => {
+                self.check_expression(value)?;
+                self.bind_pattern(pattern, Type::Error);
+                Ok(Type::Bool)
+            }
+            _ => self.check_expression(condition),
+        }
+    }
+
+    /// Rejects assignment to a binding declared without `mut`
+    fn check_assignable(&self, target: &Expression) -> Result<(), Box<TypeError>> {
+        if let ExpressionKind::Identifier(name) = &target.kind {
+            if let Some(declared) = self.immutable.get(name) {
+                return Err(TypeError::new(TypeErrorKind::AssignToImmutable(name.clone()), target.span)
+                    .with_secondary(*declared, "declared here without `mut`"));
             }
         }
+        Ok(())
     }
 
-    fn check_type(&self, type_info: &Type) -> Result<(), TypeError> {
+    /// Brings the names bound by `pattern` into scope
+    fn bind_pattern(&mut self, pattern: &Pattern, value_type: Type) {
+        match (&pattern.kind, value_type) {
+            (PatternKind::Identifier { name, mutable }, value_type) => {
+                self.variables.insert(name.clone(), value_type);
+                if *mutable {
+                    self.immutable.remove(name);
+                } else {
+                    self.immutable.insert(name.clone(), pattern.span);
+                }
+            }
+            (PatternKind::Tuple(patterns), Type::Tuple(types)) if patterns.len() == types.len() => {
+                for (pattern, value_type) in patterns.iter().zip(types) {
+                    self.bind_pattern(pattern, value_type);
+                }
+            }
+            (PatternKind::Tuple(patterns), _)
+            | (PatternKind::TupleStruct { fields: patterns, .. }, _) => {
+                for pattern in patterns {
+                    self.bind_pattern(pattern, Type::Error);
+                }
+            }
+            (PatternKind::Reference(inner), value_type) => self.bind_pattern(inner, value_type),
+            (PatternKind::Wildcard, _) | (PatternKind::Path(_), _) | (PatternKind::Literal(_), _) => {}
+        }
+    }
+
+    fn check_type(&self, type_info: &Type, span: Span) -> Result<(), Box<TypeError>> {
         match type_info {
             Type::Map { key_type, value_type } => {
-                self.check_type(key_type)?;
-                self.check_type(value_type)
+                self.check_type(key_type, span)?;
+                self.check_type(value_type, span)
             }
             Type::Array(element_type) => {
-                self.check_type(element_type)
+                self.check_type(element_type, span)
             }
             Type::Result { ok_type, err_type } => {
-                self.check_type(ok_type)?;
-                self.check_type(err_type)
+                self.check_type(ok_type, span)?;
+                self.check_type(err_type, span)
             }
             Type::Custom(name) => {
                 if !self.is_known_type(name) {
-                    return Err(TypeError::UndefinedType(name.clone()));
+                    return Err(TypeError::new(TypeErrorKind::UndefinedType(name.clone()), span));
                 }
                 Ok(())
             }
@@ -300,7 +715,9 @@ impl TypeChecker {
     }
 
     fn types_match(&self, expected: &Type, found: &Type) -> bool {
-        match (expected, found) {
+        let (expected, found) = (self.resolve(expected), self.resolve(found));
+        match (&expected, &found) {
+            (Type::Error, _) | (_, Type::Error) => true,
             (Type::Map { key_type: k1, value_type: v1 },
              Type::Map { key_type: k2, value_type: v2 }) => {
                 self.types_match(k1, k2) && self.types_match(v1, v2)
@@ -312,13 +729,80 @@ impl TypeChecker {
              Type::Result { ok_type: ok2, err_type: err2 }) => {
                 self.types_match(ok1, ok2) && self.types_match(err1, err2)
             }
+            (Type::Tuple(t1), Type::Tuple(t2)) => self.all_match(t1, t2),
+            (Type::Generic { name: n1, arguments: a1 }, Type::Generic { name: n2, arguments: a2 }) => {
+                n1 == n2 && self.all_match(a1, a2)
+            }
             _ => expected == found,
         }
     }
 
+    fn all_match(&self, expected: &[Type], found: &[Type]) -> bool {
+        expected.len() == found.len() && expected.iter().zip(found).all(|(e, f)| self.types_match(e, f))
+    }
+
+    /// Built in are the primitive types and `Error`, what a failed
+    /// `ensure!` returns, unless a contract declares its own
     fn is_known_type(&self, name: &str) -> bool {
-        // Add custom type checking logic here
-        matches!(name, "Address" | "u256" | "bool" | "string")
+        matches!(name, "Address" | "u256" | "bool" | "string" | "Error")
+            || self.structs.contains_key(name)
+            || self.interfaces.contains_key(name)
+            || self.type_names.contains(name)
+            || self.aliases.contains_key(name)
+    }
+
+    /// `type_info` with the aliases in it replaced by what they name
+    fn resolve(&self, type_info: &Type) -> Type {
+        let resolve = |type_info: &Type| Box::new(self.resolve(type_info));
+        match type_info {
+            Type::Custom(name) => self.aliases.get(name).cloned().unwrap_or_else(|| type_info.clone()),
+            Type::Map { key_type, value_type } => Type::Map {
+                key_type: resolve(key_type),
+                value_type: resolve(value_type),
+            },
+            Type::Array(element_type) => Type::Array(resolve(element_type)),
+            Type::Result { ok_type, err_type } => Type::Result {
+                ok_type: resolve(ok_type),
+                err_type: resolve(err_type),
+            },
+            Type::Tuple(types) => Type::Tuple(types.iter().map(|ty| self.resolve(ty)).collect()),
+            Type::Generic { name, arguments } => Type::Generic {
+                name: name.clone(),
+                arguments: arguments.iter().map(|ty| self.resolve(ty)).collect(),
+            },
+            _ => type_info.clone(),
+        }
+    }
+
+    /// Whether values of `type_info` are linear: a `resource` struct, or
+    /// anything holding one
+    fn is_resource(&self, type_info: &Type) -> bool {
+        self.holds_resource(type_info, &mut Vec::new())
+    }
+
+    fn holds_resource<'t>(&'t self, type_info: &'t Type, visiting: &mut Vec<&'t str>) -> bool {
+        match type_info {
+            Type::Custom(name) => match self.structs.get(name) {
+                Some(_) if visiting.contains(&name.as_str()) => false,
+                Some(decl) => {
+                    visiting.push(name);
+                    let holds = decl.is_resource
+                        || decl.fields.iter().any(|field| self.holds_resource(&field.type_info, visiting));
+                    visiting.pop();
+                    holds
+                }
+                None => false,
+            },
+            Type::Map { value_type, .. } => self.holds_resource(value_type, visiting),
+            Type::Array(element_type) => self.holds_resource(element_type, visiting),
+            Type::Result { ok_type, err_type } => {
+                self.holds_resource(ok_type, visiting) || self.holds_resource(err_type, visiting)
+            }
+            Type::Tuple(types) | Type::Generic { arguments: types, .. } => {
+                types.iter().any(|ty| self.holds_resource(ty, visiting))
+            }
+            _ => false,
+        }
     }
 
     fn check_binary_operation(
@@ -326,41 +810,42 @@ impl TypeChecker {
         op: &BinaryOp,
         left_type: &Type,
         right_type: &Type,
-    ) -> Result<Type, TypeError> {
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
         match op {
             BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
-                if !matches!(left_type, Type::U256) || !matches!(right_type, Type::U256) {
-                    return Err(TypeError::InvalidOperation {
+                if !self.types_match(&Type::U256, left_type) || !self.types_match(&Type::U256, right_type) {
+                    return Err(TypeError::new(TypeErrorKind::InvalidOperation {
                         op: format!("{:?}", op),
                         type_name: format!("{:?}", left_type),
-                    });
+                    }, span));
                 }
                 Ok(Type::U256)
             }
             BinaryOp::Eq | BinaryOp::NotEq => {
                 if !self.types_match(left_type, right_type) {
-                    return Err(TypeError::TypeMismatch {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: left_type.clone(),
                         found: right_type.clone(),
-                    });
+                    }, span));
                 }
                 Ok(Type::Bool)
             }
             BinaryOp::Lt | BinaryOp::Gt | BinaryOp::LtEq | BinaryOp::GtEq => {
-                if !matches!(left_type, Type::U256) || !matches!(right_type, Type::U256) {
-                    return Err(TypeError::InvalidOperation {
+                if !self.types_match(&Type::U256, left_type) || !self.types_match(&Type::U256, right_type) {
+                    return Err(TypeError::new(TypeErrorKind::InvalidOperation {
                         op: format!("{:?}", op),
                         type_name: format!("{:?}", left_type),
-                    });
+                    }, span));
                 }
                 Ok(Type::Bool)
             }
             BinaryOp::And | BinaryOp::Or => {
-                if !matches!(left_type, Type::Bool) || !matches!(right_type, Type::Bool) {
-                    return Err(TypeError::InvalidOperation {
+                if !self.types_match(&Type::Bool, left_type) || !self.types_match(&Type::Bool, right_type) {
+                    return Err(TypeError::new(TypeErrorKind::InvalidOperation {
                         op: format!("{:?}", op),
                         type_name: format!("{:?}", left_type),
-                    });
+                    }, span));
                 }
                 Ok(Type::Bool)
             }
@@ -371,26 +856,29 @@ impl TypeChecker {
         &self,
         op: &UnaryOp,
         operand_type: &Type,
-    ) -> Result<Type, TypeError> {
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
         match op {
             UnaryOp::Not => {
-                if !matches!(operand_type, Type::Bool) {
-                    return Err(TypeError::InvalidOperation {
+                if !self.types_match(&Type::Bool, operand_type) {
+                    return Err(TypeError::new(TypeErrorKind::InvalidOperation {
                         op: "!".to_string(),
                         type_name: format!("{:?}", operand_type),
-                    });
+                    }, span));
                 }
                 Ok(Type::Bool)
             }
             UnaryOp::Neg => {
-                if !matches!(operand_type, Type::U256) {
-                    return Err(TypeError::InvalidOperation {
+                if !self.types_match(&Type::U256, operand_type) {
+                    return Err(TypeError::new(TypeErrorKind::InvalidOperation {
                         op: "-".to_string(),
                         type_name: format!("{:?}", operand_type),
-                    });
+                    }, span));
                 }
                 Ok(Type::U256)
             }
+            // References are not distinguished from their referents yet
+            UnaryOp::Deref | UnaryOp::Ref | UnaryOp::RefMut => Ok(operand_type.clone()),
         }
     }
 
@@ -398,50 +886,196 @@ impl TypeChecker {
         &self,
         function: &Expression,
         arguments: &[Expression],
-    ) -> Result<Type, TypeError> {
-        if let Expression::Identifier(name) = function {
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
+        if let ExpressionKind::Identifier(name) = &function.kind {
+            if name == "destroy" && !self.functions.contains_key(name) {
+                return self.check_destroy(arguments, span);
+            }
             if let Some(signature) = self.functions.get(name) {
-                if arguments.len() != signature.parameters.len() {
-                    return Err(TypeError::InvalidOperation {
-                        op: format!("call to {}", name),
-                        type_name: "wrong number of arguments".to_string(),
-                    });
-                }
-
-                for (arg, param) in arguments.iter().zip(&signature.parameters) {
-                    let arg_type = self.check_expression(arg)?;
-                    if !self.types_match(&param.type_info, &arg_type) {
-                        return Err(TypeError::TypeMismatch {
-                            expected: param.type_info.clone(),
-                            found: arg_type,
-                        });
-                    }
+                if signature.is_mutable && self.is_pure_context {
+                    return Err(TypeError::new(TypeErrorKind::StateModificationInPureFunction, span)
+                        .with_secondary(signature.span, "`mut` function defined here"));
                 }
-
+                self.check_arguments(name, &signature.parameters, arguments, span, signature.span)?;
                 Ok(signature.return_type.clone().unwrap_or(Type::U256))
+            } else if let Some((_, entry)) = host::lookup(name).filter(|(_, entry)| !entry.is_value()) {
+                self.check_host_call(entry, arguments, span)
+            } else if let ("Ok" | "Err" | "Some", [argument]) = (name.as_str(), arguments) {
+                let argument_type = Box::new(self.check_expression(argument)?);
+                Ok(match name.as_str() {
+                    "Ok" => Type::Result { ok_type: argument_type, err_type: Box::new(Type::Error) },
+                    "Err" => Type::Result { ok_type: Box::new(Type::Error), err_type: argument_type },
+                    _ => Type::Generic { name: "Option".to_string(), arguments: vec![*argument_type] },
+                })
             } else {
-                Err(TypeError::UndefinedFunction(name.clone()))
+                Err(TypeError::new(TypeErrorKind::UndefinedFunction(name.clone()), function.span))
             }
         } else {
-            Err(TypeError::InvalidOperation {
-                op: "call".to_string(),
-                type_name: "not a function".to_string(),
+            // Methods of interfaces are checked like contract functions
+            if let ExpressionKind::MemberAccess { object, member } = &function.kind {
+                if let Type::Custom(name) = self.check_expression(object)? {
+                    if let Some(interface) = self.interfaces.get(&name) {
+                        return self.check_interface_call(interface, member, arguments, span);
+                    }
+                }
+            }
+            // Variants, associated functions and other methods are not
+            // checked beyond their arguments, save those compiled in place
+            for argument in arguments {
+                self.check_expression(argument)?;
+            }
+            Ok(match &function.kind {
+                ExpressionKind::Path(path) if path.join("::") == "Duration::from_days" => Type::U256,
+                ExpressionKind::Path(path) if path.join("::") == "Address::zero" => Type::Address,
+                ExpressionKind::Path(_) => Type::Error,
+                ExpressionKind::MemberAccess { object, member } => {
+                    self.check_expression(object)?;
+                    match member.as_str() {
+                        "as_days" => Type::U256,
+                        _ => Type::Error,
+                    }
+                }
+                _ => {
+                    self.check_expression(function)?;
+                    Type::Error
+                }
             })
         }
     }
 
+    fn check_interface_call(
+        &self,
+        interface: &Interface,
+        method: &str,
+        arguments: &[Expression],
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
+        let Some(function) = interface.functions.iter().find(|function| function.name == method) else {
+            return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                op: format!("method {}", method),
+                type_name: interface.name.clone(),
+            }, span).with_secondary(interface.span, "interface declared here"));
+        };
+        self.check_arguments(method, &function.parameters, arguments, span, function.span)?;
+        Ok(function.return_type.clone().unwrap_or(Type::Tuple(Vec::new())))
+    }
+
+    /// Checks the count and types of the arguments of a call to `name`,
+    /// declared at `declared`
+    fn check_arguments(
+        &self,
+        name: &str,
+        parameters: &[Parameter],
+        arguments: &[Expression],
+        span: Span,
+        declared: Span,
+    ) -> Result<(), Box<TypeError>> {
+        if arguments.len() != parameters.len() {
+            return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                op: format!("call to {}", name),
+                type_name: "wrong number of arguments".to_string(),
+            }, span).with_secondary(declared, "function defined here"));
+        }
+        for (arg, param) in arguments.iter().zip(parameters) {
+            let arg_type = self.check_expression(arg)?;
+            if !self.types_match(&param.type_info, &arg_type) {
+                return Err(TypeError::new(TypeErrorKind::TypeMismatch {
+                    expected: param.type_info.clone(),
+                    found: arg_type,
+                }, arg.span).with_secondary(param.span, "parameter declared here"));
+            }
+        }
+        Ok(())
+    }
+
+    /// A call of a function from the host table. `Bytes` parameters take
+    /// the raw bytes of strings, numbers and addresses.
+    fn check_host_call(
+        &self,
+        entry: &host::HostFunction,
+        arguments: &[Expression],
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
+        if arguments.len() != entry.params.len() {
+            return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                op: format!("call to {}", entry.name),
+                type_name: "wrong number of arguments".to_string(),
+            }, span));
+        }
+        for (arg, param) in arguments.iter().zip(entry.params) {
+            let arg_type = self.check_expression(arg)?;
+            let accepted = match param {
+                bytecode::Type::Bytes => matches!(arg_type, Type::String | Type::U256 | Type::Address | Type::Error),
+                _ => self.types_match(&host_type(param), &arg_type),
+            };
+            if !accepted {
+                return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                    op: format!("argument of {}", entry.name),
+                    type_name: format!("{:?}", arg_type),
+                }, arg.span));
+            }
+        }
+        Ok(host_type(&entry.returns))
+    }
+
+    /// `destroy(r)` ends resource `r`, the one way to get rid of it short
+    /// of moving it somewhere
+    fn check_destroy(&self, arguments: &[Expression], span: Span) -> Result<Type, Box<TypeError>> {
+        let [argument] = arguments else {
+            return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                op: "call to destroy".to_string(),
+                type_name: "wrong number of arguments".to_string(),
+            }, span));
+        };
+        let argument_type = self.check_expression(argument)?;
+        let is_declared_resource = matches!(&argument_type,
+            Type::Custom(name) if self.structs.get(name).is_some_and(|decl| decl.is_resource));
+        if !is_declared_resource && argument_type != Type::Error {
+            return Err(TypeError::new(TypeErrorKind::InvalidOperation {
+                op: "destroy".to_string(),
+                type_name: format!("{:?}, which is not a resource", argument_type),
+            }, argument.span));
+        }
+        Ok(Type::Tuple(Vec::new()))
+    }
+
     fn check_member_access(
         &self,
         object: &Expression,
         member: &str,
-    ) -> Result<Type, TypeError> {
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
+        // `self.x` for state variable `x`, `msg.sender` and the like, and
+        // `self.balance` unless a state variable took the name
+        if let ExpressionKind::Identifier(name) = &object.kind {
+            if let Some(ty) = self.state.get(member).filter(|_| name == "self") {
+                return Ok(ty.clone());
+            }
+            if !self.variables.contains_key(name) {
+                if let Some(ty) = host_value(&format!("{}.{}", name, member)) {
+                    return Ok(ty);
+                }
+            }
+        }
         let object_type = self.check_expression(object)?;
+        let field = match &object_type {
+            Type::Custom(name) => self.structs.get(name)
+                .and_then(|decl| decl.fields.iter().find(|field| field.name == member)),
+            _ => None,
+        };
+        if let Some(field) = field {
+            return Ok(field.type_info.clone());
+        }
         match object_type {
             Type::Map { value_type, .. } => Ok(*value_type),
-            _ => Err(TypeError::InvalidOperation {
+            // Fields of imported types are not known
+            Type::Custom(name) if self.type_names.contains(&name) => Ok(Type::Error),
+            Type::Error => Ok(Type::Error),
+            _ => Err(TypeError::new(TypeErrorKind::InvalidOperation {
                 op: format!("member access {}", member),
                 type_name: format!("{:?}", object_type),
-            }),
+            }, span)),
         }
     }
 
@@ -449,39 +1083,749 @@ impl TypeChecker {
         &self,
         array: &Expression,
         index: &Expression,
-    ) -> Result<Type, TypeError> {
+        span: Span,
+    ) -> Result<Type, Box<TypeError>> {
         let array_type = self.check_expression(array)?;
         let index_type = self.check_expression(index)?;
 
         match array_type {
             Type::Map { key_type, value_type } => {
                 if !self.types_match(&key_type, &index_type) {
-                    return Err(TypeError::TypeMismatch {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: *key_type,
                         found: index_type,
-                    });
+                    }, span));
                 }
                 Ok(*value_type)
             }
             Type::Array(element_type) => {
-                if !matches!(index_type, Type::U256) {
-                    return Err(TypeError::TypeMismatch {
+                if !self.types_match(&Type::U256, &index_type) {
+                    return Err(TypeError::new(TypeErrorKind::TypeMismatch {
                         expected: Type::U256,
                         found: index_type,
-                    });
+                    }, span));
                 }
                 Ok(*element_type)
             }
-            _ => Err(TypeError::InvalidOperation {
+            _ => Err(TypeError::new(TypeErrorKind::InvalidOperation {
                 op: "index access".to_string(),
                 type_name: format!("{:?}", array_type),
-            }),
+            }, span)),
+        }
+    }
+}
+
+/// How an expression's value is used: moved somewhere, or only looked at
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Use {
+    Move,
+    Read,
+}
+
+/// A resource variable in scope and whether it still holds its value
+#[derive(Debug, Clone)]
+struct Owned {
+    name: String,
+    type_info: Type,
+    declared: Span,
+    /// Where its value was moved out, if it has been
+    moved: Option<Span>,
+}
+
+/// Checks that every resource in a function is moved exactly once on every
+/// path through it: not copied, not used after a move, and not left to go
+/// out of scope. Branches must agree on what they consume, unless they
+/// return, and a loop body must leave resources from outside the loop as
+/// it found them.
+struct Linearity<'a> {
+    checker: &'a TypeChecker,
+    /// Resource variables in scope, innermost block last
+    scopes: Vec<Vec<Owned>>,
+    /// Whether the code being walked is unreachable, after a `return`
+    diverged: bool,
+}
+
+impl<'a> Linearity<'a> {
+    fn new(checker: &'a TypeChecker) -> Self {
+        Linearity { checker, scopes: Vec::new(), diverged: false }
+    }
+
+    fn check_function(&mut self, function: &Function) -> Result<(), Box<TypeError>> {
+        let parameters = function.parameters.iter()
+            .filter(|param| self.checker.is_resource(&param.type_info))
+            .map(|param| Owned {
+                name: param.name.clone(),
+                type_info: param.type_info.clone(),
+                declared: param.span,
+                moved: None,
+            })
+            .collect();
+        self.scopes.push(parameters);
+        // The body's value is the function's return value
+        self.block(&function.body, Some(Use::Move))?;
+        self.pop_scope(function.body.span.shrink_to_end())
+    }
+
+    /// Walks `block`, using its trailing expression, if any, as `tail` says
+    fn block(&mut self, block: &Block, tail: Option<Use>) -> Result<(), Box<TypeError>> {
+        self.scopes.push(Vec::new());
+        for (index, statement) in block.statements.iter().enumerate() {
+            match (&statement.kind, tail) {
+                (StatementKind::Expression { expression, has_semicolon: false }, Some(usage))
+                    if index + 1 == block.statements.len() =>
+                {
+                    self.expression(expression, usage)?;
+                }
+                _ => self.statement(statement)?,
+            }
+        }
+        self.pop_scope(block.span.shrink_to_end())
+    }
+
+    /// Leaves the innermost scope; whatever it still holds leaks at `end`
+    fn pop_scope(&mut self, end: Span) -> Result<(), Box<TypeError>> {
+        let scope = self.scopes.pop().unwrap_or_default();
+        if self.diverged {
+            return Ok(());
+        }
+        match scope.into_iter().find(|owned| owned.moved.is_none()) {
+            Some(owned) => Err(TypeError::new(TypeErrorKind::ResourceLeak(owned.name), owned.declared)
+                .with_secondary(end, "dropped here while still held")),
+            None => Ok(()),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Box<TypeError>> {
+        match &statement.kind {
+            StatementKind::Let { pattern, type_info, value } => {
+                let value_type = type_info.clone().unwrap_or_else(|| self.type_of(value));
+                self.expression(value, Use::Move)?;
+                self.bind(pattern, value_type, value.span)?;
+            }
+            StatementKind::Assignment { target, value } => self.assignment(target, value)?,
+            StatementKind::FunctionCall { function, arguments } => {
+                self.call(function, arguments)?;
+                let returned = match &function.kind {
+                    ExpressionKind::Identifier(name) => self.return_type(name),
+                    _ => Type::Error,
+                };
+                self.discard(returned, statement.span)?;
+            }
+            StatementKind::If { condition, then_block, else_block } => {
+                self.expression(condition, Use::Read)?;
+                self.branches(statement.span, then_block, else_block.as_ref(), None)?;
+            }
+            StatementKind::While { condition, block } => {
+                self.expression(condition, Use::Read)?;
+                self.in_loop(statement.span, block)?;
+            }
+            StatementKind::For { iterable, block, .. } => {
+                self.expression(iterable, Use::Read)?;
+                self.in_loop(statement.span, block)?;
+            }
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value, Use::Move)?;
+                }
+                if !self.diverged {
+                    let held = self.scopes.iter().flatten().find(|owned| owned.moved.is_none());
+                    if let Some(owned) = held {
+                        return Err(TypeError::new(TypeErrorKind::ResourceLeak(owned.name.clone()), owned.declared)
+                            .with_secondary(statement.span, "returns here while it is still held"));
+                    }
+                }
+                self.diverged = true;
+            }
+            StatementKind::Emit { arguments, .. } => {
+                for argument in arguments {
+                    self.expression(argument, Use::Read)?;
+                }
+            }
+            StatementKind::Ensure { condition, .. } => self.expression(condition, Use::Read)?,
+            StatementKind::Expression { expression, .. } => {
+                self.expression(expression, Use::Move)?;
+                self.discard(self.type_of(expression), expression.span)?;
+            }
+            StatementKind::Error => {}
         }
+        Ok(())
     }
+
+    /// Brings the names `pattern` binds into scope, tracking those that
+    /// hold resources
+    fn bind(&mut self, pattern: &Pattern, type_info: Type, value_span: Span) -> Result<(), Box<TypeError>> {
+        if !self.checker.is_resource(&type_info) {
+            return Ok(());
+        }
+        match (&pattern.kind, type_info) {
+            (PatternKind::Identifier { name, .. }, type_info) => {
+                let owned = Owned { name: name.clone(), type_info, declared: pattern.span, moved: None };
+                self.scopes.last_mut().expect("a scope is open").push(owned);
+            }
+            (PatternKind::Tuple(patterns), Type::Tuple(types)) if patterns.len() == types.len() => {
+                for (pattern, type_info) in patterns.iter().zip(types) {
+                    self.bind(pattern, type_info, value_span)?;
+                }
+            }
+            (PatternKind::Reference(inner), type_info) => self.bind(inner, type_info, value_span)?,
+            (_, type_info) => {
+                return Err(TypeError::new(TypeErrorKind::ResourceDropped(type_name(&type_info)), value_span)
+                    .with_secondary(pattern.span, "not bound to a name here"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a statement whose value of type `value_type` is thrown away
+    fn discard(&self, value_type: Type, span: Span) -> Result<(), Box<TypeError>> {
+        if self.checker.is_resource(&value_type) {
+            return Err(TypeError::new(TypeErrorKind::ResourceDropped(type_name(&value_type)), span));
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, target: &Expression, value: &Expression) -> Result<(), Box<TypeError>> {
+        self.expression(value, Use::Move)?;
+        if let ExpressionKind::Identifier(name) = &target.kind {
+            if let Some(owned) = self.lookup_mut(name) {
+                // Whatever it held would be dropped; after this it holds
+                // the new value
+                if owned.moved.is_none() {
+                    return Err(TypeError::new(TypeErrorKind::ResourceLeak(name.clone()), owned.declared)
+                        .with_secondary(target.span, "overwritten here while still held"));
+                }
+                owned.moved = None;
+                return Ok(());
+            }
+        }
+        // A storage place or a field; only the parts that pick it matter
+        match &target.kind {
+            ExpressionKind::IndexAccess { array, index } => {
+                self.expression(array, Use::Read)?;
+                self.expression(index, Use::Read)
+            }
+            ExpressionKind::MemberAccess { object, .. } => self.expression(object, Use::Read),
+            _ => Ok(()),
+        }
+    }
+
+    fn call(&mut self, function: &Expression, arguments: &[Expression]) -> Result<(), Box<TypeError>> {
+        match &function.kind {
+            ExpressionKind::MemberAccess { object, member } => {
+                if member == "clone" && self.checker.is_resource(&self.type_of(object)) {
+                    return Err(TypeError::new(TypeErrorKind::ResourceCopied(describe(object)), function.span));
+                }
+                // The receiver is borrowed
+                self.expression(object, Use::Read)?;
+            }
+            ExpressionKind::Identifier(_) | ExpressionKind::Path(_) => {}
+            _ => self.expression(function, Use::Read)?,
+        }
+        for argument in arguments {
+            self.expression(argument, Use::Move)?;
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression, usage: Use) -> Result<(), Box<TypeError>> {
+        let span = expression.span;
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => {
+                let Some(owned) = self.lookup_mut(name) else {
+                    // A state variable: its resources stay in storage
+                    if usage == Use::Move && self.checker.is_resource(&self.type_of(expression)) {
+                        return Err(TypeError::new(TypeErrorKind::ResourceCopied(name.clone()), span));
+                    }
+                    return Ok(());
+                };
+                if let Some(moved) = owned.moved {
+                    return Err(TypeError::new(TypeErrorKind::ResourceUsedAfterMove(name.clone()), span)
+                        .with_secondary(moved, "value moved here"));
+                }
+                if usage == Use::Move {
+                    owned.moved = Some(span);
+                }
+            }
+            ExpressionKind::NumberLiteral(_)
+            | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::BoolLiteral(_)
+            | ExpressionKind::AddressLiteral(_)
+            | ExpressionKind::Path(_)
+            | ExpressionKind::Error => {}
+            ExpressionKind::Binary { left, right, .. } => {
+                self.expression(left, Use::Read)?;
+                self.expression(right, Use::Read)?;
+            }
+            ExpressionKind::Unary { operand, .. } => self.expression(operand, Use::Read)?,
+            ExpressionKind::FunctionCall { function, arguments } => self.call(function, arguments)?,
+            ExpressionKind::MemberAccess { object, .. } | ExpressionKind::IndexAccess { array: object, .. } => {
+                // Reading a field or element leaves the whole in place, so
+                // taking a resource out of it would copy that resource
+                if usage == Use::Move && self.checker.is_resource(&self.type_of(expression)) {
+                    return Err(TypeError::new(TypeErrorKind::ResourceCopied(describe(expression)), span));
+                }
+                self.expression(object, Use::Read)?;
+                if let ExpressionKind::IndexAccess { index, .. } = &expression.kind {
+                    self.expression(index, Use::Read)?;
+                }
+            }
+            ExpressionKind::Tuple(elements) => {
+                for element in elements {
+                    self.expression(element, usage)?;
+                }
+            }
+            ExpressionKind::StructLiteral { fields, .. } => {
+                for (_, value) in fields {
+                    self.expression(value, usage)?;
+                }
+            }
+            ExpressionKind::MacroCall { arguments, .. } => {
+                for argument in arguments {
+                    self.expression(argument, Use::Move)?;
+                }
+            }
+            // A closure takes whatever it mentions with it
+            ExpressionKind::Closure { body, .. } => self.expression(body, Use::Move)?,
+            ExpressionKind::Try(inner) => self.expression(inner, usage)?,
+            ExpressionKind::Cast { expression, .. } => self.expression(expression, Use::Read)?,
+            ExpressionKind::Range { start, end } => {
+                self.expression(start, Use::Read)?;
+                self.expression(end, Use::Read)?;
+            }
+            ExpressionKind::If { condition, then_block, else_block } => {
+                self.expression(condition, Use::Read)?;
+                self.branches(span, then_block, else_block.as_ref(), Some(usage))?;
+            }
+            ExpressionKind::Let { value, .. } => self.expression(value, Use::Read)?,
+            ExpressionKind::Match { scrutinee, arms } => {
+                self.expression(scrutinee, Use::Read)?;
+                let before = (self.scopes.clone(), self.diverged);
+                let mut outcomes = Vec::new();
+                for arm in arms {
+                    (self.scopes, self.diverged) = before.clone();
+                    self.expression(&arm.body, usage)?;
+                    outcomes.push((std::mem::take(&mut self.scopes), self.diverged));
+                }
+                (self.scopes, self.diverged) = before;
+                self.merge(span, outcomes)?;
+            }
+            ExpressionKind::Block(block) => self.block(block, Some(usage))?,
+            ExpressionKind::Assign { target, value } => self.assignment(target, value)?,
+        }
+        Ok(())
+    }
+
+    /// Walks the two arms of an `if`, each from the state before it
+    fn branches(
+        &mut self,
+        span: Span,
+        then_block: &Block,
+        else_block: Option<&Block>,
+        tail: Option<Use>,
+    ) -> Result<(), Box<TypeError>> {
+        let before = (self.scopes.clone(), self.diverged);
+        self.block(then_block, tail)?;
+        let then_outcome = (std::mem::replace(&mut self.scopes, before.0.clone()), self.diverged);
+        self.diverged = before.1;
+        if let Some(else_block) = else_block {
+            self.block(else_block, tail)?;
+        }
+        let else_outcome = (std::mem::take(&mut self.scopes), self.diverged);
+        (self.scopes, self.diverged) = before;
+        self.merge(span, vec![then_outcome, else_outcome])
+    }
+
+    /// Joins the states at the ends of the paths through a branch at
+    /// `span`. Paths that returned do not reach the join; the rest must
+    /// agree on which resources they moved.
+    fn merge(&mut self, span: Span, outcomes: Vec<(Vec<Vec<Owned>>, bool)>) -> Result<(), Box<TypeError>> {
+        let mut reaching = outcomes.into_iter().filter(|(_, diverged)| !diverged).map(|(scopes, _)| scopes);
+        let Some(joined) = reaching.next() else {
+            self.diverged = true;
+            return Ok(());
+        };
+        for scopes in reaching {
+            for (a, b) in joined.iter().flatten().zip(scopes.iter().flatten()) {
+                if a.moved.is_some() != b.moved.is_some() {
+                    let moved = a.moved.or(b.moved).expect("one of them moved");
+                    return Err(TypeError::new(TypeErrorKind::ResourceMovedOnSomePaths(a.name.clone()), span)
+                        .with_secondary(moved, "moved here"));
+                }
+            }
+        }
+        self.scopes = joined;
+        Ok(())
+    }
+
+    /// Walks a loop body, which may run any number of times, so it must
+    /// leave every resource from outside it as it was
+    fn in_loop(&mut self, span: Span, body: &Block) -> Result<(), Box<TypeError>> {
+        let before = (self.scopes.clone(), self.diverged);
+        self.block(body, None)?;
+        if !self.diverged {
+            for (a, b) in before.0.iter().flatten().zip(self.scopes.iter().flatten()) {
+                if a.moved.is_some() != b.moved.is_some() {
+                    let at = b.moved.unwrap_or(span);
+                    return Err(TypeError::new(TypeErrorKind::ResourceMovedInLoop(a.name.clone()), at)
+                        .with_secondary(span, "inside this loop"));
+                }
+            }
+        }
+        (self.scopes, self.diverged) = before;
+        Ok(())
+    }
+
+    fn return_type(&self, function: &str) -> Type {
+        self.checker.functions.get(function)
+            .and_then(|signature| signature.return_type.clone())
+            .unwrap_or(Type::Error)
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Owned> {
+        self.scopes.iter_mut().rev().flat_map(|scope| scope.iter_mut().rev()).find(|owned| owned.name == name)
+    }
+
+    /// The type of `expression` as far as linearity cares; `Type::Error`
+    /// where it is not worked out
+    fn type_of(&self, expression: &Expression) -> Type {
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => {
+                let owned = self.scopes.iter().rev().flat_map(|scope| scope.iter().rev()).find(|owned| owned.name == *name);
+                match owned {
+                    Some(owned) => owned.type_info.clone(),
+                    None => self.checker.variables.get(name).cloned().unwrap_or(Type::Error),
+                }
+            }
+            ExpressionKind::StructLiteral { name, .. } => Type::Custom(name.clone()),
+            ExpressionKind::FunctionCall { function, .. } => match &function.kind {
+                ExpressionKind::Identifier(name) => self.return_type(name),
+                _ => Type::Error,
+            },
+            ExpressionKind::MemberAccess { object, member } => match self.type_of(object) {
+                Type::Custom(name) => self.checker.structs.get(&name)
+                    .and_then(|decl| decl.fields.iter().find(|field| field.name == *member))
+                    .map_or(Type::Error, |field| field.type_info.clone()),
+                _ => Type::Error,
+            },
+            ExpressionKind::IndexAccess { array, .. } => match self.type_of(array) {
+                Type::Map { value_type, .. } => *value_type,
+                Type::Array(element_type) => *element_type,
+                _ => Type::Error,
+            },
+            ExpressionKind::Tuple(elements) => Type::Tuple(elements.iter().map(|e| self.type_of(e)).collect()),
+            ExpressionKind::Cast { type_info, .. } => type_info.clone(),
+            _ => Type::Error,
+        }
+    }
+}
+
+/// The type of the host table's value called `name`, such as `msg.sender`
+/// Types exported by each `std` module
+const STD_TYPES: &[(&str, &[&str])] = &[
+    ("bridge", &["BridgeConfig", "ChainId", "Message", "Proof"]),
+    ("collections", &["Map", "Set", "Vec"]),
+    ("crypto", &[]),
+    ("math", &[]),
+    ("time", &["Duration", "Timestamp"]),
+    ("uri", &["Uri"]),
+];
+
+/// `std` types checked as the type they stand for: durations and
+/// timestamps count seconds
+const STD_ALIASES: &[(&str, Type)] = &[
+    ("Duration", Type::U256),
+    ("Timestamp", Type::U256),
+];
+
+fn host_value(name: &str) -> Option<Type> {
+    host::lookup(name)
+        .filter(|(_, entry)| entry.is_value())
+        .map(|(_, entry)| host_type(&entry.returns))
 }
 
-pub fn check(program: Program) -> Result<Program, TypeError> {
+fn host_type(ty: &bytecode::Type) -> Type {
+    match ty {
+        bytecode::Type::U256 => Type::U256,
+        bytecode::Type::Address => Type::Address,
+        bytecode::Type::Bool => Type::Bool,
+        bytecode::Type::String => Type::String,
+        bytecode::Type::Void => Type::Tuple(Vec::new()),
+        _ => Type::Error,
+    }
+}
+
+/// How a diagnostic names the resource `expression` evaluates to
+fn describe(expression: &Expression) -> String {
+    match &expression.kind {
+        ExpressionKind::Identifier(name) => name.clone(),
+        ExpressionKind::MemberAccess { object, member } => format!("{}.{}", describe(object), member),
+        ExpressionKind::IndexAccess { array, .. } => format!("{}[..]", describe(array)),
+        _ => "value".to_string(),
+    }
+}
+
+fn type_name(type_info: &Type) -> String {
+    match type_info {
+        Type::Custom(name) => name.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Rejects the second declaration of any name in `declarations`, which
+/// are in source order
+fn check_unique(declarations: Vec<(&String, Span)>) -> Result<(), Box<TypeError>> {
+    let mut seen: HashMap<&String, Span> = HashMap::new();
+    for (name, span) in declarations {
+        if let Some(previous) = seen.insert(name, span) {
+            return Err(TypeError::new(TypeErrorKind::DuplicateDefinition(name.clone()), span)
+                .with_secondary(previous, "previous definition here"));
+        }
+    }
+    Ok(())
+}
+
+pub fn check(program: Program) -> Result<Program, Box<TypeError>> {
     let mut checker = TypeChecker::new();
     checker.check(&program)?;
     Ok(program)
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_type_error_carries_primary_and_secondary_spans() {
+        let input = "contract C {\n    event Paid(amount: u256);\n    fn f() {\n        emit Paid(true);\n    }\n}";
+        let program = parse(tokenize(input).unwrap()).unwrap();
+        let err = check(program).unwrap_err();
+
+        assert!(matches!(err.kind, TypeErrorKind::TypeMismatch { .. }));
+        assert_eq!(&input[err.span.start..err.span.end], "true");
+        assert_eq!(&input[err.secondary[0].span.start..err.secondary[0].span.end], "amount: u256");
+    }
+
+    #[test]
+    fn test_host_values_and_functions_are_typed() {
+        let input = "contract C {
+    state owner: Address;
+    fn f(data: String) -> bool {
+        let digest: u256 = keccak256(data);
+        let late: bool = block.timestamp > chain.id + self.balance;
+        owner == msg.sender && balance(owner) > digest && late
+    }
+}";
+        assert!(check(parse(tokenize(input).unwrap()).unwrap()).is_ok());
+
+        let input = "contract C {\n    fn f() -> u256 {\n        balance(true)\n    }\n}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::InvalidOperation { ref op, .. } if op == "argument of balance"));
+        assert_eq!(&input[err.span.start..err.span.end], "true");
+    }
+
+    #[test]
+    fn test_imports_and_aliases_are_resolved() {
+        let input = "use std::time::{Duration, Timestamp};
+use std::uri::Uri;
+use std::math::min;
+contract C {
+    state base: Uri;
+    state start: Timestamp;
+    type TokenId = u256;
+    fn f(id: TokenId, period: Duration) -> Result<TokenId, Error> {
+        ensure!(block.timestamp >= start + period, \"Too early\");
+        Ok(min(id, Duration::from_days(1)))
+    }
+}";
+        assert!(check(parse(tokenize(input).unwrap()).unwrap()).is_ok());
+
+        let input = "use std::math::exp;\ncontract C {}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::UnresolvedImport(ref path) if path == "std::math::exp"));
+        assert_eq!(&input[err.span.start..err.span.end], "use std::math::exp;");
+    }
+
+    #[test]
+    fn test_interfaces_and_modifiers_are_checked() {
+        let contract = |body: &str| format!("interface Receiver {{
+    fn receive(amount: u256) -> bool;
+}}
+contract C {{
+    state owner: Address;
+    fn only_owner() {{ ensure!(msg.sender == owner, \"Not owner\"); }}
+{}
+}}", body);
+        let input = contract("    @only_owner\n    fn pay(receiver: Receiver, amount: u256) -> bool { receiver.receive(amount) }");
+        assert!(check(parse(tokenize(&input).unwrap()).unwrap()).is_ok());
+
+        let input = contract("    fn pay(receiver: Receiver) -> bool { receiver.receive(true) }");
+        let err = check(parse(tokenize(&input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::TypeMismatch { .. }));
+        assert_eq!(&input[err.secondary[0].span.start..err.secondary[0].span.end], "amount: u256");
+
+        let input = contract("    fn pay(receiver: Receiver) -> bool { receiver.refund(1) }");
+        let err = check(parse(tokenize(&input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::InvalidOperation { ref op, .. } if op == "method refund"));
+        assert!(input[err.secondary[0].span.start..].starts_with("interface Receiver"));
+
+        let input = contract("    @only_admin\n    fn pay() {}");
+        let err = check(parse(tokenize(&input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::UndefinedFunction(ref name) if name == "only_admin"));
+    }
+
+    #[test]
+    fn test_mutability_is_enforced() {
+        let input = "contract C {\n    fn f() -> u256 {\n        let x = 1;\n        x = 2;\n        x\n    }\n}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::AssignToImmutable(ref name) if name == "x"));
+        assert_eq!(err.span.start, input.find("x = 2").unwrap());
+        assert_eq!(err.secondary[0].span.start, input.find("x = 1").unwrap());
+
+        let input = "contract C { fn f() -> u256 { let mut x = 1; x = 2; x } }";
+        assert!(check(parse(tokenize(input).unwrap()).unwrap()).is_ok());
+
+        let input = "contract C { mut fn bump() -> u256 { 1 } pure fn f() -> u256 { bump() } }";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::StateModificationInPureFunction));
+    }
+
+    #[test]
+    fn test_only_test_modules_take_attributes() {
+        let module_error = |module: &str| {
+            let input = format!("contract C {{}}\n{}", module);
+            check(parse(tokenize(&input).unwrap()).unwrap()).err().map(|err| err.kind)
+        };
+        assert!(module_error("#[cfg(test)]\nmod tests {\n    #[test]\n    fn t() {}\n}").is_none());
+        assert!(matches!(module_error("mod tests {\n    #[test]\n    fn t() {}\n}"),
+            Some(TypeErrorKind::TestOutsideTestModule(name)) if name == "tests"));
+        assert!(matches!(module_error("#[cfg(test)]\nmod tests {\n    #[inline]\n    fn t() {}\n}"),
+            Some(TypeErrorKind::UnknownAttribute(name)) if name == "inline"));
+        assert!(matches!(module_error("#[cfg(feature)]\nmod m {}"),
+            Some(TypeErrorKind::UnknownAttribute(name)) if name == "cfg(feature)"));
+    }
+
+    #[test]
+    fn test_duplicate_types_and_variants_are_rejected() {
+        let input = "contract C {\n    struct Id { value: u256 }\n    type Id = u256;\n}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::DuplicateDefinition(ref name) if name == "Id"));
+        assert_eq!(&input[err.span.start..err.span.end], "type Id = u256;");
+        assert!(input[err.secondary[0].span.start..].starts_with("struct Id"));
+
+        let input = "contract C {\n    enum State { Open, Closed, Open }\n}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::DuplicateDefinition(ref name) if name == "Open"));
+        assert_eq!(err.span.start, input.rfind("Open").unwrap());
+
+        let input = "contract C {\n    type Balance = Map<Address, Coin>;\n}";
+        let err = check(parse(tokenize(input).unwrap()).unwrap()).unwrap_err();
+        assert!(matches!(err.kind, TypeErrorKind::UndefinedType(ref name) if name == "Coin"));
+        assert_eq!(&input[err.span.start..err.span.end], "type Balance = Map<Address, Coin>;");
+    }
+
+    /// A contract with a `Coin` resource around `functions`
+    fn bank(functions: &str) -> String {
+        format!("contract Bank {{
+    resource Coin {{ amount: u256 }}
+    state reserve: Coin;
+    fn mint(amount: u256) -> Coin {{ Coin {{ amount: amount }} }}
+    fn deposit(coin: Coin) {{ reserve = coin; }}
+{}
+}}", functions)
+    }
+
+    /// Checks `functions` in [`bank`] and returns the error with the text
+    /// under its primary and first secondary span
+    fn resource_error(functions: &str) -> (TypeErrorKind, String, Option<String>) {
+        let input = bank(functions);
+        let err = check(parse(tokenize(&input).unwrap()).unwrap()).unwrap_err();
+        let text = |span: Span| input[span.start..span.end].to_string();
+        (err.kind, text(err.span), err.secondary.first().map(|label| text(label.span)))
+    }
+
+    #[test]
+    fn test_resources_moved_exactly_once_pass() {
+        let input = bank("
+    fn split(coin: Coin, amount: u256) -> (Coin, Coin) {
+        let total = coin.amount;
+        destroy(coin);
+        (Coin { amount: amount }, Coin { amount: total - amount })
+    }
+    fn settle(coin: Coin, keep: bool) -> u256 {
+        let amount = coin.amount;
+        if keep {
+            deposit(coin);
+        } else {
+            destroy(coin);
+        }
+        amount
+    }
+    fn refund(coin: Coin, to_reserve: bool) {
+        if to_reserve {
+            deposit(coin);
+            return;
+        }
+        let (a, b) = split(coin, 1);
+        destroy(a);
+        let mut spare = b;
+        while reserve.amount < 10 {
+            let fresh = mint(1);
+            deposit(fresh);
+        }
+        deposit(spare);
+        spare = mint(2);
+        destroy(spare);
+    }");
+        assert!(check(parse(tokenize(&input).unwrap()).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_resource_leaks_copies_and_drops() {
+        let (kind, primary, secondary) = resource_error("fn f(coin: Coin) { let a = coin.amount; }");
+        assert!(matches!(kind, TypeErrorKind::ResourceLeak(name) if name == "coin"));
+        assert_eq!((primary.as_str(), secondary.as_deref()), ("coin: Coin", Some("")));
+
+        let (kind, primary, secondary) = resource_error("fn f(x: u256) { let c = mint(x); if x > 1 { return; } destroy(c); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceLeak(name) if name == "c"));
+        assert_eq!((primary.as_str(), secondary.as_deref()), ("c", Some("return;")));
+
+        let (kind, primary, _) = resource_error("fn f() { mint(1); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceDropped(name) if name == "Coin"));
+        assert_eq!(primary, "mint(1);");
+
+        let (kind, primary, _) = resource_error("fn f(coin: Coin) { deposit(coin.clone()); deposit(coin); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceCopied(name) if name == "coin"));
+        assert_eq!(primary, "coin.clone");
+
+        let (kind, primary, _) = resource_error("fn f() -> Coin { reserve }");
+        assert!(matches!(kind, TypeErrorKind::ResourceCopied(name) if name == "reserve"));
+        assert_eq!(primary, "reserve");
+
+        let (kind, ..) = resource_error("fn f(amount: u256) { destroy(amount); }");
+        assert!(matches!(kind, TypeErrorKind::InvalidOperation { op, .. } if op == "destroy"));
+    }
+
+    #[test]
+    fn test_resource_double_use_across_branches_and_loops() {
+        let (kind, primary, secondary) = resource_error("fn f(coin: Coin) { deposit(coin); destroy(coin); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceUsedAfterMove(name) if name == "coin"));
+        assert_eq!((primary.as_str(), secondary.as_deref()), ("coin", Some("coin")));
+
+        let (kind, primary, secondary) = resource_error("fn f(coin: Coin, b: bool) { if b { destroy(coin); } }");
+        assert!(matches!(kind, TypeErrorKind::ResourceMovedOnSomePaths(name) if name == "coin"));
+        assert!(primary.starts_with("if b"));
+        assert_eq!(secondary.as_deref(), Some("coin"));
+
+        let (kind, _, _) = resource_error("fn f(coin: Coin, b: bool) { if b { deposit(coin); } else { } destroy(coin); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceMovedOnSomePaths(name) if name == "coin"));
+
+        let (kind, primary, secondary) = resource_error("fn f(coin: Coin) { while reserve.amount > 0 { deposit(coin); } }");
+        assert!(matches!(kind, TypeErrorKind::ResourceMovedInLoop(name) if name == "coin"));
+        assert_eq!(primary, "coin");
+        assert!(secondary.unwrap().starts_with("while"));
+
+        let (kind, ..) = resource_error("fn f(coin: Coin) { let mut c = mint(1); c = coin; destroy(c); }");
+        assert!(matches!(kind, TypeErrorKind::ResourceLeak(name) if name == "c"));
+    }
+}
@@ -28,6 +28,20 @@ pub enum Error {
     
     // Contract errors
     ContractError(String),
+    InsufficientBalance,
+    /// A `@no_reentry` function was re-entered through `chain`
+    Reentrancy { function: String, chain: Vec<String> },
+
+    // VM errors
+    StackUnderflow,
+    TypeMismatch,
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidLocal(u32),
+
+    // Resource and permission errors
+    ResourceUnavailable,
+    PermissionDenied,
     
     // Serialization errors
     SerializationError(String),
@@ -36,6 +50,9 @@ pub enum Error {
     // Crypto errors
     CryptoError(String),
     
+    // Testing errors
+    TestAssertion(String),
+
     // Custom errors
     Custom(String),
 }
@@ -56,9 +73,21 @@ impl fmt::Display for Error {
             Error::AssertionFailed(msg) => write!(f, "Assertion failed: {}", msg),
             Error::IoError(err) => write!(f, "IO error: {}", err),
             Error::ContractError(msg) => write!(f, "Contract error: {}", msg),
+            Error::InsufficientBalance => write!(f, "Insufficient balance"),
+            Error::Reentrancy { function, chain } => {
+                write!(f, "Reentrant call detected in `{}` via {}", function, chain.join(" -> "))
+            }
+            Error::StackUnderflow => write!(f, "Stack underflow"),
+            Error::TypeMismatch => write!(f, "Type mismatch"),
+            Error::ArithmeticOverflow => write!(f, "Arithmetic overflow"),
+            Error::DivisionByZero => write!(f, "Division by zero"),
+            Error::InvalidLocal(index) => write!(f, "Invalid or uninitialized local: {}", index),
+            Error::ResourceUnavailable => write!(f, "Resource unavailable"),
+            Error::PermissionDenied => write!(f, "Permission denied"),
             Error::SerializationError(msg) => write!(f, "Serialization error: {}", msg),
             Error::DeserializationError(msg) => write!(f, "Deserialization error: {}", msg),
             Error::CryptoError(msg) => write!(f, "Crypto error: {}", msg),
+            Error::TestAssertion(msg) => write!(f, "Test assertion failed: {}", msg),
             Error::Custom(msg) => write!(f, "{}", msg),
         }
     }
@@ -0,0 +1,604 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{
+    Add, AddAssign, BitAnd, BitOr, BitXor, Div, DivAssign, Mul, MulAssign, Neg, Not, Rem,
+    RemAssign, Shl, Shr, Sub, SubAssign,
+};
+use std::str::FromStr;
+
+use super::{ParseNumError, U256};
+
+/// The bit pattern of `I256::MIN`
+const SIGN_BIT: U256 = U256::from_limbs([0, 0, 0, 1 << 63]);
+
+/// A 256-bit two's complement signed integer
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct I256(U256);
+
+impl I256 {
+    pub const ZERO: I256 = I256(U256::ZERO);
+    pub const ONE: I256 = I256(U256::ONE);
+    pub const MINUS_ONE: I256 = I256(U256::MAX);
+    pub const MIN: I256 = I256(SIGN_BIT);
+    pub const MAX: I256 = I256(U256::from_limbs([u64::MAX, u64::MAX, u64::MAX, u64::MAX >> 1]));
+    pub const BITS: u32 = 256;
+
+    /// Reinterprets a two's complement bit pattern
+    pub const fn from_bits(bits: U256) -> Self {
+        I256(bits)
+    }
+
+    pub const fn into_bits(self) -> U256 {
+        self.0
+    }
+
+    /// `value` if it is at most `I256::MAX`
+    pub fn from_u256(value: U256) -> Option<Self> {
+        (value <= I256::MAX.0).then_some(I256(value))
+    }
+
+    /// The value if it is not negative
+    pub fn to_u256(self) -> Option<U256> {
+        (!self.is_negative()).then_some(self.0)
+    }
+
+    pub fn to_i64(self) -> Option<i64> {
+        let [low, rest @ ..] = self.0.into_limbs();
+        let fill = self.fill();
+        (rest == [fill; 3] && (low as i64).is_negative() == self.is_negative()).then_some(low as i64)
+    }
+
+    pub fn to_i128(self) -> Option<i128> {
+        let [low, high, rest @ ..] = self.0.into_limbs();
+        let fill = self.fill();
+        let value = ((high as u128) << 64 | low as u128) as i128;
+        (rest == [fill; 2] && value.is_negative() == self.is_negative()).then_some(value)
+    }
+
+    /// The limb a sign extension fills with
+    fn fill(&self) -> u64 {
+        if self.is_negative() { u64::MAX } else { 0 }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.bit(Self::BITS - 1)
+    }
+
+    pub fn is_positive(&self) -> bool {
+        !self.is_negative() && !self.is_zero()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// -1, 0 or 1
+    pub fn signum(self) -> I256 {
+        if self.is_negative() {
+            I256::MINUS_ONE
+        } else if self.is_zero() {
+            I256::ZERO
+        } else {
+            I256::ONE
+        }
+    }
+
+    /// The magnitude, which always fits in a `U256` (even for `MIN`)
+    pub fn unsigned_abs(self) -> U256 {
+        if self.is_negative() { self.0.wrapping_neg() } else { self.0 }
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        I256(U256::from_be_bytes(bytes))
+    }
+
+    pub fn from_dec_str(src: &str) -> Result<Self, ParseNumError> {
+        src.parse()
+    }
+
+    /// `magnitude` with the given sign, if it is in range
+    fn from_sign_magnitude(negative: bool, magnitude: U256) -> Option<I256> {
+        if negative {
+            (magnitude <= SIGN_BIT).then(|| I256(magnitude.wrapping_neg()))
+        } else {
+            I256::from_u256(magnitude)
+        }
+    }
+
+    // Overflowing arithmetic: the wrapped result and whether it wrapped
+
+    pub fn overflowing_neg(self) -> (I256, bool) {
+        (I256(self.0.wrapping_neg()), self == I256::MIN)
+    }
+
+    pub fn overflowing_abs(self) -> (I256, bool) {
+        if self.is_negative() { self.overflowing_neg() } else { (self, false) }
+    }
+
+    pub fn overflowing_add(self, other: I256) -> (I256, bool) {
+        let sum = I256(self.0.wrapping_add(other.0));
+        // Only operands of the same sign can overflow, and then the sign flips
+        let overflow = self.is_negative() == other.is_negative()
+            && sum.is_negative() != self.is_negative();
+        (sum, overflow)
+    }
+
+    pub fn overflowing_sub(self, other: I256) -> (I256, bool) {
+        let diff = I256(self.0.wrapping_sub(other.0));
+        let overflow = self.is_negative() != other.is_negative()
+            && diff.is_negative() != self.is_negative();
+        (diff, overflow)
+    }
+
+    pub fn overflowing_mul(self, other: I256) -> (I256, bool) {
+        // The low 256 bits of a product are the same signed or unsigned
+        let product = I256(self.0.wrapping_mul(other.0));
+        let negative = self.is_negative() != other.is_negative();
+        let (magnitude, overflow) = self.unsigned_abs().overflowing_mul(other.unsigned_abs());
+        let overflow = overflow || I256::from_sign_magnitude(negative, magnitude).is_none();
+        (product, overflow)
+    }
+
+    /// Truncates toward zero; panics when `other` is zero
+    pub fn overflowing_div(self, other: I256) -> (I256, bool) {
+        assert!(!other.is_zero(), "attempt to divide by zero");
+        if self == I256::MIN && other == I256::MINUS_ONE {
+            return (I256::MIN, true);
+        }
+        let quotient = self.unsigned_abs() / other.unsigned_abs();
+        let negative = self.is_negative() != other.is_negative();
+        (I256::from_sign_magnitude(negative, quotient).expect("|quotient| <= |self|"), false)
+    }
+
+    /// Takes the sign of `self`; panics when `other` is zero
+    pub fn overflowing_rem(self, other: I256) -> (I256, bool) {
+        assert!(!other.is_zero(), "attempt to calculate the remainder with a divisor of zero");
+        if self == I256::MIN && other == I256::MINUS_ONE {
+            return (I256::ZERO, true);
+        }
+        let remainder = self.unsigned_abs() % other.unsigned_abs();
+        (I256::from_sign_magnitude(self.is_negative(), remainder).expect("|remainder| < |other|"), false)
+    }
+
+    pub fn overflowing_pow(self, mut exp: u32) -> (I256, bool) {
+        let mut base = self;
+        let mut acc = I256::ONE;
+        let mut overflow = false;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                let (product, o) = acc.overflowing_mul(base);
+                acc = product;
+                overflow |= o;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                let (square, o) = base.overflowing_mul(base);
+                base = square;
+                overflow |= o;
+            }
+        }
+        (acc, overflow)
+    }
+
+    // Checked arithmetic: `None` on overflow or division by zero
+
+    pub fn checked_neg(self) -> Option<I256> {
+        match self.overflowing_neg() {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn checked_abs(self) -> Option<I256> {
+        match self.overflowing_abs() {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn checked_add(self, other: I256) -> Option<I256> {
+        match self.overflowing_add(other) {
+            (sum, false) => Some(sum),
+            _ => None,
+        }
+    }
+
+    pub fn checked_sub(self, other: I256) -> Option<I256> {
+        match self.overflowing_sub(other) {
+            (diff, false) => Some(diff),
+            _ => None,
+        }
+    }
+
+    pub fn checked_mul(self, other: I256) -> Option<I256> {
+        match self.overflowing_mul(other) {
+            (product, false) => Some(product),
+            _ => None,
+        }
+    }
+
+    pub fn checked_div(self, other: I256) -> Option<I256> {
+        match other.is_zero() {
+            true => None,
+            false => match self.overflowing_div(other) {
+                (quotient, false) => Some(quotient),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn checked_rem(self, other: I256) -> Option<I256> {
+        match other.is_zero() {
+            true => None,
+            false => match self.overflowing_rem(other) {
+                (remainder, false) => Some(remainder),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn checked_pow(self, exp: u32) -> Option<I256> {
+        match self.overflowing_pow(exp) {
+            (power, false) => Some(power),
+            _ => None,
+        }
+    }
+
+    /// `None` when `shift` is 256 or more
+    pub fn checked_shl(self, shift: u32) -> Option<I256> {
+        (shift < Self::BITS).then(|| self << shift)
+    }
+
+    /// `None` when `shift` is 256 or more
+    pub fn checked_shr(self, shift: u32) -> Option<I256> {
+        (shift < Self::BITS).then(|| self >> shift)
+    }
+
+    // Wrapping arithmetic: modulo 2^256
+
+    pub fn wrapping_neg(self) -> I256 {
+        self.overflowing_neg().0
+    }
+
+    pub fn wrapping_abs(self) -> I256 {
+        self.overflowing_abs().0
+    }
+
+    pub fn wrapping_add(self, other: I256) -> I256 {
+        self.overflowing_add(other).0
+    }
+
+    pub fn wrapping_sub(self, other: I256) -> I256 {
+        self.overflowing_sub(other).0
+    }
+
+    pub fn wrapping_mul(self, other: I256) -> I256 {
+        self.overflowing_mul(other).0
+    }
+
+    /// `MIN / -1` wraps to `MIN`; panics when `other` is zero
+    pub fn wrapping_div(self, other: I256) -> I256 {
+        self.overflowing_div(other).0
+    }
+
+    /// `MIN % -1` wraps to zero; panics when `other` is zero
+    pub fn wrapping_rem(self, other: I256) -> I256 {
+        self.overflowing_rem(other).0
+    }
+
+    pub fn wrapping_pow(self, exp: u32) -> I256 {
+        self.overflowing_pow(exp).0
+    }
+
+    /// Shifts by `shift % 256`, like the primitive `wrapping_shl`
+    pub fn wrapping_shl(self, shift: u32) -> I256 {
+        self << (shift % Self::BITS)
+    }
+
+    /// Shifts by `shift % 256`, like the primitive `wrapping_shr`
+    pub fn wrapping_shr(self, shift: u32) -> I256 {
+        self >> (shift % Self::BITS)
+    }
+
+    // Saturating arithmetic: clamped to `MIN..=MAX`
+
+    pub fn saturating_neg(self) -> I256 {
+        self.checked_neg().unwrap_or(I256::MAX)
+    }
+
+    pub fn saturating_abs(self) -> I256 {
+        self.checked_abs().unwrap_or(I256::MAX)
+    }
+
+    pub fn saturating_add(self, other: I256) -> I256 {
+        self.checked_add(other)
+            .unwrap_or(if other.is_negative() { I256::MIN } else { I256::MAX })
+    }
+
+    pub fn saturating_sub(self, other: I256) -> I256 {
+        self.checked_sub(other)
+            .unwrap_or(if other.is_negative() { I256::MAX } else { I256::MIN })
+    }
+
+    pub fn saturating_mul(self, other: I256) -> I256 {
+        self.checked_mul(other).unwrap_or(
+            if self.is_negative() != other.is_negative() { I256::MIN } else { I256::MAX },
+        )
+    }
+
+    /// `MIN / -1` saturates to `MAX`; panics when `other` is zero
+    pub fn saturating_div(self, other: I256) -> I256 {
+        match self.overflowing_div(other) {
+            (quotient, false) => quotient,
+            _ => I256::MAX,
+        }
+    }
+
+    /// `MIN % -1` is zero; panics when `other` is zero
+    pub fn saturating_rem(self, other: I256) -> I256 {
+        self.overflowing_rem(other).0
+    }
+
+    pub fn saturating_pow(self, exp: u32) -> I256 {
+        self.checked_pow(exp).unwrap_or(
+            if self.is_negative() && exp % 2 == 1 { I256::MIN } else { I256::MAX },
+        )
+    }
+}
+
+impl Ord for I256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.is_negative(), other.is_negative()) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // Same sign: two's complement order matches unsigned order
+            _ => self.0.cmp(&other.0),
+        }
+    }
+}
+
+impl PartialOrd for I256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+macro_rules! impl_from_signed {
+    ($($t:ty),*) => {
+        $(impl From<$t> for I256 {
+            fn from(value: $t) -> Self {
+                I256::from(value as i128)
+            }
+        })*
+    };
+}
+
+impl_from_signed!(i8, i16, i32, i64, isize);
+
+impl From<i128> for I256 {
+    fn from(value: i128) -> Self {
+        let fill = if value < 0 { u64::MAX } else { 0 };
+        I256(U256::from_limbs([value as u64, (value >> 64) as u64, fill, fill]))
+    }
+}
+
+macro_rules! impl_checked_op {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $checked:ident, $msg:literal) => {
+        impl $trait for I256 {
+            type Output = I256;
+
+            fn $method(self, rhs: I256) -> I256 {
+                self.$checked(rhs).expect($msg)
+            }
+        }
+
+        impl $assign_trait for I256 {
+            fn $assign_method(&mut self, rhs: I256) {
+                *self = $trait::$method(*self, rhs);
+            }
+        }
+    };
+}
+
+impl_checked_op!(Add, add, AddAssign, add_assign, checked_add, "attempt to add with overflow");
+impl_checked_op!(Sub, sub, SubAssign, sub_assign, checked_sub, "attempt to subtract with overflow");
+impl_checked_op!(Mul, mul, MulAssign, mul_assign, checked_mul, "attempt to multiply with overflow");
+impl_checked_op!(Div, div, DivAssign, div_assign, checked_div, "attempt to divide by zero or with overflow");
+impl_checked_op!(Rem, rem, RemAssign, rem_assign, checked_rem, "attempt to calculate the remainder with a divisor of zero or with overflow");
+
+impl Neg for I256 {
+    type Output = I256;
+
+    fn neg(self) -> I256 {
+        self.checked_neg().expect("attempt to negate with overflow")
+    }
+}
+
+impl BitAnd for I256 {
+    type Output = I256;
+
+    fn bitand(self, rhs: I256) -> I256 {
+        I256(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for I256 {
+    type Output = I256;
+
+    fn bitor(self, rhs: I256) -> I256 {
+        I256(self.0 | rhs.0)
+    }
+}
+
+impl BitXor for I256 {
+    type Output = I256;
+
+    fn bitxor(self, rhs: I256) -> I256 {
+        I256(self.0 ^ rhs.0)
+    }
+}
+
+impl Not for I256 {
+    type Output = I256;
+
+    fn not(self) -> I256 {
+        I256(!self.0)
+    }
+}
+
+/// Shifting by 256 or more yields zero
+impl Shl<u32> for I256 {
+    type Output = I256;
+
+    fn shl(self, shift: u32) -> I256 {
+        I256(self.0 << shift)
+    }
+}
+
+/// Arithmetic shift; shifting by 256 or more yields 0 or -1
+impl Shr<u32> for I256 {
+    type Output = I256;
+
+    fn shr(self, shift: u32) -> I256 {
+        if self.is_negative() {
+            I256(!(!self.0 >> shift))
+        } else {
+            I256(self.0 >> shift)
+        }
+    }
+}
+
+/// Decimal with an optional sign
+impl FromStr for I256 {
+    type Err = ParseNumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let magnitude = U256::from_str_radix(digits, 10)?;
+        I256::from_sign_magnitude(negative, magnitude).ok_or(ParseNumError::OutOfRange)
+    }
+}
+
+impl fmt::Display for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad_integral(!self.is_negative(), "", &self.unsigned_abs().to_string())
+    }
+}
+
+impl fmt::Debug for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i(n: i128) -> I256 {
+        I256::from(n)
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let min = "-57896044618658097711785492504343953926634992332820282019728792003956564819968";
+        let max = "57896044618658097711785492504343953926634992332820282019728792003956564819967";
+        assert_eq!(min.parse::<I256>(), Ok(I256::MIN));
+        assert_eq!(max.parse::<I256>(), Ok(I256::MAX));
+        assert_eq!(I256::MIN.to_string(), min);
+        assert_eq!(I256::MAX.to_string(), max);
+        assert_eq!(i(-42).to_string(), "-42");
+        assert_eq!(format!("{:+}", i(7)), "+7");
+        assert_eq!("+5".parse::<I256>(), Ok(i(5)));
+        // One past MAX
+        let too_big = "57896044618658097711785492504343953926634992332820282019728792003956564819968";
+        assert_eq!(too_big.parse::<I256>(), Err(ParseNumError::OutOfRange));
+        assert_eq!("-".parse::<I256>(), Err(ParseNumError::Empty));
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(i(-1), I256::MINUS_ONE);
+        assert_eq!(i(i128::MIN).to_i128(), Some(i128::MIN));
+        assert_eq!(i(-5).to_i64(), Some(-5));
+        assert_eq!(i(i64::MAX as i128 + 1).to_i64(), None);
+        assert_eq!(i(i64::MIN as i128 - 1).to_i64(), None);
+        assert_eq!(I256::MIN.unsigned_abs(), U256::ONE << 255);
+        assert_eq!(I256::from_u256(U256::ONE << 255), None);
+        assert_eq!(i(-1).to_u256(), None);
+        assert_eq!(I256::from_be_bytes(i(-2).to_be_bytes()), i(-2));
+    }
+
+    #[test]
+    fn test_add_and_sub() {
+        assert_eq!(i(-3) + i(5), i(2));
+        assert_eq!(i(-3) - i(5), i(-8));
+        assert_eq!(I256::MAX.overflowing_add(I256::ONE), (I256::MIN, true));
+        assert_eq!(I256::MIN.checked_sub(I256::ONE), None);
+        assert_eq!(I256::MIN.wrapping_sub(I256::ONE), I256::MAX);
+        assert_eq!(I256::MAX.saturating_add(I256::ONE), I256::MAX);
+        assert_eq!(I256::MIN.saturating_add(I256::MINUS_ONE), I256::MIN);
+        assert_eq!(I256::MIN.saturating_sub(I256::ONE), I256::MIN);
+        assert_eq!(I256::MAX.saturating_sub(I256::MINUS_ONE), I256::MAX);
+    }
+
+    #[test]
+    fn test_mul() {
+        assert_eq!(i(-4) * i(6), i(-24));
+        assert_eq!(i(-4) * i(-6), i(24));
+        // -2^254 * 2 is exactly MIN, but 2^254 * 2 is one past MAX
+        let half = I256::ONE << 254;
+        assert_eq!((-half).checked_mul(i(2)), Some(I256::MIN));
+        assert_eq!(half.checked_mul(i(2)), None);
+        assert_eq!(I256::MIN.checked_mul(I256::MINUS_ONE), None);
+        assert_eq!(I256::MIN.wrapping_mul(I256::MINUS_ONE), I256::MIN);
+        assert_eq!(half.saturating_mul(i(-4)), I256::MIN);
+        assert_eq!(half.saturating_mul(i(4)), I256::MAX);
+    }
+
+    #[test]
+    fn test_div_and_rem() {
+        // Truncation toward zero, remainder takes the dividend's sign
+        assert_eq!(i(-7) / i(2), i(-3));
+        assert_eq!(i(-7) % i(2), i(-1));
+        assert_eq!(i(7) % i(-2), i(1));
+        assert_eq!(I256::MIN.checked_div(I256::MINUS_ONE), None);
+        assert_eq!(I256::MIN.wrapping_div(I256::MINUS_ONE), I256::MIN);
+        assert_eq!(I256::MIN.saturating_div(I256::MINUS_ONE), I256::MAX);
+        assert_eq!(I256::MIN.checked_rem(I256::MINUS_ONE), None);
+        assert_eq!(I256::MIN.wrapping_rem(I256::MINUS_ONE), I256::ZERO);
+        assert_eq!(i(1).checked_div(I256::ZERO), None);
+        assert_eq!(I256::MIN / i(2), -(I256::ONE << 254));
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(i(-2).checked_pow(255), Some(I256::MIN));
+        assert_eq!(i(2).checked_pow(255), None);
+        assert_eq!(i(-3).checked_pow(3), Some(i(-27)));
+        assert_eq!(i(-2).saturating_pow(257), I256::MIN);
+        assert_eq!(i(-2).saturating_pow(256), I256::MAX);
+        assert_eq!(i(2).wrapping_pow(256), I256::ZERO);
+    }
+
+    #[test]
+    fn test_shifts_and_ordering() {
+        assert_eq!(i(-8) >> 1, i(-4));
+        assert_eq!(i(-1) >> 300, I256::MINUS_ONE);
+        assert_eq!(i(8) >> 300, I256::ZERO);
+        assert_eq!(i(-1) << 255, I256::MIN);
+        assert!(I256::MIN < i(-1));
+        assert!(i(-1) < I256::ZERO);
+        assert!(I256::ZERO < I256::MAX);
+        assert_eq!(I256::MIN.checked_neg(), None);
+        assert_eq!(I256::MIN.saturating_abs(), I256::MAX);
+        assert_eq!(i(-9).signum(), I256::MINUS_ONE);
+    }
+}
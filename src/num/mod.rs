@@ -0,0 +1,38 @@
+//! Fixed-width 256-bit integers shared by the compiler, the VMs and the
+//! standard library.
+//!
+//! [`U256`] is the language's `u256`; [`I256`] is its two's complement
+//! signed counterpart. Both mirror the primitive integer API: the plain
+//! operators panic on overflow, and every operation also comes in
+//! `checked_`, `wrapping_`, `saturating_` and `overflowing_` forms.
+//!
+//! Like `bytecode`, this module is compiled into both binaries, so it only
+//! refers to its own submodules.
+
+mod i256;
+mod u256;
+
+pub use i256::I256;
+pub use u256::U256;
+
+use std::fmt;
+
+/// Why a string could not be parsed as a 256-bit integer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseNumError {
+    Empty,
+    InvalidDigit,
+    OutOfRange,
+}
+
+impl fmt::Display for ParseNumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseNumError::Empty => write!(f, "cannot parse integer from empty string"),
+            ParseNumError::InvalidDigit => write!(f, "invalid digit found in string"),
+            ParseNumError::OutOfRange => write!(f, "number too large to fit in 256 bits"),
+        }
+    }
+}
+
+impl std::error::Error for ParseNumError {}
@@ -0,0 +1,784 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{
+    Add, AddAssign, BitAnd, BitOr, BitXor, Div, DivAssign, Mul, MulAssign, Not, Rem, RemAssign,
+    Shl, Shr, Sub, SubAssign,
+};
+use std::str::FromStr;
+
+use super::ParseNumError;
+
+/// A 256-bit unsigned integer, stored as four little-endian `u64` limbs
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MIN: U256 = U256::ZERO;
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+    pub const BITS: u32 = 256;
+
+    /// Builds a value from little-endian limbs (`limbs[0]` is least significant)
+    pub const fn from_limbs(limbs: [u64; 4]) -> Self {
+        U256(limbs)
+    }
+
+    pub const fn into_limbs(self) -> [u64; 4] {
+        self.0
+    }
+
+    pub const fn from_u64(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    pub fn to_u64(self) -> Option<u64> {
+        match self.0 {
+            [low, 0, 0, 0] => Some(low),
+            _ => None,
+        }
+    }
+
+    pub fn to_u128(self) -> Option<u128> {
+        match self.0 {
+            [low, high, 0, 0] => Some((high as u128) << 64 | low as u128),
+            _ => None,
+        }
+    }
+
+    /// Whether bit `index` (0 is least significant) is set
+    pub fn bit(&self, index: u32) -> bool {
+        index < Self::BITS && (self.0[(index / 64) as usize] >> (index % 64)) & 1 == 1
+    }
+
+    fn set_bit(&mut self, index: u32) {
+        self.0[(index / 64) as usize] |= 1 << (index % 64);
+    }
+
+    /// Number of bits needed to represent the value
+    pub fn bits(&self) -> u32 {
+        Self::BITS - self.leading_zeros()
+    }
+
+    pub fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for limb in self.0.iter().rev() {
+            zeros += limb.leading_zeros();
+            if *limb != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+
+    pub fn trailing_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for limb in &self.0 {
+            zeros += limb.trailing_zeros();
+            if *limb != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+
+    pub fn count_ones(&self) -> u32 {
+        self.0.iter().map(|limb| limb.count_ones()).sum()
+    }
+
+    pub fn count_zeros(&self) -> u32 {
+        Self::BITS - self.count_ones()
+    }
+
+    // Byte conversions
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0; 4];
+        for (limb, chunk) in limbs.iter_mut().rev().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_be_bytes(chunk.try_into().expect("chunks are 8 bytes"));
+        }
+        U256(limbs)
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            *limb = u64::from_le_bytes(chunk.try_into().expect("chunks are 8 bytes"));
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0.iter().rev()) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(&self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Reads a big-endian value of at most 32 bytes, such as a trimmed ABI word
+    pub fn from_be_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > 32 {
+            return None;
+        }
+        let mut padded = [0; 32];
+        padded[32 - bytes.len()..].copy_from_slice(bytes);
+        Some(Self::from_be_bytes(padded))
+    }
+
+    /// Parses digits in `radix` (2 to 36), without sign or prefix
+    pub fn from_str_radix(src: &str, radix: u32) -> Result<Self, ParseNumError> {
+        assert!((2..=36).contains(&radix), "radix must be in 2..=36, got {}", radix);
+        if src.is_empty() {
+            return Err(ParseNumError::Empty);
+        }
+        let radix_value = U256::from(radix);
+        let mut value = U256::ZERO;
+        for c in src.chars() {
+            let digit = c.to_digit(radix).ok_or(ParseNumError::InvalidDigit)?;
+            value = value.checked_mul(radix_value)
+                .and_then(|v| v.checked_add(U256::from(digit)))
+                .ok_or(ParseNumError::OutOfRange)?;
+        }
+        Ok(value)
+    }
+
+    pub fn from_dec_str(src: &str) -> Result<Self, ParseNumError> {
+        Self::from_str_radix(src, 10)
+    }
+
+    // Overflowing arithmetic: the wrapped result and whether it wrapped
+
+    pub fn overflowing_add(self, other: U256) -> (U256, bool) {
+        let mut limbs = [0; 4];
+        let mut carry = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (sum, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        (U256(limbs), carry)
+    }
+
+    pub fn overflowing_sub(self, other: U256) -> (U256, bool) {
+        let mut limbs = [0; 4];
+        let mut borrow = false;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let (diff, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            *limb = diff;
+            borrow = b1 || b2;
+        }
+        (U256(limbs), borrow)
+    }
+
+    pub fn overflowing_mul(self, other: U256) -> (U256, bool) {
+        let product = self.full_mul(other);
+        let low = U256([product[0], product[1], product[2], product[3]]);
+        (low, product[4..].iter().any(|limb| *limb != 0))
+    }
+
+    pub fn overflowing_pow(self, mut exp: u32) -> (U256, bool) {
+        let mut base = self;
+        let mut acc = U256::ONE;
+        let mut overflow = false;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                let (product, o) = acc.overflowing_mul(base);
+                acc = product;
+                overflow |= o;
+            }
+            exp >>= 1;
+            // Only square when the square is still going to be used
+            if exp > 0 {
+                let (square, o) = base.overflowing_mul(base);
+                base = square;
+                overflow |= o;
+            }
+        }
+        (acc, overflow)
+    }
+
+    // Checked arithmetic: `None` on overflow or division by zero
+
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        match self.overflowing_add(other) {
+            (sum, false) => Some(sum),
+            _ => None,
+        }
+    }
+
+    pub fn checked_sub(self, other: U256) -> Option<U256> {
+        match self.overflowing_sub(other) {
+            (diff, false) => Some(diff),
+            _ => None,
+        }
+    }
+
+    pub fn checked_mul(self, other: U256) -> Option<U256> {
+        match self.overflowing_mul(other) {
+            (product, false) => Some(product),
+            _ => None,
+        }
+    }
+
+    pub fn checked_div(self, other: U256) -> Option<U256> {
+        if other.is_zero() {
+            None
+        } else {
+            Some(self.div_rem(other).0)
+        }
+    }
+
+    pub fn checked_rem(self, other: U256) -> Option<U256> {
+        if other.is_zero() {
+            None
+        } else {
+            Some(self.div_rem(other).1)
+        }
+    }
+
+    pub fn checked_pow(self, exp: u32) -> Option<U256> {
+        match self.overflowing_pow(exp) {
+            (power, false) => Some(power),
+            _ => None,
+        }
+    }
+
+    /// `None` when `shift` is 256 or more
+    pub fn checked_shl(self, shift: u32) -> Option<U256> {
+        (shift < Self::BITS).then(|| self << shift)
+    }
+
+    /// `None` when `shift` is 256 or more
+    pub fn checked_shr(self, shift: u32) -> Option<U256> {
+        (shift < Self::BITS).then(|| self >> shift)
+    }
+
+    // Wrapping arithmetic: modulo 2^256
+
+    pub fn wrapping_add(self, other: U256) -> U256 {
+        self.overflowing_add(other).0
+    }
+
+    pub fn wrapping_sub(self, other: U256) -> U256 {
+        self.overflowing_sub(other).0
+    }
+
+    pub fn wrapping_mul(self, other: U256) -> U256 {
+        self.overflowing_mul(other).0
+    }
+
+    /// Unsigned division cannot wrap; panics when `other` is zero
+    pub fn wrapping_div(self, other: U256) -> U256 {
+        self / other
+    }
+
+    /// Unsigned remainder cannot wrap; panics when `other` is zero
+    pub fn wrapping_rem(self, other: U256) -> U256 {
+        self % other
+    }
+
+    pub fn wrapping_pow(self, exp: u32) -> U256 {
+        self.overflowing_pow(exp).0
+    }
+
+    pub fn wrapping_neg(self) -> U256 {
+        U256::ZERO.wrapping_sub(self)
+    }
+
+    /// Shifts by `shift % 256`, like the primitive `wrapping_shl`
+    pub fn wrapping_shl(self, shift: u32) -> U256 {
+        self << (shift % Self::BITS)
+    }
+
+    /// Shifts by `shift % 256`, like the primitive `wrapping_shr`
+    pub fn wrapping_shr(self, shift: u32) -> U256 {
+        self >> (shift % Self::BITS)
+    }
+
+    // Saturating arithmetic: clamped to `MIN..=MAX`
+
+    pub fn saturating_add(self, other: U256) -> U256 {
+        self.checked_add(other).unwrap_or(U256::MAX)
+    }
+
+    pub fn saturating_sub(self, other: U256) -> U256 {
+        self.checked_sub(other).unwrap_or(U256::MIN)
+    }
+
+    pub fn saturating_mul(self, other: U256) -> U256 {
+        self.checked_mul(other).unwrap_or(U256::MAX)
+    }
+
+    /// Unsigned division cannot overflow; panics when `other` is zero
+    pub fn saturating_div(self, other: U256) -> U256 {
+        self / other
+    }
+
+    /// Unsigned remainder cannot overflow; panics when `other` is zero
+    pub fn saturating_rem(self, other: U256) -> U256 {
+        self % other
+    }
+
+    pub fn saturating_pow(self, exp: u32) -> U256 {
+        self.checked_pow(exp).unwrap_or(U256::MAX)
+    }
+
+    // Modular arithmetic with a full-width intermediate
+
+    /// `(self + other) % modulus` without overflowing; `None` when `modulus` is zero
+    pub fn addmod(self, other: U256, modulus: U256) -> Option<U256> {
+        let a = self.checked_rem(modulus)?;
+        let b = other % modulus;
+        // a + b < 2 * modulus, so at most one subtraction is needed
+        let (sum, carry) = a.overflowing_add(b);
+        Some(if carry || sum >= modulus { sum.wrapping_sub(modulus) } else { sum })
+    }
+
+    /// `(self * other) % modulus` using the 512-bit product; `None` when `modulus` is zero
+    pub fn mulmod(self, other: U256, modulus: U256) -> Option<U256> {
+        if modulus.is_zero() {
+            return None;
+        }
+        let product = self.full_mul(other);
+        Some(long_divide(&product, modulus, &mut [0; 8]))
+    }
+
+    /// Integer square root, rounded down
+    pub fn isqrt(self) -> U256 {
+        if self.is_zero() {
+            return U256::ZERO;
+        }
+        // Newton's method from ceil(self / 2), which is never below the root
+        let mut x = self;
+        let mut y = (self >> 1) + (self & U256::ONE);
+        while y < x {
+            x = y;
+            y = (x + self / x) >> 1;
+        }
+        x
+    }
+
+    fn full_mul(self, other: U256) -> [u64; 8] {
+        let mut product = [0u64; 8];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, &b) in other.0.iter().enumerate() {
+                // At most (2^64 - 1)^2 + 2 * (2^64 - 1), which fits in a u128
+                let t = a as u128 * b as u128 + product[i + j] as u128 + carry;
+                product[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            product[i + 4] = carry as u64;
+        }
+        product
+    }
+
+    /// Quotient and remainder; `divisor` must be non-zero
+    fn div_rem(self, divisor: U256) -> (U256, U256) {
+        if self < divisor {
+            return (U256::ZERO, self);
+        }
+        if let (Some(a), Some(b)) = (self.to_u128(), divisor.to_u128()) {
+            return (U256::from(a / b), U256::from(a % b));
+        }
+        let mut quotient = [0; 4];
+        let remainder = long_divide(&self.0, divisor, &mut quotient);
+        (U256(quotient), remainder)
+    }
+}
+
+/// Binary long division of a little-endian limb slice by a non-zero
+/// `divisor`. Writes the quotient into `quotient` (as long as `dividend`)
+/// and returns the remainder.
+fn long_divide(dividend: &[u64], divisor: U256, quotient: &mut [u64]) -> U256 {
+    let mut remainder = U256::ZERO;
+    for bit in (0..dividend.len() as u32 * 64).rev() {
+        let carry = remainder.bit(U256::BITS - 1);
+        remainder = remainder << 1;
+        if (dividend[(bit / 64) as usize] >> (bit % 64)) & 1 == 1 {
+            remainder.set_bit(0);
+        }
+        // remainder < divisor before the shift, so one subtraction suffices
+        if carry || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+    remainder
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+macro_rules! impl_from_unsigned {
+    ($($t:ty),*) => {
+        $(impl From<$t> for U256 {
+            fn from(value: $t) -> Self {
+                U256::from_u64(value as u64)
+            }
+        })*
+    };
+}
+
+impl_from_unsigned!(u8, u16, u32, u64, usize);
+
+impl From<bool> for U256 {
+    fn from(value: bool) -> Self {
+        U256::from_u64(value as u64)
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(value: u128) -> Self {
+        U256([value as u64, (value >> 64) as u64, 0, 0])
+    }
+}
+
+// Operators panic on overflow and division by zero, like primitives in debug builds
+
+macro_rules! impl_checked_op {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $checked:ident, $msg:literal) => {
+        impl $trait for U256 {
+            type Output = U256;
+
+            fn $method(self, rhs: U256) -> U256 {
+                self.$checked(rhs).expect($msg)
+            }
+        }
+
+        impl $assign_trait for U256 {
+            fn $assign_method(&mut self, rhs: U256) {
+                *self = $trait::$method(*self, rhs);
+            }
+        }
+    };
+}
+
+impl_checked_op!(Add, add, AddAssign, add_assign, checked_add, "attempt to add with overflow");
+impl_checked_op!(Sub, sub, SubAssign, sub_assign, checked_sub, "attempt to subtract with overflow");
+impl_checked_op!(Mul, mul, MulAssign, mul_assign, checked_mul, "attempt to multiply with overflow");
+impl_checked_op!(Div, div, DivAssign, div_assign, checked_div, "attempt to divide by zero");
+impl_checked_op!(Rem, rem, RemAssign, rem_assign, checked_rem, "attempt to calculate the remainder with a divisor of zero");
+
+impl BitAnd for U256 {
+    type Output = U256;
+
+    fn bitand(self, rhs: U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] & rhs.0[i]))
+    }
+}
+
+impl BitOr for U256 {
+    type Output = U256;
+
+    fn bitor(self, rhs: U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] | rhs.0[i]))
+    }
+}
+
+impl BitXor for U256 {
+    type Output = U256;
+
+    fn bitxor(self, rhs: U256) -> U256 {
+        U256(std::array::from_fn(|i| self.0[i] ^ rhs.0[i]))
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|limb| !limb))
+    }
+}
+
+/// Shifting by 256 or more yields zero, as in the EVM
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        if shift >= Self::BITS {
+            return U256::ZERO;
+        }
+        let words = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate().skip(words) {
+            *limb = self.0[i - words] << bits;
+            if bits > 0 && i > words {
+                *limb |= self.0[i - words - 1] >> (64 - bits);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+/// Shifting by 256 or more yields zero, as in the EVM
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        if shift >= Self::BITS {
+            return U256::ZERO;
+        }
+        let words = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate().take(4 - words) {
+            *limb = self.0[i + words] >> bits;
+            if bits > 0 && i + words + 1 < 4 {
+                *limb |= self.0[i + words + 1] << (64 - bits);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+/// Decimal, or hexadecimal with a `0x` prefix
+impl FromStr for U256 {
+    type Err = ParseNumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => U256::from_str_radix(hex, 16),
+            None => U256::from_str_radix(s, 10),
+        }
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off 19 decimal digits at a time, the most that fit in a u64
+        const CHUNK: U256 = U256::from_u64(10_000_000_000_000_000_000);
+        let mut chunks = Vec::new();
+        let mut rest = *self;
+        loop {
+            let (quotient, remainder) = rest.div_rem(CHUNK);
+            chunks.push(remainder.0[0]);
+            if quotient.is_zero() {
+                break;
+            }
+            rest = quotient;
+        }
+        let mut digits = chunks.pop().expect("at least one chunk").to_string();
+        for chunk in chunks.iter().rev() {
+            digits.push_str(&format!("{:019}", chunk));
+        }
+        f.pad_integral(true, "", &digits)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl U256 {
+    fn hex_digits(&self, upper: bool) -> String {
+        let mut limbs = self.0.iter().rev().skip_while(|limb| **limb == 0);
+        let mut digits = match limbs.next() {
+            Some(limb) if upper => format!("{:X}", limb),
+            Some(limb) => format!("{:x}", limb),
+            None => return "0".into(),
+        };
+        for limb in limbs {
+            if upper {
+                digits.push_str(&format!("{:016X}", limb));
+            } else {
+                digits.push_str(&format!("{:016x}", limb));
+            }
+        }
+        digits
+    }
+}
+
+impl fmt::LowerHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad_integral(true, "0x", &self.hex_digits(false))
+    }
+}
+
+impl fmt::UpperHex for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad_integral(true, "0x", &self.hex_digits(true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u(n: u128) -> U256 {
+        U256::from(n)
+    }
+
+    fn pow2(exp: u32) -> U256 {
+        U256::ONE << exp
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(max.parse::<U256>(), Ok(U256::MAX));
+        assert_eq!(U256::MAX.to_string(), max);
+        assert_eq!(U256::ZERO.to_string(), "0");
+        assert_eq!(u(10_000_000_000_000_000_000).to_string(), "10000000000000000000");
+        assert_eq!("0xff".parse::<U256>(), Ok(u(255)));
+        assert_eq!(format!("{:x}", pow2(64)), "10000000000000000");
+        assert_eq!(format!("{:#X}", u(255)), "0xFF");
+        assert_eq!(format!("{:>5}", u(42)), "   42");
+
+        // One past MAX
+        let too_big = "115792089237316195423570985008687907853269984665640564039457584007913129639936";
+        assert_eq!(too_big.parse::<U256>(), Err(ParseNumError::OutOfRange));
+        assert_eq!("".parse::<U256>(), Err(ParseNumError::Empty));
+        assert_eq!("12a".parse::<U256>(), Err(ParseNumError::InvalidDigit));
+        assert_eq!("-1".parse::<U256>(), Err(ParseNumError::InvalidDigit));
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let value: U256 = "0x0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20".parse().unwrap();
+        let be = value.to_be_bytes();
+        assert_eq!(be[0], 0x01);
+        assert_eq!(be[31], 0x20);
+        assert_eq!(U256::from_be_bytes(be), value);
+        assert_eq!(U256::from_le_bytes(value.to_le_bytes()), value);
+        assert_eq!(U256::from_be_slice(&[1, 0]), Some(u(256)));
+        assert_eq!(U256::from_be_slice(&[0; 33]), None);
+    }
+
+    #[test]
+    fn test_add_and_sub() {
+        assert_eq!(u(u64::MAX as u128) + U256::ONE, pow2(64));
+        assert_eq!(U256::MAX.overflowing_add(U256::ONE), (U256::ZERO, true));
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::MAX.wrapping_add(u(2)), U256::ONE);
+        assert_eq!(U256::MAX.saturating_add(U256::ONE), U256::MAX);
+
+        assert_eq!(pow2(64) - U256::ONE, u(u64::MAX as u128));
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+        assert_eq!(U256::ZERO.wrapping_sub(U256::ONE), U256::MAX);
+        assert_eq!(U256::ONE.saturating_sub(u(2)), U256::ZERO);
+    }
+
+    #[test]
+    #[should_panic(expected = "attempt to add with overflow")]
+    fn test_add_operator_panics_on_overflow() {
+        let _ = U256::MAX + U256::ONE;
+    }
+
+    #[test]
+    fn test_mul() {
+        // Token amounts: 1e18 * a billion tokens fits comfortably
+        let wei = u(1_000_000_000_000_000_000);
+        let supply = u(1_000_000_000);
+        assert_eq!((wei * supply).to_string(), "1000000000000000000000000000");
+        assert_eq!(pow2(128) * pow2(127), pow2(255));
+        assert_eq!(pow2(128).checked_mul(pow2(128)), None);
+        assert_eq!(pow2(128).wrapping_mul(pow2(128)), U256::ZERO);
+        assert_eq!(pow2(200).saturating_mul(pow2(100)), U256::MAX);
+        assert_eq!(U256::MAX.wrapping_mul(U256::MAX), U256::ONE);
+    }
+
+    #[test]
+    fn test_div_and_rem() {
+        assert_eq!(u(7) / u(2), u(3));
+        assert_eq!(u(7) % u(2), U256::ONE);
+        assert_eq!(U256::MAX / U256::MAX, U256::ONE);
+        assert_eq!(U256::MAX / pow2(255), U256::ONE);
+        assert_eq!(U256::MAX % pow2(255), pow2(255) - U256::ONE);
+        assert_eq!(pow2(200) / pow2(100), pow2(100));
+        let big = pow2(250) + u(12345);
+        assert_eq!((big / u(1000)) * u(1000) + big % u(1000), big);
+        assert_eq!(u(1).checked_div(U256::ZERO), None);
+        assert_eq!(u(1).checked_rem(U256::ZERO), None);
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(u(10).checked_pow(18), Some(u(1_000_000_000_000_000_000)));
+        assert_eq!(u(2).checked_pow(255), Some(pow2(255)));
+        assert_eq!(u(2).checked_pow(256), None);
+        assert_eq!(u(2).wrapping_pow(256), U256::ZERO);
+        assert_eq!(u(2).saturating_pow(300), U256::MAX);
+        assert_eq!(U256::ZERO.checked_pow(0), Some(U256::ONE));
+        assert_eq!(U256::ONE.checked_pow(u32::MAX), Some(U256::ONE));
+    }
+
+    #[test]
+    fn test_shifts() {
+        assert_eq!(U256::ONE << 255 >> 255, U256::ONE);
+        assert_eq!(U256::ONE << 256, U256::ZERO);
+        assert_eq!(U256::MAX >> 300, U256::ZERO);
+        assert_eq!(u(0b1011) << 70 >> 70, u(0b1011));
+        assert_eq!(U256::MAX >> 192, u(u64::MAX as u128));
+        assert_eq!(U256::ONE.checked_shl(256), None);
+        assert_eq!(U256::ONE.wrapping_shl(257), u(2));
+    }
+
+    #[test]
+    fn test_addmod_and_mulmod() {
+        let m = U256::MAX - U256::ONE;
+        // MAX + MAX overflows 256 bits but the modular sum does not
+        assert_eq!(U256::MAX.addmod(U256::MAX, m), Some(u(2)));
+        assert_eq!(u(5).addmod(u(6), u(7)), Some(u(4)));
+        assert_eq!(U256::MAX.mulmod(U256::MAX, m), Some(U256::ONE));
+        // 2^400 = 2^255 * 2^145, and 2^255 is -1 modulo 2^255 + 1
+        let m = pow2(255) + U256::ONE;
+        assert_eq!(pow2(200).mulmod(pow2(200), m), Some(m - pow2(145)));
+        assert_eq!(u(10).mulmod(u(10), u(7)), Some(u(2)));
+        assert_eq!(u(1).addmod(u(1), U256::ZERO), None);
+        assert_eq!(u(1).mulmod(u(1), U256::ZERO), None);
+    }
+
+    #[test]
+    fn test_bit_queries() {
+        assert_eq!(pow2(100).leading_zeros(), 155);
+        assert_eq!(pow2(100).trailing_zeros(), 100);
+        assert_eq!(U256::ZERO.leading_zeros(), 256);
+        assert_eq!(U256::ZERO.trailing_zeros(), 256);
+        assert_eq!(U256::MAX.count_ones(), 256);
+        assert_eq!(pow2(100).bits(), 101);
+        assert!(pow2(100).bit(100));
+        assert_eq!(!U256::ZERO, U256::MAX);
+        assert_eq!(u(0b1100) & u(0b1010), u(0b1000));
+        assert_eq!(u(0b1100) | u(0b1010), u(0b1110));
+        assert_eq!(u(0b1100) ^ u(0b1010), u(0b0110));
+    }
+
+    #[test]
+    fn test_ordering_and_isqrt() {
+        assert!(pow2(192) > u(u128::MAX));
+        assert!(U256::ZERO < U256::ONE);
+        assert_eq!(u(17).isqrt(), u(4));
+        assert_eq!(u(16).isqrt(), u(4));
+        assert_eq!(U256::MAX.isqrt(), u(u128::MAX));
+        assert_eq!(pow2(254).isqrt(), pow2(127));
+    }
+}
@@ -1,13 +1,23 @@
 use crate::core::{Error, Result};
+use crate::num::{I256, U256};
 
 // Safe numeric conversions
-pub fn to_u256(value: &[u8]) -> Option<u256> {
+pub fn to_u256(value: &[u8]) -> Option<U256> {
     if value.len() != 32 {
         return None;
     }
     let mut bytes = [0u8; 32];
     bytes.copy_from_slice(value);
-    Some(u256::from_be_bytes(bytes))
+    Some(U256::from_be_bytes(bytes))
+}
+
+pub fn to_i256(value: &[u8]) -> Option<I256> {
+    if value.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(value);
+    Some(I256::from_be_bytes(bytes))
 }
 
 pub fn to_u128(value: &[u8]) -> Option<u128> {
@@ -37,24 +37,22 @@ pub trait Serialize {
 
 // Basic numeric operations with overflow checking
 pub mod math {
-    pub fn checked_add(a: u256, b: u256) -> Option<u256> {
+    use crate::num::U256;
+
+    pub fn checked_add(a: U256, b: U256) -> Option<U256> {
         a.checked_add(b)
     }
 
-    pub fn checked_sub(a: u256, b: u256) -> Option<u256> {
+    pub fn checked_sub(a: U256, b: U256) -> Option<U256> {
         a.checked_sub(b)
     }
 
-    pub fn checked_mul(a: u256, b: u256) -> Option<u256> {
+    pub fn checked_mul(a: U256, b: U256) -> Option<U256> {
         a.checked_mul(b)
     }
 
-    pub fn checked_div(a: u256, b: u256) -> Option<u256> {
-        if b == 0 {
-            None
-        } else {
-            Some(a / b)
-        }
+    pub fn checked_div(a: U256, b: U256) -> Option<U256> {
+        a.checked_div(b)
     }
 }
 
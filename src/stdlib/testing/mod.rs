@@ -1,5 +1,5 @@
 use crate::core::{Result, Error};
-use crate::runtime::VM;
+use stremax_runtime::{Address, Value, Vm};
 use std::collections::HashMap;
 
 /// Test runner for executing test cases
@@ -12,13 +12,13 @@ pub struct TestRunner {
 /// Represents a single test case
 pub struct TestCase {
     name: String,
-    test_fn: Box<dyn Fn(&mut VM) -> Result<()>>,
+    test_fn: Box<dyn Fn(&mut Vm) -> Result<()>>,
     properties: Vec<Box<dyn Property>>,
 }
 
 /// Trait for property-based testing
 pub trait Property {
-    fn check(&self, vm: &mut VM) -> Result<bool>;
+    fn check(&self, vm: &mut Vm) -> Result<bool>;
     fn shrink(&self) -> Option<Box<dyn Property>>;
 }
 
@@ -45,7 +45,7 @@ impl TestRunner {
     
     pub fn add_test<F>(&mut self, name: &str, test_fn: F)
     where
-        F: Fn(&mut VM) -> Result<()> + 'static,
+        F: Fn(&mut Vm) -> Result<()> + 'static,
     {
         self.tests.push(TestCase {
             name: name.to_string(),
@@ -77,7 +77,7 @@ impl TestRunner {
             }
             
             // Run test
-            let mut vm = VM::new(Vec::new(), 1_000_000);
+            let mut vm = Vm::new();
             match (test.test_fn)(&mut vm) {
                 Ok(()) => {
                     results.add_success(&test.name);
@@ -170,10 +170,10 @@ pub struct NonNegativeBalance {
 }
 
 impl Property for NonNegativeBalance {
-    fn check(&self, vm: &mut VM) -> Result<bool> {
-        // Check that balance is never negative
-        let balance = vm.get_balance(&self.address)?;
-        Ok(balance >= 0)
+    fn check(&self, vm: &mut Vm) -> Result<bool> {
+        // Balances are unsigned and an overdraft traps and rolls back, so
+        // an account can only go wrong by disappearing from the books
+        Ok(vm.balances().contains_key(&self.address))
     }
     
     fn shrink(&self) -> Option<Box<dyn Property>> {
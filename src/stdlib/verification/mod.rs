@@ -1,5 +1,6 @@
 use crate::core::{Result, Error};
-use crate::runtime::VM;
+use stremax_runtime::num::U256;
+use stremax_runtime::Vm;
 use std::collections::HashMap;
 
 /// Represents a formal proof
@@ -36,7 +37,7 @@ pub enum Justification {
 /// Trait for verifiable properties
 pub trait Verifiable {
     fn to_proposition(&self) -> Proposition;
-    fn verify(&self, vm: &VM) -> Result<bool>;
+    fn verify(&self, vm: &Vm) -> Result<bool>;
 }
 
 /// Contract invariant checker
@@ -55,7 +56,7 @@ impl InvariantChecker {
         self.invariants.push(Box::new(invariant));
     }
     
-    pub fn check_all(&self, vm: &VM) -> Result<bool> {
+    pub fn check_all(&self, vm: &Vm) -> Result<bool> {
         for invariant in &self.invariants {
             if !invariant.verify(vm)? {
                 return Ok(false);
@@ -129,7 +130,7 @@ impl TheoremProver {
 
 // Example invariants
 pub struct BalanceInvariant {
-    min_balance: i64,
+    min_balance: U256,
 }
 
 impl Verifiable for BalanceInvariant {
@@ -140,14 +141,9 @@ impl Verifiable for BalanceInvariant {
         )
     }
     
-    fn verify(&self, vm: &VM) -> Result<bool> {
+    fn verify(&self, vm: &Vm) -> Result<bool> {
         // Check that all balances are above minimum
-        for (_, balance) in vm.get_all_balances()? {
-            if balance < self.min_balance {
-                return Ok(false);
-            }
-        }
-        Ok(true)
+        Ok(vm.balances().values().all(|balance| *balance >= self.min_balance))
     }
 }
 
@@ -1,17 +1,24 @@
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
-use crate::vm::{VM, Value, Contract};
-use crate::stdlib::core::{Error, Result, events::Event};
-use crate::stdlib::testing::{TestRunner, TestCase, Mock, Fixture};
+use sha2::{Digest, Sha256};
+use stremax_runtime::bytecode::Module;
+use stremax_runtime::num::U256;
+use stremax_runtime::{Address, Environment, Event, StorageProof, Trace, TraceHandle, TraceKind, Value, Vm};
+use crate::core::{Error, Result};
+use crate::testing::vm::trap_error;
 
 /// Blockchain test environment configuration
 #[derive(Debug, Clone)]
 pub struct BlockchainTestConfig {
     pub block_time: u64,
     pub gas_limit: u64,
-    pub memory_limit: usize,
     pub chain_id: u64,
     pub network_type: NetworkType,
+    /// Interpreted calls before a contract function is compiled, as in
+    /// [`Vm::set_jit_threshold`]
+    pub jit_threshold: Option<u32>,
+    /// Trace every transaction with a built-in tracer, for
+    /// [`BlockchainTestEnvironment::last_trace`]
+    pub trace: Option<TraceKind>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,9 +34,10 @@ impl Default for BlockchainTestConfig {
         BlockchainTestConfig {
             block_time: 15,
             gas_limit: 10_000_000,
-            memory_limit: 1024 * 1024 * 10, // 10MB
             chain_id: 1,
             network_type: NetworkType::Local,
+            jit_threshold: Some(stremax_runtime::DEFAULT_JIT_THRESHOLD),
+            trace: None,
         }
     }
 }
@@ -37,22 +45,26 @@ impl Default for BlockchainTestConfig {
 /// Account for blockchain testing
 #[derive(Debug, Clone)]
 pub struct Account {
-    pub address: [u8; 20],
-    pub balance: u64,
+    pub address: Address,
+    pub balance: U256,
     pub nonce: u64,
-    pub code: Option<Vec<u8>>,
-    pub storage: HashMap<[u8; 32], [u8; 32]>,
+    /// Name of the contract deployed at the address, if any
+    pub contract: Option<String>,
 }
 
-/// Blockchain test environment
+/// Blockchain test environment. Contracts run in the same [`Vm`] as
+/// `strxvm`; the environment adds accounts, nonces and blocks around it.
 pub struct BlockchainTestEnvironment {
     config: BlockchainTestConfig,
-    vm: VM,
-    accounts: HashMap<[u8; 20], Account>,
-    events: Vec<Event>,
+    vm: Vm,
+    nonces: HashMap<Address, u64>,
     blocks: Vec<Block>,
     current_block: u64,
     timestamp: u64,
+    /// Nonces, blocks and time when each open fork began; the VM keeps
+    /// the rest
+    forks: Vec<(HashMap<Address, u64>, Vec<Block>, u64)>,
+    trace: Option<Trace>,
 }
 
 #[derive(Debug, Clone)]
@@ -60,72 +72,93 @@ pub struct Block {
     pub number: u64,
     pub timestamp: u64,
     pub transactions: Vec<Transaction>,
+    /// Root of the contract storage after the block's last transaction
     pub state_root: [u8; 32],
+    /// Zero for the genesis block
     pub parent_hash: [u8; 32],
 }
 
+impl Block {
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.number.to_be_bytes());
+        hasher.update(self.timestamp.to_be_bytes());
+        hasher.update(self.parent_hash);
+        hasher.update(self.state_root);
+        for tx in &self.transactions {
+            hasher.update(tx.hash);
+        }
+        hasher.finalize().into()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub hash: [u8; 32],
-    pub from: [u8; 20],
-    pub to: Option<[u8; 20]>,
-    pub value: u64,
-    pub data: Vec<u8>,
+    pub from: Address,
+    /// The contract called, or `None` for a deployment
+    pub to: Option<Address>,
+    pub function: String,
+    pub args: Vec<Value>,
     pub gas_limit: u64,
-    pub gas_price: u64,
+    pub gas_used: u64,
     pub nonce: u64,
 }
 
 impl BlockchainTestEnvironment {
     pub fn new(config: BlockchainTestConfig) -> Result<Self> {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(config.jit_threshold);
         let mut env = BlockchainTestEnvironment {
             config,
-            vm: VM::new()?,
-            accounts: HashMap::new(),
-            events: Vec::new(),
+            vm,
+            nonces: HashMap::new(),
             blocks: Vec::new(),
             current_block: 0,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            forks: Vec::new(),
+            trace: None,
         };
-        
+
         env.setup_genesis_block()?;
         env.setup_default_accounts()?;
         Ok(env)
     }
 
-    pub fn deploy_contract(&mut self, contract: Contract, sender: [u8; 20]) -> Result<[u8; 20]> {
-        let address = self.generate_contract_address(sender, self.get_nonce(sender));
-        let tx = Transaction {
-            hash: [0; 32], // Will be computed
+    pub fn deploy_contract(&mut self, contract: Module, sender: Address) -> Result<Address> {
+        let nonce = self.get_nonce(sender);
+        let address = self.generate_contract_address(sender, nonce);
+        self.vm.deploy(address, contract).map_err(Error::ContractError)?;
+        self.record_transaction(Transaction {
+            hash: [0; 32], // Computed when recorded
             from: sender,
             to: None,
-            value: 0,
-            data: contract.bytecode,
+            function: String::new(),
+            args: Vec::new(),
             gas_limit: self.config.gas_limit,
-            gas_price: 1,
-            nonce: self.get_nonce(sender),
-        };
-        
-        self.execute_transaction(tx)?;
+            gas_used: 0,
+            nonce,
+        });
         Ok(address)
     }
 
-    pub fn call_contract(&mut self, address: [u8; 20], method: &str, args: &[Value], sender: [u8; 20]) -> Result<Value> {
-        let data = self.encode_contract_call(method, args)?;
+    /// Calls `method` of the contract at `address` as `sender`. A trap
+    /// rolls the call back and is returned as the error.
+    pub fn call_contract(&mut self, address: Address, method: &str, args: &[Value], sender: Address) -> Result<Value> {
         let tx = Transaction {
-            hash: [0; 32], // Will be computed
+            hash: [0; 32], // Computed when recorded
             from: sender,
             to: Some(address),
-            value: 0,
-            data,
+            function: method.to_string(),
+            args: args.to_vec(),
             gas_limit: self.config.gas_limit,
-            gas_price: 1,
+            gas_used: 0,
             nonce: self.get_nonce(sender),
         };
-        
+
         self.execute_transaction(tx)
     }
 
@@ -141,41 +174,83 @@ impl BlockchainTestEnvironment {
         Ok(())
     }
 
-    pub fn get_balance(&self, address: [u8; 20]) -> u64 {
-        self.accounts.get(&address).map(|a| a.balance).unwrap_or(0)
+    /// Forks the chain: everything until the matching [`discard_fork`]
+    /// (contract storage, balances, events, nonces, blocks and time) is
+    /// thrown away by it. Forks nest.
+    ///
+    /// [`discard_fork`]: BlockchainTestEnvironment::discard_fork
+    pub fn fork(&mut self) {
+        self.vm.begin();
+        self.forks.push((self.nonces.clone(), self.blocks.clone(), self.timestamp));
     }
 
-    pub fn set_balance(&mut self, address: [u8; 20], balance: u64) -> Result<()> {
-        if let Some(account) = self.accounts.get_mut(&address) {
-            account.balance = balance;
-        } else {
-            self.accounts.insert(address, Account {
-                address,
-                balance,
-                nonce: 0,
-                code: None,
-                storage: HashMap::new(),
-            });
-        }
+    pub fn discard_fork(&mut self) -> Result<()> {
+        let (nonces, blocks, timestamp) = self.forks.pop()
+            .ok_or_else(|| Error::RuntimeError("no fork to discard".into()))?;
+        self.vm.rollback();
+        self.nonces = nonces;
+        self.current_block = blocks.len() as u64 - 1;
+        self.blocks = blocks;
+        self.timestamp = timestamp;
         Ok(())
     }
 
+    pub fn get_balance(&self, address: Address) -> U256 {
+        self.vm.balances().get(&address).copied().unwrap_or(U256::ZERO)
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: U256) -> Result<()> {
+        self.vm.set_balance(address, balance);
+        Ok(())
+    }
+
+    pub fn get_account(&self, address: Address) -> Account {
+        Account {
+            address,
+            balance: self.get_balance(address),
+            nonce: self.get_nonce(address),
+            contract: self.vm.module(&address).map(|module| module.name.clone()),
+        }
+    }
+
     pub fn get_events(&self) -> &[Event] {
-        &self.events
+        self.vm.events()
     }
 
     pub fn get_current_block(&self) -> &Block {
         &self.blocks[self.current_block as usize]
     }
 
+    /// Root of the contract storage as it is now
+    pub fn state_root(&self) -> [u8; 32] {
+        self.vm.state_tree().root()
+    }
+
+    /// Proves what a storage slot holds against [`state_root`], for
+    /// checking without the rest of the state
+    ///
+    /// [`state_root`]: BlockchainTestEnvironment::state_root
+    pub fn prove_storage(&self, address: Address, slot: U256) -> StorageProof {
+        self.vm.state_tree().prove(&address, &slot)
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// The trace of the latest transaction, when the environment traces
+    pub fn last_trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
     // Private helper methods
-    
+
     fn setup_genesis_block(&mut self) -> Result<()> {
         let genesis = Block {
             number: 0,
             timestamp: self.timestamp,
             transactions: Vec::new(),
-            state_root: [0; 32],
+            state_root: self.state_root(),
             parent_hash: [0; 32],
         };
         self.blocks.push(genesis);
@@ -187,37 +262,73 @@ impl BlockchainTestEnvironment {
         for i in 0..10 {
             let mut address = [0; 20];
             address[19] = i as u8;
-            self.set_balance(address, 1000_000_000_000_000_000)?; // 1 ETH
+            self.set_balance(address, U256::from(1_000_000_000_000_000_000u64))?; // 1 ETH
         }
         Ok(())
     }
 
-    fn get_nonce(&self, address: [u8; 20]) -> u64 {
-        self.accounts.get(&address).map(|a| a.nonce).unwrap_or(0)
+    fn get_nonce(&self, address: Address) -> u64 {
+        self.nonces.get(&address).copied().unwrap_or(0)
     }
 
-    fn generate_contract_address(&self, sender: [u8; 20], nonce: u64) -> [u8; 20] {
-        use sha3::{Digest, Keccak256};
-        let mut hasher = Keccak256::new();
+    fn generate_contract_address(&self, sender: Address, nonce: u64) -> Address {
+        let mut hasher = Sha256::new();
         hasher.update(sender);
-        hasher.update(&nonce.to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
         let result = hasher.finalize();
         let mut address = [0; 20];
         address.copy_from_slice(&result[12..]);
         address
     }
 
-    fn execute_transaction(&mut self, tx: Transaction) -> Result<Value> {
-        // Validate transaction
-        self.validate_transaction(&tx)?;
-        
+    fn execute_transaction(&mut self, mut tx: Transaction) -> Result<Value> {
+        let to = tx.to.ok_or_else(|| Error::ContractError("a call needs a contract".into()))?;
+
         // Execute in VM
-        let result = self.vm.execute(tx.data.as_slice())?;
-        
+        self.vm.env = Environment {
+            sender: tx.from,
+            origin: tx.from,
+            value: U256::ZERO,
+            timestamp: self.timestamp,
+            block_number: self.get_current_block().number,
+            chain_id: self.config.chain_id,
+        };
+        let trace = self.config.trace.map(TraceHandle::new);
+        if let Some(trace) = &trace {
+            self.vm.set_tracer(Some(trace.tracer()));
+        }
+        let outcome = self.vm.call(&to, &tx.function, tx.args.clone(), tx.gas_limit);
+        if let Some(trace) = trace {
+            self.vm.set_tracer(None);
+            self.trace = Some(trace.finish());
+        }
+        let outcome = outcome.map_err(Error::ContractError)?;
+
         // Update state
-        self.update_state(&tx)?;
-        
-        Ok(result)
+        tx.gas_used = outcome.gas_used;
+        self.record_transaction(tx);
+
+        let result = outcome.result.map_err(trap_error)?;
+        Ok(result.unwrap_or_else(Value::unit))
+    }
+
+    /// Adds `tx` to the current block, bumps its sender's nonce and
+    /// updates the block's state root
+    fn record_transaction(&mut self, mut tx: Transaction) {
+        let mut hasher = Sha256::new();
+        hasher.update(tx.from);
+        hasher.update(tx.nonce.to_be_bytes());
+        hasher.update(tx.to.unwrap_or_default());
+        hasher.update(tx.function.as_bytes());
+        for arg in &tx.args {
+            hasher.update(arg.to_bytes());
+        }
+        tx.hash = hasher.finalize().into();
+        *self.nonces.entry(tx.from).or_insert(0) += 1;
+        let state_root = self.state_root();
+        let current = &mut self.blocks[self.current_block as usize];
+        current.transactions.push(tx);
+        current.state_root = state_root;
     }
 
     fn create_new_block(&mut self, transactions: Vec<Transaction>) -> Result<()> {
@@ -226,48 +337,158 @@ impl BlockchainTestEnvironment {
             number: parent.number + 1,
             timestamp: self.timestamp,
             transactions,
-            state_root: [0; 32], // Would be computed in real implementation
-            parent_hash: [0; 32], // Would be parent block hash
+            state_root: self.state_root(),
+            parent_hash: parent.hash(),
         };
         self.blocks.push(new_block);
         self.current_block += 1;
         Ok(())
     }
+}
 
-    fn validate_transaction(&self, tx: &Transaction) -> Result<()> {
-        // Basic transaction validation
-        let sender = self.accounts.get(&tx.from).ok_or(Error::AccountNotFound)?;
-        
-        if sender.balance < tx.value + (tx.gas_limit * tx.gas_price) {
-            return Err(Error::InsufficientFunds);
-        }
-        
-        if tx.nonce != sender.nonce {
-            return Err(Error::InvalidNonce);
-        }
-        
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stremax_runtime::bytecode::assemble;
+
+    const COUNTER: &str = "\
+.contract Counter
+.const #0 string \"msg.sender\"
+.const #1 string \"zero\"
+.storage 0 count: u256
+.storage 1 last: address
+.export bump(by: u256) -> u256
+.export fail()
+
+.function bump arity=1 locals=1
+    SLOAD 0
+    LOAD 0
+    ADD
+    SSTORE 0
+    ENV #0 ; string \"msg.sender\"
+    SSTORE 1
+    SLOAD 0
+    RET
+
+.function fail arity=0 locals=0
+    REVERT #1 ; string \"zero\"
+";
+
+    fn account(n: u8) -> Address {
+        let mut address = [0; 20];
+        address[19] = n;
+        address
     }
 
-    fn update_state(&mut self, tx: &Transaction) -> Result<()> {
-        // Update sender account
-        if let Some(sender) = self.accounts.get_mut(&tx.from) {
-            sender.nonce += 1;
-            sender.balance -= tx.value + (tx.gas_limit * tx.gas_price);
-        }
-        
-        // Update recipient account if it exists
-        if let Some(to) = tx.to {
-            if let Some(recipient) = self.accounts.get_mut(&to) {
-                recipient.balance += tx.value;
-            }
-        }
-        
-        Ok(())
+    #[test]
+    fn test_contract_calls_run_in_the_vm() {
+        let mut env = BlockchainTestEnvironment::new(BlockchainTestConfig::default()).unwrap();
+        let counter = env.deploy_contract(assemble(COUNTER).unwrap(), account(1)).unwrap();
+        assert_eq!(env.get_account(account(1)).nonce, 1);
+        assert_eq!(env.get_account(counter).contract.as_deref(), Some("Counter"));
+
+        let result = env.call_contract(counter, "bump", &[Value::U256(U256::from(2u64))], account(2)).unwrap();
+        assert_eq!(result, Value::U256(U256::from(2u64)));
+        assert_eq!(env.vm().storage().get(&counter, &U256::ONE), Some(Value::Address(account(2))));
+
+        assert!(matches!(env.call_contract(counter, "fail", &[], account(2)), Err(Error::ContractError(_))));
+        assert_eq!(env.get_account(account(2)).nonce, 2);
+        assert_eq!(env.get_current_block().transactions.len(), 3);
     }
 
-    fn encode_contract_call(&self, method: &str, args: &[Value]) -> Result<Vec<u8>> {
-        // In a real implementation, this would use ABI encoding
-        unimplemented!()
+    #[test]
+    fn test_blocks_commit_to_the_storage() {
+        let mut env = BlockchainTestEnvironment::new(BlockchainTestConfig::default()).unwrap();
+        let genesis = env.get_current_block().clone();
+        let counter = env.deploy_contract(assemble(COUNTER).unwrap(), account(1)).unwrap();
+        env.call_contract(counter, "bump", &[Value::U256(U256::from(2u64))], account(2)).unwrap();
+        let root = env.get_current_block().state_root;
+        assert_ne!(root, genesis.state_root);
+        assert_eq!(root, env.state_root());
+
+        env.advance_blocks(1).unwrap();
+        let block = env.get_current_block();
+        assert_eq!(block.parent_hash, env.blocks[0].hash());
+        assert_ne!(block.parent_hash, genesis.hash());
+        assert_eq!(block.state_root, root);
+
+        // The block header is all a light client needs
+        let count = Value::U256(U256::from(2u64));
+        let proof = env.prove_storage(counter, U256::ZERO);
+        assert!(proof.verify(&block.state_root, &counter, &U256::ZERO, Some(&count)));
+        assert!(!proof.verify(&genesis.state_root, &counter, &U256::ZERO, Some(&count)));
+        let proof = env.prove_storage(counter, U256::from(9u64));
+        assert!(proof.verify(&block.state_root, &counter, &U256::from(9u64), None));
+
+        // A transaction moves only the root of the block it lands in
+        env.call_contract(counter, "bump", &[Value::U256(U256::from(3u64))], account(2)).unwrap();
+        let (earlier, latest) = (&env.blocks[0], env.get_current_block());
+        assert_eq!(earlier.state_root, root);
+        assert_ne!(latest.state_root, root);
+        let count = Value::U256(U256::from(5u64));
+        let proof = env.prove_storage(counter, U256::ZERO);
+        assert!(proof.verify(&latest.state_root, &counter, &U256::ZERO, Some(&count)));
+        assert!(!proof.verify(&earlier.state_root, &counter, &U256::ZERO, Some(&count)));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_discarding_a_fork_restores_the_chain() {
+        let mut env = BlockchainTestEnvironment::new(BlockchainTestConfig::default()).unwrap();
+        let counter = env.deploy_contract(assemble(COUNTER).unwrap(), account(1)).unwrap();
+        env.call_contract(counter, "bump", &[Value::U256(U256::from(2u64))], account(2)).unwrap();
+
+        env.fork();
+        env.call_contract(counter, "bump", &[Value::U256(U256::from(5u64))], account(3)).unwrap();
+        env.set_balance(account(3), U256::ZERO).unwrap();
+        env.advance_blocks(2).unwrap();
+        env.discard_fork().unwrap();
+
+        assert_eq!(env.vm().storage().get(&counter, &U256::ZERO), Some(Value::U256(U256::from(2u64))));
+        assert_eq!(env.get_account(account(3)).nonce, 0);
+        assert_eq!(env.get_balance(account(3)), U256::from(1_000_000_000_000_000_000u64));
+        assert_eq!(env.get_current_block().number, 0);
+        assert!(env.discard_fork().is_err());
+    }
+
+    #[test]
+    fn test_transactions_trace_as_struct_logs() {
+        let config = BlockchainTestConfig { trace: Some(TraceKind::Struct), ..Default::default() };
+        let mut env = BlockchainTestEnvironment::new(config).unwrap();
+        let counter = env.deploy_contract(assemble(COUNTER).unwrap(), account(1)).unwrap();
+        assert!(env.last_trace().is_none());
+
+        env.call_contract(counter, "bump", &[Value::U256(U256::from(2u64))], account(2)).unwrap();
+        let Some(Trace::Struct(logs)) = env.last_trace() else {
+            panic!("expected a struct log, got {:?}", env.last_trace());
+        };
+        let ops: Vec<&str> = logs.iter().map(|log| log.op.as_str()).collect();
+        assert_eq!(ops, ["SLOAD", "LOAD", "ADD", "SSTORE", "ENV", "SSTORE", "SLOAD", "RET"]);
+        assert_eq!(logs[3].storage.get(&U256::ZERO), Some(&Value::U256(U256::from(2u64))));
+        assert_eq!(logs[5].storage.get(&U256::ONE), Some(&Value::Address(account(2))));
+        assert!(logs[6].storage.is_empty());
+
+        assert!(env.call_contract(counter, "fail", &[], account(2)).is_err());
+        let Some(Trace::Struct(logs)) = env.last_trace() else {
+            panic!("expected a struct log");
+        };
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].error.as_deref().is_some_and(|error| error.contains("zero")));
+    }
+
+    #[test]
+    fn test_trace_kind_selects_the_tracer() {
+        let trace_of_bump = |trace| {
+            let mut env = BlockchainTestEnvironment::new(BlockchainTestConfig { trace, ..Default::default() }).unwrap();
+            let counter = env.deploy_contract(assemble(COUNTER).unwrap(), account(1)).unwrap();
+            env.call_contract(counter, "bump", &[Value::U256(U256::from(2u64))], account(2)).unwrap();
+            env.last_trace().cloned()
+        };
+        assert_eq!(trace_of_bump(None), None);
+        let Some(Trace::Calls(calls)) = trace_of_bump(Some(TraceKind::Calls)) else {
+            panic!("expected a call trace");
+        };
+        assert_eq!((calls.len(), calls[0].function.as_str()), (1, "bump"));
+        assert_eq!((calls[0].from, calls[0].output.clone()), (account(2), Some(Value::U256(U256::from(2u64)))));
+        assert!(matches!(trace_of_bump(Some(TraceKind::Struct)), Some(Trace::Struct(logs)) if logs.len() == 8));
+    }
+}
@@ -1,7 +1,9 @@
-use crate::vm::{VM, Value, Contract};
-use crate::stdlib::core::{Error, Result};
-use crate::testing::blockchain::{BlockchainTestEnvironment, Account};
-use std::collections::HashMap;
+use stremax_runtime::bytecode::{Abi, Module, Param};
+use stremax_runtime::{Address, Event, Value};
+use crate::core::{Error, Result};
+use crate::testing::blockchain::BlockchainTestEnvironment;
+use std::collections::{HashMap, HashSet};
+use serde::Serialize;
 
 /// Contract test configuration
 #[derive(Debug, Clone)]
@@ -28,21 +30,21 @@ pub struct ContractTestEnvironment {
     config: ContractTestConfig,
     env: BlockchainTestEnvironment,
     deployed_contracts: HashMap<String, DeployedContract>,
-    mocks: HashMap<[u8; 20], MockContract>,
+    mocks: HashMap<Address, MockContract>,
     coverage: Coverage,
 }
 
 #[derive(Debug)]
 pub struct DeployedContract {
     pub name: String,
-    pub address: [u8; 20],
+    pub address: Address,
     pub abi: ContractABI,
     pub source_map: SourceMap,
 }
 
 #[derive(Debug)]
 pub struct MockContract {
-    pub address: [u8; 20],
+    pub address: Address,
     pub expectations: Vec<MockExpectation>,
     pub calls: Vec<MockCall>,
 }
@@ -62,7 +64,7 @@ pub struct MockCall {
     pub timestamp: u64,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Coverage {
     pub lines: HashMap<String, HashSet<usize>>,
     pub branches: HashMap<String, HashSet<usize>>,
@@ -107,7 +109,43 @@ pub enum StateMutability {
     Payable,
 }
 
-#[derive(Debug)]
+impl ContractABI {
+    /// The ABI a compiled module exports, with types spelled as in the
+    /// bytecode's assembly listing
+    pub fn from_module(module: &Module) -> Self {
+        let parameters = |params: &[Param]| -> Vec<ABIParameter> {
+            params.iter()
+                .map(|param| ABIParameter {
+                    name: param.name.clone(),
+                    type_name: param.ty.to_string(),
+                    components: None,
+                    indexed: false,
+                })
+                .collect()
+        };
+        let Abi { functions, events } = &module.abi;
+        let functions = functions.iter()
+            .map(|entry| {
+                let function = &module.functions[entry.function as usize];
+                let outputs = entry.returns.iter()
+                    .map(|ty| ABIParameter { name: String::new(), type_name: ty.to_string(), components: None, indexed: false })
+                    .collect();
+                let state_mutability = if function.is_pure { StateMutability::Pure } else { StateMutability::NonPayable };
+                let abi = ABIFunction { name: function.name.clone(), inputs: parameters(&entry.params), outputs, state_mutability };
+                (function.name.clone(), abi)
+            })
+            .collect();
+        let events = events.iter()
+            .map(|event| {
+                let abi = ABIEvent { name: event.name.clone(), inputs: parameters(&event.params), anonymous: false };
+                (event.name.clone(), abi)
+            })
+            .collect();
+        ContractABI { constructor: None, functions, events }
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct SourceMap {
     pub source_files: HashMap<String, String>,
     pub line_mappings: HashMap<usize, (String, usize)>,
@@ -131,26 +169,31 @@ impl ContractTestEnvironment {
         }
     }
 
-    pub fn deploy_contract(&mut self, name: &str, contract: Contract) -> Result<[u8; 20]> {
+    pub fn blockchain(&mut self) -> &mut BlockchainTestEnvironment {
+        &mut self.env
+    }
+
+    pub fn deploy_contract(&mut self, name: &str, contract: Module) -> Result<Address> {
         // Deploy the contract
         let sender = self.env.get_current_block().number % 10;
         let mut sender_address = [0; 20];
         sender_address[19] = sender as u8;
         
-        let address = self.env.deploy_contract(contract.clone(), sender_address)?;
+        let abi = ContractABI::from_module(&contract);
+        let address = self.env.deploy_contract(contract, sender_address)?;
         
         // Store contract info
         self.deployed_contracts.insert(name.to_string(), DeployedContract {
             name: name.to_string(),
             address,
-            abi: contract.abi,
-            source_map: contract.source_map,
+            abi,
+            source_map: SourceMap::default(),
         });
         
         Ok(address)
     }
 
-    pub fn mock_contract<F>(&mut self, address: [u8; 20], setup: F) -> Result<()> 
+    pub fn mock_contract<F>(&mut self, address: Address, setup: F) -> Result<()> 
     where
         F: FnOnce(&mut MockBuilder) -> Result<()>
     {
@@ -163,11 +206,11 @@ impl ContractTestEnvironment {
 
     pub fn call_contract(&mut self, name: &str, method: &str, args: &[Value]) -> Result<Value> {
         let contract = self.deployed_contracts.get(name)
-            .ok_or(Error::ContractNotFound)?;
+            .ok_or_else(|| Error::ContractError(format!("no contract named `{}`", name)))?;
             
         // Check if method exists in ABI
         let abi_function = contract.abi.functions.get(method)
-            .ok_or(Error::MethodNotFound)?;
+            .ok_or_else(|| Error::SymbolNotFound(method.to_string()))?;
             
         // Validate arguments against ABI
         self.validate_arguments(abi_function, args)?;
@@ -194,11 +237,11 @@ impl ContractTestEnvironment {
 
     pub fn assert_event_emitted(&self, name: &str, event: &str, args: &[Value]) -> Result<()> {
         let contract = self.deployed_contracts.get(name)
-            .ok_or(Error::ContractNotFound)?;
+            .ok_or_else(|| Error::ContractError(format!("no contract named `{}`", name)))?;
             
         // Check if event exists in ABI
         let abi_event = contract.abi.events.get(event)
-            .ok_or(Error::EventNotFound)?;
+            .ok_or_else(|| Error::SymbolNotFound(event.to_string()))?;
             
         // Check events
         for emitted in self.env.get_events() {
@@ -207,7 +250,7 @@ impl ContractTestEnvironment {
             }
         }
         
-        Err(Error::EventNotFound)
+        Err(Error::SymbolNotFound(event.to_string()))
     }
 
     pub fn get_coverage_report(&self) -> Coverage {
@@ -218,7 +261,12 @@ impl ContractTestEnvironment {
 
     fn validate_arguments(&self, function: &ABIFunction, args: &[Value]) -> Result<()> {
         if args.len() != function.inputs.len() {
-            return Err(Error::InvalidArgumentCount);
+            return Err(Error::TypeError(format!(
+                "`{}` takes {} arguments, got {}",
+                function.name,
+                function.inputs.len(),
+                args.len()
+            )));
         }
         
         for (arg, param) in args.iter().zip(function.inputs.iter()) {
@@ -231,19 +279,18 @@ impl ContractTestEnvironment {
     }
 
     fn type_matches(&self, value: &Value, type_name: &str) -> bool {
-        match (value, type_name) {
-            (Value::Int(_), "int256") => true,
-            (Value::Uint(_), "uint256") => true,
-            (Value::Address(_), "address") => true,
-            (Value::Bool(_), "bool") => true,
-            (Value::Bytes(_), "bytes") => true,
-            (Value::String(_), "string") => true,
-            _ => false,
-        }
+        matches!(
+            (value, type_name),
+            (Value::U256(_), "u256")
+                | (Value::Address(_), "address")
+                | (Value::Bool(_), "bool")
+                | (Value::Bytes(_), "bytes")
+                | (Value::String(_), "string")
+        )
     }
 
     fn event_matches(&self, emitted: &Event, abi: &ABIEvent, args: &[Value]) -> bool {
-        if emitted.name != abi.name {
+        if emitted.name != abi.name || emitted.fields.len() != args.len() {
             return false;
         }
         
@@ -265,7 +312,7 @@ impl ContractTestEnvironment {
                 .insert(function.to_string());
                 
             // Update line coverage if we have source mapping
-            if let Some(range) = contract_info.source_map.function_mappings.get(function) {
+            if let Some((_, range)) = contract_info.source_map.function_mappings.get(function) {
                 let lines = self.coverage.lines
                     .entry(contract.to_string())
                     .or_default();
@@ -281,12 +328,12 @@ impl ContractTestEnvironment {
 }
 
 pub struct MockBuilder {
-    address: [u8; 20],
+    address: Address,
     expectations: Vec<MockExpectation>,
 }
 
 impl MockBuilder {
-    fn new(address: [u8; 20]) -> Self {
+    fn new(address: Address) -> Self {
         MockBuilder {
             address,
             expectations: Vec::new(),
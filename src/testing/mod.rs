@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
-use crate::vm::{VM, Value, Contract};
-use crate::stdlib::core::{Error, Result, events::Event};
+use serde::{Deserialize, Serialize};
+use stremax_runtime::num::U256;
+use stremax_runtime::{Address, Value};
+use crate::core::{Error, Result};
 use std::path::PathBuf;
 
 pub mod blockchain;
@@ -37,7 +39,6 @@ impl Default for TestConfig {
 /// Main test environment that coordinates all testing components
 pub struct TestEnvironment {
     config: TestConfig,
-    blockchain_env: BlockchainTestEnvironment,
     contract_env: ContractTestEnvironment,
     vm_env: VMTestEnvironment,
 }
@@ -45,19 +46,19 @@ pub struct TestEnvironment {
 impl TestEnvironment {
     pub fn new(config: TestConfig) -> Result<Self> {
         let blockchain_env = BlockchainTestEnvironment::new(config.blockchain.clone())?;
-        let contract_env = ContractTestEnvironment::new(config.contract.clone(), blockchain_env.clone())?;
+        let contract_env = ContractTestEnvironment::new(config.contract.clone(), blockchain_env);
         let vm_env = VMTestEnvironment::new(config.vm.clone())?;
         
         Ok(TestEnvironment {
             config,
-            blockchain_env,
             contract_env,
             vm_env,
         })
     }
     
+    /// The chain the contract environment deploys to
     pub fn blockchain(&mut self) -> &mut BlockchainTestEnvironment {
-        &mut self.blockchain_env
+        self.contract_env.blockchain()
     }
     
     pub fn contracts(&mut self) -> &mut ContractTestEnvironment {
@@ -89,8 +90,8 @@ impl TestEnvironment {
         
         // Recursively find all test files
         for entry in walkdir::WalkDir::new(&self.config.test_dir) {
-            let entry = entry.map_err(|_| Error::IoError)?;
-            if entry.file_type().is_file() && entry.path().extension().map_or(false, |ext| ext == "rs") {
+            let entry = entry.map_err(|e| Error::RuntimeError(e.to_string()))?;
+            if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "rs") {
                 test_files.push(entry.path().to_path_buf());
             }
         }
@@ -109,28 +110,29 @@ impl TestEnvironment {
     fn execute_test_file(&mut self, path: &PathBuf) -> Result<()> {
         // Load and parse test file
         let content = std::fs::read_to_string(path)
-            .map_err(|_| Error::IoError)?;
+            .map_err(Error::IoError)?;
             
         // Execute test functions
         self.execute_test_content(&content)
     }
     
-    fn execute_test_content(&mut self, content: &str) -> Result<()> {
-        // Parse test content and execute test functions
-        unimplemented!()
+    fn execute_test_content(&mut self, _content: &str) -> Result<()> {
+        // Test files need the compiler, which this crate does not link;
+        // register tests with a `TestRunner` instead
+        Err(Error::RuntimeError("running test files is not supported".to_string()))
     }
     
     fn generate_reports(&self, report: &TestReport) -> Result<()> {
         // Create report directory
         std::fs::create_dir_all(&self.config.report_dir)
-            .map_err(|_| Error::IoError)?;
+            .map_err(Error::IoError)?;
             
         // Generate test report
         let report_path = self.config.report_dir.join("test-report.json");
         let report_content = serde_json::to_string_pretty(report)
-            .map_err(|_| Error::SerializationError)?;
+            .map_err(|e| Error::SerializationError(e.to_string()))?;
         std::fs::write(report_path, report_content)
-            .map_err(|_| Error::IoError)?;
+            .map_err(Error::IoError)?;
             
         // Generate coverage report if enabled
         if self.config.contract.coverage_enabled {
@@ -143,13 +145,11 @@ impl TestEnvironment {
     fn generate_coverage_report(&self) -> Result<()> {
         let coverage = self.contract_env.get_coverage_report();
         
-        // Generate HTML coverage report
-        let report_path = self.config.report_dir.join("coverage");
-        std::fs::create_dir_all(&report_path)
-            .map_err(|_| Error::IoError)?;
-            
-        // Generate report files
-        unimplemented!()
+        let report_path = self.config.report_dir.join("coverage.json");
+        let report_content = serde_json::to_string_pretty(&coverage)
+            .map_err(|e| Error::SerializationError(e.to_string()))?;
+        std::fs::write(report_path, report_content)
+            .map_err(Error::IoError)
     }
 }
 
@@ -201,13 +201,16 @@ impl TestReport {
 }
 
 // Test runner
+#[derive(Default)]
 pub struct TestRunner {
     tests: Vec<Test>,
 }
 
+type TestFn = Box<dyn Fn(&mut TestEnvironment) -> Result<()>>;
+
 struct Test {
     name: String,
-    function: Box<dyn Fn(&mut TestEnvironment) -> Result<()>>,
+    function: TestFn,
 }
 
 impl TestRunner {
@@ -231,10 +234,11 @@ impl TestRunner {
         let mut results = TestResults::new();
         
         for test in &self.tests {
-            let mut env = TestEnvironment::new(TestConfig::default());
             let start = Instant::now();
+            let result = TestEnvironment::new(TestConfig::default())
+                .and_then(|mut env| (test.function)(&mut env));
             
-            match (test.function)(&mut env) {
+            match result {
                 Ok(()) => {
                     results.passed += 1;
                     results.add_success(&test.name, start.elapsed());
@@ -301,6 +305,10 @@ impl TestResults {
         println!("Failed: {}", self.failed);
         println!();
 
+        for success in &self.successes {
+            println!("  {} ({:?}): ok", success.name, success.duration);
+        }
+
         if !self.failures.is_empty() {
             println!("Failures:");
             for failure in &self.failures {
@@ -315,10 +323,8 @@ impl TestResults {
 pub mod assert {
     use super::*;
 
-    pub fn balance_equals(env: &TestEnvironment, address: [u8; 20], expected: u64) -> Result<()> {
-        let actual = env.accounts.get(&address)
-            .map(|acc| acc.balance)
-            .unwrap_or(0);
+    pub fn balance_equals(env: &mut TestEnvironment, address: Address, expected: U256) -> Result<()> {
+        let actual = env.blockchain().get_balance(address);
         
         if actual != expected {
             Err(Error::ContractError(format!(
@@ -330,8 +336,8 @@ pub mod assert {
         }
     }
 
-    pub fn event_emitted(env: &TestEnvironment, name: &str) -> Result<()> {
-        if env.get_events().iter().any(|e| e.name == name) {
+    pub fn event_emitted(env: &mut TestEnvironment, name: &str) -> Result<()> {
+        if env.blockchain().get_events().iter().any(|e| e.name == name) {
             Ok(())
         } else {
             Err(Error::ContractError(format!(
@@ -342,15 +348,15 @@ pub mod assert {
     }
 
     pub fn storage_equals(
-        env: &TestEnvironment,
-        address: [u8; 20],
-        key: [u8; 32],
-        expected: &[u8],
+        env: &mut TestEnvironment,
+        address: Address,
+        slot: U256,
+        expected: &Value,
     ) -> Result<()> {
-        let actual = env.get_storage(address, key)
+        let actual = env.blockchain().vm().storage().get(&address, &slot)
             .ok_or_else(|| Error::ContractError("Storage key not found".into()))?;
         
-        if actual != expected {
+        if actual != *expected {
             Err(Error::ContractError(format!(
                 "Storage mismatch: expected {:?}, got {:?}",
                 expected, actual
@@ -365,6 +371,7 @@ pub mod assert {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use stremax_runtime::bytecode::Module;
 
     #[test]
     fn test_token_contract() {
@@ -374,26 +381,26 @@ mod tests {
             // Setup
             let owner = [1u8; 20];
             let recipient = [2u8; 20];
-            env.create_account(owner, 1000);
-            env.create_account(recipient, 0);
+            env.blockchain().set_balance(owner, U256::from(1000u64))?;
+            env.blockchain().set_balance(recipient, U256::ZERO)?;
 
             // Deploy token contract
-            let contract_code = vec![]; // Add actual contract bytecode
-            env.deploy_contract(owner, contract_code)?;
+            let contract = Module::new("Token"); // Add actual contract bytecode
+            let token = env.blockchain().deploy_contract(contract, owner)?;
 
             // Test transfer
-            env.call_contract(
-                owner,
-                owner,
+            env.blockchain().call_contract(
+                token,
                 "transfer",
-                vec![
+                &[
                     Value::Address(recipient),
-                    Value::U256(100),
+                    Value::U256(U256::from(100u64)),
                 ],
+                owner,
             )?;
 
             // Assertions
-            assert::balance_equals(env, recipient, 100)?;
+            assert::balance_equals(env, recipient, U256::from(100u64))?;
             assert::event_emitted(env, "Transfer")?;
 
             Ok(())
@@ -0,0 +1,145 @@
+//! Per-opcode conformance through [`assert_stack_effect`]. Every opcode
+//! whose effect stays on the stack has a case here, run in both tiers;
+//! control flow, storage, messaging and the other opcodes that reach past
+//! the stack are covered by the runtime's own suite.
+
+use std::collections::BTreeSet;
+
+use stremax_runtime::bytecode::{Constant, Instruction, Opcode};
+use stremax_runtime::num::U256;
+use stremax_runtime::Value;
+
+use super::{assert_stack_effect, assert_stack_effect_with};
+use crate::core::{Error, Result};
+
+const STACK_OPCODES: &[Opcode] = &[
+    Opcode::Push, Opcode::Pop, Opcode::Dup, Opcode::Swap, Opcode::Load, Opcode::Store,
+    Opcode::Add, Opcode::Sub, Opcode::Mul, Opcode::Div, Opcode::Mod,
+    Opcode::Eq, Opcode::Lt, Opcode::Gt, Opcode::LtEq, Opcode::GtEq,
+    Opcode::And, Opcode::Or, Opcode::Xor, Opcode::Not,
+    Opcode::Tuple, Opcode::Extract, Opcode::Struct, Opcode::Variant, Opcode::IsVariant,
+    Opcode::GetField, Opcode::SetField, Opcode::Index, Opcode::SetIndex, Opcode::Cast, Opcode::Array,
+];
+
+struct Case {
+    instruction: Instruction,
+    before: Vec<Value>,
+    /// The stack afterwards, or the error the instruction traps with
+    after: Result<Vec<Value>>,
+}
+
+fn case(instruction: Instruction, before: Vec<Value>, after: Result<Vec<Value>>) -> Case {
+    Case { instruction, before, after }
+}
+
+fn word(n: u64) -> Value {
+    Value::U256(U256::from(n))
+}
+
+fn boolean(b: bool) -> Value {
+    Value::Bool(b)
+}
+
+/// What the cases refer to by index
+fn constants() -> Vec<Constant> {
+    vec![
+        Constant::u256(7),
+        Constant::String("Pair {a, b}".into()),
+        Constant::String("a".into()),
+        Constant::String("Some".into()),
+        Constant::String("u256".into()),
+    ]
+}
+
+fn cases() -> Vec<Case> {
+    let pair = |a: u64| Value::Struct { name: "Pair".into(), fields: vec![("a".into(), word(a)), ("b".into(), word(2))] };
+    let some = Value::Variant { path: "Some".into(), payload: vec![word(7)] };
+    let array = Value::Array(vec![word(4), word(5)]);
+    let max = Value::U256(U256::MAX);
+    vec![
+        case(Instruction::Push(0), vec![], Ok(vec![word(7)])),
+        case(Instruction::Pop, vec![word(1), word(2)], Ok(vec![word(1)])),
+        case(Instruction::Pop, vec![], Err(Error::StackUnderflow)),
+        case(Instruction::Dup(0), vec![word(7)], Ok(vec![word(7), word(7)])),
+        case(Instruction::Dup(1), vec![word(7)], Err(Error::StackUnderflow)),
+        case(Instruction::Swap(0), vec![word(1), word(7)], Ok(vec![word(7), word(1)])),
+        case(Instruction::Swap(0), vec![word(7)], Err(Error::StackUnderflow)),
+        case(Instruction::Load(0), vec![word(7)], Ok(vec![word(7), word(7)])),
+        case(Instruction::Store(0), vec![word(1), word(7)], Ok(vec![word(1)])),
+
+        case(Instruction::Add, vec![word(2), word(3)], Ok(vec![word(5)])),
+        case(Instruction::Add, vec![max.clone(), word(1)], Err(Error::ArithmeticOverflow)),
+        case(Instruction::Add, vec![word(2), boolean(true)], Err(Error::TypeMismatch)),
+        case(Instruction::Sub, vec![word(7), word(2)], Ok(vec![word(5)])),
+        case(Instruction::Sub, vec![word(2), word(7)], Err(Error::ArithmeticOverflow)),
+        case(Instruction::Mul, vec![word(6), word(7)], Ok(vec![word(42)])),
+        case(Instruction::Mul, vec![max, word(2)], Err(Error::ArithmeticOverflow)),
+        case(Instruction::Div, vec![word(7), word(2)], Ok(vec![word(3)])),
+        case(Instruction::Div, vec![word(7), word(0)], Err(Error::DivisionByZero)),
+        case(Instruction::Mod, vec![word(7), word(2)], Ok(vec![word(1)])),
+        case(Instruction::Mod, vec![word(7), word(0)], Err(Error::DivisionByZero)),
+        case(Instruction::Eq, vec![word(7), word(7)], Ok(vec![boolean(true)])),
+        case(Instruction::Eq, vec![word(1), boolean(true)], Ok(vec![boolean(false)])),
+        case(Instruction::Lt, vec![word(2), word(7)], Ok(vec![boolean(true)])),
+        case(Instruction::Lt, vec![word(2), boolean(true)], Err(Error::TypeMismatch)),
+        case(Instruction::Gt, vec![word(2), word(7)], Ok(vec![boolean(false)])),
+        case(Instruction::LtEq, vec![word(7), word(7)], Ok(vec![boolean(true)])),
+        case(Instruction::GtEq, vec![word(2), word(7)], Ok(vec![boolean(false)])),
+        case(Instruction::And, vec![boolean(true), boolean(false)], Ok(vec![boolean(false)])),
+        case(Instruction::And, vec![word(0b110), word(0b011)], Ok(vec![word(0b010)])),
+        case(Instruction::Or, vec![boolean(true), boolean(false)], Ok(vec![boolean(true)])),
+        case(Instruction::Xor, vec![boolean(true), boolean(true)], Ok(vec![boolean(false)])),
+        case(Instruction::Xor, vec![boolean(true), word(1)], Err(Error::TypeMismatch)),
+        case(Instruction::Not, vec![boolean(false)], Ok(vec![boolean(true)])),
+        case(Instruction::Not, vec![word(0)], Ok(vec![Value::U256(U256::MAX)])),
+
+        case(Instruction::Tuple(2), vec![word(1), word(2)], Ok(vec![Value::Tuple(vec![word(1), word(2)])])),
+        case(Instruction::Array(2), vec![word(1), word(2)], Ok(vec![Value::Array(vec![word(1), word(2)])])),
+        case(Instruction::Array(1), vec![], Err(Error::StackUnderflow)),
+        case(Instruction::Extract(1), vec![Value::Tuple(vec![word(1), word(2)])], Ok(vec![word(2)])),
+        case(
+            Instruction::Extract(2),
+            vec![Value::Tuple(vec![word(1), word(2)])],
+            Err(Error::RuntimeError("Index out of bounds".into())),
+        ),
+        case(Instruction::Struct(1, 2), vec![word(1), word(2)], Ok(vec![pair(1)])),
+        case(Instruction::Variant(3, 1), vec![word(7)], Ok(vec![some.clone()])),
+        case(Instruction::IsVariant(3), vec![some], Ok(vec![boolean(true)])),
+        case(Instruction::IsVariant(3), vec![word(7)], Ok(vec![boolean(false)])),
+        case(Instruction::GetField(2), vec![pair(1)], Ok(vec![word(1)])),
+        case(Instruction::GetField(2), vec![word(1)], Err(Error::TypeMismatch)),
+        case(Instruction::SetField(2), vec![pair(1), word(7)], Ok(vec![pair(7)])),
+        case(Instruction::Index, vec![array.clone(), word(1)], Ok(vec![word(5)])),
+        case(Instruction::Index, vec![array.clone(), word(2)], Err(Error::RuntimeError("Index out of bounds".into()))),
+        case(Instruction::SetIndex, vec![array, word(1), word(7)], Ok(vec![Value::Array(vec![word(4), word(7)])])),
+        case(Instruction::Cast(4), vec![boolean(true)], Ok(vec![word(1)])),
+        case(Instruction::Cast(4), vec![Value::String("1".into())], Err(Error::TypeMismatch)),
+    ]
+}
+
+#[test]
+fn test_every_stack_opcode_has_a_case() {
+    let covered: BTreeSet<u8> = cases().iter().map(|case| case.instruction.opcode() as u8).collect();
+    let missing: Vec<_> = STACK_OPCODES.iter().filter(|opcode| !covered.contains(&(**opcode as u8))).collect();
+    assert!(missing.is_empty(), "no conformance case for {:?}", missing);
+}
+
+#[test]
+fn test_every_case_through_assert_stack_effect() {
+    let constants = constants();
+    for case in cases() {
+        let after = case.after.as_deref().unwrap_or_default();
+        let result = assert_stack_effect_with(&constants, case.instruction, &case.before, after);
+        match (&result, &case.after) {
+            (Ok(()), Ok(_)) => {}
+            (Err(actual), Err(expected)) if actual.to_string() == expected.to_string() => {}
+            _ => panic!("{:?} on {:?}: expected {:?}, got {:?}", case.instruction, case.before, case.after, result),
+        }
+    }
+}
+
+#[test]
+fn test_overflow_surfaces_as_an_error() {
+    let result = assert_stack_effect(Instruction::Add, &[Value::U256(U256::MAX), word(1)], &[]);
+    assert!(matches!(result, Err(Error::ArithmeticOverflow)));
+}
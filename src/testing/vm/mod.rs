@@ -1,233 +1,238 @@
-use crate::vm::{VM, Value, Contract, Instruction, Stack, Memory};
-use crate::stdlib::core::{Error, Result};
-use std::collections::HashMap;
+use stremax_runtime::bytecode::{Constant, Function, Instruction, Module};
+use stremax_runtime::{
+    Address, Outcome, Recorder, Recording, Trace, TraceHandle, TraceKind, Tracer, Trap, Value, Vm, DEFAULT_JIT_THRESHOLD,
+};
+use crate::core::{Error, Result};
+
+pub use stremax_runtime::record::{Change, ExecutionStep, FrameMemory, MemorySnapshot};
+
+/// Gas available to a test VM; high enough that only runaway programs hit it
+pub const TEST_GAS_LIMIT: u64 = 10_000_000;
+
+/// Where the stack-effect helpers deploy the instruction under test
+const EFFECT_CONTRACT: Address = [0xef; 20];
 
 /// VM test configuration
 #[derive(Debug, Clone)]
 pub struct VMTestConfig {
-    pub trace_execution: bool,
-    pub debug_info: bool,
-    pub gas_metering: bool,
-    pub memory_tracking: bool,
+    pub gas_limit: u64,
+    /// Interpreted calls before a function is compiled, as in
+    /// [`Vm::set_jit_threshold`]: `None` keeps a test in the interpreter
+    /// and `Some(0)` runs only compiled code
+    pub jit_threshold: Option<u32>,
+    /// Record every call instruction by instruction, for
+    /// [`VMTestEnvironment::last_recording`]. Recording keeps the calls
+    /// interpreted.
+    pub record: bool,
+    /// Trace every call with a built-in tracer, for
+    /// [`VMTestEnvironment::last_trace`]. Tracing keeps the calls
+    /// interpreted too.
+    pub trace: Option<TraceKind>,
 }
 
 impl Default for VMTestConfig {
     fn default() -> Self {
         VMTestConfig {
-            trace_execution: false,
-            debug_info: true,
-            gas_metering: true,
-            memory_tracking: true,
+            gas_limit: TEST_GAS_LIMIT,
+            jit_threshold: Some(DEFAULT_JIT_THRESHOLD),
+            record: false,
+            trace: None,
         }
     }
 }
 
-/// VM test environment
+/// VM test environment around the same [`Vm`] that `strxvm` runs
 pub struct VMTestEnvironment {
     config: VMTestConfig,
-    vm: VM,
-    execution_trace: Vec<ExecutionStep>,
-    breakpoints: HashMap<usize, Breakpoint>,
-    memory_snapshots: Vec<MemorySnapshot>,
+    vm: Vm,
     gas_usage: Vec<GasUsage>,
+    recording: Option<Recording>,
+    trace: Option<Trace>,
 }
 
-#[derive(Debug)]
-pub struct ExecutionStep {
-    pub pc: usize,
-    pub instruction: Instruction,
-    pub stack: Stack,
-    pub memory: Memory,
-    pub gas_used: u64,
-    pub error: Option<Error>,
-}
-
-#[derive(Debug)]
-pub struct Breakpoint {
-    pub condition: Box<dyn Fn(&ExecutionStep) -> bool>,
-    pub action: Box<dyn Fn(&mut VMTestEnvironment) -> Result<()>>,
-}
-
-#[derive(Debug)]
-pub struct MemorySnapshot {
-    pub pc: usize,
-    pub memory: Memory,
-    pub allocations: Vec<Allocation>,
-}
-
-#[derive(Debug)]
-pub struct Allocation {
-    pub address: usize,
-    pub size: usize,
-    pub lifetime: Range,
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct GasUsage {
-    pub pc: usize,
-    pub instruction: Instruction,
-    pub cost: u64,
-    pub cumulative: u64,
-}
-
-#[derive(Debug)]
-pub struct Range {
-    pub start: usize,
-    pub end: usize,
+    pub contract: Address,
+    pub function: String,
+    pub gas_used: u64,
 }
 
 impl VMTestEnvironment {
     pub fn new(config: VMTestConfig) -> Result<Self> {
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(config.jit_threshold);
         Ok(VMTestEnvironment {
             config,
-            vm: VM::new()?,
-            execution_trace: Vec::new(),
-            breakpoints: HashMap::new(),
-            memory_snapshots: Vec::new(),
+            vm,
             gas_usage: Vec::new(),
+            recording: None,
+            trace: None,
         })
     }
 
-    pub fn execute(&mut self, bytecode: &[u8]) -> Result<Value> {
-        let mut result = Ok(Value::Void);
-        
-        // Setup execution hooks based on config
-        self.setup_execution_hooks()?;
-        
-        // Execute bytecode
-        result = self.vm.execute(bytecode);
-        
-        // Process results
-        if self.config.trace_execution {
-            self.process_execution_trace()?;
-        }
-        
-        if self.config.memory_tracking {
-            self.analyze_memory_usage()?;
-        }
-        
-        if self.config.gas_metering {
-            self.analyze_gas_usage()?;
-        }
-        
-        result
-    }
-
-    pub fn add_breakpoint<F, A>(&mut self, pc: usize, condition: F, action: A) -> Result<()>
-    where
-        F: Fn(&ExecutionStep) -> bool + 'static,
-        A: Fn(&mut VMTestEnvironment) -> Result<()> + 'static
-    {
-        self.breakpoints.insert(pc, Breakpoint {
-            condition: Box::new(condition),
-            action: Box::new(action),
-        });
-        Ok(())
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
     }
 
-    pub fn get_execution_trace(&self) -> &[ExecutionStep] {
-        &self.execution_trace
+    pub fn deploy(&mut self, address: Address, module: Module) -> Result<()> {
+        self.vm.deploy(address, module).map_err(Error::ContractError)
     }
 
-    pub fn get_memory_snapshots(&self) -> &[MemorySnapshot] {
-        &self.memory_snapshots
+    /// Calls `function` of the contract at `address` with the configured
+    /// gas limit. A trap is part of the outcome, not an error.
+    pub fn call(&mut self, address: &Address, function: &str, args: Vec<Value>) -> Result<Outcome> {
+        let recorder = self.config.record.then(|| Recorder::new(&self.vm));
+        let trace = self.config.trace.map(TraceHandle::new);
+        let mut tracers: Vec<Box<dyn Tracer>> = Vec::new();
+        tracers.extend(recorder.clone().map(|recorder| Box::new(recorder) as Box<dyn Tracer>));
+        tracers.extend(trace.as_ref().map(TraceHandle::tracer));
+        let traced = !tracers.is_empty();
+        if traced {
+            self.vm.set_tracer(Some(Box::new(tracers)));
+        }
+        let outcome = self.vm.call(address, function, args, self.config.gas_limit);
+        if traced {
+            self.vm.set_tracer(None);
+        }
+        if let Some(recorder) = recorder {
+            self.recording = Some(recorder.finish());
+        }
+        if let Some(trace) = trace {
+            self.trace = Some(trace.finish());
+        }
+        let outcome = outcome.map_err(Error::ContractError)?;
+        self.gas_usage.push(GasUsage {
+            contract: *address,
+            function: function.to_string(),
+            gas_used: outcome.gas_used,
+        });
+        Ok(outcome)
     }
 
     pub fn get_gas_usage(&self) -> &[GasUsage] {
         &self.gas_usage
     }
 
-    // Private helper methods
-
-    fn setup_execution_hooks(&mut self) -> Result<()> {
-        if self.config.trace_execution {
-            self.vm.set_instruction_hook(Box::new(|pc, instruction, stack, memory, gas| {
-                // Record execution step
-                Ok(())
-            }))?;
-        }
-        
-        if self.config.memory_tracking {
-            self.vm.set_memory_hook(Box::new(|pc, memory, operation| {
-                // Record memory operation
-                Ok(())
-            }))?;
-        }
-        
-        if self.config.gas_metering {
-            self.vm.set_gas_hook(Box::new(|pc, instruction, cost| {
-                // Record gas usage
-                Ok(())
-            }))?;
-        }
-        
-        Ok(())
-    }
-
-    fn process_execution_trace(&mut self) -> Result<()> {
-        // Analyze execution trace
-        // Look for patterns, inefficiencies, etc.
-        Ok(())
+    /// The steps of the latest call, when the environment records
+    pub fn last_recording(&self) -> Option<&Recording> {
+        self.recording.as_ref()
     }
 
-    fn analyze_memory_usage(&mut self) -> Result<()> {
-        // Analyze memory usage patterns
-        // Look for leaks, fragmentation, etc.
-        Ok(())
+    /// The trace of the latest call, when the environment traces
+    pub fn last_trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
     }
+}
 
-    fn analyze_gas_usage(&mut self) -> Result<()> {
-        // Analyze gas usage patterns
-        // Look for optimization opportunities
-        Ok(())
+/// The error a trap surfaces as in test assertions
+pub fn trap_error(trap: Trap) -> Error {
+    match trap {
+        Trap::OutOfGas => Error::OutOfGas,
+        Trap::StackUnderflow => Error::StackUnderflow,
+        Trap::TypeMismatch(_) => Error::TypeMismatch,
+        Trap::ArithmeticOverflow => Error::ArithmeticOverflow,
+        Trap::DivisionByZero => Error::DivisionByZero,
+        Trap::InvalidLocal(index) => Error::InvalidLocal(index),
+        Trap::InsufficientBalance => Error::InsufficientBalance,
+        Trap::Revert(message) => Error::ContractError(message),
+        Trap::Reentrancy { function, chain } => Error::Reentrancy { function, chain },
+        Trap::PermissionDenied(_) => Error::PermissionDenied,
+        Trap::ResourceNotHeld(_) => Error::ResourceUnavailable,
+        other => Error::RuntimeError(other.to_string()),
     }
 }
 
 // Test helpers
 
-pub fn assert_stack_effect(instruction: Instruction, before: &[Value], after: &[Value]) -> Result<()> {
-    let mut env = VMTestEnvironment::new(VMTestConfig::default())?;
-    
-    // Setup initial stack
-    for value in before {
-        env.vm.stack_push(value.clone())?;
+/// A contract whose one function takes `arity` arguments, runs `code` on
+/// them and returns
+fn effect_module(constants: &[Constant], arity: usize, code: Vec<Instruction>) -> Module {
+    let mut module = Module::new("Effect");
+    module.constants = constants.to_vec();
+    let mut body: Vec<Instruction> = (0..arity as u32).map(Instruction::Load).collect();
+    body.extend(code);
+    body.push(Instruction::Return);
+    module.functions.push(Function {
+        name: "effect".into(),
+        arity: arity as u8,
+        locals: arity as u32,
+        is_pure: false,
+        code: body,
+    });
+    module
+}
+
+/// Runs `code` on `inputs` in a fresh VM, interpreted or compiled on first
+/// call
+fn run_effect(
+    constants: &[Constant],
+    inputs: &[Value],
+    code: Vec<Instruction>,
+    jit_threshold: Option<u32>,
+) -> Result<Outcome> {
+    let mut env = VMTestEnvironment::new(VMTestConfig { jit_threshold, ..Default::default() })?;
+    env.deploy(EFFECT_CONTRACT, effect_module(constants, inputs.len(), code))?;
+    env.call(&EFFECT_CONTRACT, "effect", inputs.to_vec())
+}
+
+/// Runs `code` in both tiers, which must agree on the result and the gas
+fn run_both_tiers(constants: &[Constant], inputs: &[Value], code: Vec<Instruction>) -> Result<Outcome> {
+    let interpreted = run_effect(constants, inputs, code.clone(), None)?;
+    let compiled = run_effect(constants, inputs, code, Some(0))?;
+    if (&compiled.result, compiled.gas_used) != (&interpreted.result, interpreted.gas_used) {
+        return Err(Error::TestAssertion(format!(
+            "Tiers disagree: interpreted {:?} for {} gas, compiled {:?} for {} gas",
+            interpreted.result,
+            interpreted.gas_used,
+            compiled.result,
+            compiled.gas_used
+        )));
     }
-    
-    // Execute instruction
-    env.vm.execute_instruction(instruction)?;
-    
-    // Verify stack state
-    for expected in after.iter().rev() {
-        let actual = env.vm.stack_pop()?;
-        if actual != *expected {
-            return Err(Error::TestAssertion("Stack effect mismatch".into()));
-        }
+    Ok(interpreted)
+}
+
+/// Checks that `instruction` turns the operands `before` into exactly
+/// `after`, bottom of the stack first, in the interpreter and in compiled
+/// code. A trap comes back as its [`trap_error`].
+pub fn assert_stack_effect(instruction: Instruction, before: &[Value], after: &[Value]) -> Result<()> {
+    assert_stack_effect_with(&[], instruction, before, after)
+}
+
+/// [`assert_stack_effect`] for an instruction that refers to `constants`
+pub fn assert_stack_effect_with(
+    constants: &[Constant],
+    instruction: Instruction,
+    before: &[Value],
+    after: &[Value],
+) -> Result<()> {
+    let collect = |count: usize| vec![instruction, Instruction::Tuple(count as u8)];
+    let actual = match run_both_tiers(constants, before, collect(after.len()))?.result.map_err(trap_error)? {
+        Some(Value::Tuple(values)) => values,
+        other => return Err(Error::TestAssertion(format!("Expected the stack as a tuple, got {:?}", other))),
+    };
+    if actual != after {
+        return Err(Error::TestAssertion(format!(
+            "Stack effect mismatch: expected {:?}, got {:?}",
+            after,
+            actual
+        )));
     }
-    
-    // Verify stack is empty
-    if !env.vm.stack_is_empty() {
+
+    // Collecting one more operand only succeeds if something was left over
+    if run_both_tiers(constants, before, collect(after.len() + 1))?.result.is_ok() {
         return Err(Error::TestAssertion("Stack not empty after instruction".into()));
     }
-    
+
     Ok(())
 }
 
 pub fn assert_gas_cost(instruction: Instruction, inputs: &[Value], expected_cost: u64) -> Result<()> {
-    let mut env = VMTestEnvironment::new(VMTestConfig {
-        gas_metering: true,
-        ..Default::default()
-    })?;
-    
-    // Setup initial stack
-    for value in inputs {
-        env.vm.stack_push(value.clone())?;
-    }
-    
-    // Execute instruction and measure gas
-    let gas_before = env.vm.gas_remaining();
-    env.vm.execute_instruction(instruction)?;
-    let gas_used = gas_before - env.vm.gas_remaining();
-    
+    let outcome = run_effect(&[], inputs, vec![instruction], None)?;
+    outcome.result.map_err(trap_error)?;
+
+    // Everything but the instruction is loading its operands and returning
+    let overhead = inputs.len() as u64 * Instruction::Load(0).gas_cost() + Instruction::Return.gas_cost();
+    let gas_used = outcome.gas_used - overhead;
     if gas_used != expected_cost {
         return Err(Error::TestAssertion(format!(
             "Gas cost mismatch: expected {}, got {}",
@@ -235,32 +240,103 @@ pub fn assert_gas_cost(instruction: Instruction, inputs: &[Value], expected_cost
             gas_used
         )));
     }
-    
+
     Ok(())
 }
 
-pub fn assert_memory_effect(
-    instruction: Instruction,
-    initial_memory: &[u8],
-    offset: usize,
-    expected_memory: &[u8]
-) -> Result<()> {
-    let mut env = VMTestEnvironment::new(VMTestConfig {
-        memory_tracking: true,
-        ..Default::default()
-    })?;
-    
-    // Setup initial memory
-    env.vm.memory_write(offset, initial_memory)?;
-    
-    // Execute instruction
-    env.vm.execute_instruction(instruction)?;
-    
-    // Verify memory state
-    let actual_memory = env.vm.memory_read(offset, expected_memory.len())?;
-    if actual_memory != expected_memory {
-        return Err(Error::TestAssertion("Memory effect mismatch".into()));
+#[cfg(test)]
+mod conformance;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stremax_runtime::num::U256;
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
     }
-    
-    Ok(())
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_stack_effects() {
+        assert_stack_effect(Instruction::Add, &[word(2), word(3)], &[word(5)]).unwrap();
+        assert_stack_effect(Instruction::Dup(1), &[word(1), word(2)], &[word(1), word(2), word(1)]).unwrap();
+        assert!(matches!(
+            assert_stack_effect(Instruction::Add, &[word(2), word(3)], &[word(6)]),
+            Err(Error::TestAssertion(_))
+        ));
+        // A leftover operand fails the assertion
+        assert!(matches!(
+            assert_stack_effect(Instruction::Dup(0), &[word(1)], &[word(1)]),
+            Err(Error::TestAssertion(_))
+        ));
+        assert!(matches!(
+            assert_stack_effect(Instruction::Div, &[word(1), word(0)], &[]),
+            Err(Error::DivisionByZero)
+        ));
+    }
+
+    #[test]
+    fn test_gas_cost() {
+        assert_gas_cost(Instruction::Add, &[word(2), word(3)], Instruction::Add.gas_cost()).unwrap();
+        assert!(assert_gas_cost(Instruction::Add, &[word(2), word(3)], 0).is_err());
+    }
+
+    #[test]
+    fn test_environment_records_gas() {
+        let mut env = VMTestEnvironment::new(VMTestConfig { jit_threshold: Some(0), ..Default::default() }).unwrap();
+        env.deploy(EFFECT_CONTRACT, effect_module(&[], 2, vec![Instruction::Mul])).unwrap();
+        let outcome = env.call(&EFFECT_CONTRACT, "effect", vec![word(6), word(7)]).unwrap();
+        assert_eq!(outcome.result, Ok(Some(word(42))));
+        assert_eq!(env.vm().compiled_functions(&EFFECT_CONTRACT), vec!["effect"]);
+        assert_eq!(env.get_gas_usage()[0].gas_used, outcome.gas_used);
+    }
+
+    #[test]
+    fn test_environment_records_steps() {
+        let mut env = VMTestEnvironment::new(VMTestConfig { record: true, ..Default::default() }).unwrap();
+        env.deploy(EFFECT_CONTRACT, effect_module(&[], 2, vec![Instruction::Mul])).unwrap();
+        env.call(&EFFECT_CONTRACT, "effect", vec![word(6), word(7)]).unwrap();
+        assert!(env.vm().compiled_functions(&EFFECT_CONTRACT).is_empty());
+
+        let recording = env.last_recording().unwrap();
+        let pcs: Vec<usize> = recording.steps.iter().map(|step| step.pc).collect();
+        assert_eq!(pcs, [0, 1, 2, 3]);
+        let before_return = recording.snapshot_at(3);
+        assert_eq!(before_return.frames, [FrameMemory { locals: vec![word(6), word(7)], stack: vec![word(42)] }]);
+        assert_eq!(recording.steps[2].changes, [Change::Stack { keep: 0, popped: vec![word(6), word(7)], pushed: vec![word(42)] }]);
+    }
+
+    #[test]
+    fn test_environment_traces_alongside_recording() {
+        let config = VMTestConfig { record: true, trace: Some(TraceKind::Calls), ..Default::default() };
+        let mut env = VMTestEnvironment::new(config).unwrap();
+        env.deploy(EFFECT_CONTRACT, effect_module(&[], 2, vec![Instruction::Mul])).unwrap();
+        let outcome = env.call(&EFFECT_CONTRACT, "effect", vec![word(6), word(7)]).unwrap();
+        assert_eq!(env.last_recording().map(|recording| recording.steps.len()), Some(4));
+
+        let Some(Trace::Calls(calls)) = env.last_trace() else {
+            panic!("expected a call trace, got {:?}", env.last_trace());
+        };
+        assert_eq!(calls.len(), 1);
+        assert_eq!((&calls[0].input, &calls[0].output), (&vec![word(6), word(7)], &Some(word(42))));
+        assert_eq!(calls[0].gas_used, outcome.gas_used);
+    }
+
+    #[test]
+    fn test_trace_kind_selects_the_tracer() {
+        let trace_of_mul = |trace| {
+            let mut env = VMTestEnvironment::new(VMTestConfig { trace, ..Default::default() }).unwrap();
+            env.deploy(EFFECT_CONTRACT, effect_module(&[], 2, vec![Instruction::Mul])).unwrap();
+            env.call(&EFFECT_CONTRACT, "effect", vec![word(6), word(7)]).unwrap();
+            assert!(env.last_recording().is_none());
+            env.last_trace().cloned()
+        };
+        assert_eq!(trace_of_mul(None), None);
+        let Some(Trace::Struct(logs)) = trace_of_mul(Some(TraceKind::Struct)) else {
+            panic!("expected a struct log");
+        };
+        let ops: Vec<&str> = logs.iter().map(|log| log.op.as_str()).collect();
+        assert_eq!(ops, ["LOAD", "LOAD", "MUL", "RET"]);
+        assert!(matches!(trace_of_mul(Some(TraceKind::Calls)), Some(Trace::Calls(calls)) if calls.len() == 1));
+    }
+}
@@ -0,0 +1,524 @@
+//! Debug Adapter Protocol server, for editors to drive the [`Debugger`].
+//!
+//! `strxvm dap` speaks DAP over stdio, or over one TCP connection with
+//! `--port`. A `launch` request names the `program` (a `.strxb` container
+//! or `.asm` file), the `function` to call, its `args` as `strxvm run`
+//! takes them, and optionally `gas`, `stopOnEntry` and the `cwd` that
+//! relative source paths are resolved against. Each frame has scopes for
+//! its locals, the contract's storage and its operand stack. Hovering
+//! evaluates a variable name. Breakpoint conditions are
+//! `<variable> == <value>`, `>`, `<` or `<variable> changed`. Execution
+//! steps and continues backwards too, and stops with an exception where a
+//! call trapped.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value as Json};
+use stremax_runtime::bytecode::{self, Type};
+use stremax_runtime::{Address, Value, Vm};
+
+use super::{Debugger, DebuggerState, Variable, WatchCondition};
+
+/// Where `launch` deploys the program
+const CONTRACT: Address = [0xcc; 20];
+
+const DEFAULT_GAS: u64 = 10_000_000;
+
+/// A call runs on a single thread
+const THREAD: i64 = 1;
+
+/// Variable references number the scopes of every frame in turn
+const SCOPES_PER_FRAME: i64 = 3;
+
+/// Serves one session on stdin and stdout
+pub fn serve_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    Session::new(io::stdout()).serve(stdin.lock())
+}
+
+/// Serves one session on the first connection to `port` on localhost
+pub fn serve_tcp(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    Session::new(stream.try_clone()?).serve(BufReader::new(stream))
+}
+
+/// One editor's session: the launched call and the breakpoints set in it
+pub struct Session<W: Write> {
+    output: W,
+    seq: i64,
+    debugger: Option<Debugger>,
+    stop_on_entry: bool,
+    /// Where relative source paths in the debug info start from
+    root: PathBuf,
+    /// Ids of the breakpoints set in each source file
+    source_breakpoints: HashMap<String, Vec<u32>>,
+    function_breakpoints: Vec<u32>,
+    terminated: bool,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(output: W) -> Self {
+        Session {
+            output,
+            seq: 1,
+            debugger: None,
+            stop_on_entry: false,
+            root: PathBuf::new(),
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            terminated: false,
+        }
+    }
+
+    /// Answers requests until the editor disconnects or closes `input`
+    pub fn serve(&mut self, mut input: impl BufRead) -> io::Result<()> {
+        while let Some(request) = read_message(&mut input)? {
+            if !self.handle(&request)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers one request, and reports whether the session goes on
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsStepBack": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setExceptionBreakpoints" | "configurationDone" | "disconnect" | "terminate" => Ok(json!({})),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "call" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(arguments),
+            "variables" => self.variables(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => self.resume(Debugger::run).map(|_| json!({ "allThreadsContinued": true })),
+            "next" => self.resume(Debugger::step_over).map(|_| json!({})),
+            "stepIn" => self.resume(Debugger::step_line).map(|_| json!({})),
+            "stepOut" => self.resume(Debugger::step_out).map(|_| json!({})),
+            "stepBack" => self.resume(|debugger| debugger.reverse_step().map(drop)).map(|_| json!({})),
+            "reverseContinue" => self.resume(Debugger::reverse_continue).map(|_| json!({})),
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+        let succeeded = body.is_ok();
+        self.respond(request, body)?;
+        if !succeeded {
+            return Ok(true);
+        }
+
+        match command {
+            "launch" => self.event("initialized", json!({}))?,
+            "configurationDone" if self.stop_on_entry => self.report_stop("entry")?,
+            "configurationDone" => {
+                let run = self.resume(Debugger::run);
+                self.report_stop(if run.is_ok() { "breakpoint" } else { "exception" })?;
+            }
+            "continue" | "reverseContinue" => self.report_stop("breakpoint")?,
+            "next" | "stepIn" | "stepOut" | "stepBack" => self.report_stop("step")?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        let program = arguments["program"].as_str().ok_or("`launch` needs a `program`")?;
+        let function = arguments["function"].as_str().ok_or("`launch` needs a `function`")?;
+        let args: Vec<String> = match arguments["args"].as_array() {
+            Some(args) => args.iter()
+                .map(|arg| arg.as_str().map_or_else(|| arg.to_string(), str::to_string))
+                .collect(),
+            None => Vec::new(),
+        };
+        let gas = arguments["gas"].as_u64().unwrap_or(DEFAULT_GAS);
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.root = arguments["cwd"].as_str().map(PathBuf::from).unwrap_or_default();
+
+        let module = bytecode::load(Path::new(program))?;
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let args = Value::parse_args(&module, function, &args)?;
+
+        let mut vm = Vm::new();
+        vm.set_jit_threshold(None);
+        vm.deploy(CONTRACT, module)?;
+        let mut debugger = Debugger::new(vm);
+        debugger.launch(&CONTRACT, function, args, gas).map_err(|e| e.to_string())?;
+        self.debugger = Some(debugger);
+        self.terminated = false;
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let source = &arguments["source"];
+        let file = source["path"].as_str().or_else(|| source["name"].as_str())
+            .ok_or("`setBreakpoints` needs a source")?
+            .to_string();
+        let debugger = self.debugger.as_mut().ok_or("no program is launched")?;
+        for id in self.source_breakpoints.remove(&file).unwrap_or_default() {
+            debugger.remove_breakpoint(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or_default();
+            let spec = format!("{}:{}", file, line);
+            match add_breakpoint(debugger, &spec, requested["condition"].as_str()) {
+                Ok((id, location)) => {
+                    ids.push(id);
+                    let line = debugger.source_of(location).map_or(line as usize, |at| at.line);
+                    results.push(json!({ "id": id, "verified": true, "line": line }));
+                }
+                Err(message) => results.push(json!({ "verified": false, "line": line, "message": message })),
+            }
+        }
+        self.source_breakpoints.insert(file, ids);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_function_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.debugger.as_mut().ok_or("no program is launched")?;
+        for id in self.function_breakpoints.drain(..) {
+            debugger.remove_breakpoint(id);
+        }
+
+        let mut results = Vec::new();
+        for requested in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let spec = format!("fn {}", requested["name"].as_str().unwrap_or_default());
+            match add_breakpoint(debugger, &spec, requested["condition"].as_str()) {
+                Ok((id, _)) => {
+                    self.function_breakpoints.push(id);
+                    results.push(json!({ "id": id, "verified": true }));
+                }
+                Err(message) => results.push(json!({ "verified": false, "message": message })),
+            }
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn stack_trace(&self) -> Result<Json, String> {
+        let frames = self.paused()?.get_stack_trace();
+        let frames: Vec<Json> = frames.iter().enumerate()
+            .map(|(id, frame)| {
+                let mut json = json!({
+                    "id": id,
+                    "name": frame.function,
+                    "line": 0,
+                    "column": 0,
+                });
+                if let Some(location) = &frame.location {
+                    let path = self.root.join(&location.file);
+                    let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+                    json["source"] = json!({ "name": name, "path": path });
+                    json["line"] = json!(location.line);
+                    json["column"] = json!(location.column);
+                }
+                json
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn scopes(&self, arguments: &Json) -> Result<Json, String> {
+        let frame = arguments["frameId"].as_i64().unwrap_or_default();
+        let reference = |scope: i64| frame * SCOPES_PER_FRAME + scope + 1;
+        Ok(json!({ "scopes": [
+            { "name": "Locals", "presentationHint": "locals", "variablesReference": reference(0), "expensive": false },
+            { "name": "Storage", "variablesReference": reference(1), "expensive": false },
+            { "name": "Operand stack", "variablesReference": reference(2), "expensive": false },
+        ] }))
+    }
+
+    fn variables(&self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.paused()?;
+        let reference = arguments["variablesReference"].as_i64().unwrap_or_default() - 1;
+        let (frame, scope) = (reference / SCOPES_PER_FRAME, reference % SCOPES_PER_FRAME);
+        let frames = debugger.get_stack_trace();
+        let frame = usize::try_from(frame).ok().and_then(|frame| frames.get(frame))
+            .ok_or("no such frame")?;
+
+        let variables: Vec<Json> = match scope {
+            0 => frame.locals.iter().map(variable).collect(),
+            1 => debugger.storage_variables().map_err(|e| e.to_string())?.iter().map(variable).collect(),
+            // The top of the stack first
+            _ => frame.stack.iter().rev().enumerate()
+                .map(|(depth, value)| json!({
+                    "name": format!("[{}]", depth),
+                    "value": value.to_string(),
+                    "variablesReference": 0,
+                }))
+                .collect(),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&self, arguments: &Json) -> Result<Json, String> {
+        let debugger = self.paused()?;
+        let expression = arguments["expression"].as_str().unwrap_or_default().trim();
+        let frame = arguments["frameId"].as_u64().unwrap_or_default() as usize;
+        let frames = debugger.get_stack_trace();
+        let found = frames.get(frame).into_iter().flat_map(|frame| frame.locals.iter().cloned())
+            .chain(debugger.storage_variables().unwrap_or_default())
+            .find(|variable| variable.name == expression)
+            .ok_or_else(|| format!("`{}` is not in scope", expression))?;
+        Ok(json!({ "result": found.value.to_string(), "type": found.ty, "variablesReference": 0 }))
+    }
+
+    /// Moves execution on with `action`
+    fn resume(&mut self, action: fn(&mut Debugger) -> crate::core::Result<()>) -> Result<(), String> {
+        let debugger = self.debugger.as_mut().ok_or("no program is launched")?;
+        action(debugger).map_err(|e| e.to_string())
+    }
+
+    fn paused(&self) -> Result<&Debugger, String> {
+        match &self.debugger {
+            Some(debugger) if *debugger.state() == DebuggerState::Paused => Ok(debugger),
+            _ => Err("the call is not paused".into()),
+        }
+    }
+
+    /// Tells the editor where execution stopped, or that the call ended
+    fn report_stop(&mut self, reason: &str) -> io::Result<()> {
+        let Some(debugger) = &self.debugger else {
+            return Ok(());
+        };
+        if let Some(trap) = debugger.trap() {
+            let body = json!({ "reason": "exception", "description": trap.to_string(), "threadId": THREAD, "allThreadsStopped": true });
+            return self.event("stopped", body);
+        }
+        if *debugger.state() == DebuggerState::Paused {
+            return self.event("stopped", json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true }));
+        }
+        if self.terminated {
+            return Ok(());
+        }
+        self.terminated = true;
+
+        let (output, exit_code) = match debugger.outcome().map(|outcome| (&outcome.result, outcome.gas_used)) {
+            Some((Ok(Some(value)), gas)) => (format!("{}\ngas used: {}\n", value, gas), 0),
+            Some((Ok(None), gas)) => (format!("gas used: {}\n", gas), 0),
+            Some((Err(trap), gas)) => (format!("error: {}\ngas used: {}\n", trap, gas), 1),
+            None => (String::new(), 0),
+        };
+        self.event("output", json!({ "category": "console", "output": output }))?;
+        self.event("exited", json!({ "exitCode": exit_code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+        });
+        match body {
+            Ok(body) => {
+                response["success"] = json!(true);
+                response["body"] = body;
+            }
+            Err(message) => {
+                response["success"] = json!(false);
+                response["message"] = json!(message);
+            }
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+/// Sets a breakpoint with an optional condition, returning its id and the
+/// first instruction it stops at
+fn add_breakpoint(debugger: &mut Debugger, spec: &str, condition: Option<&str>) -> Result<(u32, super::CodeLocation), String> {
+    let breakpoint = match condition.map(str::trim).filter(|condition| !condition.is_empty()) {
+        Some(condition) => {
+            let (variable, condition) = parse_condition(condition)?;
+            debugger.add_conditional_breakpoint(spec, &variable, condition)
+        }
+        None => debugger.add_breakpoint(spec),
+    };
+    let breakpoint = breakpoint.map_err(|e| e.to_string())?;
+    Ok((breakpoint.id, breakpoint.locations[0]))
+}
+
+/// Reads `<variable> == <value>`, `>`, `<` or `<variable> changed`
+pub(super) fn parse_condition(text: &str) -> Result<(String, WatchCondition), String> {
+    if let Some(variable) = text.strip_suffix("changed") {
+        return Ok((variable.trim().to_string(), WatchCondition::Changed));
+    }
+    for (operator, condition) in [("==", WatchCondition::Equals as fn(Value) -> WatchCondition),
+                                  (">", WatchCondition::GreaterThan),
+                                  ("<", WatchCondition::LessThan)] {
+        if let Some((variable, value)) = text.split_once(operator) {
+            return Ok((variable.trim().to_string(), condition(parse_literal(value.trim())?)));
+        }
+    }
+    Err(format!("expected `<variable> == <value>`, `>`, `<` or `<variable> changed`, found `{}`", text))
+}
+
+/// Reads a number, `true` or `false`, a quoted string, or `0x` hex: an
+/// address at 20 bytes and a byte string otherwise
+fn parse_literal(text: &str) -> Result<Value, String> {
+    let ty = match text {
+        "true" | "false" => Type::Bool,
+        _ if text.starts_with('"') => {
+            let string = text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"'));
+            return string.map(|s| Value::String(s.to_string())).ok_or_else(|| format!("unterminated string `{}`", text));
+        }
+        _ if text.starts_with("0x") && text.len() == 42 => Type::Address,
+        _ if text.starts_with("0x") => Type::Bytes,
+        _ => Type::U256,
+    };
+    Value::parse(&ty, text)
+}
+
+fn variable(variable: &Variable) -> Json {
+    json!({
+        "name": variable.name,
+        "value": variable.value.to_string(),
+        "type": variable.ty,
+        "variablesReference": 0,
+    })
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of the
+/// input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::WALLET;
+
+    fn frame(message: Json) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn messages(mut output: &[u8]) -> Vec<Json> {
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert!(matches!(parse_condition("paid changed"), Ok((name, WatchCondition::Changed)) if name == "paid"));
+        assert!(matches!(parse_condition("amount > 5"), Ok((_, WatchCondition::GreaterThan(Value::U256(_))))));
+        assert!(matches!(parse_condition("ok == true"), Ok((_, WatchCondition::Equals(Value::Bool(true))))));
+        assert!(matches!(parse_literal(&format!("0x{}", "ab".repeat(20))), Ok(Value::Address(_))));
+        assert!(parse_condition("amount").is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let program = std::env::temp_dir().join(format!("stremax-dap-{}.asm", std::process::id()));
+        std::fs::write(&program, WALLET).unwrap();
+        let requests = [
+            json!({ "seq": 1, "command": "initialize", "arguments": {} }),
+            json!({ "seq": 2, "command": "launch", "arguments": {
+                "program": program, "function": "withdraw", "args": ["500"], "cwd": "/work",
+            } }),
+            json!({ "seq": 3, "command": "setBreakpoints", "arguments": {
+                "source": { "path": "/work/contracts/wallet.strx" },
+                "breakpoints": [{ "line": 16, "condition": "amount == 500" }, { "line": 11, "condition": "paid > 10" }],
+            } }),
+            json!({ "seq": 4, "command": "setFunctionBreakpoints", "arguments": {
+                "breakpoints": [{ "name": "fee", "condition": "amount > 1000" }, { "name": "missing" }],
+            } }),
+            json!({ "seq": 5, "command": "configurationDone" }),
+            json!({ "seq": 6, "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 7, "command": "variables", "arguments": { "variablesReference": 6 } }),
+            json!({ "seq": 8, "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 9, "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "seq": 10, "command": "evaluate", "arguments": { "expression": "balance", "context": "hover" } }),
+            json!({ "seq": 11, "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 12, "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 13, "command": "reverseContinue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 14, "command": "stepBack", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 15, "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 16, "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 17, "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "seq": 18, "command": "disconnect" }),
+        ];
+        let input: String = requests.into_iter().map(frame).collect();
+        let mut session = Session::new(Vec::new());
+        session.serve(input.as_bytes()).unwrap();
+        std::fs::remove_file(&program).unwrap();
+
+        let messages = messages(&session.output);
+        let response = |seq: i64| messages.iter()
+            .find(|m| m["type"] == "response" && m["request_seq"] == seq)
+            .unwrap_or_else(|| panic!("no response to {}", seq));
+        let events: Vec<&str> = messages.iter().filter_map(|m| m["event"].as_str()).collect();
+        assert_eq!((events[0], &events[events.len() - 4..]), ("initialized", &["stopped", "output", "exited", "terminated"][..]));
+        let reasons: Vec<&Json> = messages.iter().filter(|m| m["event"] == "stopped").map(|m| &m["body"]["reason"]).collect();
+        assert_eq!(reasons, ["breakpoint", "breakpoint", "exception", "breakpoint", "step", "breakpoint", "exception"]);
+        assert!(messages.iter().all(|m| m["type"] != "response" || m["success"] == true), "{:?}", messages);
+
+        assert_eq!(response(1)["body"]["supportsConditionalBreakpoints"], true);
+        assert_eq!(response(3)["body"]["breakpoints"][1], json!({ "id": 2, "verified": true, "line": 11 }));
+        assert_eq!(response(4)["body"]["breakpoints"][1]["verified"], false);
+
+        // Stopped in `fee`, with the argument still on the caller's stack
+        let frames = &response(6)["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "fee");
+        assert_eq!(frames[0]["source"]["path"], "/work/contracts/wallet.strx");
+        assert_eq!((&frames[1]["name"], &frames[1]["line"]), (&json!("withdraw"), &json!(10)));
+        assert_eq!(response(7)["body"]["variables"][0]["value"], "500");
+
+        // Stopped at line 11 once `paid` is known
+        let locals = &response(9)["body"]["variables"];
+        assert_eq!(locals[0], json!({ "name": "amount", "value": "500", "type": "u256", "variablesReference": 0 }));
+        assert_eq!(locals[1]["value"], "505");
+        assert_eq!(response(10)["body"]["result"], "0");
+
+        // Paying 505 out of an empty balance traps, which stops on the `SUB`
+        assert_eq!(response(12)["body"]["stackFrames"][0]["line"], 11);
+        assert_eq!(messages.iter().find(|m| m["event"] == "exited").unwrap()["body"]["exitCode"], 1);
+    }
+}
@@ -1,36 +1,115 @@
-use crate::core::{Result, Error};
-use crate::runtime::{VM, Value, Instruction};
-use std::collections::{HashMap, HashSet};
+//! Source-level debugger over the runtime's [`Vm`].
+//!
+//! The VM runs a call to completion, so [`Debugger::launch`] records it
+//! with a [`Recorder`], and stepping, continuing and inspecting move
+//! through the recording, backwards as well as forwards: every change an
+//! instruction made can be undone. A call that traps pauses on the
+//! instruction that trapped, for working back from there to its cause.
+//! Names come from the debug section `strxc -g` writes: `wallet.strx:42`
+//! and `fn transfer` resolve to instructions, locals to the variables in
+//! scope and storage slots to the contract's state variables. [`dap`]
+//! serves all of it to editors over the Debug Adapter Protocol, and
+//! [`repl`] at a command line.
+
+pub mod dap;
+pub mod repl;
+
+use crate::core::{Error, Result};
+use stremax_runtime::bytecode::{format_instruction, DebugInfo, Module, StorageEntry, Type};
+use stremax_runtime::num::U256;
+use stremax_runtime::record::FrameMemory;
+use stremax_runtime::{Address, ExecutionStep, MemorySnapshot, Outcome, Recorder, Recording, Trap, Value, Vm};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
 /// Debugger for VM inspection and control
 pub struct Debugger {
-    vm: VM,
-    breakpoints: HashSet<usize>,
+    vm: Vm,
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint: u32,
     watchpoints: HashMap<String, WatchCondition>,
-    call_stack: Vec<StackFrame>,
+    /// What the conditions of `Changed` breakpoints last saw, by breakpoint
+    last_seen: HashMap<u32, Value>,
+    recording: Recording,
+    /// The instruction execution is paused before, or the number of
+    /// instructions once the call has ended
+    position: usize,
+    /// The state at `position`
+    snapshot: MemorySnapshot,
+    outcome: Option<Outcome>,
+    /// Gas the launched call was given
+    gas_limit: u64,
     state: DebuggerState,
 }
 
 /// Represents the current state of the debugger
 #[derive(Clone, Debug, PartialEq)]
 pub enum DebuggerState {
-    Running,
+    /// No call has been launched
+    Idle,
     Paused,
-    Stepping,
-    StepOver,
-    StepOut,
+    /// The call ran to its end
     Terminated,
 }
 
+/// An instruction of a deployed function
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CodeLocation {
+    pub contract: Address,
+    pub function: u32,
+    pub pc: usize,
+}
+
+/// A breakpoint and the instructions it stops at
+pub struct Breakpoint {
+    pub id: u32,
+    /// As given: `file.strx:42` or `fn transfer`
+    pub spec: String,
+    pub locations: Vec<CodeLocation>,
+    /// A variable and what must hold of it for the breakpoint to stop.
+    /// `Changed` compares with the value execution last passed it with.
+    pub condition: Option<(String, WatchCondition)>,
+}
+
 /// Represents a stack frame
 #[derive(Clone, Debug)]
 pub struct StackFrame {
     pub function: String,
     pub pc: usize,
-    pub locals: HashMap<String, Value>,
+    pub location: Option<SourceLocation>,
+    pub locals: Vec<Variable>,
+    /// The frame's operands, the top last
+    pub stack: Vec<Value>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
 }
 
-/// Watch condition for variables
+/// A local or state variable with its value at the paused instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub ty: String,
+    pub value: Value,
+}
+
+/// An instruction of the recorded call that changed a storage slot
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageWrite {
+    /// Index of the instruction in the recording
+    pub step: usize,
+    pub location: CodeLocation,
+    pub source: Option<SourceLocation>,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Watch condition for variables. A watchpoint stops execution when its
+/// condition becomes true, not while it stays true.
 pub enum WatchCondition {
     Changed,
     Equals(Value),
@@ -40,200 +119,673 @@ pub enum WatchCondition {
 }
 
 impl Debugger {
-    pub fn new(vm: VM) -> Self {
+    pub fn new(vm: Vm) -> Self {
         Debugger {
             vm,
-            breakpoints: HashSet::new(),
+            breakpoints: Vec::new(),
+            next_breakpoint: 1,
             watchpoints: HashMap::new(),
-            call_stack: Vec::new(),
-            state: DebuggerState::Paused,
+            last_seen: HashMap::new(),
+            recording: Recording::default(),
+            position: 0,
+            snapshot: MemorySnapshot::default(),
+            outcome: None,
+            gas_limit: 0,
+            state: DebuggerState::Idle,
         }
     }
-    
+
+    /// The VM, to deploy contracts and set up state before a launch
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    pub fn state(&self) -> &DebuggerState {
+        &self.state
+    }
+
+    /// The result of the launched call, known as soon as it is launched
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.outcome.as_ref()
+    }
+
+    /// Every instruction the launched call ran, and what each changed
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
     // Execution Control
-    
-    pub fn run(&mut self) -> Result<()> {
-        self.state = DebuggerState::Running;
-        
-        while self.state == DebuggerState::Running {
-            if !self.step()? {
-                self.state = DebuggerState::Terminated;
-                break;
-            }
-            
-            // Check breakpoints
-            if self.breakpoints.contains(&self.vm.pc) {
-                self.state = DebuggerState::Paused;
-                break;
-            }
-            
-            // Check watchpoints
-            if self.check_watchpoints()? {
-                self.state = DebuggerState::Paused;
-                break;
-            }
-        }
-        
+
+    /// Runs `function` of the contract at `address` as a transaction and
+    /// pauses before its first instruction
+    pub fn launch(&mut self, address: &Address, function: &str, args: Vec<Value>, gas_limit: u64) -> Result<()> {
+        let recorder = Recorder::new(&self.vm);
+        self.vm.set_tracer(Some(Box::new(recorder.clone())));
+        let outcome = self.vm.call(address, function, args, gas_limit);
+        self.vm.set_tracer(None);
+
+        self.outcome = Some(outcome.map_err(Error::ContractError)?);
+        self.gas_limit = gas_limit;
+        self.recording = recorder.finish();
+        self.snapshot = self.recording.initial.clone();
+        self.position = 0;
+        self.last_seen.clear();
+        self.state = if self.recording.steps.is_empty() { DebuggerState::Terminated } else { DebuggerState::Paused };
         Ok(())
     }
-    
+
+    /// Continues to the next breakpoint or triggered watchpoint
+    pub fn run(&mut self) -> Result<()> {
+        self.travel(true, true, |_| false)
+    }
+
+    /// Runs one instruction, and reports whether the call is still paused
+    /// inside
     pub fn step(&mut self) -> Result<bool> {
-        if self.vm.pc >= self.vm.program.len() {
-            return Ok(false);
-        }
-        
-        // Execute single instruction
-        let instruction = &self.vm.program[self.vm.pc];
-        self.vm.context.gas_used += instruction.gas_cost();
-        instruction.execute(&mut self.vm)?;
-        
-        // Update call stack
-        match instruction {
-            Instruction::Call(_) => {
-                self.call_stack.push(self.current_frame()?);
-            }
-            Instruction::Return => {
-                self.call_stack.pop();
-            }
-            _ => {}
-        }
-        
-        self.vm.pc += 1;
-        Ok(true)
+        self.travel(true, false, |_| true)?;
+        Ok(self.state == DebuggerState::Paused)
+    }
+
+    /// Runs to the start of the next statement, entering calls
+    pub fn step_line(&mut self) -> Result<()> {
+        self.travel(true, false, |debugger| debugger.at_statement())
     }
-    
+
+    /// Runs to the start of the next statement in this frame or a caller
     pub fn step_over(&mut self) -> Result<()> {
-        let current_depth = self.call_stack.len();
-        self.state = DebuggerState::StepOver;
-        
-        while self.state == DebuggerState::StepOver {
-            if !self.step()? {
-                self.state = DebuggerState::Terminated;
-                break;
-            }
-            
-            if self.call_stack.len() <= current_depth {
-                self.state = DebuggerState::Paused;
-                break;
-            }
-        }
-        
-        Ok(())
+        let depth = self.current()?.depth;
+        self.travel(true, false, |debugger| debugger.depth() <= depth && debugger.at_statement())
     }
-    
+
+    /// Runs until the current function has returned
     pub fn step_out(&mut self) -> Result<()> {
-        let target_depth = self.call_stack.len().saturating_sub(1);
-        self.state = DebuggerState::StepOut;
-        
-        while self.state == DebuggerState::StepOut {
-            if !self.step()? {
-                self.state = DebuggerState::Terminated;
-                break;
-            }
-            
-            if self.call_stack.len() <= target_depth {
-                self.state = DebuggerState::Paused;
-                break;
-            }
-        }
-        
-        Ok(())
+        let depth = self.current()?.depth;
+        self.travel(true, false, |debugger| debugger.depth() < depth)
     }
-    
+
+    /// Goes back to before the previous instruction, and reports whether
+    /// there was one
+    pub fn reverse_step(&mut self) -> Result<bool> {
+        let moved = self.position > 0;
+        self.travel(false, false, |_| true)?;
+        Ok(moved)
+    }
+
+    /// Goes back to the previous breakpoint or triggered watchpoint, or to
+    /// the start of the call
+    pub fn reverse_continue(&mut self) -> Result<()> {
+        self.travel(false, true, |_| false)
+    }
+
     // Breakpoint Management
-    
-    pub fn add_breakpoint(&mut self, pc: usize) {
-        self.breakpoints.insert(pc);
+
+    /// Sets a breakpoint at `file.strx:42`, the first statement at or after
+    /// line 42 of that file, or at `fn transfer`, the entry of every
+    /// deployed function of that name
+    pub fn add_breakpoint(&mut self, spec: &str) -> Result<&Breakpoint> {
+        self.insert_breakpoint(spec, None)
+    }
+
+    /// Sets a breakpoint that only stops when `condition` holds of the
+    /// local or state variable `variable`
+    pub fn add_conditional_breakpoint(&mut self, spec: &str, variable: &str, condition: WatchCondition) -> Result<&Breakpoint> {
+        self.insert_breakpoint(spec, Some((variable.to_string(), condition)))
+    }
+
+    pub fn remove_breakpoint(&mut self, id: u32) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != before
     }
-    
-    pub fn remove_breakpoint(&mut self, pc: usize) {
-        self.breakpoints.remove(&pc);
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
     }
-    
+
     pub fn add_watchpoint(&mut self, name: String, condition: WatchCondition) {
         self.watchpoints.insert(name, condition);
     }
-    
+
     pub fn remove_watchpoint(&mut self, name: &str) {
         self.watchpoints.remove(name);
     }
-    
+
     // State Inspection
-    
+
+    pub fn current_location(&self) -> Option<CodeLocation> {
+        self.current().ok().map(location)
+    }
+
+    /// The statement execution is paused in
+    pub fn source_location(&self) -> Option<SourceLocation> {
+        self.current().ok().and_then(|step| self.source_of(location(step)))
+    }
+
+    pub fn gas_left(&self) -> Option<u64> {
+        self.current().ok().map(|step| step.gas_left)
+    }
+
+    /// Gas the call has spent before the paused instruction
+    pub fn gas_used(&self) -> Option<u64> {
+        self.gas_left().map(|left| self.gas_limit - left)
+    }
+
+    /// The trap the call ended with, while paused on the instruction that
+    /// raised it
+    pub fn trap(&self) -> Option<&Trap> {
+        match &self.outcome.as_ref()?.result {
+            Err(trap) if self.state == DebuggerState::Paused && self.position + 1 == self.recording.steps.len() => {
+                Some(trap)
+            }
+            _ => None,
+        }
+    }
+
+    /// The operand stack of the paused frame, the top last
+    pub fn operand_stack(&self) -> Result<&[Value]> {
+        self.current()?;
+        Ok(&self.top().stack)
+    }
+
+    /// `count` local slots of the paused frame from `start`, the only
+    /// memory a frame has, cut short at its last slot
+    pub fn inspect_memory(&self, start: usize, count: usize) -> Result<&[Value]> {
+        self.current()?;
+        let locals = &self.top().locals;
+        let start = start.min(locals.len());
+        Ok(&locals[start..locals.len().min(start.saturating_add(count))])
+    }
+
+    /// Every slot of the paused contract written so far, by slot, map
+    /// entries included
+    pub fn storage_slots(&self) -> Result<Vec<(U256, Value)>> {
+        let contract = self.current()?.contract;
+        let mut slots: BTreeMap<U256, Value> = self.vm.storage().slots(&contract).into_iter().collect();
+        for ((owner, slot), value) in &self.snapshot.storage {
+            if *owner == contract {
+                slots.insert(*slot, value.clone());
+            }
+        }
+        Ok(slots.into_iter().collect())
+    }
+
+    /// The paused function's instructions within `around` of the paused
+    /// one, each with its pc
+    pub fn disassembly(&self, around: usize) -> Result<Vec<(usize, String)>> {
+        let here = location(self.current()?);
+        let module = self.module(&here.contract)
+            .ok_or_else(|| Error::RuntimeError("the paused contract is not deployed".into()))?;
+        let function = &module.functions[here.function as usize];
+        let end = function.code.len().min(here.pc.saturating_add(around + 1));
+        Ok((here.pc.saturating_sub(around)..end)
+            .map(|pc| (pc, format_instruction(module, &function.code[pc])))
+            .collect())
+    }
+
+    /// The paused frame first, then its callers
     pub fn get_stack_trace(&self) -> Vec<StackFrame> {
-        self.call_stack.clone()
+        if self.current().is_err() {
+            return Vec::new();
+        }
+
+        // Each caller is paused on the last call it made at its depth
+        let mut steps = vec![&self.recording.steps[self.position]];
+        for step in self.recording.steps[..self.position].iter().rev() {
+            if step.depth + 1 == steps[steps.len() - 1].depth {
+                steps.push(step);
+            }
+        }
+        steps.into_iter().zip(self.snapshot.frames.iter().rev())
+            .map(|(step, memory)| {
+                let location = location(step);
+                StackFrame {
+                    function: self.function_name(location),
+                    pc: location.pc,
+                    location: self.source_of(location),
+                    locals: self.locals(location, &memory.locals),
+                    stack: memory.stack.clone(),
+                }
+            })
+            .collect()
     }
-    
-    pub fn get_local_variables(&self) -> Result<HashMap<String, Value>> {
-        self.current_frame().map(|frame| frame.locals)
+
+    /// The variables in scope in the paused frame. Without debug info
+    /// every local slot is listed as `$<slot>`.
+    pub fn get_local_variables(&self) -> Result<Vec<Variable>> {
+        let step = self.current()?;
+        Ok(self.locals(location(step), &self.top().locals))
     }
-    
+
+    /// The contract's state variables as the paused instruction sees them.
+    /// Maps are left out: their entries live at hashed slots.
+    pub fn storage_variables(&self) -> Result<Vec<Variable>> {
+        let contract = self.current()?.contract;
+        Ok(self.state_variables(&contract)
+            .map(|entry| Variable {
+                name: entry.name.clone(),
+                ty: entry.ty.to_string(),
+                value: self.storage_value(&contract, U256::from(entry.slot)),
+            })
+            .collect())
+    }
+
+    /// A local in scope, or else a state variable
     pub fn get_value(&self, name: &str) -> Result<Option<Value>> {
-        self.current_frame()
-            .map(|frame| frame.locals.get(name).cloned())
+        self.current()?;
+        Ok(self.value(name))
     }
-    
-    pub fn inspect_memory(&self, address: usize, size: usize) -> Result<Vec<u8>> {
-        if address + size > self.vm.memory.size() {
-            return Err(Error::MemoryAccessViolation);
-        }
-        
-        Ok(self.vm.memory.read_bytes(address, size))
+
+    /// The last instruction before the paused one that changed a state
+    /// variable, given by name or by slot number
+    pub fn last_write(&self, target: &str) -> Result<Option<StorageWrite>> {
+        let last = self.recording.steps.len().checked_sub(1)
+            .ok_or_else(|| Error::RuntimeError("no call is recorded".into()))?;
+        let contract = self.recording.steps[self.position.min(last)].contract;
+        let slot = match self.state_variables(&contract).find(|entry| entry.name == target) {
+            Some(entry) => U256::from(entry.slot),
+            None => U256::from_dec_str(target)
+                .map_err(|_| Error::SymbolNotFound(target.to_string()))?,
+        };
+        Ok(self.recording.last_write(self.position, &contract, &slot).map(|(index, old, new)| {
+            let location = location(&self.recording.steps[index]);
+            StorageWrite { step: index, location, source: self.source_of(location), old: old.clone(), new: new.clone() }
+        }))
+    }
+
+    /// Locals as `name: type = value` lines
+    pub fn format_locals(&self) -> Result<String> {
+        self.get_local_variables().map(|variables| format_variables(&variables))
     }
-    
+
+    /// State variables as `name: type = value` lines
+    pub fn format_storage(&self) -> Result<String> {
+        self.storage_variables().map(|variables| format_variables(&variables))
+    }
+
+    /// The statement an instruction belongs to
+    pub fn source_of(&self, location: CodeLocation) -> Option<SourceLocation> {
+        let debug = self.debug_info(&location.contract)?;
+        let found = debug.location(location.function, location.pc as u32)?;
+        Some(SourceLocation { file: debug.source.clone(), line: found.line as usize, column: found.column as usize })
+    }
+
     // Helper Methods
-    
-    fn current_frame(&self) -> Result<StackFrame> {
-        self.call_stack.last()
-            .cloned()
-            .ok_or(Error::NoActiveFrame)
-    }
-    
-    fn check_watchpoints(&self) -> Result<bool> {
-        for (name, condition) in &self.watchpoints {
-            if let Some(value) = self.get_value(name)? {
-                match condition {
-                    WatchCondition::Changed => {
-                        // Compare with previous value
-                    }
-                    WatchCondition::Equals(target) => {
-                        if &value == target {
-                            return Ok(true);
-                        }
-                    }
-                    WatchCondition::GreaterThan(target) => {
-                        // Compare values
-                    }
-                    WatchCondition::LessThan(target) => {
-                        // Compare values
-                    }
-                    WatchCondition::Custom(f) => {
-                        if f(&value) {
-                            return Ok(true);
-                        }
-                    }
+
+    fn current(&self) -> Result<&ExecutionStep> {
+        match self.state {
+            DebuggerState::Paused => Ok(&self.recording.steps[self.position]),
+            _ => Err(Error::RuntimeError("no call is paused".into())),
+        }
+    }
+
+    fn top(&self) -> &FrameMemory {
+        self.snapshot.frames.last().expect("a paused call has a frame")
+    }
+
+    fn depth(&self) -> usize {
+        self.recording.steps[self.position].depth
+    }
+
+    /// Moves one instruction at a time until `stop` accepts where it got
+    /// to, and with `breakpoints` also at breakpoints and watchpoints. Going
+    /// forwards ends at the instruction that trapped or else past the end;
+    /// going backwards, at the start.
+    fn travel(&mut self, forward: bool, breakpoints: bool, stop: impl Fn(&Debugger) -> bool) -> Result<()> {
+        match (&self.state, forward) {
+            (DebuggerState::Paused, _) | (DebuggerState::Terminated, false) => {}
+            _ => return Err(Error::RuntimeError("no call is paused".into())),
+        }
+        let end = self.recording.steps.len();
+        loop {
+            let watched: Vec<Option<Value>> = self.watchpoints.keys().map(|name| self.value(name)).collect();
+            if forward {
+                self.snapshot.apply(&self.recording.steps[self.position]);
+                self.position += 1;
+                if self.position == end {
+                    self.state = DebuggerState::Terminated;
+                    return Ok(());
+                }
+            } else {
+                if self.position == 0 {
+                    return Ok(());
+                }
+                self.position -= 1;
+                self.snapshot.undo(&self.recording.steps[self.position]);
+                self.state = DebuggerState::Paused;
+            }
+
+            let hit = breakpoints && (self.breakpoint_hit() | self.watchpoint_triggered(&watched));
+            if hit || stop(self) || (forward && self.trap().is_some()) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn module(&self, contract: &Address) -> Option<&Module> {
+        self.vm.module(contract)
+    }
+
+    fn debug_info(&self, contract: &Address) -> Option<&DebugInfo> {
+        self.module(contract).and_then(|module| module.debug.as_ref())
+    }
+
+    fn function_name(&self, location: CodeLocation) -> String {
+        self.module(&location.contract)
+            .and_then(|module| module.functions.get(location.function as usize))
+            .map_or_else(|| format!("#{}", location.function), |f| f.name.clone())
+    }
+
+    /// Whether execution is at the start of a statement. Code without
+    /// debug info has a statement per instruction.
+    fn at_statement(&self) -> bool {
+        let location = location(&self.recording.steps[self.position]);
+        match self.debug_info(&location.contract) {
+            Some(debug) => debug.locations.iter()
+                .any(|l| l.function == location.function && l.pc as usize == location.pc),
+            None => true,
+        }
+    }
+
+    fn locals(&self, location: CodeLocation, values: &[Value]) -> Vec<Variable> {
+        match self.debug_info(&location.contract) {
+            Some(debug) => debug.variables_at(location.function, location.pc as u32)
+                .filter_map(|v| Some(Variable {
+                    name: v.name.clone(),
+                    ty: v.ty.to_string(),
+                    value: values.get(v.slot as usize)?.clone(),
+                }))
+                .collect(),
+            None => values.iter().enumerate()
+                .map(|(slot, value)| Variable { name: format!("${}", slot), ty: "?".into(), value: value.clone() })
+                .collect(),
+        }
+    }
+
+    fn state_variables(&self, contract: &Address) -> impl Iterator<Item = &StorageEntry> {
+        self.module(contract).into_iter()
+            .flat_map(|module| &module.storage)
+            .filter(|entry| !matches!(entry.ty, Type::Map { .. }))
+    }
+
+    /// A slot as the paused instruction sees it. Slots the call never
+    /// writes still hold what they held before it.
+    fn storage_value(&self, contract: &Address, slot: U256) -> Value {
+        match self.snapshot.storage.get(&(*contract, slot)) {
+            Some(value) => value.clone(),
+            None => self.vm.storage_at(contract, slot),
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<Value> {
+        let step = self.recording.steps.get(self.position)?;
+        self.locals(location(step), &self.top().locals).into_iter()
+            .find(|variable| variable.name == name)
+            .map(|variable| variable.value)
+            .or_else(|| {
+                let entry = self.state_variables(&step.contract).find(|entry| entry.name == name)?;
+                Some(self.storage_value(&step.contract, U256::from(entry.slot)))
+            })
+    }
+
+    fn insert_breakpoint(&mut self, spec: &str, condition: Option<(String, WatchCondition)>) -> Result<&Breakpoint> {
+        let locations = self.resolve(spec)?;
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint { id, spec: spec.trim().to_string(), locations, condition });
+        Ok(self.breakpoints.last().expect("pushed above"))
+    }
+
+    /// Whether execution is at a breakpoint whose condition holds
+    fn breakpoint_hit(&mut self) -> bool {
+        let here = location(&self.recording.steps[self.position]);
+        let mut hit = false;
+        for breakpoint in self.breakpoints.iter().filter(|b| b.locations.contains(&here)) {
+            let Some((name, condition)) = &breakpoint.condition else {
+                hit = true;
+                continue;
+            };
+            let Some(value) = self.value(name) else {
+                continue;
+            };
+            hit |= match condition {
+                WatchCondition::Changed => self.last_seen.get(&breakpoint.id).is_some_and(|seen| *seen != value),
+                _ => holds(condition, &value),
+            };
+            self.last_seen.insert(breakpoint.id, value);
+        }
+        hit
+    }
+
+    /// Resolves a breakpoint spec to the instructions it stops at
+    fn resolve(&self, spec: &str) -> Result<Vec<CodeLocation>> {
+        let spec = spec.trim();
+        let contracts: Vec<Address> = self.vm.contracts().copied().collect();
+        let mut locations = Vec::new();
+
+        if let Some(name) = spec.strip_prefix("fn ") {
+            for contract in &contracts {
+                if let Some(function) = self.module(contract).and_then(|m| m.function_index(name.trim())) {
+                    locations.push(CodeLocation { contract: *contract, function, pc: 0 });
+                }
+            }
+        } else if let Some((file, line)) = spec.rsplit_once(':') {
+            let line: u32 = line.parse()
+                .map_err(|_| Error::Custom(format!("invalid line number in `{}`", spec)))?;
+            for contract in &contracts {
+                let Some(debug) = self.debug_info(contract).filter(|debug| same_file(&debug.source, file)) else {
+                    continue;
+                };
+                // The first line at or after `line` that has code
+                let Some(target) = debug.locations.iter().map(|l| l.line).filter(|&l| l >= line).min() else {
+                    continue;
+                };
+                let mut first: HashMap<u32, u32> = HashMap::new();
+                for l in debug.locations.iter().filter(|l| l.line == target) {
+                    let pc = first.entry(l.function).or_insert(l.pc);
+                    *pc = (*pc).min(l.pc);
                 }
+                locations.extend(first.into_iter().map(|(function, pc)| CodeLocation {
+                    contract: *contract,
+                    function,
+                    pc: pc as usize,
+                }));
             }
+        } else {
+            return Err(Error::Custom(format!("expected `<file>:<line>` or `fn <name>`, found `{}`", spec)));
         }
-        Ok(false)
+
+        if locations.is_empty() {
+            return Err(Error::SymbolNotFound(spec.to_string()));
+        }
+        locations.sort_by_key(|l| (l.contract, l.function, l.pc));
+        Ok(locations)
+    }
+
+    /// Whether a watchpoint's condition became true since the values
+    /// `before` were read, in the order of `watchpoints`
+    fn watchpoint_triggered(&self, before: &[Option<Value>]) -> bool {
+        self.watchpoints.iter().zip(before).any(|((name, condition), before)| {
+            let Some(value) = self.value(name) else {
+                return false;
+            };
+            match condition {
+                WatchCondition::Changed => before.as_ref().is_some_and(|before| *before != value),
+                _ => holds(condition, &value) && !before.as_ref().is_some_and(|before| holds(condition, before)),
+            }
+        })
     }
 }
 
-// Debug information
-pub struct DebugInfo {
-    pub source_map: HashMap<usize, SourceLocation>,
-    pub variable_map: HashMap<String, VariableInfo>,
+fn location(step: &ExecutionStep) -> CodeLocation {
+    CodeLocation { contract: step.contract, function: step.function, pc: step.pc }
 }
 
-pub struct SourceLocation {
-    pub file: String,
-    pub line: usize,
-    pub column: usize,
+/// Whether a value satisfies a condition other than `Changed`
+fn holds(condition: &WatchCondition, value: &Value) -> bool {
+    match condition {
+        WatchCondition::Changed => false,
+        WatchCondition::Equals(target) => value == target,
+        WatchCondition::GreaterThan(target) => compare(value, target) == Some(Ordering::Greater),
+        WatchCondition::LessThan(target) => compare(value, target) == Some(Ordering::Less),
+        WatchCondition::Custom(f) => f(value),
+    }
 }
 
-pub struct VariableInfo {
-    pub ty: String,
-    pub stack_offset: usize,
-    pub scope_start: usize,
-    pub scope_end: usize,
-} 
\ No newline at end of file
+/// Orders numbers; other values only compare equal or not
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::U256(a), Value::U256(b)) => Some(a.cmp(b)),
+        _ => (a == b).then_some(Ordering::Equal),
+    }
+}
+
+/// Whether `file` names the source a module was compiled from, in full
+/// or by its trailing path components. Editors name files by absolute
+/// path, while `strxc` records the path it was given.
+fn same_file(source: &str, file: &str) -> bool {
+    let (source, file) = (source.trim_start_matches("./"), file.trim_start_matches("./"));
+    source == file || source.ends_with(&format!("/{}", file)) || file.ends_with(&format!("/{}", source))
+}
+
+fn format_variables(variables: &[Variable]) -> String {
+    variables.iter()
+        .map(|v| format!("{}: {} = {}\n", v.name, v.ty, v.value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stremax_runtime::bytecode::assemble;
+
+    pub(super) const WALLET: &str = "\
+.contract Wallet
+.source \"contracts/wallet.strx\"
+.const #0 u256 100
+.storage 0 balance: u256
+.export withdraw(amount: u256) -> u256
+.export fee(amount: u256) -> u256
+.export deposit(amount: u256)
+
+.function withdraw arity=1 locals=2
+    .var 0 0..10 amount: u256
+    .var 1 4..10 paid: u256
+    .loc 10:9
+    LOAD 0
+    CALL fee 1
+    LOAD 0
+    ADD
+    STORE 1
+    .loc 11:9
+    SLOAD 0
+    LOAD 1
+    SUB
+    SSTORE 0
+    .loc 12:9
+    LOAD 1
+    RET
+
+.function fee arity=1 locals=1 pure
+    .var 0 0..4 amount: u256
+    .loc 16:9
+    LOAD 0
+    PUSH #0 ; u256 100
+    DIV
+    RET
+
+.function deposit arity=1 locals=1
+    .var 0 0..5 amount: u256
+    .loc 20:9
+    SLOAD 0
+    LOAD 0
+    ADD
+    SSTORE 0
+    RET
+";
+
+    const WALLET_ADDRESS: Address = [0x77; 20];
+
+    fn word(n: u64) -> Value {
+        Value::U256(U256::from(n))
+    }
+
+    fn debugger() -> Debugger {
+        let mut vm = Vm::new();
+        vm.deploy(WALLET_ADDRESS, assemble(WALLET).unwrap()).unwrap();
+        Debugger::new(vm)
+    }
+
+    #[test]
+    fn test_breakpoints_steps_and_names() {
+        let mut debugger = debugger();
+        assert!(debugger.add_breakpoint("wallet.strx:11").is_ok());
+        assert_eq!(debugger.add_breakpoint("fn fee").unwrap().locations[0].function, 1);
+        assert!(debugger.add_breakpoint("wallet.strx:40").is_err());
+        assert!(debugger.add_breakpoint("fn missing").is_err());
+
+        debugger.launch(&WALLET_ADDRESS, "withdraw", vec![word(0)], 100_000).unwrap();
+        assert!(debugger.outcome().unwrap().result.is_ok());
+        assert_eq!(debugger.source_location().map(|l| l.line), Some(10));
+
+        debugger.run().unwrap();
+        let trace = debugger.get_stack_trace();
+        assert_eq!(trace.iter().map(|f| f.function.as_str()).collect::<Vec<_>>(), ["fee", "withdraw"]);
+        assert_eq!(trace[1].location.as_ref().map(|l| l.line), Some(10));
+        assert_eq!(debugger.get_value("amount").unwrap(), Some(word(0)));
+
+        debugger.run().unwrap();
+        assert_eq!(debugger.source_location().map(|l| (l.file, l.line)), Some(("contracts/wallet.strx".into(), 11)));
+        assert_eq!(debugger.format_locals().unwrap(), "amount: u256 = 0\npaid: u256 = 0\n");
+        assert_eq!(debugger.format_storage().unwrap(), "balance: u256 = 0\n");
+
+        debugger.step_line().unwrap();
+        assert_eq!(debugger.source_location().map(|l| l.line), Some(12));
+        debugger.step_over().unwrap();
+        assert_eq!(debugger.state(), &DebuggerState::Terminated);
+        assert!(debugger.get_local_variables().is_err());
+    }
+
+    #[test]
+    fn test_time_travel() {
+        let mut debugger = debugger();
+        debugger.launch(&WALLET_ADDRESS, "deposit", vec![word(1000)], 100_000).unwrap();
+        debugger.run().unwrap();
+        debugger.add_breakpoint("wallet.strx:12").unwrap();
+
+        debugger.launch(&WALLET_ADDRESS, "withdraw", vec![word(500)], 100_000).unwrap();
+        debugger.run().unwrap();
+        assert_eq!(debugger.source_location().map(|l| l.line), Some(12));
+        assert_eq!(debugger.get_value("balance").unwrap(), Some(word(495)));
+        let write = debugger.last_write("balance").unwrap().unwrap();
+        assert_eq!((write.source.map(|l| l.line), write.old, write.new), (Some(11), word(1000), word(495)));
+        assert_eq!(debugger.last_write("0").unwrap().map(|w| w.step), Some(write.step));
+        assert!(debugger.last_write("missing").is_err());
+
+        // Back over the store, then back to the start with no breakpoint
+        // on the way
+        assert!(debugger.reverse_step().unwrap());
+        assert_eq!(debugger.current_location().map(|l| l.pc), Some(8));
+        assert_eq!(debugger.get_value("balance").unwrap(), Some(word(1000)));
+        assert_eq!(debugger.last_write("balance").unwrap(), None);
+        debugger.reverse_continue().unwrap();
+        assert_eq!(debugger.current_location().map(|l| l.pc), Some(0));
+        assert_eq!(debugger.get_value("paid").unwrap(), None);
+        assert!(!debugger.reverse_step().unwrap());
+
+        // From past the end back to the breakpoint
+        debugger.run().unwrap();
+        debugger.run().unwrap();
+        assert_eq!(debugger.state(), &DebuggerState::Terminated);
+        debugger.reverse_continue().unwrap();
+        assert_eq!(debugger.source_location().map(|l| l.line), Some(12));
+        assert_eq!(debugger.operand_stack().unwrap(), &[]);
+
+        // A call that traps pauses where it trapped
+        debugger.launch(&WALLET_ADDRESS, "withdraw", vec![word(600)], 100_000).unwrap();
+        debugger.run().unwrap();
+        assert_eq!(debugger.trap(), Some(&Trap::ArithmeticOverflow));
+        assert_eq!(debugger.operand_stack().unwrap(), &[word(495), word(606)]);
+        debugger.step_out().unwrap();
+        assert_eq!(debugger.state(), &DebuggerState::Terminated);
+    }
+}
@@ -0,0 +1,345 @@
+//! Command line front end to the [`Debugger`], for `strxvm debug`.
+//!
+//! The call is launched before the first prompt and pauses on its first
+//! instruction. Commands read one per line, gdb style: `break` takes a
+//! line of the source file, `file.strx:42` or a function name, optionally
+//! followed by `if <condition>`; `watch` takes a variable, which is shown
+//! at every stop and stops execution when it changes, or a condition to
+//! stop at. An empty line repeats the previous command.
+
+use std::io::{self, BufRead, Write};
+
+use super::dap::parse_condition;
+use super::{Debugger, DebuggerState, WatchCondition};
+
+const PROMPT: &str = "(strx) ";
+
+/// Instructions `disassemble` shows either side of the paused one
+const DISASSEMBLY_CONTEXT: usize = 4;
+
+/// Source lines `list` shows either side of the paused one
+const LIST_CONTEXT: usize = 5;
+
+const HELP: &str = "\
+break, b <LINE|FILE:LINE|FUNCTION> [if <COND>]   Set a breakpoint
+delete, d <ID>                                   Remove a breakpoint
+breakpoints                                      List breakpoints
+continue, c                                      Run to the next breakpoint or watch
+step, s                                          Run to the next statement, entering calls
+stepi, si                                        Run one instruction
+next, n                                          Run to the next statement in this function
+finish                                           Run until this function returns
+reverse-step, rs                                 Go back one instruction
+reverse-continue, rc                             Go back to the previous breakpoint or watch
+backtrace, bt                                    Show the call stack
+locals                                           Show the variables in scope
+stack                                            Show the operand stack, the top last
+storage                                          Show the contract's state variables
+keys                                             Show every written storage slot
+memory, x <START> [COUNT]                        Show local slots from START
+print, p <NAME>                                  Show a local or state variable
+watch <NAME|COND>                                Show NAME at each stop and stop when it changes or COND holds
+unwatch <NAME>                                   Remove a watch
+gas                                              Show the gas used and left
+disassemble, disas [N]                           Show the instructions around the paused one
+list, l                                          Show the source around the paused line
+help, h                                          Show this help
+quit, q                                          Leave the debugger
+Conditions are `<variable> == <value>`, `>`, `<` or `<variable> changed`.";
+
+/// A debugging session over a launched call
+pub struct Repl<W: Write> {
+    debugger: Debugger,
+    /// The source file as given, which bare line numbers refer to
+    source: String,
+    lines: Vec<String>,
+    /// Variables shown at every stop
+    watches: Vec<String>,
+    output: W,
+}
+
+impl<W: Write> Repl<W> {
+    /// A session over `debugger`'s launched call, with `text` the contents
+    /// of `source`
+    pub fn new(debugger: Debugger, source: &str, text: &str, output: W) -> Self {
+        Repl {
+            debugger,
+            source: source.to_string(),
+            lines: text.lines().map(str::to_string).collect(),
+            watches: Vec::new(),
+            output,
+        }
+    }
+
+    /// Reads commands until `quit` or the end of `input`
+    pub fn run(&mut self, mut input: impl BufRead) -> io::Result<()> {
+        let report = self.report_stop();
+        writeln!(self.output, "{}", report)?;
+        let mut previous = String::new();
+        let mut line = String::new();
+        loop {
+            write!(self.output, "{}", PROMPT)?;
+            self.output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let command = match line.trim() {
+                "" => previous.clone(),
+                command => command.to_string(),
+            };
+            if matches!(command.as_str(), "quit" | "q") {
+                return Ok(());
+            }
+            match self.execute(&command) {
+                Ok(reply) => write!(self.output, "{}", reply)?,
+                Err(message) => writeln!(self.output, "error: {}", message)?,
+            }
+            previous = command;
+        }
+    }
+
+    /// Runs one command and returns what it prints
+    fn execute(&mut self, command: &str) -> Result<String, String> {
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        let rest = rest.trim();
+        let debugger = &mut self.debugger;
+        let reply = match name {
+            "break" | "b" => {
+                let (spec, condition) = match rest.split_once(" if ") {
+                    Some((spec, condition)) => (spec, Some(parse_condition(condition)?)),
+                    None => (rest, None),
+                };
+                let spec = self.breakpoint_spec(spec)?;
+                let debugger = &mut self.debugger;
+                let breakpoint = match condition {
+                    Some((variable, condition)) => debugger.add_conditional_breakpoint(&spec, &variable, condition),
+                    None => debugger.add_breakpoint(&spec),
+                };
+                let breakpoint = breakpoint.map_err(|e| e.to_string())?;
+                format!("breakpoint {} at {}\n", breakpoint.id, breakpoint.spec)
+            }
+            "delete" | "d" => {
+                let id = rest.parse().map_err(|_| format!("expected a breakpoint number, found `{}`", rest))?;
+                if !debugger.remove_breakpoint(id) {
+                    return Err(format!("no breakpoint {}", id));
+                }
+                String::new()
+            }
+            "breakpoints" => debugger.breakpoints().iter()
+                .map(|b| match &b.condition {
+                    Some((variable, condition)) => format!("{}: {} if {} {}\n", b.id, b.spec, variable, describe(condition)),
+                    None => format!("{}: {}\n", b.id, b.spec),
+                })
+                .collect(),
+            "continue" | "c" => self.resume(Debugger::run)?,
+            "step" | "s" => self.resume(Debugger::step_line)?,
+            "stepi" | "si" => self.resume(|debugger| debugger.step().map(drop))?,
+            "next" | "n" => self.resume(Debugger::step_over)?,
+            "finish" => self.resume(Debugger::step_out)?,
+            "reverse-step" | "rs" => self.resume(|debugger| debugger.reverse_step().map(drop))?,
+            "reverse-continue" | "rc" => self.resume(Debugger::reverse_continue)?,
+            "backtrace" | "bt" => debugger.get_stack_trace().iter().enumerate()
+                .map(|(depth, frame)| match &frame.location {
+                    Some(at) => format!("#{} {} at {}:{} (pc {})\n", depth, frame.function, at.file, at.line, frame.pc),
+                    None => format!("#{} {} (pc {})\n", depth, frame.function, frame.pc),
+                })
+                .collect(),
+            "locals" => debugger.format_locals().map_err(|e| e.to_string())?,
+            "stack" => debugger.operand_stack().map_err(|e| e.to_string())?.iter()
+                .map(|value| format!("{}\n", value))
+                .collect(),
+            "storage" => debugger.format_storage().map_err(|e| e.to_string())?,
+            "keys" => debugger.storage_slots().map_err(|e| e.to_string())?.iter()
+                .map(|(slot, value)| format!("{}: {}\n", slot, value))
+                .collect(),
+            "memory" | "x" => {
+                let mut numbers = rest.split_whitespace().map(|n| n.parse::<usize>());
+                let start = match numbers.next() {
+                    Some(Ok(start)) => start,
+                    _ => return Err("expected `memory <START> [COUNT]`".into()),
+                };
+                let count = numbers.next().unwrap_or(Ok(usize::MAX)).map_err(|e| e.to_string())?;
+                debugger.inspect_memory(start, count).map_err(|e| e.to_string())?.iter().enumerate()
+                    .map(|(offset, value)| format!("${}: {}\n", start + offset, value))
+                    .collect()
+            }
+            "print" | "p" => match debugger.get_value(rest).map_err(|e| e.to_string())? {
+                Some(value) => format!("{} = {}\n", rest, value),
+                None => return Err(format!("no variable `{}` in scope", rest)),
+            },
+            "watch" => {
+                let (variable, condition) = parse_condition(rest)
+                    .unwrap_or_else(|_| (rest.to_string(), WatchCondition::Changed));
+                if variable.is_empty() {
+                    return Err("expected `watch <NAME>` or `watch <COND>`".into());
+                }
+                if !self.watches.contains(&variable) {
+                    self.watches.push(variable.clone());
+                }
+                self.debugger.add_watchpoint(variable.clone(), condition);
+                format!("watching {}\n", variable)
+            }
+            "unwatch" => {
+                self.watches.retain(|watch| watch != rest);
+                debugger.remove_watchpoint(rest);
+                String::new()
+            }
+            "gas" => match (debugger.gas_used(), debugger.gas_left()) {
+                (Some(used), Some(left)) => format!("gas used: {}, left: {}\n", used, left),
+                _ => return Err("no call is paused".into()),
+            },
+            "disassemble" | "disas" => {
+                let around = if rest.is_empty() {
+                    DISASSEMBLY_CONTEXT
+                } else {
+                    rest.parse().map_err(|_| format!("expected a number of instructions, found `{}`", rest))?
+                };
+                let here = debugger.current_location().map(|location| location.pc);
+                debugger.disassembly(around).map_err(|e| e.to_string())?.iter()
+                    .map(|(pc, text)| {
+                        let marker = if Some(*pc) == here { "=>" } else { "  " };
+                        format!("{} {:>4}: {}\n", marker, pc, text)
+                    })
+                    .collect()
+            }
+            "list" | "l" => {
+                let at = debugger.source_location().ok_or("no source line is paused at")?;
+                let first = at.line.saturating_sub(LIST_CONTEXT).max(1);
+                (first..=at.line + LIST_CONTEXT)
+                    .filter_map(|line| {
+                        let text = self.lines.get(line - 1)?;
+                        let marker = if line == at.line { "=>" } else { "  " };
+                        Some(format!("{} {:>4} | {}\n", marker, line, text))
+                    })
+                    .collect()
+            }
+            "help" | "h" => format!("{}\n", HELP),
+            _ => return Err(format!("unknown command `{}`, see `help`", name)),
+        };
+        Ok(reply)
+    }
+
+    /// Moves execution with `action` and describes where it stopped
+    fn resume(&mut self, action: impl FnOnce(&mut Debugger) -> crate::core::Result<()>) -> Result<String, String> {
+        action(&mut self.debugger).map_err(|e| e.to_string())?;
+        Ok(format!("{}\n", self.report_stop()))
+    }
+
+    /// Where execution is paused and what it sees there, or how the call
+    /// ended
+    fn report_stop(&self) -> String {
+        let debugger = &self.debugger;
+        if *debugger.state() != DebuggerState::Paused {
+            let Some(outcome) = debugger.outcome() else {
+                return "no call is running".into();
+            };
+            let result = match &outcome.result {
+                Ok(Some(value)) => format!("call returned {}", value),
+                Ok(None) => "call returned".into(),
+                Err(trap) => format!("call trapped: {}", trap),
+            };
+            return format!("{}, gas used: {}", result, outcome.gas_used);
+        }
+
+        let function = debugger.get_stack_trace().first().map(|frame| frame.function.clone()).unwrap_or_default();
+        let mut report = match debugger.source_location() {
+            Some(at) => {
+                let text = self.lines.get(at.line.wrapping_sub(1)).map_or("", |line| line.trim());
+                format!("{} at {}:{}\n{:>4} | {}", function, at.file, at.line, at.line, text)
+            }
+            None => {
+                let pc = debugger.current_location().map_or(0, |location| location.pc);
+                let text = debugger.disassembly(0).ok()
+                    .and_then(|lines| lines.into_iter().next())
+                    .map(|(_, text)| text)
+                    .unwrap_or_default();
+                format!("{} pc {}: {}", function, pc, text)
+            }
+        };
+        if let Some(trap) = debugger.trap() {
+            report += &format!("\ntrapped: {}", trap);
+        }
+        for watch in &self.watches {
+            if let Ok(Some(value)) = debugger.get_value(watch) {
+                report += &format!("\n{} = {}", watch, value);
+            }
+        }
+        report
+    }
+
+    /// Reads a breakpoint as the debugger takes it: a bare line is in the
+    /// source file and a bare name is a function
+    fn breakpoint_spec(&self, spec: &str) -> Result<String, String> {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            Err("expected `break <LINE|FILE:LINE|FUNCTION>`".into())
+        } else if spec.bytes().all(|b| b.is_ascii_digit()) {
+            Ok(format!("{}:{}", self.source, spec))
+        } else if spec.contains(':') || spec.starts_with("fn ") {
+            Ok(spec.to_string())
+        } else {
+            Ok(format!("fn {}", spec))
+        }
+    }
+}
+
+fn describe(condition: &WatchCondition) -> String {
+    match condition {
+        WatchCondition::Changed => "changed".into(),
+        WatchCondition::Equals(value) => format!("== {}", value),
+        WatchCondition::GreaterThan(value) => format!("> {}", value),
+        WatchCondition::LessThan(value) => format!("< {}", value),
+        WatchCondition::Custom(_) => "holds".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::WALLET;
+    use stremax_runtime::bytecode::assemble;
+    use stremax_runtime::num::U256;
+    use stremax_runtime::{Address, Value, Vm};
+
+    const WALLET_ADDRESS: Address = [0x77; 20];
+
+    #[test]
+    fn test_session() {
+        let mut vm = Vm::new();
+        vm.deploy(WALLET_ADDRESS, assemble(WALLET).unwrap()).unwrap();
+        let mut debugger = Debugger::new(vm);
+        debugger.launch(&WALLET_ADDRESS, "deposit", vec![Value::U256(U256::from(1000u64))], 100_000).unwrap();
+        debugger.run().unwrap();
+        debugger.launch(&WALLET_ADDRESS, "withdraw", vec![Value::U256(U256::from(500u64))], 100_000).unwrap();
+
+        let mut text = vec![String::new(); 20];
+        text[9] = "        let paid = amount + fee(amount);".into();
+        text[10] = "        balance -= paid;".into();
+        let commands = "break 12\nwatch balance\nbreak fee\nc\nbt\nx 0\nfinish\ndisas 1\nc\nkeys\ngas\nlist\nc\n\nrs\np balance\nbogus\nq\n";
+        let mut repl = Repl::new(debugger, "./contracts/wallet.strx", &text.join("\n"), Vec::new());
+        repl.run(commands.as_bytes()).unwrap();
+        let output = String::from_utf8(repl.output).unwrap();
+        let replies: Vec<&str> = output.split(PROMPT).collect();
+
+        assert_eq!(replies[0], "withdraw at contracts/wallet.strx:10\n  10 | let paid = amount + fee(amount);\n");
+        assert_eq!(replies[1], "breakpoint 1 at ./contracts/wallet.strx:12\n");
+        assert_eq!(replies[4], "fee at contracts/wallet.strx:16\n  16 | \nbalance = 1000\n");
+        assert_eq!(replies[5], "#0 fee at contracts/wallet.strx:16 (pc 0)\n#1 withdraw at contracts/wallet.strx:10 (pc 1)\n");
+        assert_eq!(replies[6], "$0: 500\n");
+        assert!(replies[7].starts_with("withdraw at contracts/wallet.strx:10\n"));
+        assert_eq!(replies[8], "      1: CALL fee 1\n=>    2: LOAD 0\n      3: ADD\n");
+        // The watch stops on the store, and the breakpoint right after it
+        assert_eq!(replies[9], "withdraw at contracts/wallet.strx:12\n  12 | \nbalance = 495\n");
+        assert_eq!(replies[10], "0: 495\n");
+        assert_eq!(replies[11], "gas used: 5234, left: 94766\n");
+        assert!(replies[12].contains("     11 |         balance -= paid;\n=>   12 | \n"));
+        // An empty line repeats `c` past the end, and going back returns
+        assert_eq!(replies[13], "call returned 505, gas used: 5238\n");
+        assert!(replies[14].starts_with("error: "));
+        assert!(replies[15].starts_with("withdraw at contracts/wallet.strx:12\n"));
+        assert_eq!(replies[16], "balance = 495\n");
+        assert_eq!(replies[17], "error: unknown command `bogus`, see `help`\n");
+        assert_eq!(replies.len(), 19);
+    }
+}
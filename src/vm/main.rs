@@ -1,323 +0,0 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use parking_lot::RwLock;
-use cranelift::prelude::*;
-use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{Module, Linkage};
-
-// VM State
-pub struct VM {
-    // Memory and storage
-    memory: Vec<u8>,
-    storage: Arc<RwLock<HashMap<u32, Vec<u8>>>>,
-    
-    // Execution context
-    stack: Vec<Value>,
-    call_stack: Vec<CallFrame>,
-    gas_left: u64,
-    
-    // JIT compilation
-    jit: JITModule,
-    ctx: codegen::Context,
-    
-    // Contract state
-    contracts: HashMap<String, Contract>,
-    current_contract: Option<String>,
-    
-    // Transaction context
-    tx_sender: [u8; 20],
-    tx_value: u64,
-    
-    // Concurrency control
-    reentrancy_guard: Arc<Mutex<()>>,
-}
-
-#[derive(Clone)]
-pub struct Contract {
-    code: Vec<u8>,
-    storage_layout: HashMap<String, u32>,
-    functions: HashMap<String, FunctionInfo>,
-}
-
-#[derive(Clone)]
-struct FunctionInfo {
-    offset: usize,
-    params: Vec<Type>,
-    return_type: Option<Type>,
-    is_pure: bool,
-}
-
-#[derive(Clone)]
-struct CallFrame {
-    contract: String,
-    function: String,
-    pc: usize,
-    stack_base: usize,
-    locals: HashMap<u32, Value>,
-}
-
-#[derive(Clone)]
-enum Value {
-    U256(u64),
-    Address([u8; 20]),
-    Bool(bool),
-    String(String),
-}
-
-#[derive(Clone, PartialEq)]
-enum Type {
-    U256,
-    Address,
-    Bool,
-    String,
-    Void,
-}
-
-impl VM {
-    pub fn new() -> Self {
-        // Initialize JIT compiler
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder = cranelift_native::builder().unwrap_or_else(|_| {
-            panic!("host machine is not supported")
-        });
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
-        
-        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-        let module = JITModule::new(builder);
-        
-        VM {
-            memory: vec![0; 1024 * 1024], // 1MB initial memory
-            storage: Arc::new(RwLock::new(HashMap::new())),
-            stack: Vec::with_capacity(1024),
-            call_stack: Vec::new(),
-            gas_left: 1_000_000, // Initial gas limit
-            jit: module,
-            ctx: codegen::Context::new(),
-            contracts: HashMap::new(),
-            current_contract: None,
-            tx_sender: [0; 20],
-            tx_value: 0,
-            reentrancy_guard: Arc::new(Mutex::new(())),
-        }
-    }
-
-    pub fn deploy_contract(&mut self, name: String, code: Vec<u8>, storage_layout: HashMap<String, u32>) {
-        let contract = Contract {
-            code,
-            storage_layout,
-            functions: HashMap::new(),
-        };
-        self.contracts.insert(name, contract);
-    }
-
-    pub fn call_function(
-        &mut self,
-        contract_name: &str,
-        function_name: &str,
-        args: Vec<Value>,
-    ) -> Result<Option<Value>, String> {
-        // Check if contract exists
-        let contract = self.contracts.get(contract_name)
-            .ok_or_else(|| format!("Contract {} not found", contract_name))?
-            .clone();
-        
-        // Check if function exists
-        let function = contract.functions.get(function_name)
-            .ok_or_else(|| format!("Function {} not found", function_name))?
-            .clone();
-        
-        // Check argument types
-        if args.len() != function.params.len() {
-            return Err("Wrong number of arguments".into());
-        }
-        
-        // Set up new call frame
-        let frame = CallFrame {
-            contract: contract_name.to_string(),
-            function: function_name.to_string(),
-            pc: function.offset,
-            stack_base: self.stack.len(),
-            locals: HashMap::new(),
-        };
-        
-        // Push arguments to stack
-        for arg in args {
-            self.stack.push(arg);
-        }
-        
-        self.call_stack.push(frame);
-        self.current_contract = Some(contract_name.to_string());
-        
-        // Execute function
-        self.execute()?;
-        
-        // Get return value if any
-        let return_value = if function.return_type.is_some() {
-            self.stack.pop()
-        } else {
-            None
-        };
-        
-        Ok(return_value)
-    }
-
-    fn execute(&mut self) -> Result<(), String> {
-        while let Some(frame) = self.call_stack.last_mut() {
-            let contract = self.contracts.get(&frame.contract).unwrap();
-            
-            if frame.pc >= contract.code.len() {
-                self.call_stack.pop();
-                continue;
-            }
-            
-            let instruction = contract.code[frame.pc];
-            self.gas_left = self.gas_left.checked_sub(1)
-                .ok_or("Out of gas")?;
-            
-            match instruction {
-                // Stack operations
-                0x01 => { // PUSH
-                    let value = contract.code[frame.pc + 1];
-                    self.stack.push(Value::U256(value as u64));
-                    frame.pc += 2;
-                }
-                0x02 => { // POP
-                    self.stack.pop();
-                    frame.pc += 1;
-                }
-                
-                // Memory operations
-                0x10 => { // LOAD
-                    let index = contract.code[frame.pc + 1] as u32;
-                    if let Some(value) = frame.locals.get(&index) {
-                        self.stack.push(value.clone());
-                    }
-                    frame.pc += 2;
-                }
-                0x11 => { // STORE
-                    let index = contract.code[frame.pc + 1] as u32;
-                    if let Some(value) = self.stack.pop() {
-                        frame.locals.insert(index, value);
-                    }
-                    frame.pc += 2;
-                }
-                
-                // Arithmetic
-                0x20 => { // ADD
-                    if let (Some(Value::U256(b)), Some(Value::U256(a))) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push(Value::U256(a.wrapping_add(b)));
-                    }
-                    frame.pc += 1;
-                }
-                0x21 => { // SUB
-                    if let (Some(Value::U256(b)), Some(Value::U256(a))) = (self.stack.pop(), self.stack.pop()) {
-                        self.stack.push(Value::U256(a.wrapping_sub(b)));
-                    }
-                    frame.pc += 1;
-                }
-                
-                // Control flow
-                0x30 => { // JUMP
-                    let target = contract.code[frame.pc + 1] as usize;
-                    frame.pc = target;
-                }
-                0x31 => { // JUMPI
-                    let target = contract.code[frame.pc + 1] as usize;
-                    if let Some(Value::Bool(condition)) = self.stack.pop() {
-                        if condition {
-                            frame.pc = target;
-                        } else {
-                            frame.pc += 2;
-                        }
-                    }
-                }
-                0x32 => { // RETURN
-                    self.call_stack.pop();
-                }
-                
-                // Blockchain specific
-                0x40 => { // SLOAD
-                    let key = contract.code[frame.pc + 1] as u32;
-                    let storage = self.storage.read();
-                    if let Some(value) = storage.get(&key) {
-                        self.stack.push(Value::U256(
-                            u64::from_le_bytes(value[..8].try_into().unwrap())
-                        ));
-                    }
-                    frame.pc += 2;
-                }
-                0x41 => { // SSTORE
-                    let key = contract.code[frame.pc + 1] as u32;
-                    if let Some(Value::U256(value)) = self.stack.pop() {
-                        let mut storage = self.storage.write();
-                        storage.insert(key, value.to_le_bytes().to_vec());
-                    }
-                    frame.pc += 2;
-                }
-                
-                // No reentry protection
-                0x50 => { // NOREENTRY_START
-                    let _guard = self.reentrancy_guard.try_lock()
-                        .map_err(|_| "Reentrant call detected")?;
-                    frame.pc += 1;
-                }
-                0x51 => { // NOREENTRY_END
-                    drop(self.reentrancy_guard.try_lock());
-                    frame.pc += 1;
-                }
-                
-                _ => return Err(format!("Invalid opcode: {}", instruction)),
-            }
-        }
-        
-        Ok(())
-    }
-
-    // JIT compilation support
-    fn compile_function(&mut self, contract: &Contract, function: &FunctionInfo) -> Result<*, String> {
-        let mut ctx = self.ctx.clone();
-        let mut func = ctx.func;
-        let mut builder = FunctionBuilder::new(&mut func.dfg, &mut func.layout);
-        
-        // Create entry block
-        let entry_block = builder.create_block();
-        builder.append_block_params_for_function_params(entry_block);
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-        
-        // Compile function body
-        let mut pc = function.offset;
-        while pc < contract.code.len() {
-            let instruction = contract.code[pc];
-            match instruction {
-                // Implement instruction compilation
-                _ => {}
-            }
-        }
-        
-        // Finalize function
-        builder.finalize();
-        
-        // Add to JIT module
-        let id = self.jit.declare_function(
-            &function.name,
-            Linkage::Export,
-            &func.signature,
-        )?;
-        
-        self.jit.define_function(id, &mut ctx)?;
-        self.jit.finalize_definitions();
-        
-        let code = self.jit.get_finalized_function(id);
-        Ok(code)
-    }
-}
-
-fn main() {
-    println!("Stremax VM v0.1.0");
-    // Add CLI interface here
-} 
\ No newline at end of file
@@ -0,0 +1,333 @@
+.contract CrossChainToken
+
+.const #0 string "msg.sender"
+.const #1 u256 0
+.const #2 string "len"
+.const #3 u256 1
+.const #4 bool true
+.const #5 u256 7
+.const #6 u256 8
+.const #7 string "Unsupported chain"
+.const #8 string "Zero amount"
+.const #9 string "Insufficient balance"
+.const #10 string "Message {source_chain, target_chain, sender, recipient, amount, nonce}"
+.const #11 string "submit_message"
+.const #12 string "Err"
+.const #13 string "Ok"
+.const #14 string "hash"
+.const #15 u256 9
+.const #16 string "Message already processed"
+.const #17 string "verify_message"
+.const #18 string "Invalid proof"
+.const #19 string "target_chain"
+.const #20 string "Wrong target chain"
+.const #21 string "source_chain"
+.const #22 string "Unsupported source chain"
+.const #23 string "recipient"
+.const #24 string "amount"
+.const #25 string "sender"
+.const #26 string "nonce"
+.const #27 string "Not owner"
+.const #28 string "Chain already supported"
+.const #29 string "Chain not supported"
+.const #30 bool false
+
+.storage 0 bridge_config: BridgeConfig
+.storage 1 supported_chains: map<ChainId, bool>
+.storage 2 local_chain_id: ChainId
+.storage 3 name: string
+.storage 4 symbol: string
+.storage 5 decimals: u8
+.storage 6 total_supply: u256
+.storage 7 balances: map<address, u256>
+.storage 8 nonces: map<ChainId, u256>
+.storage 9 processed_messages: map<bytes32, bool>
+.storage 10 owner: address
+
+.event Transfer(from: address, to: address, amount: u256)
+.event CrossChainTransfer(from: address, to: address, amount: u256, target_chain: ChainId, nonce: u256)
+.event ReceiveTokens(from: address, to: address, amount: u256, source_chain: ChainId, nonce: u256)
+
+.export init(_name: string, _symbol: string, _decimals: u8, _bridge_config: BridgeConfig, _local_chain_id: ChainId, initial_chains: array<ChainId>)
+.export get_balance(account: address) -> u256
+.export get_nonce(chain_id: ChainId) -> u256
+.export transfer_cross_chain(to: address, amount: u256, target_chain: ChainId) -> Result<bytes32, Error>
+.export receive_tokens(message: Message, proof: Proof) -> Result<(), Error>
+.export only_owner()
+.export add_supported_chain(chain_id: ChainId) -> Result<(), Error>
+.export remove_supported_chain(chain_id: ChainId) -> Result<(), Error>
+
+.function init arity=6 locals=10
+    LOAD 0
+    SSTORE 3
+    LOAD 1
+    SSTORE 4
+    LOAD 2
+    SSTORE 5
+    LOAD 3
+    SSTORE 0
+    LOAD 4
+    SSTORE 2
+    ENV #0 ; string "msg.sender"
+    SSTORE 10
+    LOAD 5
+    STORE 8
+    PUSH #1 ; u256 0
+    STORE 6
+    LOAD 8
+    CALLM #2 0 ; string "len"
+    STORE 7
+L19:
+    LOAD 6
+    LOAD 7
+    LT
+    NOT
+    JUMPI L38
+    LOAD 8
+    LOAD 6
+    INDEX
+    STORE 9
+    PUSH #3 ; u256 1
+    LOAD 9
+    MAPSLOT
+    PUSH #4 ; bool true
+    SSTOREAT
+    LOAD 6
+    PUSH #3 ; u256 1
+    ADD
+    STORE 6
+    JUMP L19
+L38:
+    RET
+
+.function get_balance arity=1 locals=1 pure
+    PUSH #5 ; u256 7
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function get_nonce arity=1 locals=1 pure
+    PUSH #6 ; u256 8
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function transfer_cross_chain arity=3 locals=7
+    GUARD
+    PUSH #3 ; u256 1
+    LOAD 2
+    MAPSLOT
+    SLOADAT
+    JUMPI L7
+    REVERT #7 ; string "Unsupported chain"
+L7:
+    LOAD 1
+    PUSH #1 ; u256 0
+    GT
+    JUMPI L12
+    REVERT #8 ; string "Zero amount"
+L12:
+    PUSH #5 ; u256 7
+    ENV #0 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 1
+    GTE
+    JUMPI L20
+    REVERT #9 ; string "Insufficient balance"
+L20:
+    PUSH #5 ; u256 7
+    ENV #0 ; string "msg.sender"
+    MAPSLOT
+    PUSH #5 ; u256 7
+    ENV #0 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 1
+    SUB
+    SSTOREAT
+    SLOAD 6
+    LOAD 1
+    SUB
+    SSTORE 6
+    PUSH #6 ; u256 8
+    LOAD 2
+    MAPSLOT
+    SLOADAT
+    STORE 3
+    PUSH #6 ; u256 8
+    LOAD 2
+    MAPSLOT
+    PUSH #6 ; u256 8
+    LOAD 2
+    MAPSLOT
+    SLOADAT
+    PUSH #3 ; u256 1
+    ADD
+    SSTOREAT
+    SLOAD 2
+    LOAD 2
+    ENV #0 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 3
+    STRUCT #10 6 ; string "Message {source_chain, target_chain, sender, recipient, amount, nonce}"
+    STORE 4
+    SLOAD 0
+    LOAD 4
+    CALLM #11 1 ; string "submit_message"
+    STORE 5
+    LOAD 5
+    ISVARIANT #12 ; string "Err"
+    NOT
+    JUMPI L67
+    LOAD 5
+    JUMP L78
+L67:
+    LOAD 5
+    EXTRACT 0
+    STORE 6
+    ENV #0 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    LOAD 3
+    EMIT CrossChainTransfer 5
+    LOAD 6
+    VARIANT #13 1 ; string "Ok"
+L78:
+    UNGUARD
+    RET
+
+.function receive_tokens arity=2 locals=4
+    GUARD
+    LOAD 0
+    CALLM #14 0 ; string "hash"
+    STORE 2
+    PUSH #15 ; u256 9
+    LOAD 2
+    MAPSLOT
+    SLOADAT
+    NOT
+    JUMPI L11
+    REVERT #16 ; string "Message already processed"
+L11:
+    SLOAD 0
+    LOAD 0
+    LOAD 1
+    CALLM #17 2 ; string "verify_message"
+    STORE 3
+    LOAD 3
+    ISVARIANT #12 ; string "Err"
+    NOT
+    JUMPI L22
+    LOAD 3
+    JUMP L75
+L22:
+    LOAD 3
+    EXTRACT 0
+    JUMPI L26
+    REVERT #18 ; string "Invalid proof"
+L26:
+    LOAD 0
+    GETFIELD #19 ; string "target_chain"
+    SLOAD 2
+    EQ
+    JUMPI L32
+    REVERT #20 ; string "Wrong target chain"
+L32:
+    PUSH #3 ; u256 1
+    LOAD 0
+    GETFIELD #21 ; string "source_chain"
+    MAPSLOT
+    SLOADAT
+    JUMPI L39
+    REVERT #22 ; string "Unsupported source chain"
+L39:
+    PUSH #5 ; u256 7
+    LOAD 0
+    GETFIELD #23 ; string "recipient"
+    MAPSLOT
+    PUSH #5 ; u256 7
+    LOAD 0
+    GETFIELD #23 ; string "recipient"
+    MAPSLOT
+    SLOADAT
+    LOAD 0
+    GETFIELD #24 ; string "amount"
+    ADD
+    SSTOREAT
+    SLOAD 6
+    LOAD 0
+    GETFIELD #24 ; string "amount"
+    ADD
+    SSTORE 6
+    PUSH #15 ; u256 9
+    LOAD 2
+    MAPSLOT
+    PUSH #4 ; bool true
+    SSTOREAT
+    LOAD 0
+    GETFIELD #25 ; string "sender"
+    LOAD 0
+    GETFIELD #23 ; string "recipient"
+    LOAD 0
+    GETFIELD #24 ; string "amount"
+    LOAD 0
+    GETFIELD #21 ; string "source_chain"
+    LOAD 0
+    GETFIELD #26 ; string "nonce"
+    EMIT ReceiveTokens 5
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+L75:
+    UNGUARD
+    RET
+
+.function only_owner arity=0 locals=0
+    ENV #0 ; string "msg.sender"
+    SLOAD 10
+    EQ
+    JUMPI L5
+    REVERT #27 ; string "Not owner"
+L5:
+    RET
+
+.function add_supported_chain arity=1 locals=1
+    CALL only_owner 0
+    PUSH #3 ; u256 1
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    NOT
+    JUMPI L8
+    REVERT #28 ; string "Chain already supported"
+L8:
+    PUSH #3 ; u256 1
+    LOAD 0
+    MAPSLOT
+    PUSH #4 ; bool true
+    SSTOREAT
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+    RET
+
+.function remove_supported_chain arity=1 locals=1
+    CALL only_owner 0
+    PUSH #3 ; u256 1
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    JUMPI L7
+    REVERT #29 ; string "Chain not supported"
+L7:
+    PUSH #3 ; u256 1
+    LOAD 0
+    MAPSLOT
+    PUSH #30 ; bool false
+    SSTOREAT
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+    RET
@@ -0,0 +1,587 @@
+.contract LiquidityPool
+
+.const #0 u256 3000000000000000
+.const #1 string "Same tokens"
+.const #2 u256 5
+.const #3 u256 0
+.const #4 string "Invalid input amount"
+.const #5 string "Error::InvalidToken"
+.const #6 string "Err"
+.const #7 u256 1000000000000000000
+.const #8 string "Ok"
+.const #9 bool false
+.const #10 string "Zero amounts"
+.const #11 string "sqrt"
+.const #12 string "min"
+.const #13 string "msg.sender"
+.const #14 string "self"
+.const #15 string "transfer_from"
+.const #16 string "Zero shares"
+.const #17 string "Insufficient shares"
+.const #18 string "transfer"
+.const #19 string "Zero input amount"
+.const #20 bool true
+.const #21 string "Invalid token"
+.const #22 string "Zero amount"
+.const #23 string "Insufficient liquidity"
+.const #24 string "execute_operation"
+.const #25 string "call"
+.const #26 string "Callback failed"
+.const #27 string "balance_of"
+.const #28 string "Insufficient repayment"
+
+.storage 0 token_a: address
+.storage 1 token_b: address
+.storage 2 reserve_a: u256
+.storage 3 reserve_b: u256
+.storage 4 total_shares: u256
+.storage 5 shares: map<address, u256>
+.storage 6 fee_rate: u256
+
+.event AddLiquidity(provider: address, amount_a: u256, amount_b: u256, shares: u256)
+.event RemoveLiquidity(provider: address, amount_a: u256, amount_b: u256, shares: u256)
+.event Swap(sender: address, token_in: address, amount_in: u256, token_out: address, amount_out: u256)
+.event FlashLoan(borrower: address, token: address, amount: u256, fee: u256)
+
+.export init(token_a_addr: address, token_b_addr: address)
+.export get_reserves() -> (u256, u256)
+.export get_shares(provider: address) -> u256
+.export calculate_swap_out(token_in: address, amount_in: u256) -> Result<u256, Error>
+.export add_liquidity(amount_a: u256, amount_b: u256) -> Result<u256, Error>
+.export remove_liquidity(share_amount: u256) -> Result<(u256, u256), Error>
+.export swap(token_in: address, amount_in: u256) -> Result<u256, Error>
+.export flash_loan(token: address, amount: u256, callback_data: array<u8>) -> Result<(), Error>
+.export _update_reserves(new_reserve_a: u256, new_reserve_b: u256)
+
+.function init arity=2 locals=2
+    GUARD
+    PUSH #0 ; u256 3000000000000000
+    SSTORE 6
+    LOAD 0
+    LOAD 1
+    EQ
+    NOT
+    JUMPI L9
+    REVERT #1 ; string "Same tokens"
+L9:
+    LOAD 0
+    SSTORE 0
+    LOAD 1
+    SSTORE 1
+    UNGUARD
+    RET
+
+.function get_reserves arity=0 locals=0 pure
+    SLOAD 2
+    SLOAD 3
+    TUPLE 2
+    RET
+
+.function get_shares arity=1 locals=1 pure
+    PUSH #2 ; u256 5
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function calculate_swap_out arity=2 locals=8 pure
+    LOAD 1
+    PUSH #3 ; u256 0
+    GT
+    JUMPI L5
+    REVERT #4 ; string "Invalid input amount"
+L5:
+    LOAD 0
+    SLOAD 0
+    EQ
+    NOT
+    JUMPI L14
+    SLOAD 2
+    SLOAD 3
+    TUPLE 2
+    JUMP L26
+L14:
+    LOAD 0
+    SLOAD 1
+    EQ
+    NOT
+    JUMPI L23
+    SLOAD 3
+    SLOAD 2
+    TUPLE 2
+    JUMP L26
+L23:
+    VARIANT #5 0 ; string "Error::InvalidToken"
+    VARIANT #6 1 ; string "Err"
+    RET
+L26:
+    STORE 2
+    LOAD 2
+    EXTRACT 0
+    STORE 3
+    LOAD 2
+    EXTRACT 1
+    STORE 4
+    LOAD 1
+    PUSH #7 ; u256 1000000000000000000
+    SLOAD 6
+    SUB
+    MUL
+    STORE 5
+    LOAD 5
+    LOAD 4
+    MUL
+    STORE 6
+    LOAD 3
+    PUSH #7 ; u256 1000000000000000000
+    MUL
+    LOAD 5
+    ADD
+    STORE 7
+    LOAD 6
+    LOAD 7
+    DIV
+    VARIANT #8 1 ; string "Ok"
+    RET
+
+.function add_liquidity arity=2 locals=6
+    GUARD
+    LOAD 0
+    PUSH #3 ; u256 0
+    GT
+    JUMPI L7
+    PUSH #9 ; bool false
+    JUMP L10
+L7:
+    LOAD 1
+    PUSH #3 ; u256 0
+    GT
+L10:
+    JUMPI L12
+    REVERT #10 ; string "Zero amounts"
+L12:
+    SLOAD 4
+    PUSH #3 ; u256 0
+    EQ
+    NOT
+    JUMPI L22
+    LOAD 0
+    LOAD 1
+    MUL
+    CALLH #11 1 ; string "sqrt"
+    JUMP L35
+L22:
+    LOAD 0
+    SLOAD 4
+    DUP 0
+    STORE 5
+    MUL
+    SLOAD 2
+    DIV
+    LOAD 1
+    LOAD 5
+    MUL
+    SLOAD 3
+    DIV
+    CALLH #12 2 ; string "min"
+L35:
+    STORE 2
+    SLOAD 0
+    ENV #13 ; string "msg.sender"
+    ENV #14 ; string "self"
+    LOAD 0
+    CALLM #15 3 ; string "transfer_from"
+    STORE 3
+    LOAD 3
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L48
+    LOAD 3
+    JUMP L95
+L48:
+    LOAD 3
+    EXTRACT 0
+    POP
+    SLOAD 1
+    ENV #13 ; string "msg.sender"
+    ENV #14 ; string "self"
+    LOAD 1
+    CALLM #15 3 ; string "transfer_from"
+    STORE 4
+    LOAD 4
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L63
+    LOAD 4
+    JUMP L95
+L63:
+    LOAD 4
+    EXTRACT 0
+    POP
+    SLOAD 2
+    LOAD 0
+    ADD
+    SSTORE 2
+    SLOAD 3
+    LOAD 1
+    ADD
+    SSTORE 3
+    SLOAD 4
+    LOAD 2
+    ADD
+    SSTORE 4
+    PUSH #2 ; u256 5
+    ENV #13 ; string "msg.sender"
+    MAPSLOT
+    PUSH #2 ; u256 5
+    ENV #13 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 2
+    ADD
+    SSTOREAT
+    ENV #13 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    EMIT AddLiquidity 4
+    LOAD 2
+    VARIANT #8 1 ; string "Ok"
+L95:
+    UNGUARD
+    RET
+
+.function remove_liquidity arity=1 locals=6
+    GUARD
+    LOAD 0
+    PUSH #3 ; u256 0
+    GT
+    JUMPI L6
+    REVERT #16 ; string "Zero shares"
+L6:
+    PUSH #2 ; u256 5
+    ENV #13 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 0
+    GTE
+    JUMPI L14
+    REVERT #17 ; string "Insufficient shares"
+L14:
+    LOAD 0
+    SLOAD 2
+    MUL
+    SLOAD 4
+    DUP 0
+    STORE 5
+    DIV
+    STORE 1
+    LOAD 0
+    SLOAD 3
+    MUL
+    LOAD 5
+    DIV
+    STORE 2
+    PUSH #2 ; u256 5
+    ENV #13 ; string "msg.sender"
+    MAPSLOT
+    PUSH #2 ; u256 5
+    ENV #13 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 0
+    SUB
+    SSTOREAT
+    SLOAD 4
+    LOAD 0
+    SUB
+    SSTORE 4
+    SLOAD 2
+    LOAD 1
+    SUB
+    SSTORE 2
+    SLOAD 3
+    LOAD 2
+    SUB
+    SSTORE 3
+    SLOAD 0
+    ENV #13 ; string "msg.sender"
+    LOAD 1
+    CALLM #18 2 ; string "transfer"
+    STORE 3
+    LOAD 3
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L61
+    LOAD 3
+    JUMP L87
+L61:
+    LOAD 3
+    EXTRACT 0
+    POP
+    SLOAD 1
+    ENV #13 ; string "msg.sender"
+    LOAD 2
+    CALLM #18 2 ; string "transfer"
+    STORE 4
+    LOAD 4
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L75
+    LOAD 4
+    JUMP L87
+L75:
+    LOAD 4
+    EXTRACT 0
+    POP
+    ENV #13 ; string "msg.sender"
+    LOAD 1
+    LOAD 2
+    LOAD 0
+    EMIT RemoveLiquidity 4
+    LOAD 1
+    LOAD 2
+    TUPLE 2
+    VARIANT #8 1 ; string "Ok"
+L87:
+    UNGUARD
+    RET
+
+.function swap arity=2 locals=7
+    GUARD
+    LOAD 1
+    PUSH #3 ; u256 0
+    GT
+    JUMPI L6
+    REVERT #19 ; string "Zero input amount"
+L6:
+    LOAD 0
+    SLOAD 0
+    EQ
+    JUMPI L14
+    LOAD 0
+    SLOAD 1
+    EQ
+    JUMP L15
+L14:
+    PUSH #20 ; bool true
+L15:
+    JUMPI L17
+    REVERT #21 ; string "Invalid token"
+L17:
+    LOAD 0
+    LOAD 1
+    CALL calculate_swap_out 2
+    STORE 2
+    LOAD 2
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L27
+    LOAD 2
+    JUMP L98
+L27:
+    LOAD 2
+    EXTRACT 0
+    STORE 3
+    LOAD 0
+    SLOAD 0
+    EQ
+    NOT
+    JUMPI L37
+    SLOAD 1
+    JUMP L38
+L37:
+    SLOAD 0
+L38:
+    STORE 4
+    LOAD 0
+    ENV #13 ; string "msg.sender"
+    ENV #14 ; string "self"
+    LOAD 1
+    CALLM #15 3 ; string "transfer_from"
+    STORE 5
+    LOAD 5
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L51
+    LOAD 5
+    JUMP L98
+L51:
+    LOAD 5
+    EXTRACT 0
+    POP
+    LOAD 4
+    ENV #13 ; string "msg.sender"
+    LOAD 3
+    CALLM #18 2 ; string "transfer"
+    STORE 6
+    LOAD 6
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L65
+    LOAD 6
+    JUMP L98
+L65:
+    LOAD 6
+    EXTRACT 0
+    POP
+    LOAD 0
+    SLOAD 0
+    EQ
+    NOT
+    JUMPI L82
+    SLOAD 2
+    LOAD 1
+    ADD
+    SSTORE 2
+    SLOAD 3
+    LOAD 3
+    SUB
+    SSTORE 3
+    JUMP L90
+L82:
+    SLOAD 3
+    LOAD 1
+    ADD
+    SSTORE 3
+    SLOAD 2
+    LOAD 3
+    SUB
+    SSTORE 2
+L90:
+    ENV #13 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 4
+    LOAD 3
+    EMIT Swap 5
+    LOAD 3
+    VARIANT #8 1 ; string "Ok"
+L98:
+    UNGUARD
+    RET
+
+.function flash_loan arity=3 locals=9
+    GUARD
+    LOAD 0
+    SLOAD 0
+    EQ
+    JUMPI L9
+    LOAD 0
+    SLOAD 1
+    EQ
+    JUMP L10
+L9:
+    PUSH #20 ; bool true
+L10:
+    JUMPI L12
+    REVERT #21 ; string "Invalid token"
+L12:
+    LOAD 1
+    PUSH #3 ; u256 0
+    GT
+    JUMPI L17
+    REVERT #22 ; string "Zero amount"
+L17:
+    LOAD 1
+    SLOAD 6
+    MUL
+    PUSH #7 ; u256 1000000000000000000
+    DIV
+    STORE 3
+    LOAD 0
+    SLOAD 0
+    EQ
+    NOT
+    JUMPI L30
+    SLOAD 2
+    JUMP L31
+L30:
+    SLOAD 3
+L31:
+    STORE 4
+    LOAD 1
+    LOAD 4
+    LTE
+    JUMPI L37
+    REVERT #23 ; string "Insufficient liquidity"
+L37:
+    LOAD 0
+    ENV #13 ; string "msg.sender"
+    LOAD 1
+    CALLM #18 2 ; string "transfer"
+    STORE 5
+    LOAD 5
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L48
+    LOAD 5
+    JUMP L100
+L48:
+    LOAD 5
+    EXTRACT 0
+    POP
+    ENV #13 ; string "msg.sender"
+    PUSH #24 ; string "execute_operation"
+    LOAD 0
+    LOAD 1
+    LOAD 3
+    LOAD 2
+    TUPLE 4
+    CALLM #25 2 ; string "call"
+    STORE 6
+    LOAD 6
+    ISVARIANT #6 ; string "Err"
+    NOT
+    JUMPI L66
+    LOAD 6
+    JUMP L100
+L66:
+    LOAD 6
+    EXTRACT 0
+    STORE 7
+    LOAD 7
+    JUMPI L72
+    REVERT #26 ; string "Callback failed"
+L72:
+    LOAD 0
+    ENV #14 ; string "self"
+    CALLM #27 1 ; string "balance_of"
+    STORE 8
+    LOAD 8
+    LOAD 4
+    LOAD 3
+    ADD
+    GTE
+    JUMPI L83
+    REVERT #28 ; string "Insufficient repayment"
+L83:
+    LOAD 0
+    SLOAD 0
+    EQ
+    NOT
+    JUMPI L91
+    LOAD 8
+    SSTORE 2
+    JUMP L93
+L91:
+    LOAD 8
+    SSTORE 3
+L93:
+    ENV #13 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 3
+    EMIT FlashLoan 4
+    TUPLE 0
+    VARIANT #8 1 ; string "Ok"
+L100:
+    UNGUARD
+    RET
+
+.function _update_reserves arity=2 locals=2
+    LOAD 0
+    SSTORE 2
+    LOAD 1
+    SSTORE 3
+    RET
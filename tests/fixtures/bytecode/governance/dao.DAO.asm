@@ -0,0 +1,457 @@
+.contract DAO
+
+.const #0 u256 172800
+.const #1 u256 1209600
+.const #2 string "msg.sender"
+.const #3 string "Insufficient votes"
+.const #4 string "len"
+.const #5 bool false
+.const #6 string "Invalid proposal"
+.const #7 string "block.timestamp"
+.const #8 u256 1
+.const #9 string "clone"
+.const #10 u256 0
+.const #11 string "Proposal {id, proposer, targets, values, signatures, calldatas, start_time, end_time, for_votes, against_votes, abstain_votes, canceled, executed, description}"
+.const #12 u256 8
+.const #13 string "Ok"
+.const #14 string "Proposal not active"
+.const #15 string "sqrt"
+.const #16 u256 10
+.const #17 string "against_votes"
+.const #18 string "for_votes"
+.const #19 u256 2
+.const #20 string "abstain_votes"
+.const #21 string "no match arm"
+.const #22 u256 4
+.const #23 string "Proposal not succeeded"
+.const #24 string "targets"
+.const #25 string "values"
+.const #26 string "signatures"
+.const #27 string "calldatas"
+.const #28 string "Err"
+.const #29 bool true
+.const #30 string "executed"
+.const #31 string "canceled"
+.const #32 u256 5
+.const #33 string "start_time"
+.const #34 string "end_time"
+.const #35 u256 3
+.const #36 u256 6
+.const #37 string "Too early"
+.const #38 string "keccak256"
+.const #39 u256 11
+.const #40 string "TimelockTx {target, value, signature, data, eta, executed}"
+
+.storage 0 voting_delay: Duration
+.storage 1 voting_period: Duration
+.storage 2 timelock_delay: Duration
+.storage 3 proposal_threshold: u256
+.storage 4 quorum_votes: u256
+.storage 5 governance_token: address
+.storage 6 votes: map<address, u256>
+.storage 7 delegates: map<address, address>
+.storage 8 proposals: map<u256, Proposal>
+.storage 9 proposal_count: u256
+.storage 10 locked_tokens: map<address, map<u256, u256>>
+.storage 11 pending_transactions: map<bytes32, TimelockTx>
+.storage 12 min_delay: Duration
+.storage 13 grace_period: Duration
+
+.event ProposalCreated(id: u256, proposer: address, targets: array<address>, values: array<u256>, signatures: array<string>, calldatas: array<array<u8>>, start_time: Timestamp, end_time: Timestamp, description: string)
+.event VoteCast(voter: address, proposal_id: u256, vote: VoteType, votes: u256)
+.event ProposalExecuted(id: u256)
+.event ProposalCanceled(id: u256)
+
+.export init(token: address, voting_delay: Duration, voting_period: Duration, timelock_delay: Duration, proposal_threshold: u256, quorum_votes: u256)
+.export propose(targets: array<address>, values: array<u256>, signatures: array<string>, calldatas: array<array<u8>>, description: string) -> Result<u256, Error>
+.export cast_vote(proposal_id: u256, vote_type: VoteType) -> Result<(), Error>
+.export execute(proposal_id: u256) -> Result<(), Error>
+.export state(proposal_id: u256) -> ProposalState
+.export get_votes(account: address) -> u256
+.export queue_transaction(target: address, value: u256, signature: string, data: array<u8>, eta: Timestamp) -> Result<bytes32, Error>
+
+.function init arity=6 locals=6
+    LOAD 0
+    SSTORE 5
+    LOAD 1
+    SSTORE 0
+    LOAD 2
+    SSTORE 1
+    LOAD 3
+    SSTORE 2
+    LOAD 4
+    SSTORE 3
+    LOAD 5
+    SSTORE 4
+    PUSH #0 ; u256 172800
+    SSTORE 12
+    PUSH #1 ; u256 1209600
+    SSTORE 13
+    RET
+
+.function propose arity=5 locals=10
+    GUARD
+    ENV #2 ; string "msg.sender"
+    CALL get_votes 1
+    SLOAD 3
+    GTE
+    JUMPI L7
+    REVERT #3 ; string "Insufficient votes"
+L7:
+    LOAD 0
+    CALLM #4 0 ; string "len"
+    LOAD 1
+    CALLM #4 0 ; string "len"
+    EQ
+    JUMPI L15
+    PUSH #5 ; bool false
+    JUMP L20
+L15:
+    LOAD 0
+    CALLM #4 0 ; string "len"
+    LOAD 2
+    CALLM #4 0 ; string "len"
+    EQ
+L20:
+    JUMPI L23
+    PUSH #5 ; bool false
+    JUMP L28
+L23:
+    LOAD 0
+    CALLM #4 0 ; string "len"
+    LOAD 3
+    CALLM #4 0 ; string "len"
+    EQ
+L28:
+    JUMPI L30
+    REVERT #6 ; string "Invalid proposal"
+L30:
+    ENV #7 ; string "block.timestamp"
+    SLOAD 0
+    ADD
+    STORE 5
+    LOAD 5
+    SLOAD 1
+    ADD
+    STORE 6
+    SLOAD 9
+    PUSH #8 ; u256 1
+    ADD
+    DUP 0
+    STORE 8
+    SSTORE 9
+    LOAD 8
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    CALLM #9 0 ; string "clone"
+    LOAD 1
+    CALLM #9 0 ; string "clone"
+    LOAD 2
+    CALLM #9 0 ; string "clone"
+    LOAD 3
+    CALLM #9 0 ; string "clone"
+    LOAD 5
+    LOAD 6
+    PUSH #10 ; u256 0
+    PUSH #10 ; u256 0
+    PUSH #10 ; u256 0
+    PUSH #5 ; bool false
+    PUSH #5 ; bool false
+    LOAD 4
+    CALLM #9 0 ; string "clone"
+    STRUCT #11 14 ; string "Proposal {id, proposer, targets, values, signatures, calldatas, start_time, end_time, for_votes, against_votes, abstain_votes, canceled, executed, description}"
+    STORE 7
+    PUSH #12 ; u256 8
+    SLOAD 9
+    MAPSLOT
+    LOAD 7
+    SSTOREAT
+    SLOAD 9
+    DUP 0
+    STORE 9
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    LOAD 3
+    LOAD 5
+    LOAD 6
+    LOAD 4
+    EMIT ProposalCreated 9
+    LOAD 9
+    VARIANT #13 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function cast_vote arity=2 locals=8
+    GUARD
+    LOAD 0
+    CALL state 1
+    PUSH #8 ; u256 1
+    EQ
+    JUMPI L7
+    REVERT #14 ; string "Proposal not active"
+L7:
+    ENV #2 ; string "msg.sender"
+    STORE 2
+    LOAD 2
+    CALL get_votes 1
+    STORE 3
+    LOAD 3
+    CALLH #15 1 ; string "sqrt"
+    STORE 4
+    LOAD 4
+    LOAD 4
+    MUL
+    STORE 5
+    PUSH #16 ; u256 10
+    LOAD 2
+    MAPSLOT
+    LOAD 0
+    MAPSLOT
+    LOAD 3
+    SSTOREAT
+    PUSH #12 ; u256 8
+    LOAD 0
+    MAPSLOT
+    STORE 6
+    LOAD 1
+    STORE 7
+    LOAD 7
+    PUSH #10 ; u256 0
+    EQ
+    NOT
+    JUMPI L48
+    LOAD 6
+    PUSH #17 ; string "against_votes"
+    MAPSLOT
+    LOAD 6
+    PUSH #17 ; string "against_votes"
+    MAPSLOT
+    SLOADAT
+    LOAD 5
+    ADD
+    SSTOREAT
+    JUMP L81
+L48:
+    LOAD 7
+    PUSH #8 ; u256 1
+    EQ
+    NOT
+    JUMPI L64
+    LOAD 6
+    PUSH #18 ; string "for_votes"
+    MAPSLOT
+    LOAD 6
+    PUSH #18 ; string "for_votes"
+    MAPSLOT
+    SLOADAT
+    LOAD 5
+    ADD
+    SSTOREAT
+    JUMP L81
+L64:
+    LOAD 7
+    PUSH #19 ; u256 2
+    EQ
+    NOT
+    JUMPI L80
+    LOAD 6
+    PUSH #20 ; string "abstain_votes"
+    MAPSLOT
+    LOAD 6
+    PUSH #20 ; string "abstain_votes"
+    MAPSLOT
+    SLOADAT
+    LOAD 5
+    ADD
+    SSTOREAT
+    JUMP L81
+L80:
+    REVERT #21 ; string "no match arm"
+L81:
+    LOAD 2
+    LOAD 0
+    LOAD 1
+    LOAD 5
+    EMIT VoteCast 4
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function execute arity=1 locals=6
+    GUARD
+    LOAD 0
+    CALL state 1
+    PUSH #22 ; u256 4
+    EQ
+    JUMPI L7
+    REVERT #23 ; string "Proposal not succeeded"
+L7:
+    PUSH #12 ; u256 8
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    PUSH #10 ; u256 0
+    STORE 2
+    LOAD 1
+    GETFIELD #24 ; string "targets"
+    CALLM #4 0 ; string "len"
+    STORE 3
+L18:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L60
+    LOAD 2
+    STORE 4
+    LOAD 1
+    GETFIELD #24 ; string "targets"
+    LOAD 4
+    INDEX
+    LOAD 1
+    GETFIELD #25 ; string "values"
+    LOAD 4
+    INDEX
+    LOAD 1
+    GETFIELD #26 ; string "signatures"
+    LOAD 4
+    INDEX
+    LOAD 1
+    GETFIELD #27 ; string "calldatas"
+    LOAD 4
+    INDEX
+    ENV #7 ; string "block.timestamp"
+    SLOAD 2
+    ADD
+    CALL queue_transaction 5
+    STORE 5
+    LOAD 5
+    ISVARIANT #28 ; string "Err"
+    NOT
+    JUMPI L52
+    LOAD 5
+    JUMP L68
+L52:
+    LOAD 5
+    EXTRACT 0
+    POP
+    LOAD 2
+    PUSH #8 ; u256 1
+    ADD
+    STORE 2
+    JUMP L18
+L60:
+    LOAD 1
+    PUSH #29 ; bool true
+    SETFIELD #30 ; string "executed"
+    STORE 1
+    LOAD 0
+    EMIT ProposalExecuted 1
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+L68:
+    UNGUARD
+    RET
+
+.function state arity=1 locals=3 pure
+    PUSH #12 ; u256 8
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    GETFIELD #31 ; string "canceled"
+    NOT
+    JUMPI L11
+    PUSH #19 ; u256 2
+    RET
+L11:
+    LOAD 1
+    GETFIELD #30 ; string "executed"
+    NOT
+    JUMPI L17
+    PUSH #32 ; u256 5
+    RET
+L17:
+    ENV #7 ; string "block.timestamp"
+    STORE 2
+    LOAD 2
+    LOAD 1
+    GETFIELD #33 ; string "start_time"
+    LT
+    NOT
+    JUMPI L27
+    PUSH #10 ; u256 0
+    RET
+L27:
+    LOAD 2
+    LOAD 1
+    GETFIELD #34 ; string "end_time"
+    LTE
+    NOT
+    JUMPI L35
+    PUSH #8 ; u256 1
+    RET
+L35:
+    LOAD 1
+    GETFIELD #18 ; string "for_votes"
+    LOAD 1
+    GETFIELD #17 ; string "against_votes"
+    LTE
+    JUMPI L49
+    LOAD 1
+    GETFIELD #18 ; string "for_votes"
+    LOAD 1
+    GETFIELD #17 ; string "against_votes"
+    ADD
+    SLOAD 4
+    LT
+    JUMP L50
+L49:
+    PUSH #29 ; bool true
+L50:
+    NOT
+    JUMPI L54
+    PUSH #35 ; u256 3
+    RET
+L54:
+    PUSH #22 ; u256 4
+    RET
+
+.function get_votes arity=1 locals=1 pure
+    PUSH #36 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function queue_transaction arity=5 locals=6
+    LOAD 4
+    ENV #7 ; string "block.timestamp"
+    SLOAD 12
+    ADD
+    GTE
+    JUMPI L7
+    REVERT #37 ; string "Too early"
+L7:
+    LOAD 2
+    CALLH #38 1 ; string "keccak256"
+    STORE 5
+    PUSH #39 ; u256 11
+    LOAD 5
+    MAPSLOT
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    LOAD 3
+    LOAD 4
+    PUSH #5 ; bool false
+    STRUCT #40 6 ; string "TimelockTx {target, value, signature, data, eta, executed}"
+    SSTOREAT
+    LOAD 5
+    VARIANT #13 1 ; string "Ok"
+    RET
@@ -0,0 +1,656 @@
+.contract AdvancedNFT
+
+.const #0 u256 1000
+.const #1 string "Royalty too high"
+.const #2 string "msg.sender"
+.const #3 string "Token doesn't exist"
+.const #4 u256 5
+.const #5 u256 6
+.const #6 u256 7
+.const #7 u256 8
+.const #8 u256 9
+.const #9 string "Some"
+.const #10 string "{}/{}"
+.const #11 string "format!"
+.const #12 u256 11
+.const #13 string "clone"
+.const #14 u256 12
+.const #15 string "Sale not active"
+.const #16 string "msg.value"
+.const #17 string "Insufficient payment"
+.const #18 string "Max supply reached"
+.const #19 u256 1
+.const #20 string "Err"
+.const #21 string "Ok"
+.const #22 string "contains"
+.const #23 string "Not whitelisted"
+.const #24 u256 17
+.const #25 u256 0
+.const #26 string "Exceeded mint limit"
+.const #27 bool true
+.const #28 string "Not authorized"
+.const #29 string "Wrong owner"
+.const #30 address 0x0000000000000000000000000000000000000000
+.const #31 string "Invalid recipient"
+.const #32 string "Self approval"
+.const #33 u256 10000
+.const #34 string "Not owner"
+.const #35 string "len"
+.const #36 string "insert"
+.const #37 u256 2
+.const #38 string "Token already exists"
+.const #39 string "block.timestamp"
+.const #40 string "keccak256"
+.const #41 string "Background"
+.const #42 string "to_string"
+.const #43 string "get_random_background"
+.const #44 string "calculate_trait_rarity"
+.const #45 string "Attribute {trait_type, value, rarity}"
+.const #46 string "Base"
+.const #47 string "get_random_base"
+.const #48 string "rarity"
+.const #49 string "u256"
+
+.storage 0 name: string
+.storage 1 symbol: string
+.storage 2 base_uri: Uri
+.storage 3 max_supply: u256
+.storage 4 mint_price: u256
+.storage 5 owners: map<u256, address>
+.storage 6 balances: map<address, u256>
+.storage 7 token_approvals: map<u256, address>
+.storage 8 operator_approvals: map<address, map<address, bool>>
+.storage 9 token_uris: map<u256, Uri>
+.storage 10 total_supply: u256
+.storage 11 attributes: map<u256, array<Attribute>>
+.storage 12 rarity_scores: map<u256, u256>
+.storage 13 royalty_recipient: address
+.storage 14 royalty_percentage: u256
+.storage 15 is_sale_active: bool
+.storage 16 whitelist: Set<Address>
+.storage 17 presale_mint_limit: map<address, u256>
+.storage 18 owner: address
+
+.event Transfer(from: address, to: address, token_id: u256)
+.event Approval(owner: address, approved: address, token_id: u256)
+.event ApprovalForAll(owner: address, operator: address, approved: bool)
+.event Mint(to: address, token_id: u256, attributes: array<Attribute>)
+.event MetadataUpdate(token_id: u256)
+
+.export init(name: string, symbol: string, base_uri: Uri, max_supply: u256, mint_price: u256, royalty_recipient: address, royalty_percentage: u256)
+.export owner_of(token_id: u256) -> address
+.export balance_of(owner: address) -> u256
+.export get_approved(token_id: u256) -> address
+.export is_approved_for_all(owner: address, operator: address) -> bool
+.export token_uri(token_id: u256) -> Uri
+.export get_attributes(token_id: u256) -> array<Attribute>
+.export get_rarity_score(token_id: u256) -> u256
+.export mint_public() -> Result<u256, Error>
+.export mint_whitelist() -> Result<u256, Error>
+.export transfer_from(from: address, to: address, token_id: u256) -> Result<(), Error>
+.export approve(approved: address, token_id: u256) -> Result<(), Error>
+.export set_approval_for_all(operator: address, approved: bool) -> Result<(), Error>
+.export get_royalty_info(token_id: u256, sale_price: u256) -> (address, u256)
+.export only_owner()
+.export set_base_uri(new_base_uri: Uri) -> Result<(), Error>
+.export set_sale_active(active: bool) -> Result<(), Error>
+.export add_to_whitelist(users: array<address>) -> Result<(), Error>
+.export _mint(to: address, token_id: u256) -> Result<(), Error>
+.export exists(token_id: u256) -> bool
+.export generate_random_attributes(token_id: u256) -> array<Attribute>
+.export calculate_rarity_score(attrs: array<Attribute>) -> u256
+
+.function init arity=7 locals=7
+    LOAD 6
+    PUSH #0 ; u256 1000
+    LTE
+    JUMPI L5
+    REVERT #1 ; string "Royalty too high"
+L5:
+    LOAD 0
+    SSTORE 0
+    LOAD 1
+    SSTORE 1
+    LOAD 2
+    SSTORE 2
+    LOAD 3
+    SSTORE 3
+    LOAD 4
+    SSTORE 4
+    LOAD 5
+    SSTORE 13
+    LOAD 6
+    SSTORE 14
+    ENV #2 ; string "msg.sender"
+    SSTORE 18
+    RET
+
+.function owner_of arity=1 locals=1 pure
+    LOAD 0
+    CALL exists 1
+    JUMPI L4
+    REVERT #3 ; string "Token doesn't exist"
+L4:
+    PUSH #4 ; u256 5
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function balance_of arity=1 locals=1 pure
+    PUSH #5 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function get_approved arity=1 locals=1 pure
+    LOAD 0
+    CALL exists 1
+    JUMPI L4
+    REVERT #3 ; string "Token doesn't exist"
+L4:
+    PUSH #6 ; u256 7
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function is_approved_for_all arity=2 locals=2 pure
+    PUSH #7 ; u256 8
+    LOAD 0
+    MAPSLOT
+    LOAD 1
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function token_uri arity=1 locals=3 pure
+    LOAD 0
+    CALL exists 1
+    JUMPI L4
+    REVERT #3 ; string "Token doesn't exist"
+L4:
+    PUSH #8 ; u256 9
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    ISVARIANT #9 ; string "Some"
+    NOT
+    JUMPI L19
+    LOAD 1
+    EXTRACT 0
+    STORE 2
+    LOAD 2
+    POP
+    RET
+L19:
+    PUSH #10 ; string "{}/{}"
+    SLOAD 2
+    LOAD 0
+    CALLH #11 3 ; string "format!"
+    POP
+    RET
+
+.function get_attributes arity=1 locals=1 pure
+    LOAD 0
+    CALL exists 1
+    JUMPI L4
+    REVERT #3 ; string "Token doesn't exist"
+L4:
+    PUSH #12 ; u256 11
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    CALLM #13 0 ; string "clone"
+    RET
+
+.function get_rarity_score arity=1 locals=1 pure
+    LOAD 0
+    CALL exists 1
+    JUMPI L4
+    REVERT #3 ; string "Token doesn't exist"
+L4:
+    PUSH #14 ; u256 12
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    RET
+
+.function mint_public arity=0 locals=4
+    GUARD
+    SLOAD 15
+    JUMPI L4
+    REVERT #15 ; string "Sale not active"
+L4:
+    ENV #16 ; string "msg.value"
+    SLOAD 4
+    GTE
+    JUMPI L9
+    REVERT #17 ; string "Insufficient payment"
+L9:
+    SLOAD 10
+    SLOAD 3
+    LT
+    JUMPI L14
+    REVERT #18 ; string "Max supply reached"
+L14:
+    SLOAD 10
+    PUSH #19 ; u256 1
+    ADD
+    STORE 0
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    CALL _mint 2
+    STORE 1
+    LOAD 1
+    ISVARIANT #20 ; string "Err"
+    NOT
+    JUMPI L28
+    LOAD 1
+    JUMP L54
+L28:
+    LOAD 1
+    EXTRACT 0
+    POP
+    LOAD 0
+    CALL generate_random_attributes 1
+    STORE 2
+    PUSH #12 ; u256 11
+    LOAD 0
+    MAPSLOT
+    LOAD 2
+    CALLM #13 0 ; string "clone"
+    SSTOREAT
+    LOAD 2
+    CALL calculate_rarity_score 1
+    STORE 3
+    PUSH #14 ; u256 12
+    LOAD 0
+    MAPSLOT
+    LOAD 3
+    SSTOREAT
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    LOAD 2
+    EMIT Mint 3
+    LOAD 0
+    VARIANT #21 1 ; string "Ok"
+L54:
+    UNGUARD
+    RET
+
+.function mint_whitelist arity=0 locals=0
+    GUARD
+    SLOAD 15
+    JUMPI L4
+    REVERT #15 ; string "Sale not active"
+L4:
+    SLOAD 16
+    ENV #2 ; string "msg.sender"
+    CALLM #22 1 ; string "contains"
+    JUMPI L9
+    REVERT #23 ; string "Not whitelisted"
+L9:
+    PUSH #24 ; u256 17
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    PUSH #25 ; u256 0
+    GT
+    JUMPI L17
+    REVERT #26 ; string "Exceeded mint limit"
+L17:
+    PUSH #24 ; u256 17
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    PUSH #24 ; u256 17
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    PUSH #19 ; u256 1
+    SUB
+    SSTOREAT
+    CALL mint_public 0
+    UNGUARD
+    RET
+
+.function transfer_from arity=3 locals=3
+    GUARD
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    EQ
+    JUMPI L12
+    ENV #2 ; string "msg.sender"
+    PUSH #6 ; u256 7
+    LOAD 2
+    MAPSLOT
+    SLOADAT
+    EQ
+    JUMP L13
+L12:
+    PUSH #27 ; bool true
+L13:
+    JUMPI L21
+    PUSH #7 ; u256 8
+    LOAD 0
+    MAPSLOT
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    JUMP L22
+L21:
+    PUSH #27 ; bool true
+L22:
+    JUMPI L24
+    REVERT #28 ; string "Not authorized"
+L24:
+    LOAD 2
+    CALL owner_of 1
+    LOAD 0
+    EQ
+    JUMPI L30
+    REVERT #29 ; string "Wrong owner"
+L30:
+    LOAD 1
+    PUSH #30 ; address 0x0000000000000000000000000000000000000000
+    EQ
+    NOT
+    JUMPI L36
+    REVERT #31 ; string "Invalid recipient"
+L36:
+    PUSH #6 ; u256 7
+    LOAD 2
+    MAPSLOT
+    PUSH #30 ; address 0x0000000000000000000000000000000000000000
+    SSTOREAT
+    PUSH #5 ; u256 6
+    LOAD 0
+    MAPSLOT
+    PUSH #5 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    PUSH #19 ; u256 1
+    SUB
+    SSTOREAT
+    PUSH #5 ; u256 6
+    LOAD 1
+    MAPSLOT
+    PUSH #5 ; u256 6
+    LOAD 1
+    MAPSLOT
+    SLOADAT
+    PUSH #19 ; u256 1
+    ADD
+    SSTOREAT
+    PUSH #4 ; u256 5
+    LOAD 2
+    MAPSLOT
+    LOAD 1
+    SSTOREAT
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    EMIT Transfer 3
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function approve arity=2 locals=3
+    GUARD
+    LOAD 1
+    CALL owner_of 1
+    STORE 2
+    ENV #2 ; string "msg.sender"
+    LOAD 2
+    EQ
+    JUMPI L15
+    PUSH #7 ; u256 8
+    LOAD 2
+    MAPSLOT
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    JUMP L16
+L15:
+    PUSH #27 ; bool true
+L16:
+    JUMPI L18
+    REVERT #28 ; string "Not authorized"
+L18:
+    PUSH #6 ; u256 7
+    LOAD 1
+    MAPSLOT
+    LOAD 0
+    SSTOREAT
+    LOAD 2
+    LOAD 0
+    LOAD 1
+    EMIT Approval 3
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function set_approval_for_all arity=2 locals=2
+    GUARD
+    LOAD 0
+    ENV #2 ; string "msg.sender"
+    EQ
+    NOT
+    JUMPI L7
+    REVERT #32 ; string "Self approval"
+L7:
+    PUSH #7 ; u256 8
+    ENV #2 ; string "msg.sender"
+    MAPSLOT
+    LOAD 0
+    MAPSLOT
+    LOAD 1
+    SSTOREAT
+    ENV #2 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    EMIT ApprovalForAll 3
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function get_royalty_info arity=2 locals=3
+    GUARD
+    LOAD 0
+    CALL exists 1
+    JUMPI L5
+    REVERT #3 ; string "Token doesn't exist"
+L5:
+    LOAD 1
+    SLOAD 14
+    MUL
+    PUSH #33 ; u256 10000
+    DIV
+    STORE 2
+    SLOAD 13
+    LOAD 2
+    TUPLE 2
+    UNGUARD
+    RET
+
+.function only_owner arity=0 locals=0
+    ENV #2 ; string "msg.sender"
+    SLOAD 18
+    EQ
+    JUMPI L5
+    REVERT #34 ; string "Not owner"
+L5:
+    RET
+
+.function set_base_uri arity=1 locals=1
+    CALL only_owner 0
+    LOAD 0
+    SSTORE 2
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    RET
+
+.function set_sale_active arity=1 locals=1
+    CALL only_owner 0
+    LOAD 0
+    SSTORE 15
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    RET
+
+.function add_to_whitelist arity=1 locals=5
+    CALL only_owner 0
+    LOAD 0
+    STORE 3
+    PUSH #25 ; u256 0
+    STORE 1
+    LOAD 3
+    CALLM #35 0 ; string "len"
+    STORE 2
+L8:
+    LOAD 1
+    LOAD 2
+    LT
+    NOT
+    JUMPI L31
+    LOAD 3
+    LOAD 1
+    INDEX
+    STORE 4
+    SLOAD 16
+    LOAD 4
+    CALLM #36 1 ; string "insert"
+    POP
+    PUSH #24 ; u256 17
+    LOAD 4
+    MAPSLOT
+    PUSH #37 ; u256 2
+    SSTOREAT
+    LOAD 1
+    PUSH #19 ; u256 1
+    ADD
+    STORE 1
+    JUMP L8
+L31:
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    RET
+
+.function _mint arity=2 locals=2
+    LOAD 1
+    CALL exists 1
+    NOT
+    JUMPI L5
+    REVERT #38 ; string "Token already exists"
+L5:
+    LOAD 0
+    PUSH #30 ; address 0x0000000000000000000000000000000000000000
+    EQ
+    NOT
+    JUMPI L11
+    REVERT #31 ; string "Invalid recipient"
+L11:
+    PUSH #4 ; u256 5
+    LOAD 1
+    MAPSLOT
+    LOAD 0
+    SSTOREAT
+    PUSH #5 ; u256 6
+    LOAD 0
+    MAPSLOT
+    PUSH #5 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    PUSH #19 ; u256 1
+    ADD
+    SSTOREAT
+    SLOAD 10
+    PUSH #19 ; u256 1
+    ADD
+    SSTORE 10
+    PUSH #30 ; address 0x0000000000000000000000000000000000000000
+    LOAD 0
+    LOAD 1
+    EMIT Transfer 3
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+    RET
+
+.function exists arity=1 locals=1
+    PUSH #4 ; u256 5
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    PUSH #30 ; address 0x0000000000000000000000000000000000000000
+    EQ
+    NOT
+    RET
+
+.function generate_random_attributes arity=1 locals=2
+    LOAD 0
+    ENV #39 ; string "block.timestamp"
+    ADD
+    CALLH #40 1 ; string "keccak256"
+    STORE 1
+    PUSH #41 ; string "Background"
+    CALLM #42 0 ; string "to_string"
+    LOAD 1
+    CALLH #43 1 ; string "get_random_background"
+    PUSH #41 ; string "Background"
+    CALLH #44 1 ; string "calculate_trait_rarity"
+    STRUCT #45 3 ; string "Attribute {trait_type, value, rarity}"
+    PUSH #46 ; string "Base"
+    CALLM #42 0 ; string "to_string"
+    LOAD 1
+    CALLH #47 1 ; string "get_random_base"
+    PUSH #46 ; string "Base"
+    CALLH #44 1 ; string "calculate_trait_rarity"
+    STRUCT #45 3 ; string "Attribute {trait_type, value, rarity}"
+    ARRAY 2
+    RET
+
+.function calculate_rarity_score arity=1 locals=6
+    PUSH #25 ; u256 0
+    STORE 1
+    LOAD 0
+    STORE 4
+    PUSH #25 ; u256 0
+    STORE 2
+    LOAD 4
+    CALLM #35 0 ; string "len"
+    STORE 3
+L9:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L28
+    LOAD 4
+    LOAD 2
+    INDEX
+    STORE 5
+    LOAD 1
+    LOAD 5
+    GETFIELD #48 ; string "rarity"
+    ADD
+    STORE 1
+    LOAD 2
+    PUSH #19 ; u256 1
+    ADD
+    STORE 2
+    JUMP L9
+L28:
+    LOAD 1
+    LOAD 0
+    CALLM #35 0 ; string "len"
+    CAST #49 ; string "u256"
+    DIV
+    RET
@@ -0,0 +1,778 @@
+.contract NFTMarketplace
+
+.const #0 u256 1000
+.const #1 string "Fee too high"
+.const #2 u256 0
+.const #3 string "Invalid price"
+.const #4 string "msg.sender"
+.const #5 string "self"
+.const #6 string "transfer_from"
+.const #7 string "Err"
+.const #8 u256 1
+.const #9 u256 2
+.const #10 bool true
+.const #11 string "map"
+.const #12 string "Listing {seller, nft_contract, token_id, price, is_active, end_time}"
+.const #13 string "Ok"
+.const #14 string "is_active"
+.const #15 string "Listing not active"
+.const #16 string "msg.value"
+.const #17 string "price"
+.const #18 string "Insufficient payment"
+.const #19 string "end_time"
+.const #20 string "Some"
+.const #21 string "block.timestamp"
+.const #22 string "Listing expired"
+.const #23 u256 10000
+.const #24 string "nft_contract"
+.const #25 string "token_id"
+.const #26 string "seller"
+.const #27 string "get_royalty_info"
+.const #28 bool false
+.const #29 string "Invalid min bid"
+.const #30 string "Invalid increment"
+.const #31 u256 86400
+.const #32 string "Duration too short"
+.const #33 u256 3
+.const #34 address 0x0000000000000000000000000000000000000000
+.const #35 string "Auction {highest_bidder, highest_bid, min_bid, increment, end_time, is_active}"
+.const #36 string "Auction not active"
+.const #37 string "Auction ended"
+.const #38 string "min_bid"
+.const #39 string "highest_bid"
+.const #40 string "increment"
+.const #41 string "Bid too low"
+.const #42 string "highest_bidder"
+.const #43 string "Auction not ended"
+.const #44 string "Invalid offer amount"
+.const #45 string "Offer {buyer, price, expiration}"
+.const #46 u256 4
+.const #47 string "push"
+.const #48 string "expiration"
+.const #49 string "Not seller"
+.const #50 string "Offer expired"
+.const #51 string "buyer"
+.const #52 string "iter"
+.const #53 string "enumerate"
+.const #54 string "len"
+.const #55 string "transfer"
+.const #56 string "ETH transfer failed"
+.const #57 string "expect"
+
+.storage 0 platform_fee: u256
+.storage 1 fee_recipient: address
+.storage 2 listings: map<u256, Listing>
+.storage 3 auctions: map<u256, Auction>
+.storage 4 offers: map<u256, array<Offer>>
+.storage 5 listing_count: u256
+
+.event ListingCreated(listing_id: u256, seller: address, nft_contract: address, token_id: u256, price: u256)
+.event ListingSold(listing_id: u256, buyer: address, price: u256)
+.event AuctionCreated(listing_id: u256, min_bid: u256, duration: Duration)
+.event BidPlaced(listing_id: u256, bidder: address, amount: u256)
+.event OfferMade(listing_id: u256, buyer: address, price: u256, expiration: Timestamp)
+
+.export init(platform_fee: u256, fee_recipient: address)
+.export create_listing(nft_contract: address, token_id: u256, price: u256, duration: Option<Duration>) -> Result<u256, Error>
+.export buy_listing(listing_id: u256) -> Result<(), Error>
+.export create_auction(nft_contract: address, token_id: u256, min_bid: u256, increment: u256, duration: Duration) -> Result<u256, Error>
+.export place_bid(listing_id: u256) -> Result<(), Error>
+.export end_auction(listing_id: u256) -> Result<(), Error>
+.export make_offer(listing_id: u256, duration: Duration) -> Result<(), Error>
+.export accept_offer(listing_id: u256, offer_index: u256) -> Result<(), Error>
+.export calculate_royalty(nft_contract: address, token_id: u256, sale_price: u256) -> u256
+.export transfer_eth(to: address, amount: u256)
+
+.function init arity=2 locals=2
+    LOAD 0
+    PUSH #0 ; u256 1000
+    LTE
+    JUMPI L5
+    REVERT #1 ; string "Fee too high"
+L5:
+    LOAD 0
+    SSTORE 0
+    LOAD 1
+    SSTORE 1
+    RET
+
+.function create_listing arity=4 locals=7
+    GUARD
+    LOAD 2
+    PUSH #2 ; u256 0
+    GT
+    JUMPI L6
+    REVERT #3 ; string "Invalid price"
+L6:
+    LOAD 0
+    ENV #4 ; string "msg.sender"
+    ENV #5 ; string "self"
+    LOAD 1
+    CALLM #6 3 ; string "transfer_from"
+    STORE 4
+    LOAD 4
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L18
+    LOAD 4
+    JUMP L50
+L18:
+    LOAD 4
+    EXTRACT 0
+    POP
+    SLOAD 5
+    PUSH #8 ; u256 1
+    ADD
+    DUP 0
+    STORE 6
+    SSTORE 5
+    LOAD 6
+    STORE 5
+    PUSH #9 ; u256 2
+    LOAD 5
+    MAPSLOT
+    ENV #4 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    PUSH #10 ; bool true
+    LOAD 3
+    CLOSURE create_listing::closure0 0
+    CALLM #11 1 ; string "map"
+    STRUCT #12 6 ; string "Listing {seller, nft_contract, token_id, price, is_active, end_time}"
+    SSTOREAT
+    LOAD 5
+    ENV #4 ; string "msg.sender"
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    EMIT ListingCreated 5
+    LOAD 5
+    VARIANT #13 1 ; string "Ok"
+L50:
+    UNGUARD
+    RET
+
+.function buy_listing arity=1 locals=10
+    GUARD
+    PUSH #9 ; u256 2
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    GETFIELD #14 ; string "is_active"
+    JUMPI L10
+    REVERT #15 ; string "Listing not active"
+L10:
+    ENV #16 ; string "msg.value"
+    LOAD 1
+    GETFIELD #17 ; string "price"
+    GTE
+    JUMPI L16
+    REVERT #18 ; string "Insufficient payment"
+L16:
+    LOAD 1
+    GETFIELD #19 ; string "end_time"
+    STORE 2
+    LOAD 2
+    ISVARIANT #20 ; string "Some"
+    NOT
+    JUMPI L31
+    LOAD 2
+    EXTRACT 0
+    STORE 3
+    ENV #21 ; string "block.timestamp"
+    LOAD 3
+    LTE
+    JUMPI L31
+    REVERT #22 ; string "Listing expired"
+L31:
+    ENV #16 ; string "msg.value"
+    SLOAD 0
+    MUL
+    PUSH #23 ; u256 10000
+    DIV
+    STORE 4
+    LOAD 1
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 1
+    GETFIELD #25 ; string "token_id"
+    ENV #16 ; string "msg.value"
+    CALL calculate_royalty 3
+    STORE 5
+    ENV #16 ; string "msg.value"
+    LOAD 4
+    SUB
+    LOAD 5
+    SUB
+    STORE 6
+    SLOAD 1
+    LOAD 4
+    CALL transfer_eth 2
+    LOAD 1
+    GETFIELD #26 ; string "seller"
+    LOAD 6
+    CALL transfer_eth 2
+    LOAD 5
+    PUSH #2 ; u256 0
+    GT
+    NOT
+    JUMPI L75
+    LOAD 1
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 1
+    GETFIELD #25 ; string "token_id"
+    ENV #16 ; string "msg.value"
+    CALLM #27 2 ; string "get_royalty_info"
+    STORE 7
+    LOAD 7
+    EXTRACT 0
+    STORE 8
+    LOAD 8
+    LOAD 5
+    CALL transfer_eth 2
+L75:
+    LOAD 1
+    GETFIELD #24 ; string "nft_contract"
+    ENV #5 ; string "self"
+    ENV #4 ; string "msg.sender"
+    LOAD 1
+    GETFIELD #25 ; string "token_id"
+    CALLM #6 3 ; string "transfer_from"
+    STORE 9
+    LOAD 9
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L89
+    LOAD 9
+    JUMP L102
+L89:
+    LOAD 9
+    EXTRACT 0
+    POP
+    LOAD 1
+    PUSH #28 ; bool false
+    SETFIELD #14 ; string "is_active"
+    STORE 1
+    LOAD 0
+    ENV #4 ; string "msg.sender"
+    ENV #16 ; string "msg.value"
+    EMIT ListingSold 3
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+L102:
+    UNGUARD
+    RET
+
+.function create_auction arity=5 locals=7
+    GUARD
+    LOAD 2
+    PUSH #2 ; u256 0
+    GT
+    JUMPI L6
+    REVERT #29 ; string "Invalid min bid"
+L6:
+    LOAD 3
+    PUSH #2 ; u256 0
+    GT
+    JUMPI L11
+    REVERT #30 ; string "Invalid increment"
+L11:
+    LOAD 4
+    PUSH #31 ; u256 86400
+    GTE
+    JUMPI L16
+    REVERT #32 ; string "Duration too short"
+L16:
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    LOAD 4
+    VARIANT #20 1 ; string "Some"
+    CALL create_listing 4
+    STORE 5
+    LOAD 5
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L29
+    LOAD 5
+    JUMP L51
+L29:
+    LOAD 5
+    EXTRACT 0
+    STORE 6
+    PUSH #33 ; u256 3
+    LOAD 6
+    MAPSLOT
+    PUSH #34 ; address 0x0000000000000000000000000000000000000000
+    PUSH #2 ; u256 0
+    LOAD 2
+    LOAD 3
+    ENV #21 ; string "block.timestamp"
+    LOAD 4
+    ADD
+    PUSH #10 ; bool true
+    STRUCT #35 6 ; string "Auction {highest_bidder, highest_bid, min_bid, increment, end_time, is_active}"
+    SSTOREAT
+    LOAD 6
+    LOAD 2
+    LOAD 4
+    EMIT AuctionCreated 3
+    LOAD 6
+    VARIANT #13 1 ; string "Ok"
+L51:
+    UNGUARD
+    RET
+
+.function place_bid arity=1 locals=3
+    GUARD
+    PUSH #33 ; u256 3
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    GETFIELD #14 ; string "is_active"
+    JUMPI L10
+    REVERT #36 ; string "Auction not active"
+L10:
+    ENV #21 ; string "block.timestamp"
+    LOAD 1
+    GETFIELD #19 ; string "end_time"
+    LT
+    JUMPI L16
+    REVERT #37 ; string "Auction ended"
+L16:
+    ENV #16 ; string "msg.value"
+    STORE 2
+    LOAD 2
+    LOAD 1
+    GETFIELD #38 ; string "min_bid"
+    GTE
+    JUMPI L25
+    PUSH #28 ; bool false
+    JUMP L32
+L25:
+    LOAD 2
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    LOAD 1
+    GETFIELD #40 ; string "increment"
+    ADD
+    GTE
+L32:
+    JUMPI L34
+    REVERT #41 ; string "Bid too low"
+L34:
+    LOAD 1
+    GETFIELD #42 ; string "highest_bidder"
+    PUSH #34 ; address 0x0000000000000000000000000000000000000000
+    EQ
+    JUMPI L44
+    LOAD 1
+    GETFIELD #42 ; string "highest_bidder"
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    CALL transfer_eth 2
+L44:
+    LOAD 1
+    ENV #4 ; string "msg.sender"
+    SETFIELD #42 ; string "highest_bidder"
+    STORE 1
+    LOAD 1
+    LOAD 2
+    SETFIELD #39 ; string "highest_bid"
+    STORE 1
+    LOAD 0
+    ENV #4 ; string "msg.sender"
+    LOAD 2
+    EMIT BidPlaced 3
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function end_auction arity=1 locals=10
+    GUARD
+    PUSH #33 ; u256 3
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    GETFIELD #14 ; string "is_active"
+    JUMPI L10
+    REVERT #36 ; string "Auction not active"
+L10:
+    ENV #21 ; string "block.timestamp"
+    LOAD 1
+    GETFIELD #19 ; string "end_time"
+    GTE
+    JUMPI L16
+    REVERT #43 ; string "Auction not ended"
+L16:
+    PUSH #9 ; u256 2
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 2
+    LOAD 1
+    GETFIELD #42 ; string "highest_bidder"
+    PUSH #34 ; address 0x0000000000000000000000000000000000000000
+    EQ
+    JUMPI L99
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    SLOAD 0
+    MUL
+    PUSH #23 ; u256 10000
+    DIV
+    STORE 3
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    CALL calculate_royalty 3
+    STORE 4
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    LOAD 3
+    SUB
+    LOAD 4
+    SUB
+    STORE 5
+    SLOAD 1
+    LOAD 3
+    CALL transfer_eth 2
+    LOAD 2
+    GETFIELD #26 ; string "seller"
+    LOAD 5
+    CALL transfer_eth 2
+    LOAD 4
+    PUSH #2 ; u256 0
+    GT
+    NOT
+    JUMPI L74
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    CALLM #27 2 ; string "get_royalty_info"
+    STORE 6
+    LOAD 6
+    EXTRACT 0
+    STORE 7
+    LOAD 7
+    LOAD 4
+    CALL transfer_eth 2
+L74:
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    ENV #5 ; string "self"
+    LOAD 1
+    GETFIELD #42 ; string "highest_bidder"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    CALLM #6 3 ; string "transfer_from"
+    STORE 8
+    LOAD 8
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L89
+    LOAD 8
+    JUMP L127
+L89:
+    LOAD 8
+    EXTRACT 0
+    POP
+    LOAD 0
+    LOAD 1
+    GETFIELD #42 ; string "highest_bidder"
+    LOAD 1
+    GETFIELD #39 ; string "highest_bid"
+    EMIT ListingSold 3
+    JUMP L117
+L99:
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    ENV #5 ; string "self"
+    LOAD 2
+    GETFIELD #26 ; string "seller"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    CALLM #6 3 ; string "transfer_from"
+    STORE 9
+    LOAD 9
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L114
+    LOAD 9
+    JUMP L127
+L114:
+    LOAD 9
+    EXTRACT 0
+    POP
+L117:
+    LOAD 1
+    PUSH #28 ; bool false
+    SETFIELD #14 ; string "is_active"
+    STORE 1
+    LOAD 2
+    PUSH #28 ; bool false
+    SETFIELD #14 ; string "is_active"
+    STORE 2
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+L127:
+    UNGUARD
+    RET
+
+.function make_offer arity=2 locals=4
+    GUARD
+    PUSH #9 ; u256 2
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 2
+    LOAD 2
+    GETFIELD #14 ; string "is_active"
+    JUMPI L10
+    REVERT #15 ; string "Listing not active"
+L10:
+    ENV #16 ; string "msg.value"
+    PUSH #2 ; u256 0
+    GT
+    JUMPI L15
+    REVERT #44 ; string "Invalid offer amount"
+L15:
+    ENV #4 ; string "msg.sender"
+    ENV #16 ; string "msg.value"
+    ENV #21 ; string "block.timestamp"
+    LOAD 1
+    ADD
+    STRUCT #45 3 ; string "Offer {buyer, price, expiration}"
+    STORE 3
+    PUSH #46 ; u256 4
+    LOAD 0
+    MAPSLOT
+    PUSH #46 ; u256 4
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    LOAD 3
+    CALLM #47 1 ; string "push"
+    SSTOREAT
+    LOAD 0
+    ENV #4 ; string "msg.sender"
+    ENV #16 ; string "msg.value"
+    LOAD 3
+    GETFIELD #48 ; string "expiration"
+    EMIT OfferMade 4
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+    UNGUARD
+    RET
+
+.function accept_offer arity=2 locals=16
+    GUARD
+    PUSH #9 ; u256 2
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 2
+    ENV #4 ; string "msg.sender"
+    LOAD 2
+    GETFIELD #26 ; string "seller"
+    EQ
+    JUMPI L12
+    REVERT #49 ; string "Not seller"
+L12:
+    PUSH #46 ; u256 4
+    LOAD 0
+    MAPSLOT
+    LOAD 1
+    MAPSLOT
+    SLOADAT
+    STORE 3
+    ENV #21 ; string "block.timestamp"
+    LOAD 3
+    GETFIELD #48 ; string "expiration"
+    LTE
+    JUMPI L25
+    REVERT #50 ; string "Offer expired"
+L25:
+    LOAD 3
+    GETFIELD #17 ; string "price"
+    SLOAD 0
+    MUL
+    PUSH #23 ; u256 10000
+    DIV
+    STORE 4
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    LOAD 3
+    GETFIELD #17 ; string "price"
+    CALL calculate_royalty 3
+    STORE 5
+    LOAD 3
+    GETFIELD #17 ; string "price"
+    LOAD 4
+    SUB
+    LOAD 5
+    SUB
+    STORE 6
+    SLOAD 1
+    LOAD 4
+    CALL transfer_eth 2
+    LOAD 2
+    GETFIELD #26 ; string "seller"
+    LOAD 6
+    CALL transfer_eth 2
+    LOAD 5
+    PUSH #2 ; u256 0
+    GT
+    NOT
+    JUMPI L73
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    LOAD 3
+    GETFIELD #17 ; string "price"
+    CALLM #27 2 ; string "get_royalty_info"
+    STORE 7
+    LOAD 7
+    EXTRACT 0
+    STORE 8
+    LOAD 8
+    LOAD 5
+    CALL transfer_eth 2
+L73:
+    LOAD 2
+    GETFIELD #24 ; string "nft_contract"
+    ENV #5 ; string "self"
+    LOAD 3
+    GETFIELD #51 ; string "buyer"
+    LOAD 2
+    GETFIELD #25 ; string "token_id"
+    CALLM #6 3 ; string "transfer_from"
+    STORE 9
+    LOAD 9
+    ISVARIANT #7 ; string "Err"
+    NOT
+    JUMPI L88
+    LOAD 9
+    JUMP L144
+L88:
+    LOAD 9
+    EXTRACT 0
+    POP
+    LOAD 2
+    PUSH #28 ; bool false
+    SETFIELD #14 ; string "is_active"
+    STORE 2
+    PUSH #46 ; u256 4
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    CALLM #52 0 ; string "iter"
+    CALLM #53 0 ; string "enumerate"
+    STORE 12
+    PUSH #2 ; u256 0
+    STORE 10
+    LOAD 12
+    CALLM #54 0 ; string "len"
+    STORE 11
+L107:
+    LOAD 10
+    LOAD 11
+    LT
+    NOT
+    JUMPI L136
+    LOAD 12
+    LOAD 10
+    INDEX
+    STORE 13
+    LOAD 13
+    EXTRACT 0
+    STORE 14
+    LOAD 13
+    EXTRACT 1
+    STORE 15
+    LOAD 14
+    LOAD 1
+    EQ
+    JUMPI L131
+    LOAD 15
+    GETFIELD #51 ; string "buyer"
+    LOAD 15
+    GETFIELD #17 ; string "price"
+    CALL transfer_eth 2
+L131:
+    LOAD 10
+    PUSH #8 ; u256 1
+    ADD
+    STORE 10
+    JUMP L107
+L136:
+    LOAD 0
+    LOAD 3
+    GETFIELD #51 ; string "buyer"
+    LOAD 3
+    GETFIELD #17 ; string "price"
+    EMIT ListingSold 3
+    TUPLE 0
+    VARIANT #13 1 ; string "Ok"
+L144:
+    UNGUARD
+    RET
+
+.function calculate_royalty arity=3 locals=6
+    LOAD 0
+    LOAD 1
+    LOAD 2
+    CALLM #27 2 ; string "get_royalty_info"
+    STORE 3
+    LOAD 3
+    ISVARIANT #13 ; string "Ok"
+    NOT
+    JUMPI L18
+    LOAD 3
+    EXTRACT 0
+    STORE 4
+    LOAD 4
+    EXTRACT 1
+    STORE 5
+    LOAD 5
+    POP
+    RET
+L18:
+    RET
+
+.function transfer_eth arity=2 locals=2
+    LOAD 1
+    PUSH #2 ; u256 0
+    GT
+    NOT
+    JUMPI L11
+    LOAD 0
+    LOAD 1
+    CALLM #55 1 ; string "transfer"
+    PUSH #56 ; string "ETH transfer failed"
+    CALLM #57 1 ; string "expect"
+    POP
+L11:
+    RET
+
+.function create_listing::closure0 arity=1 locals=1
+    ENV #21 ; string "block.timestamp"
+    LOAD 0
+    ADD
+    RET
@@ -0,0 +1,671 @@
+.contract NFTStaking
+
+.const #0 u256 1000000000000000000000
+.const #1 u256 5000000000000000000000
+.const #2 u256 10000000000000000000000
+.const #3 u256 50000000000000000000000
+.const #4 u256 10000
+.const #5 u256 12000
+.const #6 u256 15000
+.const #7 u256 20000
+.const #8 u256 30000
+.const #9 string "Lock duration too short"
+.const #10 string "get_rarity_score"
+.const #11 string "msg.sender"
+.const #12 string "self"
+.const #13 string "transfer_from"
+.const #14 string "Err"
+.const #15 string "block.timestamp"
+.const #16 u256 0
+.const #17 string "Stake {owner, token_id, rarity_score, start_time, locked_until, accumulated_rewards, boost_multiplier}"
+.const #18 u256 5
+.const #19 u256 6
+.const #20 string "push"
+.const #21 string "Ok"
+.const #22 string "owner"
+.const #23 string "Not owner"
+.const #24 string "locked_until"
+.const #25 string "Still locked"
+.const #26 u256 11
+.const #27 string "rarity_score"
+.const #28 string "No rewards"
+.const #29 string "transfer"
+.const #30 string "clone"
+.const #31 string "len"
+.const #32 u256 1
+.const #33 u256 14
+.const #34 string "iter"
+.const #35 string "enumerate"
+.const #36 string "u256"
+.const #37 u256 10
+.const #38 string "start_time"
+.const #39 string "min"
+.const #40 string "boost_multiplier"
+.const #41 u256 1000000000000000000
+.const #42 u256 86400
+.const #43 u256 5000
+.const #44 u256 365
+.const #45 string "position"
+.const #46 string "Some"
+.const #47 string "swap_remove"
+.const #48 string "Stake::default"
+
+.storage 0 nft_contract: address
+.storage 1 reward_token: address
+.storage 2 reward_rate: u256
+.storage 3 min_stake_duration: Duration
+.storage 4 rarity_multiplier_base: u256
+.storage 5 stakes: map<u256, Stake>
+.storage 6 user_stakes: map<address, array<u256>>
+.storage 7 total_value_locked: u256
+.storage 8 rewards_per_point: u256
+.storage 9 last_update_time: Timestamp
+.storage 10 user_rewards_per_point: map<address, u256>
+.storage 11 pending_rewards: map<address, u256>
+.storage 12 level_thresholds: array<u256>
+.storage 13 level_multipliers: array<u256>
+.storage 14 user_boost_score: map<address, u256>
+
+.event NFTStaked(owner: address, token_id: u256, rarity_score: u256, lock_duration: Duration)
+.event NFTUnstaked(owner: address, token_id: u256, rewards: u256)
+.event RewardsClaimed(owner: address, amount: u256)
+.event BoostUpdated(owner: address, new_score: u256, new_level: u256)
+
+.export init(nft_contract: address, reward_token: address, reward_rate: u256, min_stake_duration: Duration, rarity_multiplier_base: u256)
+.export stake(token_id: u256, lock_duration: Duration) -> Result<(), Error>
+.export unstake(token_id: u256) -> Result<(), Error>
+.export claim_rewards() -> Result<(), Error>
+.export get_user_stakes(user: address) -> array<u256>
+.export get_stake_info(token_id: u256) -> Stake
+.export get_pending_rewards(user: address) -> u256
+.export get_boost_level(user: address) -> u256
+.export update_rewards(user: address)
+.export calculate_rewards(stake: Stake) -> u256
+.export calculate_boost_multiplier(lock_duration: Duration) -> u256
+.export calculate_user_points(user: address) -> u256
+.export update_boost_score(user: address)
+.export calculate_rewards_per_point() -> u256
+.export remove_stake(user: address, token_id: u256)
+
+.function init arity=5 locals=5
+    LOAD 0
+    SSTORE 0
+    LOAD 1
+    SSTORE 1
+    LOAD 2
+    SSTORE 2
+    LOAD 3
+    SSTORE 3
+    LOAD 4
+    SSTORE 4
+    PUSH #0 ; u256 1000000000000000000000
+    PUSH #1 ; u256 5000000000000000000000
+    PUSH #2 ; u256 10000000000000000000000
+    PUSH #3 ; u256 50000000000000000000000
+    ARRAY 4
+    SSTORE 12
+    PUSH #4 ; u256 10000
+    PUSH #5 ; u256 12000
+    PUSH #6 ; u256 15000
+    PUSH #7 ; u256 20000
+    PUSH #8 ; u256 30000
+    ARRAY 5
+    SSTORE 13
+    RET
+
+.function stake arity=2 locals=6
+    GUARD
+    LOAD 1
+    SLOAD 3
+    GTE
+    JUMPI L6
+    REVERT #9 ; string "Lock duration too short"
+L6:
+    SLOAD 0
+    LOAD 0
+    CALLM #10 1 ; string "get_rarity_score"
+    STORE 2
+    LOAD 1
+    CALL calculate_boost_multiplier 1
+    STORE 3
+    SLOAD 0
+    ENV #11 ; string "msg.sender"
+    ENV #12 ; string "self"
+    LOAD 0
+    CALLM #13 3 ; string "transfer_from"
+    STORE 4
+    LOAD 4
+    ISVARIANT #14 ; string "Err"
+    NOT
+    JUMPI L25
+    LOAD 4
+    JUMP L69
+L25:
+    LOAD 4
+    EXTRACT 0
+    POP
+    ENV #11 ; string "msg.sender"
+    CALL update_rewards 1
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    LOAD 2
+    ENV #15 ; string "block.timestamp"
+    ENV #15 ; string "block.timestamp"
+    LOAD 1
+    ADD
+    PUSH #16 ; u256 0
+    LOAD 3
+    STRUCT #17 7 ; string "Stake {owner, token_id, rarity_score, start_time, locked_until, accumulated_rewards, boost_multiplier}"
+    STORE 5
+    PUSH #18 ; u256 5
+    LOAD 0
+    MAPSLOT
+    LOAD 5
+    SSTOREAT
+    PUSH #19 ; u256 6
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    PUSH #19 ; u256 6
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 0
+    CALLM #20 1 ; string "push"
+    SSTOREAT
+    SLOAD 7
+    LOAD 2
+    ADD
+    SSTORE 7
+    ENV #11 ; string "msg.sender"
+    CALL update_boost_score 1
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    LOAD 2
+    LOAD 1
+    EMIT NFTStaked 4
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+L69:
+    UNGUARD
+    RET
+
+.function unstake arity=1 locals=4
+    GUARD
+    PUSH #18 ; u256 5
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    LOAD 1
+    GETFIELD #22 ; string "owner"
+    ENV #11 ; string "msg.sender"
+    EQ
+    JUMPI L12
+    REVERT #23 ; string "Not owner"
+L12:
+    ENV #15 ; string "block.timestamp"
+    LOAD 1
+    GETFIELD #24 ; string "locked_until"
+    GTE
+    JUMPI L18
+    REVERT #25 ; string "Still locked"
+L18:
+    ENV #11 ; string "msg.sender"
+    CALL update_rewards 1
+    LOAD 1
+    CALL calculate_rewards 1
+    STORE 2
+    PUSH #26 ; u256 11
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    PUSH #26 ; u256 11
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    LOAD 2
+    ADD
+    SSTOREAT
+    SLOAD 0
+    ENV #12 ; string "self"
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    CALLM #13 3 ; string "transfer_from"
+    STORE 3
+    LOAD 3
+    ISVARIANT #14 ; string "Err"
+    NOT
+    JUMPI L45
+    LOAD 3
+    JUMP L64
+L45:
+    LOAD 3
+    EXTRACT 0
+    POP
+    SLOAD 7
+    LOAD 1
+    GETFIELD #27 ; string "rarity_score"
+    SUB
+    SSTORE 7
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    CALL remove_stake 2
+    ENV #11 ; string "msg.sender"
+    CALL update_boost_score 1
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    LOAD 2
+    EMIT NFTUnstaked 3
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+L64:
+    UNGUARD
+    RET
+
+.function claim_rewards arity=0 locals=2
+    GUARD
+    ENV #11 ; string "msg.sender"
+    CALL update_rewards 1
+    PUSH #26 ; u256 11
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    SLOADAT
+    STORE 0
+    LOAD 0
+    PUSH #16 ; u256 0
+    GT
+    JUMPI L13
+    REVERT #28 ; string "No rewards"
+L13:
+    PUSH #26 ; u256 11
+    ENV #11 ; string "msg.sender"
+    MAPSLOT
+    PUSH #16 ; u256 0
+    SSTOREAT
+    SLOAD 1
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    CALLM #29 2 ; string "transfer"
+    STORE 1
+    LOAD 1
+    ISVARIANT #14 ; string "Err"
+    NOT
+    JUMPI L29
+    LOAD 1
+    JUMP L37
+L29:
+    LOAD 1
+    EXTRACT 0
+    POP
+    ENV #11 ; string "msg.sender"
+    LOAD 0
+    EMIT RewardsClaimed 2
+    TUPLE 0
+    VARIANT #21 1 ; string "Ok"
+L37:
+    UNGUARD
+    RET
+
+.function get_user_stakes arity=1 locals=1 pure
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    CALLM #30 0 ; string "clone"
+    RET
+
+.function get_stake_info arity=1 locals=1 pure
+    PUSH #18 ; u256 5
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    CALLM #30 0 ; string "clone"
+    RET
+
+.function get_pending_rewards arity=1 locals=7 pure
+    PUSH #26 ; u256 11
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 4
+    PUSH #16 ; u256 0
+    STORE 2
+    LOAD 4
+    CALLM #31 0 ; string "len"
+    STORE 3
+L15:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L39
+    LOAD 4
+    LOAD 2
+    INDEX
+    STORE 5
+    PUSH #18 ; u256 5
+    LOAD 5
+    MAPSLOT
+    SLOADAT
+    STORE 6
+    LOAD 1
+    LOAD 6
+    CALL calculate_rewards 1
+    ADD
+    STORE 1
+    LOAD 2
+    PUSH #32 ; u256 1
+    ADD
+    STORE 2
+    JUMP L15
+L39:
+    LOAD 1
+    RET
+
+.function get_boost_level arity=1 locals=8 pure
+    PUSH #33 ; u256 14
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 1
+    SLOAD 12
+    CALLM #34 0 ; string "iter"
+    CALLM #35 0 ; string "enumerate"
+    STORE 4
+    PUSH #16 ; u256 0
+    STORE 2
+    LOAD 4
+    CALLM #31 0 ; string "len"
+    STORE 3
+L14:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L42
+    LOAD 4
+    LOAD 2
+    INDEX
+    STORE 5
+    LOAD 5
+    EXTRACT 0
+    STORE 6
+    LOAD 5
+    EXTRACT 1
+    STORE 7
+    LOAD 1
+    LOAD 7
+    LT
+    NOT
+    JUMPI L37
+    LOAD 6
+    CAST #36 ; string "u256"
+    RET
+L37:
+    LOAD 2
+    PUSH #32 ; u256 1
+    ADD
+    STORE 2
+    JUMP L14
+L42:
+    SLOAD 12
+    CALLM #31 0 ; string "len"
+    CAST #36 ; string "u256"
+    RET
+
+.function update_rewards arity=1 locals=4
+    CALL calculate_rewards_per_point 0
+    STORE 1
+    LOAD 1
+    SLOAD 8
+    GT
+    NOT
+    JUMPI L11
+    LOAD 1
+    SSTORE 8
+    ENV #15 ; string "block.timestamp"
+    SSTORE 9
+L11:
+    LOAD 0
+    CALL calculate_user_points 1
+    STORE 2
+    LOAD 2
+    SLOAD 8
+    PUSH #37 ; u256 10
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    SUB
+    MUL
+    STORE 3
+    PUSH #26 ; u256 11
+    LOAD 0
+    MAPSLOT
+    PUSH #26 ; u256 11
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    LOAD 3
+    ADD
+    SSTOREAT
+    PUSH #37 ; u256 10
+    LOAD 0
+    MAPSLOT
+    SLOAD 8
+    SSTOREAT
+    RET
+
+.function calculate_rewards arity=1 locals=5
+    ENV #15 ; string "block.timestamp"
+    LOAD 0
+    GETFIELD #38 ; string "start_time"
+    SUB
+    LOAD 0
+    GETFIELD #24 ; string "locked_until"
+    LOAD 0
+    GETFIELD #38 ; string "start_time"
+    SUB
+    CALLH #39 2 ; string "min"
+    STORE 1
+    LOAD 1
+    SLOAD 2
+    MUL
+    STORE 2
+    LOAD 0
+    GETFIELD #27 ; string "rarity_score"
+    SLOAD 4
+    MUL
+    PUSH #4 ; u256 10000
+    DIV
+    STORE 3
+    LOAD 0
+    GETFIELD #40 ; string "boost_multiplier"
+    STORE 4
+    LOAD 2
+    LOAD 3
+    MUL
+    LOAD 4
+    MUL
+    PUSH #41 ; u256 1000000000000000000
+    DIV
+    RET
+
+.function calculate_boost_multiplier arity=1 locals=2
+    LOAD 0
+    PUSH #42 ; u256 86400
+    DIV
+    STORE 1
+    PUSH #4 ; u256 10000
+    LOAD 1
+    PUSH #43 ; u256 5000
+    MUL
+    PUSH #44 ; u256 365
+    DIV
+    ADD
+    PUSH #6 ; u256 15000
+    CALLH #39 2 ; string "min"
+    RET
+
+.function calculate_user_points arity=1 locals=7
+    PUSH #16 ; u256 0
+    STORE 1
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 4
+    PUSH #16 ; u256 0
+    STORE 2
+    LOAD 4
+    CALLM #31 0 ; string "len"
+    STORE 3
+L12:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L39
+    LOAD 4
+    LOAD 2
+    INDEX
+    STORE 5
+    PUSH #18 ; u256 5
+    LOAD 5
+    MAPSLOT
+    SLOADAT
+    STORE 6
+    LOAD 1
+    LOAD 6
+    GETFIELD #27 ; string "rarity_score"
+    LOAD 6
+    GETFIELD #40 ; string "boost_multiplier"
+    MUL
+    ADD
+    STORE 1
+    LOAD 2
+    PUSH #32 ; u256 1
+    ADD
+    STORE 2
+    JUMP L12
+L39:
+    LOAD 1
+    RET
+
+.function update_boost_score arity=1 locals=7
+    PUSH #16 ; u256 0
+    STORE 1
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 4
+    PUSH #16 ; u256 0
+    STORE 2
+    LOAD 4
+    CALLM #31 0 ; string "len"
+    STORE 3
+L12:
+    LOAD 2
+    LOAD 3
+    LT
+    NOT
+    JUMPI L36
+    LOAD 4
+    LOAD 2
+    INDEX
+    STORE 5
+    PUSH #18 ; u256 5
+    LOAD 5
+    MAPSLOT
+    SLOADAT
+    STORE 6
+    LOAD 1
+    LOAD 6
+    GETFIELD #27 ; string "rarity_score"
+    ADD
+    STORE 1
+    LOAD 2
+    PUSH #32 ; u256 1
+    ADD
+    STORE 2
+    JUMP L12
+L36:
+    PUSH #33 ; u256 14
+    LOAD 0
+    MAPSLOT
+    LOAD 1
+    SSTOREAT
+    LOAD 0
+    LOAD 1
+    LOAD 0
+    CALL get_boost_level 1
+    EMIT BoostUpdated 3
+    RET
+
+.function calculate_rewards_per_point arity=0 locals=1
+    SLOAD 7
+    PUSH #16 ; u256 0
+    EQ
+    NOT
+    JUMPI L7
+    SLOAD 8
+    RET
+L7:
+    ENV #15 ; string "block.timestamp"
+    SLOAD 9
+    SUB
+    STORE 0
+    SLOAD 8
+    LOAD 0
+    SLOAD 2
+    MUL
+    SLOAD 7
+    DIV
+    ADD
+    RET
+
+.function remove_stake arity=2 locals=5
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    SLOADAT
+    STORE 2
+    LOAD 2
+    CALLM #34 0 ; string "iter"
+    LOAD 1
+    CLOSURE remove_stake::closure0 1
+    CALLM #45 1 ; string "position"
+    STORE 3
+    LOAD 3
+    ISVARIANT #46 ; string "Some"
+    NOT
+    JUMPI L22
+    LOAD 3
+    EXTRACT 0
+    STORE 4
+    LOAD 2
+    LOAD 4
+    CALLM #47 1 ; string "swap_remove"
+    POP
+L22:
+    PUSH #19 ; u256 6
+    LOAD 0
+    MAPSLOT
+    LOAD 2
+    SSTOREAT
+    PUSH #18 ; u256 5
+    LOAD 1
+    MAPSLOT
+    CALLH #48 0 ; string "Stake::default"
+    SSTOREAT
+    RET
+
+.function remove_stake::closure0 arity=2 locals=2
+    LOAD 1
+    LOAD 0
+    EQ
+    RET